@@ -0,0 +1,97 @@
+// A triangular automaton of a cell type of your own, on the library: heat
+// spreading from a hot plate, a level lost per cell travelled. Implementing
+// CellState is all it takes for the light rules and the compact renderer
+// to work on it; the colors come from a color map of the example's own.
+//
+//     cargo run --example custom_cells
+
+use triangle_automata::{Automata, CellState, Grid};
+use triangle_automata::render::{render_compact, Glyph};
+use triangle_automata::rules;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Heat
+{
+    // Kept at its temperature, like a light source.
+    Plate(u8),
+    Air(u8)
+}
+
+impl CellState for Heat
+{
+    fn level(&self) -> u8
+    {
+        match self
+        {
+            Heat::Plate(level) | Heat::Air(level) => *level
+        }
+    }
+
+    fn with_level(&self, level: u8) -> Self
+    {
+        match self
+        {
+            Heat::Plate(_) => Heat::Plate(level),
+            Heat::Air(_) => Heat::Air(level)
+        }
+    }
+
+    fn default_free() -> Self
+    {
+        Heat::Air(0)
+    }
+
+    fn is_pinned(&self) -> bool
+    {
+        matches!(self, Heat::Plate(_))
+    }
+}
+
+impl fmt::Display for Heat
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{:^3}", self.level())
+    }
+}
+
+const PLATE: u8 = 9;
+
+// Blue when cold, through white, to red at the temperature of the plate.
+fn color(heat: &Heat) -> (u8, u8, u8)
+{
+    let t = f64::from(heat.level()) / f64::from(PLATE);
+    let scale = |from: f64, to: f64, t: f64| (from + (to - from)*t).round() as u8;
+    if t < 0.5
+    {
+        (scale(40.0, 255.0, 2.0*t), scale(60.0, 255.0, 2.0*t), 255)
+    }
+    else
+    {
+        (255, scale(255.0, 40.0, 2.0*t - 1.0), scale(255.0, 40.0, 2.0*t - 1.0))
+    }
+}
+
+fn main()
+{
+    let grid = Grid::from_fn((11, 6), |coord| if coord == (5, 3) { Heat::Plate(PLATE) } else { Heat::default_free() });
+    let mut automata = Automata::new(grid);
+    for _ in 0..4
+    {
+        automata.evolve(rules::light_falloff);
+    }
+    print!("{}", render_compact(automata.current()));
+    // The same cells in 24-bit terminal colors.
+    for row in automata.current().rows()
+    {
+        for heat in row
+        {
+            let (r, g, b) = color(heat);
+            print!("\x1b[38;2;{};{};{}m{}", r, g, b, heat.glyph());
+        }
+        println!("\x1b[0m");
+    }
+    println!("step {}, {} cells", automata.step(), automata.current().as_slice().len());
+}
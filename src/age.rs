@@ -0,0 +1,273 @@
+// Optional layer counting, for every cell, the steps it has spent in its
+// current state: 0 on the step it changed, one more for every step it
+// stays equal to itself. Edits with get_mut between steps are not seen as
+// changes.
+
+use crate::{Automata, Grid};
+use crate::scroll;
+
+use std::fmt::{Debug, Display};
+
+pub struct AgeLayer<T>
+{
+    ages: Grid<u32>,
+    same: fn(&T, &T) -> bool,
+    // Ages of the cells reset by each step since the reversible history
+    // started, with their index, so that step_backward can restore them.
+    resets: Vec<Vec<(usize, u32)>>
+}
+
+impl<T: Clone + Display + Copy + Debug> Automata<T>
+{
+    // None when ages are not tracked or the cell is out of the grid.
+    pub fn age(&self, (i, j): (usize, usize)) -> Option<u32>
+    {
+        self.ages.as_ref().and_then(|layer| layer.ages.get((i, j)).cloned())
+    }
+
+    pub fn ages(&self) -> Option<&Grid<u32>>
+    {
+        self.ages.as_ref().map(|layer| &layer.ages)
+    }
+
+    pub fn disable_ages(&mut self)
+    {
+        self.ages = None;
+    }
+
+    // Called once the new generation is in place, with the one before it
+    // either kept for step_backward or left in the scratch buffer.
+    pub(crate) fn update_ages(&mut self)
+    {
+        let layer = match self.ages.as_mut()
+        {
+            Some(layer) => layer,
+            None => return
+        };
+        let before = self.previous.as_ref().unwrap_or(&self.scratch);
+        let mut resets = vec![];
+        for (index, (age, (old, new))) in layer.ages.data.iter_mut()
+            .zip(before.data.iter().zip(self.current.data.iter()))
+            .enumerate()
+        {
+            if (layer.same)(old, new)
+            {
+                *age = age.saturating_add(1);
+            }
+            else
+            {
+                resets.push((index, *age));
+                *age = 0;
+            }
+        }
+        if self.previous.is_some()
+        {
+            layer.resets.push(resets);
+        }
+        else
+        {
+            layer.resets.clear();
+        }
+    }
+
+    // Undoes update_ages, for step_backward.
+    pub(crate) fn rewind_ages(&mut self)
+    {
+        let layer = match self.ages.as_mut()
+        {
+            Some(layer) => layer,
+            None => return
+        };
+        for age in layer.ages.data.iter_mut()
+        {
+            *age = age.saturating_sub(1);
+        }
+        // Without a record (ages enabled after the history started), the
+        // cells that changed keep 0.
+        for (index, age) in layer.resets.pop().unwrap_or_default()
+        {
+            layer.ages.data[index] = age;
+        }
+    }
+
+    // The entries of the history of ages and the cells they hold, for
+    // memory_report.
+    pub(crate) fn age_history(&self) -> (usize, usize)
+    {
+        self.ages.as_ref().map_or((0, 0), |layer| (layer.resets.len(), layer.resets.iter().map(Vec::len).sum()))
+    }
+
+    // Drops the oldest entry of the history of ages: stepping back that far
+    // leaves the cells that changed at 0. False without one.
+    pub(crate) fn drop_oldest_age_history(&mut self) -> bool
+    {
+        match self.ages.as_mut()
+        {
+            Some(layer) if !layer.resets.is_empty() =>
+            {
+                layer.resets.remove(0);
+                true
+            },
+            _ => false
+        }
+    }
+
+    // Every age back to 0, for reset_from.
+    pub(crate) fn reset_ages(&mut self)
+    {
+        if let Some(layer) = self.ages.as_mut()
+        {
+            layer.ages.data.iter_mut().for_each(|age| *age = 0);
+            layer.resets.clear();
+        }
+    }
+
+    // The ages moved with the cells, those coming in at 0, for scroll.
+    pub(crate) fn scroll_ages(&mut self, dx: isize, dy: isize)
+    {
+        if let Some(layer) = self.ages.as_mut()
+        {
+            scroll::shift(&mut layer.ages, dx, dy, 0);
+            layer.resets.clear();
+        }
+    }
+
+    pub(crate) fn restore_ages(&mut self, ages: Grid<u32>)
+    where
+        T: PartialEq
+    {
+        self.ages = Some(AgeLayer{ages, same: |a, b| a == b, resets: vec![]});
+    }
+}
+
+impl<T: Clone + Display + Copy + Debug + PartialEq> Automata<T>
+{
+    // Starts counting from 0 for every cell.
+    pub fn enable_ages(&mut self)
+    {
+        let dims = self.current.dims;
+        self.restore_ages(Grid::new(dims, 0));
+    }
+
+    // Like evolve, the rule also getting the age of the cell before the
+    // step. Enables ages if they were not tracked yet.
+    pub fn evolve_aged<F>(&mut self, rule: F)
+    where
+        F: Fn(u32, Vec<T>) -> T
+    {
+        if self.ages.is_none()
+        {
+            self.enable_ages();
+        }
+        let ages = self.ages().unwrap().clone();
+        self.next_generation(|coord, ngh| rule(*ages.get(coord).unwrap(), ngh));
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::analysis::distance_field;
+    use crate::{rules, validate, Light};
+
+    fn lamp() -> Automata<Light>
+    {
+        let mut automata = Automata::new(validate::lamp((17, 11), (8, 5), 9));
+        automata.enable_ages();
+        automata
+    }
+
+    #[test]
+    fn only_the_frontier_resets()
+    {
+        let mut automata = lamp();
+        let distances = distance_field(automata.current(), &[(8, 5)], |_| true);
+        let distance = |coord| distances.get(coord).unwrap().unwrap();
+        for step in 1..=6
+        {
+            let before = automata.ages().unwrap().clone();
+            automata.evolve(rules::light_falloff);
+            let ages = automata.ages().unwrap();
+            for (coord, &age) in (0..11).flat_map(|j| ages.row_coords(j))
+            {
+                if distance(coord) == step
+                {
+                    assert_eq!(age, 0, "at {:?}", coord);
+                }
+                else
+                {
+                    assert_eq!(age, before.get(coord).unwrap() + 1, "at {:?}", coord);
+                }
+            }
+        }
+        // Lit at step d, unchanged since.
+        assert_eq!(automata.age((9, 5)), Some(5));
+        assert_eq!(automata.age((8, 5)), Some(6));
+        assert_eq!(automata.age((40, 5)), None);
+        automata.disable_ages();
+        assert_eq!(automata.age((8, 5)), None);
+    }
+
+    #[test]
+    fn rules_see_the_age_of_their_cell()
+    {
+        // Cells lit for more than two steps burn out.
+        let burn_out = |age: u32, ngh: Vec<Light>| match ngh[0]
+        {
+            Light::Space(level) if level > 0 && age >= 2 => Light::Space(0),
+            _ => rules::light_falloff(ngh)
+        };
+        let mut automata = Automata::new(Grid::from_fn((17, 11), |coord| if coord == (8, 5) {Light::Source(9)} else {Light::Space(0)}));
+        for _ in 0..3
+        {
+            automata.evolve_aged(burn_out);
+        }
+        assert!(automata.ages().is_some());
+        assert_eq!(automata.get((9, 5)), Some(&Light::Space(8)));
+        automata.evolve_aged(burn_out);
+        assert_eq!(automata.get((9, 5)), Some(&Light::Space(0)));
+        assert_eq!(automata.age((9, 5)), Some(0));
+    }
+
+    // XOR of the neighborhood, the center included, as in automata.rs.
+    fn parity(ngh: Vec<u8>) -> u8
+    {
+        ngh.iter().fold(0, |acc, &cell| acc ^ cell.rotate_left(1))
+    }
+
+    #[test]
+    fn ages_rewind_and_survive_checkpoints()
+    {
+        let mut automata = Automata::new(Grid::from_fn((9, 6), |(i, j)| if (i, j) == (4, 3) {1} else {0u8}));
+        automata.enable_ages();
+        let mut seen = vec![automata.ages().unwrap().clone()];
+        for _ in 0..6
+        {
+            automata.evolve_reversible(parity, |a, b| a ^ b);
+            seen.push(automata.ages().unwrap().clone());
+        }
+        for back in (0..6).rev()
+        {
+            assert!(automata.step_backward(parity, |a, b| a ^ b));
+            assert_eq!(automata.ages(), Some(&seen[back]), "back to step {}", back);
+        }
+
+        let path = std::env::temp_dir().join(format!("triangle-automata-{}-ages.tria", std::process::id()));
+        let mut lit = lamp();
+        for _ in 0..4
+        {
+            lit.evolve(rules::light_falloff);
+        }
+        lit.save_checkpoint(&path).unwrap();
+        let mut loaded = Automata::<Light>::load_checkpoint(&path).unwrap();
+        assert_eq!(loaded.ages(), lit.ages());
+        loaded.evolve(rules::light_falloff);
+        lit.evolve(rules::light_falloff);
+        assert_eq!(loaded.ages(), lit.ages());
+        let mut ages = path.as_os_str().to_owned();
+        ages.push(".ages");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(ages);
+    }
+}
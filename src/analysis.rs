@@ -0,0 +1,705 @@
+use crate::{Automata, CellState, Grid, Slot};
+use crate::session::StepHook;
+use crate::stats::StatsLogger;
+
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::fmt::{Debug, Display};
+use std::rc::Rc;
+
+fn region_cells<T>(automata: &Automata<T>, (origin, dims): ((usize, usize), (usize, usize))) -> Vec<T>
+where
+    T: Copy + Debug + Display
+{
+    let mut cells = Vec::with_capacity(dims.0*dims.1);
+    for j in origin.1..origin.1+dims.1
+    {
+        for i in origin.0..origin.0+dims.0
+        {
+            if let Some(cell) = automata.get((i, j))
+            {
+                cells.push(*cell);
+            }
+        }
+    }
+    cells
+}
+
+// Evolves the whole automaton for up to `max_period` steps while recording
+// only the given region (origin, dims), and returns the smallest period at
+// which the region's contents come back to an earlier configuration. A
+// still region gives Some(1). The automaton is left at the step where the
+// repetition was found.
+pub fn region_period<T, F>(automata: &mut Automata<T>, rule: F, region: ((usize, usize), (usize, usize)), max_period: usize) -> Option<usize>
+where
+    T: Copy + Debug + Display + PartialEq,
+    F: Fn(Vec<T>) -> T
+{
+    let mut seen = vec![region_cells(automata, region)];
+    for _ in 0..max_period
+    {
+        automata.evolve(&rule);
+        let cells = region_cells(automata, region);
+        if let Some(pos) = seen.iter().rposition(|earlier| *earlier == cells)
+        {
+            return Some(seen.len() - pos);
+        }
+        seen.push(cells);
+    }
+    None
+}
+
+// Where a cell's intensity increases fastest: the brightest neighbor, and
+// how much brighter it is. `toward` is None when no neighbor is brighter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GradientInfo
+{
+    pub toward: Option<Slot>,
+    pub delta: f32
+}
+
+pub fn gradient_field<T, F>(grid: &Grid<T>, intensity: F) -> Grid<GradientInfo>
+where
+    T: Copy + Debug,
+    F: Fn(&T) -> f32
+{
+    Grid::from_fn(grid.dims, |coord| {
+        let here = intensity(grid.get(coord).unwrap());
+        let mut best = GradientInfo{toward: None, delta: 0.0};
+        for &slot in Slot::ALL.iter()
+        {
+            if let Some(ncoord) = grid.neighbor_slot(coord, slot)
+            {
+                let delta = intensity(grid.get(ncoord).unwrap()) - here;
+                if delta > best.delta
+                {
+                    best = GradientInfo{toward: Some(slot), delta};
+                }
+            }
+        }
+        best
+    })
+}
+
+// Number of edges crossed from the nearest source to every cell, walking
+// only through passable cells; None where no source can be reached.
+pub fn distance_field<T, P>(grid: &Grid<T>, sources: &[(usize, usize)], passable: P) -> Grid<Option<u32>>
+where
+    T: Copy + Debug,
+    P: Fn(&T) -> bool
+{
+    let mut field = Grid::new(grid.dims, None);
+    let mut queue = VecDeque::new();
+    for &source in sources
+    {
+        if let Some(distance) = field.get_mut(source)
+        {
+            *distance = Some(0);
+            queue.push_back((source, 0));
+        }
+    }
+    while let Some((coord, distance)) = queue.pop_front()
+    {
+        for ncoord in grid.neighbor_coords(coord).into_iter().skip(1)
+        {
+            if field.get(ncoord).unwrap().is_none() && passable(grid.get(ncoord).unwrap())
+            {
+                *field.get_mut(ncoord).unwrap() = Some(distance + 1);
+                queue.push_back((ncoord, distance + 1));
+            }
+        }
+    }
+    field
+}
+
+// Light left in every cell when each (cell, intensity) source shines along
+// its cheapest paths: entering a cell costs `cost` of it, None meaning a
+// wall. With a cost of 1 everywhere this is the intensity minus the
+// distance_field, which the falloff rule converges to; with other costs
+// refraction::RefractiveFalloff converges to it.
+pub fn weighted_light_field<T, C>(grid: &Grid<T>, cost: C, sources: &[((usize, usize), u32)]) -> Grid<u32>
+where
+    T: Copy + Debug,
+    C: Fn(&T) -> Option<u32>
+{
+    let mut field = Grid::new(grid.dims, 0u32);
+    let mut heap = BinaryHeap::new();
+    for &(source, intensity) in sources
+    {
+        if let Some(light) = field.get_mut(source)
+        {
+            if intensity > *light
+            {
+                *light = intensity;
+                heap.push((intensity, Reverse(source)));
+            }
+        }
+    }
+    // Brightest first, so that every cell is settled by its best path.
+    while let Some((light, Reverse(coord))) = heap.pop()
+    {
+        if light < *field.get(coord).unwrap()
+        {
+            continue;
+        }
+        for ncoord in grid.neighbor_coords(coord).into_iter().skip(1)
+        {
+            let step = match cost(grid.get(ncoord).unwrap())
+            {
+                Some(step) => step,
+                None => continue
+            };
+            let remaining = light.saturating_sub(step);
+            let known = field.get_mut(ncoord).unwrap();
+            if remaining > *known
+            {
+                *known = remaining;
+                heap.push((remaining, Reverse(ncoord)));
+            }
+        }
+    }
+    field
+}
+
+// `map` of every cell of row j, from left to right; empty when the row is
+// out of the grid.
+pub fn profile<T, F>(grid: &Grid<T>, j: usize, map: F) -> Vec<f64>
+where
+    T: Copy + Debug,
+    F: Fn(&T) -> f64
+{
+    grid.row_coords(j).map(|(_, cell)| map(cell)).collect()
+}
+
+// The same along column i, from the top.
+pub fn column_profile<T, F>(grid: &Grid<T>, i: usize, map: F) -> Vec<f64>
+where
+    T: Copy + Debug,
+    F: Fn(&T) -> f64
+{
+    grid.column(i).map(map).collect()
+}
+
+// How many times every cell changed over a run. Feed it the generations
+// before and after each step; normalized() scales the counts to [0, 1] for
+// the heat map of the image and plot renderers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActivityTracker
+{
+    counts: Grid<u32>,
+    steps: u64
+}
+
+impl ActivityTracker
+{
+    pub fn new(dims: (usize, usize)) -> Self
+    {
+        Self{counts: Grid::new(dims, 0), steps: 0}
+    }
+
+    pub fn record<T: Copy + Debug + PartialEq>(&mut self, before: &Grid<T>, after: &Grid<T>)
+    {
+        for ((count, old), new) in self.counts.data.iter_mut().zip(before.data.iter()).zip(after.data.iter())
+        {
+            if old != new
+            {
+                *count += 1;
+            }
+        }
+        self.steps += 1;
+    }
+
+    // After a plain evolve, the scratch buffer still holds the generation
+    // before the current one.
+    pub fn observe<T: Copy + Debug + Display + PartialEq>(&mut self, automata: &Automata<T>)
+    {
+        self.record(automata.scratch(), automata.current());
+    }
+
+    pub fn reset(&mut self)
+    {
+        self.counts.data.iter_mut().for_each(|count| *count = 0);
+        self.steps = 0;
+    }
+
+    pub fn counts(&self) -> &Grid<u32>
+    {
+        &self.counts
+    }
+
+    // Steps recorded since the last reset.
+    pub fn steps(&self) -> u64
+    {
+        self.steps
+    }
+
+    // Counts divided by the largest one; all 0 while nothing changed.
+    pub fn normalized(&self) -> Grid<f32>
+    {
+        let max = self.counts.data.iter().cloned().max().unwrap_or(0).max(1) as f32;
+        let mut grid = Grid::new(self.counts.dims, 0.0);
+        for (value, &count) in grid.data.iter_mut().zip(self.counts.data.iter())
+        {
+            *value = count as f32 / max;
+        }
+        grid
+    }
+}
+
+// The front of a pattern spreading from `origin`: the cells lit (of a
+// level above 0) by the last step that were not before it, and how far
+// the farthest of them is from the origin, in edges crossed. Disconnected
+// fronts count as one, of the largest radius. Once a front cell is on the
+// border of the grid the spread is no longer free, and measuring stops:
+// boundary_hit says at which step, and the front and radii stay those of
+// the step before.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrontTracker
+{
+    origin: (usize, usize),
+    distances: Option<Grid<Option<u32>>>,
+    front: Vec<(usize, usize)>,
+    // The step of every observation and its radius, None without a front.
+    radii: Vec<(u64, Option<u32>)>,
+    boundary_hit: Option<u64>
+}
+
+impl FrontTracker
+{
+    pub fn new(origin: (usize, usize)) -> Self
+    {
+        Self{origin, distances: None, front: vec![], radii: vec![], boundary_hit: None}
+    }
+
+    pub fn observe<T: CellState + Clone + Display + Debug>(&mut self, automata: &Automata<T>)
+    {
+        if self.boundary_hit.is_some()
+        {
+            return;
+        }
+        let grid = automata.current();
+        let before = automata.previous.as_ref().unwrap_or(&automata.scratch);
+        if self.distances.as_ref().map(|distances| distances.dims) != Some(grid.dims)
+        {
+            self.distances = Some(distance_field(grid, &[self.origin], |_| true));
+        }
+        let (w, h) = grid.dims;
+        let front: Vec<(usize, usize)> = before.data.iter().zip(grid.data.iter())
+            .enumerate()
+            .filter(|(_, (old, new))| old.level() == 0 && new.level() > 0)
+            .map(|(index, _)| (index % w, index / w))
+            .collect();
+        if front.iter().any(|&(i, j)| i == 0 || j == 0 || i + 1 == w || j + 1 == h)
+        {
+            self.boundary_hit = Some(automata.step());
+            return;
+        }
+        let distances = self.distances.as_ref().unwrap();
+        let radius = front.iter().filter_map(|&coord| *distances.get(coord).unwrap()).max();
+        self.front = front;
+        self.radii.push((automata.step(), radius));
+    }
+
+    pub fn front_cells(&self) -> &[(usize, usize)]
+    {
+        &self.front
+    }
+
+    pub fn radius(&self) -> Option<u32>
+    {
+        self.radii.last().and_then(|&(_, radius)| radius)
+    }
+
+    // Edges gained by the radius per step over the last `window`
+    // observations; None before there are that many, or when there was no
+    // front at either end.
+    pub fn speed(&self, window: usize) -> Option<f64>
+    {
+        let last = self.radii.len().checked_sub(1)?;
+        let first = last.checked_sub(window).filter(|_| window > 0)?;
+        match (self.radii[first], self.radii[last])
+        {
+            ((from, Some(r0)), (to, Some(r1))) => Some((f64::from(r1) - f64::from(r0)) / (to - from) as f64),
+            _ => None
+        }
+    }
+
+    pub fn boundary_hit(&self) -> Option<u64>
+    {
+        self.boundary_hit
+    }
+}
+
+impl<T: CellState + Clone + Display + Debug> StepHook<T> for FrontTracker
+{
+    fn after_step(&mut self, automata: &Automata<T>)
+    {
+        self.observe(automata);
+    }
+}
+
+// The logger with three more metrics read from the tracker, which has to
+// be attached to the same session: front_cells, front_radius and
+// front_speed (over `window` steps), NaN when there is no radius or speed.
+pub fn log_front<T: Copy + Debug>(logger: StatsLogger<T>, tracker: &Rc<RefCell<FrontTracker>>, window: usize) -> StatsLogger<T>
+{
+    let (cells, radius, speed) = (tracker.clone(), tracker.clone(), tracker.clone());
+    logger.metric("front_cells", move |_| cells.borrow().front_cells().len() as f64)
+        .metric("front_radius", move |_| radius.borrow().radius().map_or(f64::NAN, f64::from))
+        .metric("front_speed", move |_| speed.borrow().speed(window).unwrap_or(f64::NAN))
+}
+
+// Evolves the automaton until its front from `origin` dies out, checking
+// that it spreads by exactly one edge per step: a radius of n after n
+// steps. Returns the steps it spread for, or the first step where the
+// radius was another one (None if there was no front or it hit the
+// border).
+pub fn check_unit_speed<T, F>(automata: &mut Automata<T>, rule: F, origin: (usize, usize)) -> Result<u64, (u64, Option<u32>)>
+where
+    T: CellState + Clone + Display + Debug,
+    F: Fn(Vec<T>) -> T
+{
+    let mut tracker = FrontTracker::new(origin);
+    let mut steps = 0;
+    loop
+    {
+        automata.evolve(&rule);
+        tracker.observe(automata);
+        if tracker.boundary_hit().is_some()
+        {
+            return Err((steps + 1, None));
+        }
+        if tracker.front_cells().is_empty()
+        {
+            return Ok(steps);
+        }
+        steps += 1;
+        let unit = steps == 1 || tracker.speed(1) == Some(1.0);
+        if tracker.radius() != Some(steps as u32) || !unit
+        {
+            return Err((steps, tracker.radius()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{Light, SourceProgram};
+    use crate::{render, rules, run};
+    use crate::color::ColorMap;
+
+    fn dark(dims: (usize, usize)) -> Automata<Light>
+    {
+        Automata::new(Grid::new(dims, Light::Space(0)))
+    }
+
+    // A source switched on and off every other step, under the falloff
+    // rule: the cells around it follow it with a delay, two steps apart.
+    #[test]
+    fn blinking_source_has_period_two()
+    {
+        let mut automata = dark((12, 8));
+        automata.add_source_program((5, 4), SourceProgram::Square{period: 2, duty: 1, phase: 0, on: Light::Source(6), off: Light::Source(0)});
+        for _ in 0..4
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        assert_eq!(region_period(&mut automata, rules::light_falloff, ((3, 2), (5, 5)), 10), Some(2));
+    }
+
+    #[test]
+    fn still_region_has_period_one()
+    {
+        let mut automata = dark((12, 8));
+        *automata.get_mut((2, 2)).unwrap() = Light::Source(3);
+        // Far from the light, the region never changes.
+        assert_eq!(region_period(&mut automata, rules::light_falloff, ((8, 4), (3, 3)), 10), Some(1));
+        assert_eq!(automata.step(), 1);
+    }
+
+    #[test]
+    fn region_that_keeps_changing_has_no_period()
+    {
+        let mut automata = dark((12, 8));
+        *automata.get_mut((0, 3)).unwrap() = Light::Source(9);
+        // The light crosses the row one cell a step.
+        assert_eq!(region_period(&mut automata, rules::light_falloff, ((1, 3), (8, 1)), 5), None);
+        assert_eq!(automata.step(), 5);
+    }
+
+    // The falloff field of one source, converged.
+    fn lamp() -> Grid<Light>
+    {
+        let mut automata = dark((15, 9));
+        *automata.get_mut((7, 4)).unwrap() = Light::Source(20);
+        for _ in 0..40
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        automata.current().clone()
+    }
+
+    #[test]
+    fn gradients_point_toward_the_source()
+    {
+        let grid = lamp();
+        let field = gradient_field(&grid, |cell| f32::from(cell.level()));
+        let distances = distance_field(&grid, &[(7, 4)], |_| true);
+        for j in 0..9
+        {
+            for i in 0..15
+            {
+                let info = field.get((i, j)).unwrap();
+                if (i, j) == (7, 4)
+                {
+                    assert_eq!(*info, GradientInfo{toward: None, delta: 0.0});
+                    continue;
+                }
+                let uphill = grid.neighbor_slot((i, j), info.toward.unwrap()).unwrap();
+                assert_eq!(info.delta, 1.0, "at {:?}", (i, j));
+                assert_eq!(distances.get(uphill).unwrap().unwrap() + 1, distances.get((i, j)).unwrap().unwrap(), "at {:?}", (i, j));
+            }
+        }
+        assert!(gradient_field(&Grid::new((4, 3), 1u8), |&cell| f32::from(cell)).as_slice().iter().all(|info| info.toward.is_none()));
+    }
+
+    #[test]
+    fn flow_arrows_surround_the_source()
+    {
+        let grid = lamp();
+        let flow = render::render_flow(&grid, &gradient_field(&grid, |cell| f32::from(cell.level())));
+        // Ties go to the first slot: left, right, then across.
+        assert_eq!(flow, ">>>>v<v<v<v<<<<\n\
+                          >>>>>v<v<v<<<<<\n\
+                          >>>>>>v<v<<<<<<\n\
+                          >>>>>>>v<<<<<<<\n\
+                          >>>>>>>*<<<<<<<\n\
+                          >>>>>>^<^<<<<<<\n\
+                          >>>>>^<^<^<<<<<\n\
+                          >>>>^<^<^<^<<<<\n\
+                          >>>^<^<^<^<^<<<\n");
+    }
+
+    // Cells of `dims` for weighted_light_field: the cost of entering them.
+    fn medium<F: Fn((usize, usize)) -> Option<u32>>(dims: (usize, usize), cost: F) -> Grid<Option<u32>>
+    {
+        Grid::from_fn(dims, cost)
+    }
+
+    #[test]
+    fn uniform_media_match_the_distance_field()
+    {
+        // A wall with a gap, and two sources on either side of it.
+        let grid = medium((17, 9), |(i, j)| if i == 8 && j > 1 { None } else { Some(1) });
+        let sources = [((3, 4), 15), ((13, 6), 9)];
+        let field = weighted_light_field(&grid, |cost| *cost, &sources);
+        let passable = |cost: &Option<u32>| cost.is_some();
+        let one = distance_field(&grid, &[(3, 4)], passable);
+        let other = distance_field(&grid, &[(13, 6)], passable);
+        let expected = Grid::from_fn((17, 9), |coord| {
+            let lit = |distances: &Grid<Option<u32>>, intensity: u32| distances.get(coord).unwrap().map_or(0, |d| intensity.saturating_sub(d));
+            lit(&one, 15).max(lit(&other, 9))
+        });
+        assert_eq!(field, expected);
+        assert_eq!(field.get((8, 5)), Some(&0));
+    }
+
+    #[test]
+    fn dense_strips_bend_the_light()
+    {
+        // A strip costing 6 per cell down column 10, open on the last two
+        // rows.
+        let grid = medium((21, 9), |(i, j)| Some(if i == 10 && j < 7 {6} else {1}));
+        let field = weighted_light_field(&grid, |cost| *cost, &[((5, 4), 30)]);
+        let uniform = weighted_light_field(&grid, |_| Some(1), &[((5, 4), 30)]);
+        // Straight across, the light loses more behind the strip than it
+        // does going around it.
+        for j in 0..7
+        {
+            assert!(field.get((11, j)).unwrap() < uniform.get((11, j)).unwrap());
+        }
+        assert_eq!(field.get((11, 8)), uniform.get((11, 8)));
+        // Behind the strip, the brightest cells are by the opening.
+        let behind: Vec<u32> = (0..9).map(|j| *field.get((14, j)).unwrap()).collect();
+        let brightest = (0..9).max_by_key(|&j| (behind[j], j)).unwrap();
+        assert!(brightest >= 6, "{:?}", behind);
+        assert!(behind[8] > behind[4] && behind[4] > behind[0], "{:?}", behind);
+        // In front of it, nothing changes.
+        for j in 0..9
+        {
+            for i in 0..10
+            {
+                assert_eq!(field.get((i, j)), uniform.get((i, j)));
+            }
+        }
+    }
+
+    #[test]
+    fn activity_stops_at_the_frontier()
+    {
+        let mut automata = dark((21, 13));
+        *automata.get_mut((10, 6)).unwrap() = Light::Source(6);
+        let mut tracker = ActivityTracker::new((21, 13));
+        run::run_loop_tracked(&mut automata, rules::light_falloff, 15, |_| (), &run::LoopOptions::default(), Some(&mut tracker));
+        assert_eq!(tracker.steps(), 15);
+        // Under falloff a cell changes once, when the light reaches it,
+        // and cells as far as the source's level stay dark: the activity
+        // is the disc inside the frontier ring, the source excluded.
+        let distances = distance_field(automata.current(), &[(10, 6)], |_| true);
+        let expected = Grid::from_fn((21, 13), |coord| match distances.get(coord).unwrap().unwrap()
+        {
+            0 => 0,
+            d if d < 6 => 1,
+            _ => 0
+        });
+        assert_eq!(tracker.counts(), &expected);
+        let normalized = tracker.normalized();
+        assert_eq!(normalized.get((10, 6)), Some(&0.0));
+        assert_eq!(normalized.get((11, 6)), Some(&1.0));
+        // Straight onto the heat map: the source black, the lit cells white.
+        let heat = |coord| ColorMap::Heat.color(f64::from(*normalized.get(coord).unwrap()));
+        assert_eq!((heat((10, 6)), heat((11, 6))), ((0, 0, 0), (255, 255, 255)));
+
+        tracker.reset();
+        assert_eq!((tracker.steps(), tracker.counts()), (0, &Grid::new((21, 13), 0)));
+        assert!(tracker.normalized().as_slice().iter().all(|&t| t == 0.0));
+    }
+
+    #[test]
+    fn blinking_sources_keep_their_surroundings_busy()
+    {
+        let mut automata = dark((21, 13));
+        automata.add_source_program((10, 6), SourceProgram::Square{period: 4, duty: 2, phase: 0, on: Light::Source(6), off: Light::Space(0)});
+        let mut tracker = ActivityTracker::new((21, 13));
+        for _ in 0..40
+        {
+            automata.evolve(rules::light_falloff);
+            tracker.observe(&automata);
+        }
+        // Every pulse goes in and out of the cells near the source, less
+        // and less of it making it further away.
+        let counts = tracker.counts();
+        let distances = distance_field(counts, &[(10, 6)], |_| true);
+        let ring = |d: u32| -> Vec<u32> {
+            (0..13).flat_map(|j| (0..21).map(move |i| (i, j)))
+                .filter(|&coord| distances.get(coord).unwrap().unwrap() == d)
+                .map(|coord| *counts.get(coord).unwrap())
+                .collect()
+        };
+        assert!(ring(1).iter().all(|&count| count > 0));
+        assert!(ring(6).iter().chain(ring(9).iter()).all(|&count| count == 0));
+        let max = *counts.as_slice().iter().max().unwrap();
+        assert!(ring(1).iter().chain(ring(2).iter()).any(|&count| count == max));
+    }
+
+    #[test]
+    fn profiles_follow_rows_and_columns()
+    {
+        let grid = Grid::from_fn((4, 3), |(i, j)| (i + 10*j) as u8);
+        assert_eq!(profile(&grid, 1, |&cell| f64::from(cell)), vec![10.0, 11.0, 12.0, 13.0]);
+        assert_eq!(column_profile(&grid, 2, |&cell| f64::from(cell)), vec![2.0, 12.0, 22.0]);
+        assert!(profile(&grid, 3, |&cell| f64::from(cell)).is_empty());
+        assert!(column_profile(&grid, 4, |&cell| f64::from(cell)).is_empty());
+        // Along the lit row of the light demo, the levels fall off from the
+        // source.
+        let row = profile(&lamp(), 4, |cell| f64::from(cell.level()));
+        assert_eq!(row[7], 20.0);
+        assert!(row[..8].windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(row[7..].windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    fn sources(dims: (usize, usize), sources: &[((usize, usize), u8)]) -> Automata<Light>
+    {
+        let mut automata = dark(dims);
+        for &(coord, level) in sources
+        {
+            *automata.get_mut(coord).unwrap() = Light::Source(level);
+        }
+        automata
+    }
+
+    #[test]
+    fn the_light_spreads_one_layer_a_step()
+    {
+        // Until the source's 10 levels are used up, 9 cells away.
+        let mut automata = sources((30, 21), &[((10, 10), 10)]);
+        assert_eq!(check_unit_speed(&mut automata, rules::light_falloff, (10, 10)), Ok(9));
+
+        let mut automata = sources((30, 21), &[((10, 10), 10)]);
+        let mut tracker = FrontTracker::new((10, 10));
+        for step in 1..=9
+        {
+            automata.evolve(rules::light_falloff);
+            tracker.observe(&automata);
+            assert_eq!(tracker.radius(), Some(step));
+            assert!(!tracker.front_cells().is_empty());
+        }
+        assert_eq!(tracker.speed(1), Some(1.0));
+        assert_eq!(tracker.speed(8), Some(1.0));
+        assert_eq!(tracker.speed(9), None);
+        assert_eq!(tracker.speed(0), None);
+        automata.evolve(rules::light_falloff);
+        tracker.observe(&automata);
+        assert!(tracker.front_cells().is_empty());
+        assert_eq!((tracker.radius(), tracker.speed(1)), (None, None));
+        assert_eq!(tracker.boundary_hit(), None);
+    }
+
+    #[test]
+    fn disconnected_fronts_report_the_farthest()
+    {
+        // A weaker source 6 cells right of the origin, lit from the start:
+        // its front is 6 cells farther out than the origin's.
+        let mut automata = sources((40, 21), &[((10, 10), 10), ((16, 10), 4)]);
+        let mut tracker = FrontTracker::new((10, 10));
+        for step in 1..=3
+        {
+            automata.evolve(rules::light_falloff);
+            tracker.observe(&automata);
+            assert_eq!(tracker.radius(), Some(step + 6));
+        }
+        // The weaker light is used up, only the origin's front is left.
+        automata.evolve(rules::light_falloff);
+        tracker.observe(&automata);
+        assert_eq!(tracker.radius(), Some(4));
+    }
+
+    #[test]
+    fn fronts_stop_at_the_border()
+    {
+        let mut automata = sources((11, 7), &[((5, 3), 12)]);
+        let mut tracker = FrontTracker::new((5, 3));
+        for _ in 0..10
+        {
+            automata.evolve(rules::light_falloff);
+            tracker.observe(&automata);
+        }
+        // Row 0, 3 rows up, is 5 edges away: the fifth step hits it.
+        let hit = tracker.boundary_hit().unwrap();
+        assert_eq!(hit, 5);
+        assert_eq!(tracker.radius(), Some(hit as u32 - 1));
+        assert_eq!(check_unit_speed(&mut sources((11, 7), &[((5, 3), 12)]), rules::light_falloff, (5, 3)), Err((hit, None)));
+    }
+
+    #[test]
+    fn fronts_are_logged()
+    {
+        let tracker = Rc::new(RefCell::new(FrontTracker::new((10, 10))));
+        let logger = Rc::new(RefCell::new(log_front(StatsLogger::new(), &tracker, 2)));
+        let falloff: Box<dyn crate::Rule<Light>> = Box::new(rules::light_falloff::<Light>);
+        let mut session = crate::session::Session::new(sources((30, 21), &[((10, 10), 10)]), falloff);
+        session.attach(tracker.clone());
+        session.attach(logger.clone());
+        session.run(4);
+        let logger = logger.borrow();
+        assert_eq!(logger.names(), ["front_cells", "front_radius", "front_speed"]);
+        let radii: Vec<f64> = logger.rows().iter().map(|(_, row)| row[1]).collect();
+        assert_eq!(radii, [1.0, 2.0, 3.0, 4.0]);
+        assert!(logger.rows()[1].1[2].is_nan());
+        assert_eq!(logger.rows()[3].1[2], 1.0);
+        assert!(logger.rows().iter().all(|(_, row)| row[0] > 0.0));
+    }
+}
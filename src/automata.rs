@@ -0,0 +1,1076 @@
+// The automaton: two generation buffers stepped by a rule, with everything
+// that can be attached to the steps (source programs, an injector, ages,
+// events...), and the builder of its first generation.
+
+use crate::{age, boundary, cell_rules, events, flux, hotspot, rows};
+use crate::{DimMismatch, Grid};
+use crate::sweep::RuleConfig;
+
+use std::collections::BTreeMap;
+
+// State forced onto a cell at the start of every step, as a function of the
+// step number. Registered on an Automata with add_source_program.
+pub enum SourceProgram<T>
+{
+    Constant(T),
+    // `on` during the first `duty` steps of every `period` steps, `off` for
+    // the rest, the whole pattern being delayed by `phase` steps.
+    Square{period: u64, duty: u64, phase: u64, on: T, off: T},
+    Custom(Box<dyn Fn(u64) -> T>)
+}
+
+impl<T: Copy> SourceProgram<T>
+{
+    pub fn value(&self, step: u64) -> T
+    {
+        match self
+        {
+            SourceProgram::Constant(state) => *state,
+            SourceProgram::Square{period, duty, phase, on, off} =>
+            {
+                let period = (*period).max(1);
+                let t = (step + period - phase % period) % period;
+                if t < *duty {*on} else {*off}
+            },
+            SourceProgram::Custom(f) => f(step)
+        }
+    }
+}
+
+// Writes requested after each step, given the index of the step just
+// reached.
+pub type Injector<T> = Box<dyn FnMut(u64) -> Vec<((usize, usize), T)>>;
+
+// What to do with injected writes that fall outside of the grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutOfBounds
+{
+    Ignore,
+    // Keep the coordinates for take_injection_errors.
+    Record
+}
+
+// A local rule: the next state of a cell from its neighborhood, center
+// first. Every `Fn(Vec<T>) -> T` is one; named rules can say what they are,
+// and with which parameters, so that they can be saved next to a
+// checkpoint.
+pub trait Rule<T>
+{
+    fn apply(&self, ngh: Vec<T>) -> T;
+
+    fn name(&self) -> Option<&str>
+    {
+        None
+    }
+
+    fn config(&self) -> Option<&RuleConfig>
+    {
+        None
+    }
+
+    // Whether equal neighborhoods always give equal states, the rule
+    // keeping no state and drawing nothing at random: a Simulation then
+    // lets evolve skip the rows that cannot change (see rows.rs). Closures
+    // are not known to be.
+    fn is_deterministic(&self) -> bool
+    {
+        false
+    }
+}
+
+impl<T, F: Fn(Vec<T>) -> T> Rule<T> for F
+{
+    fn apply(&self, ngh: Vec<T>) -> T
+    {
+        self(ngh)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildError<T>
+{
+    // The builder was never given a fill state.
+    NoFill,
+    // A cell to set lies outside of the grid.
+    OutOfBounds{coord: (usize, usize), state: T, dims: (usize, usize)},
+    // Same for a source program.
+    ProgramOutOfBounds{coord: (usize, usize), dims: (usize, usize)}
+}
+
+// Automata::new with the initial cells given one by one:
+//
+//     AutomataBuilder::new((30, 20))
+//         .fill(Light::Space(0))
+//         .set((10, 10), Light::Source(10))
+//         .build()
+pub struct AutomataBuilder<T>
+{
+    dims: (usize, usize),
+    fill: Option<T>,
+    cells: Vec<((usize, usize), T)>,
+    programs: Vec<((usize, usize), SourceProgram<T>)>
+}
+
+impl<T: Clone + std::fmt::Display + Copy + std::fmt::Debug> AutomataBuilder<T>
+{
+    pub fn new(dims: (usize, usize)) -> Self
+    {
+        Self{dims, fill: None, cells: vec![], programs: vec![]}
+    }
+
+    pub fn fill(mut self, state: T) -> Self
+    {
+        self.fill = Some(state);
+        self
+    }
+
+    // Later sets of the same cell win.
+    pub fn set(mut self, coord: (usize, usize), state: T) -> Self
+    {
+        self.cells.push((coord, state));
+        self
+    }
+
+    pub fn source_program(mut self, coord: (usize, usize), program: SourceProgram<T>) -> Self
+    {
+        self.programs.push((coord, program));
+        self
+    }
+
+    pub fn build(self) -> Result<Automata<T>, BuildError<T>>
+    {
+        let fill = self.fill.ok_or(BuildError::NoFill)?;
+        let mut grid = Grid::new(self.dims, fill);
+        for (coord, state) in self.cells
+        {
+            *grid.get_mut(coord).ok_or(BuildError::OutOfBounds{coord, state, dims: self.dims})? = state;
+        }
+        let mut automata = Automata::new(grid);
+        for (coord, program) in self.programs
+        {
+            if coord.0 >= self.dims.0 || coord.1 >= self.dims.1
+            {
+                return Err(BuildError::ProgramOutOfBounds{coord, dims: self.dims});
+            }
+            automata.add_source_program(coord, program);
+        }
+        Ok(automata)
+    }
+}
+
+// A rule failing on a cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuleError<E>
+{
+    pub coord: (usize, usize),
+    pub error: E
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StepSummary
+{
+    pub changed: usize
+}
+
+pub struct Automata<T>
+{
+    pub(crate) current: Grid<T>,
+    // Where the next generation is computed before being swapped in. Holds
+    // the generation before the current one between steps.
+    pub(crate) scratch: Grid<T>,
+    // Generation before the current one, only kept by evolve_reversible.
+    pub(crate) previous: Option<Grid<T>>,
+    // Cleared when the current grid is edited from outside while previous
+    // is kept, since stepping backward would then not give back the past.
+    pub(crate) reversible: bool,
+    // Offset of the pairing used by the next evolve_blocks, 0 or 1.
+    pub(crate) block_phase: usize,
+    // Number of steps taken so far.
+    pub(crate) step: u64,
+    pub(crate) programs: Vec<((usize, usize), SourceProgram<T>)>,
+    pub(crate) injector: Option<Injector<T>>,
+    pub(crate) injection_policy: OutOfBounds,
+    pub(crate) injection_errors: Vec<(usize, usize)>,
+    // Steps every cell has kept its state, when enabled (see age.rs).
+    pub(crate) ages: Option<age::AgeLayer<T>>,
+    // Region of the last evolve_within, see hotspot.rs.
+    pub(crate) hotspots: Option<hotspot::HotspotRegion>,
+    // What left the grid during the last step, see flux.rs.
+    pub(crate) boundary_flux: flux::BoundaryFlux,
+    // Events of the steps not drained yet, when enabled (see events.rs).
+    pub(crate) events: Option<events::EventLayer<T>>,
+    // Rows the last step changed, when evolve skips the others (see
+    // rows.rs).
+    pub(crate) rows: Option<rows::RowTracker<T>>,
+    // Cells stepped by rules of their own, see cell_rules.rs.
+    pub(crate) cell_rules: cell_rules::CellRules<T>,
+    // Next row of the step evolve_budgeted is part way through, see
+    // budget.rs.
+    pub(crate) pending_row: Option<usize>,
+    // World coordinates of cell (0, 0), moved by scroll (see scroll.rs).
+    pub(crate) world_offset: (isize, isize),
+    // What evolve sees past the edges, see boundary.rs.
+    pub(crate) boundary: boundary::BoundaryCondition<T>,
+    // Bytes the history and event queue are kept within, and the entries
+    // dropped for it so far, see memory.rs.
+    pub(crate) memory_budget: Option<usize>,
+    pub(crate) memory_evictions: u64
+}
+
+impl<T: Clone + std::fmt::Display + Copy + std::fmt::Debug> Automata<T>
+{
+    // evolve computes every row until enable_row_skipping, see rows.rs.
+    pub fn new(grid: Grid<T>) -> Self
+    {
+        Self
+        {
+            scratch: grid.clone(),
+            current: grid,
+            previous: None,
+            reversible: false,
+            block_phase: 0,
+            step: 0,
+            programs: vec![],
+            injector: None,
+            injection_policy: OutOfBounds::Ignore,
+            injection_errors: vec![],
+            ages: None,
+            hotspots: None,
+            boundary_flux: flux::BoundaryFlux::default(),
+            events: None,
+            rows: None,
+            cell_rules: cell_rules::CellRules::new(),
+            pending_row: None,
+            world_offset: (0, 0),
+            boundary: boundary::BoundaryCondition::Open,
+            memory_budget: None,
+            memory_evictions: 0
+        }
+    }
+
+    pub fn from_fn<F>(dims: (usize, usize), f: F) -> Self
+    where
+        F: FnMut((usize, usize)) -> T
+    {
+        Self::new(Grid::from_fn(dims, f))
+    }
+
+    pub fn print(&self)
+    {
+        self.current.print();
+    }
+    
+    pub fn evolve<F>(&mut self, rule: F)
+    where
+        F: Fn(Vec<T>) -> T
+    {
+        // Rows are only skipped with no cell rules and no edges to follow.
+        if self.rows.is_some() && self.cell_rules.is_empty() && matches!(self.boundary, boundary::BoundaryCondition::Open)
+        {
+            self.evolve_rows(rule);
+        }
+        else
+        {
+            self.next_generation(|_, ngh| rule(ngh));
+        }
+    }
+
+    // Like evolve, with the rule also given the index of the step being
+    // computed (0 for the first one), the cell itself and its neighbors.
+    pub fn evolve_timed<F>(&mut self, rule: F)
+    where
+        F: Fn(u64, &T, &[T]) -> T
+    {
+        let step = self.step;
+        self.next_generation(|_, ngh| rule(step, &ngh[0], &ngh[1..]));
+    }
+
+    // One plain step: every cell of the scratch buffer is computed from the
+    // coordinates and neighborhood of the cell in the current one, then the
+    // buffers are swapped. A cell with a rule of its own (see cell_rules.rs)
+    // is given to it instead of `cell_rule`.
+    pub fn next_generation<G>(&mut self, mut cell_rule: G)
+    where
+        G: FnMut((usize, usize), Vec<T>) -> T
+    {
+        match self.try_next_generation(|coord, ngh| Ok::<T, std::convert::Infallible>(cell_rule(coord, ngh)))
+        {
+            Ok(()) => (),
+            Err(error) => match error.error {}
+        }
+    }
+
+    // Same as next_generation for fallible rules. On the first error the
+    // generation is left as it was (source programs aside) and the step is
+    // not counted.
+    pub fn try_next_generation<G, E>(&mut self, mut cell_rule: G) -> Result<(), RuleError<E>>
+    where
+        G: FnMut((usize, usize), Vec<T>) -> Result<T, E>
+    {
+        let boundary = self.boundary;
+        self.with_cell_rules(|automata, overrides| automata.try_next_generation_from(|grid, coord| {
+            let ngh = grid.neighborhood_under(coord, &boundary);
+            match overrides.get(&coord)
+            {
+                Some(overridden) => Ok(overridden.apply(ngh)),
+                None => cell_rule(coord, ngh)
+            }
+        }))
+    }
+
+    // Same as try_next_generation, the rule reading what it needs from the
+    // current grid itself, for neighborhoods other than the edge one. Cell
+    // rules are left to the caller.
+    pub fn try_next_generation_from<G, E>(&mut self, mut cell_rule: G) -> Result<(), RuleError<E>>
+    where
+        G: FnMut(&Grid<T>, (usize, usize)) -> Result<T, E>
+    {
+        self.apply_source_programs();
+        for i in 0..self.current.dims.0
+        {
+            for j in 0..self.current.dims.1
+            {
+                let new_cell = cell_rule(&self.current, (i, j)).map_err(|error| RuleError{coord: (i, j), error})?;
+                *self.scratch.get_mut((i,j)).unwrap() = new_cell;
+            }
+        }
+        std::mem::swap(&mut self.current, &mut self.scratch);
+        self.previous = None;
+        self.finish_step();
+        Ok(())
+    }
+
+    pub fn try_evolve<F, E>(&mut self, rule: F) -> Result<(), RuleError<E>>
+    where
+        F: Fn(Vec<T>) -> Result<T, E>
+    {
+        self.try_next_generation(|_, ngh| rule(ngh))
+    }
+
+    pub fn step(&self) -> u64
+    {
+        self.step
+    }
+
+    // Counts the step, then lets the injector write into the new
+    // generation before anything else looks at it, ages included.
+    pub(crate) fn finish_step(&mut self)
+    {
+        self.step += 1;
+        self.pending_row = None;
+        self.boundary_flux = flux::BoundaryFlux::default();
+        self.update_rows();
+        let writes = match self.injector.as_mut()
+        {
+            Some(injector) => injector(self.step),
+            None => vec![]
+        };
+        if !writes.is_empty() && self.previous.is_some()
+        {
+            self.reversible = false;
+        }
+        for (coord, state) in writes
+        {
+            match self.current.get_mut(coord)
+            {
+                Some(cell) =>
+                {
+                    *cell = state;
+                    self.mark_row_changed(coord.1);
+                },
+                None if self.injection_policy == OutOfBounds::Record => self.injection_errors.push(coord),
+                None => ()
+            }
+        }
+        self.update_ages();
+        self.record_events();
+        self.enforce_memory_budget();
+    }
+
+    // The injector is called after every step with the index of the step
+    // just reached, and its writes are applied to the new generation.
+    pub fn set_injector<I>(&mut self, injector: I)
+    where
+        I: FnMut(u64) -> Vec<((usize, usize), T)> + 'static
+    {
+        self.injector = Some(Box::new(injector));
+    }
+
+    pub fn clear_injector(&mut self)
+    {
+        self.injector = None;
+    }
+
+    pub fn set_injection_policy(&mut self, policy: OutOfBounds)
+    {
+        self.injection_policy = policy;
+    }
+
+    // Out-of-bounds coordinates seen since the last call, under
+    // OutOfBounds::Record.
+    pub fn take_injection_errors(&mut self) -> Vec<(usize, usize)>
+    {
+        std::mem::take(&mut self.injection_errors)
+    }
+
+    // Programs are applied straight to the current buffer at the start of
+    // every step, so the rule sees the programmed states.
+    pub fn add_source_program(&mut self, coord: (usize, usize), program: SourceProgram<T>)
+    {
+        self.programs.push((coord, program));
+    }
+
+    pub fn clear_source_programs(&mut self)
+    {
+        self.programs.clear();
+    }
+
+    pub(crate) fn apply_source_programs(&mut self)
+    {
+        if self.programs.is_empty()
+        {
+            return;
+        }
+        if self.previous.is_some()
+        {
+            self.reversible = false;
+        }
+        for (coord, program) in self.programs.iter()
+        {
+            if let Some(cell) = self.current.get_mut(*coord)
+            {
+                *cell = program.value(self.step);
+            }
+        }
+        if self.rows.is_some()
+        {
+            let rows: Vec<usize> = self.programs.iter().map(|((_, j), _)| *j).collect();
+            for j in rows
+            {
+                self.mark_row_changed(j);
+            }
+        }
+    }
+
+    // Second-order step: next = combine(rule(neighborhood), previous cell),
+    // where the previous generation defaults to the current one on the first
+    // call. With a combine that can be undone (XOR, modular subtraction...)
+    // the dynamics is exactly reversible through step_backward. This keeps a
+    // third grid alive, so it costs one more buffer than evolve. Editing
+    // cells with get_mut in between breaks reversibility, which
+    // is_reversible reports; a plain evolve drops the previous generation.
+    pub fn evolve_reversible<F, C>(&mut self, rule: F, combine: C)
+    where
+        F: Fn(Vec<T>) -> T,
+        C: Fn(T, T) -> T
+    {
+        self.apply_source_programs();
+        let mut previous = match self.previous.take()
+        {
+            Some(previous) => previous,
+            None =>
+            {
+                self.reversible = true;
+                self.current.clone()
+            }
+        };
+        for i in 0..self.current.dims.0
+        {
+            for j in 0..self.current.dims.1
+            {
+                let ngh = self.current.neighborhood_under((i, j), &self.boundary);
+                let old = *previous.get((i, j)).unwrap();
+                let new = match self.cell_rules.get(&(i, j))
+                {
+                    Some(overridden) => overridden.apply(ngh),
+                    None => rule(ngh)
+                };
+                *self.scratch.get_mut((i, j)).unwrap() = combine(new, old);
+            }
+        }
+        // The current generation becomes the previous one, and the old
+        // previous grid is recycled as scratch.
+        std::mem::swap(&mut self.current, &mut self.scratch);
+        std::mem::swap(&mut previous, &mut self.scratch);
+        self.previous = Some(previous);
+        self.finish_step();
+    }
+
+    // Undoes one evolve_reversible step, given the same rule and the inverse
+    // of its combine: previous = uncombine(rule(neighborhood of previous),
+    // current). Returns false when there is no previous generation.
+    pub fn step_backward<F, U>(&mut self, rule: F, uncombine: U) -> bool
+    where
+        F: Fn(Vec<T>) -> T,
+        U: Fn(T, T) -> T
+    {
+        let mut previous = match self.previous.take()
+        {
+            Some(previous) => previous,
+            None => return false
+        };
+        for i in 0..previous.dims.0
+        {
+            for j in 0..previous.dims.1
+            {
+                let ngh = previous.neighborhood_under((i, j), &self.boundary);
+                let current = *self.current.get((i, j)).unwrap();
+                let new = match self.cell_rules.get(&(i, j))
+                {
+                    Some(overridden) => overridden.apply(ngh),
+                    None => rule(ngh)
+                };
+                *self.scratch.get_mut((i, j)).unwrap() = uncombine(new, current);
+            }
+        }
+        // previous <- scratch (the generation before that), current <- the
+        // old previous, scratch <- the old current.
+        std::mem::swap(&mut previous, &mut self.current);
+        std::mem::swap(&mut previous, &mut self.scratch);
+        self.previous = Some(previous);
+        self.step = self.step.saturating_sub(1);
+        self.invalidate_rows();
+        self.pending_row = None;
+        self.rewind_ages();
+        true
+    }
+
+    // Block-partitioned step: each row is cut into pairs of adjacent
+    // triangles (i, i+1) with i of the parity of the current phase, and the
+    // rule maps every pair to its new pair. The phase alternates on every
+    // call so consecutive partitions overlap, as in the Margolus
+    // neighborhood. A cell left without a partner at a row end is copied
+    // unchanged, unless the boundary is Periodic: the last cell of the row
+    // then pairs with the first. Pairs never straddle rows, so information
+    // only travels along the rows.
+    pub fn evolve_blocks<F>(&mut self, rule: F)
+    where
+        F: Fn((T, T)) -> (T, T)
+    {
+        self.apply_source_programs();
+        let (w, h) = self.current.dims;
+        let wraps = self.boundary.is_periodic();
+        for j in 0..h
+        {
+            let row = j*w..(j + 1)*w;
+            self.scratch.data[row.clone()].copy_from_slice(&self.current.data[row]);
+            let mut i = self.block_phase;
+            while i+1 < w || (wraps && i < w)
+            {
+                let partner = (i + 1) % w;
+                let pair = (*self.current.get((i, j)).unwrap(), *self.current.get((partner, j)).unwrap());
+                let (new_left, new_right) = rule(pair);
+                *self.scratch.get_mut((i, j)).unwrap() = new_left;
+                *self.scratch.get_mut((partner, j)).unwrap() = new_right;
+                i += 2;
+            }
+        }
+        std::mem::swap(&mut self.current, &mut self.scratch);
+        self.block_phase = 1 - self.block_phase;
+        self.previous = None;
+        self.finish_step();
+    }
+
+    pub fn is_reversible(&self) -> bool
+    {
+        self.previous.is_some() && self.reversible
+    }
+
+    pub fn current(&self) -> &Grid<T>
+    {
+        &self.current
+    }
+
+    // The other buffer: the generation before the current one after a
+    // plain step, or leftovers after a failed try_evolve.
+    pub fn scratch(&self) -> &Grid<T>
+    {
+        &self.scratch
+    }
+
+    pub fn get(&self, (i,j): (usize, usize)) -> Option<&T>
+    {
+        self.current.get((i,j))
+    }
+    pub fn get_mut(&mut self, (i,j): (usize, usize)) -> Option<&mut T>
+    {
+        if self.previous.is_some()
+        {
+            self.reversible = false;
+        }
+        self.mark_row_changed(j);
+        self.pending_row = None;
+        self.current.get_mut((i,j))
+    }
+
+    // Replaces the current generation with cells in storage order (see
+    // Grid::as_slice).
+    pub fn load_state(&mut self, data: &[T]) -> Result<(), DimMismatch>
+    {
+        self.current.copy_from_slice(data)?;
+        if self.previous.is_some()
+        {
+            self.reversible = false;
+        }
+        self.invalidate_rows();
+        self.pending_row = None;
+        Ok(())
+    }
+
+    // Starts over from `grid`, as a new automaton would but keeping its
+    // buffers, source programs and injector: step 0, no reversible history,
+    // ages back to 0 if they are tracked, the world offset of scroll back to
+    // (0, 0) and no event waiting. The grid must have the dims of the
+    // automaton's.
+    pub fn reset_from(&mut self, grid: &Grid<T>) -> Result<(), DimMismatch>
+    {
+        if grid.dims != self.current.dims
+        {
+            return Err(DimMismatch{expected: self.current.data.len(), found: grid.data.len()});
+        }
+        self.current.data.copy_from_slice(&grid.data);
+        self.restart();
+        Ok(())
+    }
+
+    // reset_from with the cells given by `f`, in storage order.
+    pub fn reset_with<F>(&mut self, mut f: F)
+    where
+        F: FnMut((usize, usize)) -> T
+    {
+        let w = self.current.dims.0;
+        for (index, cell) in self.current.data.iter_mut().enumerate()
+        {
+            *cell = f((index % w, index / w));
+        }
+        self.restart();
+    }
+
+    pub(crate) fn restart(&mut self)
+    {
+        self.scratch.data.copy_from_slice(&self.current.data);
+        self.previous = None;
+        self.reversible = false;
+        self.block_phase = 0;
+        self.step = 0;
+        self.injection_errors.clear();
+        self.boundary_flux = flux::BoundaryFlux::default();
+        self.invalidate_rows();
+        self.pending_row = None;
+        self.reset_ages();
+        self.world_offset = (0, 0);
+        if let Some(events) = self.events.as_mut()
+        {
+            events.clear();
+        }
+    }
+
+}
+
+
+impl<T: Clone + std::fmt::Display + Copy + std::fmt::Debug + PartialEq> Automata<T>
+{
+    // Same as evolve, counting the cells whose state differs from the one
+    // they had before the step. The old generation is still in the scratch
+    // buffer, so this costs one comparison per cell and no copy, but for
+    // the cells of source programs: those hold what the programs wrote at
+    // the start of the step, so their states from before are kept aside.
+    pub fn evolve_counted<F>(&mut self, rule: F) -> StepSummary
+    where
+        F: Fn(Vec<T>) -> T
+    {
+        let written: BTreeMap<(usize, usize), T> = self.programs.iter()
+            .filter_map(|(coord, _)| self.current.get(*coord).map(|cell| (*coord, *cell)))
+            .collect();
+        self.evolve(rule);
+        let mut changed = self.current.data.iter()
+            .zip(self.scratch.data.iter())
+            .filter(|(new, old)| new != old)
+            .count();
+        for (&(i, j), before) in &written
+        {
+            let k = j*self.current.dims.0 + i;
+            let new = &self.current.data[k];
+            changed = changed - usize::from(new != &self.scratch.data[k]) + usize::from(new != before);
+        }
+        StepSummary{changed}
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::Light;
+    use crate::rng::SplitMix64;
+    use crate::rules;
+    use crate::validate::{random_grain, seeded_grid};
+
+    // XOR of the neighborhood, the center included.
+    fn parity(ngh: Vec<u8>) -> u8
+    {
+        ngh.iter().fold(0, |acc, &cell| acc ^ cell.rotate_left(1))
+    }
+
+    #[test]
+    fn reversible_steps_undo_exactly()
+    {
+        let start = seeded_grid(5, (13, 9), random_grain);
+        let mut automata = Automata::new(start.clone());
+        for _ in 0..50
+        {
+            automata.evolve_reversible(parity, |a, b| a ^ b);
+        }
+        assert_eq!(automata.step(), 50);
+        assert!(automata.is_reversible());
+        assert_ne!(automata.current(), &start);
+        for _ in 0..50
+        {
+            assert!(automata.step_backward(parity, |a, b| a ^ b));
+        }
+        assert_eq!(automata.current(), &start);
+        assert_eq!(automata.step(), 0);
+    }
+
+    #[test]
+    fn edits_break_reversibility()
+    {
+        let mut automata = Automata::new(seeded_grid(6, (6, 4), random_grain));
+        assert!(!automata.step_backward(parity, |a, b| a ^ b));
+        automata.evolve_reversible(parity, |a, b| a ^ b);
+        assert!(automata.is_reversible());
+        *automata.get_mut((2, 2)).unwrap() ^= 1;
+        assert!(!automata.is_reversible());
+        // A plain step forgets the previous generation.
+        automata.evolve_reversible(parity, |a, b| a ^ b);
+        automata.evolve(parity);
+        assert!(!automata.is_reversible());
+        assert!(!automata.step_backward(parity, |a, b| a ^ b));
+    }
+
+    #[test]
+    fn blocks_alternate_and_leave_row_ends_alone()
+    {
+        let mut automata = Automata::new(Grid::new((5, 2), 0u8));
+        let touch = |(left, right): (u8, u8)| (left + 1, right + 10);
+        automata.evolve_blocks(touch);
+        assert_eq!(automata.current().row(0), [1, 10, 1, 10, 0]);
+        automata.evolve_blocks(touch);
+        assert_eq!(automata.current().row(1), [1, 11, 11, 11, 10]);
+        assert_eq!(automata.step(), 2);
+    }
+
+    #[test]
+    fn grain_shuffle_conserves_grains()
+    {
+        let mut automata = Automata::new(seeded_grid(7, (11, 6), random_grain));
+        let total = |automata: &Automata<u8>| automata.current().data.iter().map(|&grains| u64::from(grains)).sum::<u64>();
+        let before = total(&automata);
+        for step in 0..200
+        {
+            automata.evolve_blocks(rules::grain_shuffle);
+            assert_eq!(total(&automata), before, "after step {}", step + 1);
+        }
+    }
+
+    // The light demo settles once its source's light has spread as far as
+    // it goes, one cell a step: 9 steps with changes for a source of 10,
+    // and a 10th that changes nothing.
+    #[test]
+    fn light_demo_converges_after_its_intensity()
+    {
+        let mut automata = Automata::new(Grid::new((30, 20), Light::Space(0)));
+        *automata.get_mut((10, 10)).unwrap() = Light::Source(10);
+        while automata.evolve_counted(rules::light_falloff).changed > 0
+        {
+        }
+        assert_eq!(automata.step(), 10);
+    }
+
+    #[test]
+    fn counts_are_exact_at_the_edges()
+    {
+        let mut rng = SplitMix64::new(8);
+        let mut automata = Automata::new(Grid::from_fn((7, 5), |_| rng.below(6) as u8));
+        for _ in 0..10
+        {
+            let before = automata.current().clone();
+            let summary = automata.evolve_counted(rules::sandpile);
+            let changed = before.data.iter().zip(&automata.current().data).filter(|(old, new)| old != new).count();
+            assert_eq!(summary, StepSummary{changed});
+        }
+    }
+
+    #[test]
+    fn counts_include_what_programs_write()
+    {
+        let mut automata = Automata::new(Grid::new((9, 5), Light::Space(0)));
+        *automata.get_mut((4, 2)).unwrap() = Light::Source(3);
+        while automata.evolve_counted(rules::light_falloff).changed > 0
+        {
+        }
+        // A dark corner turned into a source, twice over, and a program
+        // writing what the lamp already is.
+        automata.add_source_program((0, 0), SourceProgram::Constant(Light::Source(5)));
+        automata.add_source_program((0, 0), SourceProgram::Constant(Light::Source(5)));
+        automata.add_source_program((4, 2), SourceProgram::Constant(Light::Source(3)));
+        for _ in 0..6
+        {
+            let before = automata.current().clone();
+            let summary = automata.evolve_counted(rules::light_falloff);
+            let changed = before.data.iter().zip(&automata.current().data).filter(|(old, new)| old != new).count();
+            assert_eq!(summary, StepSummary{changed});
+        }
+        assert_eq!(automata.evolve_counted(rules::light_falloff).changed, 0);
+    }
+
+    #[test]
+    fn programs_set_their_level_every_step()
+    {
+        let mut automata = Automata::new(Grid::new((9, 5), Light::Space(0)));
+        // On at steps 1, 5, 9.
+        automata.add_source_program((2, 2), SourceProgram::Square{period: 4, duty: 1, phase: 1, on: Light::Source(8), off: Light::Source(0)});
+        automata.add_source_program((6, 2), SourceProgram::Custom(Box::new(|step| Light::Source(step as u8 * 2))));
+        let (mut square, mut custom) = (vec![], vec![]);
+        for _ in 0..10
+        {
+            automata.evolve(rules::light_falloff);
+            square.push(*automata.get((2, 2)).unwrap());
+            custom.push(*automata.get((6, 2)).unwrap());
+        }
+        let on = |step: u64| if step % 4 == 1 {Light::Source(8)} else {Light::Source(0)};
+        assert_eq!(square, (0..10).map(on).collect::<Vec<_>>());
+        assert_eq!(custom, (0..10).map(|step| Light::Source(2*step)).collect::<Vec<_>>());
+        assert_eq!(SourceProgram::Constant(Light::Source(3)).value(7), Light::Source(3));
+    }
+
+    // Frozen on even steps, falling off on odd ones.
+    fn every_other_step(step: u64, cell: &Light, ngh: &[Light]) -> Light
+    {
+        if step.is_multiple_of(2)
+        {
+            *cell
+        }
+        else
+        {
+            let mut all = vec![*cell];
+            all.extend_from_slice(ngh);
+            rules::light_falloff(all)
+        }
+    }
+
+    #[test]
+    fn timed_rules_see_the_step()
+    {
+        let mut automata = Automata::new(Grid::new((15, 9), Light::Space(0)));
+        *automata.get_mut((7, 4)).unwrap() = Light::Source(6);
+        let mut activity = vec![];
+        for _ in 0..8
+        {
+            let before = automata.current().clone();
+            automata.evolve_timed(every_other_step);
+            activity.push(before != *automata.current());
+        }
+        assert_eq!(activity, [false, true, false, true, false, true, false, true]);
+        assert_eq!(automata.step(), 8);
+    }
+
+    #[test]
+    fn the_step_survives_checkpoints_and_rewinds()
+    {
+        let mut automata = Automata::new(seeded_grid(2, (8, 6), random_grain));
+        for _ in 0..7
+        {
+            automata.evolve_reversible(parity, |a, b| a ^ b);
+        }
+        let path = std::env::temp_dir().join(format!("triangle-automata-{}-step.tria", std::process::id()));
+        automata.save_checkpoint(&path).unwrap();
+        let loaded = Automata::<u8>::load_checkpoint(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(loaded.step(), 7);
+        assert_eq!(loaded.current(), automata.current());
+        assert!(automata.step_backward(parity, |a, b| a ^ b));
+        assert_eq!(automata.step(), 6);
+        // A failed step does not count.
+        let failed = automata.try_evolve(|_| Err::<u8, _>("no"));
+        assert!(failed.is_err());
+        assert_eq!(automata.step(), 6);
+    }
+
+    #[test]
+    fn injections_match_manual_edits()
+    {
+        let start = Grid::new((11, 7), Light::Space(0));
+        let mut injected = Automata::new(start.clone());
+        injected.set_injector(|step| match step
+        {
+            5 => vec![((5, 3), Light::Source(4))],
+            10 => vec![((5, 3), Light::Space(0))],
+            _ => vec![]
+        });
+        let mut manual = Automata::new(start);
+        for step in 1..=15
+        {
+            injected.evolve(rules::light_falloff);
+            manual.evolve(rules::light_falloff);
+            match step
+            {
+                5 => *manual.get_mut((5, 3)).unwrap() = Light::Source(4),
+                10 => *manual.get_mut((5, 3)).unwrap() = Light::Space(0),
+                _ => ()
+            }
+            assert_eq!(injected.current(), manual.current(), "after step {}", step);
+        }
+        assert_eq!(injected.get((5, 3)), Some(&Light::Space(0)));
+    }
+
+    #[test]
+    fn out_of_bounds_injections()
+    {
+        let mut automata = Automata::new(Grid::new((4, 3), 0u8));
+        automata.set_injector(|_| vec![((9, 0), 1), ((1, 1), 2)]);
+        automata.evolve(|ngh| ngh[0]);
+        assert!(automata.take_injection_errors().is_empty());
+        automata.set_injection_policy(OutOfBounds::Record);
+        automata.evolve(|ngh| ngh[0]);
+        assert_eq!(automata.take_injection_errors(), [(9, 0)]);
+        assert!(automata.take_injection_errors().is_empty());
+        assert_eq!(automata.get((1, 1)), Some(&2));
+    }
+
+    #[test]
+    fn built_automata_match_the_manual_ones()
+    {
+        let built = AutomataBuilder::new((30, 20))
+            .fill(Light::Space(0))
+            .set((10, 10), Light::Source(10))
+            .set((3, 4), Light::Source(2))
+            .set((3, 4), Light::Source(5))
+            .source_program((0, 0), SourceProgram::Constant(Light::Source(7)))
+            .build()
+            .unwrap();
+        let mut manual = Automata::new(Grid::new((30, 20), Light::Space(0)));
+        *manual.get_mut((10, 10)).unwrap() = Light::Source(10);
+        *manual.get_mut((3, 4)).unwrap() = Light::Source(5);
+        manual.add_source_program((0, 0), SourceProgram::Constant(Light::Source(7)));
+        assert_eq!(built.current(), manual.current());
+        assert_eq!(built.step(), 0);
+
+        let from_fn = Automata::from_fn((30, 20), |coord| *manual.get(coord).unwrap());
+        assert_eq!(from_fn.current(), manual.current());
+
+        let (mut built, mut manual) = (built, manual);
+        for _ in 0..5
+        {
+            built.evolve(rules::light_falloff);
+            manual.evolve(rules::light_falloff);
+        }
+        assert_eq!(built.current(), manual.current());
+        assert_eq!(built.get((0, 0)), Some(&Light::Source(7)));
+    }
+
+    #[test]
+    fn invalid_builds_name_the_offending_value()
+    {
+        let builder = || AutomataBuilder::new((30, 20)).fill(Light::Space(0));
+        assert_eq!(AutomataBuilder::<Light>::new((30, 20)).set((1, 1), Light::Source(1)).build().err(), Some(BuildError::NoFill));
+        assert_eq!(builder().set((1, 1), Light::Source(1)).set((30, 2), Light::Source(9)).build().err(),
+                   Some(BuildError::OutOfBounds{coord: (30, 2), state: Light::Source(9), dims: (30, 20)}));
+        assert_eq!(builder().set((4, 20), Light::Space(3)).build().err(),
+                   Some(BuildError::OutOfBounds{coord: (4, 20), state: Light::Space(3), dims: (30, 20)}));
+        assert_eq!(builder().source_program((2, 25), SourceProgram::Constant(Light::Source(1))).build().err(),
+                   Some(BuildError::ProgramOutOfBounds{coord: (2, 25), dims: (30, 20)}));
+    }
+
+    #[test]
+    fn accessors_follow_the_swapped_buffers()
+    {
+        let start = seeded_grid(8, (11, 7), random_grain);
+        let mut automata = Automata::new(start.clone());
+        assert_eq!(automata.scratch(), &start);
+        automata.evolve(parity);
+        let first = automata.current().clone();
+        assert_ne!(first, start);
+        // After a step, the scratch buffer holds the generation before.
+        assert_eq!(automata.scratch(), &start);
+        for j in 0..7
+        {
+            for i in 0..11
+            {
+                assert_eq!(automata.get((i, j)), first.get((i, j)));
+            }
+        }
+        // Writes land in the generation the next step starts from.
+        *automata.get_mut((4, 3)).unwrap() = 200;
+        let mut edited = first.clone();
+        *edited.get_mut((4, 3)).unwrap() = 200;
+        assert_eq!(automata.current(), &edited);
+        automata.evolve(parity);
+        let mut manual = Automata::new(edited.clone());
+        manual.evolve(parity);
+        assert_eq!(automata.current(), manual.current());
+        assert_eq!(automata.scratch(), &edited);
+        assert_eq!(automata.get((20, 0)), None);
+        assert!(automata.get_mut((0, 7)).is_none());
+    }
+
+    fn buffers(automata: &Automata<u8>) -> Vec<*const u8>
+    {
+        let mut buffers = vec![automata.current().as_slice().as_ptr(), automata.scratch().as_slice().as_ptr()];
+        buffers.sort();
+        buffers
+    }
+
+    #[test]
+    fn resets_leave_nothing_behind()
+    {
+        let start = seeded_grid(2, (11, 7), random_grain);
+        let mut automata = Automata::new(start.clone());
+        automata.enable_ages();
+        for _ in 0..5
+        {
+            automata.evolve(parity);
+        }
+        automata.evolve_reversible(parity, |a, b| a ^ b);
+        assert!(automata.is_reversible());
+        let used = buffers(&automata);
+
+        let other = seeded_grid(3, (11, 7), random_grain);
+        automata.reset_from(&other).unwrap();
+        assert_eq!(automata.step(), 0);
+        assert_eq!(automata.current(), &other);
+        assert_eq!(automata.scratch(), &other);
+        assert!(!automata.is_reversible());
+        assert!(!automata.step_backward(parity, |a, b| a ^ b));
+        assert!(automata.ages().unwrap().data.iter().all(|&age| age == 0));
+        // The buffers are those of the run before, whichever is which.
+        assert!(buffers(&automata).iter().all(|buffer| used.contains(buffer)));
+        // And the run goes on as a new automaton's would.
+        let mut fresh = Automata::new(other.clone());
+        fresh.enable_ages();
+        for _ in 0..3
+        {
+            automata.evolve(parity);
+            fresh.evolve(parity);
+        }
+        assert_eq!(automata.current(), fresh.current());
+        assert_eq!(automata.ages(), fresh.ages());
+
+        assert!(automata.reset_from(&seeded_grid(3, (7, 11), random_grain)).is_err());
+        assert_eq!(automata.step(), 3);
+        let error = automata.reset_from(&Grid::new((3, 3), 0)).unwrap_err();
+        assert_eq!(error, DimMismatch{expected: 77, found: 9});
+    }
+
+    #[test]
+    fn resets_from_a_closure()
+    {
+        let mut automata = Automata::new(seeded_grid(4, (11, 7), random_grain));
+        automata.evolve(parity);
+        let used = buffers(&automata);
+        automata.reset_with(|(i, j)| (i*j) as u8);
+        assert_eq!(automata.current(), &Grid::from_fn((11, 7), |(i, j)| (i*j) as u8));
+        assert_eq!(automata.step(), 0);
+        assert_eq!(buffers(&automata), used);
+    }
+}
@@ -0,0 +1,331 @@
+// Pictures of saved states without writing a program, for the render
+// subcommand:
+//
+//   render --input dir_or_file [--format png|svg|ppm] [--cell-px N]
+//          [--palette NAME] [--evolve rule:steps] [--out dir]
+//
+// Every input is a checkpoint, of light or grain cells, or a pattern of
+// light cells in either text format, told apart by their content. The
+// output of `name.ext` is `name.png` (or .svg, .ppm) in the output
+// directory. A file that cannot be read or drawn is reported and the
+// others go on.
+
+use crate::{Automata, CellState, Grid, Light, Rule};
+use crate::checkpoint;
+use crate::codec::{self, CellCodec};
+use crate::color::ColorMap;
+use crate::image::{self, RenderOptions};
+use crate::pattern::Pattern;
+use crate::plots;
+use crate::registry::RuleRegistry;
+use crate::sweep::RuleConfig;
+
+use std::fmt::{Debug, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format
+{
+    #[default]
+    Png,
+    Svg,
+    Ppm
+}
+
+impl Format
+{
+    pub fn extension(self) -> &'static str
+    {
+        match self
+        {
+            Format::Png => "png",
+            Format::Svg => "svg",
+            Format::Ppm => "ppm"
+        }
+    }
+}
+
+impl FromStr for Format
+{
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String>
+    {
+        match text
+        {
+            "png" => Ok(Format::Png),
+            "svg" => Ok(Format::Svg),
+            "ppm" => Ok(Format::Ppm),
+            other => Err(format!("unknown format '{}' (png, svg or ppm)", other))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderJob
+{
+    // A file, or a directory whose files are all rendered.
+    pub input: PathBuf,
+    pub format: Format,
+    // Side of a triangle in pixels, for png and ppm.
+    pub cell_px: usize,
+    pub colormap: ColorMap,
+    // A registered rule and a number of steps to run before drawing.
+    pub evolve: Option<(String, usize)>,
+    pub out: PathBuf
+}
+
+impl RenderJob
+{
+    pub fn new<P: AsRef<Path>>(input: P) -> Self
+    {
+        Self{
+            input: input.as_ref().to_path_buf(),
+            format: Format::default(),
+            cell_px: RenderOptions::default().cell_px,
+            colormap: ColorMap::Viridis,
+            evolve: None,
+            out: PathBuf::from(".")
+        }
+    }
+}
+
+// `rule:steps`, as given to --evolve.
+pub fn parse_evolve(text: &str) -> Result<(String, usize), String>
+{
+    let (rule, steps) = text.rsplit_once(':').ok_or_else(|| format!("expected rule:steps, not '{}'", text))?;
+    let steps = steps.parse().map_err(|_| format!("invalid step count '{}'", steps))?;
+    Ok((rule.to_string(), steps))
+}
+
+// What an input holds.
+enum Loaded
+{
+    Light(Grid<Light>),
+    Grains(Grid<u8>)
+}
+
+fn load(path: &Path) -> Result<Loaded, String>
+{
+    let bytes = fs::read(path).map_err(|error| error.to_string())?;
+    if bytes.starts_with(b"TRIA")
+    {
+        // The codec id follows the magic and the version.
+        let codec = bytes.get(5..7).map(|id| u16::from_le_bytes([id[0], id[1]]));
+        let loaded = match codec
+        {
+            Some(Light::ID) => checkpoint::decode(&bytes[..]).map(|(grid, _)| Loaded::Light(grid)),
+            Some(u8::ID) => checkpoint::decode(&bytes[..]).map(|(grid, _)| Loaded::Grains(grid)),
+            Some(other) => return Err(match codec::lookup(other)
+            {
+                Some(info) => format!("cannot draw checkpoints of {} cells", info.name),
+                None => format!("checkpoint of unknown cell codec {}", other)
+            }),
+            None => Err(checkpoint::SnapshotError::Truncated)
+        };
+        return loaded.map_err(|error| error.to_string());
+    }
+    let text = String::from_utf8(bytes).map_err(|_| "neither a checkpoint nor a pattern".to_string())?;
+    Pattern::parse_any(&text, str::parse).map(|pattern| Loaded::Light(pattern.cells))
+}
+
+fn evolve<T>(grid: Grid<T>, registry: &RuleRegistry<T>, evolve: &Option<(String, usize)>) -> Result<Grid<T>, String>
+where
+    T: Clone + Display + Copy + Debug + 'static
+{
+    let (name, steps) = match evolve
+    {
+        Some(evolve) => evolve,
+        None => return Ok(grid)
+    };
+    let rule = registry.instantiate(name, &RuleConfig::new(0)).map_err(|error| error.to_string())?;
+    let mut automata = Automata::new(grid);
+    for _ in 0..*steps
+    {
+        automata.evolve(|ngh| rule.apply(ngh));
+    }
+    Ok(automata.current().clone())
+}
+
+fn draw<T, F>(grid: &Grid<T>, value: F, job: &RenderJob, path: &Path) -> Result<(), String>
+where
+    T: Copy + Debug,
+    F: Fn(&T) -> f64
+{
+    let high = grid.data.iter().map(&value).fold(0.0, f64::max);
+    let scale = if high > 0.0 { high } else { 1.0 };
+    let color = |cell: &T| job.colormap.color(value(cell) / scale);
+    let options = RenderOptions{cell_px: job.cell_px, ..RenderOptions::default()};
+    match job.format
+    {
+        Format::Png => image::png(grid, color, &options, path),
+        Format::Ppm => image::ppm(grid, color, &options, path),
+        Format::Svg => plots::heatmap(grid, value, job.colormap, path)
+    }.map_err(|error| error.to_string())
+}
+
+fn render_one(input: &Path, job: &RenderJob) -> Result<PathBuf, String>
+{
+    let stem = input.file_stem().ok_or("no file name")?.to_string_lossy();
+    let path = job.out.join(format!("{}.{}", stem, job.format.extension()));
+    match load(input)?
+    {
+        Loaded::Light(grid) =>
+        {
+            let grid = evolve(grid, &RuleRegistry::<Light>::global(), &job.evolve)?;
+            draw(&grid, |cell| f64::from(cell.level()), job, &path)?;
+        },
+        Loaded::Grains(grid) =>
+        {
+            let grid = evolve(grid, &RuleRegistry::<u8>::global(), &job.evolve)?;
+            draw(&grid, |cell| f64::from(*cell), job, &path)?;
+        }
+    }
+    Ok(path)
+}
+
+// The inputs of the job: the file itself, or the files of the directory
+// in name order, leaving out the ages, rule names and cell rules saved
+// next to checkpoints.
+fn inputs(job: &RenderJob) -> Result<Vec<PathBuf>, String>
+{
+    if !job.input.is_dir()
+    {
+        return Ok(vec![job.input.clone()]);
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(&job.input)
+        .map_err(|error| format!("{}: {}", job.input.display(), error))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| !matches!(path.extension().and_then(|ext| ext.to_str()), Some("ages") | Some("rule") | Some("cells")))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+// An input with what became of it: the file written, or why not.
+pub type Rendered = (PathBuf, Result<PathBuf, String>);
+
+// Every input in turn. Err when the inputs or the output directory cannot
+// be listed or made.
+pub fn run(job: &RenderJob) -> Result<Vec<Rendered>, String>
+{
+    let files = inputs(job)?;
+    fs::create_dir_all(&job.out).map_err(|error| format!("{}: {}", job.out.display(), error))?;
+    Ok(files.into_iter().map(|file| {
+        let result = render_one(&file, job);
+        (file, result)
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::validate;
+
+    fn temp(name: &str) -> PathBuf
+    {
+        std::env::temp_dir().join(format!("triangle-automata-{}-{}", std::process::id(), name))
+    }
+
+    fn lamp() -> Grid<Light>
+    {
+        validate::lamp((12, 6), (5, 2), 6)
+    }
+
+    fn scene(dir: &Path)
+    {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("lamp.tria"), checkpoint::encode(&lamp(), 0)).unwrap();
+        fs::write(dir.join("lamp.ages"), b"left out").unwrap();
+        fs::write(dir.join("pile.tria"), checkpoint::encode(&Grid::from_fn((8, 4), |(i, j)| ((i + j) % 5) as u8), 3)).unwrap();
+        let pattern = Pattern{cells: lamp(), parity: 0};
+        fs::write(dir.join("text.pattern"), pattern.to_text(|cell| format!("{:?}", cell))).unwrap();
+        fs::write(dir.join("broken.tria"), &checkpoint::encode(&lamp(), 0)[..12]).unwrap();
+        fs::write(dir.join("noise.bin"), [0xff, 0xfe, 0x00]).unwrap();
+    }
+
+    #[test]
+    fn bad_files_do_not_stop_the_batch()
+    {
+        let (input, out) = (temp("batch-in"), temp("batch-out"));
+        scene(&input);
+        let job = RenderJob{out: out.clone(), ..RenderJob::new(&input)};
+        let rendered = run(&job);
+        let written: Vec<String> = fs::read_dir(&out).map(|entries| {
+            let mut names: Vec<String> = entries.map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+            names.sort();
+            names
+        }).unwrap_or_default();
+        fs::remove_dir_all(&input).unwrap();
+        fs::remove_dir_all(&out).unwrap();
+
+        let rendered = rendered.unwrap();
+        let names: Vec<String> = rendered.iter().map(|(path, _)| path.file_name().unwrap().to_string_lossy().into_owned()).collect();
+        assert_eq!(names, ["broken.tria", "lamp.tria", "noise.bin", "pile.tria", "text.pattern"]);
+        assert_eq!(rendered[0].1, Err("the snapshot is cut short".to_string()));
+        assert_eq!(rendered[1].1, Ok(out.join("lamp.png")));
+        assert_eq!(rendered[2].1, Err("neither a checkpoint nor a pattern".to_string()));
+        assert_eq!(rendered[3].1, Ok(out.join("pile.png")));
+        assert_eq!(rendered[4].1, Ok(out.join("text.png")));
+        assert_eq!(written, ["lamp.png", "pile.png", "text.png"]);
+    }
+
+    #[test]
+    fn files_are_evolved_then_drawn()
+    {
+        let (input, out, expected) = (temp("lamp-in.tria"), temp("lamp-out"), temp("lamp-expected.ppm"));
+        fs::write(&input, checkpoint::encode(&lamp(), 0)).unwrap();
+        let job = RenderJob{format: Format::Ppm, cell_px: 5, evolve: Some(("falloff".to_string(), 4)), out: out.clone(), ..RenderJob::new(&input)};
+        let rendered = run(&job);
+        let mut automata = Automata::new(lamp());
+        for _ in 0..4
+        {
+            automata.evolve(crate::rules::light_falloff);
+        }
+        let color = |cell: &Light| ColorMap::Viridis.color(f64::from(cell.level()) / 6.0);
+        image::ppm(automata.current(), color, &RenderOptions{cell_px: 5, ..RenderOptions::default()}, &expected).unwrap();
+        let (drawn, wanted) = (fs::read(out.join(format!("triangle-automata-{}-lamp-in.ppm", std::process::id()))), fs::read(&expected));
+        let unknown = run(&RenderJob{evolve: Some(("nope".to_string(), 1)), ..job.clone()});
+        for path in [&input, &expected]
+        {
+            fs::remove_file(path).unwrap();
+        }
+        fs::remove_dir_all(&out).unwrap();
+
+        assert_eq!(rendered.unwrap().len(), 1);
+        assert_eq!(drawn.unwrap(), wanted.unwrap());
+        assert!(unknown.unwrap()[0].1.as_ref().unwrap_err().contains("nope"));
+    }
+
+    #[test]
+    fn svg_and_missing_inputs()
+    {
+        let (input, out) = (temp("svg-in.pattern"), temp("svg-out"));
+        fs::write(&input, Pattern{cells: lamp(), parity: 0}.to_text(|cell| format!("{:?}", cell))).unwrap();
+        let rendered = run(&RenderJob{format: Format::Svg, out: out.clone(), ..RenderJob::new(&input)});
+        let svg = fs::read_to_string(out.join(format!("triangle-automata-{}-svg-in.svg", std::process::id())));
+        let missing = run(&RenderJob{out: out.clone(), ..RenderJob::new(temp("absent.tria"))});
+        fs::remove_file(&input).unwrap();
+        fs::remove_dir_all(&out).unwrap();
+
+        assert!(rendered.unwrap()[0].1.is_ok());
+        assert!(svg.unwrap().starts_with("<svg"));
+        assert!(missing.unwrap()[0].1.is_err());
+    }
+
+    #[test]
+    fn formats_and_evolve_specs()
+    {
+        assert_eq!("svg".parse(), Ok(Format::Svg));
+        assert_eq!("gif".parse::<Format>(), Err("unknown format 'gif' (png, svg or ppm)".to_string()));
+        assert_eq!(Format::Ppm.extension(), "ppm");
+        assert_eq!(parse_evolve("decay:12"), Ok(("decay".to_string(), 12)));
+        assert_eq!(parse_evolve("a:b:3"), Ok(("a:b".to_string(), 3)));
+        assert_eq!(parse_evolve("decay"), Err("expected rule:steps, not 'decay'".to_string()));
+        assert_eq!(parse_evolve("decay:-1"), Err("invalid step count '-1'".to_string()));
+    }
+}
@@ -0,0 +1,339 @@
+// What cells at the edges of a grid see past them. The plain steps leave
+// neighbors outside of the grid out, so that edge cells see fewer of them
+// and light past the edges is gone: that is Open. The others give every
+// cell its three edge neighbors:
+//
+//  * Fixed(state): the cells past the edges are all in `state`;
+//  * Periodic: opposite edges are glued, the grid is a torus. An axis of
+//    odd size cannot wrap, the triangles on both sides pointing the same
+//    way, so a periodic boundary on a grid with one is an error;
+//  * Reflective: the cell past an edge is the cell's own mirror image
+//    across it, the cell itself, so that waves bounce off the edges.
+//
+// An automaton has a boundary, Open until set_boundary, which every step
+// follows: evolve and the steps built on next_generation (evolve_timed,
+// evolve_aged, evolve_stochastic...), evolve_par, evolve_reversible and
+// step_backward, evolve_budgeted, evolve_within, whose region goes on
+// across the edges of a periodic grid, and evolve_blocks, whose pairs
+// then wrap around the rows; so Simulation and Session. The vertex
+// neighborhood is given the cells past the edges the same way; a radius
+// neighborhood goes on across the edges of a periodic grid, and leaves
+// the cells past the others out. The flux of flux.rs, the graphs of
+// graph.rs and the seams of seam.rs take their boundary from here too.
+
+use crate::{Automata, Grid, Slot};
+use crate::error::GridError;
+use crate::flux::{self, Side};
+
+use std::fmt::{Debug, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BoundaryCondition<T>
+{
+    #[default]
+    Open,
+    Fixed(T),
+    Periodic,
+    Reflective
+}
+
+impl<T> BoundaryCondition<T>
+{
+    // An error for a periodic boundary on dims with an odd axis.
+    pub fn check(&self, dims: (usize, usize)) -> Result<(), GridError>
+    {
+        match self
+        {
+            BoundaryCondition::Periodic if !(dims.0.is_multiple_of(2) && dims.1.is_multiple_of(2)) => Err(GridError::OddPeriodic(dims)),
+            _ => Ok(())
+        }
+    }
+
+    pub fn is_periodic(&self) -> bool
+    {
+        matches!(self, BoundaryCondition::Periodic)
+    }
+}
+
+impl<T: Copy + Debug> Grid<T>
+{
+    // The cell, then its neighbors across the edges as in
+    // Grid::neighborhood, those past the edges given by `boundary`, and
+    // the sides of the grid those are past. None for a cell outside of the
+    // grid. The boundary must suit the dims.
+    pub(crate) fn neighborhood_past_edges(&self, coord: (usize, usize), boundary: &BoundaryCondition<T>) -> Option<(Vec<T>, Vec<Side>)>
+    {
+        let cell = *self.get(coord)?;
+        let mut ngh = vec![cell];
+        let mut past = vec![];
+        for &slot in &Slot::ALL
+        {
+            match flux::neighbor(self.dims, coord, slot, boundary)
+            {
+                Ok(ncoord) => ngh.push(*self.get(ncoord).unwrap()),
+                Err(side) =>
+                {
+                    past.push(side);
+                    match boundary
+                    {
+                        BoundaryCondition::Fixed(state) => ngh.push(*state),
+                        BoundaryCondition::Reflective => ngh.push(cell),
+                        _ => ()
+                    }
+                }
+            }
+        }
+        Some((ngh, past))
+    }
+
+    // The cell, then its neighbors across the edges as in
+    // Grid::neighborhood, those past the edges of the grid given by
+    // `boundary`.
+    pub fn neighborhood_with_boundary(&self, coord: (usize, usize), boundary: &BoundaryCondition<T>) -> Result<Vec<T>, GridError>
+    {
+        boundary.check(self.dims)?;
+        self.neighborhood_past_edges(coord, boundary)
+            .map(|(ngh, _)| ngh)
+            .ok_or(GridError::OutOfBounds{coord, dims: self.dims})
+    }
+
+    // What the steps give rules: Grid::neighborhood under Open, the
+    // neighborhood past the edges otherwise. For cells of the grid, under
+    // a boundary the dims can have.
+    pub(crate) fn neighborhood_under(&self, coord: (usize, usize), boundary: &BoundaryCondition<T>) -> Vec<T>
+    {
+        match boundary
+        {
+            BoundaryCondition::Open => self.neighborhood(coord).into_iter().cloned().collect(),
+            _ => self.neighborhood_past_edges(coord, boundary).expect("the cells stepped are in the grid").0
+        }
+    }
+}
+
+impl<T: Clone + Display + Copy + Debug> Automata<T>
+{
+    // The boundary of the steps from the next one on; an error, and the
+    // boundary left as it was, if the dims cannot have it.
+    pub fn set_boundary(&mut self, boundary: BoundaryCondition<T>) -> Result<(), GridError>
+    {
+        boundary.check(self.current.dims)?;
+        self.boundary = boundary;
+        self.invalidate_rows();
+        Ok(())
+    }
+
+    pub fn boundary(&self) -> &BoundaryCondition<T>
+    {
+        &self.boundary
+    }
+
+    // One step of evolve under `boundary` instead of the automaton's; an
+    // error, and no step, if the dims cannot have it.
+    pub fn evolve_with_boundary<F>(&mut self, rule: F, boundary: &BoundaryCondition<T>) -> Result<(), GridError>
+    where
+        F: Fn(Vec<T>) -> T
+    {
+        boundary.check(self.current.dims)?;
+        let kept = std::mem::replace(&mut self.boundary, *boundary);
+        self.evolve(rule);
+        self.boundary = kept;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, validate, Light};
+    use crate::neighborhood::NeighborhoodKind;
+    use crate::rng::SplitMix64;
+
+    use std::time::Duration;
+
+    fn lamps(seed: u64) -> Grid<Light>
+    {
+        validate::seeded_grid(seed, (12, 8), |rng| if rng.below(10) == 0 { Light::Source(4 + rng.below(8) as u8) } else { Light::Space(0) })
+    }
+
+    type Step = fn(&mut Automata<Light>);
+
+    fn run(start: Grid<Light>, boundary: &BoundaryCondition<Light>, steps: usize) -> Grid<Light>
+    {
+        let mut automata = Automata::new(start);
+        for _ in 0..steps
+        {
+            automata.evolve_with_boundary(rules::light_falloff, boundary).unwrap();
+        }
+        automata.current().clone()
+    }
+
+    #[test]
+    fn corners_see_past_the_edges()
+    {
+        // (0, 0) points up: its left neighbor is past the edge, (3, 0) on
+        // the torus.
+        let grid = Grid::from_fn((4, 2), |(i, j)| (i + 10*j) as u8);
+        let cases = [
+            (BoundaryCondition::Open, vec![0, 1, 10]),
+            (BoundaryCondition::Fixed(99), vec![0, 99, 1, 10]),
+            (BoundaryCondition::Reflective, vec![0, 0, 1, 10]),
+            (BoundaryCondition::Periodic, vec![0, 3, 1, 10])
+        ];
+        for (boundary, expected) in &cases
+        {
+            assert_eq!(grid.neighborhood_with_boundary((0, 0), boundary).as_ref(), Ok(expected), "{:?}", boundary);
+        }
+        // (1, 0) points down, the cell across its top edge is (1, 1).
+        assert_eq!(grid.neighborhood_with_boundary((1, 0), &BoundaryCondition::Periodic), Ok(vec![1, 0, 2, 11]));
+        assert_eq!(grid.neighborhood_with_boundary((4, 0), &BoundaryCondition::Fixed(99)), Err(GridError::OutOfBounds{coord: (4, 0), dims: (4, 2)}));
+    }
+
+    #[test]
+    fn odd_axes_cannot_wrap()
+    {
+        for &dims in &[(5, 4), (4, 3), (1, 1)]
+        {
+            let grid = Grid::new(dims, 0u8);
+            assert_eq!(grid.neighborhood_with_boundary((0, 0), &BoundaryCondition::Periodic), Err(GridError::OddPeriodic(dims)));
+            let mut automata = Automata::new(grid);
+            assert_eq!(automata.set_boundary(BoundaryCondition::Periodic), Err(GridError::OddPeriodic(dims)));
+            assert_eq!(automata.evolve_with_boundary(|ngh| ngh[0] + 1, &BoundaryCondition::Periodic), Err(GridError::OddPeriodic(dims)));
+            // Neither stepped nor left wrapping.
+            assert_eq!((automata.step(), automata.boundary()), (0, &BoundaryCondition::Open));
+            assert!(BoundaryCondition::Fixed(0).check(dims).is_ok());
+        }
+        assert!(GridError::OddPeriodic((5, 4)).to_string().contains("5x4"));
+    }
+
+    #[test]
+    fn periodic_runs_commute_with_shifts()
+    {
+        let start = lamps(3);
+        // Shifting by (3, 1) keeps every triangle pointing the same way.
+        let shift = |grid: &Grid<Light>| Grid::from_fn(grid.dims, |(i, j)| *grid.get_signed_wrapped((i as isize - 3, j as isize - 1), &BoundaryCondition::Periodic).unwrap());
+        assert_eq!(run(shift(&start), &BoundaryCondition::Periodic, 10), shift(&run(start.clone(), &BoundaryCondition::Periodic, 10)));
+        let mut automata = Automata::new(start.clone());
+        for _ in 0..10
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        assert_eq!(run(start, &BoundaryCondition::Open, 10), *automata.current());
+    }
+
+    #[test]
+    fn evolve_follows_the_boundary_it_is_set()
+    {
+        let start = lamps(5);
+        let mut automata = Automata::new(start.clone());
+        automata.enable_row_skipping();
+        automata.set_boundary(BoundaryCondition::Reflective).unwrap();
+        // Cell rules still apply: the corner stays dark.
+        automata.set_cell_rule((0, 0), |_: Vec<Light>| Light::Space(0));
+        for _ in 0..10
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        let mut expected = start;
+        for _ in 0..10
+        {
+            let mut next = run(expected, &BoundaryCondition::Reflective, 1);
+            *next.get_mut((0, 0)).unwrap() = Light::Space(0);
+            expected = next;
+        }
+        assert_eq!(automata.current(), &expected);
+        assert_eq!(automata.rows_skipped(), 0);
+        // Back to open edges.
+        automata.set_boundary(BoundaryCondition::Open).unwrap();
+        let mut open = Automata::new(automata.current().clone());
+        open.evolve(rules::light_falloff);
+        automata.clear_cell_rules();
+        automata.evolve(rules::light_falloff);
+        assert_eq!(automata.current(), open.current());
+    }
+
+    #[test]
+    fn every_step_follows_the_boundary()
+    {
+        let start = lamps(9);
+        // additive, unlike falloff, notices a reflected cell seeing itself
+        let additive = |start: Grid<Light>, boundary: &BoundaryCondition<Light>| {
+            let mut automata = Automata::new(start);
+            for _ in 0..5
+            {
+                automata.evolve_with_boundary(rules::light_additive, boundary).unwrap();
+            }
+            automata.current().clone()
+        };
+        for boundary in [BoundaryCondition::Periodic, BoundaryCondition::Fixed(Light::Space(3)), BoundaryCondition::Reflective]
+        {
+            let expected = additive(start.clone(), &boundary);
+            assert_ne!(expected, additive(start.clone(), &BoundaryCondition::Open), "{:?}", boundary);
+            let steps: [(&str, Step); 9] = [
+                ("timed", |automata| automata.evolve_timed(|_, cell, neighbors| rules::light_additive([&[*cell], neighbors].concat()))),
+                ("aged", |automata| automata.evolve_aged(|_, ngh| rules::light_additive(ngh))),
+                ("stochastic", |automata| automata.evolve_stochastic(|_, ngh| rules::light_additive(ngh), 1)),
+                ("par", |automata| automata.evolve_par(rules::light_additive, 3)),
+                ("stochastic par", |automata| automata.evolve_stochastic_par(|_, ngh| rules::light_additive(ngh), 1, 3)),
+                ("reversible", |automata| automata.evolve_reversible(rules::light_additive, |new, _| new)),
+                ("budgeted", |automata| assert!(automata.evolve_budgeted(rules::light_additive, Duration::from_secs(60)).completed)),
+                ("within", |automata| automata.evolve_within(rules::light_additive, &[(0, 0)], 100).unwrap()),
+                ("with", |automata| automata.evolve_with(|cell, neighbors| rules::light_additive([&[*cell], neighbors].concat()), NeighborhoodKind::Edge))
+            ];
+            for (name, step) in &steps
+            {
+                let mut automata = Automata::new(start.clone());
+                automata.set_boundary(boundary).unwrap();
+                for _ in 0..5
+                {
+                    step(&mut automata);
+                }
+                assert_eq!(automata.current(), &expected, "{} under {:?}", name, boundary);
+            }
+        }
+    }
+
+    #[test]
+    fn steps_undo_on_the_torus()
+    {
+        let mut rng = SplitMix64::new(4);
+        let start = Grid::from_fn((8, 6), |_| rng.next_u64() as u8);
+        let parity = |ngh: Vec<u8>| ngh.iter().fold(0, |acc, &cell| acc ^ cell.rotate_left(1));
+        let mut automata = Automata::new(start.clone());
+        automata.set_boundary(BoundaryCondition::Periodic).unwrap();
+        for _ in 0..20
+        {
+            automata.evolve_reversible(parity, |a, b| a ^ b);
+        }
+        let mut open = Automata::new(start.clone());
+        for _ in 0..20
+        {
+            open.evolve_reversible(parity, |a, b| a ^ b);
+        }
+        assert_ne!(automata.current(), open.current());
+        for _ in 0..20
+        {
+            assert!(automata.step_backward(parity, |a, b| a ^ b));
+        }
+        assert_eq!(automata.current(), &start);
+    }
+
+    #[test]
+    fn blocks_wrap_around_the_rows()
+    {
+        let swap = |(left, right): (u8, u8)| (right, left);
+        let row = |automata: &Automata<u8>| (0..4).map(|i| *automata.get((i, 0)).unwrap()).collect::<Vec<_>>();
+        let mut wrapped = Automata::new(Grid::from_fn((4, 2), |(i, _)| i as u8));
+        wrapped.set_boundary(BoundaryCondition::Periodic).unwrap();
+        let mut open = Automata::new(Grid::from_fn((4, 2), |(i, _)| i as u8));
+        for automata in [&mut wrapped, &mut open]
+        {
+            automata.evolve_blocks(swap);
+            assert_eq!(row(automata), [1, 0, 3, 2]);
+            automata.evolve_blocks(swap);
+        }
+        // (3, 0) pairs with (0, 0) across the edge.
+        assert_eq!(row(&wrapped), [2, 3, 0, 1]);
+        assert_eq!(row(&open), [1, 3, 0, 2]);
+    }
+}
@@ -0,0 +1,184 @@
+// Steps cut into pieces, for interfaces that cannot wait for a whole
+// generation of a huge grid between two frames: evolve_budgeted computes
+// rows into the scratch buffer until its budget runs out, and the next call
+// goes on from the row it stopped at. The current generation is only
+// replaced once every row is computed, so get never sees half a step.
+//
+// Source programs are applied as the other steps do, the rows reading what
+// they write, but only while a call is computing: a partial call leaves
+// their cells as they were. Cell overrides are honoured; rows are never
+// skipped. Editing the automaton (get_mut, loads,
+// restarts) or stepping it any other way drops the step in progress, which
+// the next call starts over.
+
+use crate::Automata;
+
+use std::fmt::{Debug, Display};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetResult
+{
+    // Whether the call finished a step.
+    pub completed: bool,
+    // Cells computed by the call.
+    pub cells: usize
+}
+
+impl<T: Clone + Display + Copy + Debug> Automata<T>
+{
+    // At least one row per call, then more until `budget` has passed.
+    pub fn evolve_budgeted<F>(&mut self, rule: F, budget: Duration) -> BudgetResult
+    where
+        F: Fn(Vec<T>) -> T
+    {
+        let started = Instant::now();
+        let first = self.pending_row.unwrap_or(0);
+        let unprogrammed: Vec<((usize, usize), T)> = self.programs.iter()
+            .filter_map(|(coord, _)| self.current.get(*coord).map(|cell| (*coord, *cell)))
+            .collect();
+        self.apply_source_programs();
+        let (w, h) = self.current.dims;
+        let mut cells = 0;
+        let mut j = first;
+        while j < h
+        {
+            for i in 0..w
+            {
+                let ngh = self.current.neighborhood_under((i, j), &self.boundary);
+                self.scratch.data[j*w + i] = match self.cell_rules.get(&(i, j))
+                {
+                    Some(cell_rule) => cell_rule.apply(ngh),
+                    None => rule(ngh)
+                };
+            }
+            cells += w;
+            j += 1;
+            if started.elapsed() >= budget
+            {
+                break;
+            }
+        }
+        if j < h
+        {
+            for (coord, cell) in unprogrammed
+            {
+                self.current.data[coord.1*w + coord.0] = cell;
+            }
+            self.pending_row = Some(j);
+            return BudgetResult{completed: false, cells};
+        }
+        std::mem::swap(&mut self.current, &mut self.scratch);
+        self.previous = None;
+        self.finish_step();
+        BudgetResult{completed: true, cells}
+    }
+
+    // Whether a budgeted step is part way through.
+    pub fn step_pending(&self) -> bool
+    {
+        self.pending_row.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, CellState, Light, SourceProgram};
+    use crate::validate::{random_light, seeded_grid};
+
+    // A random light grid with a source program and an injector.
+    fn programmed(seed: u64, dims: (usize, usize)) -> Automata<Light>
+    {
+        let mut automata = Automata::new(seeded_grid(seed, dims, random_light));
+        automata.add_source_program((0, 0), SourceProgram::Square{period: 6, duty: 3, phase: 0, on: Light::Source(9), off: Light::Space(0)});
+        automata.set_injector(move |step| vec![(((step*5) as usize % dims.0, (step*3) as usize % dims.1), Light::Source(7))]);
+        automata
+    }
+
+    #[test]
+    fn row_at_a_time_matches_evolve()
+    {
+        for seed in 0..5
+        {
+            let (w, h) = (17, 9);
+            let (mut plain, mut budgeted) = (programmed(seed, (w, h)), programmed(seed, (w, h)));
+            for step in 1..=30
+            {
+                plain.evolve(rules::light_falloff);
+                // Without a budget, a row per call, the grid unchanged
+                // until the last one, the cell of the source program
+                // included.
+                let before = budgeted.current().clone();
+                for row in 1..h
+                {
+                    assert_eq!(budgeted.evolve_budgeted(rules::light_falloff, Duration::ZERO), BudgetResult{completed: false, cells: w});
+                    assert!(budgeted.step_pending());
+                    assert_eq!(budgeted.step(), step - 1, "row {}", row);
+                    assert_eq!(budgeted.current(), &before, "row {}", row);
+                }
+                assert_eq!(budgeted.evolve_budgeted(rules::light_falloff, Duration::ZERO), BudgetResult{completed: true, cells: w});
+                assert!(!budgeted.step_pending());
+                assert_eq!(budgeted.current(), plain.current(), "seed {}, step {}", seed, step);
+            }
+        }
+    }
+
+    #[test]
+    fn ample_budgets_make_whole_steps()
+    {
+        let (mut plain, mut budgeted) = (programmed(7, (40, 30)), programmed(7, (40, 30)));
+        for _ in 0..10
+        {
+            plain.evolve(rules::light_falloff);
+            assert_eq!(budgeted.evolve_budgeted(rules::light_falloff, Duration::from_secs(60)), BudgetResult{completed: true, cells: 40*30});
+        }
+        assert_eq!(budgeted.current(), plain.current());
+        assert_eq!(budgeted.step(), 10);
+    }
+
+    #[test]
+    fn edits_start_the_step_over()
+    {
+        let (mut plain, mut budgeted) = (programmed(2, (12, 6)), programmed(2, (12, 6)));
+        for _ in 0..3
+        {
+            budgeted.evolve_budgeted(rules::light_falloff, Duration::ZERO);
+        }
+        *budgeted.get_mut((6, 3)).unwrap() = Light::Source(11);
+        assert!(!budgeted.step_pending());
+        *plain.get_mut((6, 3)).unwrap() = Light::Source(11);
+        plain.evolve(rules::light_falloff);
+        let mut calls = 1;
+        while !budgeted.evolve_budgeted(rules::light_falloff, Duration::ZERO).completed
+        {
+            calls += 1;
+        }
+        assert_eq!(calls, 6);
+        assert_eq!(budgeted.current(), plain.current());
+
+        // As does stepping any other way.
+        budgeted.evolve_budgeted(rules::light_falloff, Duration::ZERO);
+        budgeted.evolve(rules::light_falloff);
+        plain.evolve(rules::light_falloff);
+        assert!(!budgeted.step_pending());
+        assert_eq!(budgeted.current(), plain.current());
+    }
+
+    #[test]
+    fn overrides_are_honoured()
+    {
+        let (mut plain, mut budgeted) = (programmed(4, (10, 5)), programmed(4, (10, 5)));
+        for automata in [&mut plain, &mut budgeted]
+        {
+            automata.set_cell_rule((3, 2), |ngh: Vec<Light>| if ngh[0].level() > 0 { Light::Source(1) } else { ngh[0] });
+        }
+        for _ in 0..8
+        {
+            plain.evolve(rules::light_falloff);
+            while !budgeted.evolve_budgeted(rules::light_falloff, Duration::ZERO).completed {}
+        }
+        assert_eq!(budgeted.current(), plain.current());
+    }
+}
@@ -0,0 +1,254 @@
+// Rules of their own for a few cells: sticky cells fading faster than the
+// field around them, sensors that never change... Every step given a rule
+// for the neighborhood (evolve and the steps built on next_generation,
+// evolve_with, the parallel, reversible, budgeted and hotspot steps) gives
+// an overridden cell's neighborhood to its rule instead of the global one.
+// Under evolve_with, that is the cell then its neighbors of the kind.
+// Block steps, mapping pairs of cells, leave them aside. Without overrides,
+// evolve goes on as before; with some, it computes every cell (no row
+// skipping).
+//
+// Checkpoints hold the overrides with a registered name (see
+// checkpoint.rs), the rule written as registry::rule_line does, for
+// restore_cell_rules to instantiate again with the same parameters. Other
+// overrides are not saved.
+
+use crate::{Automata, Rule};
+use crate::checkpoint::{self, SnapshotError};
+use crate::codec::CellCodec;
+use crate::registry::{self, RuleRegistry};
+
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+pub type CellRules<T> = BTreeMap<(usize, usize), Box<dyn Rule<T>>>;
+
+// How a rule is saved next to a checkpoint: its name and, when it has
+// them, its parameters; None for rules without a name.
+pub(crate) fn saved_rule<T, R: Rule<T> + ?Sized>(rule: &R) -> Option<String>
+{
+    let name = rule.name()?;
+    Some(match rule.config()
+    {
+        Some(config) => registry::rule_line(name, config),
+        None => name.to_string()
+    })
+}
+
+impl<T: Clone + Display + Copy + Debug> Automata<T>
+{
+    // Replaces the override of the cell, if any; false, and nothing done,
+    // outside of the grid.
+    pub fn set_cell_rule<R: Rule<T> + 'static>(&mut self, coord: (usize, usize), rule: R) -> bool
+    {
+        if self.current.get(coord).is_none()
+        {
+            return false;
+        }
+        self.cell_rules.insert(coord, Box::new(rule));
+        true
+    }
+
+    pub fn clear_cell_rule(&mut self, coord: (usize, usize)) -> Option<Box<dyn Rule<T>>>
+    {
+        self.cell_rules.remove(&coord)
+    }
+
+    pub fn clear_cell_rules(&mut self)
+    {
+        self.cell_rules.clear();
+    }
+
+    pub fn cell_rules(&self) -> &CellRules<T>
+    {
+        &self.cell_rules
+    }
+
+    // Runs `step` with the overrides taken out of the automaton, for the
+    // rule it steps with to look them up.
+    pub(crate) fn with_cell_rules<R, S>(&mut self, step: S) -> R
+    where
+        S: FnOnce(&mut Self, &CellRules<T>) -> R
+    {
+        let overrides = std::mem::take(&mut self.cell_rules);
+        let result = step(self, &overrides);
+        self.cell_rules = overrides;
+        result
+    }
+
+    // The overrides with a name, as a checkpoint holds them.
+    pub(crate) fn saved_cell_rules(&self) -> checkpoint::SavedCellRules
+    {
+        self.cell_rules.iter()
+            .filter_map(|(&coord, rule)| saved_rule(&**rule).map(|line| (coord, line)))
+            .collect()
+    }
+}
+
+impl<T: Clone + Display + Copy + Debug + CellCodec + 'static> Automata<T>
+{
+    // The overrides saved in the checkpoint at `path`, in place of the
+    // current ones, and how many there were.
+    pub fn restore_cell_rules<P: AsRef<Path>>(&mut self, path: P, registry: &RuleRegistry<T>) -> Result<usize, SnapshotError>
+    {
+        let (_, _, saved) = checkpoint::decode_with_rules::<T, _>(BufReader::new(File::open(path)?))?;
+        let mut overrides = CellRules::new();
+        for (n, (coord, line)) in saved.into_iter().enumerate()
+        {
+            if self.current.get(coord).is_none()
+            {
+                return Err(SnapshotError::BadCellRule(n + 1));
+            }
+            let rule = registry.instantiate_line(&line).map_err(|reason| SnapshotError::BadRule{rule: line, reason})?;
+            overrides.insert(coord, rule);
+        }
+        self.cell_rules = overrides;
+        Ok(self.cell_rules.len())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, CellState, Light};
+    use crate::neighborhood::NeighborhoodKind;
+    use crate::sweep::RuleConfig;
+    use crate::validate;
+
+    use std::fs;
+    use std::time::Duration;
+
+    fn lamp() -> Automata<Light>
+    {
+        Automata::new(validate::lamp((15, 9), (7, 4), 12))
+    }
+
+    fn deviations(a: &Automata<Light>, b: &Automata<Light>) -> Vec<(usize, usize)>
+    {
+        let w = a.current().dims.0;
+        (0..a.current().data.len()).filter(|&k| a.current().data[k] != b.current().data[k]).map(|k| (k % w, k / w)).collect()
+    }
+
+    #[test]
+    fn only_the_overridden_cell_deviates()
+    {
+        // A sensor in the corner, lit last: nothing else takes its light
+        // from it, so it alone stays dark.
+        let (mut reference, mut overridden) = (lamp(), lamp());
+        assert!(overridden.set_cell_rule((0, 0), |ngh: Vec<Light>| ngh[0]));
+        for _ in 0..30
+        {
+            reference.evolve(rules::light_falloff);
+            overridden.evolve(rules::light_falloff);
+            assert!(deviations(&reference, &overridden).iter().all(|&coord| coord == (0, 0)));
+        }
+        assert!(reference.current().get((0, 0)).unwrap().level() > 0);
+        assert_eq!(deviations(&reference, &overridden), vec![(0, 0)]);
+        assert_eq!(overridden.current().get((0, 0)), Some(&Light::Space(0)));
+
+        // Cleared, the cell follows the global rule again.
+        assert!(overridden.clear_cell_rule((0, 0)).is_some());
+        assert!(overridden.cell_rules().is_empty());
+        reference.evolve(rules::light_falloff);
+        overridden.evolve(rules::light_falloff);
+        assert_eq!(deviations(&reference, &overridden), vec![]);
+    }
+
+    #[test]
+    fn overrides_see_the_global_inputs()
+    {
+        let (mut reference, mut overridden) = (lamp(), lamp());
+        assert!(overridden.set_cell_rule((6, 4), rules::light_decay::<Light>(2)));
+        assert!(!overridden.set_cell_rule((15, 0), rules::light_decay::<Light>(2)));
+        assert_eq!(overridden.cell_rules().len(), 1);
+        let ngh = reference.current().neighborhood((6, 4)).into_iter().cloned().collect();
+        let expected = rules::light_decay::<Light>(2)(ngh);
+        reference.evolve(rules::light_falloff);
+        overridden.evolve(rules::light_falloff);
+        assert_eq!(deviations(&reference, &overridden), vec![(6, 4)]);
+        assert_eq!(overridden.current().get((6, 4)), Some(&expected));
+        overridden.clear_cell_rules();
+        assert!(overridden.cell_rules().is_empty());
+    }
+
+    type Step = fn(&mut Automata<Light>);
+
+    #[test]
+    fn every_step_follows_the_overrides()
+    {
+        // A sensor next to the lamp, which the global rule lights at once.
+        let steps: [(&str, Step); 12] = [
+            ("evolve", |automata| automata.evolve(rules::light_falloff)),
+            ("timed", |automata| automata.evolve_timed(|_, cell, neighbors| rules::light_falloff([&[*cell], neighbors].concat()))),
+            ("aged", |automata| automata.evolve_aged(|_, ngh| rules::light_falloff(ngh))),
+            ("stochastic", |automata| automata.evolve_stochastic(|_, ngh| rules::light_falloff(ngh), 1)),
+            ("par", |automata| automata.evolve_par(rules::light_falloff, 3)),
+            ("stochastic par", |automata| automata.evolve_stochastic_par(|_, ngh| rules::light_falloff(ngh), 1, 3)),
+            ("reversible", |automata| automata.evolve_reversible(rules::light_falloff, |new, _| new)),
+            ("budgeted", |automata| assert!(automata.evolve_budgeted(rules::light_falloff, Duration::from_secs(60)).completed)),
+            ("within", |automata| automata.evolve_within(rules::light_falloff, &[(7, 4)], 20).unwrap()),
+            ("bounded", |automata| automata.evolve_bounded(rules::light_falloff, |_| 0.0)),
+            ("vertex", |automata| automata.evolve_rule(&rules::light_falloff::<Light>, NeighborhoodKind::Vertex)),
+            ("radius", |automata| automata.evolve_rule(&rules::light_falloff::<Light>, NeighborhoodKind::Radius(2)))
+        ];
+        for (name, step) in &steps
+        {
+            let (mut reference, mut overridden) = (lamp(), lamp());
+            assert!(overridden.set_cell_rule((6, 4), |ngh: Vec<Light>| ngh[0]));
+            for _ in 0..3
+            {
+                step(&mut reference);
+                step(&mut overridden);
+            }
+            assert!(reference.current().get((6, 4)).unwrap().level() > 0, "{}", name);
+            assert_eq!(overridden.current().get((6, 4)), Some(&Light::Space(0)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn named_overrides_are_saved_in_the_checkpoint()
+    {
+        let path = std::env::temp_dir().join(format!("triangle-automata-{}-cell-rules", std::process::id()));
+        let registry = RuleRegistry::<Light>::global();
+        let mut automata = lamp();
+        automata.set_cell_rule((3, 2), registry.instantiate("decay", &RuleConfig::new(4).with("amount", 3.0)).unwrap());
+        automata.set_cell_rule((5, 5), |ngh: Vec<Light>| ngh[0]);
+        automata.save_checkpoint(&path).unwrap();
+        let saved = checkpoint::decode_with_rules::<Light, _>(&fs::read(&path).unwrap()[..]).unwrap().2;
+
+        let mut restored = lamp();
+        let count = restored.restore_cell_rules(&path, &registry);
+        let grid = lamp().current().clone();
+        fs::write(&path, checkpoint::encode_with_rules(&grid, 0, &[((3, 2), "decay".to_string()), ((40, 2), "decay".to_string())])).unwrap();
+        let outside = lamp().restore_cell_rules(&path, &registry);
+        fs::write(&path, checkpoint::encode_with_rules(&grid, 0, &[((3, 2), "sunlight".to_string())])).unwrap();
+        let unknown = lamp().restore_cell_rules(&path, &registry);
+        // A plain snapshot has none.
+        fs::write(&path, checkpoint::encode(&grid, 0)).unwrap();
+        let none = lamp().restore_cell_rules(&path, &registry);
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(saved, [((3, 2), "decay amount=3 seed=4".to_string())]);
+        assert_eq!(count.unwrap(), 1);
+        assert_eq!(restored.cell_rules()[&(3, 2)].config(), Some(&RuleConfig::new(4).with("amount", 3.0)));
+        assert!(matches!(outside, Err(SnapshotError::BadCellRule(2))));
+        assert!(matches!(unknown, Err(SnapshotError::BadRule{..})));
+        assert_eq!(none.unwrap(), 0);
+    }
+
+    #[test]
+    fn cell_rules_must_be_text()
+    {
+        let grid = lamp().current().clone();
+        let mut bytes = checkpoint::encode_with_rules(&grid, 0, &[((3, 2), "decay".to_string())]);
+        let last = bytes.len() - 1;
+        bytes[last] = 0xff;
+        assert!(matches!(checkpoint::decode::<Light, _>(&bytes[..]), Err(SnapshotError::BadCellRule(1))));
+        bytes.truncate(last);
+        assert!(matches!(checkpoint::decode::<Light, _>(&bytes[..]), Err(SnapshotError::Truncated)));
+    }
+}
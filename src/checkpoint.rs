@@ -0,0 +1,408 @@
+// Binary snapshots of an automaton's current generation:
+//
+//   b"TRIA", version (1 byte), cell codec id (u16, little endian),
+//   width, height and step as varints, then runs of identical cells in
+//   row order, each a varint count followed by the encoded cell.
+//
+// Checkpoints are of version 2: the same, followed by the rules of their
+// own some cells follow (see cell_rules.rs), a varint count then, for each,
+// the column and row as varints and the rule as a varint length and that
+// many bytes of UTF-8, written as registry::rule_line does.
+//
+// Varints are LEB128: 7 bits per byte, least significant first, the high
+// bit set on every byte but the last. Cells are encoded by the codec of
+// their type (see codec.rs).
+
+use crate::{Automata, Grid};
+use crate::codec::{self, CellCodec, CodecClash, CodecError};
+use crate::error::check_dims;
+
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Display};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"TRIA";
+const VERSION: u8 = 1;
+const RULES_VERSION: u8 = 2;
+
+// The rules of some cells, as a checkpoint holds them.
+pub type SavedCellRules = Vec<((usize, usize), String)>;
+
+#[derive(Debug)]
+pub enum SnapshotError
+{
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u8),
+    // The file holds cells of another type.
+    WrongCodec{expected: u16, found: u16},
+    // The file holds cells of a codec nobody registered.
+    UnknownCodec(u16),
+    // The type read has the id of another type's codec.
+    CodecClash(CodecClash),
+    // The file ends in the middle of the header or of the cells.
+    Truncated,
+    // A varint that does not fit in 64 bits, or dims of more than
+    // error::MAX_CELLS cells.
+    Overflow,
+    // A cell encoding no valid state, with the index of its run.
+    InvalidCell(usize),
+    // The runs cover more cells than the grid has.
+    TooManyCells,
+    // Bytes left after the last cell.
+    TrailingData,
+    // The ages saved next to the snapshot are for another grid or step.
+    AgeMismatch,
+    // A cell rule of a checkpoint, counted from 1, that is not UTF-8 or not
+    // for a cell of the grid.
+    BadCellRule(usize),
+    // A rule saved next to the snapshot, the automaton's or a cell's, that
+    // the registry cannot make again: the saved rule and why.
+    BadRule{rule: String, reason: String}
+}
+
+impl fmt::Display for SnapshotError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let name = |id: u16| codec::lookup(id).map_or_else(|| format!("codec {}", id), |info| info.name.to_string());
+        match self
+        {
+            SnapshotError::Io(error) => write!(f, "{}", error),
+            SnapshotError::BadMagic => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "snapshot of version {}, not {} or {}", version, VERSION, RULES_VERSION),
+            SnapshotError::WrongCodec{expected, found} => write!(f, "snapshot of {} cells, not {}", name(*found), name(*expected)),
+            SnapshotError::UnknownCodec(id) => write!(f, "snapshot of cells of codec {}, which is not registered", id),
+            SnapshotError::CodecClash(clash) => write!(f, "{}", clash),
+            SnapshotError::Truncated => write!(f, "the snapshot is cut short"),
+            SnapshotError::Overflow => write!(f, "the snapshot's numbers or dims are too large"),
+            SnapshotError::InvalidCell(run) => write!(f, "run {} holds no valid cell", run),
+            SnapshotError::TooManyCells => write!(f, "the runs cover more cells than the grid has"),
+            SnapshotError::TrailingData => write!(f, "bytes after the last cell"),
+            SnapshotError::AgeMismatch => write!(f, "the saved ages are for another grid or step"),
+            SnapshotError::BadCellRule(n) => write!(f, "cell rule {} is not text for a cell of the grid", n),
+            SnapshotError::BadRule{rule, reason} => write!(f, "cannot restore the rule '{}': {}", rule, reason)
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+        match self
+        {
+            SnapshotError::Io(error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError
+{
+    fn from(error: io::Error) -> Self
+    {
+        match error.kind()
+        {
+            io::ErrorKind::UnexpectedEof => SnapshotError::Truncated,
+            _ => SnapshotError::Io(error)
+        }
+    }
+}
+
+fn push_varint(out: &mut Vec<u8>, mut value: u64)
+{
+    while value >= 0x80
+    {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> Result<u64, SnapshotError>
+{
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7)
+    {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        let bits = u64::from(byte[0] & 0x7f);
+        if shift == 63 && bits > 1
+        {
+            return Err(SnapshotError::Overflow);
+        }
+        value |= bits << shift;
+        if byte[0] & 0x80 == 0
+        {
+            return Ok(value);
+        }
+    }
+    Err(SnapshotError::Overflow)
+}
+
+pub fn encode<T: Copy + Debug + PartialEq + CellCodec>(grid: &Grid<T>, step: u64) -> Vec<u8>
+{
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    out.extend_from_slice(&T::ID.to_le_bytes());
+    push_varint(&mut out, grid.dims.0 as u64);
+    push_varint(&mut out, grid.dims.1 as u64);
+    push_varint(&mut out, step);
+
+    let mut cells = grid.data.iter().peekable();
+    while let Some(cell) = cells.next()
+    {
+        let mut count = 1;
+        while cells.next_if_eq(&cell).is_some()
+        {
+            count += 1;
+        }
+        push_varint(&mut out, count);
+        cell.encode(&mut out);
+    }
+    out
+}
+
+// A checkpoint: the snapshot, then the rules of some cells as their lines.
+pub fn encode_with_rules<T: Copy + Debug + PartialEq + CellCodec>(grid: &Grid<T>, step: u64, rules: &[((usize, usize), String)]) -> Vec<u8>
+{
+    let mut out = encode(grid, step);
+    out[MAGIC.len()] = RULES_VERSION;
+    push_varint(&mut out, rules.len() as u64);
+    for ((i, j), line) in rules
+    {
+        push_varint(&mut out, *i as u64);
+        push_varint(&mut out, *j as u64);
+        push_varint(&mut out, line.len() as u64);
+        out.extend_from_slice(line.as_bytes());
+    }
+    out
+}
+
+// The grid and the step it was saved at; the cell rules of a checkpoint
+// are left out.
+pub fn decode<T: Copy + Debug + CellCodec, R: Read>(reader: R) -> Result<(Grid<T>, u64), SnapshotError>
+{
+    decode_with_rules(reader).map(|(grid, step, _)| (grid, step))
+}
+
+// Same as decode, with the cell rules of a checkpoint, none for a plain
+// snapshot. Their cells are not checked against the grid.
+pub fn decode_with_rules<T: Copy + Debug + CellCodec, R: Read>(mut reader: R) -> Result<(Grid<T>, u64, SavedCellRules), SnapshotError>
+{
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC
+    {
+        return Err(SnapshotError::BadMagic);
+    }
+    let mut header = [0; 3];
+    reader.read_exact(&mut header)?;
+    if header[0] != VERSION && header[0] != RULES_VERSION
+    {
+        return Err(SnapshotError::UnsupportedVersion(header[0]));
+    }
+    codec::register::<T>().map_err(SnapshotError::CodecClash)?;
+    let found = u16::from_le_bytes([header[1], header[2]]);
+    if found != T::ID
+    {
+        return Err(match codec::lookup(found)
+        {
+            Some(_) => SnapshotError::WrongCodec{expected: T::ID, found},
+            None => SnapshotError::UnknownCodec(found)
+        });
+    }
+    let width = usize::try_from(read_varint(&mut reader)?).map_err(|_| SnapshotError::Overflow)?;
+    let height = usize::try_from(read_varint(&mut reader)?).map_err(|_| SnapshotError::Overflow)?;
+    let step = read_varint(&mut reader)?;
+    let total = check_dims((width, height)).map_err(|_| SnapshotError::Overflow)?;
+
+    // Cells of varying widths are only told apart by decoding them.
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut rest = &bytes[..];
+    let mut data = Vec::new();
+    let mut run = 0;
+    while data.len() < total
+    {
+        let count = read_varint(&mut rest)?;
+        if count > (total - data.len()) as u64
+        {
+            return Err(SnapshotError::TooManyCells);
+        }
+        let (cell, len) = T::decode(rest).map_err(|error| match error
+        {
+            CodecError::Truncated => SnapshotError::Truncated,
+            CodecError::Invalid => SnapshotError::InvalidCell(run)
+        })?;
+        rest = &rest[len..];
+        data.extend(std::iter::repeat_n(cell, count as usize));
+        run += 1;
+    }
+    let mut rules = vec![];
+    if header[0] == RULES_VERSION
+    {
+        let varint = |rest: &mut &[u8]| read_varint(rest).and_then(|value| usize::try_from(value).map_err(|_| SnapshotError::Overflow));
+        for n in 0..read_varint(&mut rest)?
+        {
+            let coord = (varint(&mut rest)?, varint(&mut rest)?);
+            let len = varint(&mut rest)?;
+            if len > rest.len()
+            {
+                return Err(SnapshotError::Truncated);
+            }
+            let line = std::str::from_utf8(&rest[..len]).map_err(|_| SnapshotError::BadCellRule(n as usize + 1))?;
+            rules.push((coord, line.to_string()));
+            rest = &rest[len..];
+        }
+    }
+    if !rest.is_empty()
+    {
+        return Err(SnapshotError::TrailingData);
+    }
+    Ok((Grid{data, dims: (width, height)}, step, rules))
+}
+
+// Where the age layer of a snapshot is saved, next to it.
+fn ages_path(path: &Path) -> PathBuf
+{
+    let mut name = path.as_os_str().to_owned();
+    name.push(".ages");
+    PathBuf::from(name)
+}
+
+impl<T: Clone + Display + Copy + Debug + PartialEq + CellCodec> Automata<T>
+{
+    // The cell rules with a name are saved in the checkpoint. With ages
+    // enabled, they are saved as a second snapshot in `<path>.ages`;
+    // otherwise a stale one is removed.
+    pub fn save_checkpoint<P: AsRef<Path>>(&self, path: P) -> io::Result<()>
+    {
+        let path = path.as_ref();
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&encode_with_rules(self.current(), self.step(), &self.saved_cell_rules()))?;
+        writer.flush()?;
+        match self.ages()
+        {
+            Some(ages) => fs::write(ages_path(path), encode(ages, self.step())),
+            None => match fs::remove_file(ages_path(path))
+            {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+                _ => Ok(())
+            }
+        }
+    }
+
+    // Restores the generation, the step counter and the ages if they were
+    // saved. Source programs, injectors and the reversible history are not
+    // part of a snapshot; cell rules come back with restore_cell_rules.
+    pub fn load_checkpoint<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError>
+    {
+        let path = path.as_ref();
+        let (grid, step) = decode(BufReader::new(File::open(path)?))?;
+        let mut automata = Automata::new(grid);
+        automata.step = step;
+        match File::open(ages_path(path))
+        {
+            Ok(file) =>
+            {
+                let (ages, ages_step) = decode::<u32, _>(BufReader::new(file))?;
+                if ages.dims != automata.current().dims || ages_step != step
+                {
+                    return Err(SnapshotError::AgeMismatch);
+                }
+                automata.restore_ages(ages);
+            },
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => return Err(error.into())
+        }
+        Ok(automata)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::Light;
+    use crate::rng::SplitMix64;
+    use crate::validate::{random_light, seeded_grid};
+
+    #[test]
+    fn default_grids_are_tiny()
+    {
+        let grid = Grid::new((1000, 1000), Light::Space(0));
+        let bytes = encode(&grid, 12);
+        // The header, then a single run.
+        assert!(bytes.len() < 32, "{} bytes", bytes.len());
+        let (decoded, step) = decode::<Light, _>(&bytes[..]).unwrap();
+        assert_eq!((decoded, step), (grid, 12));
+    }
+
+    #[test]
+    fn random_grids_round_trip()
+    {
+        for seed in 0..5
+        {
+            let grid = seeded_grid(seed, (37, 23), random_light);
+            let step = seed * 1_000_003;
+            assert_eq!(decode::<Light, _>(&encode(&grid, step)[..]).unwrap(), (grid, step));
+        }
+        let mut rng = SplitMix64::new(6);
+        let bits = Grid::from_fn((9, 4), |_| rng.below(2) == 1);
+        assert_eq!(decode::<bool, _>(&encode(&bits, u64::MAX)[..]).unwrap(), (bits, u64::MAX));
+    }
+
+    #[test]
+    fn broken_snapshots_are_typed()
+    {
+        let bytes = encode(&seeded_grid(1, (6, 5), random_light), 3);
+        for cut in 0..bytes.len()
+        {
+            assert!(matches!(decode::<Light, _>(&bytes[..cut]), Err(SnapshotError::Truncated)), "cut at {}", cut);
+        }
+        let mut wrong = bytes.clone();
+        wrong[0] = b'X';
+        assert!(matches!(decode::<Light, _>(&wrong[..]), Err(SnapshotError::BadMagic)));
+        let mut wrong = bytes.clone();
+        wrong[4] = 9;
+        assert!(matches!(decode::<Light, _>(&wrong[..]), Err(SnapshotError::UnsupportedVersion(9))));
+        assert!(matches!(decode::<u8, _>(&bytes[..]), Err(SnapshotError::WrongCodec{expected: 1, found: 3})));
+        let mut wrong = bytes.clone();
+        wrong[5] = 0xee;
+        assert!(matches!(decode::<Light, _>(&wrong[..]), Err(SnapshotError::UnknownCodec(0x00ee))));
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert!(matches!(decode::<Light, _>(&longer[..]), Err(SnapshotError::TrailingData)));
+
+        // The first run of a 2x1 grid: a count of 1, then a cell of tag 7.
+        let header = encode(&Grid::new((2, 1), Light::Space(0)), 0)[..10].to_vec();
+        let mut invalid = header.clone();
+        invalid.extend_from_slice(&[1, 0, 0, 1, 7, 0]);
+        assert!(matches!(decode::<Light, _>(&invalid[..]), Err(SnapshotError::InvalidCell(1))));
+        let mut too_many = header.clone();
+        too_many.extend_from_slice(&[3, 0, 0]);
+        assert!(matches!(decode::<Light, _>(&too_many[..]), Err(SnapshotError::TooManyCells)));
+        let mut huge = header[..7].to_vec();
+        huge.extend_from_slice(&[0xff; 10]);
+        assert!(matches!(decode::<Light, _>(&huge[..]), Err(SnapshotError::Overflow)));
+    }
+
+    #[test]
+    fn checkpoints_round_trip()
+    {
+        let mut automata = Automata::new(seeded_grid(4, (10, 6), random_light));
+        automata.step = 41;
+        let path = std::env::temp_dir().join(format!("triangle-automata-{}-checkpoint.tria", std::process::id()));
+        automata.save_checkpoint(&path).unwrap();
+        let loaded = Automata::<Light>::load_checkpoint(&path);
+        let wrong = Automata::<u8>::load_checkpoint(&path);
+        let _ = fs::remove_file(&path);
+        let loaded = loaded.unwrap();
+        assert_eq!((loaded.current(), loaded.step()), (automata.current(), 41));
+        assert!(matches!(wrong, Err(SnapshotError::WrongCodec{..})));
+    }
+}
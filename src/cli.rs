@@ -0,0 +1,394 @@
+use triangle_automata::batch::{self, RenderJob};
+use triangle_automata::color::ColorMap;
+use triangle_automata::lantern::LanternParams;
+use triangle_automata::neighborhood::NeighborhoodKind;
+use triangle_automata::palette::{self, Palette};
+use triangle_automata::render::{self, RenderMode};
+use triangle_automata::run::LoopOptions;
+use triangle_automata::sources::Source;
+
+use std::path::PathBuf;
+
+// Command line of the demo binary. Flags are parsed by hand to keep the
+// crate free of dependencies.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModeChoice
+{
+    Auto,
+    Full,
+    Compact,
+    Viewport
+}
+
+impl ModeChoice
+{
+    pub fn resolve(self, grid_dims: (usize, usize), term_dims: (usize, usize)) -> RenderMode
+    {
+        match self
+        {
+            ModeChoice::Auto => render::choose_mode(grid_dims, term_dims),
+            ModeChoice::Full => RenderMode::Full,
+            ModeChoice::Compact => RenderMode::Compact,
+            ModeChoice::Viewport =>
+            {
+                match render::choose_mode(grid_dims, term_dims)
+                {
+                    RenderMode::Viewport{origin, dims} => RenderMode::Viewport{origin, dims},
+                    _ => RenderMode::Viewport{origin: (0, 0), dims: grid_dims}
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Demo
+{
+    Light,
+    Blink,
+    Heat,
+    Compare,
+    Sandpile,
+    Embers,
+    Lens,
+    Lantern,
+    Wireworld
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options
+{
+    pub mode: ModeChoice,
+    pub demo: Demo,
+    // Redraw only the cells that changed (compact renderings only).
+    pub diff: bool,
+    // Run a script file (see script.rs) instead of a demo.
+    pub script: Option<String>,
+    // Read commands from stdin instead of running a demo.
+    pub repl: bool,
+    // Pacing of the light and blink demos.
+    pub pacing: LoopOptions,
+    // Ceiling of lit cells in the light and blink demos.
+    pub max_intensity: u8,
+    // Check the invariants of the demo's rule before running it.
+    pub validate: bool,
+    // List the registered rules instead of running anything.
+    pub list_rules: bool,
+    // Time evolve with and without row skipping (see rows.rs) instead.
+    pub bench: bool,
+    // Write the gallery (see gallery.rs) into this directory instead.
+    pub gallery: Option<String>,
+    // Compare the rules with a gallery manifest instead.
+    pub gallery_check: Option<String>,
+    // Write the CSV of the decay sweep (see sweep.rs) to this file instead.
+    pub sweep: Option<String>,
+    // Threads the sweep runs on, 0 for every core.
+    pub threads: usize,
+    // Cells the rule sees in scripts and the REPL, over what the script
+    // says.
+    pub neighborhood: Option<NeighborhoodKind>,
+    // Named sources of the light demo, in place of its single one.
+    pub sources: Vec<Source>,
+    // Pattern file (plain or RLE) the light demo starts from.
+    pub pattern: Option<String>,
+    // Colors of the embers and lens demos and of script heatmaps, over
+    // their own.
+    pub palette: Option<ColorMap>,
+    // PNG file the lens and wireworld demos draw their last generation to.
+    pub image: Option<String>,
+    // State machine (see machine.rs) the wireworld demo runs in place of
+    // wireworld.
+    pub machine: Option<String>,
+    // Parameters of the lantern demo, --threshold and --diffusivity over
+    // its defaults.
+    pub lantern: LanternParams,
+    // Broadcast the blink demo to browsers on this address (ws feature).
+    pub ws: Option<String>
+}
+
+impl Default for Options
+{
+    fn default() -> Self
+    {
+        Self{mode: ModeChoice::Auto, demo: Demo::Light, diff: false, script: None, repl: false, pacing: LoopOptions::default(), max_intensity: u8::MAX, validate: false, list_rules: false, bench: false, gallery: None, gallery_check: None, sweep: None, threads: 0, neighborhood: None, sources: vec![], pattern: None, palette: None, image: None, machine: None, lantern: LanternParams::default(), ws: None}
+    }
+}
+
+fn value<'a, I: Iterator<Item = &'a String>>(flag: &str, args: &mut I) -> Result<&'a String, String>
+{
+    args.next().ok_or_else(|| format!("{} expects a value", flag))
+}
+
+// A color map or palette by name, or a palette file (.toml), registered
+// under its name.
+fn colormap(name: &str) -> Result<ColorMap, String>
+{
+    if name.ends_with(".toml")
+    {
+        let palette = Palette::load(name).map_err(|error| format!("{}: {}", name, error))?;
+        return match ColorMap::named(&palette.name)
+        {
+            Ok(ColorMap::Palette(known)) if *known == palette => Ok(ColorMap::Palette(known)),
+            _ => palette::register(palette).map_err(|error| format!("{}: {}", name, error))
+        };
+    }
+    ColorMap::named(name).map_err(|error| error.to_string())
+}
+
+pub fn parse(args: &[String]) -> Result<Options, String>
+{
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--mode" =>
+            {
+                options.mode = match value(arg, &mut args)?.as_str()
+                {
+                    "auto" => ModeChoice::Auto,
+                    "full" => ModeChoice::Full,
+                    "compact" => ModeChoice::Compact,
+                    "viewport" => ModeChoice::Viewport,
+                    other => return Err(format!("unknown render mode '{}' (auto, full, compact or viewport)", other))
+                };
+            },
+            "--demo" =>
+            {
+                options.demo = match value(arg, &mut args)?.as_str()
+                {
+                    "light" => Demo::Light,
+                    "blink" => Demo::Blink,
+                    "heat" => Demo::Heat,
+                    "compare" => Demo::Compare,
+                    "sandpile" => Demo::Sandpile,
+                    "embers" => Demo::Embers,
+                    "lens" => Demo::Lens,
+                    "lantern" => Demo::Lantern,
+                    "wireworld" => Demo::Wireworld,
+                    other => return Err(format!("unknown demo '{}' (light, blink, heat, compare, sandpile, embers, lens, lantern or wireworld)", other))
+                };
+            },
+            "--diff" => options.diff = true,
+            "--repl" => options.repl = true,
+            "--validate" => options.validate = true,
+            "--rules" => options.list_rules = true,
+            "--bench" => options.bench = true,
+            "--gallery" => options.gallery = Some(value(arg, &mut args)?.clone()),
+            "--gallery-check" => options.gallery_check = Some(value(arg, &mut args)?.clone()),
+            "--sweep" => options.sweep = Some(value(arg, &mut args)?.clone()),
+            "--threads" =>
+            {
+                let threads = value(arg, &mut args)?;
+                options.threads = threads.parse()
+                    .map_err(|_| format!("--threads expects a number of threads, 0 for every core, not '{}'", threads))?;
+            },
+            "--steps-per-frame" =>
+            {
+                let count = value(arg, &mut args)?;
+                options.pacing.steps_per_frame = match count.parse()
+                {
+                    Ok(count) if count > 0 => count,
+                    _ => return Err(format!("--steps-per-frame expects a positive integer, not '{}'", count))
+                };
+            },
+            "--max-intensity" =>
+            {
+                let max = value(arg, &mut args)?;
+                options.max_intensity = max.parse()
+                    .map_err(|_| format!("--max-intensity expects an integer from 0 to 255, not '{}'", max))?;
+            },
+            "--fps" =>
+            {
+                let fps = value(arg, &mut args)?;
+                options.pacing.target_fps = match fps.parse::<f32>()
+                {
+                    Ok(fps) if fps >= 0.0 && fps.is_finite() => fps,
+                    _ => return Err(format!("--fps expects a non-negative number, not '{}'", fps))
+                };
+            },
+            "--threshold" =>
+            {
+                let threshold = value(arg, &mut args)?;
+                options.lantern.threshold = match threshold.parse::<f32>()
+                {
+                    Ok(threshold) if threshold > 0.0 && threshold.is_finite() => threshold,
+                    _ => return Err(format!("--threshold expects a positive number, not '{}'", threshold))
+                };
+            },
+            "--diffusivity" =>
+            {
+                let diffusivity = value(arg, &mut args)?;
+                options.lantern.diffusivity = match diffusivity.parse::<f32>()
+                {
+                    Ok(diffusivity) if (0.0..=1.0).contains(&diffusivity) => diffusivity,
+                    _ => return Err(format!("--diffusivity expects a number from 0 to 1, not '{}'", diffusivity))
+                };
+            },
+            "--neighborhood" => options.neighborhood = Some(value(arg, &mut args)?.parse()?),
+            "--source" => options.sources.push(value(arg, &mut args)?.parse()?),
+            "--palette" => options.palette = Some(colormap(value(arg, &mut args)?)?),
+            "--image" => options.image = Some(value(arg, &mut args)?.clone()),
+            "--machine" => options.machine = Some(value(arg, &mut args)?.clone()),
+            "--pattern" => options.pattern = Some(value(arg, &mut args)?.clone()),
+            "--script" => options.script = Some(value(arg, &mut args)?.clone()),
+            "--ws" if cfg!(feature = "ws") => options.ws = Some(value(arg, &mut args)?.clone()),
+            "--ws" => return Err("--ws needs the ws feature (cargo run --features ws)".to_string()),
+            other => return Err(format!("unknown argument '{}'", other))
+        }
+    }
+    Ok(options)
+}
+
+// The arguments of the render subcommand, after `render`.
+pub fn parse_render(args: &[String]) -> Result<RenderJob, String>
+{
+    let mut input = None;
+    let mut job = RenderJob::new("");
+    let mut args = args.iter();
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--input" => input = Some(PathBuf::from(value(arg, &mut args)?)),
+            "--format" => job.format = value(arg, &mut args)?.parse()?,
+            "--cell-px" =>
+            {
+                let px = value(arg, &mut args)?;
+                job.cell_px = match px.parse()
+                {
+                    Ok(px) if px > 0 => px,
+                    _ => return Err(format!("--cell-px expects a positive integer, not '{}'", px))
+                };
+            },
+            "--palette" => job.colormap = colormap(value(arg, &mut args)?)?,
+            "--evolve" => job.evolve = Some(batch::parse_evolve(value(arg, &mut args)?)?),
+            "--out" => job.out = PathBuf::from(value(arg, &mut args)?),
+            other => return Err(format!("unknown argument '{}' for render", other))
+        }
+    }
+    job.input = input.ok_or("render needs --input dir_or_file")?;
+    Ok(job)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn args(line: &str) -> Vec<String>
+    {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn mode_flag()
+    {
+        assert_eq!(parse(&[]).unwrap().mode, ModeChoice::Auto);
+        assert_eq!(parse(&args("--mode compact")).unwrap().mode, ModeChoice::Compact);
+        assert_eq!(parse(&args("--mode full")).unwrap().mode, ModeChoice::Full);
+        assert!(parse(&args("--mode tiny")).unwrap_err().contains("unknown render mode 'tiny'"));
+        assert!(parse(&args("--mode")).unwrap_err().contains("--mode expects a value"));
+        // Forcing the viewport keeps the size that would fit.
+        assert_eq!(ModeChoice::Viewport.resolve((100, 50), (80, 24)), RenderMode::Viewport{origin: (0, 0), dims: (80, 23)});
+        assert_eq!(ModeChoice::Full.resolve((100, 50), (80, 24)), RenderMode::Full);
+    }
+
+    #[test]
+    fn pacing_flags()
+    {
+        let options = parse(&args("--steps-per-frame 4 --fps 12.5")).unwrap();
+        assert_eq!(options.pacing, LoopOptions{steps_per_frame: 4, target_fps: 12.5, ..LoopOptions::default()});
+        assert!(parse(&args("--steps-per-frame 0")).unwrap_err().contains("positive integer, not '0'"));
+        assert!(parse(&args("--fps -1")).unwrap_err().contains("non-negative number, not '-1'"));
+        assert!(parse(&args("--fps inf")).is_err());
+    }
+
+    #[test]
+    fn intensity_ceiling()
+    {
+        assert_eq!(parse(&[]).unwrap().max_intensity, 255);
+        assert_eq!(parse(&args("--max-intensity 5")).unwrap().max_intensity, 5);
+        assert!(parse(&args("--max-intensity 256")).unwrap_err().contains("from 0 to 255, not '256'"));
+    }
+
+    #[test]
+    fn repeated_sources()
+    {
+        let options = parse(&args("--source east=12,3,4,period=6 --source west=1,1,9,ttl=5")).unwrap();
+        let sources: Vec<String> = options.sources.iter().map(Source::to_string).collect();
+        assert_eq!(sources, ["east=12,3,4,period=6", "west=1,1,9,ttl=5"]);
+        assert!(parse(&args("--source east=12")).unwrap_err().contains("invalid source 'east=12'"));
+        assert!(parse(&args("--source")).unwrap_err().contains("--source expects a value"));
+    }
+
+    #[test]
+    fn palette_flag()
+    {
+        assert_eq!(parse(&args("--palette okabe-ito")).unwrap().palette, Some(ColorMap::named("okabe-ito").unwrap()));
+        assert!(parse(&args("--palette sepia")).unwrap_err().contains("unknown palette 'sepia'"));
+        let path = std::env::temp_dir().join(format!("triangle-automata-{}-cli.toml", std::process::id()));
+        std::fs::write(&path, "name = \"cli-test\"\ncolors = [\"#123\", \"#456\"]\n").unwrap();
+        let line = format!("--palette {}", path.display());
+        // Loading the same file twice finds the palette it registered.
+        let first = parse(&args(&line)).map(|options| options.palette);
+        let second = parse(&args(&line)).map(|options| options.palette);
+        std::fs::write(&path, "name = \"cli-test\"\ncolors = [\"#789\"]\n").unwrap();
+        let changed = parse(&args(&line)).map(|options| options.palette);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(first.unwrap(), Some(ColorMap::named("cli-test").unwrap()));
+        assert_eq!(second.unwrap(), Some(ColorMap::named("cli-test").unwrap()));
+        assert!(changed.unwrap_err().contains("a palette named 'cli-test' is already registered"));
+        assert!(parse(&args(&line)).unwrap_err().contains("cli.toml: "));
+    }
+
+    #[test]
+    fn render_subcommand()
+    {
+        let job = parse_render(&args("--input saves --format svg --cell-px 12 --palette heat --evolve decay:30 --out pictures")).unwrap();
+        assert_eq!(job, RenderJob{
+            format: batch::Format::Svg,
+            cell_px: 12,
+            colormap: ColorMap::Heat,
+            evolve: Some(("decay".to_string(), 30)),
+            out: PathBuf::from("pictures"),
+            ..RenderJob::new("saves")
+        });
+        assert_eq!(parse_render(&args("--input one.tria")).unwrap(), RenderJob::new("one.tria"));
+        assert_eq!(parse_render(&args("--format png")).unwrap_err(), "render needs --input dir_or_file");
+        assert!(parse_render(&args("--input a --cell-px 0")).unwrap_err().contains("positive integer, not '0'"));
+        assert!(parse_render(&args("--input a --format gif")).unwrap_err().contains("unknown format 'gif'"));
+        assert!(parse_render(&args("--input a --evolve decay")).unwrap_err().contains("expected rule:steps"));
+        assert_eq!(parse_render(&args("--input a --steps 3")).unwrap_err(), "unknown argument '--steps' for render");
+    }
+
+    #[test]
+    fn lantern_flags()
+    {
+        let options = parse(&args("--demo lantern --threshold 2.5 --diffusivity 0.25")).unwrap();
+        assert_eq!(options.demo, Demo::Lantern);
+        assert_eq!(options.lantern, LanternParams{threshold: 2.5, diffusivity: 0.25, ..LanternParams::default()});
+        assert!(parse(&args("--threshold 0")).unwrap_err().contains("positive number, not '0'"));
+        assert!(parse(&args("--diffusivity 1.5")).unwrap_err().contains("from 0 to 1, not '1.5'"));
+    }
+
+    // A --source outside of the light demo used to be dropped without a
+    // word.
+    #[test]
+    fn sources_outside_of_the_demo_are_errors()
+    {
+        use crate::demos;
+        use triangle_automata::error::{Error, GridError};
+
+        let options = parse(&args("--source far=500,3,9")).unwrap();
+        match demos::light_start(&options, (30, 20))
+        {
+            Err(Error::Grid(GridError::OutOfBounds{coord: (500, 3), dims: (30, 20)})) => (),
+            other => panic!("--source far=500,3,9 starts the light demo with {:?}", other.err())
+        }
+        let error = demos::light_start(&options, (30, 20)).err().unwrap();
+        assert_eq!(error.to_string(), "(500, 3) is outside of the 30x20 grid");
+    }
+}
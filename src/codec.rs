@@ -0,0 +1,402 @@
+// Byte encodings of cell states, for everything that saves or sends cells:
+// checkpoints and their age layers, frame logs, timelines and network
+// frames all go through checkpoint::encode and decode, so a type with a
+// codec has all of them. Every codec has an id, written in the header of
+// what it produced, and a width, fixed or varying from a state to the
+// next (decode says how many bytes it read).
+//
+// The registry knows the ids in use: the built-in ones and those of the
+// codecs registered, which a codec is the first time its cells are read.
+// Reading an artifact of an id nobody registered is an UnknownCodec error,
+// one of another registered codec a WrongCodec one, and a type taking the
+// id of another is refused rather than decoding its cells.
+
+use crate::Light;
+
+use std::any::{type_name, TypeId};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecError
+{
+    // Fewer bytes than the state takes.
+    Truncated,
+    // Bytes that encode no state.
+    Invalid
+}
+
+pub trait CellCodec: Sized + 'static
+{
+    // Identifies the encoding in headers.
+    const ID: u16;
+    const NAME: &'static str;
+    // Bytes per cell, None when it varies.
+    const WIDTH: Option<usize>;
+
+    fn encode(&self, out: &mut Vec<u8>);
+    // The state at the start of `bytes` and the number of bytes it took.
+    fn decode(bytes: &[u8]) -> Result<(Self, usize), CodecError>;
+}
+
+// The first N bytes, for fixed widths.
+fn take<const N: usize>(bytes: &[u8]) -> Result<[u8; N], CodecError>
+{
+    let mut taken = [0; N];
+    taken.copy_from_slice(bytes.get(..N).ok_or(CodecError::Truncated)?);
+    Ok(taken)
+}
+
+impl CellCodec for u8
+{
+    const ID: u16 = 1;
+    const NAME: &'static str = "u8";
+    const WIDTH: Option<usize> = Some(1);
+
+    fn encode(&self, out: &mut Vec<u8>)
+    {
+        out.push(*self);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<(Self, usize), CodecError>
+    {
+        let [byte] = take(bytes)?;
+        Ok((byte, 1))
+    }
+}
+
+impl CellCodec for bool
+{
+    const ID: u16 = 2;
+    const NAME: &'static str = "bool";
+    const WIDTH: Option<usize> = Some(1);
+
+    fn encode(&self, out: &mut Vec<u8>)
+    {
+        out.push(*self as u8);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<(Self, usize), CodecError>
+    {
+        match take(bytes)?
+        {
+            [0] => Ok((false, 1)),
+            [1] => Ok((true, 1)),
+            _ => Err(CodecError::Invalid)
+        }
+    }
+}
+
+impl CellCodec for Light
+{
+    const ID: u16 = 3;
+    const NAME: &'static str = "light";
+    const WIDTH: Option<usize> = Some(2);
+
+    fn encode(&self, out: &mut Vec<u8>)
+    {
+        match self
+        {
+            Light::Space(level) => out.extend_from_slice(&[0, *level]),
+            Light::Source(level) => out.extend_from_slice(&[1, *level])
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<(Self, usize), CodecError>
+    {
+        match take(bytes)?
+        {
+            [0, level] => Ok((Light::Space(level), 2)),
+            [1, level] => Ok((Light::Source(level), 2)),
+            _ => Err(CodecError::Invalid)
+        }
+    }
+}
+
+// Little endian, for the age layer.
+impl CellCodec for u32
+{
+    const ID: u16 = 4;
+    const NAME: &'static str = "u32";
+    const WIDTH: Option<usize> = Some(4);
+
+    fn encode(&self, out: &mut Vec<u8>)
+    {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<(Self, usize), CodecError>
+    {
+        Ok((u32::from_le_bytes(take(bytes)?), 4))
+    }
+}
+
+// The bits, little endian: NaNs come back as they were.
+impl CellCodec for f32
+{
+    const ID: u16 = 5;
+    const NAME: &'static str = "f32";
+    const WIDTH: Option<usize> = Some(4);
+
+    fn encode(&self, out: &mut Vec<u8>)
+    {
+        out.extend_from_slice(&self.to_bits().to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<(Self, usize), CodecError>
+    {
+        Ok((f32::from_bits(u32::from_le_bytes(take(bytes)?)), 4))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodecInfo
+{
+    pub id: u16,
+    pub name: &'static str,
+    pub width: Option<usize>,
+    // The cell type.
+    pub cells: &'static str,
+    type_id: TypeId
+}
+
+impl CodecInfo
+{
+    fn of<T: CellCodec>() -> Self
+    {
+        Self{id: T::ID, name: T::NAME, width: T::WIDTH, cells: type_name::<T>(), type_id: TypeId::of::<T>()}
+    }
+}
+
+// A codec taking the id of another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodecClash
+{
+    pub id: u16,
+    pub registered: &'static str,
+    pub refused: &'static str
+}
+
+impl fmt::Display for CodecClash
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "codec id {} is {}'s, not {}'s", self.id, self.registered, self.refused)
+    }
+}
+
+fn registry() -> MutexGuard<'static, BTreeMap<u16, CodecInfo>>
+{
+    static REGISTRY: OnceLock<Mutex<BTreeMap<u16, CodecInfo>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let builtins = [CodecInfo::of::<u8>(), CodecInfo::of::<bool>(), CodecInfo::of::<Light>(), CodecInfo::of::<u32>(), CodecInfo::of::<f32>()];
+        Mutex::new(builtins.iter().map(|info| (info.id, *info)).collect())
+    }).lock().unwrap()
+}
+
+// Registering a codec again is fine, another codec under its id is not.
+pub fn register<T: CellCodec>() -> Result<(), CodecClash>
+{
+    let info = CodecInfo::of::<T>();
+    let mut registry = registry();
+    let registered = registry.entry(T::ID).or_insert(info);
+    if registered.type_id == info.type_id
+    {
+        Ok(())
+    }
+    else
+    {
+        Err(CodecClash{id: T::ID, registered: registered.cells, refused: info.cells})
+    }
+}
+
+pub fn lookup(id: u16) -> Option<CodecInfo>
+{
+    registry().get(&id).copied()
+}
+
+// Every codec registered, by id.
+pub fn codecs() -> Vec<CodecInfo>
+{
+    registry().values().copied().collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::Grid;
+    use crate::checkpoint::{self, SnapshotError};
+
+    // A count along with the cells, a varint (see checkpoint.rs) of 1 to 10
+    // bytes: a codec of varying width, registered as a user's would be.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Tally(u64);
+
+    impl CellCodec for Tally
+    {
+        const ID: u16 = 0x7f00;
+        const NAME: &'static str = "tally";
+        const WIDTH: Option<usize> = None;
+
+        fn encode(&self, out: &mut Vec<u8>)
+        {
+            let mut value = self.0;
+            while value >= 0x80
+            {
+                out.push(value as u8 | 0x80);
+                value >>= 7;
+            }
+            out.push(value as u8);
+        }
+
+        fn decode(bytes: &[u8]) -> Result<(Self, usize), CodecError>
+        {
+            let mut value = 0u64;
+            for (n, &byte) in bytes.iter().enumerate().take(10)
+            {
+                value |= u64::from(byte & 0x7f) << (7*n);
+                if byte & 0x80 == 0
+                {
+                    return Ok((Tally(value), n + 1));
+                }
+            }
+            Err(if bytes.len() < 10 { CodecError::Truncated } else { CodecError::Invalid })
+        }
+    }
+
+    // Taking u8's id.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Impostor;
+
+    impl CellCodec for Impostor
+    {
+        const ID: u16 = 1;
+        const NAME: &'static str = "impostor";
+        const WIDTH: Option<usize> = Some(0);
+
+        fn encode(&self, _: &mut Vec<u8>) {}
+
+        fn decode(_: &[u8]) -> Result<(Self, usize), CodecError>
+        {
+            Ok((Impostor, 0))
+        }
+    }
+
+    // Encodes `states` one after the other and decodes them back, each
+    // telling how many bytes it took, and checks that a cut encoding is
+    // Truncated.
+    fn round_trip<T: CellCodec + fmt::Debug>(states: &[T], same: fn(&T, &T) -> bool)
+    {
+        let mut bytes = vec![];
+        for state in states
+        {
+            let start = bytes.len();
+            state.encode(&mut bytes);
+            if let Some(width) = T::WIDTH
+            {
+                assert_eq!(bytes.len() - start, width, "{:?} of {}", state, T::NAME);
+            }
+        }
+        let mut rest = &bytes[..];
+        for state in states
+        {
+            let (decoded, len) = T::decode(rest).unwrap();
+            assert!(same(state, &decoded), "{:?} decodes as {:?}", state, decoded);
+            rest = &rest[len..];
+        }
+        assert!(rest.is_empty(), "{} bytes of {} left once decoded", rest.len(), T::NAME);
+        let mut one = vec![];
+        states[states.len() - 1].encode(&mut one);
+        assert_eq!(T::decode(&one[..one.len() - 1]).err(), Some(CodecError::Truncated), "a cut {}", T::NAME);
+    }
+
+    #[test]
+    fn built_in_codecs_round_trip()
+    {
+        round_trip(&[0u8, 7, 255], |a, b| a == b);
+        round_trip(&[false, true, true], |a, b| a == b);
+        round_trip(&[0u32, 1, 0xdead_beef, u32::MAX], |a, b| a == b);
+        round_trip(&[0.0f32, -1.5, f32::INFINITY, f32::NAN, f32::MIN_POSITIVE], |a, b| a.to_bits() == b.to_bits());
+        round_trip(&[Light::Space(0), Light::Source(9), Light::Space(255)], |a, b| a == b);
+        round_trip(&[Tally(0), Tally(127), Tally(128), Tally(u64::MAX)], |a, b| a == b);
+        // Bytes of no state.
+        assert_eq!(bool::decode(&[2]), Err(CodecError::Invalid));
+        assert_eq!(Light::decode(&[7, 0]), Err(CodecError::Invalid));
+        let ids: Vec<u16> = codecs().iter().map(|info| info.id).collect();
+        assert!([u8::ID, bool::ID, u32::ID, f32::ID, Light::ID].iter().all(|id| ids.contains(id)), "{:?}", ids);
+    }
+
+    // The only test that reads tallies: they must be registered by it.
+    #[test]
+    fn snapshots_register_their_codec()
+    {
+        let grid = Grid::from_fn((9, 5), |(i, j)| Tally(((i*j) as u64) << (5*j)));
+        let snapshot = checkpoint::encode(&grid, 12);
+        assert!(lookup(Tally::ID).is_none());
+        let (decoded, step) = checkpoint::decode::<Tally, _>(&snapshot[..]).unwrap();
+        assert_eq!((decoded, step), (grid, 12));
+        assert_eq!(lookup(Tally::ID).map(|info| info.name), Some("tally"));
+        assert!(matches!(checkpoint::decode::<u8, _>(&snapshot[..]), Err(SnapshotError::WrongCodec{expected: 1, found: Tally::ID})));
+    }
+
+    #[test]
+    fn unknown_ids_are_errors()
+    {
+        let mut snapshot = checkpoint::encode(&Grid::new((3, 2), 5u32), 0);
+        snapshot[5..7].copy_from_slice(&0x7fffu16.to_le_bytes());
+        assert!(matches!(checkpoint::decode::<u32, _>(&snapshot[..]), Err(SnapshotError::UnknownCodec(0x7fff))));
+    }
+
+    #[test]
+    fn ids_cannot_be_taken()
+    {
+        let clash = CodecClash{id: 1, registered: type_name::<u8>(), refused: type_name::<Impostor>()};
+        assert_eq!(register::<Impostor>(), Err(clash));
+        assert_eq!(register::<u8>(), Ok(()));
+        match checkpoint::decode::<Impostor, _>(&checkpoint::encode(&Grid::new((2, 2), 3u8), 0)[..])
+        {
+            Err(SnapshotError::CodecClash(found)) => assert_eq!(found, clash),
+            other => panic!("an impostor of u8 reads its cells as {:?}", other.map(|(_, step)| step))
+        }
+    }
+
+    // Saves a checkpoint of a light run in the temporary directory, serves
+    // the run from it over a local socket and draws the last frame
+    // received: it must be the drawing of the same run kept on this side.
+    #[cfg(feature = "net")]
+    #[test]
+    fn checkpoints_stream_and_render()
+    {
+        use crate::{net, rules, Automata};
+        use crate::render::render_compact;
+        use std::net::TcpListener;
+        use std::thread;
+
+        let path = std::env::temp_dir().join(format!("triangle-automata-{}-pipeline.tria", std::process::id()));
+        let grid = Grid::from_fn((18, 8), |(i, j)| if (i, j) == (4, 3) || (i, j) == (13, 5) { Light::Source(7) } else { Light::Space(0) });
+        let mut automata = Automata::new(grid);
+        for _ in 0..3
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        automata.save_checkpoint(&path).unwrap();
+        let loaded = (Automata::<Light>::load_checkpoint(&path), Automata::<Light>::load_checkpoint(&path));
+        let _ = std::fs::remove_file(&path);
+        let (mut expected, mut served) = (loaded.0.unwrap(), loaded.1.unwrap());
+        for _ in 0..10
+        {
+            expected.evolve(rules::light_falloff);
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || net::connect::<Light, _>(addr).unwrap().collect::<Result<Vec<_>, _>>());
+        net::serve_on(&mut served, rules::light_falloff, 10, 0.0, listener).unwrap();
+        let frames = client.join().unwrap().unwrap();
+        let (grid, step) = frames.last().unwrap();
+        assert_eq!(*step, 13);
+        assert_eq!(render_compact(grid), render_compact(expected.current()));
+    }
+}
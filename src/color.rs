@@ -0,0 +1,121 @@
+// Color maps from [0, 1] to RGB, shared by the image and plot outputs.
+
+use crate::palette::{self, Palette, PaletteError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMap
+{
+    Gray,
+    // Black, red, yellow, white.
+    Heat,
+    // Perceptually uniform dark blue to yellow, sampled from matplotlib's
+    // viridis.
+    Viridis,
+    // A registered palette, see palette.rs.
+    Palette(&'static Palette)
+}
+
+const VIRIDIS: [(u8, u8, u8); 9] = [
+    (68, 1, 84), (71, 44, 122), (59, 81, 139), (44, 113, 142), (33, 144, 141),
+    (39, 173, 129), (92, 200, 99), (170, 220, 50), (253, 231, 37)
+];
+
+const HEAT: [(u8, u8, u8); 4] = [(0, 0, 0), (230, 30, 0), (255, 220, 0), (255, 255, 255)];
+
+pub(crate) fn interpolate(stops: &[(u8, u8, u8)], t: f64) -> (u8, u8, u8)
+{
+    let position = t * (stops.len() - 1) as f64;
+    let n = (position.floor() as usize).min(stops.len() - 2);
+    let f = position - n as f64;
+    let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * f).round() as u8;
+    let (a, b) = (stops[n], stops[n + 1]);
+    (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+}
+
+impl ColorMap
+{
+    // Values outside of [0, 1] are clamped, NaN is drawn as 0.
+    pub fn color(self, t: f64) -> (u8, u8, u8)
+    {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        match self
+        {
+            ColorMap::Gray =>
+            {
+                let v = (t * 255.0).round() as u8;
+                (v, v, v)
+            },
+            ColorMap::Heat => interpolate(&HEAT, t),
+            ColorMap::Viridis => interpolate(&VIRIDIS, t),
+            ColorMap::Palette(palette) => palette.color(t)
+        }
+    }
+
+    // gray, heat, viridis or a registered palette.
+    pub fn named(name: &str) -> Result<Self, PaletteError>
+    {
+        match name
+        {
+            "gray" => Ok(ColorMap::Gray),
+            "heat" => Ok(ColorMap::Heat),
+            "viridis" => Ok(ColorMap::Viridis),
+            _ => palette::get(name).map(ColorMap::Palette).ok_or_else(|| PaletteError::UnknownName{
+                name: name.to_string(),
+                known: ["gray", "heat", "viridis"].iter().map(|known| known.to_string()).chain(palette::names()).collect()
+            })
+        }
+    }
+
+    pub fn hex(self, t: f64) -> String
+    {
+        let (r, g, b) = self.color(t);
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn maps_go_through_their_stops()
+    {
+        assert_eq!(ColorMap::Gray.color(0.5), (128, 128, 128));
+        assert_eq!(ColorMap::Heat.color(0.0), HEAT[0]);
+        assert_eq!(ColorMap::Heat.color(1.0 / 3.0), HEAT[1]);
+        assert_eq!(ColorMap::Heat.color(1.0), HEAT[3]);
+        assert_eq!(ColorMap::Viridis.color(0.5), VIRIDIS[4]);
+        // Halfway between two stops.
+        assert_eq!(interpolate(&[(0, 0, 0), (100, 200, 255)], 0.5), (50, 100, 128));
+    }
+
+    #[test]
+    fn values_out_of_range_are_clamped()
+    {
+        for map in [ColorMap::Gray, ColorMap::Heat, ColorMap::Viridis]
+        {
+            assert_eq!(map.color(-3.0), map.color(0.0));
+            assert_eq!(map.color(7.5), map.color(1.0));
+            assert_eq!(map.color(f64::NAN), map.color(0.0));
+        }
+        assert_eq!(ColorMap::Viridis.hex(1.0), "#fde725");
+        assert_eq!(ColorMap::Gray.hex(0.0), "#000000");
+    }
+
+    #[test]
+    fn maps_by_name()
+    {
+        assert_eq!(ColorMap::named("heat").unwrap(), ColorMap::Heat);
+        assert_eq!(ColorMap::named("gray").unwrap(), ColorMap::Gray);
+        match ColorMap::named("sepia")
+        {
+            Err(PaletteError::UnknownName{name, known}) =>
+            {
+                assert_eq!(name, "sepia");
+                assert_eq!(known[..3], ["gray", "heat", "viridis"]);
+            },
+            other => panic!("expected an unknown name, got {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,115 @@
+// Two rules evolved in lockstep from the same initial grid, to see where
+// their behaviors part.
+
+use crate::{Automata, Grid};
+use crate::render::Glyph;
+
+use std::fmt::{Debug, Display};
+
+const GUTTER: &str = " | ";
+
+// Number of cells that differ between two grids of the same dims.
+pub fn divergence<T: Copy + Debug + PartialEq>(a: &Grid<T>, b: &Grid<T>) -> usize
+{
+    a.data.iter().zip(&b.data).filter(|(a, b)| a != b).count()
+}
+
+// Compact renderings of both grids and a third panel marking with `x` the
+// cells where they differ.
+pub fn side_by_side<T: Copy + Debug + PartialEq + Glyph>(a: &Grid<T>, b: &Grid<T>) -> String
+{
+    let mut out = String::new();
+    for j in 0..a.dims.1
+    {
+        let row = |grid: &Grid<T>| (0..grid.dims.0).map(|i| grid.get((i, j)).unwrap().glyph()).collect::<String>();
+        let diff: String = (0..a.dims.0)
+            .map(|i| if a.get((i, j)) == b.get((i, j)) { ' ' } else { 'x' })
+            .collect();
+        out.push_str(&[row(a), row(b), diff].join(GUTTER));
+        out.push('\n');
+    }
+    out
+}
+
+// Evolves both rules `steps` times, calling `frame` with the step, both
+// generations and their divergence before every step and after the last
+// one. Returns the divergence at every step, the initial one included.
+pub fn compare<T, A, B, F>(initial: Grid<T>, rule_a: A, rule_b: B, steps: usize, mut frame: F) -> Vec<usize>
+where
+    T: Clone + Display + Copy + Debug + PartialEq,
+    A: Fn(Vec<T>) -> T,
+    B: Fn(Vec<T>) -> T,
+    F: FnMut(u64, &Grid<T>, &Grid<T>, usize)
+{
+    let mut a = Automata::new(initial.clone());
+    let mut b = Automata::new(initial);
+    let mut counts = Vec::with_capacity(steps + 1);
+    for n in 0..=steps
+    {
+        if n > 0
+        {
+            a.evolve(&rule_a);
+            b.evolve(&rule_b);
+        }
+        let count = divergence(a.current(), b.current());
+        frame(a.step(), a.current(), b.current(), count);
+        counts.push(count);
+    }
+    counts
+}
+
+// Prints every step side by side, under a status line.
+pub fn run<T, A, B>(initial: Grid<T>, rule_a: A, rule_b: B, steps: usize) -> Vec<usize>
+where
+    T: Clone + Display + Copy + Debug + PartialEq + Glyph,
+    A: Fn(Vec<T>) -> T,
+    B: Fn(Vec<T>) -> T
+{
+    compare(initial, rule_a, rule_b, steps, |step, a, b, count| {
+        println!("step {}: {} of {} cells differ", step, count, a.data.len());
+        print!("{}", side_by_side(a, b));
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, Light};
+
+    fn lit() -> Grid<Light>
+    {
+        Grid::from_fn((15, 9), |coord| if coord == (7, 4) {Light::Source(8)} else {Light::Space(0)})
+    }
+
+    #[test]
+    fn the_same_rule_never_diverges()
+    {
+        let counts = compare(lit(), rules::light_decay(1), rules::light_decay(1), 12, |_, _, _, _| ());
+        assert_eq!(counts, vec![0; 13]);
+    }
+
+    #[test]
+    fn another_parameter_diverges()
+    {
+        let mut frames = vec![];
+        let counts = compare(lit(), rules::light_decay(1), rules::light_decay(2), 12, |step, a, b, count| {
+            assert_eq!(count, divergence(a, b));
+            frames.push(step);
+        });
+        assert_eq!(frames, (0..=12).collect::<Vec<u64>>());
+        // The first ring around the source is already different.
+        assert_eq!(counts[0], 0);
+        assert_eq!(counts[1], 3);
+        assert!(counts[2..].iter().all(|&count| count > 0));
+    }
+
+    #[test]
+    fn panels_mark_the_differences()
+    {
+        let a = Grid::from_fn((4, 2), |(i, _)| i == 1);
+        let b = Grid::from_fn((4, 2), |(i, j)| i == 1 || (i, j) == (3, 1));
+        assert_eq!(side_by_side(&a, &b), " #   |  #   |     \n #   |  # # |    x\n");
+        assert_eq!(divergence(&a, &b), 1);
+    }
+}
@@ -0,0 +1,280 @@
+// Convergence of rules on real values, which only come to rest in the
+// limit: two generations count as the same when no cell moved by more than
+// a Tolerance. A NaN never converges; it stops the run with the cell it
+// showed up in.
+
+use crate::{Automata, Grid};
+
+use std::fmt::{self, Debug, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance
+{
+    pub absolute: f64,
+    // Fraction of the larger magnitude of the two values.
+    pub relative: f64
+}
+
+impl Tolerance
+{
+    // Values within either bound are close enough.
+    pub fn new(absolute: f64, relative: f64) -> Self
+    {
+        Self{absolute, relative}
+    }
+
+    pub fn absolute(eps: f64) -> Self
+    {
+        Self::new(eps, 0.0)
+    }
+
+    pub fn relative(fraction: f64) -> Self
+    {
+        Self::new(0.0, fraction)
+    }
+
+    // Only equal values, as PartialEq would have it.
+    pub fn exact() -> Self
+    {
+        Self::new(0.0, 0.0)
+    }
+
+    // False as soon as either value is NaN.
+    pub fn accepts(&self, a: f64, b: f64) -> bool
+    {
+        let diff = (a - b).abs();
+        diff <= self.absolute || diff <= self.relative * a.abs().max(b.abs())
+    }
+}
+
+impl Default for Tolerance
+{
+    fn default() -> Self
+    {
+        Self::exact()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvergenceError
+{
+    // The cell held NaN at that step.
+    NotANumber{coord: (usize, usize), step: u64},
+    // Still moving after that many steps.
+    NotStable{steps: usize}
+}
+
+impl fmt::Display for ConvergenceError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            ConvergenceError::NotANumber{coord, step} => write!(f, "cell {:?} is NaN at step {}", coord, step),
+            ConvergenceError::NotStable{steps} => write!(f, "not stable after {} steps", steps)
+        }
+    }
+}
+
+impl<T: Copy + Debug> Grid<T>
+{
+    // Largest difference between the values of matching cells, None when
+    // the dims differ, NaN when a cell of either grid maps to NaN.
+    pub fn max_abs_diff<M: Fn(&T) -> f64>(&self, other: &Grid<T>, map: M) -> Option<f64>
+    {
+        if self.dims != other.dims
+        {
+            return None;
+        }
+        let mut max = 0.0f64;
+        for (a, b) in self.data.iter().zip(&other.data)
+        {
+            let diff = (map(a) - map(b)).abs();
+            if diff.is_nan()
+            {
+                return Some(f64::NAN);
+            }
+            max = max.max(diff);
+        }
+        Some(max)
+    }
+
+    // Whether every cell is within the tolerance of the other grid's; never
+    // for grids of other dims or holding NaN.
+    pub fn within<M: Fn(&T) -> f64>(&self, other: &Grid<T>, map: M, tolerance: Tolerance) -> bool
+    {
+        self.dims == other.dims
+            && self.data.iter().zip(&other.data).all(|(a, b)| tolerance.accepts(map(a), map(b)))
+    }
+
+    // First cell, in storage order, mapping to NaN.
+    pub fn find_nan<M: Fn(&T) -> f64>(&self, map: M) -> Option<(usize, usize)>
+    {
+        self.data.iter()
+            .position(|cell| map(cell).is_nan())
+            .map(|index| (index % self.dims.0, index / self.dims.0))
+    }
+}
+
+fn check_nan<T, M>(automata: &Automata<T>, map: M) -> Result<(), ConvergenceError>
+where
+    T: Copy + Debug + Display,
+    M: Fn(&T) -> f64
+{
+    match automata.current().find_nan(map)
+    {
+        Some(coord) => Err(ConvergenceError::NotANumber{coord, step: automata.step()}),
+        None => Ok(())
+    }
+}
+
+// Evolves until a step leaves every cell within the tolerance of its value
+// before it, for at most `max_steps` steps, and returns the number of steps
+// taken, that last one included.
+pub fn run_until_stable<T, F, M>(automata: &mut Automata<T>, rule: F, map: M, tolerance: Tolerance, max_steps: usize)
+                                 -> Result<usize, ConvergenceError>
+where
+    T: Copy + Debug + Display,
+    F: Fn(Vec<T>) -> T,
+    M: Fn(&T) -> f64
+{
+    check_nan(automata, &map)?;
+    for steps in 1..=max_steps
+    {
+        let previous = automata.current().clone();
+        automata.evolve(&rule);
+        check_nan(automata, &map)?;
+        if automata.current().within(&previous, &map, tolerance)
+        {
+            return Ok(steps);
+        }
+    }
+    Err(ConvergenceError::NotStable{steps: max_steps})
+}
+
+// Evolves for up to `max_period` steps and returns the smallest period at
+// which the whole grid comes back within the tolerance of an earlier
+// generation, as analysis::region_period does for exact repetitions. A
+// grid that comes to rest gives Some(1).
+pub fn find_cycle<T, F, M>(automata: &mut Automata<T>, rule: F, map: M, tolerance: Tolerance, max_period: usize)
+                           -> Result<Option<usize>, ConvergenceError>
+where
+    T: Copy + Debug + Display,
+    F: Fn(Vec<T>) -> T,
+    M: Fn(&T) -> f64
+{
+    check_nan(automata, &map)?;
+    let mut seen = vec![automata.current().clone()];
+    for _ in 0..max_period
+    {
+        automata.evolve(&rule);
+        check_nan(automata, &map)?;
+        let grid = automata.current();
+        if let Some(pos) = seen.iter().rposition(|earlier| grid.within(earlier, &map, tolerance))
+        {
+            return Ok(Some(seen.len() - pos));
+        }
+        seen.push(grid.clone());
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::rules;
+
+    fn hot(value: f32) -> Automata<f32>
+    {
+        let mut automata = Automata::new(Grid::new((12, 8), 0.0f32));
+        *automata.get_mut((5, 3)).unwrap() = value;
+        automata
+    }
+
+    fn value(cell: &f32) -> f64
+    {
+        f64::from(*cell)
+    }
+
+    #[test]
+    fn diffusion_settles_under_a_tolerance()
+    {
+        let mut automata = hot(100.0);
+        let steps = run_until_stable(&mut automata, rules::heat_diffusion(0.5), value, Tolerance::absolute(1e-3), 10_000).unwrap();
+        assert_eq!(automata.step(), steps as u64);
+        // The last step moved no cell by more than the tolerance, the one
+        // before did.
+        let mut again = hot(100.0);
+        for _ in 1..steps
+        {
+            again.evolve(rules::heat_diffusion(0.5));
+        }
+        let moved = again.current().max_abs_diff(automata.current(), value).unwrap();
+        assert!(moved <= 1e-3, "{}", moved);
+        let mut before = hot(100.0);
+        for _ in 2..steps
+        {
+            before.evolve(rules::heat_diffusion(0.5));
+        }
+        assert!(before.current().max_abs_diff(again.current(), value).unwrap() > 1e-3);
+
+        let mut short = hot(100.0);
+        assert_eq!(run_until_stable(&mut short, rules::heat_diffusion(0.5), value, Tolerance::absolute(1e-3), 5),
+                   Err(ConvergenceError::NotStable{steps: 5}));
+    }
+
+    #[test]
+    fn nans_name_their_cell()
+    {
+        let error = run_until_stable(&mut hot(f32::NAN), rules::heat_diffusion(0.5), value, Tolerance::absolute(1.0), 100).unwrap_err();
+        assert_eq!(error, ConvergenceError::NotANumber{coord: (5, 3), step: 0});
+        assert_eq!(error.to_string(), "cell (5, 3) is NaN at step 0");
+        // An infinite cell minus its infinite mean gives NaN at the first
+        // step, there and nowhere else.
+        let mut automata = hot(f32::INFINITY);
+        let error = find_cycle(&mut automata, rules::heat_diffusion(0.5), value, Tolerance::absolute(1.0), 100).unwrap_err();
+        assert_eq!(error, ConvergenceError::NotANumber{coord: (5, 3), step: 1});
+        assert_eq!(automata.current().find_nan(value), Some((5, 3)));
+        assert_eq!(automata.current().data.iter().filter(|cell| cell.is_nan()).count(), 1);
+    }
+
+    #[test]
+    fn tolerances_and_differences()
+    {
+        assert!(Tolerance::absolute(0.1).accepts(1.0, 1.05));
+        assert!(!Tolerance::absolute(0.1).accepts(1.0, 1.2));
+        assert!(Tolerance::relative(0.01).accepts(1000.0, 1009.0));
+        assert!(!Tolerance::relative(0.01).accepts(1.0, 1.1));
+        assert!(Tolerance::exact().accepts(2.0, 2.0));
+        assert!(!Tolerance::new(1.0, 1.0).accepts(f64::NAN, f64::NAN));
+
+        let a = Grid::from_fn((3, 2), |(i, j)| (i + j) as f32);
+        let mut b = a.clone();
+        *b.get_mut((2, 1)).unwrap() += 0.5;
+        assert_eq!(a.max_abs_diff(&b, value), Some(0.5));
+        assert_eq!(a.max_abs_diff(&Grid::new((2, 3), 0.0), value), None);
+        assert!(a.within(&b, value, Tolerance::absolute(0.5)));
+        assert!(!a.within(&b, value, Tolerance::exact()));
+        *b.get_mut((0, 1)).unwrap() = f32::NAN;
+        assert!(a.max_abs_diff(&b, value).unwrap().is_nan());
+        assert!(!a.within(&b, value, Tolerance::absolute(f64::INFINITY)));
+        assert_eq!(b.find_nan(value), Some((0, 1)));
+    }
+
+    #[test]
+    fn cycles_within_a_tolerance()
+    {
+        // Every cell flips sign and shrinks a little: exactly, it never
+        // repeats; within 5%, it does every two steps.
+        let flip = |ngh: Vec<f32>| -0.99 * ngh[0];
+        let mut automata = Automata::new(Grid::from_fn((4, 3), |(i, j)| (1 + i + j) as f32));
+        assert_eq!(find_cycle(&mut automata, flip, value, Tolerance::exact(), 10), Ok(None));
+        let mut automata = Automata::new(Grid::from_fn((4, 3), |(i, j)| (1 + i + j) as f32));
+        assert_eq!(find_cycle(&mut automata, flip, value, Tolerance::relative(0.05), 10), Ok(Some(2)));
+        // Diffusion at rest repeats every step.
+        let mut settled = hot(100.0);
+        run_until_stable(&mut settled, rules::heat_diffusion(0.5), value, Tolerance::absolute(1e-3), 10_000).unwrap();
+        assert_eq!(find_cycle(&mut settled, rules::heat_diffusion(0.5), value, Tolerance::absolute(1e-3), 10), Ok(Some(1)));
+    }
+}
@@ -0,0 +1,285 @@
+// Triangles are stored row by row: (i, j) is the i-th triangle of the j-th
+// row, pointing up when i+j is even and down otherwise. That is convenient
+// for storage but not for geometry, so TriCoord gives every triangle three
+// integer coordinates (a, b, c), one per family of lattice lines:
+//
+//  - a counts the horizontal lines above the triangle (it is the row j),
+//  - b and c count the two families of slanted lines,
+//
+// with a + b + c == 1 for upward triangles and 2 for downward ones. The
+// six triangles around the lattice vertex used as origin (the bottom
+// vertex of storage cell (1, 0)) are, numbered as in the drawing at the
+// bottom of main.rs:
+//
+//      ·-----·
+//     / \ 2 / \           1: (0,0,1) = (0,0)    4: (1,1,0) = (2,1)
+//    / 1 \ / 3 \          2: (0,1,1) = (1,0)    5: (1,0,0) = (1,1)
+//   ·-----·-----·         3: (0,1,0) = (2,0)    6: (1,0,1) = (0,1)
+//    \ 6 / \ 4 /
+//     \ / 5 \ /
+//      ·-----·
+//
+// Moving to an edge-adjacent triangle changes exactly one coordinate by
+// one, and rotations and mirrors around the origin are permutations.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Coord
+{
+    pub i: isize,
+    pub j: isize
+}
+
+impl Coord
+{
+    pub fn new(i: isize, j: isize) -> Self
+    {
+        Self{i, j}
+    }
+
+    // The storage index pair, if it lies inside a grid of the given dims.
+    pub fn to_storage(self, dims: (usize, usize)) -> Option<(usize, usize)>
+    {
+        if self.i < 0 || self.j < 0
+        {
+            return None;
+        }
+        let (i, j) = (self.i as usize, self.j as usize);
+        if i < dims.0 && j < dims.1
+        {
+            Some((i, j))
+        }
+        else
+        {
+            None
+        }
+    }
+
+    // The coordinate moved by (di, dj), if it is still inside a grid of the
+    // given dims.
+    pub fn offset(self, di: isize, dj: isize, dims: (usize, usize)) -> Option<Coord>
+    {
+        let moved = Coord::new(self.i.checked_add(di)?, self.j.checked_add(dj)?);
+        moved.to_storage(dims).map(|_| moved)
+    }
+
+    // Center of the triangle in the plane, for unit edges, with x to the
+    // right and y downwards like the rows.
+    pub fn centroid(self) -> (f64, f64)
+    {
+        let row_height = 3f64.sqrt() / 2.0;
+        let x = (self.i as f64 + 1.0) / 2.0;
+        let y = if (self.i + self.j).rem_euclid(2) == 0
+        {
+            self.j as f64 + 2.0 / 3.0
+        }
+        else
+        {
+            self.j as f64 + 1.0 / 3.0
+        };
+        (x, y * row_height)
+    }
+}
+
+impl From<(usize, usize)> for Coord
+{
+    fn from((i, j): (usize, usize)) -> Self
+    {
+        Self{i: i as isize, j: j as isize}
+    }
+}
+
+impl From<TriCoord> for Coord
+{
+    fn from(tri: TriCoord) -> Self
+    {
+        tri.to_storage()
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TriCoord
+{
+    pub a: isize,
+    pub b: isize,
+    pub c: isize
+}
+
+impl TriCoord
+{
+    pub fn new(a: isize, b: isize, c: isize) -> Option<Self>
+    {
+        match a + b + c
+        {
+            1 | 2 => Some(Self{a, b, c}),
+            _ => None
+        }
+    }
+
+    pub fn from_storage<C: Into<Coord>>(coord: C) -> Self
+    {
+        let Coord{i, j} = coord.into();
+        if (i + j).rem_euclid(2) == 0
+        {
+            let b = (i - j) / 2;
+            Self{a: j, b, c: 1 - b - j}
+        }
+        else
+        {
+            let b = (i - j + 1) / 2;
+            Self{a: j, b, c: 2 - b - j}
+        }
+    }
+
+    pub fn to_storage(self) -> Coord
+    {
+        if self.is_up()
+        {
+            Coord{i: 2*self.b + self.a, j: self.a}
+        }
+        else
+        {
+            Coord{i: 2*self.b + self.a - 1, j: self.a}
+        }
+    }
+
+    pub fn is_up(self) -> bool
+    {
+        self.a + self.b + self.c == 1
+    }
+
+    // Edge-adjacent triangles in the same order as Grid::neighbor_coords:
+    // left, right, then the one across the horizontal edge.
+    pub fn neighbors(self) -> [TriCoord; 3]
+    {
+        let Self{a, b, c} = self;
+        if self.is_up()
+        {
+            [Self{a, b, c: c+1}, Self{a, b: b+1, c}, Self{a: a+1, b, c}]
+        }
+        else
+        {
+            [Self{a, b: b-1, c}, Self{a, b, c: c-1}, Self{a: a-1, b, c}]
+        }
+    }
+
+    // 60° rotations around the origin vertex, as seen in the drawing above
+    // (1 -> 6 -> 5 -> ... counter-clockwise).
+    pub fn rotate_ccw(self) -> Self
+    {
+        Self{a: 1 - self.b, b: 1 - self.c, c: 1 - self.a}
+    }
+
+    pub fn rotate_cw(self) -> Self
+    {
+        Self{a: 1 - self.c, b: 1 - self.a, c: 1 - self.b}
+    }
+
+    // Mirror across the vertical line through the origin vertex (1 <-> 3).
+    pub fn mirror(self) -> Self
+    {
+        Self{a: self.a, b: self.c, c: self.b}
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::Grid;
+
+    #[test]
+    fn storage_round_trips()
+    {
+        for j in -6..6
+        {
+            for i in -6..6
+            {
+                let tri = TriCoord::from_storage(Coord::new(i, j));
+                assert_eq!(TriCoord::new(tri.a, tri.b, tri.c), Some(tri));
+                assert_eq!(tri.is_up(), (i + j).rem_euclid(2) == 0);
+                assert_eq!(tri.to_storage(), Coord::new(i, j));
+            }
+        }
+    }
+
+    #[test]
+    fn drawing_around_the_origin()
+    {
+        let cells = [((0, 0, 1), (0, 0)), ((0, 1, 1), (1, 0)), ((0, 1, 0), (2, 0)),
+                     ((1, 1, 0), (2, 1)), ((1, 0, 0), (1, 1)), ((1, 0, 1), (0, 1))];
+        for &((a, b, c), storage) in &cells
+        {
+            assert_eq!(TriCoord::from_storage(storage), TriCoord::new(a, b, c).unwrap());
+        }
+    }
+
+    #[test]
+    fn neighbors_match_the_grid()
+    {
+        let grid = Grid::from_fn((7, 5), |(i, j)| (i, j));
+        for j in 0..5
+        {
+            for i in 0..7
+            {
+                let tri = TriCoord::from_storage((i, j));
+                let axial: Vec<(usize, usize)> = tri.neighbors().iter()
+                    .filter_map(|&n| Coord::from(n).to_storage(grid.dims))
+                    .collect();
+                assert_eq!(axial, grid.neighbor_coords((i, j))[1..], "around {:?}", (i, j));
+                let through_get: Vec<(usize, usize)> = tri.neighbors().iter().filter_map(|&n| grid.get(n).copied()).collect();
+                let stored: Vec<(usize, usize)> = grid.neighborhood((i, j))[1..].iter().map(|&&cell| cell).collect();
+                assert_eq!(through_get, stored);
+            }
+        }
+    }
+
+    #[test]
+    fn neighbors_change_one_coordinate()
+    {
+        let tri = TriCoord::from_storage((3, 2));
+        for n in &tri.neighbors()
+        {
+            let moved = (n.a - tri.a).abs() + (n.b - tri.b).abs() + (n.c - tri.c).abs();
+            assert_eq!(moved, 1);
+            assert!(n.neighbors().contains(&tri));
+        }
+    }
+
+    #[test]
+    fn rotations_and_mirrors()
+    {
+        let tri = TriCoord::from_storage((5, 3));
+        let mut turned = tri;
+        for _ in 0..6
+        {
+            turned = turned.rotate_ccw();
+        }
+        assert_eq!(turned, tri);
+        assert_eq!(tri.rotate_ccw().rotate_cw(), tri);
+        assert_eq!(tri.mirror().mirror(), tri);
+        // 1 -> 6 in the drawing, and 1 <-> 3.
+        assert_eq!(TriCoord::from_storage((0, 0)).rotate_ccw(), TriCoord::from_storage((0, 1)));
+        assert_eq!(TriCoord::from_storage((0, 0)).mirror(), TriCoord::from_storage((2, 0)));
+        // Adjacency survives a rotation.
+        for n in &tri.neighbors()
+        {
+            assert!(tri.rotate_ccw().neighbors().contains(&n.rotate_ccw()));
+        }
+    }
+
+    #[test]
+    fn offsets_stay_in_the_grid()
+    {
+        let dims = (7, 5);
+        assert_eq!(Coord::new(3, 2).offset(-3, -2, dims), Some(Coord::new(0, 0)));
+        assert_eq!(Coord::new(3, 2).offset(-4, 0, dims), None);
+        assert_eq!(Coord::new(3, 2).offset(0, -3, dims), None);
+        assert_eq!(Coord::new(3, 2).offset(3, 2, dims), Some(Coord::new(6, 4)));
+        // Exactly one past the far edges.
+        assert_eq!(Coord::new(3, 2).offset(4, 0, dims), None);
+        assert_eq!(Coord::new(3, 2).offset(0, 3, dims), None);
+        assert_eq!(Coord::new(0, 0).offset(isize::MIN, 0, dims), None);
+        assert_eq!(Coord::new(isize::MAX, 0).offset(1, 0, dims), None);
+    }
+}
@@ -0,0 +1,173 @@
+// Grids as CSV matrices, for spreadsheets: one line per row j, one field
+// per column i, each cell written and read back by functions of the
+// caller's. Fields are not quoted, so cells must not write commas or line
+// breaks. Reading accepts CRLF line endings and blank lines at the end.
+
+use crate::{Grid, Light};
+
+use std::fmt::{self, Debug, Display};
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+#[derive(Debug)]
+pub enum CsvError
+{
+    Io(io::Error),
+    // No line at all.
+    Empty,
+    // Rows and columns count from 1, like lines in an editor.
+    Ragged{row: usize, columns: usize, expected: usize},
+    Cell{row: usize, column: usize, message: String}
+}
+
+impl fmt::Display for CsvError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            CsvError::Io(error) => write!(f, "{}", error),
+            CsvError::Empty => write!(f, "no rows"),
+            CsvError::Ragged{row, columns, expected} => write!(f, "row {}: {} columns where the first row has {}", row, columns, expected),
+            CsvError::Cell{row, column, message} => write!(f, "row {}, column {}: {}", row, column, message)
+        }
+    }
+}
+
+impl From<io::Error> for CsvError
+{
+    fn from(error: io::Error) -> Self
+    {
+        CsvError::Io(error)
+    }
+}
+
+impl<T: Copy + Debug> Grid<T>
+{
+    pub fn to_csv<W: Write, F: Fn(&T) -> String>(&self, mut writer: W, format: F) -> io::Result<()>
+    {
+        for row in self.data.chunks(self.dims.0.max(1))
+        {
+            let fields: Vec<String> = row.iter().map(&format).collect();
+            writeln!(writer, "{}", fields.join(","))?;
+        }
+        writer.flush()
+    }
+
+    pub fn from_csv<R, F, E>(reader: R, parse: F) -> Result<Grid<T>, CsvError>
+    where
+        R: Read,
+        F: Fn(&str) -> Result<T, E>,
+        E: Display
+    {
+        let mut lines = vec![];
+        for line in BufReader::new(reader).lines()
+        {
+            let line = line?;
+            lines.push(line.strip_suffix('\r').map(str::to_string).unwrap_or(line));
+        }
+        while lines.last().is_some_and(|line| line.is_empty())
+        {
+            lines.pop();
+        }
+        let width = lines.first().ok_or(CsvError::Empty)?.split(',').count();
+        let mut data = Vec::with_capacity(width*lines.len());
+        for (j, line) in lines.iter().enumerate()
+        {
+            let fields: Vec<&str> = line.split(',').collect();
+            if fields.len() != width
+            {
+                return Err(CsvError::Ragged{row: j + 1, columns: fields.len(), expected: width});
+            }
+            for (i, field) in fields.into_iter().enumerate()
+            {
+                data.push(parse(field).map_err(|error| CsvError::Cell{row: j + 1, column: i + 1, message: error.to_string()})?);
+            }
+        }
+        Ok(Grid{data, dims: (width, lines.len())})
+    }
+}
+
+// Light cells for to_csv and from_csv: S10 for Source(10), 12 for
+// Space(12).
+pub fn format_light(cell: &Light) -> String
+{
+    match cell
+    {
+        Light::Source(level) => format!("S{}", level),
+        Light::Space(level) => level.to_string()
+    }
+}
+
+pub fn parse_light(text: &str) -> Result<Light, String>
+{
+    let text = text.trim();
+    let level = |digits: &str| digits.parse::<u8>().map_err(|_| format!("invalid light '{}' (S<level> or <level>)", text));
+    match text.strip_prefix('S')
+    {
+        Some(digits) => level(digits).map(Light::Source),
+        None => level(text).map(Light::Space)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn lights() -> Grid<Light>
+    {
+        Grid::from_fn((4, 3), |(i, j)| if (i, j) == (1, 2) {Light::Source(10)} else {Light::Space((i*7 + j) as u8)})
+    }
+
+    fn written<T: Copy + Debug>(grid: &Grid<T>, format: impl Fn(&T) -> String) -> String
+    {
+        let mut out = vec![];
+        grid.to_csv(&mut out, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn lights_round_trip()
+    {
+        let text = written(&lights(), format_light);
+        assert_eq!(text, "0,7,14,21\n1,8,15,22\n2,S10,16,23\n");
+        let read = Grid::from_csv(text.as_bytes(), parse_light).unwrap();
+        assert_eq!(read, lights());
+        // Other states, with the caller's functions.
+        let numbers = Grid::from_fn((3, 5), |(i, j)| i as f32 * 0.5 - j as f32);
+        let text = written(&numbers, |cell| cell.to_string());
+        assert_eq!(Grid::from_csv(text.as_bytes(), str::parse::<f32>).unwrap(), numbers);
+    }
+
+    #[test]
+    fn line_endings_and_blank_lines()
+    {
+        let read = Grid::from_csv("0,S3\r\n4, 5\r\n\r\n\n".as_bytes(), parse_light).unwrap();
+        assert_eq!(read.dims, (2, 2));
+        assert_eq!(read.data, vec![Light::Space(0), Light::Source(3), Light::Space(4), Light::Space(5)]);
+        let single = Grid::from_csv("7".as_bytes(), parse_light).unwrap();
+        assert_eq!((single.dims, single.data), ((1, 1), vec![Light::Space(7)]));
+    }
+
+    #[test]
+    fn malformed_input_is_located()
+    {
+        match Grid::from_csv("1,2,3\n4,5,6\n7,8\n".as_bytes(), parse_light)
+        {
+            Err(error @ CsvError::Ragged{..}) => assert_eq!(error.to_string(), "row 3: 2 columns where the first row has 3"),
+            other => panic!("expected a ragged row, got {:?}", other)
+        }
+        match Grid::from_csv("1,2\r\n3,S300\r\n".as_bytes(), parse_light)
+        {
+            Err(CsvError::Cell{row, column, message}) =>
+            {
+                assert_eq!((row, column), (2, 2));
+                assert_eq!(message, "invalid light 'S300' (S<level> or <level>)");
+            },
+            other => panic!("expected a bad cell, got {:?}", other)
+        }
+        assert!(matches!(Grid::from_csv("1,,3\n".as_bytes(), parse_light), Err(CsvError::Cell{row: 1, column: 2, ..})));
+        assert!(matches!(Grid::from_csv("\n\n".as_bytes(), parse_light), Err(CsvError::Empty)));
+        assert!(matches!(Grid::from_csv(&[0xff, b'\n'][..], parse_light), Err(CsvError::Io(_))));
+    }
+}
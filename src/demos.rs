@@ -0,0 +1,545 @@
+use triangle_automata::{Automata, CellState, Grid, Light, Rule, SourceProgram};
+use triangle_automata::analysis;
+use triangle_automata::color::ColorMap;
+use crate::cli::{ModeChoice, Options};
+use triangle_automata::compare;
+use triangle_automata::convergence::{self, Tolerance};
+use triangle_automata::coord::Coord;
+use triangle_automata::error::Error;
+use triangle_automata::image::{self, RenderOptions};
+use triangle_automata::lantern::{self, Lantern, LanternRule};
+use triangle_automata::machine::{self, StateMachineRule};
+use triangle_automata::pattern::Pattern;
+use triangle_automata::refraction::{self, Medium, RefractiveFalloff};
+use triangle_automata::render::{self, CellFormat, DiffRenderer};
+use triangle_automata::rules::{self, Clamp};
+use triangle_automata::session::Session;
+use triangle_automata::sources::{self, SourceSet};
+use triangle_automata::validate;
+use triangle_automata::run;
+#[cfg(feature = "ws")]
+use triangle_automata::ws;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+// The falloff rule with the ceiling asked for on the command line.
+fn falloff(options: &Options) -> impl Fn(Vec<Light>) -> Light
+{
+    let rule = rules::light_falloff_clamped(Clamp::saturate(options.max_intensity));
+    move |ngh| rule(ngh).expect("saturating rules do not fail")
+}
+
+// With --validate, checks that the demo rule keeps sources fixed, on small
+// neighborhoods exhaustively and on random ones, and exits if it does not.
+fn check_sources(options: &Options)
+{
+    if !options.validate
+    {
+        return;
+    }
+    let rule = falloff(options);
+    let small = [Light::Source(0), Light::Source(3), Light::Space(0), Light::Space(2), Light::Space(255)];
+    let result = validate::check_invariant(&rule, validate::sources_are_fixed, validate::exhaustive_neighborhoods(&small))
+        .and_then(|()| validate::check_invariant(&rule, validate::sources_are_fixed,
+                                                 validate::random_neighborhoods(0, 10_000, validate::random_light)));
+    match result
+    {
+        Ok(()) => println!("sources stay fixed"),
+        Err(violation) =>
+        {
+            eprintln!("the rule changes a source: {:?} -> {:?}", violation.neighborhood, violation.output);
+            std::process::exit(1);
+        }
+    }
+}
+
+// With --validate, checks that the light of the demo's source spreads by
+// one cell a step for as long as it lasts, and exits if it does not.
+fn check_front(options: &Options, dims: (usize, usize))
+{
+    if !options.validate
+    {
+        return;
+    }
+    let mut grid = Grid::new(dims, Light::Space(0));
+    *grid.get_mut((10, 10)).unwrap() = Light::Source(10);
+    match analysis::check_unit_speed(&mut Automata::new(grid), falloff(options), (10, 10))
+    {
+        Ok(steps) => println!("the light spreads by one cell a step for {} steps", steps),
+        Err((step, radius)) =>
+        {
+            eprintln!("the light's front has a radius of {:?} after {} steps", radius, step);
+            std::process::exit(1);
+        }
+    }
+}
+
+// The light demo's automaton of at least `dims`, grown to fit the
+// --pattern, with the --source sources, which must be in the grid, or its
+// single source.
+pub(crate) fn light_start(options: &Options, (w, h): (usize, usize)) -> Result<Automata<Light>, Error>
+{
+    let mut automata = Automata::new(Grid::new((w,h), Light::Space(0)));
+    if let Some(path) = &options.pattern
+    {
+        let pattern = std::fs::read_to_string(path)
+            .map_err(|error| error.to_string())
+            .and_then(|text| Pattern::parse_any(&text, str::parse))
+            .map_err(|message| Error::Parse(format!("{}: {}", path, message)))?;
+        let (pw, ph) = pattern.dims();
+        let mut grid = Grid::try_new((w.max(pw + pattern.parity), h.max(ph)), Light::Space(0))?;
+        grid.stamp(&pattern, (pattern.parity, 0)).expect("the offset has the pattern's parity");
+        automata = Automata::new(grid);
+    }
+    if options.sources.is_empty() && options.pattern.is_none()
+    {
+        if let Some(light) = automata.get_mut((10,10))
+        {
+            *light = Light::Source(10);
+        }
+    }
+    if !options.sources.is_empty()
+    {
+        let mut sources = SourceSet::new();
+        for source in &options.sources
+        {
+            source.check(automata.current().dims())?;
+            sources.add(source.clone());
+        }
+        sources::attach(&Rc::new(RefCell::new(sources)), &mut automata);
+    }
+    Ok(automata)
+}
+
+// A single source lit for 10 frames, then switched off, or the --pattern
+// and --source sources for 30 frames, the grid growing to fit the pattern.
+// With --diff, frames are drawn by a DiffRenderer and the bytes saved are
+// reported at the end.
+pub fn light(options: &Options)
+{
+    let (w,h) = (30, 20);
+    let term_dims = render::terminal_size();
+    let (diff, pacing) = (options.diff, &options.pacing);
+    check_sources(options);
+    check_front(options, (w, h));
+
+    let automata = match light_start(options, (w, h))
+    {
+        Ok(automata) => automata,
+        Err(error) =>
+        {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    let mode = options.mode.resolve(automata.current().dims(), term_dims);
+
+    let mut session = Session::new(automata, Box::new(falloff(options)));
+    let mut renderer = DiffRenderer::new(0.5);
+    let (mut written, mut repainted) = (0, 0);
+    let mut show = |grid: &Grid<Light>| {
+        if diff
+        {
+            let frame = renderer.frame(grid, mode, term_dims);
+            print!("{}", frame);
+            written += frame.len();
+            repainted += render::render_mode(grid, mode).len() + render::CLEAR.len();
+        }
+        else
+        {
+            render::show(grid, mode);
+        }
+    };
+
+    if options.sources.is_empty() && options.pattern.is_none()
+    {
+        session.run_loop(10, &mut show, pacing);
+        if let Some(light) = session.automata.get_mut((10,10))
+        {
+            *light = Light::Space(10);
+        }
+        session.run_loop(20, &mut show, pacing);
+    }
+    else
+    {
+        session.run_loop(30, &mut show, pacing);
+    }
+    if diff
+    {
+        // Leave the cursor below the grid.
+        let rows = render::render_mode(session.current(), mode).lines().count();
+        println!("\x1b[{};1H", rows + 1);
+        eprintln!("{} bytes written, {} with full repaints", written, repainted);
+    }
+}
+
+// Two sources blinking out of phase: the wave fronts they emit alternate and
+// leave bands between them.
+pub fn blink(options: &Options)
+{
+    let (w,h) = (40, 20);
+    let mode = options.mode.resolve((w, h), render::terminal_size());
+    check_sources(options);
+
+    let mut automata = Automata::new(Grid::new((w,h), Light::Space(0)));
+    for &(coord, phase) in &[((10, 10), 0), ((30, 10), 4)]
+    {
+        automata.add_source_program(coord, SourceProgram::Square{
+            period: 8,
+            duty: 4,
+            phase,
+            on: Light::Source(9),
+            off: Light::Source(0)
+        });
+    }
+
+    run::run_loop(&mut automata, falloff(options), 40, |grid| render::show(grid, mode), &options.pacing);
+}
+
+// The blink demo, broadcast to browsers (see examples/ws_viewer.html)
+// for an hour at the --fps rate, 10 steps per second by default.
+#[cfg(feature = "ws")]
+pub fn live(options: &Options, addr: &str) -> std::io::Result<()>
+{
+    let mut automata = Automata::new(Grid::new((40, 20), Light::Space(0)));
+    for &(coord, phase) in &[((10, 10), 0), ((30, 10), 4)]
+    {
+        automata.add_source_program(coord, SourceProgram::Square{period: 8, duty: 4, phase, on: Light::Source(9), off: Light::Source(0)});
+    }
+    let fps = if options.pacing.target_fps > 0.0 { f64::from(options.pacing.target_fps) } else { 10.0 };
+    let broadcast = ws::BroadcastOptions{fps, ..ws::BroadcastOptions::default()};
+    println!("open http://{}/ to watch", addr);
+    let steps = (fps * 3600.0) as usize;
+    let stats = ws::broadcast(&mut automata, falloff(options), steps, |light: &Light| f64::from(light.level()), addr, &broadcast)?;
+    println!("{} steps, {} clients, {} messages sent, {} skipped", stats.steps, stats.clients, stats.sent, stats.skipped);
+    Ok(())
+}
+
+// The falloff and additive rules side by side, from a single source.
+pub fn compare()
+{
+    let mut grid = Grid::new((30, 12), Light::Space(0));
+    *grid.get_mut((14, 6)).unwrap() = Light::Source(9);
+    compare::run(grid, rules::light_falloff, rules::light_additive, 10);
+}
+
+// A pile of grains dropped in the middle of the grid, spreading until no
+// cell holds 3 grains anymore. With --validate, the grain count is checked
+// against what falls off the edges first.
+pub fn sandpile(options: &Options)
+{
+    let (w,h) = (31, 16);
+    let mode = options.mode.resolve((w, h), render::terminal_size());
+    let mut grid = Grid::new((w,h), 0u8);
+    *grid.get_mut((15, 8)).unwrap() = 200;
+    let steps = 100;
+
+    if options.validate
+    {
+        let mut check = Automata::new(grid.clone());
+        let grains = |cell: &u8| i64::from(*cell);
+        match validate::check_conservation(&mut check, rules::sandpile, grains, steps, Some(rules::sandpile_boundary_loss))
+        {
+            Ok(()) => println!("grains conserved over {} steps", steps),
+            Err(violation) =>
+            {
+                eprintln!("conservation violated: {:?}", violation);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut automata = Automata::new(grid);
+    run::run_loop(&mut automata, rules::sandpile, steps as usize, |grid| render::show(grid, mode), &options.pacing);
+}
+
+// A hot cell kept at 100 on a cold grid: heat spreads until the grid stops
+// changing by more than 0.05 per step, showing every tenth step.
+pub fn heat(mode: ModeChoice)
+{
+    let (w,h) = (24, 12);
+    let mode = mode.resolve((w, h), render::terminal_size());
+    let format = CellFormat{width: 3, precision: 0, ..CellFormat::default()};
+
+    let mut automata = Automata::new(Grid::new((w,h), 0f32));
+    automata.add_source_program((12, 6), SourceProgram::Constant(100.0));
+    let rule = rules::heat_diffusion(0.5);
+
+    for step in 0..2000
+    {
+        let previous = automata.current().clone();
+        automata.evolve(&rule);
+        if automata.current().approx_stable(&previous, 0.05)
+        {
+            println!("stable after {} steps", step + 1);
+            break;
+        }
+        if step % 10 == 0
+        {
+            render::show_formatted(automata.current(), mode, &format);
+        }
+    }
+    render::show_formatted(automata.current(), mode, &format);
+}
+
+// A second kind of cells for the light rules: burning coals, which keep
+// their heat, and the ash around them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ember
+{
+    Coal(u8),
+    Ash(u8)
+}
+
+impl CellState for Ember
+{
+    fn level(&self) -> u8
+    {
+        match self
+        {
+            Ember::Coal(level) | Ember::Ash(level) => *level
+        }
+    }
+
+    fn with_level(&self, level: u8) -> Self
+    {
+        match self
+        {
+            Ember::Coal(_) => Ember::Coal(level),
+            Ember::Ash(_) => Ember::Ash(level)
+        }
+    }
+
+    fn default_free() -> Self
+    {
+        Ember::Ash(0)
+    }
+
+    fn is_pinned(&self) -> bool
+    {
+        matches!(self, Ember::Coal(_))
+    }
+}
+
+impl std::fmt::Display for Ember
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        write!(f, "{:^3}", self.level())
+    }
+}
+
+const EMBER_HEAT: u8 = 12;
+
+// Compact rendering with every glyph in its color on the map, in a
+// terminal that understands 24-bit colors.
+fn show_embers(grid: &Grid<Ember>, colormap: ColorMap)
+{
+    let mut text = String::new();
+    for j in 0..grid.dims().1
+    {
+        for i in 0..grid.dims().0
+        {
+            let cell = grid.get((i, j)).unwrap();
+            let (r, g, b) = colormap.color(f64::from(cell.level()) / f64::from(EMBER_HEAT));
+            text.push_str(&format!("\x1b[38;2;{};{};{}m{}", r, g, b, render::Glyph::glyph(cell)));
+        }
+        text.push_str("\x1b[0m\n");
+    }
+    println!("{}", text);
+}
+
+// Three coals heating the ash around them, with heat lost twice as fast as
+// light, then put out after 10 steps, drawn with the heat colors or the
+// --palette ones.
+pub fn embers(options: &Options)
+{
+    let colormap = options.palette.unwrap_or(ColorMap::Heat);
+    let show = |grid: &Grid<Ember>| show_embers(grid, colormap);
+    let mut automata = Automata::new(Grid::new((36, 12), Ember::default_free()));
+    for &coord in &[(8, 6), (18, 3), (27, 8)]
+    {
+        *automata.get_mut(coord).unwrap() = Ember::Coal(EMBER_HEAT);
+    }
+    let rule = rules::light_decay(2);
+    run::run_loop(&mut automata, &rule, 10, show, &options.pacing);
+    for &coord in &[(8, 6), (18, 3), (27, 8)]
+    {
+        *automata.get_mut(coord).unwrap() = Ember::Ash(EMBER_HEAT);
+    }
+    run::run_loop(&mut automata, &rule, 8, show, &options.pacing);
+}
+
+const LENS_SOURCE: u8 = 200;
+
+// A source left of a lens of dense medium (cost 3), lit until the light
+// settles. Its fronts, drawn every 10 levels, fall behind inside the lens
+// and come out bent around it. With --validate, the result is checked
+// against the cheapest paths of refraction::reference_field; with --image,
+// drawn to a PNG as well.
+pub fn lens(options: &Options)
+{
+    let (w, h) = (60, 30);
+    let mode = options.mode.resolve((w, h), render::terminal_size());
+    let in_lens = |(i, j): (usize, usize)| {
+        let (x, y) = Coord::from((i, j)).centroid();
+        let (dx, dy) = ((x - 16.0) / 3.0, (y - 13.0) / 9.0);
+        dx*dx + dy*dy < 1.0
+    };
+    let mut grid = Grid::from_fn((w, h), |coord| Medium::space(if in_lens(coord) { 3 } else { 1 }));
+    *grid.get_mut((4, 15)).unwrap() = Medium::source(LENS_SOURCE);
+
+    let reference = refraction::reference_field(&grid);
+    let mut automata = Automata::new(grid);
+    let level = |cell: &Medium| f64::from(cell.level());
+    let steps = match convergence::run_until_stable(&mut automata, |ngh| RefractiveFalloff.apply(ngh), level, Tolerance::exact(), 1000)
+    {
+        Ok(steps) => steps,
+        Err(error) =>
+        {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    // Bands of 20 levels, which single digits can show.
+    let bands = Grid::from_fn((w, h), |coord| automata.current().get(coord).unwrap().level() / 20);
+    render::show(&bands, mode);
+    println!("settled after {} steps", steps);
+
+    if options.validate
+    {
+        let settled = automata.current();
+        match (0..w*h).map(|k| (k % w, k / w)).find(|&coord| u32::from(settled.get(coord).unwrap().level()) != *reference.get(coord).unwrap())
+        {
+            None => println!("equal to the cheapest paths everywhere"),
+            Some(coord) =>
+            {
+                eprintln!("{:?} holds {}, its cheapest path brings {}", coord, settled.get(coord).unwrap().level(), reference.get(coord).unwrap());
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(path) = &options.image
+    {
+        let colormap = options.palette.unwrap_or(ColorMap::Viridis);
+        let color = |cell: &Medium| {
+            let level = cell.level();
+            if level > 0 && level.is_multiple_of(10)
+            {
+                return (255, 255, 255);
+            }
+            let (r, g, b) = colormap.color(f64::from(level) / f64::from(LENS_SOURCE));
+            // The lens a little darker.
+            if cell.cost > 1 { (r / 4 * 3, g / 4 * 3, b / 4 * 3) } else { (r, g, b) }
+        };
+        if let Err(error) = image::png(automata.current(), color, &RenderOptions{supersample: 2, ..RenderOptions::default()}, path)
+        {
+            eprintln!("{}: {}", path, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+// A lantern lit in the dark for 40 frames: the cells around it warm up
+// until they catch fire, burn out and cool down, the light and the heat
+// drawn side by side. --threshold and --diffusivity change those of the
+// rule; with --validate, the ignition threshold is checked first, and that
+// without coupling light and heat evolve as their own rules would.
+pub fn lantern(options: &Options)
+{
+    let params = options.lantern;
+    let mut grid = Grid::new((36, 12), Lantern::space());
+    *grid.get_mut((8, 6)).unwrap() = Lantern::source(12);
+
+    if options.validate
+    {
+        let warm = Grid::from_fn(grid.dims(), |(i, j)| Lantern{heat: ((i*7 + j*3) % 10) as f32, ..*grid.get((i, j)).unwrap()});
+        match lantern::check_ignition(params).and_then(|()| lantern::check_uncoupled(&warm, params, 50))
+        {
+            Ok(()) => println!("ignites past the threshold only, and uncoupled fields evolve on their own"),
+            Err(mismatch) =>
+            {
+                eprintln!("{}", mismatch);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let rule = LanternRule{params};
+    let step = Cell::new(0);
+    let mut automata = Automata::new(grid);
+    run::run_loop_with(&mut automata, |automata| {
+        automata.evolve(|ngh| rule.apply(ngh));
+        step.set(automata.step());
+    }, 40, |grid| print!("{}", lantern::panels(grid, step.get(), params.threshold)), &options.pacing);
+}
+
+// Electrons going round a wire loop for 40 frames, the wire leaving the
+// loop taking a copy of every one of them, or a --machine description run
+// on the same cells (0 off the wire, 1 for heads, 2 for tails and 3 for
+// the wire). With --validate, a signal is first checked to run one cell a
+// step along a straight wire.
+pub fn wireworld(options: &Options)
+{
+    if options.validate
+    {
+        match machine::check_signal(24)
+        {
+            Ok(()) => println!("signals run one cell a step along wires"),
+            Err(step) =>
+            {
+                eprintln!("the signal is not where it should be after {} steps", step);
+                std::process::exit(1);
+            }
+        }
+    }
+    let rule = match &options.machine
+    {
+        Some(path) => StateMachineRule::load(path).unwrap_or_else(|error| {
+            eprintln!("{}: {}", path, error);
+            std::process::exit(1);
+        }),
+        None => machine::wireworld()
+    };
+
+    // The loop is row 2 from column 6 to 20 and row 6 from 3 to 23, joined
+    // at both ends by staircases of cells, and the exit carries on from the
+    // right one along row 4.
+    let (w, h) = (36, 9);
+    let mut grid = Grid::new((w, h), 0u8);
+    let stairs = |i: usize, right: bool| (1..4).flat_map(move |r| {
+        let (near, far) = if right { (i + r - 1, i + r) } else { (i + 1 - r, i - r) };
+        vec![(near, 2 + r), (far, 2 + r)]
+    });
+    let wire: Vec<(usize, usize)> = (6..=20).map(|i| (i, 2))
+        .chain((3..=23).map(|i| (i, 6)))
+        .chain(stairs(20, true))
+        .chain(stairs(6, false))
+        .chain((23..w - 1).map(|i| (i, 4)))
+        .collect();
+    for coord in wire
+    {
+        if let Some(cell) = grid.get_mut(coord)
+        {
+            *cell = 3;
+        }
+    }
+    for &(coord, state) in &[((8, 2), 2), ((9, 2), 1), ((15, 6), 2), ((14, 6), 1)]
+    {
+        *grid.get_mut(coord).unwrap() = state;
+    }
+
+    let mut automata = Automata::new(grid);
+    let glyph = |cell: &u8| format!(" {} ", rule.glyph(*cell));
+    run::run_loop(&mut automata, |ngh| rule.apply(ngh), 40, |grid| print!("{}", grid.render_labels(glyph)), &options.pacing);
+    if let Some(path) = &options.image
+    {
+        if let Err(error) = image::png(automata.current(), |cell| rule.color(*cell), &RenderOptions{supersample: 2, ..RenderOptions::default()}, path)
+        {
+            eprintln!("{}: {}", path, error);
+            std::process::exit(1);
+        }
+    }
+}
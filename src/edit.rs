@@ -0,0 +1,365 @@
+// Painting into the current generation, for frontends building scenes by
+// hand: brushes giving the cells a click covers, a bucket fill, and a
+// bounded stack of edits to undo.
+//
+// Edits go to the current generation only, like Automata::get_mut. A step
+// computes the next generation from the edited cells; there is no unmaking
+// that, so the stack forgets the edits made before the last step.
+
+use crate::{Automata, Grid};
+use crate::geometry;
+use crate::hotspot::HotspotRegion;
+
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Brush
+{
+    #[default]
+    Cell,
+    // The cells at most this many edge crossings away.
+    Ball(usize),
+    // The cells whose storage coordinates lie between the last click and
+    // this one, or the clicked cell alone for the first click.
+    Rect,
+    // The cells on the way from the last click to this one (geometry::line).
+    Line
+}
+
+impl Brush
+{
+    // The cells covered by a click at `at` in a grid of `dims`, none if it
+    // is outside of it.
+    pub fn footprint(&self, dims: (usize, usize), at: (usize, usize), last: Option<(usize, usize)>) -> Vec<(usize, usize)>
+    {
+        if at.0 >= dims.0 || at.1 >= dims.1
+        {
+            return vec![];
+        }
+        let last = last.filter(|&(i, j)| i < dims.0 && j < dims.1);
+        match (self, last)
+        {
+            (Brush::Cell, _) | (Brush::Rect, None) | (Brush::Line, None) => vec![at],
+            (Brush::Ball(radius), _) =>
+            {
+                let grid = Grid::new(dims, ());
+                HotspotRegion::new(&grid, &[at], *radius).cells().to_vec()
+            },
+            (Brush::Rect, Some(last)) =>
+            {
+                let (i0, i1) = (last.0.min(at.0), last.0.max(at.0));
+                let (j0, j1) = (last.1.min(at.1), last.1.max(at.1));
+                (j0..=j1).flat_map(|j| (i0..=i1).map(move |i| (i, j))).collect()
+            },
+            (Brush::Line, Some(last)) => geometry::line(last, at, dims)
+        }
+    }
+}
+
+// `cell`, `ball=R`, `rect` or `line`.
+impl FromStr for Brush
+{
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String>
+    {
+        match text
+        {
+            "cell" => Ok(Brush::Cell),
+            "rect" => Ok(Brush::Rect),
+            "line" => Ok(Brush::Line),
+            _ => text.strip_prefix("ball=")
+                .and_then(|radius| radius.parse().ok())
+                .map(Brush::Ball)
+                .ok_or_else(|| format!("unknown brush '{}' (cell, ball=R, rect or line)", text))
+        }
+    }
+}
+
+impl<T: Copy + Debug + PartialEq> Grid<T>
+{
+    // The edge-connected cells holding the same state as `start`, start
+    // included; empty outside of the grid.
+    pub fn fill_region(&self, start: (usize, usize)) -> Vec<(usize, usize)>
+    {
+        let target = match self.get(start)
+        {
+            Some(&state) => state,
+            None => return vec![]
+        };
+        let (w, _) = self.dims;
+        let mut seen = vec![false; self.data.len()];
+        seen[start.1*w + start.0] = true;
+        let mut queue = VecDeque::from(vec![start]);
+        let mut region = vec![];
+        while let Some(coord) = queue.pop_front()
+        {
+            region.push(coord);
+            for (i, j) in self.neighbor_coords(coord).into_iter().skip(1)
+            {
+                if !seen[j*w + i] && *self.get((i, j)).unwrap() == target
+                {
+                    seen[j*w + i] = true;
+                    queue.push_back((i, j));
+                }
+            }
+        }
+        region
+    }
+
+    // Sets the region of fill_region to `state`, returning its cells.
+    pub fn flood_fill(&mut self, start: (usize, usize), state: T) -> Vec<(usize, usize)>
+    {
+        let region = self.fill_region(start);
+        for &coord in &region
+        {
+            *self.get_mut(coord).unwrap() = state;
+        }
+        region
+    }
+}
+
+// The cells one edit changed, with the states they had before.
+type Edit<T> = Vec<((usize, usize), T)>;
+
+pub struct EditStack<T>
+{
+    edits: VecDeque<Edit<T>>,
+    capacity: usize,
+    // The step the edits were made at.
+    step: u64
+}
+
+impl<T: Clone + Display + Copy + Debug + PartialEq> EditStack<T>
+{
+    // Keeps the last `capacity` edits.
+    pub fn new(capacity: usize) -> Self
+    {
+        Self{edits: VecDeque::new(), capacity, step: 0}
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.edits.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.edits.is_empty()
+    }
+
+    fn catch_up(&mut self, automata: &Automata<T>)
+    {
+        if automata.step() != self.step
+        {
+            self.edits.clear();
+            self.step = automata.step();
+        }
+    }
+
+    // Sets the cells to `state` as one edit, those outside of the grid
+    // left out, and returns how many changed. Edits changing nothing are
+    // not kept.
+    pub fn paint(&mut self, automata: &mut Automata<T>, cells: &[(usize, usize)], state: T) -> usize
+    {
+        self.catch_up(automata);
+        let mut edit: Edit<T> = vec![];
+        for &coord in cells
+        {
+            if let Some(cell) = automata.get_mut(coord)
+            {
+                if *cell != state && !edit.iter().any(|&(done, _)| done == coord)
+                {
+                    edit.push((coord, *cell));
+                    *cell = state;
+                }
+            }
+        }
+        let changed = edit.len();
+        if changed > 0 && self.capacity > 0
+        {
+            if self.edits.len() == self.capacity
+            {
+                self.edits.pop_front();
+            }
+            self.edits.push_back(edit);
+        }
+        changed
+    }
+
+    // Paints the brush's footprint.
+    pub fn brush(&mut self, automata: &mut Automata<T>, brush: Brush, at: (usize, usize), last: Option<(usize, usize)>, state: T) -> usize
+    {
+        let cells = brush.footprint(automata.current().dims, at, last);
+        self.paint(automata, &cells, state)
+    }
+
+    // Bucket fill from `start` as one edit.
+    pub fn fill(&mut self, automata: &mut Automata<T>, start: (usize, usize), state: T) -> usize
+    {
+        let cells = automata.current().fill_region(start);
+        self.paint(automata, &cells, state)
+    }
+
+    // Puts back the cells of the last edit; false when there is none since
+    // the last step.
+    pub fn undo(&mut self, automata: &mut Automata<T>) -> bool
+    {
+        self.catch_up(automata);
+        match self.edits.pop_back()
+        {
+            Some(edit) =>
+            {
+                for (coord, state) in edit
+                {
+                    *automata.get_mut(coord).unwrap() = state;
+                }
+                true
+            },
+            None => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, Light};
+    use crate::rng::SplitMix64;
+
+    const DIMS: (usize, usize) = (9, 5);
+
+    fn sorted(mut cells: Vec<(usize, usize)>) -> Vec<(usize, usize)>
+    {
+        cells.sort();
+        cells
+    }
+
+    #[test]
+    fn footprints_near_the_edges()
+    {
+        assert_eq!(Brush::Cell.footprint(DIMS, (8, 4), None), [(8, 4)]);
+        assert_eq!(Brush::Ball(3).footprint(DIMS, (9, 0), None), []);
+        // (0, 0) points up: its only neighbors are (1, 0) and (0, 1).
+        assert_eq!(sorted(Brush::Ball(1).footprint(DIMS, (0, 0), None)), [(0, 0), (0, 1), (1, 0)]);
+        let ball = Brush::Ball(2).footprint(DIMS, (8, 4), None);
+        // (8, 4) has neither a right neighbor nor one across, below.
+        assert_eq!(sorted(ball.clone()), [(6, 4), (7, 3), (7, 4), (8, 4)]);
+        assert!(ball.iter().all(|&(i, j)| i < DIMS.0 && j < DIMS.1));
+        assert_eq!(Brush::Ball(0).footprint(DIMS, (4, 2), None), [(4, 2)]);
+        assert_eq!(Brush::Ball(100).footprint(DIMS, (4, 2), None).len(), 45);
+
+        assert_eq!(Brush::Rect.footprint(DIMS, (7, 4), None), [(7, 4)]);
+        assert_eq!(Brush::Rect.footprint(DIMS, (7, 4), Some((8, 3))), [(7, 3), (8, 3), (7, 4), (8, 4)]);
+        // A last click outside of the grid counts as none.
+        assert_eq!(Brush::Rect.footprint(DIMS, (0, 0), Some((20, 20))), [(0, 0)]);
+
+        let line = Brush::Line.footprint(DIMS, (8, 4), Some((0, 0)));
+        assert_eq!((line[0], line[line.len() - 1]), ((0, 0), (8, 4)));
+        assert!(line.windows(2).all(|pair| Grid::new(DIMS, ()).neighbor_coords(pair[0]).contains(&pair[1])));
+        assert_eq!(Brush::Line.footprint(DIMS, (3, 0), Some((0, 0))), [(0, 0), (1, 0), (2, 0), (3, 0)]);
+    }
+
+    #[test]
+    fn brushes_by_name()
+    {
+        assert_eq!("cell".parse(), Ok(Brush::Cell));
+        assert_eq!("ball=3".parse(), Ok(Brush::Ball(3)));
+        assert_eq!("rect".parse(), Ok(Brush::Rect));
+        assert_eq!("line".parse(), Ok(Brush::Line));
+        assert_eq!("ball=-1".parse::<Brush>(), Err("unknown brush 'ball=-1' (cell, ball=R, rect or line)".to_string()));
+    }
+
+    #[test]
+    fn fills_stop_at_other_states()
+    {
+        // A wall across the grid, all of column 4.
+        let mut grid = Grid::from_fn(DIMS, |(i, _)| u8::from(i == 4));
+        let left = sorted(grid.fill_region((0, 0)));
+        assert_eq!(left, sorted((0..5).flat_map(|j| (0..4).map(move |i| (i, j))).collect()));
+        // The cells of a column only meet across, in pairs.
+        assert_eq!(sorted(grid.fill_region((4, 3))), [(4, 2), (4, 3)]);
+        assert_eq!(grid.fill_region((9, 0)), []);
+        assert_eq!(grid.flood_fill((8, 0), 2).len(), 20);
+        assert_eq!(grid.data.iter().filter(|&&cell| cell == 2).count(), 20);
+        assert_eq!(*grid.get((0, 0)).unwrap(), 0);
+    }
+
+    #[test]
+    fn undo_puts_back_the_exact_states()
+    {
+        let mut rng = SplitMix64::new(171);
+        let mut automata = Automata::new(Grid::from_fn(DIMS, |_| rng.below(4) as u8));
+        let original = automata.current().clone();
+        let mut edits = EditStack::new(100);
+        let mut states = vec![original.clone()];
+        for k in 0..30
+        {
+            let at = (rng.below(DIMS.0 as u64) as usize, rng.below(DIMS.1 as u64) as usize);
+            let state = rng.below(4) as u8;
+            let changed = match k % 3
+            {
+                0 => edits.brush(&mut automata, Brush::Ball(rng.below(3) as usize), at, None, state),
+                1 => edits.brush(&mut automata, Brush::Rect, at, Some((0, 4)), state),
+                _ => edits.fill(&mut automata, at, state)
+            };
+            if changed > 0
+            {
+                states.push(automata.current().clone());
+            }
+        }
+        assert_eq!(edits.len(), states.len() - 1);
+        while states.len() > 1
+        {
+            states.pop();
+            assert!(edits.undo(&mut automata));
+            assert_eq!(automata.current(), states.last().unwrap());
+        }
+        assert!(!edits.undo(&mut automata));
+        assert_eq!(automata.current(), &original);
+        // Painting a cell its own state, or a cell outside, is no edit.
+        let same = *automata.current().get((2, 2)).unwrap();
+        assert_eq!(edits.paint(&mut automata, &[(2, 2), (40, 2)], same), 0);
+        assert!(edits.is_empty());
+    }
+
+    #[test]
+    fn the_stack_is_bounded()
+    {
+        let mut automata = Automata::new(Grid::new(DIMS, 0u8));
+        let mut edits = EditStack::new(2);
+        for state in 1..=4
+        {
+            edits.paint(&mut automata, &[(0, 0), (1, 0), (0, 0)], state);
+        }
+        assert_eq!(edits.len(), 2);
+        assert!(edits.undo(&mut automata) && edits.undo(&mut automata));
+        assert!(!edits.undo(&mut automata));
+        assert_eq!((automata.current().get((0, 0)), automata.current().get((1, 0))), (Some(&2), Some(&2)));
+        let mut none = EditStack::new(0);
+        assert_eq!(none.paint(&mut automata, &[(3, 3)], 9), 1);
+        assert!(!none.undo(&mut automata));
+    }
+
+    #[test]
+    fn steps_evolve_the_edits()
+    {
+        let mut automata = Automata::new(Grid::new(DIMS, Light::Space(0)));
+        let mut edits = EditStack::new(10);
+        edits.brush(&mut automata, Brush::Ball(1), (4, 2), None, Light::Source(6));
+        let edited = automata.current().clone();
+        automata.evolve(rules::light_falloff);
+        let mut fresh = Automata::new(edited);
+        fresh.evolve(rules::light_falloff);
+        assert_eq!(automata.current(), fresh.current());
+        // The edit is in the previous generation now, past undoing.
+        assert!(!edits.undo(&mut automata));
+        assert_eq!(automata.current(), fresh.current());
+        assert_eq!(edits.paint(&mut automata, &[(0, 0)], Light::Source(2)), 1);
+        assert!(edits.undo(&mut automata));
+        assert_eq!(automata.current(), fresh.current());
+    }
+}
@@ -0,0 +1,233 @@
+// One error type for the whole crate, for callers that handle everything
+// that can go wrong in one place: the errors of the modules convert into
+// it with `?`.
+//
+// What users give (dims, coordinates, files, patterns, command lines) gives
+// errors, never panics. The panics left are of invariants of the crate
+// itself, documented where they are: a Grid whose data is not dims.0 *
+// dims.1 cells, coordinates the crate computed inside a grid, built-in
+// rules or machines that do not build. Grid::new and Automata::new take
+// their dims as given, panicking when the number of cells overflows and
+// aborting like any allocation when they do not fit in memory: dims from
+// users go through check_dims or Grid::try_new first.
+
+use crate::DimMismatch;
+use crate::checkpoint::SnapshotError;
+use crate::pattern::{ParityMismatch, PatternParseError};
+use crate::rle::RleError;
+
+use std::fmt;
+use std::io;
+
+// The largest grids made from dims users give, 2^30 cells.
+pub const MAX_CELLS: usize = 1 << 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridError
+{
+    OutOfBounds{coord: (usize, usize), dims: (usize, usize)},
+    // More than MAX_CELLS cells, or more than memory can address.
+    TooLarge((usize, usize)),
+    // A pattern without cells, where cells are needed.
+    EmptyPattern,
+    // Cells of a pattern put on triangles of the other orientation.
+    Parity(ParityMismatch),
+    // A periodic boundary on a grid with an axis of odd size, which cannot
+    // wrap (see boundary.rs).
+    OddPeriodic((usize, usize))
+}
+
+impl fmt::Display for GridError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            GridError::OutOfBounds{coord, dims} => write!(f, "{:?} is outside of the {}x{} grid", coord, dims.0, dims.1),
+            GridError::TooLarge(dims) => write!(f, "a {}x{} grid is too large (at most {} cells)", dims.0, dims.1, MAX_CELLS),
+            GridError::EmptyPattern => write!(f, "the pattern has no cells"),
+            GridError::Parity(mismatch) =>
+                write!(f, "offset {:?} puts a pattern starting with a {} triangle on the other orientation",
+                       mismatch.offset, if mismatch.parity == 0 { "up" } else { "down" }),
+            GridError::OddPeriodic(dims) =>
+                write!(f, "a {}x{} grid cannot wrap around: the triangles on both sides of an odd axis point the same way", dims.0, dims.1)
+        }
+    }
+}
+
+impl std::error::Error for GridError {}
+
+impl From<ParityMismatch> for GridError
+{
+    fn from(mismatch: ParityMismatch) -> Self
+    {
+        GridError::Parity(mismatch)
+    }
+}
+
+// The number of cells of a grid of `dims` made for a user, which must be
+// MAX_CELLS at most.
+pub fn check_dims(dims: (usize, usize)) -> Result<usize, GridError>
+{
+    match dims.0.checked_mul(dims.1)
+    {
+        Some(cells) if cells <= MAX_CELLS => Ok(cells),
+        _ => Err(GridError::TooLarge(dims))
+    }
+}
+
+pub fn check_coord(dims: (usize, usize), coord: (usize, usize)) -> Result<(), GridError>
+{
+    if coord.0 < dims.0 && coord.1 < dims.1 { Ok(()) } else { Err(GridError::OutOfBounds{coord, dims}) }
+}
+
+#[derive(Debug)]
+pub enum Error
+{
+    Grid(GridError),
+    Io(io::Error),
+    // Text that does not parse, with where and why.
+    Parse(String),
+    // A snapshot, frame or checkpoint that does not decode.
+    Codec(SnapshotError),
+    // A buffer of another number of cells than the grid.
+    DimsMismatch{expected: usize, found: usize}
+}
+
+impl fmt::Display for Error
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            Error::Grid(error) => write!(f, "{}", error),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Parse(message) => write!(f, "{}", message),
+            Error::Codec(error) => write!(f, "{}", error),
+            Error::DimsMismatch{expected, found} => write!(f, "{} cells given for a grid of {}", found, expected)
+        }
+    }
+}
+
+impl std::error::Error for Error
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+        match self
+        {
+            Error::Grid(error) => Some(error),
+            Error::Io(error) => Some(error),
+            Error::Codec(error) => Some(error),
+            Error::Parse(_) | Error::DimsMismatch{..} => None
+        }
+    }
+}
+
+impl From<GridError> for Error
+{
+    fn from(error: GridError) -> Self
+    {
+        Error::Grid(error)
+    }
+}
+
+impl From<io::Error> for Error
+{
+    fn from(error: io::Error) -> Self
+    {
+        Error::Io(error)
+    }
+}
+
+impl From<SnapshotError> for Error
+{
+    fn from(error: SnapshotError) -> Self
+    {
+        Error::Codec(error)
+    }
+}
+
+impl From<DimMismatch> for Error
+{
+    fn from(error: DimMismatch) -> Self
+    {
+        Error::DimsMismatch{expected: error.expected, found: error.found}
+    }
+}
+
+impl From<PatternParseError> for Error
+{
+    fn from(error: PatternParseError) -> Self
+    {
+        Error::Parse(error.to_string())
+    }
+}
+
+impl From<RleError> for Error
+{
+    fn from(error: RleError) -> Self
+    {
+        Error::Parse(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{checkpoint, Grid, Light};
+    use crate::pattern::Pattern;
+
+    fn message<T, E: fmt::Display>(result: Result<T, E>) -> String
+    {
+        result.err().map(|error| error.to_string()).unwrap_or_default()
+    }
+
+    // Tiny grids of odd dims used to panic when printed.
+    #[test]
+    fn tiny_grids_print()
+    {
+        for w in 0..4
+        {
+            for h in 0..4
+            {
+                let text = Grid::new((w, h), Light::Space(1)).render();
+                assert_eq!(text.contains(&format!("{:^3}", Light::Space(1))), w*h > 0, "{}x{}", w, h);
+            }
+        }
+    }
+
+    #[test]
+    fn dims_from_users_are_checked()
+    {
+        assert!(message(Grid::try_new((usize::MAX, 2), 0u8)).contains("too large"));
+        assert_eq!(check_dims((1 << 15, 1 << 15)), Ok(MAX_CELLS));
+        assert_eq!(check_dims((1 << 15, (1 << 15) + 1)), Err(GridError::TooLarge((1 << 15, (1 << 15) + 1))));
+        assert!(message(Pattern::<Light>::from_text("pattern 99999999999 99999999999 up\n", str::parse)).contains("too large"));
+        assert!(message(Pattern::<Light>::from_rle("x = 4000000000, y = 4000000000\n!\n", str::parse)).contains("too large"));
+        // 2^20 x 2^20 cells of u8, and no runs.
+        let snapshot = [b'T', b'R', b'I', b'A', 1, 1, 0, 0x80, 0x80, 0x40, 0x80, 0x80, 0x40, 0];
+        assert!(message(checkpoint::decode::<u8, _>(&snapshot[..])).contains("too large"));
+    }
+
+    #[test]
+    fn cells_and_patterns()
+    {
+        assert_eq!(message(Grid::new((3, 1), 0u8).set((5, 0), 1)), "(5, 0) is outside of the 3x1 grid");
+        assert_eq!(check_coord((3, 1), (2, 0)), Ok(()));
+        let empty = Pattern::<Light>::from_text("pattern 0 0 up\n", str::parse).unwrap();
+        assert_eq!(message(Grid::tiled((4, 4), &empty)), "the pattern has no cells");
+    }
+
+    #[test]
+    fn sources_of_errors()
+    {
+        let error = Error::from(io::Error::new(io::ErrorKind::NotFound, "gone"));
+        assert_eq!(std::error::Error::source(&error).map(|source| source.to_string()), Some("gone".to_string()));
+        let error = Error::from(GridError::EmptyPattern);
+        assert_eq!(std::error::Error::source(&error).map(|source| source.to_string()), Some(error.to_string()));
+        let error = Error::from(DimMismatch{expected: 6, found: 4});
+        assert_eq!(error.to_string(), "4 cells given for a grid of 6");
+        assert!(std::error::Error::source(&error).is_none());
+    }
+}
@@ -0,0 +1,353 @@
+// Optional layer turning steps into a stream of events, for programs
+// driven by the automaton (a synthesizer playing a note whenever a cell
+// lights up, say) rather than by its grids. After every step, a mapper
+// looks at every cell that changed, in row-major order, and says which
+// event that change is, if any; sources that vanished and appeared in the
+// same step are then paired, in the same order, into SourceMoved events.
+// The events wait in a bounded queue until the caller drains it, the
+// oldest dropped when it is full. Without a layer, steps do not look at
+// the cells at all.
+
+use crate::{Automata, CellState};
+use crate::render::json_string;
+
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display};
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event
+{
+    CellLit{step: u64, coord: (usize, usize), level: u8},
+    CellExtinguished{step: u64, coord: (usize, usize)},
+    SourceMoved{step: u64, from: (usize, usize), to: (usize, usize)}
+}
+
+impl Event
+{
+    pub fn step(&self) -> u64
+    {
+        match *self
+        {
+            Event::CellLit{step, ..} | Event::CellExtinguished{step, ..} | Event::SourceMoved{step, ..} => step
+        }
+    }
+
+    // One line of NDJSON, without the newline.
+    pub fn to_json(self) -> String
+    {
+        let pair = |(i, j): (usize, usize)| format!("[{},{}]", i, j);
+        match self
+        {
+            Event::CellLit{step, coord, level} =>
+                format!("{{\"event\":{},\"step\":{},\"coord\":{},\"level\":{}}}", json_string("cell_lit"), step, pair(coord), level),
+            Event::CellExtinguished{step, coord} =>
+                format!("{{\"event\":{},\"step\":{},\"coord\":{}}}", json_string("cell_extinguished"), step, pair(coord)),
+            Event::SourceMoved{step, from, to} =>
+                format!("{{\"event\":{},\"step\":{},\"from\":{},\"to\":{}}}", json_string("source_moved"), step, pair(from), pair(to))
+        }
+    }
+}
+
+// The events as NDJSON, one per line.
+pub fn write_ndjson<W: Write>(events: &[Event], mut writer: W) -> io::Result<()>
+{
+    for event in events
+    {
+        writeln!(writer, "{}", event.to_json())?;
+    }
+    Ok(())
+}
+
+// The event, if any, of a cell going from `old` to `new` at `step`.
+type CellMapper<T> = Box<dyn FnMut(u64, (usize, usize), &T, &T) -> Option<Event>>;
+
+pub struct EventMapper<T>
+{
+    cells: CellMapper<T>,
+    source: Option<fn(&T) -> bool>
+}
+
+impl<T> EventMapper<T>
+{
+    pub fn new<F: FnMut(u64, (usize, usize), &T, &T) -> Option<Event> + 'static>(cells: F) -> Self
+    {
+        Self{cells: Box::new(cells), source: None}
+    }
+
+    // Tells SourceMoved events from the cells `source` says are sources.
+    pub fn with_sources(mut self, source: fn(&T) -> bool) -> Self
+    {
+        self.source = Some(source);
+        self
+    }
+}
+
+impl<T: CellState> EventMapper<T>
+{
+    // CellLit when a cell's level leaves 0 (or changes while lit, with
+    // `every_level`), CellExtinguished when it drops back to 0, and moves
+    // of pinned cells.
+    pub fn levels(every_level: bool) -> Self
+    {
+        Self::new(move |step, coord, old: &T, new: &T| match (old.level(), new.level())
+        {
+            (before, 0) if before > 0 => Some(Event::CellExtinguished{step, coord}),
+            (0, level) if level > 0 => Some(Event::CellLit{step, coord, level}),
+            (before, level) if every_level && before != level => Some(Event::CellLit{step, coord, level}),
+            _ => None
+        }).with_sources(|cell| cell.is_pinned())
+    }
+}
+
+pub struct EventLayer<T>
+{
+    mapper: EventMapper<T>,
+    same: fn(&T, &T) -> bool,
+    queue: VecDeque<Event>,
+    capacity: usize,
+    dropped: u64
+}
+
+impl<T> EventLayer<T>
+{
+    // Forgets the events waiting and dropped, for restarts.
+    pub(crate) fn clear(&mut self)
+    {
+        self.queue.clear();
+        self.dropped = 0;
+    }
+}
+
+impl<T: Clone + Display + Copy + Debug + PartialEq> Automata<T>
+{
+    // Events from the next step on, at most `capacity` of them waiting.
+    pub fn enable_events(&mut self, mapper: EventMapper<T>, capacity: usize)
+    {
+        self.events = Some(EventLayer{mapper, same: |a, b| a == b, queue: VecDeque::new(), capacity, dropped: 0});
+    }
+}
+
+impl<T: Clone + Display + Copy + Debug> Automata<T>
+{
+    pub fn disable_events(&mut self)
+    {
+        self.events = None;
+    }
+
+    // The events waiting, oldest first, leaving the queue empty.
+    pub fn drain_events(&mut self) -> Vec<Event>
+    {
+        self.events.as_mut().map_or_else(Vec::new, |layer| layer.queue.drain(..).collect())
+    }
+
+    pub fn events_waiting(&self) -> usize
+    {
+        self.events.as_ref().map_or(0, |layer| layer.queue.len())
+    }
+
+    // Drops the oldest event waiting, as a full queue does. False without
+    // one.
+    pub(crate) fn drop_oldest_event(&mut self) -> bool
+    {
+        match self.events.as_mut().and_then(|layer| layer.queue.pop_front().map(|_| layer))
+        {
+            Some(layer) =>
+            {
+                layer.dropped += 1;
+                true
+            },
+            None => false
+        }
+    }
+
+    // Events dropped so far because the queue was full or over the memory
+    // budget.
+    pub fn events_dropped(&self) -> u64
+    {
+        self.events.as_ref().map_or(0, |layer| layer.dropped)
+    }
+
+    // Called once the new generation is in place, like update_ages.
+    pub(crate) fn record_events(&mut self)
+    {
+        let layer = match self.events.as_mut()
+        {
+            Some(layer) => layer,
+            None => return
+        };
+        let before = self.previous.as_ref().unwrap_or(&self.scratch);
+        let (w, step) = (self.current.dims.0, self.step);
+        let (mut vanished, mut appeared) = (vec![], vec![]);
+        let mut events = vec![];
+        for (index, (old, new)) in before.data.iter().zip(self.current.data.iter()).enumerate()
+        {
+            if (layer.same)(old, new)
+            {
+                continue;
+            }
+            let coord = (index % w, index / w);
+            if let Some(event) = (layer.mapper.cells)(step, coord, old, new)
+            {
+                events.push(event);
+            }
+            if let Some(source) = layer.mapper.source
+            {
+                match (source(old), source(new))
+                {
+                    (true, false) => vanished.push(coord),
+                    (false, true) => appeared.push(coord),
+                    _ => ()
+                }
+            }
+        }
+        events.extend(vanished.into_iter().zip(appeared).map(|(from, to)| Event::SourceMoved{step, from, to}));
+        for event in events
+        {
+            if layer.capacity == 0
+            {
+                layer.dropped += 1;
+                continue;
+            }
+            if layer.queue.len() == layer.capacity
+            {
+                layer.queue.pop_front();
+                layer.dropped += 1;
+            }
+            layer.queue.push_back(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, Grid, Light};
+
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // A source of level 3 in a 4x2 grid, moved to the other corner after
+    // the third step, then put out for good after the fourth.
+    fn scripted() -> Automata<Light>
+    {
+        let mut automata = Automata::new(Grid::new((4, 2), Light::Space(0)));
+        *automata.get_mut((0, 0)).unwrap() = Light::Source(3);
+        automata.set_injector(|step| match step
+        {
+            3 => vec![((0, 0), Light::Space(0)), ((3, 1), Light::Source(3))],
+            4 => vec![((3, 1), Light::Space(0))],
+            _ => vec![]
+        });
+        automata
+    }
+
+    #[test]
+    fn a_scripted_run()
+    {
+        let mut automata = scripted();
+        automata.enable_events(EventMapper::levels(false), 100);
+        let mut events = vec![];
+        for _ in 0..5
+        {
+            automata.evolve(rules::light_falloff);
+            events.extend(automata.drain_events());
+            assert_eq!(automata.events_waiting(), 0);
+        }
+        use Event::*;
+        assert_eq!(events, [
+            CellLit{step: 1, coord: (1, 0), level: 2},
+            CellLit{step: 1, coord: (0, 1), level: 2},
+            CellLit{step: 2, coord: (2, 0), level: 1},
+            CellLit{step: 2, coord: (1, 1), level: 1},
+            CellExtinguished{step: 3, coord: (0, 0)},
+            CellLit{step: 3, coord: (3, 1), level: 3},
+            SourceMoved{step: 3, from: (0, 0), to: (3, 1)},
+            CellLit{step: 4, coord: (0, 0), level: 1},
+            CellLit{step: 4, coord: (2, 1), level: 2},
+            CellExtinguished{step: 4, coord: (3, 1)},
+            CellExtinguished{step: 5, coord: (0, 0)},
+            CellExtinguished{step: 5, coord: (1, 0)},
+            CellExtinguished{step: 5, coord: (0, 1)},
+            CellLit{step: 5, coord: (3, 1), level: 1}
+        ]);
+        assert_eq!(automata.events_dropped(), 0);
+
+        let mut out = vec![];
+        write_ndjson(&events[5..7], &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "{\"event\":\"cell_lit\",\"step\":3,\"coord\":[3,1],\"level\":3}\n\
+                    {\"event\":\"source_moved\",\"step\":3,\"from\":[0,0],\"to\":[3,1]}\n");
+        assert_eq!(events[4].to_json(), "{\"event\":\"cell_extinguished\",\"step\":3,\"coord\":[0,0]}");
+        assert_eq!(events.iter().map(Event::step).max(), Some(5));
+    }
+
+    #[test]
+    fn runs_give_the_same_events()
+    {
+        let run = |every_level: bool| {
+            let mut automata = scripted();
+            automata.enable_events(EventMapper::levels(every_level), 100);
+            for _ in 0..4
+            {
+                automata.evolve(rules::light_falloff);
+            }
+            automata.drain_events()
+        };
+        assert_eq!(run(false), run(false));
+        // Every change of level, the cells dimming once the source moved
+        // too.
+        let every = run(true);
+        assert_eq!(every.len(), 10 + 2);
+        assert!(every.contains(&Event::CellLit{step: 4, coord: (1, 0), level: 1}));
+    }
+
+    #[test]
+    fn the_queue_is_bounded()
+    {
+        let mut automata = scripted();
+        automata.enable_events(EventMapper::levels(false), 3);
+        automata.evolve(rules::light_falloff);
+        automata.evolve(rules::light_falloff);
+        // The oldest of the 4 events went.
+        assert_eq!(automata.events_waiting(), 3);
+        assert_eq!(automata.events_dropped(), 1);
+        assert_eq!(automata.drain_events()[0], Event::CellLit{step: 1, coord: (0, 1), level: 2});
+
+        let mut none = scripted();
+        none.enable_events(EventMapper::levels(false), 0);
+        none.evolve(rules::light_falloff);
+        assert_eq!((none.events_waiting(), none.events_dropped()), (0, 2));
+    }
+
+    #[test]
+    fn without_a_mapper_cells_are_not_looked_at()
+    {
+        let calls = Rc::new(Cell::new(0));
+        let counted = calls.clone();
+        let mut automata = scripted();
+        automata.enable_events(EventMapper::new(move |_, _, _: &Light, _: &Light| {
+            counted.set(counted.get() + 1);
+            None
+        }), 10);
+        automata.evolve(rules::light_falloff);
+        // Called on the changed cells only.
+        assert_eq!(calls.get(), 2);
+        automata.disable_events();
+        for _ in 0..4
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        assert_eq!(calls.get(), 2);
+        assert_eq!((automata.drain_events(), automata.events_waiting(), automata.events_dropped()), (vec![], 0, 0));
+
+        // The same run as without a layer ever enabled.
+        let mut plain = scripted();
+        for _ in 0..5
+        {
+            plain.evolve(rules::light_falloff);
+        }
+        assert_eq!(plain.current(), automata.current());
+    }
+}
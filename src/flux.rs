@@ -0,0 +1,286 @@
+// What leaves the grid through its edges. evolve_bounded tells, for every
+// neighbor of a cell that is past an edge of the grid, the side it is
+// past, and credits that side with what the cell would have sent it:
+// `outflow` of the cell, one grain for a toppling sandpile cell, say.
+// Neighborhoods follow the automaton's boundary (see boundary.rs): a
+// periodic grid has no edges to cross, and nothing crosses a reflective
+// one.
+
+use crate::{Automata, Grid, Slot};
+use crate::boundary::BoundaryCondition;
+use crate::coord::Coord;
+use crate::error::GridError;
+
+use std::fmt::{Debug, Display};
+use std::ops::AddAssign;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side
+{
+    Top,
+    Bottom,
+    Left,
+    Right
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BoundaryFlux
+{
+    pub top: f64,
+    pub bottom: f64,
+    pub left: f64,
+    pub right: f64
+}
+
+impl BoundaryFlux
+{
+    pub fn get(&self, side: Side) -> f64
+    {
+        match side
+        {
+            Side::Top => self.top,
+            Side::Bottom => self.bottom,
+            Side::Left => self.left,
+            Side::Right => self.right
+        }
+    }
+
+    pub fn add(&mut self, side: Side, amount: f64)
+    {
+        match side
+        {
+            Side::Top => self.top += amount,
+            Side::Bottom => self.bottom += amount,
+            Side::Left => self.left += amount,
+            Side::Right => self.right += amount
+        }
+    }
+
+    pub fn total(&self) -> f64
+    {
+        self.top + self.bottom + self.left + self.right
+    }
+}
+
+impl AddAssign for BoundaryFlux
+{
+    fn add_assign(&mut self, other: Self)
+    {
+        self.top += other.top;
+        self.bottom += other.bottom;
+        self.left += other.left;
+        self.right += other.right;
+    }
+}
+
+// The neighbor across `slot` of the cell, on the torus under Periodic, or
+// the side of the grid it is past. A periodic boundary must have been
+// checked against the dims (see BoundaryCondition::check).
+pub(crate) fn neighbor<T>(dims: (usize, usize), (i, j): (usize, usize), slot: Slot, boundary: &BoundaryCondition<T>) -> Result<(usize, usize), Side>
+{
+    let (di, dj, side) = match slot
+    {
+        Slot::Left => (-1, 0, Side::Left),
+        Slot::Right => (1, 0, Side::Right),
+        Slot::Across if (i+j) & 1 == 0 => (0, 1, Side::Bottom),
+        Slot::Across => (0, -1, Side::Top)
+    };
+    let (w, h) = dims;
+    let (ni, nj) = (i as isize + di, j as isize + dj);
+    if let BoundaryCondition::Periodic = boundary
+    {
+        debug_assert!(w.is_multiple_of(2) && h.is_multiple_of(2), "periodic {:?}", dims);
+        Ok((ni.rem_euclid(w as isize) as usize, nj.rem_euclid(h as isize) as usize))
+    }
+    else
+    {
+        Coord::new(ni, nj).to_storage(dims).ok_or(side)
+    }
+}
+
+impl<T: Copy + Debug> Grid<T>
+{
+    // The cell, then its neighbors as in neighbor_coords under the
+    // boundary (see Grid::neighborhood_with_boundary), and the sides of
+    // those past the edges. Empty for a cell outside of the grid.
+    pub fn bounded_neighborhood(&self, coord: (usize, usize), boundary: &BoundaryCondition<T>) -> Result<(Vec<T>, Vec<Side>), GridError>
+    {
+        boundary.check(self.dims)?;
+        Ok(self.neighborhood_past_edges(coord, boundary).unwrap_or_default())
+    }
+}
+
+impl<T: Clone + Display + Copy + Debug> Automata<T>
+{
+    // evolve under the automaton's boundary, tallying
+    // what `outflow` says every cell sends to each of its neighbors past
+    // the edges; see last_boundary_flux. Nothing crosses a Reflective
+    // edge, what is sent coming back.
+    pub fn evolve_bounded<F, O>(&mut self, rule: F, outflow: O)
+    where
+        F: Fn(Vec<T>) -> T,
+        O: Fn(&T) -> f64
+    {
+        let mut flux = BoundaryFlux::default();
+        let boundary = self.boundary;
+        let reflective = matches!(boundary, BoundaryCondition::Reflective);
+        let stepped = self.with_cell_rules(|automata, overrides| automata.try_next_generation_from(|grid, coord| {
+            let (ngh, past) = grid.neighborhood_past_edges(coord, &boundary).expect("the cells stepped are in the grid");
+            if !past.is_empty() && !reflective
+            {
+                let amount = outflow(&ngh[0]);
+                for side in past
+                {
+                    flux.add(side, amount);
+                }
+            }
+            Ok::<T, std::convert::Infallible>(match overrides.get(&coord)
+            {
+                Some(overridden) => overridden.apply(ngh),
+                None => rule(ngh)
+            })
+        }));
+        match stepped
+        {
+            Ok(()) => self.boundary_flux = flux,
+            Err(error) => match error.error {}
+        }
+    }
+
+    // What left the grid during the last step, all zero unless it was made
+    // by evolve_bounded.
+    pub fn last_boundary_flux(&self) -> BoundaryFlux
+    {
+        self.boundary_flux
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::rng::SplitMix64;
+    use crate::rules;
+
+    fn grains(automata: &Automata<u8>) -> f64
+    {
+        automata.current().data.iter().map(|&cell| f64::from(cell)).sum()
+    }
+
+    fn toppling(cell: &u8) -> f64
+    {
+        if *cell >= 3 { 1.0 } else { 0.0 }
+    }
+
+    fn random_pile(seed: u64, dims: (usize, usize)) -> Automata<u8>
+    {
+        let mut rng = SplitMix64::new(seed);
+        Automata::new(Grid::from_fn(dims, |_| rng.below(6) as u8))
+    }
+
+    #[test]
+    fn lost_grains_are_the_flux()
+    {
+        for seed in 0..8
+        {
+            let mut automata = random_pile(seed, (13, 7));
+            for _ in 0..40
+            {
+                let (before, expected) = (grains(&automata), rules::sandpile_boundary_loss(automata.current()));
+                automata.evolve_bounded(rules::sandpile, toppling);
+                let flux = automata.last_boundary_flux();
+                assert_eq!(before - grains(&automata), flux.total());
+                assert_eq!(-expected as f64, flux.total());
+            }
+        }
+    }
+
+    #[test]
+    fn sides_are_told_apart()
+    {
+        let mut automata = Automata::new(Grid::new((6, 4), 0u8));
+        // Missing its left neighbor, its right one and the one above.
+        for &coord in &[(0, 0), (5, 2), (3, 0)]
+        {
+            *automata.get_mut(coord).unwrap() = 3;
+        }
+        // Missing the one below and the one on the right.
+        *automata.get_mut((5, 3)).unwrap() = 4;
+        automata.evolve_bounded(rules::sandpile, toppling);
+        let flux = automata.last_boundary_flux();
+        assert_eq!(flux, BoundaryFlux{top: 1.0, bottom: 1.0, left: 1.0, right: 2.0});
+        assert_eq!((flux.get(Side::Top), flux.get(Side::Right)), (1.0, 2.0));
+        // Other steps leave the tally at zero.
+        automata.evolve(rules::sandpile);
+        assert_eq!(automata.last_boundary_flux(), BoundaryFlux::default());
+    }
+
+    #[test]
+    fn wrapped_grids_lose_nothing()
+    {
+        let mut automata = random_pile(3, (12, 6));
+        automata.set_boundary(BoundaryCondition::Periodic).unwrap();
+        let total = grains(&automata);
+        for _ in 0..40
+        {
+            automata.evolve_bounded(rules::sandpile, toppling);
+            assert_eq!(automata.last_boundary_flux(), BoundaryFlux::default());
+            assert_eq!(grains(&automata), total);
+        }
+        // An odd axis cannot wrap, and the boundary stays open.
+        let mut automata = random_pile(3, (12, 5));
+        assert_eq!(automata.set_boundary(BoundaryCondition::Periodic), Err(GridError::OddPeriodic((12, 5))));
+        assert_eq!(automata.boundary(), &BoundaryCondition::Open);
+    }
+
+    #[test]
+    fn reflective_edges_let_nothing_out()
+    {
+        let mut automata = random_pile(5, (13, 7));
+        automata.set_boundary(BoundaryCondition::Reflective).unwrap();
+        for _ in 0..10
+        {
+            automata.evolve_bounded(rules::sandpile, toppling);
+            assert_eq!(automata.last_boundary_flux(), BoundaryFlux::default());
+        }
+        // Fixed edges are crossed as open ones are.
+        let mut automata = Automata::new(Grid::new((6, 4), 0u8));
+        automata.set_boundary(BoundaryCondition::Fixed(0)).unwrap();
+        *automata.get_mut((0, 0)).unwrap() = 3;
+        automata.evolve_bounded(rules::sandpile, toppling);
+        assert_eq!(automata.last_boundary_flux(), BoundaryFlux{top: 0.0, bottom: 0.0, left: 1.0, right: 0.0});
+    }
+
+    #[test]
+    fn bounded_neighborhoods()
+    {
+        let grid = Grid::from_fn((6, 4), |(i, j)| (i + 10*j) as u8);
+        for coord in (0..4).flat_map(|j| (0..6).map(move |i| (i, j)))
+        {
+            let (ngh, missing) = grid.bounded_neighborhood(coord, &BoundaryCondition::Open).unwrap();
+            assert_eq!(ngh, grid.neighborhood(coord).into_iter().copied().collect::<Vec<_>>());
+            assert_eq!(ngh.len() + missing.len(), 4);
+            let (ngh, missing) = grid.bounded_neighborhood(coord, &BoundaryCondition::Periodic).unwrap();
+            assert_eq!((ngh.len(), missing), (4, vec![]));
+        }
+        assert_eq!(grid.bounded_neighborhood((0, 0), &BoundaryCondition::Periodic).unwrap().0, [0, 5, 1, 10]);
+        assert_eq!(grid.bounded_neighborhood((3, 0), &BoundaryCondition::Periodic).unwrap().0, [3, 2, 4, 33]);
+        assert_eq!(grid.bounded_neighborhood((0, 0), &BoundaryCondition::Fixed(99)).unwrap(), (vec![0, 99, 1, 10], vec![Side::Left]));
+        assert_eq!(grid.bounded_neighborhood((9, 0), &BoundaryCondition::Open).unwrap(), (vec![], vec![]));
+        let odd = Grid::new((5, 4), 0u8);
+        assert_eq!(odd.bounded_neighborhood((0, 0), &BoundaryCondition::Periodic), Err(GridError::OddPeriodic((5, 4))));
+    }
+
+    #[test]
+    fn the_flux_is_logged()
+    {
+        let mut automata = Automata::new(Grid::new((6, 4), 0u8));
+        *automata.get_mut((0, 0)).unwrap() = 3;
+        let mut logger = crate::stats::StatsLogger::new().with_flux();
+        automata.evolve_bounded(rules::sandpile, toppling);
+        logger.observe(&automata);
+        automata.evolve_bounded(rules::sandpile, toppling);
+        logger.observe(&automata);
+        assert_eq!(logger.rows(), &[(1, vec![0.0, 0.0, 1.0, 0.0]), (2, vec![0.0; 4])]);
+    }
+}
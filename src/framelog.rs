@@ -0,0 +1,411 @@
+// A log of the generations of a long run in a single file, appended to as
+// it goes and kept readable through crashes:
+//
+//   b"TRLG", version (1 byte), then frames, each the length of a snapshot
+//   (u32, little endian), its CRC-32 (the one of PNG) and the snapshot
+//   itself (see checkpoint.rs), step included.
+//
+// A crash can only leave the last frame half written: reading stops at the
+// first frame that is cut short or fails its checksum, and resuming cuts
+// the file back to the frames before it. What reached the disk is what was
+// synced; frames written since the last sync may be lost with the machine.
+
+use crate::Automata;
+use crate::checkpoint::{self, SnapshotError};
+use crate::codec::CellCodec;
+use crate::image::crc32;
+use crate::Grid;
+
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Display};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"TRLG";
+const VERSION: u8 = 1;
+const HEADER_LEN: u64 = 5;
+
+#[derive(Debug)]
+pub enum FrameLogError
+{
+    Io(io::Error),
+    // Not a frame log, or one of another version.
+    BadHeader,
+    // A frame whose checksum holds but whose snapshot does not decode.
+    Snapshot(SnapshotError),
+    // Nothing to resume from.
+    NoFrame
+}
+
+impl From<io::Error> for FrameLogError
+{
+    fn from(error: io::Error) -> Self
+    {
+        FrameLogError::Io(error)
+    }
+}
+
+impl fmt::Display for FrameLogError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            FrameLogError::Io(error) => write!(f, "{}", error),
+            FrameLogError::BadHeader => write!(f, "not a frame log"),
+            FrameLogError::Snapshot(error) => write!(f, "{}", error),
+            FrameLogError::NoFrame => write!(f, "no complete frame in the log")
+        }
+    }
+}
+
+impl std::error::Error for FrameLogError
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+        match self
+        {
+            FrameLogError::Io(error) => Some(error),
+            FrameLogError::Snapshot(error) => Some(error),
+            FrameLogError::BadHeader | FrameLogError::NoFrame => None
+        }
+    }
+}
+
+pub struct FrameLog
+{
+    file: File,
+    every: u64,
+    sync_every: usize,
+    unsynced: usize
+}
+
+impl FrameLog
+{
+    // A new log at `path`, replacing any file there, keeping every
+    // `every`-th step (1 at least) and syncing every `sync_every` frames
+    // (0 leaves it to sync and to the system).
+    pub fn create<P: AsRef<Path>>(path: P, every: u64, sync_every: usize) -> io::Result<Self>
+    {
+        let mut file = File::create(path)?;
+        file.write_all(MAGIC)?;
+        file.write_all(&[VERSION])?;
+        file.sync_all()?;
+        Ok(Self{file, every: every.max(1), sync_every, unsynced: 0})
+    }
+
+    // Appends the generation if its step is one to keep; returns whether it
+    // was.
+    pub fn record<T>(&mut self, automata: &Automata<T>) -> io::Result<bool>
+    where
+        T: Clone + Display + Copy + Debug + PartialEq + CellCodec
+    {
+        if !automata.step().is_multiple_of(self.every)
+        {
+            return Ok(false);
+        }
+        self.append(automata.current(), automata.step())?;
+        Ok(true)
+    }
+
+    pub fn append<T: Copy + Debug + PartialEq + CellCodec>(&mut self, grid: &Grid<T>, step: u64) -> io::Result<()>
+    {
+        let snapshot = checkpoint::encode(grid, step);
+        let len = u32::try_from(snapshot.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "snapshot over 4 GiB"))?;
+        // One write for the whole frame, so that a crash tears at most it.
+        let mut frame = Vec::with_capacity(8 + snapshot.len());
+        frame.extend_from_slice(&len.to_le_bytes());
+        frame.extend_from_slice(&crc32(&snapshot).to_le_bytes());
+        frame.extend_from_slice(&snapshot);
+        self.file.write_all(&frame)?;
+        self.unsynced += 1;
+        if self.sync_every > 0 && self.unsynced >= self.sync_every
+        {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> io::Result<()>
+    {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+// The frames of a log, in order, until the end or a torn frame.
+pub struct FrameReader<T, R>
+{
+    reader: R,
+    // Bytes of header and frames read and found whole.
+    valid_len: u64,
+    torn: bool,
+    done: bool,
+    cells: PhantomData<T>
+}
+
+impl<T> FrameReader<T, BufReader<File>>
+{
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, FrameLogError>
+    {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+// Reads as much of `buf` as there is, returning how much that was.
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize>
+{
+    let mut filled = 0;
+    while filled < buf.len()
+    {
+        match reader.read(&mut buf[filled..])
+        {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => (),
+            Err(error) => return Err(error)
+        }
+    }
+    Ok(filled)
+}
+
+impl<T, R: Read> FrameReader<T, R>
+{
+    pub fn new(mut reader: R) -> Result<Self, FrameLogError>
+    {
+        let mut header = [0; HEADER_LEN as usize];
+        if read_up_to(&mut reader, &mut header)? < header.len() || &header[..4] != MAGIC || header[4] != VERSION
+        {
+            return Err(FrameLogError::BadHeader);
+        }
+        Ok(Self{reader, valid_len: HEADER_LEN, torn: false, done: false, cells: PhantomData})
+    }
+
+    // Where the whole frames end: the length to cut a torn log back to.
+    pub fn valid_len(&self) -> u64
+    {
+        self.valid_len
+    }
+
+    // Whether reading stopped at a torn frame rather than at the end.
+    pub fn torn(&self) -> bool
+    {
+        self.torn
+    }
+
+    // The next snapshot, None at the end of the log or at a torn frame.
+    fn next_snapshot(&mut self) -> io::Result<Option<Vec<u8>>>
+    {
+        let mut prefix = [0; 8];
+        let read = read_up_to(&mut self.reader, &mut prefix)?;
+        if read < prefix.len()
+        {
+            self.torn = read > 0;
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+        let crc = u32::from_le_bytes([prefix[4], prefix[5], prefix[6], prefix[7]]);
+        // Read by chunks rather than trusting a torn length with memory.
+        let mut snapshot = Vec::new();
+        (&mut self.reader).take(len as u64).read_to_end(&mut snapshot)?;
+        if snapshot.len() < len || crc32(&snapshot) != crc
+        {
+            self.torn = true;
+            return Ok(None);
+        }
+        self.valid_len += 8 + len as u64;
+        Ok(Some(snapshot))
+    }
+}
+
+impl<T: Copy + Debug + CellCodec, R: Read> Iterator for FrameReader<T, R>
+{
+    // A generation and its step.
+    type Item = Result<(Grid<T>, u64), FrameLogError>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        if self.done
+        {
+            return None;
+        }
+        let frame = match self.next_snapshot()
+        {
+            Ok(Some(snapshot)) => checkpoint::decode(snapshot.as_slice()).map_err(FrameLogError::Snapshot),
+            Ok(None) =>
+            {
+                self.done = true;
+                return None;
+            },
+            Err(error) => Err(error.into())
+        };
+        self.done = frame.is_err();
+        Some(frame)
+    }
+}
+
+// The automaton at the last whole frame of the log at `path`, and the log,
+// cut back to that frame, to go on appending to. Like a checkpoint, the
+// frame holds the generation and step only.
+pub fn resume_from<T, P>(path: P, every: u64, sync_every: usize) -> Result<(Automata<T>, FrameLog), FrameLogError>
+where
+    T: Clone + Display + Copy + Debug + PartialEq + CellCodec,
+    P: AsRef<Path>
+{
+    let path = path.as_ref();
+    let mut frames = FrameReader::open(path)?;
+    let mut last = None;
+    for frame in &mut frames
+    {
+        last = Some(frame?);
+    }
+    let (grid, step) = last.ok_or(FrameLogError::NoFrame)?;
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    if frames.torn()
+    {
+        file.set_len(frames.valid_len())?;
+        file.sync_all()?;
+    }
+    file.seek(SeekFrom::End(0))?;
+    let mut automata = Automata::new(grid);
+    automata.step = step;
+    Ok((automata, FrameLog{file, every: every.max(1), sync_every, unsynced: 0}))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, Light};
+
+    use std::error::Error;
+
+    fn temp_path(name: &str) -> std::path::PathBuf
+    {
+        std::env::temp_dir().join(format!("triangle-automata-{}-{}.log", std::process::id(), name))
+    }
+
+    fn light_run(steps: u64) -> (Grid<Light>, Vec<Grid<Light>>)
+    {
+        let grid = Grid::from_fn((20, 12), |(i, j)| if (i*3 + j*7) % 23 == 0 { Light::Source(9) } else { Light::Space(0) });
+        let mut automata = Automata::new(grid.clone());
+        let mut run = vec![automata.current().clone()];
+        for _ in 0..steps
+        {
+            automata.evolve(rules::light_falloff);
+            run.push(automata.current().clone());
+        }
+        (grid, run)
+    }
+
+    fn cut(path: &Path, bytes: u64)
+    {
+        let len = std::fs::metadata(path).unwrap().len();
+        OpenOptions::new().write(true).open(path).unwrap().set_len(len - bytes).unwrap();
+    }
+
+    // Every 4th step of a light run, its last frame cut in two, then
+    // resumed and run on to step 60.
+    #[test]
+    fn torn_logs_resume_from_the_frame_before()
+    {
+        let path = temp_path("resume");
+        let (grid, run) = light_run(60);
+        let mut automata = Automata::new(grid);
+        let mut log = FrameLog::create(&path, 4, 2).unwrap();
+        assert!(log.record(&automata).unwrap());
+        while automata.step() < 30
+        {
+            automata.evolve(rules::light_falloff);
+            assert_eq!(log.record(&automata).unwrap(), automata.step() % 4 == 0);
+        }
+        drop(log);
+        cut(&path, 5);
+
+        let mut frames = FrameReader::<Light, _>::open(&path).unwrap();
+        let steps: Vec<u64> = (&mut frames).map(|frame| frame.unwrap().1).collect();
+        let torn = frames.torn();
+
+        let (mut automata, mut log) = resume_from::<Light, _>(&path, 4, 2).unwrap();
+        let resumed = (automata.step(), automata.current().clone());
+        while automata.step() < 60
+        {
+            automata.evolve(rules::light_falloff);
+            log.record(&automata).unwrap();
+        }
+        drop(log);
+        let mut frames = FrameReader::<Light, _>::open(&path).unwrap();
+        let read: Vec<(Grid<Light>, u64)> = (&mut frames).map(Result::unwrap).collect();
+        let torn_after = frames.torn();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(steps, [0, 4, 8, 12, 16, 20, 24]);
+        assert!(torn);
+        assert_eq!(resumed, (24, run[24].clone()));
+        assert_eq!(read.iter().map(|(_, step)| *step).collect::<Vec<_>>(), (0..=60).step_by(4).collect::<Vec<u64>>());
+        assert!(read.iter().all(|(grid, step)| grid == &run[*step as usize]));
+        assert!(!torn_after);
+    }
+
+    #[test]
+    fn checksums_and_prefixes_stop_reading()
+    {
+        let path = temp_path("checksum");
+        let (_, run) = light_run(3);
+        let mut log = FrameLog::create(&path, 1, 0).unwrap();
+        for (step, grid) in run.iter().enumerate()
+        {
+            log.append(grid, step as u64).unwrap();
+        }
+        log.sync().unwrap();
+        drop(log);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let whole = FrameReader::<Light, _>::new(bytes.as_slice()).unwrap().count();
+        // A flipped bit in the last snapshot.
+        let last = bytes.len() - 3;
+        bytes[last] ^= 1;
+        let mut flipped = FrameReader::<Light, _>::new(bytes.as_slice()).unwrap();
+        let before_flip = (&mut flipped).count();
+        // Only part of a length prefix.
+        let mut prefix = std::fs::read(&path).unwrap();
+        prefix.truncate(HEADER_LEN as usize + 3);
+        let mut short = FrameReader::<Light, _>::new(prefix.as_slice()).unwrap();
+        let none = (&mut short).count();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(whole, 4);
+        assert_eq!(before_flip, 3);
+        assert!(flipped.torn());
+        assert_eq!((none, short.torn(), short.valid_len()), (0, true, HEADER_LEN));
+    }
+
+    #[test]
+    fn errors()
+    {
+        assert!(matches!(FrameReader::<Light, _>::new(&b"TRLG"[..]), Err(FrameLogError::BadHeader)));
+        assert!(matches!(FrameReader::<Light, _>::new(&b"TRLG\x02"[..]), Err(FrameLogError::BadHeader)));
+        let path = temp_path("empty");
+        drop(FrameLog::create(&path, 1, 0).unwrap());
+        let empty = resume_from::<Light, _>(&path, 1, 0);
+        std::fs::remove_file(&path).unwrap();
+        let missing = resume_from::<Light, _>(&path, 1, 0);
+        assert!(matches!(empty, Err(FrameLogError::NoFrame)));
+        assert!(matches!(missing, Err(FrameLogError::Io(_))));
+
+        // A whole frame holding something other than a snapshot.
+        let mut bytes = b"TRLG\x01".to_vec();
+        let junk = b"not a snapshot";
+        bytes.extend_from_slice(&(junk.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&crc32(junk).to_le_bytes());
+        bytes.extend_from_slice(junk);
+        let error = FrameReader::<Light, _>::new(bytes.as_slice()).unwrap().next().unwrap().unwrap_err();
+        assert!(matches!(error, FrameLogError::Snapshot(_)));
+        // Shown with the message of the snapshot error, which is its source.
+        let source = error.source().unwrap().to_string();
+        assert_eq!(error.to_string(), source);
+        assert!(!source.contains("Snapshot"));
+    }
+}
@@ -0,0 +1,263 @@
+// A picture of every registered rule after a run from a fixed seed, and a
+// manifest of what was run:
+//
+//   {"rules": [
+//     {"cells": "light", "rule": "decay", "params": {"amount": 2}, "seed": 1, "steps": 40, "fingerprint": "8c3e..."},
+//     ...
+//   ]}
+//
+// The fingerprint is a hash of the final generation, so the manifest kept
+// in gallery/ doubles as a coarse regression check: check runs the rules
+// again and names those whose final generation changed.
+
+use crate::{Automata, CellState, Grid, Light, Rule};
+use crate::color::ColorMap;
+use crate::image::{self, RenderOptions};
+use crate::registry::RuleRegistry;
+use crate::render::json_string;
+use crate::rng::SplitMix64;
+use crate::sweep::RuleConfig;
+
+use std::fmt::{Debug, Display};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const SEED: u64 = 1;
+pub const STEPS: usize = 40;
+const DIMS: (usize, usize) = (40, 20);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GalleryEntry
+{
+    // The registry the rule is from: light or grains.
+    pub cells: String,
+    pub rule: String,
+    // All of them, the defaults included.
+    pub params: RuleConfig,
+    pub seed: u64,
+    pub steps: usize,
+    pub fingerprint: u64
+}
+
+impl GalleryEntry
+{
+    fn to_json(&self) -> String
+    {
+        let params: Vec<String> = self.params.params.iter()
+            .map(|(name, value)| format!("{}: {}", json_string(name), value))
+            .collect();
+        format!("{{\"cells\": {}, \"rule\": {}, \"params\": {{{}}}, \"seed\": {}, \"steps\": {}, \"fingerprint\": \"{:016x}\"}}",
+                json_string(&self.cells), json_string(&self.rule), params.join(", "), self.seed, self.steps, self.fingerprint)
+    }
+
+    fn file_name(&self) -> String
+    {
+        format!("{}-{}.png", self.cells, self.rule)
+    }
+}
+
+impl<T: Copy + Debug> Grid<T>
+{
+    // FNV-1a of the dims and of the Debug form of every cell, the same
+    // from one build or platform to the next.
+    pub fn fingerprint(&self) -> u64
+    {
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        let text = format!("{:?};{}", self.dims, self.data.iter().map(|cell| format!("{:?}", cell)).collect::<Vec<_>>().join(","));
+        for byte in text.bytes()
+        {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        hash
+    }
+}
+
+// A few sources in the dark.
+fn light_start(seed: u64) -> Grid<Light>
+{
+    let mut rng = SplitMix64::new(seed);
+    let mut grid = Grid::new(DIMS, Light::Space(0));
+    for _ in 0..4
+    {
+        let coord = (rng.below(DIMS.0 as u64) as usize, rng.below(DIMS.1 as u64) as usize);
+        *grid.get_mut(coord).unwrap() = Light::Source(8 + rng.below(13) as u8);
+    }
+    grid
+}
+
+// Up to 5 grains everywhere.
+fn grains_start(seed: u64) -> Grid<u8>
+{
+    let mut rng = SplitMix64::new(seed);
+    Grid::from_fn(DIMS, |_| rng.below(6) as u8)
+}
+
+fn run_registry<T, C>(cells: &str, registry: &RuleRegistry<T>, start: &Grid<T>, color: C, dir: Option<&Path>) -> io::Result<Vec<GalleryEntry>>
+where
+    T: Copy + Debug + Display + 'static,
+    C: Fn(&T) -> (u8, u8, u8)
+{
+    let mut entries = vec![];
+    for entry in registry.entries()
+    {
+        let rule = registry.instantiate(&entry.name, &RuleConfig::new(SEED)).expect("the defaults are in range");
+        let mut automata = Automata::new(start.clone());
+        for _ in 0..STEPS
+        {
+            automata.evolve(|ngh| rule.apply(ngh));
+        }
+        let mut params = RuleConfig::new(SEED);
+        for spec in &entry.params
+        {
+            params.set(&spec.name, spec.default);
+        }
+        let done = GalleryEntry{
+            cells: cells.to_string(),
+            rule: entry.name.clone(),
+            params,
+            seed: SEED,
+            steps: STEPS,
+            fingerprint: automata.current().fingerprint()
+        };
+        if let Some(dir) = dir
+        {
+            image::png(automata.current(), &color, &RenderOptions::default(), dir.join(done.file_name()))?;
+        }
+        entries.push(done);
+    }
+    Ok(entries)
+}
+
+// Every registered rule, its final generation drawn in `dir` if given.
+fn run(dir: Option<&Path>) -> io::Result<Vec<GalleryEntry>>
+{
+    let mut entries = run_registry("light", &RuleRegistry::<Light>::global(), &light_start(SEED),
+                                   |cell| ColorMap::Heat.color(f64::from(cell.level()) / 20.0), dir)?;
+    entries.extend(run_registry("grains", &RuleRegistry::<u8>::global(), &grains_start(SEED),
+                                |cell| ColorMap::Viridis.color(f64::from(*cell) / 5.0), dir)?);
+    Ok(entries)
+}
+
+pub fn manifest(entries: &[GalleryEntry]) -> String
+{
+    let lines: Vec<String> = entries.iter().map(|entry| format!("  {}", entry.to_json())).collect();
+    format!("{{\"rules\": [\n{}\n]}}\n", lines.join(",\n"))
+}
+
+// Writes the pictures and manifest.json into `dir`, which is created if
+// needed.
+pub fn write<P: AsRef<Path>>(dir: P) -> io::Result<Vec<GalleryEntry>>
+{
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let entries = run(Some(dir))?;
+    fs::write(dir.join("manifest.json"), manifest(&entries))?;
+    Ok(entries)
+}
+
+// The string value of `key` in a line of the manifest.
+fn field<'a>(line: &'a str, key: &str) -> Option<&'a str>
+{
+    let start = line.find(&format!("\"{}\": \"", key))? + key.len() + 5;
+    let len = line[start..].find('"')?;
+    Some(&line[start..start + len])
+}
+
+// Runs the rules again and compares their fingerprints with the manifest;
+// the error has a line per rule that changed, appeared or went away.
+pub fn check(manifest: &str) -> Result<usize, Vec<String>>
+{
+    let mut expected = vec![];
+    for line in manifest.lines().filter(|line| line.contains("\"fingerprint\""))
+    {
+        match (field(line, "cells"), field(line, "rule"), field(line, "fingerprint").and_then(|hex| u64::from_str_radix(hex, 16).ok()))
+        {
+            (Some(cells), Some(rule), Some(fingerprint)) => expected.push((cells.to_string(), rule.to_string(), fingerprint)),
+            _ => return Err(vec![format!("cannot read '{}'", line.trim())])
+        }
+    }
+    let entries = run(None).map_err(|error| vec![error.to_string()])?;
+    let mut problems = vec![];
+    for entry in &entries
+    {
+        match expected.iter().find(|(cells, rule, _)| *cells == entry.cells && *rule == entry.rule)
+        {
+            Some(&(_, _, fingerprint)) if fingerprint == entry.fingerprint => (),
+            Some(&(_, _, fingerprint)) =>
+                problems.push(format!("{} ({}): fingerprint {:016x}, the manifest has {:016x}", entry.rule, entry.cells, entry.fingerprint, fingerprint)),
+            None => problems.push(format!("{} ({}): not in the manifest", entry.rule, entry.cells))
+        }
+    }
+    for (cells, rule, _) in &expected
+    {
+        if !entries.iter().any(|entry| entry.cells == *cells && entry.rule == *rule)
+        {
+            problems.push(format!("{} ({}): in the manifest but no longer registered", rule, cells));
+        }
+    }
+    if problems.is_empty() { Ok(entries.len()) } else { Err(problems) }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const MANIFEST: &str = include_str!("../gallery/manifest.json");
+
+    #[test]
+    fn the_committed_manifest_is_current()
+    {
+        match check(MANIFEST)
+        {
+            Ok(rules) => assert_eq!(rules, MANIFEST.matches("\"fingerprint\"").count()),
+            Err(problems) => panic!("run --gallery gallery if these changed on purpose:\n{}", problems.join("\n"))
+        }
+    }
+
+    #[test]
+    fn changes_name_their_rule()
+    {
+        let entries = run(None).unwrap();
+        let falloff = entries.iter().find(|entry| entry.rule == "falloff").unwrap();
+        let changed: Vec<String> = MANIFEST.lines()
+            .map(|line| if line.contains("\"falloff\"") { line.replace(&format!("{:016x}", falloff.fingerprint), "0123456789abcdef") } else { line.to_string() })
+            .collect();
+        let changed = changed.join("\n");
+        assert_eq!(check(&changed), Err(vec![format!("falloff (light): fingerprint {:016x}, the manifest has 0123456789abcdef", falloff.fingerprint)]));
+
+        let without: Vec<&str> = MANIFEST.lines().filter(|line| !line.contains("\"sandpile\"")).collect();
+        assert_eq!(check(&without.join("\n")), Err(vec!["sandpile (grains): not in the manifest".to_string()]));
+        let with = MANIFEST.replacen("\"rules\": [\n", "\"rules\": [\n  {\"cells\": \"light\", \"rule\": \"gone\", \"fingerprint\": \"00\"},\n", 1);
+        assert_eq!(check(&with), Err(vec!["gone (light): in the manifest but no longer registered".to_string()]));
+        assert_eq!(check("{\"fingerprint\": 3}\n"), Err(vec!["cannot read '{\"fingerprint\": 3}'".to_string()]));
+    }
+
+    #[test]
+    fn fingerprints_are_stable()
+    {
+        // FNV-1a of "(2, 1);0,1".
+        assert_eq!(Grid::from_fn((2, 1), |(i, _)| i as u8).fingerprint(), 0xd1cd_115b_fef9_bb65);
+        assert_ne!(Grid::new((2, 1), 0u8).fingerprint(), Grid::new((1, 2), 0u8).fingerprint());
+        assert_eq!(light_start(SEED), light_start(SEED));
+        assert_ne!(grains_start(SEED).fingerprint(), grains_start(SEED + 1).fingerprint());
+    }
+
+    #[test]
+    fn pictures_and_manifest_are_written()
+    {
+        let dir = std::env::temp_dir().join(format!("triangle-automata-{}-gallery", std::process::id()));
+        let written = write(&dir);
+        let text = fs::read_to_string(dir.join("manifest.json"));
+        let pictures: Vec<bool> = written.as_ref().map(|entries| entries.iter().map(|entry| dir.join(entry.file_name()).is_file()).collect()).unwrap_or_default();
+        fs::remove_dir_all(&dir).unwrap();
+        let entries = written.unwrap();
+        assert!(pictures.iter().all(|&found| found) && pictures.len() == entries.len());
+        assert_eq!(text.unwrap(), manifest(&entries));
+        assert_eq!(manifest(&entries), MANIFEST);
+        let decay = entries.iter().find(|entry| entry.rule == "decay").unwrap();
+        assert!(decay.to_json().starts_with("{\"cells\": \"light\", \"rule\": \"decay\", \"params\": {\"amount\": 2}, \"seed\": 1, \"steps\": 40, "));
+    }
+}
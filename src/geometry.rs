@@ -0,0 +1,303 @@
+// From cells to the plane and back, in the render space of the SVG
+// outputs: x to the right and y downwards, edges `cell_size` long, the
+// first row's top at y = 0 and the first cell's left corner at x = 0.
+//
+// A column half an edge wide in a row holds parts of two triangles split
+// by a diagonal, rising where the cell starting at that column points up
+// and falling otherwise.
+
+use crate::Grid;
+use crate::coord::Coord;
+use crate::render;
+
+use std::fmt::Debug;
+
+fn row_height(cell_size: f64) -> f64
+{
+    cell_size * 3f64.sqrt() / 2.0
+}
+
+fn to_point((x, y): (usize, usize), cell_size: f64) -> (f64, f64)
+{
+    (x as f64 * cell_size / 2.0, y as f64 * row_height(cell_size))
+}
+
+impl<T: Copy + Debug> Grid<T>
+{
+    // Corners of the cell in render space, in the order of render::corners.
+    pub fn vertices(&self, (i, j): (usize, usize), cell_size: f64) -> Option<[(f64, f64); 3]>
+    {
+        if i >= self.dims.0 || j >= self.dims.1
+        {
+            return None;
+        }
+        let [a, b, c] = render::corners((i, j));
+        Some([to_point(a, cell_size), to_point(b, cell_size), to_point(c, cell_size)])
+    }
+
+    pub fn cell_at_point(&self, x: f64, y: f64, cell_size: f64) -> Option<(usize, usize)>
+    {
+        cell_at_point(x, y, cell_size, self.dims)
+    }
+}
+
+// The cell of a grid of the given dims holding the point, None outside of
+// the grid. Points on an edge go to the cell below a horizontal edge and,
+// on a slanted one, to the upward cell.
+pub fn cell_at_point(x: f64, y: f64, cell_size: f64, dims: (usize, usize)) -> Option<(usize, usize)>
+{
+    if !(x >= 0.0 && y >= 0.0 && cell_size > 0.0)
+    {
+        return None;
+    }
+    // In half edges and rows, as the lattice points of render::corners.
+    let (u, v) = (x / (cell_size / 2.0), y / row_height(cell_size));
+    let (k, j) = (u.floor(), v.floor());
+    if k >= (dims.0 + 1) as f64 || j >= dims.1 as f64
+    {
+        return None;
+    }
+    let (k, j) = (k as usize, j as usize);
+    // Where the point is in its column, from its top left corner.
+    let (du, dv) = (u - k as f64, v - j as f64);
+    let left = if (k + j).is_multiple_of(2)
+    {
+        // The rising diagonal is the left edge of the upward cell k.
+        dv + du < 1.0
+    }
+    else
+    {
+        // The falling one is the right edge of the upward cell k - 1.
+        dv >= du
+    };
+    let i = if left { k.checked_sub(1)? } else { k };
+    if i < dims.0
+    {
+        Some((i, j))
+    }
+    else
+    {
+        None
+    }
+}
+
+// Samples per edge length along the segments of line, before the gaps
+// between them are bisected.
+const LINE_SAMPLES: f64 = 4.0;
+
+// How far bisection goes, in fractions of the segment.
+const LINE_PRECISION: f64 = 1e-9;
+
+fn adjacent(dims: (usize, usize), a: (usize, usize), b: (usize, usize)) -> bool
+{
+    a == b || crate::neighbor_coords(dims, a).contains(&b)
+}
+
+// The cells the segment between the centroids of `from` and `to` goes
+// through, from `from` to `to`, each once and each one sharing an edge
+// with the next. Where the segment goes right through a corner, the cell
+// closest to it on one side fills the gap.
+pub fn line(from: (usize, usize), to: (usize, usize), dims: (usize, usize)) -> Vec<(usize, usize)>
+{
+    if from.0 >= dims.0 || from.1 >= dims.1 || to.0 >= dims.0 || to.1 >= dims.1
+    {
+        return vec![];
+    }
+    let (a, b) = (Coord::from(from).centroid(), Coord::from(to).centroid());
+    let point = |t: f64| (a.0 + t*(b.0 - a.0), a.1 + t*(b.1 - a.1));
+    let at = |t: f64| {
+        let (x, y) = point(t);
+        cell_at_point(x, y, 1.0, dims)
+    };
+    let length = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+    let samples = ((length * LINE_SAMPLES).ceil() as usize).max(1);
+
+    let mut cells = vec![from];
+    let mut last = (0.0, from);
+    for k in 1..=samples
+    {
+        let t = k as f64 / samples as f64;
+        let cell = if k == samples { Some(to) } else { at(t) };
+        if let Some(cell) = cell
+        {
+            bisect(&at, dims, last, (t, cell), &mut cells);
+            last = (t, cell);
+        }
+    }
+
+    // Right through a corner, to the opposite cell or one next to it: the
+    // cells around the corner on the side closest to the segment.
+    let distance = |c: (usize, usize)| {
+        let (x, y) = Coord::from(c).centroid();
+        ((b.0 - a.0)*(a.1 - y) - (a.0 - x)*(b.1 - a.1)).abs()
+    };
+    let neighbors = |c: (usize, usize)| crate::neighbor_coords(dims, c).into_iter().skip(1);
+    let mut connected: Vec<(usize, usize)> = vec![];
+    for &cell in &cells
+    {
+        if let Some(&previous) = connected.last()
+        {
+            if !adjacent(dims, previous, cell)
+            {
+                let bridge = neighbors(previous)
+                    .filter(|&c| adjacent(dims, c, cell))
+                    .map(|c| (distance(c), vec![c]))
+                    .chain(neighbors(previous).flat_map(|c| {
+                        neighbors(cell).filter(move |&d| adjacent(dims, c, d)).map(move |d| (distance(c) + distance(d), vec![c, d]))
+                    }))
+                    .min_by(|x, y| (x.1.len(), x.0).partial_cmp(&(y.1.len(), y.0)).unwrap());
+                if let Some((_, bridge)) = bridge
+                {
+                    connected.extend(bridge);
+                }
+            }
+        }
+        if !connected.contains(&cell)
+        {
+            connected.push(cell);
+        }
+    }
+    connected
+}
+
+// Appends the cells between two samples, and the second one.
+fn bisect<F>(at: &F, dims: (usize, usize), (t0, c0): (f64, (usize, usize)), (t1, c1): (f64, (usize, usize)), cells: &mut Vec<(usize, usize)>)
+where
+    F: Fn(f64) -> Option<(usize, usize)>
+{
+    if !adjacent(dims, c0, c1) && t1 - t0 > LINE_PRECISION
+    {
+        let t = (t0 + t1) / 2.0;
+        if let Some(c) = at(t)
+        {
+            bisect(at, dims, (t0, c0), (t, c), cells);
+            bisect(at, dims, (t, c), (t1, c1), cells);
+            return;
+        }
+    }
+    if cells.last() != Some(&c1)
+    {
+        cells.push(c1);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::rng::SplitMix64;
+
+    const DIMS: [(usize, usize); 4] = [(9, 6), (1, 1), (2, 5), (7, 1)];
+
+    fn cells((w, h): (usize, usize)) -> impl Iterator<Item = (usize, usize)>
+    {
+        (0..h).flat_map(move |j| (0..w).map(move |i| (i, j)))
+    }
+
+    fn mix([a, b, c]: [(f64, f64); 3], (s, t, u): (f64, f64, f64)) -> (f64, f64)
+    {
+        (s*a.0 + t*b.0 + u*c.0, s*a.1 + t*b.1 + u*c.1)
+    }
+
+    #[test]
+    fn centroids_map_back()
+    {
+        for &dims in &DIMS
+        {
+            let grid = Grid::new(dims, ());
+            for cell_size in [1.0, 12.5, 0.01]
+            {
+                for coord in cells(dims)
+                {
+                    let corners = grid.vertices(coord, cell_size).unwrap();
+                    let (x, y) = mix(corners, (1.0/3.0, 1.0/3.0, 1.0/3.0));
+                    assert_eq!(grid.cell_at_point(x, y, cell_size), Some(coord));
+                    let (cx, cy) = Coord::from(coord).centroid();
+                    assert!((cx*cell_size - x).abs() < 1e-9 && (cy*cell_size - y).abs() < 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn points_inside_a_triangle_map_to_it()
+    {
+        let mut rng = SplitMix64::new(166);
+        for &dims in &DIMS
+        {
+            let grid = Grid::new(dims, ());
+            for coord in cells(dims)
+            {
+                let corners = grid.vertices(coord, 3.0).unwrap();
+                for _ in 0..200
+                {
+                    let (s, t) = (rng.next_f64(), rng.next_f64());
+                    let (s, t) = if s + t > 1.0 { (1.0 - s, 1.0 - t) } else { (s, t) };
+                    // Kept off the edges, where the tie breaking rules.
+                    let margin = 1e-6;
+                    let weights = (margin + s*(1.0 - 3.0*margin), margin + t*(1.0 - 3.0*margin), 0.0);
+                    let weights = (weights.0, weights.1, 1.0 - weights.0 - weights.1);
+                    let (x, y) = mix(corners, weights);
+                    assert_eq!(cell_at_point(x, y, 3.0, dims), Some(coord), "{:?} at {:?}", coord, (x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn points_near_edges_map_to_either_side()
+    {
+        let mut rng = SplitMix64::new(7);
+        for &dims in &DIMS
+        {
+            let grid = Grid::new(dims, ());
+            for coord in cells(dims)
+            {
+                let corners = grid.vertices(coord, 1.0).unwrap();
+                for (a, b) in [(0, 1), (1, 2), (2, 0)]
+                {
+                    let (a, b) = (corners[a], corners[b]);
+                    for _ in 0..100
+                    {
+                        let t = 0.01 + 0.98*rng.next_f64();
+                        let jitter = |rng: &mut SplitMix64| (rng.next_f64() - 0.5) * 1e-7;
+                        let (x, y) = (a.0 + t*(b.0 - a.0) + jitter(&mut rng), a.1 + t*(b.1 - a.1) + jitter(&mut rng));
+                        if let Some(found) = cell_at_point(x, y, 1.0, dims)
+                        {
+                            assert!(adjacent(dims, coord, found), "{:?} beside {:?} at {:?}", found, coord, (x, y));
+                        }
+                    }
+                    // Right on the edge, the point is in one of the two cells.
+                    let (x, y) = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+                    if let Some(found) = cell_at_point(x, y, 1.0, dims)
+                    {
+                        assert!(adjacent(dims, coord, found));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn edges_and_the_outside()
+    {
+        let dims = (4, 2);
+        let h = 3f64.sqrt() / 2.0;
+        // Below a horizontal edge: (0, 0) points up, (0, 1) down.
+        assert_eq!(cell_at_point(0.5, h, 1.0, dims), Some((0, 1)));
+        assert_eq!(cell_at_point(1.0, 0.0, 1.0, dims), Some((1, 0)));
+        // Halfway up the edge between the upward (0, 0) and (1, 0).
+        assert_eq!(cell_at_point(0.75, h / 2.0, 1.0, dims), Some((0, 0)));
+        // And between (1, 0) and the upward (2, 0).
+        assert_eq!(cell_at_point(1.25, h / 2.0, 1.0, dims), Some((2, 0)));
+
+        for (x, y) in [(-0.1, 0.5), (0.5, -0.1), (0.1, 0.5), (2.4, 0.5), (1.0, 2.0*h), (f64::NAN, 0.5), (0.5, f64::INFINITY)]
+        {
+            assert_eq!(cell_at_point(x, y, 1.0, dims), None, "{:?}", (x, y));
+        }
+        assert_eq!(cell_at_point(1.0, 0.5, 0.0, dims), None);
+        assert_eq!(cell_at_point(1.0, 0.5, 1.0, (0, 0)), None);
+        assert_eq!(Grid::new(dims, 0u8).vertices((4, 0), 1.0), None);
+        assert_eq!(Grid::new(dims, 0u8).vertices((0, 0), 2.0), Some([(1.0, 0.0), (0.0, 2.0*h), (2.0, 2.0*h)]));
+    }
+}
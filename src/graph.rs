@@ -0,0 +1,170 @@
+// The adjacency of a grid as a plain undirected graph, for graph analysis
+// (centrality of cells, articulation points of the open region...): a node
+// per passable cell, in storage order, and an edge per edge the cell
+// shares with a passable neighbor, seams included when the boundary is
+// Periodic; past the edges of the others there are no cells to join. Edges are pairs of node indices, smaller first, which is what
+// graph libraries build graphs from (petgraph's Graph::from_edges, say).
+
+use crate::{Grid, Slot};
+use crate::boundary::BoundaryCondition;
+use crate::error::GridError;
+use crate::flux;
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Debug;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CellGraph
+{
+    pub nodes: Vec<(usize, usize)>,
+    pub index: HashMap<(usize, usize), usize>,
+    // Sorted, without duplicates: on grids 2 cells wide the left and right
+    // neighbors of a wrapped cell are the same.
+    pub edges: Vec<(usize, usize)>
+}
+
+impl CellGraph
+{
+    pub fn node_count(&self) -> usize
+    {
+        self.nodes.len()
+    }
+
+    pub fn edge_count(&self) -> usize
+    {
+        self.edges.len()
+    }
+
+    // The neighbors of every node.
+    pub fn adjacency(&self) -> Vec<Vec<usize>>
+    {
+        let mut adjacency = vec![vec![]; self.nodes.len()];
+        for &(a, b) in &self.edges
+        {
+            adjacency[a].push(b);
+            adjacency[b].push(a);
+        }
+        adjacency
+    }
+}
+
+// An error for a periodic boundary the grid cannot have.
+pub fn cell_graph<T, P>(grid: &Grid<T>, passable: P, boundary: &BoundaryCondition<T>) -> Result<CellGraph, GridError>
+where
+    T: Copy + Debug,
+    P: Fn(&T) -> bool
+{
+    boundary.check(grid.dims)?;
+    let (w, h) = grid.dims;
+    let nodes: Vec<(usize, usize)> = (0..h)
+        .flat_map(|j| (0..w).map(move |i| (i, j)))
+        .filter(|&coord| passable(grid.get(coord).unwrap()))
+        .collect();
+    let index: HashMap<(usize, usize), usize> = nodes.iter().enumerate().map(|(n, &coord)| (coord, n)).collect();
+    let mut edges = BTreeSet::new();
+    for (a, &coord) in nodes.iter().enumerate()
+    {
+        for &slot in &[Slot::Left, Slot::Right, Slot::Across]
+        {
+            if let Some(&b) = flux::neighbor(grid.dims, coord, slot, boundary).ok().and_then(|other| index.get(&other))
+            {
+                if a != b
+                {
+                    edges.insert((a.min(b), a.max(b)));
+                }
+            }
+        }
+    }
+    Ok(CellGraph{nodes, index, edges: edges.into_iter().collect()})
+}
+
+// Back onto the grid: the score of every node (centrality, component...)
+// on its cell, None on the cells that are not nodes. Drawn with a color map
+// for the scores and another color for None.
+pub fn score_grid(graph: &CellGraph, dims: (usize, usize), scores: &[f64]) -> Grid<Option<f64>>
+{
+    let mut grid = Grid::new(dims, None);
+    for (&coord, &score) in graph.nodes.iter().zip(scores)
+    {
+        if let Some(cell) = grid.get_mut(coord)
+        {
+            *cell = Some(score);
+        }
+    }
+    grid
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // A 4x2 grid with a wall at (1, 0), false.
+    fn walled() -> Grid<bool>
+    {
+        Grid::from_fn((4, 2), |coord| coord != (1, 0))
+    }
+
+    fn coord_edges(graph: &CellGraph) -> Vec<((usize, usize), (usize, usize))>
+    {
+        graph.edges.iter().map(|&(a, b)| (graph.nodes[a], graph.nodes[b])).collect()
+    }
+
+    #[test]
+    fn walls_are_left_out()
+    {
+        let graph = cell_graph(&walled(), |&open| open, &BoundaryCondition::Open).unwrap();
+        assert_eq!(graph.node_count(), 7);
+        assert_eq!(graph.nodes[..3], [(0, 0), (2, 0), (3, 0)]);
+        assert_eq!(graph.index[&(2, 0)], 1);
+        assert!(!graph.index.contains_key(&(1, 0)));
+        assert_eq!(coord_edges(&graph), vec![
+            ((0, 0), (0, 1)), ((2, 0), (3, 0)), ((2, 0), (2, 1)),
+            ((0, 1), (1, 1)), ((1, 1), (2, 1)), ((2, 1), (3, 1))
+        ]);
+        let degrees: Vec<usize> = graph.adjacency().iter().map(Vec::len).collect();
+        assert_eq!(degrees, vec![1, 2, 1, 2, 2, 3, 1]);
+
+        // Open everywhere, h(w - 1) edges along the rows and w/2 between
+        // two rows.
+        let open = cell_graph(&Grid::new((6, 4), ()), |_| true, &BoundaryCondition::Open).unwrap();
+        assert_eq!((open.node_count(), open.edge_count()), (24, 4*5 + 3*3));
+    }
+
+    #[test]
+    fn wrapping_adds_the_seams()
+    {
+        let clamped = cell_graph(&walled(), |&open| open, &BoundaryCondition::Open).unwrap();
+        let wrapped = cell_graph(&walled(), |&open| open, &BoundaryCondition::Periodic).unwrap();
+        let seams: Vec<_> = coord_edges(&wrapped).into_iter().filter(|edge| !coord_edges(&clamped).contains(edge)).collect();
+        // Across the left and right seam on both rows, and the bottom of
+        // (3, 1) onto the top of (3, 0); that of (1, 1) is the wall.
+        assert_eq!(seams, vec![((0, 0), (3, 0)), ((3, 0), (3, 1)), ((0, 1), (3, 1))]);
+        assert_eq!(wrapped.edge_count(), clamped.edge_count() + 3);
+
+        // Every cell of an even grid then has its three neighbors.
+        let open = cell_graph(&Grid::new((6, 4), ()), |_| true, &BoundaryCondition::Periodic).unwrap();
+        assert_eq!(open.edge_count(), 24*3/2);
+        assert!(open.adjacency().iter().all(|neighbors| neighbors.len() == 3));
+        // An odd axis cannot wrap.
+        assert_eq!(cell_graph(&Grid::new((5, 4), ()), |_| true, &BoundaryCondition::Periodic), Err(GridError::OddPeriodic((5, 4))));
+        assert_eq!(cell_graph(&Grid::new((6, 3), ()), |_| true, &BoundaryCondition::Periodic), Err(GridError::OddPeriodic((6, 3))));
+        // Nothing to join past fixed or reflective edges.
+        assert_eq!(cell_graph(&walled(), |&open| open, &BoundaryCondition::Fixed(true)), Ok(clamped.clone()));
+        assert_eq!(cell_graph(&walled(), |&open| open, &BoundaryCondition::Reflective), Ok(clamped.clone()));
+        // Two cells wide, left and right are the same edge.
+        let narrow = cell_graph(&Grid::new((2, 2), ()), |_| true, &BoundaryCondition::Periodic).unwrap();
+        assert_eq!(narrow.edges, vec![(0, 1), (0, 2), (1, 3), (2, 3)]);
+    }
+
+    #[test]
+    fn scores_go_back_onto_the_cells()
+    {
+        let graph = cell_graph(&walled(), |&open| open, &BoundaryCondition::Open).unwrap();
+        let scores: Vec<f64> = (0..graph.node_count()).map(|n| n as f64 * 0.5).collect();
+        let grid = score_grid(&graph, (4, 2), &scores);
+        assert_eq!(grid.get((1, 0)), Some(&None));
+        assert_eq!(grid.get((2, 0)), Some(&Some(0.5)));
+        assert_eq!(grid.get((3, 1)), Some(&Some(3.0)));
+    }
+}
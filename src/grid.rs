@@ -0,0 +1,627 @@
+// Grids of triangles: the storage, coordinates, neighborhoods and the
+// ASCII-art drawing.
+
+use crate::boundary::BoundaryCondition;
+use crate::convergence;
+use crate::coord::Coord;
+use crate::error::{self, GridError};
+
+use std::fmt::{Debug, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid<T>
+{
+    pub(crate) data: Vec<T>,
+    pub(crate) dims: (usize, usize)
+}
+
+// The three edges of a triangle, in the order of Grid::neighbor_coords.
+// Across is the horizontal edge: below up triangles, above down ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Slot
+{
+    Left,
+    Right,
+    Across
+}
+
+impl Slot
+{
+    pub const ALL: [Slot; 3] = [Slot::Left, Slot::Right, Slot::Across];
+}
+
+// A buffer whose length is not the number of cells of the grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DimMismatch
+{
+    pub expected: usize,
+    pub found: usize
+}
+
+// The cell itself, then its neighbors in a grid of `dims`: left, right and
+// across the horizontal edge, those outside of the grid left out.
+pub fn neighbor_coords(dims: (usize, usize), (i, j): (usize, usize)) -> Vec<(usize, usize)>
+{
+    // 1/c\2        /3\
+    //  \3/   or   1\c/2
+    let across = if (i+j) & 1 == 0 { 1 } else { -1 };
+    let center = Coord::from((i, j));
+    std::iter::once((i, j))
+        .chain([(-1, 0), (1, 0), (0, across)].iter()
+               .filter_map(|&(di, dj)| center.offset(di, dj, dims)?.to_storage(dims)))
+        .collect()
+}
+
+// Cells laid out as in Grid, wherever they are stored. The neighborhood
+// only depends on the dims.
+pub trait GridAccess<T: Copy>
+{
+    fn dims(&self) -> (usize, usize);
+    fn get(&self, coord: (usize, usize)) -> Option<&T>;
+    fn get_mut(&mut self, coord: (usize, usize)) -> Option<&mut T>;
+
+    fn neighborhood(&self, coord: (usize, usize)) -> Vec<&T>
+    {
+        neighbor_coords(self.dims(), coord).into_iter()
+            .filter_map(|co| self.get(co))
+            .collect()
+    }
+}
+
+impl<T: Copy + Debug> GridAccess<T> for Grid<T>
+{
+    fn dims(&self) -> (usize, usize)
+    {
+        self.dims
+    }
+
+    fn get(&self, coord: (usize, usize)) -> Option<&T>
+    {
+        Grid::get(self, coord)
+    }
+
+    fn get_mut(&mut self, coord: (usize, usize)) -> Option<&mut T>
+    {
+        Grid::get_mut(self, coord)
+    }
+}
+
+impl<T: Copy + Debug> Grid<T>
+{
+    // Panics if dims.0 * dims.1 overflows, and aborts if memory runs out:
+    // try_new is for dims given by users.
+    pub fn new(dims: (usize, usize), default: T) -> Self
+    {
+        Self{data: vec![default; dims.0*dims.1], dims}
+    }
+
+    // A grid of at most error::MAX_CELLS cells, or an error.
+    pub fn try_new(dims: (usize, usize), default: T) -> Result<Self, GridError>
+    {
+        let cells = error::check_dims(dims)?;
+        let mut data = Vec::new();
+        data.try_reserve_exact(cells).map_err(|_| GridError::TooLarge(dims))?;
+        data.resize(cells, default);
+        Ok(Self{data, dims})
+    }
+
+    // Writes a cell, or says why it is not in the grid; get_mut gives None
+    // instead.
+    pub fn set(&mut self, coord: (usize, usize), state: T) -> Result<(), GridError>
+    {
+        let dims = self.dims;
+        let cell = self.get_mut(coord).ok_or(GridError::OutOfBounds{coord, dims})?;
+        *cell = state;
+        Ok(())
+    }
+
+    pub fn from_fn<F>(dims: (usize, usize), mut f: F) -> Self
+    where
+        F: FnMut((usize, usize)) -> T
+    {
+        let mut data = Vec::with_capacity(dims.0*dims.1);
+        for j in 0..dims.1
+        {
+            for i in 0..dims.0
+            {
+                data.push(f((i, j)));
+            }
+        }
+        Self{data, dims}
+    }
+
+    // The cells in storage order: row by row from the top, each row from
+    // left to right, so that (i, j) is at index i + j*width.
+    pub fn as_slice(&self) -> &[T]
+    {
+        &self.data
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T]
+    {
+        &mut self.data
+    }
+
+    pub fn dims(&self) -> (usize, usize)
+    {
+        self.dims
+    }
+
+    // Rows from the top, as slices of the storage.
+    pub fn rows(&self) -> impl Iterator<Item = &[T]>
+    {
+        self.data.chunks(self.dims.0.max(1))
+    }
+
+    // The cells of row j from left to right, none when j is out of the grid.
+    pub fn row_coords(&self, j: usize) -> impl Iterator<Item = ((usize, usize), &T)>
+    {
+        self.rows().nth(j).into_iter()
+            .flatten()
+            .enumerate()
+            .map(move |(i, cell)| ((i, j), cell))
+    }
+
+    // The cells of column i from the top, none when i is out of the grid.
+    // Columns are strided in the storage, so they are not slices.
+    pub fn column(&self, i: usize) -> impl Iterator<Item = &T>
+    {
+        let (w, h) = self.dims;
+        self.data.iter()
+            .skip(i)
+            .step_by(w.max(1))
+            .take(if i < w { h } else { 0 })
+    }
+
+    // Columns from the left.
+    pub fn columns(&self) -> impl Iterator<Item = impl Iterator<Item = &T>>
+    {
+        (0..self.dims.0).map(move |i| self.column(i))
+    }
+
+    // Overwrites every cell from a buffer in storage order.
+    pub fn copy_from_slice(&mut self, src: &[T]) -> Result<(), DimMismatch>
+    {
+        if src.len() != self.data.len()
+        {
+            return Err(DimMismatch{expected: self.data.len(), found: src.len()});
+        }
+        self.data.copy_from_slice(src);
+        Ok(())
+    }
+
+    // Row j, panicking past the last row like slice indexing.
+    pub fn row(&self, j: usize) -> &[T]
+    {
+        &self.data[j*self.dims.0..(j+1)*self.dims.0]
+    }
+
+    pub fn row_mut(&mut self, j: usize) -> &mut [T]
+    {
+        &mut self.data[j*self.dims.0..(j+1)*self.dims.0]
+    }
+
+    // Accepts storage pairs as well as TriCoord.
+    pub fn get<C: Into<Coord>>(&self, coord: C) -> Option<&T>
+    {
+        let (i, j) = coord.into().to_storage(self.dims)?;
+        self.data.get(i + j*self.dims.0)
+    }
+    pub fn get_mut<C: Into<Coord>>(&mut self, coord: C) -> Option<&mut T>
+    {
+        let (i, j) = coord.into().to_storage(self.dims)?;
+        self.data.get_mut(i + j*self.dims.0)
+    }
+
+    // For coordinates computed with signed arithmetic, such as i - 1.
+    pub fn get_signed(&self, (i, j): (isize, isize)) -> Option<&T>
+    {
+        self.get(Coord::new(i, j))
+    }
+
+    // The same with what lies past the edges given by `boundary`, as in
+    // boundary.rs: nothing under Open, `state` under Fixed(state), the
+    // coordinates taken modulo the dims under Periodic and mirrored back
+    // across the edges, as many times as it takes, under Reflective. A
+    // grid with an odd axis cannot wrap, its cells past the edges pointing
+    // the other way than those they would stand for, so Periodic gives
+    // None past its edges. Always None for a grid without cells.
+    pub fn get_signed_wrapped<'a>(&'a self, (i, j): (isize, isize), boundary: &'a BoundaryCondition<T>) -> Option<&'a T>
+    {
+        if self.data.is_empty()
+        {
+            return None;
+        }
+        if let Some(cell) = self.get_signed((i, j))
+        {
+            return Some(cell);
+        }
+        let (w, h) = (self.dims.0 as isize, self.dims.1 as isize);
+        match boundary
+        {
+            BoundaryCondition::Open => None,
+            BoundaryCondition::Fixed(state) => Some(state),
+            BoundaryCondition::Periodic =>
+            {
+                boundary.check(self.dims).ok()?;
+                self.get((i.rem_euclid(w) as usize, j.rem_euclid(h) as usize))
+            },
+            BoundaryCondition::Reflective =>
+            {
+                let mirror = |k: isize, len: isize| match k.rem_euclid(2*len)
+                {
+                    folded if folded < len => folded as usize,
+                    folded => (2*len - 1 - folded) as usize
+                };
+                self.get((mirror(i, w), mirror(j, h)))
+            }
+        }
+    }
+
+    // Mirror across the vertical axis. Triangle orientations only line up
+    // with the storage parity when the width is odd.
+    pub fn mirror_x(&self) -> Option<Self>
+    {
+        if self.dims.0.is_multiple_of(2)
+        {
+            return None;
+        }
+        let w = self.dims.0;
+        Some(Self::from_fn(self.dims, |(i, j)| *self.get((w-1-i, j)).unwrap()))
+    }
+
+    // Flip across the horizontal axis, which turns up triangles into down
+    // ones: the height has to be even.
+    pub fn flip_y(&self) -> Option<Self>
+    {
+        if !self.dims.1.is_multiple_of(2)
+        {
+            return None;
+        }
+        let h = self.dims.1;
+        Some(Self::from_fn(self.dims, |(i, j)| *self.get((i, h-1-j)).unwrap()))
+    }
+
+    // Half turn around the center, possible when width + height is odd.
+    pub fn rotate_180(&self) -> Option<Self>
+    {
+        if (self.dims.0 + self.dims.1).is_multiple_of(2)
+        {
+            return None;
+        }
+        let (w, h) = self.dims;
+        Some(Self::from_fn(self.dims, |(i, j)| *self.get((w-1-i, h-1-j)).unwrap()))
+    }
+
+    pub fn neighbor_coords(&self, coord: (usize, usize)) -> Vec<(usize, usize)>
+    {
+        neighbor_coords(self.dims, coord)
+    }
+
+    // The neighbor across one edge of the cell, if it is in the grid.
+    pub fn neighbor_slot(&self, (i, j): (usize, usize), slot: Slot) -> Option<(usize, usize)>
+    {
+        let (di, dj) = match slot
+        {
+            Slot::Left => (-1, 0),
+            Slot::Right => (1, 0),
+            Slot::Across if (i+j) & 1 == 0 => (0, 1),
+            Slot::Across => (0, -1)
+        };
+        Coord::from((i, j)).offset(di, dj, self.dims)?.to_storage(self.dims)
+    }
+
+    pub fn neighborhood(&self, (i, j): (usize, usize)) -> Vec<&T>
+    {
+        self.neighbor_coords((i, j)).into_iter()
+            .filter_map(|co| self.get(co))
+            .collect()
+    }
+
+}
+impl<T: Copy + Debug> Grid<T>
+{
+    // The ASCII-art drawing, with `label` giving the three characters
+    // written inside each triangle.
+    pub fn render_labels<F: Fn(&T) -> String>(&self, label: F) -> String
+    {
+        self.render_marked(label, |_| '·')
+    }
+
+    // The same with `marker` giving the character of every lattice vertex,
+    // as (x, y) points of render::corners.
+    pub fn render_marked<F: Fn(&T) -> String, M: Fn((usize, usize)) -> char>(&self, label: F, marker: M) -> String
+    {
+        let mut out = String::new();
+        
+        write!(out, "      {}", marker((1, 0))).unwrap();
+        for i in (1..self.dims.0).step_by(2)
+        {
+            write!(out, "-----{}", marker((i + 2, 0))).unwrap();
+        }
+        writeln!(out).unwrap();
+        for j in (0..self.dims.1).step_by(2)
+        {
+
+
+            write!(out, "     /").unwrap();
+            for i in (1..self.dims.0).step_by(2)
+            {
+                write!(out, " \\{}/", label(self.get((i, j)).unwrap())).unwrap();
+            }
+            if !self.dims.0.is_multiple_of(2)
+            {
+                write!(out, " \\").unwrap();
+            }
+            writeln!(out).unwrap();
+            write!(out, "    ").unwrap();
+            for i in (0..self.dims.0).step_by(2)
+            {
+                write!(out, "/{}\\ ", label(self.get((i, j)).unwrap())).unwrap();
+            }
+            if self.dims.0.is_multiple_of(2)
+            {
+                write!(out, "/").unwrap();
+            }
+            writeln!(out).unwrap();
+            write!(out, "   {}", marker((0, j + 1))).unwrap();
+            for i in (0..self.dims.0).step_by(2)
+            {
+                write!(out, "-----{}", marker((i + 2, j + 1))).unwrap();
+            }
+            writeln!(out).unwrap();
+
+            if j+1 == self.dims.1
+            {
+                break;
+            }
+            
+            write!(out, "    ").unwrap();
+            for i in (0..self.dims.0).step_by(2)
+            {
+                write!(out, "\\{}/ ", label(self.get((i, j+1)).unwrap())).unwrap();
+            }
+            if self.dims.0.is_multiple_of(2)
+            {
+                write!(out, "\\").unwrap();
+            }
+            writeln!(out).unwrap();
+            write!(out, "     \\").unwrap();
+            for i in (1..self.dims.0).step_by(2)
+            {
+                write!(out, " /{}\\", label(self.get((i, j+1)).unwrap())).unwrap();
+            }
+            if !self.dims.0.is_multiple_of(2)
+            {
+                write!(out, " /").unwrap();
+            }
+            writeln!(out).unwrap();
+
+            write!(out, "      {}", marker((1, j + 2))).unwrap();
+            for i in (1..self.dims.0).step_by(2)
+            {
+                write!(out, "-----{}", marker((i + 2, j + 2))).unwrap();
+            }
+            writeln!(out).unwrap();
+
+        }
+        
+        out
+    }
+
+}
+
+impl<T: Copy + Debug + Into<f64>> Grid<T>
+{
+    // Whether no cell moved by more than `tol` since `prev`, for rules on
+    // real values that only converge in the limit.
+    pub fn approx_stable(&self, prev: &Grid<T>, tol: f64) -> bool
+    {
+        self.within(prev, |&cell| cell.into(), convergence::Tolerance::absolute(tol))
+    }
+}
+
+impl<T: Copy + Debug + std::fmt::Display> Grid<T>
+{
+    pub fn render(&self) -> String
+    {
+        self.render_labels(|cell| format!("{:^3}", cell))
+    }
+
+    pub fn print(&self)
+    {
+        print!("{}", self.render());
+    }
+    
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, Automata};
+
+    #[test]
+    fn approx_stable_takes_a_tolerance()
+    {
+        let grid = Grid::from_fn((5, 3), |(i, j)| (i + j) as f32);
+        let mut moved = grid.clone();
+        *moved.get_mut((2, 1)).unwrap() += 0.01;
+        assert!(moved.approx_stable(&grid, 0.02));
+        assert!(!moved.approx_stable(&grid, 0.005));
+        assert!(grid.approx_stable(&grid, 0.0));
+        assert!(!Grid::new((5, 4), 0f32).approx_stable(&grid, 1.0));
+    }
+
+    #[test]
+    fn heat_diffusion_converges()
+    {
+        let mut automata = Automata::new(Grid::from_fn((12, 8), |(i, j)| if (i, j) == (5, 4) {100f32} else {0.0}));
+        let mut steps = 0;
+        loop
+        {
+            let before = automata.current().clone();
+            automata.evolve(rules::heat_diffusion(0.5));
+            steps += 1;
+            if automata.current().approx_stable(&before, 1e-3)
+            {
+                break;
+            }
+            assert!(steps < 10_000, "no convergence");
+        }
+        // Stable means flat, here.
+        let cells = &automata.current().data;
+        let (low, high) = cells.iter().fold((f32::MAX, f32::MIN), |(low, high), &cell| (low.min(cell), high.max(cell)));
+        assert!(high - low < 0.5, "from {} to {} after {} steps", low, high, steps);
+    }
+
+    #[test]
+    fn rows_line_up_with_get()
+    {
+        let mut grid = Grid::from_fn((7, 4), |(i, j)| (i + 10*j) as u8);
+        for j in 0..4
+        {
+            assert_eq!(grid.row(j).len(), 7);
+            for (i, cell) in grid.row(j).iter().enumerate()
+            {
+                assert_eq!(grid.get((i, j)), Some(cell));
+                assert_eq!(grid.as_slice()[i + j*7], *cell);
+            }
+        }
+        grid.row_mut(2)[5] = 99;
+        grid.as_mut_slice()[3 + 7] = 98;
+        assert_eq!(grid.get((5, 2)), Some(&99));
+        assert_eq!(grid.get((3, 1)), Some(&98));
+        assert_eq!(grid.rows().nth(2), Some(grid.row(2)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn rows_past_the_grid_panic()
+    {
+        Grid::new((7, 4), 0u8).row(4);
+    }
+
+    #[test]
+    fn bulk_loads_check_the_length()
+    {
+        let mut grid = Grid::new((7, 4), 0u8);
+        let data: Vec<u8> = (0..28).collect();
+        assert_eq!(grid.copy_from_slice(&data), Ok(()));
+        assert_eq!(grid, Grid::from_fn((7, 4), |(i, j)| (i + 7*j) as u8));
+        assert_eq!(grid.copy_from_slice(&data[1..]), Err(DimMismatch{expected: 28, found: 27}));
+        assert_eq!(grid.copy_from_slice(&[0; 29]), Err(DimMismatch{expected: 28, found: 29}));
+        assert_eq!(grid.get((6, 3)), Some(&27));
+
+        let mut automata = Automata::new(Grid::new((7, 4), 0u8));
+        assert_eq!(automata.load_state(&[1; 20]), Err(DimMismatch{expected: 28, found: 20}));
+        assert_eq!(automata.load_state(&data), Ok(()));
+        assert_eq!(automata.current(), &grid);
+        // The next step starts from the loaded cells.
+        let mut manual = Automata::new(grid);
+        automata.evolve(rules::sandpile);
+        manual.evolve(rules::sandpile);
+        assert_eq!(automata.current(), manual.current());
+    }
+
+    fn check_lines(grid: &Grid<u8>)
+    {
+        let (w, h) = grid.dims;
+        assert_eq!(grid.rows().count(), h);
+        for (j, row) in grid.rows().enumerate()
+        {
+            assert_eq!(row.len(), w);
+            let coords: Vec<_> = grid.row_coords(j).collect();
+            assert_eq!(coords.len(), w);
+            for (i, cell) in row.iter().enumerate()
+            {
+                assert_eq!(grid.get((i, j)), Some(cell));
+                assert_eq!(coords[i], ((i, j), cell));
+            }
+        }
+        assert_eq!(grid.columns().count(), w);
+        for (i, column) in grid.columns().enumerate()
+        {
+            let column: Vec<_> = column.collect();
+            assert_eq!(column.len(), h);
+            for (j, cell) in column.into_iter().enumerate()
+            {
+                assert_eq!(grid.get((i, j)), Some(cell));
+            }
+            assert!(grid.column(i).eq(grid.rows().map(|row| &row[i])));
+        }
+        // Past the grid, nothing.
+        assert_eq!(grid.row_coords(h).count(), 0);
+        assert_eq!(grid.column(w).count(), 0);
+    }
+
+    #[test]
+    fn rows_and_columns_agree_with_get()
+    {
+        check_lines(&Grid::from_fn((7, 4), |(i, j)| (i + 10*j) as u8));
+        // A single row, then a single column.
+        check_lines(&Grid::from_fn((9, 1), |(i, _)| i as u8));
+        check_lines(&Grid::from_fn((1, 9), |(_, j)| j as u8));
+        check_lines(&Grid::new((1, 1), 5u8));
+    }
+
+    #[test]
+    fn signed_coordinates()
+    {
+        let grid = Grid::from_fn((6, 4), |(i, j)| (i + 10*j) as u8);
+        assert_eq!(grid.get_signed((0, 0)), Some(&0));
+        assert_eq!(grid.get_signed((5, 3)), Some(&35));
+        assert_eq!(grid.get_signed((-1, 0)), None);
+        assert_eq!(grid.get_signed((0, -1)), None);
+        assert_eq!(grid.get_signed((6, 0)), None);
+        assert_eq!(grid.get_signed((0, 4)), None);
+        assert_eq!(grid.get_signed((isize::MIN, isize::MAX)), None);
+
+        let periodic = BoundaryCondition::Periodic;
+        assert_eq!(grid.get_signed_wrapped((-1, 0), &periodic), Some(&5));
+        assert_eq!(grid.get_signed_wrapped((0, -1), &periodic), Some(&30));
+        assert_eq!(grid.get_signed_wrapped((-7, -5), &periodic), Some(&35));
+        assert_eq!(grid.get_signed_wrapped((6, 4), &periodic), Some(&0));
+        assert_eq!(grid.get_signed_wrapped((13, 9), &periodic), Some(&11));
+        // Wrapping keeps every cell pointing the way it did.
+        for (i, j) in [(-1isize, 0isize), (0, -3), (-13, 7), (8, -9)].iter().copied()
+        {
+            let (wi, wj) = (i.rem_euclid(6), j.rem_euclid(4));
+            assert_eq!(grid.get_signed_wrapped((i, j), &periodic), grid.get((wi as usize, wj as usize)));
+            assert_eq!((i + j).rem_euclid(2), (wi + wj) % 2);
+        }
+    }
+
+    #[test]
+    fn odd_axes_do_not_wrap()
+    {
+        let grid = Grid::from_fn((5, 4), |(i, j)| (i + 10*j) as u8);
+        let periodic = BoundaryCondition::Periodic;
+        assert_eq!(grid.get_signed_wrapped((4, 3), &periodic), Some(&34));
+        assert_eq!(grid.get_signed_wrapped((-1, 0), &periodic), None);
+        assert_eq!(grid.get_signed_wrapped((5, 0), &periodic), None);
+        assert_eq!(grid.get_signed_wrapped((2, -1), &periodic), None);
+        assert_eq!(Grid::<u8>::new((0, 4), 0).get_signed_wrapped((0, 0), &periodic), None);
+    }
+
+    #[test]
+    fn other_boundaries_past_the_edges()
+    {
+        let grid = Grid::from_fn((6, 4), |(i, j)| (i + 10*j) as u8);
+        let (open, fixed, reflective) = (BoundaryCondition::Open, BoundaryCondition::Fixed(99), BoundaryCondition::Reflective);
+        for coord in [(-1, 0), (6, 0), (0, 4), (isize::MIN, isize::MAX)]
+        {
+            assert_eq!(grid.get_signed_wrapped(coord, &open), None);
+            assert_eq!(grid.get_signed_wrapped(coord, &fixed), Some(&99));
+        }
+        assert_eq!(grid.get_signed_wrapped((2, 3), &fixed), Some(&32));
+        // One cell past an edge is the cell itself, further the cells
+        // mirrored, bouncing off the far edge past a grid length.
+        assert_eq!(grid.get_signed_wrapped((-1, 2), &reflective), Some(&20));
+        assert_eq!(grid.get_signed_wrapped((6, -1), &reflective), Some(&5));
+        assert_eq!(grid.get_signed_wrapped((-3, 5), &reflective), Some(&22));
+        assert_eq!(grid.get_signed_wrapped((13, 0), &reflective), Some(&1));
+        assert!(grid.get_signed_wrapped((isize::MIN, isize::MAX), &reflective).is_some());
+        assert_eq!(Grid::<u8>::new((0, 0), 0).get_signed_wrapped((1, 1), &fixed), None);
+    }
+}
@@ -0,0 +1,258 @@
+// Hexagons made of the six triangles around a lattice vertex, to run hex
+// grid rules on a triangle field. Every triangle has exactly one corner
+// among the vertices (x, y) (in the lattice of render::corners, see
+// vertex.rs) with x = 1 + 3c and y of the parity of c, so the hexagons
+// around those tile the plane. Hexagon (a, b) is the one around
+//
+//     (4 + 3a, 2b + 1 + a % 2),
+//
+// the columns a going right, a third of an hexagon apart, and the rows b
+// down, odd columns half a hexagon lower. Only hexagons whose six
+// triangles are all in the grid are there, which leaves out the triangles
+// along the border whose hexagon it cuts through.
+//
+// Members of a hexagon come in the order of vertex::incident_faces: the
+// three triangles above the center from left to right, then the three
+// below. Neighbors come clockwise from the one above.
+
+use crate::Grid;
+use crate::vertex;
+
+use std::convert::TryInto;
+use std::fmt::Debug;
+
+pub struct HexView<'a, T>
+{
+    grid: &'a Grid<T>
+}
+
+// The center of hexagon (a, b), without checking it is in a grid.
+pub fn hex_center((a, b): (usize, usize)) -> (usize, usize)
+{
+    (4 + 3*a, 2*b + 1 + a % 2)
+}
+
+// Columns and rows of hexagons of a grid of `dims`; odd columns have one
+// less when the rows of hexagons leave half a hexagon at the bottom.
+pub fn hex_dims((w, h): (usize, usize)) -> (usize, usize)
+{
+    let columns = if w >= 5 { (w - 5)/3 + 1 } else { 0 };
+    let rows = if h >= 2 { (h - 2)/2 + 1 } else { 0 };
+    (columns, rows)
+}
+
+// The cells of the hexagon in a grid of `dims`, None if one is out of it.
+pub fn members(dims: (usize, usize), hex: (usize, usize)) -> Option<[(usize, usize); 6]>
+{
+    let (columns, rows) = hex_dims(dims);
+    if hex.0 >= columns || hex.1 >= rows
+    {
+        return None;
+    }
+    vertex::incident_faces(dims, hex_center(hex)).try_into().ok()
+}
+
+// The six hexagons around one, clockwise from the one above, None where
+// there is none in the grid.
+pub fn hex_neighbors(dims: (usize, usize), (a, b): (usize, usize)) -> [Option<(usize, usize)>; 6]
+{
+    let (a, b) = (a as isize, b as isize);
+    // Odd columns sit half a hexagon lower than even ones.
+    let (upper, lower) = if a % 2 == 0 { (b - 1, b) } else { (b, b + 1) };
+    [(a, b - 1), (a + 1, upper), (a + 1, lower), (a, b + 1), (a - 1, lower), (a - 1, upper)].map(|(a, b)| {
+        if a < 0 || b < 0
+        {
+            return None;
+        }
+        let hex = (a as usize, b as usize);
+        members(dims, hex).map(|_| hex)
+    })
+}
+
+// For every cell, the hexagon it is in, if any.
+pub fn membership(dims: (usize, usize)) -> Grid<Option<(usize, usize)>>
+{
+    let mut grid = Grid::new(dims, None);
+    let (columns, rows) = hex_dims(dims);
+    for a in 0..columns
+    {
+        for b in 0..rows
+        {
+            for coord in members(dims, (a, b)).into_iter().flatten()
+            {
+                *grid.get_mut(coord).unwrap() = Some((a, b));
+            }
+        }
+    }
+    grid
+}
+
+impl<'a, T: Copy + Debug> HexView<'a, T>
+{
+    pub fn new(grid: &'a Grid<T>) -> Self
+    {
+        Self{grid}
+    }
+
+    pub fn hex_dims(&self) -> (usize, usize)
+    {
+        hex_dims(self.grid.dims)
+    }
+
+    pub fn get_hex(&self, hex: (usize, usize)) -> Option<[&'a T; 6]>
+    {
+        let grid = self.grid;
+        members(grid.dims, hex).map(|cells| cells.map(|coord| grid.get(coord).unwrap()))
+    }
+
+    pub fn reduce_hex<U, F: Fn([&T; 6]) -> U>(&self, hex: (usize, usize), f: F) -> Option<U>
+    {
+        self.get_hex(hex).map(f)
+    }
+
+    pub fn neighbors(&self, hex: (usize, usize)) -> [Option<(usize, usize)>; 6]
+    {
+        hex_neighbors(self.grid.dims, hex)
+    }
+}
+
+// One step of a hex rule: every hexagon reduced to a value, the rule given
+// the value of each and of its neighbors (clockwise from the one above,
+// None past the border), and the new value splat back onto the six cells
+// of the hexagon. Cells outside of all hexagons keep their state.
+pub fn evolve_hex<T, U, R, F, S>(grid: &mut Grid<T>, reduce: R, rule: F, splat: S)
+where
+    T: Copy + Debug,
+    U: Copy + Debug,
+    R: Fn([&T; 6]) -> U,
+    F: Fn(U, [Option<U>; 6]) -> U,
+    S: Fn(U, &T) -> T
+{
+    let (columns, rows) = hex_dims(grid.dims);
+    let view = HexView::new(grid);
+    let values = Grid::from_fn((columns, rows), |hex| view.reduce_hex(hex, &reduce));
+    let next: Vec<((usize, usize), U)> = (0..rows)
+        .flat_map(|b| (0..columns).map(move |a| (a, b)))
+        .filter_map(|hex| {
+            let value = (*values.get(hex).unwrap())?;
+            let around = view.neighbors(hex).map(|neighbor| neighbor.and_then(|neighbor| *values.get(neighbor).unwrap()));
+            Some((hex, rule(value, around)))
+        })
+        .collect();
+    for (hex, value) in next
+    {
+        for coord in members(grid.dims, hex).expect("hexagons with a value are in the grid")
+        {
+            let cell = grid.get_mut(coord).unwrap();
+            *cell = splat(value, cell);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::image::{self, RenderOptions};
+
+    #[test]
+    fn an_inner_hexagon_and_its_neighbors()
+    {
+        let dims = (16, 9);
+        assert_eq!(hex_dims(dims), (4, 4));
+        assert_eq!(hex_center((2, 1)), (10, 3));
+        assert_eq!(members(dims, (2, 1)), Some([(8, 2), (9, 2), (10, 2), (8, 3), (9, 3), (10, 3)]));
+        assert_eq!(hex_neighbors(dims, (2, 1)), [Some((2, 0)), Some((3, 0)), Some((3, 1)), Some((2, 2)), Some((1, 1)), Some((1, 0))]);
+        // Along the border, some are missing; odd columns sit lower.
+        assert_eq!(hex_neighbors(dims, (0, 0)), [None, None, Some((1, 0)), Some((0, 1)), None, None]);
+        // The last of an odd column may be past the bottom.
+        assert_eq!(members(dims, (1, 3)), Some([(5, 7), (6, 7), (7, 7), (5, 8), (6, 8), (7, 8)]));
+        assert_eq!(hex_dims((16, 8)), (4, 4));
+        assert_eq!(members((16, 8), (1, 3)), None);
+        assert_eq!(members(dims, (4, 0)), None);
+        assert_eq!(hex_dims((4, 1)), (0, 0));
+    }
+
+    #[test]
+    fn hexagons_do_not_overlap_and_neighbors_are_mutual()
+    {
+        for &dims in &[(16, 9), (5, 2), (20, 13), (9, 4)]
+        {
+            let (columns, rows) = hex_dims(dims);
+            let membership = membership(dims);
+            let mut seen = Grid::new(dims, false);
+            for hex in (0..rows).flat_map(|b| (0..columns).map(move |a| (a, b)))
+            {
+                let cells = match members(dims, hex)
+                {
+                    Some(cells) => cells,
+                    None => continue
+                };
+                for coord in cells
+                {
+                    assert!(!std::mem::replace(seen.get_mut(coord).unwrap(), true), "{:?}: cell {:?} is in two hexagons", dims, coord);
+                    assert_eq!(membership.get(coord), Some(&Some(hex)));
+                }
+                for &neighbor in hex_neighbors(dims, hex).iter().flatten()
+                {
+                    assert!(hex_neighbors(dims, neighbor).contains(&Some(hex)), "{:?}: {:?} and {:?}", dims, hex, neighbor);
+                }
+            }
+            // Cells left out are along the border.
+            for (k, &inside) in seen.data.iter().enumerate()
+            {
+                let (i, j) = (k % dims.0, k / dims.0);
+                assert!(inside || i < 2 || j == 0 || i + 3 >= dims.0 || j + 2 >= dims.1, "{:?}: {:?}", dims, (i, j));
+                assert_eq!(membership.data[k].is_some(), inside);
+            }
+        }
+    }
+
+    #[test]
+    fn views_reduce_and_rules_splat_back()
+    {
+        let grid = Grid::from_fn((16, 9), |(i, j)| (i + 100*j) as u32);
+        let view = HexView::new(&grid);
+        assert_eq!(view.hex_dims(), (4, 4));
+        assert_eq!(view.get_hex((2, 1)), Some([&208, &209, &210, &308, &309, &310]));
+        assert_eq!(view.reduce_hex((2, 1), |cells| cells.iter().map(|&&cell| cell).sum::<u32>()), Some(1554));
+        assert_eq!(view.reduce_hex((9, 9), |_| ()), None);
+        assert_eq!(view.neighbors((2, 1)), hex_neighbors((16, 9), (2, 1)));
+
+        // A hexagon lit only by its neighbors: one lit hexagon lights its
+        // six neighbors and goes dark.
+        let mut grid = Grid::new((16, 9), 0u8);
+        for coord in members(grid.dims, (2, 1)).unwrap()
+        {
+            *grid.get_mut(coord).unwrap() = 1;
+        }
+        *grid.get_mut((0, 0)).unwrap() = 7;
+        evolve_hex(&mut grid, |cells| *cells[0], |_, around| u8::from(around.iter().flatten().any(|&lit| lit == 1)), |value, _| value);
+        let lit: Vec<(usize, usize)> = membership(grid.dims).data.iter().zip(&grid.data)
+            .filter(|(_, &cell)| cell == 1)
+            .filter_map(|(hex, _)| *hex)
+            .collect::<std::collections::BTreeSet<_>>().into_iter().collect();
+        assert_eq!(lit, vec![(1, 0), (1, 1), (2, 0), (2, 2), (3, 0), (3, 1)]);
+        assert_eq!(grid.get((0, 0)), Some(&7));
+    }
+
+    #[test]
+    fn outlines_follow_the_hexagons()
+    {
+        let grid = Grid::new((16, 9), 0u8);
+        let options = RenderOptions{cell_px: 12, hex_outlines: Some([255, 255, 255]), ..RenderOptions::default()};
+        let black = |_: &u8| (0, 0, 0);
+        let outlined = image::rasterize(&grid, black, &options);
+        let plain = image::rasterize(&grid, black, &RenderOptions{hex_outlines: None, ..options});
+        let white = |image: &image::Image| image.pixels.iter().filter(|&&pixel| pixel == [255, 255, 255]).count();
+        assert_eq!(white(&plain), 0);
+        assert!(white(&outlined) > 0);
+        // Not inside, around the centers.
+        let (x, y) = hex_center((2, 1));
+        let (px, py) = (x*6, (y as f64 * 6.0 * 3f64.sqrt()) as usize);
+        for (dx, dy) in [(0, 0), (2, 2), (3, 0), (0, 3)]
+        {
+            assert_eq!(outlined.pixels[(py + dy - 1)*outlined.width + px + dx - 1], [0, 0, 0]);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+// Steps restricted to the cells near a few hotspots, for large grids where
+// all the activity stays within some distance of its sources.
+
+use crate::{Automata, Grid, Slot};
+use crate::boundary::BoundaryCondition;
+use crate::flux;
+
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Display};
+
+// The cells at most `radius` edge crossings from one of the centers, across
+// the edges of a periodic grid, kept by the automaton between
+// evolve_within calls with the same arguments.
+pub struct HotspotRegion
+{
+    centers: Vec<(usize, usize)>,
+    radius: usize,
+    dims: (usize, usize),
+    wraps: bool,
+    // In storage order.
+    cells: Vec<(usize, usize)>,
+    // The cells of the region with a neighbor outside of it.
+    boundary: Vec<(usize, usize)>
+}
+
+impl HotspotRegion
+{
+    // Coordinates held, centers, cells and boundary, for memory_report.
+    pub(crate) fn coord_count(&self) -> usize
+    {
+        self.centers.len() + self.cells.len() + self.boundary.len()
+    }
+
+    // Centers outside of the grid are left out.
+    pub fn new<T: Copy + Debug>(grid: &Grid<T>, centers: &[(usize, usize)], radius: usize) -> Self
+    {
+        Self::with_boundary(grid, centers, radius, &BoundaryCondition::Open)
+    }
+
+    // The region of a grid under `boundary`, which the dims must be able to
+    // have: only a periodic one joins cells across the edges.
+    pub fn with_boundary<T: Copy + Debug>(grid: &Grid<T>, centers: &[(usize, usize)], radius: usize, boundary: &BoundaryCondition<T>) -> Self
+    {
+        let (w, h) = grid.dims;
+        let wraps = boundary.is_periodic();
+        let around = |coord| Slot::ALL.iter().filter_map(move |&slot| flux::neighbor(grid.dims, coord, slot, boundary).ok());
+        let mut distance = vec![usize::MAX; w*h];
+        let mut queue = VecDeque::new();
+        for &(i, j) in centers.iter().filter(|&&(i, j)| i < w && j < h)
+        {
+            if distance[j*w + i] != 0
+            {
+                distance[j*w + i] = 0;
+                queue.push_back((i, j));
+            }
+        }
+        while let Some(coord) = queue.pop_front()
+        {
+            let d = distance[coord.1*w + coord.0];
+            if d == radius
+            {
+                continue;
+            }
+            for (i, j) in around(coord)
+            {
+                if distance[j*w + i] == usize::MAX
+                {
+                    distance[j*w + i] = d + 1;
+                    queue.push_back((i, j));
+                }
+            }
+        }
+
+        let inside = |(i, j): (usize, usize)| distance[j*w + i] != usize::MAX;
+        let cells: Vec<(usize, usize)> = (0..h)
+            .flat_map(|j| (0..w).map(move |i| (i, j)))
+            .filter(|&coord| inside(coord))
+            .collect();
+        let boundary = cells.iter()
+            .copied()
+            .filter(|&coord| around(coord).any(|ncoord| !inside(ncoord)))
+            .collect();
+        Self{centers: centers.to_vec(), radius, dims: grid.dims, wraps, cells, boundary}
+    }
+
+    pub fn is_for(&self, centers: &[(usize, usize)], radius: usize, dims: (usize, usize), wraps: bool) -> bool
+    {
+        self.centers == centers && self.radius == radius && self.dims == dims && self.wraps == wraps
+    }
+
+    pub fn cells(&self) -> &[(usize, usize)]
+    {
+        &self.cells
+    }
+
+    pub fn boundary(&self) -> &[(usize, usize)]
+    {
+        &self.boundary
+    }
+}
+
+// The cells of the region's boundary that changed during an evolve_within
+// step: the activity may have gone past the radius, and the cells outside
+// of it, left as they were, may be wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionOverflow
+{
+    pub step: u64,
+    pub changed: Vec<(usize, usize)>
+}
+
+impl fmt::Display for RegionOverflow
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "step {}: {} cells changed on the boundary of the region, first {:?}; the radius is too small",
+               self.step, self.changed.len(), self.changed[0])
+    }
+}
+
+impl<T: Clone + Display + Copy + Debug + PartialEq> Automata<T>
+{
+    // Like evolve, computing only the cells of the region around `centers`,
+    // and those with a rule of their own, and copying the others unchanged. The region is computed again only
+    // when the centers, the radius or the dims change. The step is taken
+    // in any case; the error tells that the region was too small for it.
+    pub fn evolve_within<F>(&mut self, rule: F, centers: &[(usize, usize)], radius: usize) -> Result<(), RegionOverflow>
+    where
+        F: Fn(Vec<T>) -> T
+    {
+        let wraps = self.boundary.is_periodic();
+        if !self.hotspots.as_ref().is_some_and(|region| region.is_for(centers, radius, self.current.dims, wraps))
+        {
+            self.hotspots = Some(HotspotRegion::with_boundary(&self.current, centers, radius, &self.boundary));
+        }
+        self.apply_source_programs();
+        let region = self.hotspots.as_ref().unwrap();
+        // The scratch grid becomes the previous generation, as after evolve.
+        self.scratch.data.clone_from(&self.current.data);
+        self.scratch.dims = self.current.dims;
+        for &coord in region.cells.iter().chain(self.cell_rules.keys())
+        {
+            let ngh = self.current.neighborhood_under(coord, &self.boundary);
+            *self.scratch.get_mut(coord).unwrap() = match self.cell_rules.get(&coord)
+            {
+                Some(overridden) => overridden.apply(ngh),
+                None => rule(ngh)
+            };
+        }
+        let changed: Vec<(usize, usize)> = region.boundary.iter()
+            .copied()
+            .filter(|&coord| self.current.get(coord) != self.scratch.get(coord))
+            .collect();
+
+        std::mem::swap(&mut self.current, &mut self.scratch);
+        self.previous = None;
+        self.finish_step();
+        if changed.is_empty()
+        {
+            Ok(())
+        }
+        else
+        {
+            Err(RegionOverflow{step: self.step, changed})
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, Light};
+
+    const CENTERS: [(usize, usize); 2] = [(10, 8), (40, 20)];
+
+    fn lamps() -> Automata<Light>
+    {
+        let mut automata = Automata::new(Grid::new((60, 30), Light::Space(0)));
+        for &coord in &CENTERS
+        {
+            *automata.get_mut(coord).unwrap() = Light::Source(6);
+        }
+        automata
+    }
+
+    #[test]
+    fn generous_radii_match_evolve()
+    {
+        let mut within = lamps();
+        let mut full = lamps();
+        for _ in 0..30
+        {
+            within.evolve_within(rules::light_falloff, &CENTERS, 8).unwrap();
+            full.evolve(rules::light_falloff);
+            assert_eq!(within.current(), full.current());
+            assert_eq!(within.step(), full.step());
+        }
+    }
+
+    #[test]
+    fn small_radii_are_reported()
+    {
+        let mut automata = lamps();
+        // The light reaches the boundary, 2 cells away, at the second step.
+        automata.evolve_within(rules::light_falloff, &CENTERS, 2).unwrap();
+        let overflow = automata.evolve_within(rules::light_falloff, &CENTERS, 2).unwrap_err();
+        assert_eq!(overflow.step, 2);
+        let region = HotspotRegion::new(automata.current(), &CENTERS, 2);
+        assert!(overflow.changed.iter().all(|coord| region.boundary().contains(coord)));
+        assert!(overflow.to_string().starts_with("step 2: "));
+        assert!(overflow.to_string().ends_with("the radius is too small"));
+        // The step was taken all the same.
+        assert_eq!(automata.step(), 2);
+    }
+
+    #[test]
+    fn regions_are_balls_around_the_centers()
+    {
+        let grid = Grid::new((60, 30), 0u8);
+        let point = HotspotRegion::new(&grid, &[(10, 8), (10, 8), (99, 0)], 0);
+        assert_eq!(point.cells(), &[(10, 8)]);
+        assert_eq!(point.boundary(), &[(10, 8)]);
+
+        let region = HotspotRegion::new(&grid, &CENTERS, 3);
+        let field = crate::analysis::distance_field(&grid, &CENTERS, |_| true);
+        let near: Vec<(usize, usize)> = (0..30)
+            .flat_map(|j| (0..60).map(move |i| (i, j)))
+            .filter(|&coord| field.get(coord).unwrap().is_some_and(|d| d <= 3))
+            .collect();
+        assert_eq!(region.cells(), &near[..]);
+        // A ball of radius 3 holds 1 + 3 + 6 + 9 cells away from the edges.
+        assert_eq!(region.cells().len(), 2*19);
+        assert!(region.boundary().iter().all(|coord| region.cells().contains(coord)));
+        assert!(region.is_for(&CENTERS, 3, (60, 30), false));
+        assert!(!region.is_for(&CENTERS, 4, (60, 30), false));
+        assert!(!region.is_for(&CENTERS, 3, (60, 30), true));
+    }
+}
@@ -0,0 +1,1069 @@
+// Raster images of a grid: every cell drawn as its actual triangle, written
+// as PPM or PNG, and recordings of a run as animated PNG. Both writers are done by hand; PNG data is stored without
+// compression, which keeps the encoder small at the price of file size.
+//
+// Edges are anti-aliased by supersampling: the image is rasterized at
+// `supersample` times the resolution in both directions and each pixel is
+// the mean of its samples, so pixels straddling the edge between two cells
+// blend their colors (and those of the grid lines).
+
+use crate::{Automata, Grid};
+use crate::coord::Coord;
+use crate::hex;
+use crate::overlay::{Overlay, DEFAULT_HIGHLIGHT};
+use crate::run::FrameFilter;
+use crate::seam::Seams;
+
+use std::fmt::{Debug, Display};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderOptions
+{
+    // Side of a triangle in pixels.
+    pub cell_px: usize,
+    // Samples per pixel along each axis; 1 draws hard edges.
+    pub supersample: u8,
+    // Color of the lines along cell edges, one pixel wide, if any.
+    pub grid_lines: Option<[u8; 3]>,
+    // Ghost cells past the wrapped edges, with a dashed seam in the color
+    // of the grid lines (white without them).
+    pub seams: Option<Seams>,
+    // Color of the outlines of the hexagons of hex.rs, if any; with seams,
+    // the hexagons of the grid with its ghosts.
+    pub hex_outlines: Option<[u8; 3]>,
+    // Frame sequences redraw only the cells that changed since the last
+    // frame (see IncrementalRaster).
+    pub incremental: bool
+}
+
+impl Default for RenderOptions
+{
+    fn default() -> Self
+    {
+        Self{cell_px: 12, supersample: 1, grid_lines: None, seams: None, hex_outlines: None, incremental: false}
+    }
+}
+
+// Pixels outside of every cell, at the ends of the rows.
+const BACKGROUND: [u8; 3] = [0, 0, 0];
+
+// Length in pixels of the dashes and gaps of a seam.
+const SEAM_DASH: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image
+{
+    pub width: usize,
+    pub height: usize,
+    // Row major, top row first.
+    pub pixels: Vec<[u8; 3]>
+}
+
+impl Image
+{
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3]
+    {
+        self.pixels[y*self.width + x]
+    }
+
+    // Binary PPM (P6).
+    pub fn write_ppm<W: Write>(&self, mut writer: W) -> io::Result<()>
+    {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        for pixel in &self.pixels
+        {
+            writer.write_all(pixel)?;
+        }
+        writer.flush()
+    }
+
+    // 8 bit RGB PNG.
+    pub fn write_png<W: Write>(&self, mut writer: W) -> io::Result<()>
+    {
+        writer.write_all(PNG_SIGNATURE)?;
+        write_chunk(&mut writer, b"IHDR", &self.png_header())?;
+        write_chunk(&mut writer, b"IDAT", &self.png_data())?;
+        write_chunk(&mut writer, b"IEND", &[])?;
+        writer.flush()
+    }
+
+    fn png_header(&self) -> Vec<u8>
+    {
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // Bit depth 8, color type 2 (RGB), default compression, filter and
+        // no interlacing.
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        header
+    }
+
+    // The zlib stream of the scanlines, each starting with its filter
+    // type, 0 for none.
+    fn png_data(&self) -> Vec<u8>
+    {
+        let mut raw = Vec::with_capacity(self.height * (1 + 3*self.width));
+        for row in self.pixels.chunks(self.width.max(1))
+        {
+            raw.push(0);
+            for pixel in row
+            {
+                raw.extend_from_slice(pixel);
+            }
+        }
+        zlib_stored(&raw)
+    }
+}
+
+const PNG_SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+pub(crate) fn crc32(bytes: &[u8]) -> u32
+{
+    let mut crc = !0u32;
+    for &byte in bytes
+    {
+        crc ^= u32::from(byte);
+        for _ in 0..8
+        {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32
+{
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes
+    {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+// A zlib stream made of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8>
+{
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none()
+    {
+        // A final, empty block.
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next()
+    {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()>
+{
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let mut crc = kind.to_vec();
+    crc.extend_from_slice(data);
+    writer.write_all(&crc32(&crc).to_be_bytes())
+}
+
+// Size in pixels of the image of a grid of `dims` cells.
+pub fn image_size((w, h): (usize, usize), cell_px: usize) -> (usize, usize)
+{
+    let row_px = cell_px as f64 * 3f64.sqrt() / 2.0;
+    ((w + 1) * cell_px / 2, (h as f64 * row_px).round() as usize)
+}
+
+// The cell under a point given in lattice units (x in half edges, y in
+// rows), with the distance from the point to the nearest edge of that cell
+// in pixels.
+fn locate((w, h): (usize, usize), cell_px: usize, x: f64, y: f64) -> Option<((usize, usize), f64)>
+{
+    if x < 0.0 || y < 0.0 || y >= h as f64
+    {
+        return None;
+    }
+    let j = y as usize;
+    let fy = y - j as f64;
+    let half_px = cell_px as f64 / 2.0;
+    let row_px = half_px * 3f64.sqrt();
+    // Exactly one of the two cells centered around x contains the point,
+    // unless it lies outside of the row's ends.
+    let k = x as usize;
+    for i in [k.wrapping_sub(1), k]
+    {
+        if i >= w
+        {
+            continue;
+        }
+        let off_center = (x - (i + 1) as f64).abs();
+        // How far the slanted edges are from the point, horizontally.
+        let slack = if (i + j).is_multiple_of(2) { fy - off_center } else { 1.0 - fy - off_center };
+        if slack >= 0.0
+        {
+            let slanted = slack * half_px * 3f64.sqrt() / 2.0;
+            let flat = fy.min(1.0 - fy) * row_px;
+            return Some(((i, j), slanted.min(flat)));
+        }
+    }
+    None
+}
+
+fn cell_colors<T: Copy + Debug, F: Fn(&T) -> (u8, u8, u8)>(grid: &Grid<T>, color: F) -> Vec<[u8; 3]>
+{
+    grid.data.iter()
+        .map(|cell| {
+            let (r, g, b) = color(cell);
+            [r, g, b]
+        })
+        .collect()
+}
+
+// The pixel at (px, py) of the image of a grid of `dims` with the cells in
+// `colors`: the mean of its samples.
+fn shade(dims: (usize, usize), colors: &[[u8; 3]], options: &RenderOptions, px: usize, py: usize) -> [u8; 3]
+{
+    let samples = usize::from(options.supersample.max(1));
+    let half_px = options.cell_px as f64 / 2.0;
+    let row_px = half_px * 3f64.sqrt();
+    let mut sum = [0u32; 3];
+    for sy in 0..samples
+    {
+        for sx in 0..samples
+        {
+            let x = px as f64 + (sx as f64 + 0.5) / samples as f64;
+            let y = py as f64 + (sy as f64 + 0.5) / samples as f64;
+            let sample = match (locate(dims, options.cell_px, x / half_px, y / row_px), options.grid_lines)
+            {
+                (None, _) => BACKGROUND,
+                (Some((_, edge)), Some(line)) if edge < 0.5 => line,
+                (Some(((i, j), _)), _) => colors[j*dims.0 + i]
+            };
+            for (total, channel) in sum.iter_mut().zip(sample.iter())
+            {
+                *total += u32::from(*channel);
+            }
+        }
+    }
+    let n = (samples * samples) as u32;
+    [0, 1, 2].map(|c| ((sum[c] + n/2) / n) as u8)
+}
+
+// Draws every cell with `color`. Samples within half a pixel of an edge
+// take the color of the grid lines when there are some.
+pub fn rasterize<T, F>(grid: &Grid<T>, color: F, options: &RenderOptions) -> Image
+where
+    T: Copy + Debug,
+    F: Fn(&T) -> (u8, u8, u8)
+{
+    let mut image = Image{width: 0, height: 0, pixels: Vec::new()};
+    rasterize_into(grid, color, options, &mut image);
+    image
+}
+
+// The same, reusing the pixel buffer of `image`, for recordings.
+pub fn rasterize_into<T, F>(grid: &Grid<T>, color: F, options: &RenderOptions, image: &mut Image)
+where
+    T: Copy + Debug,
+    F: Fn(&T) -> (u8, u8, u8)
+{
+    let ghosted;
+    let (grid, inner_dims) = match &options.seams
+    {
+        Some(seams) =>
+        {
+            ghosted = seams.ghost_grid(grid);
+            (&ghosted, grid.dims)
+        },
+        None => (grid, grid.dims)
+    };
+    let (width, height) = image_size(grid.dims, options.cell_px);
+    let colors = cell_colors(grid, color);
+
+    image.width = width;
+    image.height = height;
+    image.pixels.clear();
+    for py in 0..height
+    {
+        for px in 0..width
+        {
+            image.pixels.push(shade(grid.dims, &colors, options, px, py));
+        }
+    }
+    if let Some(seams) = &options.seams
+    {
+        draw_seams(image, grid.dims, inner_dims, seams, options);
+    }
+    if let Some(color) = options.hex_outlines
+    {
+        draw_hex_outlines(image, grid.dims, color, options);
+    }
+}
+
+// Pixels of an image, in the frame sequences handed to encoders that
+// take updates of parts of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect
+{
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize
+}
+
+// The images of successive generations, each redrawn from the last: only
+// the pixels of the cells whose color changed, and a pixel around them for
+// the samples and grid lines crossing their edges, which gives the image
+// rasterize would. Seams redraw everything, their ghosts being elsewhere.
+pub struct IncrementalRaster
+{
+    image: Image,
+    colors: Vec<[u8; 3]>,
+    dims: (usize, usize),
+    drawn_with: Option<RenderOptions>
+}
+
+impl IncrementalRaster
+{
+    pub fn new() -> Self
+    {
+        Self{image: Image{width: 0, height: 0, pixels: Vec::new()}, colors: vec![], dims: (0, 0), drawn_with: None}
+    }
+
+    pub fn image(&self) -> &Image
+    {
+        &self.image
+    }
+
+    // Brings the image up to date with the grid, returning the rectangles
+    // redrawn: the whole image the first time, after a change of dims or
+    // options and with seams or hexagon outlines, nothing when no cell
+    // changed color.
+    pub fn update<T, F>(&mut self, grid: &Grid<T>, color: F, options: &RenderOptions) -> Vec<Rect>
+    where
+        T: Copy + Debug,
+        F: Fn(&T) -> (u8, u8, u8)
+    {
+        let colors = cell_colors(grid, &color);
+        if self.drawn_with != Some(*options) || self.dims != grid.dims || options.seams.is_some() || options.hex_outlines.is_some()
+        {
+            rasterize_into(grid, color, options, &mut self.image);
+            self.drawn_with = Some(*options);
+            self.dims = grid.dims;
+            self.colors = colors;
+            return vec![Rect{x: 0, y: 0, width: self.image.width, height: self.image.height}];
+        }
+        let (w, h) = grid.dims;
+        let half_px = options.cell_px as f64 / 2.0;
+        let row_px = half_px * 3f64.sqrt();
+        let mut rects = vec![];
+        for j in 0..h
+        {
+            let mut i = 0;
+            while i < w
+            {
+                if colors[j*w + i] == self.colors[j*w + i]
+                {
+                    i += 1;
+                    continue;
+                }
+                let start = i;
+                while i < w && colors[j*w + i] != self.colors[j*w + i]
+                {
+                    i += 1;
+                }
+                // Cells start..i cover half edges start to i + 1.
+                let x0 = ((start as f64 * half_px).floor() as usize).saturating_sub(1);
+                let x1 = (((i + 1) as f64 * half_px).ceil() as usize + 1).min(self.image.width);
+                let y0 = ((j as f64 * row_px).floor() as usize).saturating_sub(1);
+                let y1 = (((j + 1) as f64 * row_px).ceil() as usize + 1).min(self.image.height);
+                for py in y0..y1
+                {
+                    for px in x0..x1
+                    {
+                        self.image.pixels[py*self.image.width + px] = shade(grid.dims, &colors, options, px, py);
+                    }
+                }
+                rects.push(Rect{x: x0, y: y0, width: x1 - x0, height: y1 - y0});
+            }
+        }
+        self.colors = colors;
+        rects
+    }
+}
+
+impl Default for IncrementalRaster
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+// 3x5 pixel glyphs of the overlay labels, one row of 3 bits per byte from
+// the top. Lowercase letters are drawn as uppercase, unknown characters as
+// a question mark.
+fn font_glyph(c: char) -> [u8; 5]
+{
+    match c.to_ascii_uppercase()
+    {
+        '0' => [7, 5, 5, 5, 7], '1' => [2, 6, 2, 2, 7], '2' => [7, 1, 7, 4, 7], '3' => [7, 1, 7, 1, 7],
+        '4' => [5, 5, 7, 1, 1], '5' => [7, 4, 7, 1, 7], '6' => [7, 4, 7, 5, 7], '7' => [7, 1, 1, 2, 2],
+        '8' => [7, 5, 7, 5, 7], '9' => [7, 5, 7, 1, 7],
+        'A' => [2, 5, 7, 5, 5], 'B' => [6, 5, 6, 5, 6], 'C' => [3, 4, 4, 4, 3], 'D' => [6, 5, 5, 5, 6],
+        'E' => [7, 4, 6, 4, 7], 'F' => [7, 4, 6, 4, 4], 'G' => [3, 4, 5, 5, 3], 'H' => [5, 5, 7, 5, 5],
+        'I' => [7, 2, 2, 2, 7], 'J' => [1, 1, 1, 5, 2], 'K' => [5, 5, 6, 5, 5], 'L' => [4, 4, 4, 4, 7],
+        'M' => [5, 7, 7, 5, 5], 'N' => [6, 5, 5, 5, 5], 'O' => [2, 5, 5, 5, 2], 'P' => [6, 5, 6, 4, 4],
+        'Q' => [2, 5, 5, 6, 3], 'R' => [6, 5, 6, 5, 5], 'S' => [3, 4, 2, 1, 6], 'T' => [7, 2, 2, 2, 2],
+        'U' => [5, 5, 5, 5, 7], 'V' => [5, 5, 5, 5, 2], 'W' => [5, 5, 7, 7, 5], 'X' => [5, 5, 2, 5, 5],
+        'Y' => [5, 5, 2, 2, 2], 'Z' => [7, 1, 2, 4, 7],
+        ' ' => [0; 5], '-' => [0, 0, 7, 0, 0], '.' => [0, 0, 0, 0, 2],
+        _ => [7, 1, 2, 0, 2]
+    }
+}
+
+// rasterize with every annotated cell outlined in its color and its label
+// written over its center, in the inverse of the pixels under it. With
+// seams, the ghosts of annotated cells are annotated too.
+pub fn rasterize_overlaid<T, F>(grid: &Grid<T>, color: F, options: &RenderOptions, overlay: &Overlay) -> Image
+where
+    T: Copy + Debug,
+    F: Fn(&T) -> (u8, u8, u8)
+{
+    let mut image = rasterize(grid, color, options);
+    let (margins, drawn_dims) = match &options.seams
+    {
+        Some(seams) => (seams.margins(), seams.ghosted_dims(grid.dims)),
+        None => ((0, 0), grid.dims)
+    };
+    // The cell of the grid drawn at a coordinate of the image.
+    let (w, h) = grid.dims;
+    let source = |(i, j): (usize, usize)| {
+        ((i as isize - margins.0 as isize).rem_euclid(w as isize) as usize,
+         (j as isize - margins.1 as isize).rem_euclid(h as isize) as usize)
+    };
+    let half_px = options.cell_px as f64 / 2.0;
+    let row_px = half_px * 3f64.sqrt();
+    let border = (options.cell_px as f64 / 10.0).max(1.0);
+    for py in 0..image.height
+    {
+        for px in 0..image.width
+        {
+            let located = locate(drawn_dims, options.cell_px, (px as f64 + 0.5) / half_px, (py as f64 + 0.5) / row_px);
+            if let Some((coord, edge)) = located
+            {
+                match overlay.get(source(coord))
+                {
+                    Some(annotation) if edge < border =>
+                        image.pixels[py*image.width + px] = annotation.color.unwrap_or(DEFAULT_HIGHLIGHT),
+                    _ => ()
+                }
+            }
+        }
+    }
+
+    let scale = (options.cell_px / 16).max(1);
+    for j in 0..drawn_dims.1
+    {
+        for i in 0..drawn_dims.0
+        {
+            let label = match overlay.get(source((i, j))).and_then(|annotation| annotation.label.as_ref())
+            {
+                Some(label) => label,
+                None => continue
+            };
+            let (x, y) = Coord::from((i, j)).centroid();
+            let chars = label.chars().count();
+            let left = x * options.cell_px as f64 - (4*chars - 1) as f64 * scale as f64 / 2.0;
+            let top = y * options.cell_px as f64 - 2.5 * scale as f64;
+            for (n, c) in label.chars().enumerate()
+            {
+                for (row, bits) in font_glyph(c).iter().enumerate()
+                {
+                    for column in 0..3
+                    {
+                        if bits >> (2 - column) & 1 == 0
+                        {
+                            continue;
+                        }
+                        for dy in 0..scale
+                        {
+                            for dx in 0..scale
+                            {
+                                let px = left + ((4*n + column)*scale + dx) as f64;
+                                let py = top + (row*scale + dy) as f64;
+                                if px >= 0.0 && py >= 0.0 && (px as usize) < image.width && (py as usize) < image.height
+                                {
+                                    let pixel = &mut image.pixels[py as usize*image.width + px as usize];
+                                    *pixel = pixel.map(|channel| 255 - channel);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    image
+}
+
+// Dashes over the pixels where a ghost cell meets a cell of the grid,
+// found by comparing the cell under each pixel center with the ones right
+// of and below it.
+fn draw_seams(image: &mut Image, dims: (usize, usize), inner_dims: (usize, usize), seams: &Seams, options: &RenderOptions)
+{
+    let half_px = options.cell_px as f64 / 2.0;
+    let row_px = half_px * 3f64.sqrt();
+    let ghost = |px: usize, py: usize| {
+        locate(dims, options.cell_px, (px as f64 + 0.5) / half_px, (py as f64 + 0.5) / row_px)
+            .map(|(coord, _)| seams.is_ghost(inner_dims, coord))
+    };
+    let color = options.grid_lines.unwrap_or([255, 255, 255]);
+    for py in 0..image.height
+    {
+        for px in 0..image.width
+        {
+            if ((px + py) / SEAM_DASH).is_multiple_of(2)
+            {
+                continue;
+            }
+            let here = match ghost(px, py)
+            {
+                Some(here) => here,
+                None => continue
+            };
+            let across = [(px + 1, py), (px, py + 1)].iter()
+                .filter(|&&(x, y)| x < image.width && y < image.height)
+                .any(|&(x, y)| ghost(x, y).is_some_and(|other| other != here));
+            if across
+            {
+                image.pixels[py*image.width + px] = color;
+            }
+        }
+    }
+}
+
+// The edges between hexagons and around them, one pixel wide, found the
+// way seams are.
+fn draw_hex_outlines(image: &mut Image, dims: (usize, usize), color: [u8; 3], options: &RenderOptions)
+{
+    let half_px = options.cell_px as f64 / 2.0;
+    let row_px = half_px * 3f64.sqrt();
+    let membership = hex::membership(dims);
+    let hex = |px: usize, py: usize| {
+        locate(dims, options.cell_px, (px as f64 + 0.5) / half_px, (py as f64 + 0.5) / row_px)
+            .and_then(|(coord, _)| *membership.get(coord).unwrap())
+    };
+    for py in 0..image.height
+    {
+        for px in 0..image.width
+        {
+            let here = hex(px, py);
+            let across = [(px + 1, py), (px, py + 1)].iter()
+                .filter(|&&(x, y)| x < image.width && y < image.height)
+                .any(|&(x, y)| {
+                    let other = hex(x, y);
+                    other != here && (other.is_some() || here.is_some())
+                });
+            if across
+            {
+                image.pixels[py*image.width + px] = color;
+            }
+        }
+    }
+}
+
+pub fn ppm<T, F, P>(grid: &Grid<T>, color: F, options: &RenderOptions, path: P) -> io::Result<()>
+where
+    T: Copy + Debug,
+    F: Fn(&T) -> (u8, u8, u8),
+    P: AsRef<Path>
+{
+    rasterize(grid, color, options).write_ppm(BufWriter::new(File::create(path)?))
+}
+
+pub fn png<T, F, P>(grid: &Grid<T>, color: F, options: &RenderOptions, path: P) -> io::Result<()>
+where
+    T: Copy + Debug,
+    F: Fn(&T) -> (u8, u8, u8),
+    P: AsRef<Path>
+{
+    rasterize(grid, color, options).write_png(BufWriter::new(File::create(path)?))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApngOptions
+{
+    pub render: RenderOptions,
+    // Time each frame is shown.
+    pub delay_ms: u16,
+    // Times the animation is played, 0 for forever.
+    pub loops: u32
+}
+
+impl Default for ApngOptions
+{
+    fn default() -> Self
+    {
+        Self{render: RenderOptions::default(), delay_ms: 100, loops: 0}
+    }
+}
+
+// Writes an animated PNG of `steps` steps, the initial state included, in
+// full color. The first frame is also the image shown by viewers that do
+// not know APNG.
+pub fn write_apng<T, F, C, W>(automata: &mut Automata<T>, rule: F, steps: usize, color: C, options: &ApngOptions, writer: W) -> io::Result<()>
+where
+    T: Copy + Debug + Display,
+    F: Fn(Vec<T>) -> T,
+    C: Fn(&T) -> (u8, u8, u8),
+    W: Write
+{
+    write_apng_filtered(automata, rule, steps, color, options, &FrameFilter::all(), writer)
+}
+
+// The same with only the generations `filter` selects as frames. Their
+// count is only known at the end of the run, so the frames are kept in
+// memory until then; an animation needs at least one.
+pub fn write_apng_filtered<T, F, C, W>(automata: &mut Automata<T>, rule: F, steps: usize, color: C, options: &ApngOptions,
+                                       filter: &FrameFilter<T>, mut writer: W) -> io::Result<()>
+where
+    T: Copy + Debug + Display,
+    F: Fn(Vec<T>) -> T,
+    C: Fn(&T) -> (u8, u8, u8),
+    W: Write
+{
+    let mut image = Image{width: 0, height: 0, pixels: Vec::new()};
+    let mut frames = vec![];
+    for step in 0..=steps
+    {
+        if step > 0
+        {
+            automata.evolve(&rule);
+        }
+        if filter.selects(automata.step(), automata.current())
+        {
+            rasterize_into(automata.current(), &color, &options.render, &mut image);
+            frames.push(image.png_data());
+        }
+    }
+    if frames.is_empty()
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no step was selected for the animation"));
+    }
+
+    writer.write_all(PNG_SIGNATURE)?;
+    write_chunk(&mut writer, b"IHDR", &image.png_header())?;
+    let mut control = Vec::with_capacity(8);
+    control.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+    control.extend_from_slice(&options.loops.to_be_bytes());
+    write_chunk(&mut writer, b"acTL", &control)?;
+
+    // fcTL and fdAT chunks share one sequence.
+    let mut sequence = 0u32;
+    for (frame, png_data) in frames.iter().enumerate()
+    {
+        let mut frame_control = Vec::with_capacity(26);
+        for value in &[sequence, image.width as u32, image.height as u32, 0, 0]
+        {
+            frame_control.extend_from_slice(&value.to_be_bytes());
+        }
+        frame_control.extend_from_slice(&options.delay_ms.to_be_bytes());
+        frame_control.extend_from_slice(&1000u16.to_be_bytes());
+        // Nothing to dispose of, and frames replace the previous one.
+        frame_control.extend_from_slice(&[0, 0]);
+        write_chunk(&mut writer, b"fcTL", &frame_control)?;
+        sequence += 1;
+
+        if frame == 0
+        {
+            write_chunk(&mut writer, b"IDAT", png_data)?;
+        }
+        else
+        {
+            let mut data = sequence.to_be_bytes().to_vec();
+            data.extend_from_slice(png_data);
+            write_chunk(&mut writer, b"fdAT", &data)?;
+            sequence += 1;
+        }
+    }
+    write_chunk(&mut writer, b"IEND", &[])?;
+    writer.flush()
+}
+
+pub fn record_apng<T, F, C, P>(automata: &mut Automata<T>, rule: F, steps: usize, path: P, color: C, options: &ApngOptions) -> io::Result<()>
+where
+    T: Copy + Debug + Display,
+    F: Fn(Vec<T>) -> T,
+    C: Fn(&T) -> (u8, u8, u8),
+    P: AsRef<Path>
+{
+    write_apng(automata, rule, steps, color, options, BufWriter::new(File::create(path)?))
+}
+
+pub fn record_apng_filtered<T, F, C, P>(automata: &mut Automata<T>, rule: F, steps: usize, path: P, color: C, options: &ApngOptions,
+                                        filter: &FrameFilter<T>) -> io::Result<()>
+where
+    T: Copy + Debug + Display,
+    F: Fn(Vec<T>) -> T,
+    C: Fn(&T) -> (u8, u8, u8),
+    P: AsRef<Path>
+{
+    write_apng_filtered(automata, rule, steps, color, options, filter, BufWriter::new(File::create(path)?))
+}
+
+// One image per generation `filter` selects, written to `pattern` with
+// `{step}` replaced by the step of the automaton (5 digits), as PPM when
+// the pattern ends in .ppm and as PNG otherwise. Returns the paths
+// written, in order. With options.incremental, each frame is redrawn from
+// the last where cells changed.
+pub fn export_frames<T, F, C>(automata: &mut Automata<T>, rule: F, steps: usize, pattern: &str, color: C, options: &RenderOptions,
+                              filter: &FrameFilter<T>) -> io::Result<Vec<PathBuf>>
+where
+    T: Copy + Debug + Display,
+    F: Fn(Vec<T>) -> T,
+    C: Fn(&T) -> (u8, u8, u8)
+{
+    let mut image = Image{width: 0, height: 0, pixels: Vec::new()};
+    let mut raster = IncrementalRaster::new();
+    let mut paths = vec![];
+    for step in 0..=steps
+    {
+        if step > 0
+        {
+            automata.evolve(&rule);
+        }
+        if !filter.selects(automata.step(), automata.current())
+        {
+            continue;
+        }
+        let image = if options.incremental
+        {
+            raster.update(automata.current(), &color, options);
+            raster.image()
+        }
+        else
+        {
+            rasterize_into(automata.current(), &color, options, &mut image);
+            &image
+        };
+        let path = PathBuf::from(pattern.replace("{step}", &format!("{:05}", automata.step())));
+        let writer = BufWriter::new(File::create(&path)?);
+        if pattern.ends_with(".ppm")
+        {
+            image.write_ppm(writer)?;
+        }
+        else
+        {
+            image.write_png(writer)?;
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, CellState, Light};
+    use crate::boundary::BoundaryCondition;
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+    const WHITE: [u8; 3] = [255, 255, 255];
+
+    // An up triangle in red next to a down one in blue.
+    fn pair() -> Grid<bool>
+    {
+        Grid::from_fn((2, 1), |(i, _)| i == 0)
+    }
+
+    fn red_or_blue(cell: &bool) -> (u8, u8, u8)
+    {
+        if *cell {(255, 0, 0)} else {(0, 0, 255)}
+    }
+
+    fn options(supersample: u8, grid_lines: Option<[u8; 3]>) -> RenderOptions
+    {
+        RenderOptions{cell_px: 12, supersample, grid_lines, ..RenderOptions::default()}
+    }
+
+    #[test]
+    fn supersampling_blends_the_edges()
+    {
+        let hard = rasterize(&pair(), red_or_blue, &options(1, None));
+        let soft = rasterize(&pair(), red_or_blue, &options(4, None));
+        assert_eq!((hard.width, hard.height), (18, 10));
+        assert_eq!((soft.width, soft.height), (hard.width, hard.height));
+        assert!(hard.pixels.iter().all(|pixel| [RED, BLUE, BACKGROUND].contains(pixel)));
+        // Pixels along the slanted edge mix red and blue.
+        let blended: Vec<usize> = (0..soft.pixels.len()).filter(|&n| soft.pixels[n] != hard.pixels[n]).collect();
+        assert!(!blended.is_empty());
+        assert!(blended.iter().any(|&n| soft.pixels[n][0] > 0 && soft.pixels[n][2] > 0));
+        // Away from the edges, both agree.
+        assert_eq!(soft.pixel(6, 8), RED);
+        assert_eq!(hard.pixel(6, 8), RED);
+        assert_eq!(soft.pixel(12, 1), BLUE);
+    }
+
+    #[test]
+    fn grid_lines_are_anti_aliased_too()
+    {
+        let hard = rasterize(&pair(), red_or_blue, &options(1, Some(WHITE)));
+        let soft = rasterize(&pair(), red_or_blue, &options(4, Some(WHITE)));
+        assert!(hard.pixels.contains(&WHITE));
+        assert!(hard.pixels.iter().all(|pixel| [RED, BLUE, BACKGROUND, WHITE].contains(pixel)));
+        // Partly covered by a line: lighter than the cell, not white.
+        assert!(soft.pixels.iter().any(|&[r, g, b]| r == 255 && g > 0 && g < 255 && b == g));
+    }
+
+    #[test]
+    fn encoders_share_the_raster()
+    {
+        let image = rasterize(&pair(), red_or_blue, &options(4, Some(WHITE)));
+        let mut ppm = vec![];
+        image.write_ppm(&mut ppm).unwrap();
+        let header = format!("P6\n{} {}\n255\n", image.width, image.height);
+        assert!(ppm.starts_with(header.as_bytes()));
+        assert_eq!(&ppm[header.len()..], image.pixels.concat().as_slice());
+
+        let mut png = vec![];
+        image.write_png(&mut png).unwrap();
+        assert!(png.starts_with(PNG_SIGNATURE));
+        let idat = png.windows(4).position(|kind| kind == b"IDAT").unwrap();
+        let length = u32::from_be_bytes([png[idat - 4], png[idat - 3], png[idat - 2], png[idat - 1]]) as usize;
+        assert_eq!(&png[idat + 4..idat + 4 + length], image.png_data().as_slice());
+    }
+
+    // The (kind, data) of every chunk of a PNG, checking their CRCs.
+    fn png_chunks(png: &[u8]) -> Vec<(String, Vec<u8>)>
+    {
+        assert!(png.starts_with(PNG_SIGNATURE));
+        let mut chunks = vec![];
+        let mut rest = &png[8..];
+        while !rest.is_empty()
+        {
+            let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let (body, crc) = rest[4..].split_at(4 + length);
+            assert_eq!(crc[..4], crc32(body).to_be_bytes());
+            chunks.push((String::from_utf8(body[..4].to_vec()).unwrap(), body[4..].to_vec()));
+            rest = &crc[4..];
+        }
+        chunks
+    }
+
+    fn be32(bytes: &[u8]) -> u32
+    {
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    #[test]
+    fn apng_chunks_hold_every_frame()
+    {
+        let mut automata = Automata::new(Grid::from_fn((5, 3), |coord| if coord == (2, 1) {Light::Source(3)} else {Light::Space(0)}));
+        let options = ApngOptions{render: RenderOptions{cell_px: 4, ..RenderOptions::default()}, delay_ms: 40, loops: 2};
+        let mut apng = vec![];
+        write_apng(&mut automata, rules::light_falloff, 3, |cell| (cell.level()*80, 0, 0), &options, &mut apng).unwrap();
+        let chunks = png_chunks(&apng);
+        let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+        assert_eq!(kinds, ["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "fcTL", "fdAT", "fcTL", "fdAT", "IEND"]);
+        // Four frames, the initial state included, played twice.
+        assert_eq!((be32(&chunks[1].1), be32(&chunks[1].1[4..])), (4, 2));
+        let (width, height) = image_size((5, 3), 4);
+        let mut sequence = vec![];
+        for (kind, data) in &chunks
+        {
+            match kind.as_str()
+            {
+                "fcTL" =>
+                {
+                    assert_eq!(data.len(), 26);
+                    assert_eq!((be32(&data[4..]), be32(&data[8..])), (width as u32, height as u32));
+                    assert_eq!(data[20..24], [0, 40, 3, 232]);
+                    sequence.push(be32(data));
+                },
+                "fdAT" => sequence.push(be32(data)),
+                _ => ()
+            }
+        }
+        assert_eq!(sequence, (0..7).collect::<Vec<u32>>());
+
+        // The last frame is the last generation.
+        let mut last = vec![];
+        rasterize(automata.current(), |cell| (cell.level()*80, 0, 0), &options.render).write_png(&mut last).unwrap();
+        assert_eq!(chunks[9].1[4..], png_chunks(&last)[1].1[..]);
+    }
+
+    #[test]
+    fn apng_needs_a_frame()
+    {
+        let mut automata = Automata::new(Grid::new((5, 3), Light::Space(0)));
+        let filter = FrameFilter::all().only_when(|_| false);
+        let written = write_apng_filtered(&mut automata, rules::light_falloff, 3, |_| (0, 0, 0), &ApngOptions::default(), &filter, vec![]);
+        assert_eq!(written.map_err(|error| error.kind()), Err(io::ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn seams_draw_the_ghosts_and_dash_the_edges()
+    {
+        let grid = Grid::from_fn((8, 4), |(i, j)| j == 1 && (i == 0 || i >= 6));
+        let seams = Seams::new(&BoundaryCondition::<bool>::Periodic, 2);
+        let image = rasterize(&grid, red_or_blue, &RenderOptions{seams: Some(seams), ..options(1, None)});
+        assert_eq!((image.width, image.height), image_size((12, 8), 12));
+        // Apart from the dashes, the ghosted grid drawn as is.
+        let plain = rasterize(&seams.ghost_grid(&grid), red_or_blue, &options(1, None));
+        let dashes: Vec<_> = (0..image.pixels.len()).filter(|&n| image.pixels[n] != plain.pixels[n]).collect();
+        assert!(!dashes.is_empty());
+        assert!(dashes.iter().all(|&n| image.pixels[n] == WHITE));
+        // The four seams are dashed, the vertical ones each a cell's width
+        // from the ghosts, the horizontal ones on the edges between rows.
+        let row_px = 6.0*3f64.sqrt();
+        let near_x = |x: usize, seam: usize| x + 12 >= seam && x <= seam + 12;
+        let near_y = |y: usize, row: usize| (y as f64 + 0.5 - row as f64*row_px).abs() <= 1.0;
+        let at: Vec<_> = dashes.iter().map(|&n| (n % image.width, n / image.width)).collect();
+        assert!(at.iter().all(|&(x, y)| near_x(x, 2*6) || near_x(x, 10*6) || near_y(y, 2) || near_y(y, 6)));
+        assert!(at.iter().any(|&(x, _)| near_x(x, 2*6)) && at.iter().any(|&(x, _)| near_x(x, 10*6)));
+        assert!(at.iter().any(|&(_, y)| near_y(y, 2)) && at.iter().any(|&(_, y)| near_y(y, 6)));
+    }
+
+    #[test]
+    fn overlays_outline_and_label()
+    {
+        let grid = Grid::new((6, 3), false);
+        let mut overlay = Overlay::new();
+        overlay.highlight((2, 1), RED).label((2, 1), "8");
+        let options = options(1, None);
+        let plain = rasterize(&grid, red_or_blue, &options);
+        let image = rasterize_overlaid(&grid, red_or_blue, &options, &overlay);
+        assert_eq!((image.width, image.height), (plain.width, plain.height));
+        let changed: Vec<_> = (0..image.pixels.len()).filter(|&n| image.pixels[n] != plain.pixels[n]).collect();
+        assert!(changed.iter().any(|&n| image.pixels[n] == RED));
+        // The label, in the inverse of the blue under it.
+        assert!(changed.iter().any(|&n| image.pixels[n] == [255, 255, 0]));
+        // Everything changed is over the cell.
+        let half_px = options.cell_px as f64 / 2.0;
+        let row_px = half_px * 3f64.sqrt();
+        assert!(changed.iter().all(|&n| {
+            let (px, py) = (n % image.width, n / image.width);
+            locate(grid.dims, options.cell_px, (px as f64 + 0.5) / half_px, (py as f64 + 0.5) / row_px).map(|(coord, _)| coord) == Some((2, 1))
+        }));
+        assert_eq!(rasterize_overlaid(&grid, red_or_blue, &options, &Overlay::new()), plain);
+    }
+
+    fn every_seventh() -> Vec<u64>
+    {
+        (0..=100).step_by(7).collect()
+    }
+
+    // 100 steps, one frame out of 7: steps 0 to 98.
+    #[test]
+    fn exports_keep_the_selected_steps()
+    {
+        let light = || Automata::new(Grid::from_fn((5, 3), |coord| if coord == (2, 1) {Light::Source(3)} else {Light::Space(0)}));
+        let render = RenderOptions{cell_px: 4, ..RenderOptions::default()};
+        let pattern = std::env::temp_dir().join(format!("triangle-automata-{}-decimated-{{step}}.ppm", std::process::id()));
+        let mut automata = light();
+        let paths = export_frames(&mut automata, rules::light_falloff, 100, pattern.to_str().unwrap(), |cell| (cell.level()*80, 0, 0),
+                                  &render, &FrameFilter::every(7)).unwrap();
+        assert_eq!(automata.step(), 100);
+        assert_eq!(paths.len(), 15);
+        for (path, step) in paths.iter().zip(every_seventh())
+        {
+            assert!(path.to_str().unwrap().ends_with(&format!("-decimated-{:05}.ppm", step)));
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let options = ApngOptions{render, ..ApngOptions::default()};
+        let mut apng = vec![];
+        write_apng_filtered(&mut light(), rules::light_falloff, 100, |cell| (cell.level()*80, 0, 0), &options, &FrameFilter::every(7), &mut apng).unwrap();
+        let chunks = png_chunks(&apng);
+        assert_eq!(be32(&chunks[1].1), 15);
+        assert_eq!(chunks.iter().filter(|(kind, _)| kind == "fcTL").count(), 15);
+    }
+
+    // Light from a source, with a source written somewhere else every step,
+    // drawn both in full and incrementally at a few sizes with and without
+    // supersampling and grid lines.
+    #[test]
+    fn incremental_frames_match_full_ones()
+    {
+        let sizes = [(12, 1, None), (7, 3, Some([40, 40, 40])), (5, 2, None), (16, 4, Some(WHITE))];
+        let color = |cell: &Light| {
+            let level = cell.level();
+            (level.wrapping_mul(19), 255 - level.wrapping_mul(7), level)
+        };
+        for &(cell_px, supersample, grid_lines) in &sizes
+        {
+            let options = RenderOptions{cell_px, supersample, grid_lines, incremental: true, ..RenderOptions::default()};
+            let mut grid = Grid::new((23, 11), Light::Space(0));
+            *grid.get_mut((5, 5)).unwrap() = Light::Source(12);
+            let mut automata = Automata::new(grid);
+            automata.set_injector(|step| vec![(((step*7 % 23) as usize, (step*3 % 11) as usize), Light::Source((step % 13) as u8))]);
+            let mut raster = IncrementalRaster::new();
+            for step in 1..=20
+            {
+                automata.evolve(rules::light_falloff);
+                raster.update(automata.current(), color, &options);
+                assert!(raster.image().pixels == rasterize(automata.current(), color, &options).pixels, "{:?}, step {}", options, step);
+            }
+        }
+    }
+
+    #[test]
+    fn only_changed_cells_are_redrawn()
+    {
+        let options = RenderOptions{incremental: true, ..options(1, None)};
+        let mut grid = Grid::new((6, 3), false);
+        let mut raster = IncrementalRaster::new();
+        let whole = raster.update(&grid, red_or_blue, &options);
+        assert_eq!(whole, vec![Rect{x: 0, y: 0, width: raster.image().width, height: raster.image().height}]);
+        assert_eq!(raster.update(&grid, red_or_blue, &options), vec![]);
+        *grid.get_mut((2, 1)).unwrap() = true;
+        *grid.get_mut((3, 1)).unwrap() = true;
+        let rects = raster.update(&grid, red_or_blue, &options);
+        assert_eq!(rects.len(), 1);
+        let rect = rects[0];
+        assert!(rect.width < raster.image().width && rect.height < raster.image().height);
+        assert!(rect.x <= 2*6 && rect.x + rect.width >= 5*6);
+        assert_eq!(raster.image().pixels, rasterize(&grid, red_or_blue, &options).pixels);
+        // New options redraw everything.
+        let larger = RenderOptions{cell_px: 20, ..options};
+        assert_eq!(raster.update(&grid, red_or_blue, &larger).len(), 1);
+        assert_eq!(raster.image().pixels, rasterize(&grid, red_or_blue, &larger).pixels);
+    }
+
+    #[test]
+    fn incremental_exports_write_the_same_files()
+    {
+        let light = || Automata::new(Grid::from_fn((9, 5), |coord| if coord == (4, 2) {Light::Source(6)} else {Light::Space(0)}));
+        let pattern = |name: &str| std::env::temp_dir().join(format!("triangle-automata-{}-{}-{{step}}.ppm", std::process::id(), name));
+        let color = |cell: &Light| (cell.level()*40, 0, 0);
+        let full = RenderOptions{cell_px: 6, supersample: 2, ..RenderOptions::default()};
+        let incremental = RenderOptions{incremental: true, ..full};
+        let a = export_frames(&mut light(), rules::light_falloff, 20, pattern("full").to_str().unwrap(), color, &full, &FrameFilter::every(1)).unwrap();
+        let b = export_frames(&mut light(), rules::light_falloff, 20, pattern("incremental").to_str().unwrap(), color, &incremental,
+                              &FrameFilter::every(1)).unwrap();
+        let read = |paths: &[PathBuf]| -> Vec<Vec<u8>> {
+            paths.iter().map(|path| {
+                let bytes = std::fs::read(path).unwrap();
+                std::fs::remove_file(path).unwrap();
+                bytes
+            }).collect()
+        };
+        let (a, b) = (read(&a), read(&b));
+        assert_eq!(a.len(), 21);
+        assert!(a == b);
+    }
+}
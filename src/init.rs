@@ -0,0 +1,206 @@
+// Initial conditions that are smoother than independent random cells.
+
+use crate::coord::Coord;
+use crate::rng;
+use crate::Grid;
+
+use std::fmt::Debug;
+
+// Value noise: random values on a square lattice of spacing `scale` (in
+// triangle edges), smoothly interpolated and sampled at the centroid of
+// every triangle. Values are in [0, 1) and only depend on the seed.
+pub fn value_noise(dims: (usize, usize), seed: u64, scale: f32) -> Grid<f32>
+{
+    let scale = f64::from(scale).max(1e-6);
+    Grid::from_fn(dims, |coord| {
+        let (x, y) = Coord::from(coord).centroid();
+        sample(seed, x / scale, y / scale) as f32
+    })
+}
+
+fn lattice(seed: u64, x: i64, y: i64) -> f64
+{
+    let key = ((x as u32 as u64) << 32) | y as u32 as u64;
+    (rng::mix(seed, key) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn smoothstep(t: f64) -> f64
+{
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn sample(seed: u64, x: f64, y: f64) -> f64
+{
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (smoothstep(x - x0), smoothstep(y - y0));
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let top = lattice(seed, x0, y0) * (1.0 - tx) + lattice(seed, x0 + 1, y0) * tx;
+    let bottom = lattice(seed, x0, y0 + 1) * (1.0 - tx) + lattice(seed, x0 + 1, y0 + 1) * tx;
+    top * (1.0 - ty) + bottom * ty
+}
+
+// Turns a noise field into two states: `high` where the noise is at
+// least `t`, `low` elsewhere.
+pub fn threshold<T: Copy + Debug>(noise: &Grid<f32>, t: f32, low: T, high: T) -> Grid<T>
+{
+    Grid::from_fn(noise.dims, |coord| {
+        if *noise.get(coord).unwrap() >= t { high } else { low }
+    })
+}
+
+// Why a symmetric grid could not be built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymmetryError
+{
+    // The mirror needs an odd width.
+    EvenWidth(usize),
+    // The horizontal mirror needs an even height.
+    OddHeight(usize),
+    // The half turn needs width + height to be odd.
+    EvenPerimeter((usize, usize)),
+    // The pattern is larger than the fundamental domain.
+    PatternTooLarge{pattern: (usize, usize), domain: (usize, usize)}
+}
+
+// Fills a grid of the given dims from the representative of each cell in
+// the fundamental domain, which sits at the origin: pattern cells have the
+// same orientation as the grid cells they land on.
+fn replicate<T, F>(pattern: &Grid<T>, dims: (usize, usize), domain: (usize, usize), fill: T, representative: F)
+                   -> Result<Grid<T>, SymmetryError>
+where
+    T: Copy + Debug,
+    F: Fn((usize, usize)) -> (usize, usize)
+{
+    if pattern.dims.0 > domain.0 || pattern.dims.1 > domain.1
+    {
+        return Err(SymmetryError::PatternTooLarge{pattern: pattern.dims, domain});
+    }
+    Ok(Grid::from_fn(dims, |coord| *pattern.get(representative(coord)).unwrap_or(&fill)))
+}
+
+// Left half of the grid, mirrored across the vertical axis. The middle
+// column is its own mirror image and belongs to the pattern.
+pub fn mirror_x<T: Copy + Debug>(pattern: &Grid<T>, dims: (usize, usize), fill: T) -> Result<Grid<T>, SymmetryError>
+{
+    let (w, h) = dims;
+    if w.is_multiple_of(2)
+    {
+        return Err(SymmetryError::EvenWidth(w));
+    }
+    replicate(pattern, dims, (w.div_ceil(2), h), fill, |(i, j)| (i.min(w-1-i), j))
+}
+
+// Top left quarter, mirrored across both axes.
+pub fn mirror_xy<T: Copy + Debug>(pattern: &Grid<T>, dims: (usize, usize), fill: T) -> Result<Grid<T>, SymmetryError>
+{
+    let (w, h) = dims;
+    if w.is_multiple_of(2)
+    {
+        return Err(SymmetryError::EvenWidth(w));
+    }
+    if !h.is_multiple_of(2)
+    {
+        return Err(SymmetryError::OddHeight(h));
+    }
+    replicate(pattern, dims, (w.div_ceil(2), h/2), fill, |(i, j)| (i.min(w-1-i), j.min(h-1-j)))
+}
+
+// Top half, turned around the grid center. With an odd height the middle
+// row maps onto itself, so only the left half of its pattern row is used.
+pub fn rotate_180<T: Copy + Debug>(pattern: &Grid<T>, dims: (usize, usize), fill: T) -> Result<Grid<T>, SymmetryError>
+{
+    let (w, h) = dims;
+    if (w + h).is_multiple_of(2)
+    {
+        return Err(SymmetryError::EvenPerimeter(dims));
+    }
+    replicate(pattern, dims, (w, h.div_ceil(2)), fill, |(i, j)| {
+        if 2*j + 1 < h { (i, j) }
+        else if 2*j + 1 > h { (w-1-i, h-1-j) }
+        else { (i.min(w-1-i), j) }
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn noise_only_depends_on_the_seed()
+    {
+        let noise = value_noise((40, 30), 7, 6.0);
+        assert_eq!(noise, value_noise((40, 30), 7, 6.0));
+        assert_ne!(noise, value_noise((40, 30), 8, 6.0));
+        assert!(noise.data.iter().all(|&value| (0.0..1.0).contains(&value)));
+        let high = threshold(&noise, 0.5, 0u8, 1);
+        assert!(high.data.iter().zip(&noise.data).all(|(&state, &value)| (state == 1) == (value >= 0.5)));
+    }
+
+    // Neighboring centroids are 1/sqrt(3) apart and the interpolation
+    // changes by at most 1.5 per lattice spacing along each axis, so
+    // neighbors differ by less than 2/scale.
+    #[test]
+    fn neighbors_have_close_noise()
+    {
+        for &scale in &[2.0, 5.0, 12.0, 40.0]
+        {
+            let noise = value_noise((60, 40), 3, scale);
+            let mut largest = 0f32;
+            for j in 0..40
+            {
+                for i in 0..60
+                {
+                    let here = *noise.get((i, j)).unwrap();
+                    for &ngh in &noise.neighborhood((i, j))[1..]
+                    {
+                        largest = largest.max((here - ngh).abs());
+                    }
+                }
+            }
+            assert!(largest < 2.0 / scale, "neighbors differ by {} at scale {}", largest, scale);
+        }
+    }
+
+    fn numbered(dims: (usize, usize)) -> Grid<usize>
+    {
+        Grid::from_fn(dims, |(i, j)| 1 + i + 100*j)
+    }
+
+    #[test]
+    fn symmetric_grids_are_invariant()
+    {
+        let grid = mirror_x(&numbered((4, 5)), (7, 5), 0).unwrap();
+        assert_eq!(grid.mirror_x(), Some(grid.clone()));
+        assert_eq!(grid.get((5, 2)), Some(&202));
+
+        let grid = mirror_xy(&numbered((3, 2)), (5, 4), 0).unwrap();
+        assert_eq!(grid.mirror_x(), Some(grid.clone()));
+        assert_eq!(grid.flip_y(), Some(grid.clone()));
+
+        let cases: [(usize, usize); 3] = [(6, 5), (7, 4), (4, 3)];
+        for &dims in &cases
+        {
+            let pattern = numbered((dims.0, dims.1.div_ceil(2)));
+            let grid = rotate_180(&pattern, dims, 0).unwrap();
+            assert_eq!(grid.rotate_180(), Some(grid.clone()), "for {:?}", dims);
+        }
+        // A small pattern is padded with the fill.
+        let grid = mirror_x(&numbered((1, 1)), (5, 3), 0).unwrap();
+        assert_eq!(grid.data.iter().filter(|&&cell| cell != 0).count(), 2);
+    }
+
+    #[test]
+    fn impossible_symmetries_are_refused()
+    {
+        let pattern = numbered((2, 2));
+        assert_eq!(mirror_x(&pattern, (6, 4), 0), Err(SymmetryError::EvenWidth(6)));
+        assert_eq!(mirror_xy(&pattern, (5, 3), 0), Err(SymmetryError::OddHeight(3)));
+        assert_eq!(rotate_180(&pattern, (5, 3), 0), Err(SymmetryError::EvenPerimeter((5, 3))));
+        assert_eq!(mirror_x(&numbered((4, 2)), (5, 2), 0), Err(SymmetryError::PatternTooLarge{pattern: (4, 2), domain: (3, 2)}));
+        // The grid transforms refuse the same dims.
+        assert_eq!(numbered((6, 4)).mirror_x(), None);
+        assert_eq!(numbered((5, 3)).flip_y(), None);
+        assert_eq!(numbered((5, 3)).rotate_180(), None);
+    }
+}
@@ -0,0 +1,298 @@
+// Light and heat feeding each other. Light spreads with light_falloff and
+// heats the cells it lights, in proportion to their level; heat spreads
+// with heat_diffusion and leaks away. A cell whose heat goes past the
+// ignition threshold burns: it turns into a source for a few steps, using
+// up its heat, and lights (and so heats) its neighbors in turn.
+
+use crate::{Automata, CellState, Grid, Light, Rule};
+use crate::render::Glyph;
+use crate::rules;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lantern
+{
+    pub light: Light,
+    pub heat: f32,
+    // Steps left to burn, 0 for cells not on fire (sources included).
+    pub burning: u8
+}
+
+impl Lantern
+{
+    pub fn space() -> Self
+    {
+        Self{light: Light::Space(0), heat: 0.0, burning: 0}
+    }
+
+    pub fn source(level: u8) -> Self
+    {
+        Self{light: Light::Source(level), heat: 0.0, burning: 0}
+    }
+}
+
+impl fmt::Display for Lantern
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}/{:.1}", self.light, self.heat)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LanternParams
+{
+    // Heat a cell gains per step and level of its light.
+    pub coupling: f32,
+    // The relaxation of heat_diffusion.
+    pub diffusivity: f32,
+    // Fraction of its heat a cell loses every step.
+    pub loss: f32,
+    // Heat past which a cell catches fire.
+    pub threshold: f32,
+    // Level of the light of a burning cell, and the steps it burns.
+    pub flame: u8,
+    pub burn: u8
+}
+
+impl Default for LanternParams
+{
+    fn default() -> Self
+    {
+        Self{coupling: 0.05, diffusivity: 0.5, loss: 0.1, threshold: 3.5, flame: 10, burn: 6}
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LanternRule
+{
+    pub params: LanternParams
+}
+
+impl Rule<Lantern> for LanternRule
+{
+    fn apply(&self, ngh: Vec<Lantern>) -> Lantern
+    {
+        let params = &self.params;
+        let cell = ngh[0];
+        let diffused = rules::heat_diffusion(params.diffusivity)(ngh.iter().map(|ncel| ncel.heat).collect());
+        let heat = diffused * (1.0 - params.loss) + params.coupling * f32::from(cell.light.level());
+        if cell.burning > 0
+        {
+            // Burnt out, the flame is left to fade like any other light.
+            let light = if cell.burning > 1 { cell.light } else { Light::Space(cell.light.level()) };
+            return Lantern{light, heat, burning: cell.burning - 1};
+        }
+        if !cell.light.is_pinned() && heat > params.threshold
+        {
+            return Lantern{light: Light::Source(params.flame), heat: 0.0, burning: params.burn};
+        }
+        let light = rules::light_falloff(ngh.iter().map(|ncel| ncel.light).collect());
+        Lantern{light, heat, burning: 0}
+    }
+
+    fn name(&self) -> Option<&str>
+    {
+        Some("lantern")
+    }
+}
+
+const GUTTER: &str = " | ";
+
+// Heat in tenths of the threshold, '#' on burning cells.
+fn heat_glyph(cell: &Lantern, threshold: f32) -> char
+{
+    if cell.burning > 0
+    {
+        return '#';
+    }
+    match (cell.heat / threshold * 10.0) as u32
+    {
+        0 => ' ',
+        tenths => std::char::from_digit(tenths.min(9), 10).unwrap()
+    }
+}
+
+// The light left and the heat right, compact, under the step.
+pub fn panels(grid: &Grid<Lantern>, step: u64, threshold: f32) -> String
+{
+    let mut out = format!("step {}: light{}heat\n", step, " ".repeat(grid.dims.0.saturating_sub(5)));
+    for j in 0..grid.dims.1
+    {
+        let light: String = (0..grid.dims.0).map(|i| grid.get((i, j)).unwrap().light.glyph()).collect();
+        let heat: String = (0..grid.dims.0).map(|i| heat_glyph(grid.get((i, j)).unwrap(), threshold)).collect();
+        out.push_str(&[light, heat].join(GUTTER));
+        out.push('\n');
+    }
+    out
+}
+
+// A difference with the rules run apart, or a cell that did not behave at
+// the threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch
+{
+    pub step: u64,
+    pub coord: (usize, usize),
+    pub message: String
+}
+
+impl fmt::Display for Mismatch
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "step {}, cell {:?}: {}", self.step, self.coord, self.message)
+    }
+}
+
+// Without coupling nor loss, and a threshold out of reach, the light and
+// the heat of `start` follow light_falloff and heat_diffusion exactly, each
+// run on its own field, for `steps` steps.
+pub fn check_uncoupled(start: &Grid<Lantern>, params: LanternParams, steps: u64) -> Result<(), Mismatch>
+{
+    let rule = LanternRule{params: LanternParams{coupling: 0.0, loss: 0.0, threshold: f32::INFINITY, ..params}};
+    let mut both = Automata::new(start.clone());
+    let mut light = Automata::from_fn(start.dims, |coord| start.get(coord).unwrap().light);
+    let mut heat = Automata::from_fn(start.dims, |coord| start.get(coord).unwrap().heat);
+    let diffusion = rules::heat_diffusion(params.diffusivity);
+    for step in 1..=steps
+    {
+        both.evolve(|ngh| rule.apply(ngh));
+        light.evolve(rules::light_falloff);
+        heat.evolve(&diffusion);
+        let (light, heat) = (&light.current().data, &heat.current().data);
+        for (k, cell) in both.current().data.iter().enumerate()
+        {
+            if cell.light != light[k] || cell.heat.to_bits() != heat[k].to_bits()
+            {
+                let coord = (k % start.dims.0, k / start.dims.0);
+                let message = format!("{} apart from {}/{}", cell, light[k], heat[k]);
+                return Err(Mismatch{step, coord, message});
+            }
+        }
+    }
+    Ok(())
+}
+
+// A dark cell among dark neighbors, all at the same heat: it catches fire
+// once that heat is past the threshold, and only then.
+pub fn check_ignition(params: LanternParams) -> Result<(), Mismatch>
+{
+    let rule = LanternRule{params: LanternParams{loss: 0.0, ..params}};
+    let at = |heat: f32| {
+        let cell = Lantern{heat, ..Lantern::space()};
+        rule.apply(vec![cell; 4])
+    };
+    let below = at(params.threshold);
+    if below.burning != 0
+    {
+        return Err(Mismatch{step: 1, coord: (0, 0), message: format!("caught fire at the threshold, {}", params.threshold)});
+    }
+    let above = at(params.threshold + params.threshold.abs().max(1.0) * 1e-3);
+    if above.burning != params.burn || above.light != Light::Source(params.flame) || above.heat != 0.0
+    {
+        return Err(Mismatch{step: 1, coord: (0, 0), message: format!("did not catch fire past the threshold, {} instead", above)});
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn lit() -> Automata<Lantern>
+    {
+        let mut grid = Grid::new((36, 12), Lantern::space());
+        *grid.get_mut((8, 6)).unwrap() = Lantern::source(12);
+        Automata::new(grid)
+    }
+
+    #[test]
+    fn cells_ignite_past_the_threshold_only()
+    {
+        for threshold in [0.5, 3.5, 40.0]
+        {
+            let params = LanternParams{threshold, ..LanternParams::default()};
+            assert_eq!(check_ignition(params), Ok(()));
+            let rule = LanternRule{params: LanternParams{loss: 0.0, ..params}};
+            let at = |heat: f32| rule.apply(vec![Lantern{heat, ..Lantern::space()}; 4]);
+            assert_eq!(at(threshold * 0.99).burning, 0);
+            assert_eq!(at(threshold).burning, 0);
+            assert_eq!(at(threshold * 1.01), Lantern{light: Light::Source(10), heat: 0.0, burning: 6});
+            // Sources do not catch fire.
+            let source = Lantern{heat: threshold * 2.0, ..Lantern::source(3)};
+            assert_eq!(rule.apply(vec![source; 4]).burning, 0);
+        }
+    }
+
+    #[test]
+    fn flames_burn_out_and_fade()
+    {
+        let rule = LanternRule{params: LanternParams::default()};
+        let mut cell = Lantern{light: Light::Source(10), heat: 0.0, burning: 2};
+        cell = rule.apply(vec![cell]);
+        assert_eq!((cell.light, cell.burning), (Light::Source(10), 1));
+        cell = rule.apply(vec![cell]);
+        assert_eq!((cell.light, cell.burning), (Light::Space(10), 0));
+        // Then it fades a level a step, as light_falloff has it.
+        cell = rule.apply(vec![cell, Lantern::space()]);
+        assert_eq!((cell.light, cell.burning), (Light::Space(9), 0));
+    }
+
+    #[test]
+    fn uncoupled_fields_evolve_apart()
+    {
+        let warm = Grid::from_fn((36, 12), |(i, j)| Lantern{heat: ((i*7 + j*3) % 10) as f32, ..*lit().current().get((i, j)).unwrap()});
+        for diffusivity in [0.0, 0.3, 0.5, 1.0]
+        {
+            assert_eq!(check_uncoupled(&warm, LanternParams{diffusivity, ..LanternParams::default()}, 50), Ok(()));
+        }
+        // Coupled, the light heats the cells it lights.
+        let rule = LanternRule{params: LanternParams{loss: 0.0, threshold: f32::INFINITY, ..LanternParams::default()}};
+        let mut automata = lit();
+        automata.evolve(|ngh| rule.apply(ngh));
+        automata.evolve(|ngh| rule.apply(ngh));
+        assert!(automata.current().get((7, 6)).unwrap().heat > 0.0);
+        assert_eq!(automata.current().get((30, 6)).unwrap().heat, 0.0);
+    }
+
+    #[test]
+    fn the_heat_sets_the_dark_on_fire()
+    {
+        let rule = LanternRule{params: LanternParams::default()};
+        let mut automata = lit();
+        let mut burnt = 0;
+        for _ in 0..40
+        {
+            automata.evolve(|ngh| rule.apply(ngh));
+            burnt += automata.current().data.iter().filter(|cell| cell.burning > 0).count();
+        }
+        assert!(burnt > 0);
+        // Too high a threshold, and nothing ever burns.
+        let rule = LanternRule{params: LanternParams{threshold: 1000.0, ..LanternParams::default()}};
+        let mut automata = lit();
+        for _ in 0..40
+        {
+            automata.evolve(|ngh| rule.apply(ngh));
+            assert!(automata.current().data.iter().all(|cell| cell.burning == 0));
+        }
+    }
+
+    #[test]
+    fn two_panels()
+    {
+        let mut grid = Grid::new((6, 2), Lantern::space());
+        *grid.get_mut((0, 0)).unwrap() = Lantern{heat: 2.0, ..Lantern::source(9)};
+        *grid.get_mut((3, 1)).unwrap() = Lantern{light: Light::Source(10), heat: 0.0, burning: 3};
+        *grid.get_mut((5, 1)).unwrap() = Lantern{heat: 100.0, ..Lantern::space()};
+        let text = panels(&grid, 7, 4.0);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "step 7: light heat");
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].ends_with(" | 5     "));
+        assert!(lines[2].ends_with(" |    # 9"));
+        assert_eq!(lines[1].find(GUTTER), Some(6));
+    }
+}
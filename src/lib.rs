@@ -0,0 +1,82 @@
+// Cellular automata on a grid of triangles: the grid and its automaton,
+// the rules and what runs, draws, saves and checks them. The binary is
+// the command line of src/cli.rs over this crate.
+
+pub mod age;
+pub mod analysis;
+pub mod automata;
+pub mod batch;
+pub mod boundary;
+pub mod budget;
+pub mod cell_rules;
+pub mod checkpoint;
+pub mod codec;
+pub mod color;
+pub mod compare;
+pub mod convergence;
+pub mod coord;
+pub mod csv;
+pub mod edit;
+pub mod error;
+pub mod events;
+pub mod flux;
+pub mod framelog;
+pub mod gallery;
+pub mod geometry;
+pub mod graph;
+pub mod grid;
+pub mod hex;
+pub mod hotspot;
+pub mod image;
+pub mod init;
+pub mod lantern;
+pub mod light;
+pub mod machine;
+pub mod memory;
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+pub mod mmap;
+pub mod modulation;
+pub mod neighborhood;
+#[cfg(feature = "net")]
+pub mod net;
+pub mod overlay;
+pub mod palette;
+pub mod pattern;
+pub mod plots;
+pub mod provenance;
+pub mod refraction;
+pub mod registry;
+pub mod regression;
+pub mod render;
+pub mod repl;
+pub mod report;
+pub mod rhombus;
+pub mod rle;
+pub mod rng;
+pub mod rows;
+pub mod rules;
+pub mod run;
+pub mod script;
+pub mod scroll;
+pub mod seam;
+pub mod search;
+pub mod session;
+pub mod simulation;
+#[cfg(test)]
+mod snapshot;
+pub mod sources;
+pub mod stack;
+pub mod stats;
+pub mod stochastic;
+pub mod sweep;
+pub mod timeline;
+pub mod validate;
+pub mod vertex;
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(feature = "ws")]
+pub mod ws;
+
+pub use automata::{Automata, AutomataBuilder, BuildError, Injector, OutOfBounds, Rule, RuleError, SourceProgram, StepSummary};
+pub use grid::{neighbor_coords, DimMismatch, Grid, GridAccess, Slot};
+pub use light::{CellState, Light};
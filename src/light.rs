@@ -0,0 +1,254 @@
+// The light of the demos, and the levelled states the light rules work on.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Light
+{
+    Source(u8),
+    Space(u8)
+}
+
+// Reads the Debug form, "Source(10)" or "Space(0)".
+impl std::str::FromStr for Light
+{
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err>
+    {
+        let parse = |level: &str| level.trim().parse::<u8>()
+            .map_err(|_| format!("invalid light level '{}'", level));
+        let text = text.trim();
+        if let Some(level) = text.strip_prefix("Source(").and_then(|rest| rest.strip_suffix(')'))
+        {
+            Ok(Light::Source(parse(level)?))
+        }
+        else if let Some(level) = text.strip_prefix("Space(").and_then(|rest| rest.strip_suffix(')'))
+        {
+            Ok(Light::Space(parse(level)?))
+        }
+        else
+        {
+            Err(format!("invalid light '{}' (Source(n) or Space(n))", text))
+        }
+    }
+}
+
+// States carrying a level (of light, heat...), some of them pinned: kept as
+// they are by the rules, like light sources. Changing the level keeps
+// everything else about the state, in particular whether it is pinned. The
+// light rules, shading and glyphs work on any such type.
+pub trait CellState: Copy
+{
+    fn level(&self) -> u8;
+    fn with_level(&self, level: u8) -> Self;
+    // The free state at level 0, which grids are usually filled with.
+    fn default_free() -> Self;
+
+    fn is_pinned(&self) -> bool
+    {
+        false
+    }
+
+    fn brighter(&self, n: u8) -> Self
+    {
+        self.with_level(self.level().saturating_add(n))
+    }
+
+    fn dimmer(&self, n: u8) -> Self
+    {
+        self.with_level(self.level().saturating_sub(n))
+    }
+}
+
+impl CellState for Light
+{
+    fn level(&self) -> u8
+    {
+        match self
+        {
+            Light::Source(level) | Light::Space(level) => *level
+        }
+    }
+
+    fn with_level(&self, level: u8) -> Self
+    {
+        match self
+        {
+            Light::Source(_) => Light::Source(level),
+            Light::Space(_) => Light::Space(level)
+        }
+    }
+
+    fn default_free() -> Self
+    {
+        Light::Space(0)
+    }
+
+    fn is_pinned(&self) -> bool
+    {
+        matches!(self, Light::Source(_))
+    }
+}
+
+// Saturating, like CellState::brighter and CellState::dimmer.
+impl std::ops::Add<u8> for Light
+{
+    type Output = Light;
+
+    fn add(self, n: u8) -> Light
+    {
+        self.brighter(n)
+    }
+}
+
+impl std::ops::Sub<u8> for Light
+{
+    type Output = Light;
+
+    fn sub(self, n: u8) -> Light
+    {
+        self.dimmer(n)
+    }
+}
+
+impl std::fmt::Display for Light
+{
+    fn fmt(&self,  f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        let intensity = self.level();
+        if intensity == 0
+        {
+            write!(f, "   ")            
+        }
+        else
+        {
+            write!(f, "{:^3}", intensity)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::rules;
+
+    #[test]
+    fn levels_saturate()
+    {
+        assert_eq!(Light::Space(250) + 10, Light::Space(255));
+        assert_eq!(Light::Space(3) - 10, Light::Space(0));
+        assert_eq!(Light::Source(255).brighter(1), Light::Source(255));
+        assert_eq!(Light::Source(0).dimmer(1), Light::Source(0));
+        assert_eq!(Light::Space(7).with_level(2), Light::Space(2));
+    }
+
+    #[test]
+    fn sources_stay_sources()
+    {
+        for source in &[Light::Source(0), Light::Source(9), Light::Source(255)]
+        {
+            assert!((*source + 200).is_pinned());
+            assert!((*source - 200).is_pinned());
+            assert!(source.with_level(4).is_pinned());
+        }
+        assert!(!(Light::Space(0) + 255).is_pinned());
+        // The rules keep sources as they are and see only their level.
+        assert_eq!(rules::light_falloff(vec![Light::Source(3), Light::Space(9)]), Light::Source(3));
+        assert_eq!(rules::light_falloff(vec![Light::Space(0), Light::Source(9), Light::Space(2)]), Light::Space(8));
+    }
+
+    #[test]
+    fn parses_the_debug_form()
+    {
+        for light in &[Light::Source(10), Light::Space(0)]
+        {
+            assert_eq!(format!("{:?}", light).parse::<Light>(), Ok(*light));
+        }
+        assert!("Source(256)".parse::<Light>().is_err());
+        assert!("Lamp(1)".parse::<Light>().is_err());
+    }
+
+    // Any levelled type of the same shape as Light.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Heat
+    {
+        Plate(u8),
+        Air(u8)
+    }
+
+    impl CellState for Heat
+    {
+        fn level(&self) -> u8
+        {
+            match self
+            {
+                Heat::Plate(level) | Heat::Air(level) => *level
+            }
+        }
+
+        fn with_level(&self, level: u8) -> Self
+        {
+            match self
+            {
+                Heat::Plate(_) => Heat::Plate(level),
+                Heat::Air(_) => Heat::Air(level)
+            }
+        }
+
+        fn default_free() -> Self
+        {
+            Heat::Air(0)
+        }
+
+        fn is_pinned(&self) -> bool
+        {
+            matches!(self, Heat::Plate(_))
+        }
+    }
+
+    impl std::fmt::Display for Heat
+    {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+        {
+            write!(f, "{:^3}", self.level())
+        }
+    }
+
+    fn as_light(heat: &Heat) -> Light
+    {
+        match *heat
+        {
+            Heat::Plate(level) => Light::Source(level),
+            Heat::Air(level) => Light::Space(level)
+        }
+    }
+
+    #[test]
+    fn other_cell_types_get_the_light_rules()
+    {
+        use crate::{render, Automata, Grid};
+
+        let start = Grid::from_fn((13, 7), |coord| match coord
+        {
+            (3, 3) => Heat::Plate(9),
+            (9, 2) => Heat::Plate(5),
+            _ => Heat::default_free()
+        });
+        let light = Grid::from_fn((13, 7), |coord| as_light(start.get(coord).unwrap()));
+        let (mut heat, mut lit) = (Automata::new(start.clone()), Automata::new(light.clone()));
+        for step in 0..12
+        {
+            match step % 3
+            {
+                0 => { heat.evolve(rules::light_falloff); lit.evolve(rules::light_falloff); },
+                1 => { heat.evolve(rules::light_additive); lit.evolve(rules::light_additive); },
+                _ => { heat.evolve(rules::light_decay(2)); lit.evolve(rules::light_decay(2)); }
+            }
+            assert_eq!(&Grid::from_fn((13, 7), |coord| as_light(heat.get(coord).unwrap())), lit.current());
+        }
+        assert_eq!(heat.get((3, 3)), Some(&Heat::Plate(9)));
+        assert_eq!(render::render_compact(heat.current()), render::render_compact(lit.current()));
+        assert_eq!(Heat::Air(250).brighter(10), Heat::Air(255));
+        assert_eq!(Heat::Plate(3).dimmer(5), Heat::Plate(0));
+    }
+}
@@ -0,0 +1,507 @@
+// Rules given as state machines rather than code, for protocol-like
+// automata: named states, numbered in the order they are listed (the
+// first one filling empty grids), and for every state the transitions it
+// may take, tried in order. A transition is taken always, or when the
+// number of neighbors in some state is one of a list; a cell taking none
+// keeps its state. Cells are the u8 ids of the states.
+//
+// Machines are built in code with MachineBuilder or read from a small
+// subset of TOML:
+//
+//     states = ["empty", "head", "tail", "wire"]
+//     # Optional, one color per state; a palette's otherwise.
+//     colors = ["#000", "#4af", "#f62", "#cb4"]
+//
+//     [[transition]]
+//     from = "wire"
+//     to = "head"
+//     count = "head"
+//     in = [1, 2]
+
+use crate::Rule;
+use crate::palette::{self, Palette, PaletteError};
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+// Neighbor counts are bits of a u64.
+const MAX_COUNT: u8 = 63;
+
+#[derive(Debug)]
+pub enum MachineError
+{
+    NoStates,
+    // At most 256, the ids being u8.
+    TooManyStates(usize),
+    DuplicateState(String),
+    UnknownState(String),
+    BadCount(u32),
+    ColorCount{colors: usize, states: usize},
+    Colors(PaletteError),
+    Syntax{line: usize, message: String},
+    Io(io::Error)
+}
+
+impl fmt::Display for MachineError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            MachineError::NoStates => write!(f, "a state machine needs at least one state"),
+            MachineError::TooManyStates(count) => write!(f, "{} states, at most 256 are supported", count),
+            MachineError::DuplicateState(name) => write!(f, "state '{}' is listed twice", name),
+            MachineError::UnknownState(name) => write!(f, "unknown state '{}'", name),
+            MachineError::BadCount(count) => write!(f, "neighbor count {} is out of range (0 to {})", count, MAX_COUNT),
+            MachineError::ColorCount{colors, states} => write!(f, "{} colors for {} states", colors, states),
+            MachineError::Colors(error) => write!(f, "colors: {}", error),
+            MachineError::Syntax{line, message} => write!(f, "line {}: {}", line, message),
+            MachineError::Io(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+impl From<io::Error> for MachineError
+{
+    fn from(error: io::Error) -> Self
+    {
+        MachineError::Io(error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Transition
+{
+    from: String,
+    // The state counted and the counts that allow the transition.
+    when: Option<(String, Vec<u32>)>,
+    to: String
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MachineBuilder
+{
+    states: Vec<String>,
+    colors: Option<Vec<(u8, u8, u8)>>,
+    transitions: Vec<Transition>
+}
+
+impl MachineBuilder
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    pub fn state(mut self, name: &str) -> Self
+    {
+        self.states.push(name.to_string());
+        self
+    }
+
+    // One per state, in order.
+    pub fn colors(mut self, colors: &[(u8, u8, u8)]) -> Self
+    {
+        self.colors = Some(colors.to_vec());
+        self
+    }
+
+    pub fn always(mut self, from: &str, to: &str) -> Self
+    {
+        self.transitions.push(Transition{from: from.to_string(), when: None, to: to.to_string()});
+        self
+    }
+
+    // From `from` to `to` when the count of neighbors in state `counted`
+    // is one of `counts`.
+    pub fn when_count(mut self, from: &str, counted: &str, counts: &[u32], to: &str) -> Self
+    {
+        let when = Some((counted.to_string(), counts.to_vec()));
+        self.transitions.push(Transition{from: from.to_string(), when, to: to.to_string()});
+        self
+    }
+
+    pub fn build(self) -> Result<StateMachineRule, MachineError>
+    {
+        if self.states.is_empty()
+        {
+            return Err(MachineError::NoStates);
+        }
+        if self.states.len() > 256
+        {
+            return Err(MachineError::TooManyStates(self.states.len()));
+        }
+        if let Some((k, _)) = self.states.iter().enumerate().find(|(k, name)| self.states[..*k].contains(name))
+        {
+            return Err(MachineError::DuplicateState(self.states[k].clone()));
+        }
+        let id = |name: &str| self.states.iter().position(|state| state == name)
+            .map(|k| k as u8)
+            .ok_or_else(|| MachineError::UnknownState(name.to_string()));
+        let mut table = vec![vec![]; self.states.len()];
+        for transition in &self.transitions
+        {
+            let guard = match &transition.when
+            {
+                None => None,
+                Some((counted, counts)) =>
+                {
+                    let mut mask = 0u64;
+                    for &count in counts
+                    {
+                        if count > u32::from(MAX_COUNT)
+                        {
+                            return Err(MachineError::BadCount(count));
+                        }
+                        mask |= 1 << count;
+                    }
+                    Some((id(counted)?, mask))
+                }
+            };
+            table[usize::from(id(&transition.from)?)].push(Compiled{guard, to: id(&transition.to)?});
+        }
+        let colors = match self.colors
+        {
+            Some(colors) if colors.len() != self.states.len() =>
+                return Err(MachineError::ColorCount{colors: colors.len(), states: self.states.len()}),
+            Some(colors) => colors,
+            None =>
+            {
+                let palette = palette::get("okabe-ito").expect("okabe-ito is built in");
+                (0..self.states.len()).map(|k| if k == 0 { (0, 0, 0) } else { palette.index(k - 1) }).collect()
+            }
+        };
+        Ok(StateMachineRule{names: self.states, colors, table})
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Compiled
+{
+    // The id counted, and a bit per count allowing the transition.
+    guard: Option<(u8, u64)>,
+    to: u8
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateMachineRule
+{
+    names: Vec<String>,
+    colors: Vec<(u8, u8, u8)>,
+    // The transitions of every state, by id.
+    table: Vec<Vec<Compiled>>
+}
+
+impl StateMachineRule
+{
+    pub fn parse_toml(text: &str) -> Result<Self, MachineError>
+    {
+        let syntax = |line: usize, message: &str| MachineError::Syntax{line, message: message.to_string()};
+        let mut builder = MachineBuilder::new();
+        // The transition being read: its line, from, to, count and in.
+        type Partial = (usize, Option<String>, Option<String>, Option<String>, Option<Vec<u32>>);
+        let mut partial: Option<Partial> = None;
+        let finish = |builder: MachineBuilder, partial: Option<Partial>| match partial
+        {
+            None => Ok(builder),
+            Some((_, Some(from), Some(to), None, None)) => Ok(builder.always(&from, &to)),
+            Some((_, Some(from), Some(to), Some(counted), Some(counts))) => Ok(builder.when_count(&from, &counted, &counts, &to)),
+            Some((line, ..)) => Err(syntax(line, "a transition needs from and to, and in along with count"))
+        };
+        let mut lines = text.lines().enumerate();
+        while let Some((n, line)) = lines.next()
+        {
+            let line = palette::strip_comment(line).trim();
+            if line.is_empty()
+            {
+                continue;
+            }
+            if line == "[[transition]]"
+            {
+                builder = finish(builder, partial.take())?;
+                partial = Some((n + 1, None, None, None, None));
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| syntax(n + 1, "expected 'key = value' or [[transition]]"))?;
+            let mut value = value.trim().to_string();
+            if value.starts_with('[')
+            {
+                // Arrays may go on over several lines.
+                while !value.ends_with(']')
+                {
+                    let (_, more) = lines.next().ok_or_else(|| syntax(n + 1, "unclosed array"))?;
+                    value.push_str(palette::strip_comment(more).trim());
+                }
+            }
+            let string = |value: &str| palette::unquote(value).ok_or_else(|| syntax(n + 1, "expected a quoted string"));
+            let items = |value: &str| value.strip_prefix('[').and_then(|rest| rest.strip_suffix(']'))
+                .map(|items| items.split(',').map(str::trim).filter(|item| !item.is_empty()).map(str::to_string).collect::<Vec<_>>())
+                .ok_or_else(|| syntax(n + 1, "expected an array"));
+            match (partial.as_mut(), key.trim())
+            {
+                (None, "states") =>
+                {
+                    for item in items(&value)?
+                    {
+                        builder = builder.state(&string(&item)?);
+                    }
+                },
+                (None, "colors") =>
+                {
+                    let hex = items(&value)?.iter().map(|item| string(item)).collect::<Result<Vec<_>, _>>()?;
+                    let hex: Vec<&str> = hex.iter().map(String::as_str).collect();
+                    builder = builder.colors(&Palette::from_hex(&hex).map_err(MachineError::Colors)?.colors);
+                },
+                (Some(partial), "from") => partial.1 = Some(string(&value)?),
+                (Some(partial), "to") => partial.2 = Some(string(&value)?),
+                (Some(partial), "count") => partial.3 = Some(string(&value)?),
+                (Some(partial), "in") =>
+                {
+                    let counts = items(&value)?.iter()
+                        .map(|item| item.parse().map_err(|_| syntax(n + 1, "expected an array of counts")))
+                        .collect::<Result<Vec<u32>, _>>()?;
+                    partial.4 = Some(counts);
+                },
+                (_, other) => return Err(syntax(n + 1, &format!("unknown key '{}'", other)))
+            }
+        }
+        finish(builder, partial)?.build()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MachineError>
+    {
+        Self::parse_toml(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn states(&self) -> &[String]
+    {
+        &self.names
+    }
+
+    pub fn id(&self, name: &str) -> Option<u8>
+    {
+        self.names.iter().position(|state| state == name).map(|k| k as u8)
+    }
+
+    // The next state of a cell of state `cell`; ids out of the machine
+    // stay as they are.
+    pub fn next(&self, cell: u8, neighbors: &[u8]) -> u8
+    {
+        let transitions = match self.table.get(usize::from(cell))
+        {
+            Some(transitions) => transitions,
+            None => return cell
+        };
+        for transition in transitions
+        {
+            match transition.guard
+            {
+                None => return transition.to,
+                Some((counted, mask)) =>
+                {
+                    let count = neighbors.iter().filter(|&&state| state == counted).count().min(usize::from(MAX_COUNT));
+                    if mask & (1 << count) != 0
+                    {
+                        return transition.to;
+                    }
+                }
+            }
+        }
+        cell
+    }
+
+    // The first letter of the state's name, blank for the first state.
+    pub fn glyph(&self, cell: u8) -> char
+    {
+        match usize::from(cell)
+        {
+            0 => ' ',
+            k => self.names.get(k).and_then(|name| name.chars().next()).unwrap_or('?')
+        }
+    }
+
+    // Gray for ids out of the machine.
+    pub fn color(&self, cell: u8) -> (u8, u8, u8)
+    {
+        self.colors.get(usize::from(cell)).copied().unwrap_or((128, 128, 128))
+    }
+}
+
+impl Rule<u8> for StateMachineRule
+{
+    fn apply(&self, ngh: Vec<u8>) -> u8
+    {
+        self.next(ngh[0], &ngh[1..])
+    }
+}
+
+// Wireworld: electron heads become tails, tails become wire again, and
+// wire carrying 1 or 2 heads next to it becomes a head. On triangles a
+// wire cell has at most 3 neighbors, so signals run along rows of wire and
+// split where wires fork.
+pub const WIREWORLD: &str = r##"states = ["empty", "head", "tail", "wire"]
+colors = ["#000000", "#44aaff", "#ff6622", "#ccbb44"]
+
+[[transition]]
+from = "head"
+to = "tail"
+
+[[transition]]
+from = "tail"
+to = "wire"
+
+[[transition]]
+from = "wire"
+to = "head"
+count = "head"
+in = [1, 2]
+"##;
+
+pub fn wireworld() -> StateMachineRule
+{
+    StateMachineRule::parse_toml(WIREWORLD).expect("the wireworld machine is valid")
+}
+
+// A wire along the middle row of a grid `length` cells wide, a head at
+// its left end with a tail behind it: after n steps the head must be at
+// n + 1, the tail at n and the cells before it wire again. Also checks
+// that the builder gives the same machine as the description. Returns the
+// first step where the wire is anything else.
+pub fn check_signal(length: usize) -> Result<(), usize>
+{
+    let machine = wireworld();
+    let built = MachineBuilder::new()
+        .state("empty").state("head").state("tail").state("wire")
+        .colors(&machine.colors)
+        .always("head", "tail")
+        .always("tail", "wire")
+        .when_count("wire", "head", &[1, 2], "head")
+        .build()
+        .expect("the wireworld machine is valid");
+    if built != machine
+    {
+        return Err(0);
+    }
+    let (head, tail, wire) = (1, 2, 3);
+    let mut automata = crate::Automata::from_fn((length, 3), |(i, j)| match (i, j)
+    {
+        (0, 1) => tail,
+        (1, 1) => head,
+        (_, 1) => wire,
+        _ => 0
+    });
+    for step in 1..length - 1
+    {
+        automata.evolve(|ngh| machine.apply(ngh));
+        let expected = |i: usize| if i == step + 1 { head } else if i == step { tail } else { wire };
+        if (0..length).any(|i| *automata.get((i, 1)).unwrap() != expected(i))
+        {
+            return Err(step);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::Automata;
+
+    const HEAD: u8 = 1;
+    const TAIL: u8 = 2;
+    const WIRE: u8 = 3;
+
+    #[test]
+    fn signals_run_along_the_wire()
+    {
+        for length in [4, 9, 24]
+        {
+            assert_eq!(check_signal(length), Ok(()));
+        }
+        // And off its end, leaving the wire as it was.
+        let machine = wireworld();
+        let mut automata = Automata::from_fn((8, 1), |(i, _)| match i { 0 => TAIL, 1 => HEAD, _ => WIRE });
+        for _ in 0..8
+        {
+            automata.evolve(|ngh| machine.apply(ngh));
+        }
+        assert_eq!(automata.current().data, vec![WIRE; 8]);
+    }
+
+    #[test]
+    fn heads_split_where_wires_fork()
+    {
+        // Row 1 is the wire; (4, 1) points up, and its third edge leads to
+        // the branch (4, 0) and on to (5, 0).
+        let machine = wireworld();
+        let mut automata = Automata::from_fn((8, 3), |coord| match coord
+        {
+            (0, 1) => TAIL,
+            (1, 1) => HEAD,
+            (_, 1) | (4, 0) | (5, 0) => WIRE,
+            _ => 0
+        });
+        let mut heads = vec![];
+        for _ in 0..4
+        {
+            automata.evolve(|ngh| machine.apply(ngh));
+            let grid = automata.current();
+            heads.push((0..grid.data.len()).filter(|&k| grid.data[k] == HEAD).map(|k| (k % 8, k / 8)).collect::<Vec<_>>());
+        }
+        assert_eq!(heads, vec![vec![(2, 1)], vec![(3, 1)], vec![(4, 1)], vec![(4, 0), (5, 1)]]);
+    }
+
+    #[test]
+    fn builders_and_descriptions_agree()
+    {
+        let machine = wireworld();
+        let built = MachineBuilder::new()
+            .state("empty").state("head").state("tail").state("wire")
+            .colors(&[(0, 0, 0), (0x44, 0xaa, 0xff), (0xff, 0x66, 0x22), (0xcc, 0xbb, 0x44)])
+            .always("head", "tail")
+            .always("tail", "wire")
+            .when_count("wire", "head", &[1, 2], "head")
+            .build()
+            .unwrap();
+        assert_eq!(built, machine);
+        assert_eq!(machine.states(), ["empty", "head", "tail", "wire"]);
+        assert_eq!((machine.id("tail"), machine.id("spark")), (Some(TAIL), None));
+        assert_eq!(machine.next(WIRE, &[HEAD, HEAD, HEAD]), WIRE);
+        assert_eq!(machine.next(WIRE, &[HEAD, 0, HEAD]), HEAD);
+        assert_eq!(machine.next(9, &[HEAD]), 9);
+        assert_eq!((machine.glyph(0), machine.glyph(HEAD), machine.glyph(9)), (' ', 'h', '?'));
+        assert_eq!((machine.color(TAIL), machine.color(9)), ((0xff, 0x66, 0x22), (128, 128, 128)));
+        // Without colors, black and then the palette's.
+        let plain = MachineBuilder::new().state("off").state("on").always("on", "off").build().unwrap();
+        assert_eq!(plain.color(0), (0, 0, 0));
+        assert_eq!(plain.color(1), palette::get("okabe-ito").unwrap().index(0));
+    }
+
+    #[test]
+    fn bad_machines_are_errors()
+    {
+        let error = |result: Result<StateMachineRule, MachineError>| result.unwrap_err().to_string();
+        assert_eq!(error(MachineBuilder::new().build()), "a state machine needs at least one state");
+        assert_eq!(error(MachineBuilder::new().state("a").state("a").build()), "state 'a' is listed twice");
+        assert_eq!(error(MachineBuilder::new().state("a").always("a", "b").build()), "unknown state 'b'");
+        assert_eq!(error(MachineBuilder::new().state("a").when_count("a", "a", &[64], "a").build()), "neighbor count 64 is out of range (0 to 63)");
+        assert_eq!(error(MachineBuilder::new().state("a").colors(&[]).build()), "0 colors for 1 states");
+        let many = (0..257).fold(MachineBuilder::new(), |builder, k| builder.state(&k.to_string()));
+        assert_eq!(error(many.build()), "257 states, at most 256 are supported");
+
+        let parsed = |text: &str| error(StateMachineRule::parse_toml(text));
+        assert_eq!(parsed("states = [\"a\"]\n[[transition]]\nfrom = \"a\"\n"), "line 2: a transition needs from and to, and in along with count");
+        assert_eq!(parsed("states = [\"a\"]\nspeed = 3\n"), "line 2: unknown key 'speed'");
+        assert_eq!(parsed("states = [a]\n"), "line 1: expected a quoted string");
+        assert_eq!(parsed("states = [\"a\",\n"), "line 1: unclosed array");
+        assert_eq!(parsed("states\n"), "line 1: expected 'key = value' or [[transition]]");
+        assert_eq!(parsed("states = [\"a\"]\n[[transition]]\nfrom = \"a\"\nto = \"a\"\ncount = \"a\"\nin = [x]\n"), "line 6: expected an array of counts");
+        assert!(parsed("states = [\"a\"]\ncolors = [\"#zz0000\"]\n").starts_with("colors: "));
+        // Arrays over several lines, and comments.
+        let machine = StateMachineRule::parse_toml("states = [\"off\", # dark\n  \"on\"]\n[[transition]]\nfrom = \"on\"\nto = \"off\"\n").unwrap();
+        assert_eq!(machine.states(), ["off", "on"]);
+        assert_eq!(machine.next(1, &[]), 0);
+    }
+}
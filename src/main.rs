@@ -1,272 +1,208 @@
-use std::fmt::Debug;
+// The command line (see cli.rs), over the library in lib.rs.
 
-#[derive(Debug, Clone)]
-struct Grid<T>
-{
-    data: Vec<T>,
-    dims: (usize, usize)
-}
+mod cli;
+mod demos;
 
-#[derive(Debug, Clone, Copy)]
-enum Light
-{
-    Source(u8),
-    Space(u8)
-}
+use triangle_automata::{batch, gallery, registry, render, repl, rows, script, sweep, Light};
 
-impl std::fmt::Display for Light
+// The render subcommand: every input drawn, the failures reported, and a
+// non-zero exit status if there were any.
+fn render_files(args: &[String])
 {
-    fn fmt(&self,  f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    let job = match cli::parse_render(args)
     {
-        let intensity = match self
+        Ok(job) => job,
+        Err(message) =>
         {
-            Light::Source(intensity) => intensity,
-            Light::Space(intensity) => intensity
-        };
-        if *intensity == 0
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+    let rendered = match batch::run(&job)
+    {
+        Ok(rendered) => rendered,
+        Err(message) =>
         {
-            write!(f, "   ")            
+            eprintln!("{}", message);
+            std::process::exit(1);
         }
-        else
+    };
+    let mut failed = 0;
+    for (input, result) in &rendered
+    {
+        match result
         {
-            write!(f, "{:^3}", intensity)
+            Ok(output) => println!("{} -> {}", input.display(), output.display()),
+            Err(message) =>
+            {
+                eprintln!("{}: {}", input.display(), message);
+                failed += 1;
+            }
         }
     }
-}
-
-
-impl<T: Copy + Debug> Grid<T>
-{
-    fn new(dims: (usize, usize), default: T) -> Self
+    if failed > 0
     {
-        Self{data: vec![default; dims.0*dims.1], dims}
+        eprintln!("{} of {} files failed", failed, rendered.len());
+        std::process::exit(1);
     }
+}
 
-    fn get<'a>(&'a self, (i,j): (usize, usize)) -> Option<&'a T>
-    {
-        self.data.get(i + j*self.dims.0)
-    }
-    fn get_mut<'a>(&'a mut self, (i,j): (usize, usize)) -> Option<&'a mut T>
+fn main()
+{
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.first().map(String::as_str) == Some("render")
     {
-        self.data.get_mut(i + j*self.dims.0)
+        render_files(&args[1..]);
+        return;
     }
-    fn neighborhood(&self, (i, j): (usize, usize)) -> Vec<&T>
+    let options = match cli::parse(&args)
     {
-        
-        let coords = if (i+j) & 1 == 0
+        Ok(options) => options,
+        Err(message) =>
         {
-            // 1/c\2
-            //  \3/
-            [Some((i,j)),
-             if (i > 0) {Some((i-1, j))} else {None},
-             if (i+1 < self.dims.0) {Some((i+1, j))} else {None},
-             if (j+1 < self.dims.1) {Some((i, j+1))} else {None}]
-                
+            eprintln!("{}", message);
+            std::process::exit(2);
         }
-        else
-        {
-            //  /3\
-            // 1\c/2
-            [Some((i,j)),
-             if (i > 0) {Some((i-1, j))} else {None},
-             if (i+1 < self.dims.0) {Some((i+1, j))} else {None},
-             if (j > 0) {Some((i, j-1))} else {None}]
-        };
+    };
 
-        coords.iter()
-            .filter(|x| x.is_some())
-            .map(|co| self.get(co.unwrap()))
-            .filter(|x| x.is_some()).map(|x| x.unwrap()).collect()
-        
+    if options.list_rules
+    {
+        println!("light rules:\n{}", registry::describe(&registry::RuleRegistry::<Light>::global()));
+        println!("grain rules:\n{}", registry::describe(&registry::RuleRegistry::<u8>::global()));
+        return;
     }
 
-}
-impl<T: Copy + Debug + std::fmt::Display> Grid<T>
-{
-    fn print(&self)
+    if options.bench
     {
-        print!("      ·");
-        for i in (1..self.dims.0).step_by(2)
-        {
-            print!("-----·");
-        }
-        println!();
-        for j in (0..self.dims.1).step_by(2)
-        {
-
-
-            print!("     /");
-            for i in (1..self.dims.0).step_by(2)
-            {
-                print!(" \\{:^3}/", self.get((i, j)).unwrap())
-            }
-            if self.dims.0 % 2 == 1
-            {
-                print!(" \\");
-            }
-            println!();
-            print!("    ");
-            for i in (0..self.dims.0).step_by(2)
-            {
-                print!("/{:^3}\\ ", self.get((i, j)).unwrap());
-            }
-            if self.dims.0 % 2 == 0
-            {
-                print!("/");
-            }
-            println!();
-            print!("   ·");
-            for i in (0..self.dims.0).step_by(2)
-            {
-                print!("-----·");
-            }
-            println!();
-
-            if j+1 == self.dims.1
-            {
-                break;
-            }
-            
-            print!("    ");
-            for i in (0..self.dims.0).step_by(2)
-            {
-                print!("\\{:^3}/ ", self.get((i, j+1)).unwrap());
-            }
-            if self.dims.0 % 2 == 0
-            {
-                print!("\\");
-            }
-            println!();
-            print!("     \\");
-            for i in (1..self.dims.0).step_by(2)
-            {
-                print!(" /{:^3}\\", self.get((i, j+1)).unwrap())
-            }
-            if self.dims.0 % 2 == 1
-            {
-                print!(" /");
-            }
-            println!();
+        let steps = 100;
+        let (full, skipping, skipped) = rows::benchmark(steps);
+        println!("settled light, {} steps: {:.2?} in full, {:.2?} skipping {} rows", steps, full, skipping, skipped);
+        return;
+    }
 
-            print!("      ·");
-            for i in (1..self.dims.0).step_by(2)
+    if let Some(dir) = &options.gallery
+    {
+        match gallery::write(dir)
+        {
+            Ok(entries) => println!("{} rules drawn in {}", entries.len(), dir),
+            Err(error) =>
             {
-                print!("-----·");
+                eprintln!("{}: {}", dir, error);
+                std::process::exit(1);
             }
-            println!();
-
         }
-        
-
+        return;
     }
 
-
-    
-}
-
-
-struct Automata<T>
-{
-    grids: [Grid<T>; 2],
-    flag: usize
-}
-
-impl<T: Clone + std::fmt::Display + Copy + std::fmt::Debug> Automata<T>
-{
-    fn new(grid: Grid<T>) -> Self
+    if let Some(path) = &options.sweep
     {
-        Self
+        let results = sweep::decay_sweep(0, options.threads, |progress| eprint!("\r{:<48}", progress.to_string()));
+        eprintln!();
+        let written = std::fs::File::create(path).and_then(|file| results.to_csv(std::io::BufWriter::new(file)));
+        if let Err(error) = written
         {
-            grids: [grid.clone(), grid.clone()],
-            flag: 0
+            eprintln!("{}: {}", path, error);
+            std::process::exit(1);
         }
+        println!("{} combinations written to {}", results.rows.len(), path);
+        return;
     }
 
-    fn print(&self)
-    {
-        self.grids[self.flag].print();
-    }
-    
-    fn evolve<F>(&mut self, rule: F)
-    where
-        F: Fn(Vec<T>) -> T
+    if let Some(path) = &options.gallery_check
     {
-        for i in 0..self.grids[self.flag].dims.0
+        let result = std::fs::read_to_string(path)
+            .map_err(|error| vec![format!("{}: {}", path, error)])
+            .and_then(|text| gallery::check(&text));
+        match result
         {
-            for j in 0..self.grids[self.flag].dims.1
+            Ok(count) => println!("{} rules match {}", count, path),
+            Err(problems) =>
             {
-                let ngh = self.grids[self.flag].neighborhood((i, j)).into_iter().cloned().collect::<Vec<_>>();
-                let new_cell = self.grids[(self.flag+1)%2].get_mut((i,j)).unwrap();
-                *new_cell = rule(ngh);
+                for problem in problems
+                {
+                    eprintln!("{}", problem);
+                }
+                std::process::exit(1);
             }
         }
-        self.flag = (self.flag+1) % 2;
-        
+        return;
     }
 
-    fn get<'a>(&'a self, (i,j): (usize, usize)) -> Option<&'a T>
-    {
-        self.grids[self.flag].get((i,j))
-    }
-    fn get_mut<'a>(&'a mut self, (i,j): (usize, usize)) -> Option<&'a mut T>
+    if options.repl
     {
-        self.grids[self.flag].get_mut((i,j))
+        let dims = (30, 20);
+        let mut session = repl::Session::new(dims, options.mode.resolve(dims, render::terminal_size()));
+        session.core.neighborhood = options.neighborhood.unwrap_or_default();
+        for source in &options.sources
+        {
+            if let Err(error) = source.check(dims)
+            {
+                eprintln!("--source {}: {}", source.name, error);
+                std::process::exit(1);
+            }
+            session.sources.borrow_mut().add(source.clone());
+        }
+        session.sources.borrow_mut().apply(&mut session.core.automata);
+        if let Err(error) = repl::run(&mut session)
+        {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        return;
     }
 
-}
-
-
-
-fn main()
-{
-    let (w,h) = (30, 20);
-
-    let mut automata = Automata::new(Grid::new((w,h), Light::Space(0)));
-
-    automata.get_mut((10,10)).map(|light| *light = Light::Source(10));
-    
-    let mut flag = 0usize;
-
-    let rule = |ngh: Vec<Light>| {
-        match ngh[0]
+    if let Some(path) = &options.script
+    {
+        let result = std::fs::read_to_string(path)
+            .map_err(|error| format!("{}: {}", path, error))
+            .and_then(|text| script::parse(&text).map_err(|error| format!("{}: {}", path, error)));
+        match result
         {
-            Light::Space(_) =>
+            Ok(mut timeline) =>
             {
-                let mut max = 0u8;
-                for ncel in ngh
+                timeline.neighborhood = options.neighborhood.unwrap_or(timeline.neighborhood);
+                timeline.colormap = options.palette.unwrap_or(timeline.colormap);
+                let mode = options.mode.resolve(timeline.dims, render::terminal_size());
+                if let Err(error) = timeline.run(mode)
                 {
-                    let level = match ncel
-                    {
-                        Light::Source(level) => level,
-                        Light::Space(level) => level
-                    };
-                    if level > max
-                    {
-                        max = level;
-                    }
+                    eprintln!("{}", error);
+                    std::process::exit(1);
                 }
-                Light::Space(max.max(1)-1)
             },
-            Light::Source(lvl) => Light::Source(lvl)
+            Err(message) =>
+            {
+                eprintln!("{}", message);
+                std::process::exit(2);
+            }
         }
+        return;
+    }
 
-    };
-
-    for _ in 0..10
+    #[cfg(feature = "ws")]
     {
-        automata.print();
-        automata.evolve(rule);
+        if let Some(addr) = &options.ws
+        {
+            if let Err(error) = demos::live(&options, addr)
+            {
+                eprintln!("{}: {}", addr, error);
+                std::process::exit(1);
+            }
+            return;
+        }
     }
-    automata.get_mut((10,10)).map(|light| *light = Light::Space(10));
 
-    for _ in 0..20
+    match options.demo
     {
-        automata.print();
-        automata.evolve(rule);
+        cli::Demo::Light => demos::light(&options),
+        cli::Demo::Blink => demos::blink(&options),
+        cli::Demo::Heat => demos::heat(options.mode),
+        cli::Demo::Compare => demos::compare(),
+        cli::Demo::Sandpile => demos::sandpile(&options),
+        cli::Demo::Embers => demos::embers(&options),
+        cli::Demo::Lens => demos::lens(&options),
+        cli::Demo::Lantern => demos::lantern(&options),
+        cli::Demo::Wireworld => demos::wireworld(&options)
     }
-
-    
-    
 }
 
 //      ·-----·
@@ -0,0 +1,308 @@
+// What an automaton holds in memory, part by part: the two generation
+// buffers, the generation kept for step_backward, the ages and their
+// history, the events waiting, the row flags of row skipping, the hotspot
+// region, source programs and cell rules. Bytes are counted from the sizes
+// of the elements held (size_of), not from what the allocator reserved,
+// and cell rules by their pointers, what they hold being their own.
+// Neighborhoods are computed on the fly, there are no tables of them.
+// Parts held outside of the automaton, such as the keyframes of a
+// Timeline, can be added to a report.
+//
+// Under a memory budget, every step ends by dropping the oldest entries of
+// the history of ages, then the oldest events waiting, until the
+// automaton's parts fit in it or there are none left. A timeline recording
+// the automaton then drops its oldest keyframes but the initial one until
+// its keyframes fit in what is left (see Timeline::record). The rest
+// cannot be dropped without losing the run.
+
+use crate::{Automata, Rule, SourceProgram};
+use crate::events::Event;
+use crate::timeline::Timeline;
+
+use std::fmt::{self, Debug, Display};
+use std::mem::size_of;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Component
+{
+    pub name: &'static str,
+    // Grids, entries, events... of the part.
+    pub count: usize,
+    pub bytes: usize
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MemoryReport
+{
+    pub components: Vec<Component>
+}
+
+impl MemoryReport
+{
+    pub fn add(&mut self, name: &'static str, count: usize, bytes: usize)
+    {
+        self.components.push(Component{name, count, bytes});
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Component>
+    {
+        self.components.iter().find(|component| component.name == name)
+    }
+
+    pub fn total(&self) -> usize
+    {
+        self.components.iter().map(|component| component.bytes).sum()
+    }
+
+    // With the keyframes of a timeline, as "keyframes".
+    pub fn with_timeline(mut self, timeline: &Timeline) -> Self
+    {
+        self.add("keyframes", timeline.keyframes().len(), timeline.memory());
+        self
+    }
+}
+
+impl fmt::Display for MemoryReport
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        for component in &self.components
+        {
+            writeln!(f, "{:<16} {:>8} {:>12} B", component.name, component.count, component.bytes)?;
+        }
+        write!(f, "{:<16} {:>8} {:>12} B", "total", "", self.total())
+    }
+}
+
+// The two buffers are more than the budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverBudget
+{
+    pub core: usize,
+    pub budget: usize
+}
+
+impl fmt::Display for OverBudget
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "the generation buffers alone take {} bytes, over a budget of {}", self.core, self.budget)
+    }
+}
+
+impl<T: Clone + Display + Copy + Debug> Automata<T>
+{
+    pub fn memory_report(&self) -> MemoryReport
+    {
+        let cells = self.current.data.len();
+        let mut report = MemoryReport::default();
+        report.add("buffers", 2, 2*cells*size_of::<T>());
+        let previous = usize::from(self.previous.is_some());
+        report.add("previous", previous, previous*cells*size_of::<T>());
+        let ages = self.ages().map_or(0, |ages| ages.data.len());
+        report.add("ages", usize::from(self.ages.is_some()), ages*size_of::<u32>());
+        let (entries, records) = self.age_history();
+        report.add("age history", entries, records*size_of::<(usize, u32)>());
+        report.add("events", self.events_waiting(), self.events_waiting()*size_of::<Event>());
+        report.add("row flags", self.row_flags(), self.row_flags()*size_of::<bool>());
+        let hotspots = self.hotspots.as_ref().map_or(0, |region| region.coord_count());
+        report.add("hotspots", hotspots, hotspots*size_of::<(usize, usize)>());
+        report.add("source programs", self.programs.len(), self.programs.len()*size_of::<((usize, usize), SourceProgram<T>)>());
+        report.add("cell rules", self.cell_rules.len(), self.cell_rules.len()*size_of::<((usize, usize), Box<dyn Rule<T>>)>());
+        report
+    }
+
+    // The parts that can be dropped are kept within `budget` bytes from
+    // the next step on; an error, and no budget, if the two buffers are
+    // larger than it.
+    pub fn set_memory_budget(&mut self, budget: usize) -> Result<(), OverBudget>
+    {
+        let core = self.memory_report().get("buffers").map_or(0, |buffers| buffers.bytes);
+        if core > budget
+        {
+            self.memory_budget = None;
+            return Err(OverBudget{core, budget});
+        }
+        self.memory_budget = Some(budget);
+        Ok(())
+    }
+
+    pub fn clear_memory_budget(&mut self)
+    {
+        self.memory_budget = None;
+    }
+
+    pub fn memory_budget(&self) -> Option<usize>
+    {
+        self.memory_budget
+    }
+
+    // Entries of the history of ages and events dropped for the budget;
+    // keyframes are counted by Timeline::evicted.
+    pub fn memory_evictions(&self) -> u64
+    {
+        self.memory_evictions
+    }
+
+    pub(crate) fn enforce_memory_budget(&mut self)
+    {
+        let budget = match self.memory_budget
+        {
+            Some(budget) => budget,
+            None => return
+        };
+        while self.memory_report().total() > budget
+        {
+            if !self.drop_oldest_age_history() && !self.drop_oldest_event()
+            {
+                break;
+            }
+            self.memory_evictions += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, CellState, Light};
+    use crate::events::EventMapper;
+    use crate::validate;
+
+    fn lamp() -> Automata<Light>
+    {
+        Automata::new(validate::lamp((10, 4), (4, 2), 9))
+    }
+
+    fn combine(new: Light, old: Light) -> Light
+    {
+        new.with_level(new.level() ^ old.level())
+    }
+
+    // A copy of `automata` with ages and events, `steps` reversible steps
+    // on.
+    fn run(automata: &Automata<Light>, steps: usize) -> Automata<Light>
+    {
+        let mut copy = Automata::new(automata.current().clone());
+        copy.enable_ages();
+        copy.enable_events(EventMapper::levels(false), 1000);
+        copy.disable_row_skipping();
+        for _ in 0..steps
+        {
+            copy.evolve_reversible(rules::light_falloff, combine);
+        }
+        copy
+    }
+
+    #[test]
+    fn sizes_worked_out_by_hand()
+    {
+        let light = size_of::<Light>();
+        let mut automata = lamp();
+        let report = automata.memory_report();
+        assert_eq!(report.total(), 2*40*light, "{}", report);
+        assert_eq!(report.get("buffers"), Some(&Component{name: "buffers", count: 2, bytes: 2*40*light}));
+        assert_eq!(report.get("previous").map(|previous| previous.count), Some(0));
+
+        automata.enable_ages();
+        automata.enable_events(EventMapper::levels(false), 1000);
+        automata.disable_row_skipping();
+        assert_eq!(automata.memory_report().total(), 2*40*light + 40*size_of::<u32>());
+        automata.add_source_program((0, 0), SourceProgram::Constant(Light::Source(3)));
+        let report = automata.memory_report();
+        assert_eq!(report.get("source programs").map(|programs| programs.count), Some(1));
+        assert_eq!(report.total(), 2*40*light + 40*size_of::<u32>() + size_of::<((usize, usize), SourceProgram<Light>)>());
+
+        automata.evolve_reversible(rules::light_falloff, combine);
+        let report = automata.memory_report();
+        assert_eq!(report.get("previous"), Some(&Component{name: "previous", count: 1, bytes: 40*light}));
+        let events = report.get("events").unwrap();
+        assert!(events.count > 0);
+        assert_eq!(events.bytes, events.count*size_of::<Event>());
+        assert_eq!(report.total(), report.components.iter().map(|component| component.bytes).sum::<usize>());
+        assert_eq!(report.to_string().lines().last().map(|line| line.ends_with(&format!("{} B", report.total()))), Some(true));
+    }
+
+    #[test]
+    fn budgets_below_the_buffers_are_refused()
+    {
+        let core = 2*40*size_of::<Light>();
+        let mut automata = lamp();
+        assert_eq!(automata.set_memory_budget(core - 1), Err(OverBudget{core, budget: core - 1}));
+        assert_eq!(automata.memory_budget(), None);
+        assert_eq!(automata.set_memory_budget(core), Ok(()));
+        assert_eq!(automata.memory_budget(), Some(core));
+        automata.clear_memory_budget();
+        assert_eq!(automata.memory_budget(), None);
+    }
+
+    // A budget that holds the history of 3 reversible steps: the 4th must
+    // be the first to drop an entry, and the report must stay within the
+    // budget from then on.
+    #[test]
+    fn eviction_starts_at_the_threshold()
+    {
+        let start = lamp();
+        let budget = run(&start, 3).memory_report().total();
+        let mut automata = run(&start, 0);
+        automata.set_memory_budget(budget).unwrap();
+        for step in 1..=8
+        {
+            automata.evolve_reversible(rules::light_falloff, combine);
+            let total = automata.memory_report().total();
+            assert_eq!(automata.memory_evictions() > 0, step > 3, "step {}", step);
+            assert!(total <= budget, "step {}: {} bytes over a budget of {}\n{}", step, total, budget, automata.memory_report());
+        }
+        // Without a budget, nothing is dropped.
+        assert_eq!(run(&start, 8).memory_evictions(), 0);
+    }
+
+    #[test]
+    fn timelines_add_their_keyframes()
+    {
+        let mut automata = lamp();
+        let mut timeline = Timeline::new(2, 1 << 20);
+        for _ in 0..5
+        {
+            timeline.record(&automata);
+            automata.evolve(rules::light_falloff);
+        }
+        let report = automata.memory_report().with_timeline(&timeline);
+        assert_eq!(report.get("keyframes"), Some(&Component{name: "keyframes", count: timeline.keyframes().len(), bytes: timeline.memory()}));
+        assert_eq!(report.total(), automata.memory_report().total() + timeline.memory());
+    }
+
+    #[test]
+    fn keyframes_are_dropped_for_the_budget()
+    {
+        let record = |budget: Option<usize>| {
+            let mut automata = lamp();
+            if let Some(budget) = budget
+            {
+                automata.set_memory_budget(budget).unwrap();
+            }
+            let mut timeline = Timeline::new(1, usize::MAX);
+            timeline.record(&automata);
+            let mut totals = vec![];
+            for _ in 0..40
+            {
+                automata.evolve(rules::light_falloff);
+                timeline.record(&automata);
+                totals.push(automata.memory_report().with_timeline(&timeline).total());
+            }
+            (timeline, totals)
+        };
+        let budget = 2*2*40*size_of::<Light>();
+        // Without a budget, the keyframes grow past what the buffers take.
+        let (unbounded, totals) = record(None);
+        assert!(*totals.last().unwrap() > budget);
+        assert_eq!(unbounded.evicted(), 0);
+
+        let (timeline, totals) = record(Some(budget));
+        assert!(totals.iter().all(|&total| total <= budget), "{:?} over {}", totals, budget);
+        assert!(timeline.evicted() > 0);
+        assert_eq!(timeline.keyframes()[0], 0);
+        assert_eq!(timeline.keyframes().last(), Some(&40));
+    }
+}
@@ -0,0 +1,520 @@
+// Grids stored in memory-mapped files, for grids larger than the memory:
+// the cells are laid out as in Grid, with nothing around them, so the file
+// of a 50000x50000 grid of u8 is 2.5 GB of cells. Steps read one file and
+// write the other, one band of rows at a time. The pages of the rows left
+// behind are handed back to the system, keeping the working set to a few
+// bands whatever the size of the grid.
+//
+// Writes reach the files whenever the system decides, until a checkpoint
+// flushes the current generation. After a crash between two checkpoints,
+// both files may hold a mix of generations.
+//
+// Linux only: the mapping calls are declared here rather than taken from a
+// crate.
+
+use crate::{neighbor_coords, Grid, GridAccess};
+
+use std::fmt::{self, Debug};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::raw::{c_int, c_long, c_void};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+extern "C"
+{
+    fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn msync(addr: *mut c_void, len: usize, flags: c_int) -> c_int;
+    fn madvise(addr: *mut c_void, len: usize, advice: c_int) -> c_int;
+    fn sysconf(name: c_int) -> c_long;
+}
+
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const MAP_SHARED: c_int = 1;
+const MS_ASYNC: c_int = 1;
+const MS_SYNC: c_int = 4;
+const MADV_DONTNEED: c_int = 4;
+const SC_PAGESIZE: c_int = 30;
+
+fn page_size() -> usize
+{
+    // SAFETY: sysconf has no preconditions.
+    match unsafe { sysconf(SC_PAGESIZE) }
+    {
+        size if size > 0 => size as usize,
+        _ => 4096
+    }
+}
+
+/// Cells that can be read from any bytes: every bit pattern of their size
+/// is a valid value, and they hold no pointers.
+///
+/// # Safety
+///
+/// Implementors must be plain data with no invalid bit patterns.
+pub unsafe trait MappedCell: Copy + Debug {}
+
+unsafe impl MappedCell for u8 {}
+unsafe impl MappedCell for u16 {}
+unsafe impl MappedCell for u32 {}
+unsafe impl MappedCell for u64 {}
+unsafe impl MappedCell for f32 {}
+unsafe impl MappedCell for f64 {}
+
+#[derive(Debug)]
+pub enum MmapError
+{
+    Io(io::Error),
+    // The file is shorter than the cells of the grid.
+    TooSmall{expected: u64, found: u64},
+    // The file cannot be written, or the grid was opened read-only and is
+    // being written.
+    ReadOnly(PathBuf),
+    // A grid without cells, which cannot be mapped.
+    Empty,
+    // More bytes than can be addressed.
+    Overflow,
+    // The two grids of a MappedAutomata have different dims.
+    DimMismatch{current: (usize, usize), next: (usize, usize)}
+}
+
+impl From<io::Error> for MmapError
+{
+    fn from(error: io::Error) -> Self
+    {
+        MmapError::Io(error)
+    }
+}
+
+impl fmt::Display for MmapError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            MmapError::Io(error) => write!(f, "{}", error),
+            MmapError::TooSmall{expected, found} => write!(f, "the file holds {} bytes, the grid needs {}", found, expected),
+            MmapError::ReadOnly(path) => write!(f, "{} is read-only", path.display()),
+            MmapError::Empty => write!(f, "the grid has no cells"),
+            MmapError::Overflow => write!(f, "the grid is too large to map"),
+            MmapError::DimMismatch{current, next} =>
+                write!(f, "grids of {}x{} and {}x{} cells", current.0, current.1, next.0, next.1)
+        }
+    }
+}
+
+pub struct MmapGrid<T>
+{
+    cells: *mut T,
+    len: usize,
+    dims: (usize, usize),
+    writable: bool,
+    path: PathBuf,
+    // Bytes from the start already synced and released by release_until.
+    released: usize,
+    // Kept open for the lifetime of the mapping.
+    _file: File
+}
+
+fn byte_len<T>(dims: (usize, usize)) -> Result<usize, MmapError>
+{
+    let bytes = dims.0.checked_mul(dims.1)
+        .and_then(|cells| cells.checked_mul(std::mem::size_of::<T>()))
+        .ok_or(MmapError::Overflow)?;
+    if bytes == 0
+    {
+        return Err(MmapError::Empty);
+    }
+    Ok(bytes)
+}
+
+fn map_error(path: &Path, error: io::Error) -> MmapError
+{
+    match error.kind()
+    {
+        io::ErrorKind::PermissionDenied => MmapError::ReadOnly(path.to_path_buf()),
+        _ => MmapError::Io(error)
+    }
+}
+
+impl<T: MappedCell> MmapGrid<T>
+{
+    fn map(file: File, path: &Path, dims: (usize, usize), writable: bool) -> Result<Self, MmapError>
+    {
+        let bytes = byte_len::<T>(dims)?;
+        let found = file.metadata()?.len();
+        if found < bytes as u64
+        {
+            return Err(MmapError::TooSmall{expected: bytes as u64, found});
+        }
+        let prot = if writable { PROT_READ | PROT_WRITE } else { PROT_READ };
+        // SAFETY: a fresh shared mapping of an open file at least `bytes`
+        // long; the result is checked against MAP_FAILED.
+        let cells = unsafe { mmap(std::ptr::null_mut(), bytes, prot, MAP_SHARED, file.as_raw_fd(), 0) };
+        if cells as isize == -1
+        {
+            return Err(map_error(path, io::Error::last_os_error()));
+        }
+        Ok(Self{cells: cells as *mut T, len: dims.0*dims.1, dims, writable, path: path.to_path_buf(), released: 0, _file: file})
+    }
+
+    // Creates (or truncates) the file at `path`, sized for `dims` cells of
+    // `fill`.
+    pub fn create<P: AsRef<Path>>(path: P, dims: (usize, usize), fill: T) -> Result<Self, MmapError>
+    {
+        let path = path.as_ref();
+        let bytes = byte_len::<T>(dims)?;
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)
+            .map_err(|error| map_error(path, error))?;
+        file.set_len(bytes as u64)?;
+        let mut grid = Self::map(file, path, dims, true)?;
+        // SAFETY: T is plain data, read as its bytes.
+        let fill_bytes = unsafe { std::slice::from_raw_parts(&fill as *const T as *const u8, std::mem::size_of::<T>()) };
+        // The file starts out zeroed; other fills are written band by band.
+        if fill_bytes.iter().any(|&byte| byte != 0)
+        {
+            let w = dims.0;
+            for j in 0..dims.1
+            {
+                grid.as_mut_slice()[j*w..(j+1)*w].iter_mut().for_each(|cell| *cell = fill);
+                if (j + 1) % 64 == 0
+                {
+                    grid.release_until(j + 1);
+                }
+            }
+        }
+        Ok(grid)
+    }
+
+    // Maps an existing file for reading and writing. Bytes past the cells
+    // are left alone.
+    pub fn open<P: AsRef<Path>>(path: P, dims: (usize, usize)) -> Result<Self, MmapError>
+    {
+        let path = path.as_ref();
+        let file = OpenOptions::new().read(true).write(true).open(path).map_err(|error| map_error(path, error))?;
+        Self::map(file, path, dims, true)
+    }
+
+    // get_mut gives None and the grid cannot be one of the two of a
+    // MappedAutomata.
+    pub fn open_readonly<P: AsRef<Path>>(path: P, dims: (usize, usize)) -> Result<Self, MmapError>
+    {
+        let path = path.as_ref();
+        Self::map(File::open(path)?, path, dims, false)
+    }
+
+    // In storage order, as Grid::as_slice.
+    pub fn as_slice(&self) -> &[T]
+    {
+        // SAFETY: the mapping holds `len` cells for as long as self lives,
+        // and every bit pattern is a valid T.
+        unsafe { std::slice::from_raw_parts(self.cells, self.len) }
+    }
+
+    // Empty when the grid is read-only.
+    pub fn as_mut_slice(&mut self) -> &mut [T]
+    {
+        let len = if self.writable { self.len } else { 0 };
+        // SAFETY: as for as_slice, the mapping being writable and borrowed
+        // mutably through self.
+        unsafe { std::slice::from_raw_parts_mut(self.cells, len) }
+    }
+
+    pub fn path(&self) -> &Path
+    {
+        &self.path
+    }
+
+    pub fn is_writable(&self) -> bool
+    {
+        self.writable
+    }
+
+    // Copies the cells into memory.
+    pub fn to_grid(&self) -> Grid<T>
+    {
+        Grid{data: self.as_slice().to_vec(), dims: self.dims}
+    }
+
+    // Waits until every cell written so far is in the file.
+    pub fn flush(&self) -> Result<(), MmapError>
+    {
+        if !self.writable
+        {
+            return Ok(());
+        }
+        // SAFETY: the whole mapping, which is page-aligned.
+        match unsafe { msync(self.cells as *mut c_void, self.len*std::mem::size_of::<T>(), MS_SYNC) }
+        {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error().into())
+        }
+    }
+
+    // Starts writing back and drops from memory the pages entirely before
+    // row j. They are read again from the file if needed.
+    fn release_until(&mut self, j: usize)
+    {
+        let page = page_size();
+        let end = j*self.dims.0*std::mem::size_of::<T>();
+        let end = end - end % page;
+        if end <= self.released
+        {
+            return;
+        }
+        // SAFETY: both ranges are page-aligned and inside the mapping. The
+        // advice only drops pages, their contents staying in the file.
+        unsafe {
+            let start = (self.cells as *mut u8).add(self.released) as *mut c_void;
+            if self.writable
+            {
+                msync(start, end - self.released, MS_ASYNC);
+            }
+            madvise(start, end - self.released, MADV_DONTNEED);
+        }
+        self.released = end;
+    }
+}
+
+impl<T: MappedCell> GridAccess<T> for MmapGrid<T>
+{
+    fn dims(&self) -> (usize, usize)
+    {
+        self.dims
+    }
+
+    fn get(&self, (i, j): (usize, usize)) -> Option<&T>
+    {
+        if i >= self.dims.0 || j >= self.dims.1
+        {
+            return None;
+        }
+        self.as_slice().get(j*self.dims.0 + i)
+    }
+
+    fn get_mut(&mut self, (i, j): (usize, usize)) -> Option<&mut T>
+    {
+        if i >= self.dims.0 || j >= self.dims.1
+        {
+            return None;
+        }
+        let w = self.dims.0;
+        self.as_mut_slice().get_mut(j*w + i)
+    }
+}
+
+impl<T> Drop for MmapGrid<T>
+{
+    fn drop(&mut self)
+    {
+        // SAFETY: the mapping made by map, unmapped once.
+        unsafe {
+            munmap(self.cells as *mut c_void, self.len*std::mem::size_of::<T>());
+        }
+    }
+}
+
+// Two mapped grids taking turns as the current generation and the next.
+pub struct MappedAutomata<T>
+{
+    current: MmapGrid<T>,
+    next: MmapGrid<T>,
+    step: u64,
+    // Rows computed between two releases of pages.
+    band_rows: usize
+}
+
+impl<T: MappedCell> MappedAutomata<T>
+{
+    // `next` is overwritten by the first step.
+    pub fn new(current: MmapGrid<T>, next: MmapGrid<T>) -> Result<Self, MmapError>
+    {
+        if current.dims != next.dims
+        {
+            return Err(MmapError::DimMismatch{current: current.dims, next: next.dims});
+        }
+        if !current.writable || !next.writable
+        {
+            let path = if next.writable { &current.path } else { &next.path };
+            return Err(MmapError::ReadOnly(path.clone()));
+        }
+        Ok(Self{current, next, step: 0, band_rows: 64})
+    }
+
+    pub fn set_band_rows(&mut self, rows: usize)
+    {
+        self.band_rows = rows.max(1);
+    }
+
+    pub fn current(&self) -> &MmapGrid<T>
+    {
+        &self.current
+    }
+
+    pub fn current_mut(&mut self) -> &mut MmapGrid<T>
+    {
+        &mut self.current
+    }
+
+    pub fn step(&self) -> u64
+    {
+        self.step
+    }
+
+    // Same as Automata::evolve, the rule getting the cell and then its
+    // neighbors. Cells are computed row by row; once a band is done, the
+    // rows no step will read again are released from both files.
+    pub fn evolve<F>(&mut self, rule: F)
+    where
+        F: Fn(Vec<T>) -> T
+    {
+        let (w, h) = self.current.dims;
+        self.current.released = 0;
+        self.next.released = 0;
+        for start in (0..h).step_by(self.band_rows)
+        {
+            let end = (start + self.band_rows).min(h);
+            for j in start..end
+            {
+                for i in 0..w
+                {
+                    let ngh = neighbor_coords((w, h), (i, j)).into_iter()
+                        .map(|coord| *self.current.get(coord).unwrap())
+                        .collect();
+                    *self.next.get_mut((i, j)).unwrap() = rule(ngh);
+                }
+            }
+            // The next band reads from row end - 1 on.
+            self.current.release_until(end.saturating_sub(1));
+            self.next.release_until(end);
+        }
+        std::mem::swap(&mut self.current, &mut self.next);
+        self.step += 1;
+    }
+
+    // Flushes the current generation and gives the file holding it, which
+    // alternates between the two from step to step.
+    pub fn checkpoint(&self) -> Result<&Path, MmapError>
+    {
+        self.current.flush()?;
+        Ok(self.current.path())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, Automata};
+
+    fn temp(name: &str) -> PathBuf
+    {
+        std::env::temp_dir().join(format!("triangle-automata-{}-{}.cells", std::process::id(), name))
+    }
+
+    fn remove(paths: &[PathBuf])
+    {
+        for path in paths
+        {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    // A sandpile with a few heaps, the same on disk and in memory.
+    fn heaps((i, j): (usize, usize)) -> u8
+    {
+        ((i*7 + j*13) % 11) as u8
+    }
+
+    #[test]
+    fn mapped_runs_match_the_in_memory_ones()
+    {
+        let dims = (37, 29);
+        let (a, b) = (temp("run-a"), temp("run-b"));
+        let mut current = MmapGrid::create(&a, dims, 0u8).unwrap();
+        for j in 0..dims.1
+        {
+            for i in 0..dims.0
+            {
+                *current.get_mut((i, j)).unwrap() = heaps((i, j));
+            }
+        }
+        let mut mapped = MappedAutomata::new(current, MmapGrid::create(&b, dims, 0u8).unwrap()).unwrap();
+        // Bands that do not divide the height, and pages released as it
+        // goes.
+        mapped.set_band_rows(4);
+        let mut automata = Automata::new(Grid::from_fn(dims, heaps));
+        for step in 1..=20
+        {
+            mapped.evolve(rules::sandpile);
+            automata.evolve(rules::sandpile);
+            assert_eq!(mapped.step(), step);
+            assert_eq!(&mapped.current().to_grid(), automata.current());
+        }
+        let coord = (5, 5);
+        assert_eq!(mapped.current().neighborhood(coord), automata.current().neighborhood(coord));
+
+        // The checkpoint names the file holding the current generation,
+        // which reads back the same.
+        let path = mapped.checkpoint().unwrap().to_path_buf();
+        assert_eq!(path, a);
+        drop(mapped);
+        assert_eq!(&MmapGrid::<u8>::open_readonly(&path, dims).unwrap().to_grid(), automata.current());
+        remove(&[a, b]);
+    }
+
+    #[test]
+    fn grids_are_created_filled()
+    {
+        let path = temp("filled");
+        let grid = MmapGrid::create(&path, (300, 70), 1.5f32).unwrap();
+        assert!(grid.as_slice().iter().all(|&cell| cell == 1.5));
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 300*70*4);
+        drop(grid);
+        // Bytes past the cells are left alone.
+        let mut grid = MmapGrid::<f32>::open(&path, (300, 60)).unwrap();
+        *grid.get_mut((299, 59)).unwrap() = 2.0;
+        assert_eq!(grid.get((300, 0)), None);
+        assert_eq!(grid.get_mut((0, 60)), None);
+        grid.flush().unwrap();
+        drop(grid);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 300*70*4);
+        remove(&[path]);
+    }
+
+    #[test]
+    fn failures_are_reported()
+    {
+        let path = temp("errors");
+        drop(MmapGrid::create(&path, (10, 10), 0u8).unwrap());
+        match MmapGrid::<u16>::open(&path, (10, 10))
+        {
+            Err(MmapError::TooSmall{expected: 200, found: 100}) => (),
+            other => panic!("{:?}", other.map(|grid| grid.dims))
+        }
+        assert!(matches!(MmapGrid::create(temp("empty"), (0, 10), 0u8), Err(MmapError::Empty)));
+        assert!(matches!(MmapGrid::create(temp("huge"), (usize::MAX, 2), 0u8), Err(MmapError::Overflow)));
+
+        let mut readonly = MmapGrid::<u8>::open_readonly(&path, (10, 10)).unwrap();
+        assert!(!readonly.is_writable());
+        assert_eq!(readonly.get((3, 3)), Some(&0));
+        assert_eq!(readonly.get_mut((3, 3)), None);
+        assert!(readonly.as_mut_slice().is_empty());
+        let other = temp("errors-next");
+        let next = MmapGrid::create(&other, (10, 10), 0u8).unwrap();
+        match MappedAutomata::new(readonly, next)
+        {
+            Err(MmapError::ReadOnly(read_only)) => assert_eq!(read_only, path),
+            _ => panic!("a read-only grid was accepted")
+        }
+        let small = MmapGrid::create(&other, (10, 9), 0u8).unwrap();
+        match MappedAutomata::new(MmapGrid::open(&path, (10, 10)).unwrap(), small)
+        {
+            Err(error) => assert_eq!(error.to_string(), "grids of 10x10 and 10x9 cells"),
+            _ => panic!("grids of different sizes were accepted")
+        }
+        remove(&[path, other]);
+    }
+}
@@ -0,0 +1,451 @@
+// Rule parameters and source levels driven by time series read from a CSV
+// file or stdin, such as the loudness of a sound, one row per sample under
+// a header naming the columns:
+//
+//     step,loudness,wind
+//     0,4,0.1
+//     1,9,
+//
+// The columns become named channels of f64. Rows are read as the run
+// needs them, so a stream on stdin can keep coming while the automaton
+// runs. With a rate of r rows per step, step s reads the data at row s*r:
+// the row before it when holding, or between it and the next one when
+// interpolating linearly. A field that is blank or not a number is missing,
+// and the data runs out after the last row: the channel then holds its
+// last value, and the sample is flagged as held.
+//
+// A Modulation binds channels to parameters of a registered rule (the
+// ceiling `max` of clamped falloff) and to the levels of named
+// sources, and sets them before every step of a Simulation. The forest
+// fire rule is stochastic and not registered: its lightning probability f
+// is read from the channel by the caller, step by step, as in
+//
+//     let f = modulator.value(step, "f").map_or(0.002, |sample| sample.value);
+
+use crate::Rule;
+use crate::registry::{RegistryError, RuleRegistry};
+use crate::simulation::Simulation;
+use crate::sources::SourceSet;
+use crate::sweep::RuleConfig;
+
+use std::cell::RefCell;
+use std::fmt::{self, Debug, Display};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation
+{
+    Hold,
+    Linear
+}
+
+#[derive(Debug)]
+pub enum ModulationError
+{
+    Io(io::Error),
+    // No header line.
+    Empty,
+    // A column of the map that is not in the header.
+    MissingColumn(String)
+}
+
+impl fmt::Display for ModulationError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            ModulationError::Io(error) => write!(f, "{}", error),
+            ModulationError::Empty => write!(f, "no header line"),
+            ModulationError::MissingColumn(column) => write!(f, "no column '{}' in the header", column)
+        }
+    }
+}
+
+impl From<io::Error> for ModulationError
+{
+    fn from(error: io::Error) -> Self
+    {
+        ModulationError::Io(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample
+{
+    pub value: f64,
+    // The data was missing or had run out, and the last value was kept.
+    pub held: bool
+}
+
+pub struct Modulator
+{
+    lines: Box<dyn BufRead>,
+    // Channel names, with the index of their column in the input.
+    channels: Vec<(String, usize)>,
+    // The rows read so far, a field per channel.
+    rows: Vec<Vec<Option<f64>>>,
+    exhausted: bool,
+    rate: f64,
+    interpolation: Interpolation
+}
+
+impl Modulator
+{
+    // Channels named after the columns of `column_map`, as (column,
+    // channel) pairs, or every column under its own name when the map is
+    // empty.
+    pub fn from_csv<P: AsRef<Path>>(path: P, column_map: &[(&str, &str)]) -> Result<Self, ModulationError>
+    {
+        Self::from_reader(File::open(path)?, column_map)
+    }
+
+    // Every column of stdin, read as the run goes.
+    pub fn from_stdin() -> Result<Self, ModulationError>
+    {
+        Self::from_reader(io::stdin(), &[])
+    }
+
+    pub fn from_reader<R: Read + 'static>(reader: R, column_map: &[(&str, &str)]) -> Result<Self, ModulationError>
+    {
+        let mut lines: Box<dyn BufRead> = Box::new(BufReader::new(reader));
+        let header = read_line(&mut *lines)?.ok_or(ModulationError::Empty)?;
+        let columns: Vec<&str> = header.split(',').map(str::trim).collect();
+        let channels = if column_map.is_empty()
+        {
+            columns.iter().enumerate().map(|(index, column)| (column.to_string(), index)).collect()
+        }
+        else
+        {
+            column_map.iter()
+                .map(|&(column, channel)| match columns.iter().position(|&name| name == column)
+                {
+                    Some(index) => Ok((channel.to_string(), index)),
+                    None => Err(ModulationError::MissingColumn(column.to_string()))
+                })
+                .collect::<Result<_, _>>()?
+        };
+        Ok(Self{lines, channels, rows: vec![], exhausted: false, rate: 1.0, interpolation: Interpolation::Hold})
+    }
+
+    // Rows of data per step, 1 by default: 0.5 for data at half the rate
+    // of the steps, 2 to skip every other row.
+    pub fn with_rate(mut self, rows_per_step: f64) -> Self
+    {
+        self.rate = rows_per_step.max(0.0);
+        self
+    }
+
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self
+    {
+        self.interpolation = interpolation;
+        self
+    }
+
+    pub fn channels(&self) -> Vec<&str>
+    {
+        self.channels.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    // Reads rows until row `index` is in, or the input ends. Input that
+    // cannot be read ends it too.
+    fn fill(&mut self, index: usize)
+    {
+        while self.rows.len() <= index && !self.exhausted
+        {
+            match read_line(&mut *self.lines)
+            {
+                Ok(Some(line)) if line.trim().is_empty() => (),
+                Ok(Some(line)) =>
+                {
+                    let fields: Vec<&str> = line.split(',').collect();
+                    let row = self.channels.iter()
+                        .map(|&(_, column)| fields.get(column).and_then(|field| field.trim().parse().ok()))
+                        .collect();
+                    self.rows.push(row);
+                },
+                Ok(None) | Err(_) => self.exhausted = true
+            }
+        }
+    }
+
+    // The last value of the channel at row `index` or before it.
+    fn last(&self, channel: usize, index: usize) -> Option<f64>
+    {
+        self.rows[..self.rows.len().min(index + 1)].iter().rev().find_map(|row| row[channel])
+    }
+
+    // Every channel at `step`, in the order of channels(); None for those
+    // without a value yet.
+    pub fn sample(&mut self, step: u64) -> Vec<Option<Sample>>
+    {
+        let position = step as f64*self.rate;
+        let (index, between) = (position.floor() as usize, position.fract());
+        self.fill(index + 1);
+        (0..self.channels.len())
+            .map(|channel| {
+                let at = |index: usize| self.rows.get(index).and_then(|row| row[channel]);
+                let value = match (at(index), self.interpolation)
+                {
+                    (Some(value), Interpolation::Hold) => Some(value),
+                    (Some(value), Interpolation::Linear) if between == 0.0 => Some(value),
+                    (Some(value), Interpolation::Linear) => at(index + 1).map(|next| value + (next - value)*between),
+                    (None, _) => None
+                };
+                match value
+                {
+                    Some(value) => Some(Sample{value, held: false}),
+                    None => self.last(channel, index).map(|value| Sample{value, held: true})
+                }
+            })
+            .collect()
+    }
+
+    pub fn value(&mut self, step: u64, channel: &str) -> Option<Sample>
+    {
+        let index = self.channels.iter().position(|(name, _)| name == channel)?;
+        self.sample(step)[index]
+    }
+}
+
+fn read_line(lines: &mut dyn BufRead) -> io::Result<Option<String>>
+{
+    let mut line = String::new();
+    if lines.read_line(&mut line)? == 0
+    {
+        return Ok(None);
+    }
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    Ok(Some(line.strip_suffix('\r').unwrap_or(line).to_string()))
+}
+
+enum Target
+{
+    Param(String),
+    Source(Rc<RefCell<SourceSet>>, String)
+}
+
+pub struct Modulation<T>
+{
+    modulator: Modulator,
+    registry: RuleRegistry<T>,
+    rule: String,
+    config: RuleConfig,
+    bindings: Vec<(String, Target)>,
+    held: Vec<String>
+}
+
+impl<T: 'static> Modulation<T>
+{
+    // Modulates the parameters of `rule` in `registry`, which start from
+    // `config`.
+    pub fn new(modulator: Modulator, registry: RuleRegistry<T>, rule: &str, config: RuleConfig) -> Result<Self, RegistryError>
+    {
+        registry.instantiate(rule, &config)?;
+        Ok(Self{modulator, registry, rule: rule.to_string(), config, bindings: vec![], held: vec![]})
+    }
+
+    // The channel sets the rule's parameter, clamped to its range.
+    pub fn bind_param(mut self, channel: &str, param: &str) -> Result<Self, RegistryError>
+    {
+        let entry = self.registry.get(&self.rule).expect("the rule was instantiated");
+        if !entry.params.iter().any(|spec| spec.name == param)
+        {
+            return Err(RegistryError::UnknownParam{rule: entry.name.clone(), param: param.to_string()});
+        }
+        self.bindings.push((channel.to_string(), Target::Param(param.to_string())));
+        Ok(self)
+    }
+
+    // The channel sets the level of the source `name` of `sources`,
+    // rounded to 0 to 255.
+    pub fn bind_source(mut self, channel: &str, sources: &Rc<RefCell<SourceSet>>, name: &str) -> Self
+    {
+        self.bindings.push((channel.to_string(), Target::Source(Rc::clone(sources), name.to_string())));
+        self
+    }
+
+    // The rule with its parameters as they are now, to start the run
+    // with.
+    pub fn rule(&self) -> Box<dyn Rule<T>>
+    {
+        self.registry.instantiate(&self.rule, &self.config).expect("parameters are kept within range")
+    }
+
+    pub fn config(&self) -> &RuleConfig
+    {
+        &self.config
+    }
+
+    // The channels held at the last step.
+    pub fn held(&self) -> &[String]
+    {
+        &self.held
+    }
+
+    // Sets what the channels are bound to from their samples at the
+    // simulation's step, then evolves: the generation of step s + 1 is
+    // computed with the samples of step s. Channels without a value yet
+    // leave what they are bound to as it is.
+    pub fn step(&mut self, simulation: &mut Simulation<T, Box<dyn Rule<T>>>)
+    where
+        T: Clone + Display + Copy + Debug
+    {
+        let samples = self.modulator.sample(simulation.step());
+        let channels: Vec<String> = self.modulator.channels().iter().map(|name| name.to_string()).collect();
+        self.held = channels.iter().zip(&samples)
+            .filter(|(_, sample)| sample.is_some_and(|sample| sample.held))
+            .map(|(name, _)| name.clone())
+            .collect();
+        let mut changed = false;
+        let entry = self.registry.get(&self.rule).expect("the rule was instantiated");
+        for (channel, target) in &self.bindings
+        {
+            let sample = match channels.iter().position(|name| name == channel).and_then(|index| samples[index])
+            {
+                Some(sample) => sample,
+                None => continue
+            };
+            match target
+            {
+                Target::Param(param) =>
+                {
+                    let spec = entry.params.iter().find(|spec| &spec.name == param).expect("bound parameters are the rule's");
+                    let value = sample.value.clamp(spec.range.0, spec.range.1);
+                    if self.config.get(param) != Some(value)
+                    {
+                        self.config.set(param, value);
+                        changed = true;
+                    }
+                },
+                Target::Source(sources, name) =>
+                {
+                    sources.borrow_mut().set_level(name, sample.value.round().clamp(0.0, 255.0) as u8);
+                }
+            }
+        }
+        if changed
+        {
+            simulation.set_rule(self.rule());
+        }
+        simulation.evolve();
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{Grid, Light};
+    use crate::sources::{self, Source};
+
+    use std::fs;
+    use std::io::Cursor;
+
+    fn reader(text: &str) -> Cursor<String>
+    {
+        Cursor::new(text.to_string())
+    }
+
+    const LEVELS: [Option<u8>; 10] = [Some(4), Some(9), Some(15), Some(2), Some(0), None, Some(30), Some(7), Some(12), Some(20)];
+
+    fn levels_csv() -> String
+    {
+        let mut csv = "step,loudness\n".to_string();
+        for (row, level) in LEVELS.iter().enumerate()
+        {
+            csv.push_str(&format!("{},{}\n", row, level.map_or(String::new(), |level| level.to_string())));
+        }
+        csv
+    }
+
+    // The level of a source from a CSV of 10 rows, one of them missing its
+    // level, for 12 steps: the source must have the level of row s in the
+    // generation of step s + 1, the level before the missing one there,
+    // and the last level once the rows run out, flagged as held.
+    #[test]
+    fn a_csv_drives_the_level_of_a_source()
+    {
+        let path = std::env::temp_dir().join(format!("triangle-automata-{}-levels.csv", std::process::id()));
+        fs::write(&path, levels_csv()).unwrap();
+        let modulator = Modulator::from_csv(&path, &[("loudness", "level")]);
+        let _ = fs::remove_file(&path);
+        let sources = Rc::new(RefCell::new(SourceSet::new()));
+        sources.borrow_mut().add(Source::new("lamp", (8, 4), 1));
+        let mut modulation = Modulation::new(modulator.unwrap(), RuleRegistry::<Light>::with_builtins(), "falloff", RuleConfig::new(0))
+            .unwrap()
+            .bind_source("level", &sources, "lamp");
+        let mut simulation = Simulation::new(Grid::new((16, 8), Light::Space(0)), modulation.rule());
+        sources::attach(&sources, simulation.automata_mut());
+        for step in 0..12
+        {
+            let (expected, held) = match LEVELS.get(step)
+            {
+                Some(Some(level)) => (*level, false),
+                Some(None) => (0, true),
+                None => (20, true)
+            };
+            modulation.step(&mut simulation);
+            assert_eq!(simulation.current().get((8, 4)), Some(&Light::Source(expected)), "step {}", step);
+            assert_eq!(modulation.held(), if held { vec!["level".to_string()] } else { vec![] }, "step {}", step);
+        }
+        assert_eq!(simulation.step(), 12);
+    }
+
+    #[test]
+    fn rates_and_interpolation()
+    {
+        let mut modulator = Modulator::from_reader(reader(&levels_csv()), &[]).unwrap()
+            .with_rate(0.5)
+            .with_interpolation(Interpolation::Linear);
+        assert_eq!(modulator.channels(), ["step", "loudness"]);
+        let expected = [4.0, 6.5, 9.0, 12.0, 15.0, 8.5, 2.0, 1.0, 0.0, 0.0];
+        for (step, &value) in expected.iter().enumerate()
+        {
+            assert_eq!(modulator.value(step as u64, "loudness"), Some(Sample{value, held: step == 9}), "step {}", step);
+        }
+        // Held at half the rate, and every other row at twice it.
+        let mut held = Modulator::from_reader(reader(&levels_csv()), &[]).unwrap().with_rate(0.5);
+        assert_eq!((0..4).map(|step| held.value(step, "loudness").unwrap().value).collect::<Vec<_>>(), [4.0, 4.0, 9.0, 9.0]);
+        let mut fast = Modulator::from_reader(reader(&levels_csv()), &[]).unwrap().with_rate(2.0);
+        assert_eq!((0..4).map(|step| fast.value(step, "loudness").unwrap().value).collect::<Vec<_>>(), [4.0, 15.0, 0.0, 30.0]);
+        assert_eq!(fast.value(0, "wind"), None);
+    }
+
+    #[test]
+    fn channels_set_parameters_within_their_range()
+    {
+        let csv = "ceiling\n3\n\n900\n";
+        let modulator = Modulator::from_reader(reader(csv), &[("ceiling", "max")]).unwrap();
+        let mut modulation = Modulation::new(modulator, RuleRegistry::<Light>::with_builtins(), "clamped", RuleConfig::new(0))
+            .unwrap()
+            .bind_param("max", "max")
+            .unwrap();
+        let mut grid = Grid::new((12, 6), Light::Space(0));
+        *grid.get_mut((5, 3)).unwrap() = Light::Source(40);
+        let mut simulation = Simulation::new(grid, modulation.rule());
+        modulation.step(&mut simulation);
+        assert_eq!(modulation.config().get("max"), Some(3.0));
+        assert_eq!(simulation.current().get((6, 3)), Some(&Light::Space(3)));
+        modulation.step(&mut simulation);
+        assert_eq!(modulation.config().get("max"), Some(255.0));
+        assert!(modulation.held().is_empty());
+        modulation.step(&mut simulation);
+        assert_eq!(modulation.held(), ["max"]);
+        assert_eq!(simulation.current().get((6, 3)), Some(&Light::Space(39)));
+    }
+
+    #[test]
+    fn bad_inputs_are_errors()
+    {
+        assert!(matches!(Modulator::from_reader(reader(""), &[]), Err(ModulationError::Empty)));
+        let missing = Modulator::from_reader(reader("step,loudness\n"), &[("wind", "f")]).err().map(|error| error.to_string());
+        assert_eq!(missing.as_deref(), Some("no column 'wind' in the header"));
+        let modulator = Modulator::from_reader(reader("level\n"), &[]).unwrap();
+        let modulation = Modulation::new(modulator, RuleRegistry::<Light>::with_builtins(), "falloff", RuleConfig::new(0)).unwrap();
+        assert!(matches!(modulation.bind_param("level", "max"), Err(RegistryError::UnknownParam{..})));
+    }
+}
@@ -0,0 +1,394 @@
+// Which cells count as neighbors, for rules that want more than the three
+// across the edges.
+
+use crate::{Automata, Grid, Rule, Slot};
+use crate::boundary::BoundaryCondition;
+use crate::coord::Coord;
+use crate::flux;
+
+use std::collections::HashSet;
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NeighborhoodKind
+{
+    // The (up to) 3 cells sharing an edge, as in Grid::neighbor_coords.
+    #[default]
+    Edge,
+    // The (up to) 12 cells sharing at least a corner.
+    Vertex,
+    // Every cell at most this many edge crossings away.
+    Radius(usize)
+}
+
+impl FromStr for NeighborhoodKind
+{
+    type Err = String;
+
+    // edge, vertex or radius=N.
+    fn from_str(text: &str) -> Result<Self, String>
+    {
+        match text
+        {
+            "edge" => Ok(NeighborhoodKind::Edge),
+            "vertex" => Ok(NeighborhoodKind::Vertex),
+            _ => text.strip_prefix("radius=")
+                .and_then(|radius| radius.parse().ok())
+                .map(NeighborhoodKind::Radius)
+                .ok_or_else(|| format!("unknown neighborhood '{}' (edge, vertex or radius=N)", text))
+        }
+    }
+}
+
+// Where the vertex neighbors of a cell are, row by row: the row the cell
+// points away from shares only the apex, with 3 cells; the row across its
+// flat edge shares two corners, with 5.
+fn vertex_offsets((i, j): (usize, usize)) -> Vec<(isize, isize)>
+{
+    let (apex, flat) = if (i + j).is_multiple_of(2) { (-1, 1) } else { (1, -1) };
+    let mut offsets: Vec<(isize, isize)> = vec![];
+    offsets.extend((-1..=1).map(|di| (di, apex)));
+    offsets.extend([-2, -1, 1, 2].iter().map(|&di| (di, 0)));
+    offsets.extend((-2..=2).map(|di| (di, flat)));
+    offsets.sort_by_key(|&(di, dj)| (dj, di));
+    offsets
+}
+
+impl<T: Copy + Debug> Grid<T>
+{
+    // Neighbors of the cell for `kind`, without the cell itself, row by
+    // row for Vertex and by distance for Radius. Cells outside of the
+    // grid are left out.
+    pub fn neighbors_of_kind(&self, (i, j): (usize, usize), kind: NeighborhoodKind) -> Vec<(usize, usize)>
+    {
+        match kind
+        {
+            NeighborhoodKind::Edge => self.neighbor_coords((i, j)).into_iter().skip(1).collect(),
+            NeighborhoodKind::Vertex =>
+            {
+                let center = Coord::from((i, j));
+                vertex_offsets((i, j)).into_iter()
+                    .filter_map(|(di, dj)| center.offset(di, dj, self.dims)?.to_storage(self.dims))
+                    .collect()
+            },
+            NeighborhoodKind::Radius(radius) =>
+            {
+                if self.get((i, j)).is_none()
+                {
+                    return vec![];
+                }
+                // Breadth first, a ring of cells at a time: only the cells
+                // within the radius are ever visited, the 1 + 3r(r + 1)/2
+                // of them inside a large grid.
+                let within = radius.saturating_add(1).saturating_mul(radius).saturating_mul(3) / 2;
+                let mut seen = HashSet::with_capacity(within.min(self.data.len()));
+                seen.insert((i, j));
+                let mut found = vec![];
+                let mut ring = vec![(i, j)];
+                for _ in 0..radius
+                {
+                    let start = found.len();
+                    for coord in ring
+                    {
+                        found.extend(self.neighbor_coords(coord).into_iter().skip(1).filter(|&next| seen.insert(next)));
+                    }
+                    if found.len() == start
+                    {
+                        break;
+                    }
+                    ring = found[start..].to_vec();
+                }
+                found
+            }
+        }
+    }
+
+    // The cell, then every cell at most `radius` edge crossings away, by
+    // distance, as Grid::neighborhood is for a radius of 1.
+    pub fn neighborhood_radius(&self, coord: (usize, usize), radius: usize) -> Vec<&T>
+    {
+        let cell = match self.get(coord)
+        {
+            Some(cell) => cell,
+            None => return vec![]
+        };
+        std::iter::once(cell)
+            .chain(self.neighbors_of_kind(coord, NeighborhoodKind::Radius(radius)).into_iter().map(|ncoord| self.get(ncoord).unwrap()))
+            .collect()
+    }
+
+    // The states of the neighbors of a cell of the grid for `kind`, in the
+    // order of neighbors_of_kind, under a boundary the dims can have:
+    // across the edges of a periodic grid, and for Edge and Vertex, the
+    // cells past the edges of the others as in boundary.rs.
+    pub(crate) fn neighbors_past_edges(&self, (i, j): (usize, usize), kind: NeighborhoodKind, boundary: &BoundaryCondition<T>) -> Vec<T>
+    {
+        let state = |coord| *self.get(coord).unwrap();
+        match (kind, boundary)
+        {
+            (_, BoundaryCondition::Open) => self.neighbors_of_kind((i, j), kind).into_iter().map(state).collect(),
+            (NeighborhoodKind::Edge, _) =>
+            {
+                let (ngh, _) = self.neighborhood_past_edges((i, j), boundary).expect("the cell is in the grid");
+                ngh[1..].to_vec()
+            },
+            (NeighborhoodKind::Vertex, _) =>
+            {
+                let (w, h) = (self.dims.0 as isize, self.dims.1 as isize);
+                let center = Coord::from((i, j));
+                vertex_offsets((i, j)).into_iter()
+                    .filter_map(|(di, dj)| {
+                        if boundary.is_periodic()
+                        {
+                            let (ni, nj) = ((i as isize + di).rem_euclid(w), (j as isize + dj).rem_euclid(h));
+                            return Some(state((ni as usize, nj as usize)));
+                        }
+                        match (center.offset(di, dj, self.dims).and_then(|ncoord| ncoord.to_storage(self.dims)), boundary)
+                        {
+                            (Some(ncoord), _) => Some(state(ncoord)),
+                            (None, BoundaryCondition::Fixed(past)) => Some(*past),
+                            (None, BoundaryCondition::Reflective) => Some(state((i, j))),
+                            (None, _) => None
+                        }
+                    })
+                    .collect()
+            },
+            (NeighborhoodKind::Radius(radius), BoundaryCondition::Periodic) =>
+            {
+                // As neighbors_of_kind, the rings going on across the
+                // edges.
+                let mut seen = HashSet::new();
+                seen.insert((i, j));
+                let mut found = vec![];
+                let mut ring = vec![(i, j)];
+                for _ in 0..radius
+                {
+                    let start = found.len();
+                    for coord in ring
+                    {
+                        found.extend(Slot::ALL.iter()
+                            .filter_map(|&slot| flux::neighbor(self.dims, coord, slot, boundary).ok())
+                            .filter(|&next| seen.insert(next)));
+                    }
+                    if found.len() == start
+                    {
+                        break;
+                    }
+                    ring = found[start..].to_vec();
+                }
+                found.into_iter().map(state).collect()
+            },
+            (NeighborhoodKind::Radius(_), _) => self.neighbors_of_kind((i, j), kind).into_iter().map(state).collect()
+        }
+    }
+}
+
+impl<T: Clone + Display + Copy + Debug> Automata<T>
+{
+    // One step where every cell is given its state and those of its
+    // neighbors for `kind`, in the order of Grid::neighbors_of_kind, under
+    // the automaton's boundary. A cell rule is given the cell, then the
+    // same neighbors.
+    pub fn evolve_with<F>(&mut self, rule: F, kind: NeighborhoodKind)
+    where
+        F: Fn(&T, &[T]) -> T
+    {
+        let boundary = self.boundary;
+        let stepped = self.with_cell_rules(|automata, overrides| automata.try_next_generation_from(|grid, coord| {
+            let cell = grid.get(coord).unwrap();
+            let neighbors = grid.neighbors_past_edges(coord, kind, &boundary);
+            Ok::<T, std::convert::Infallible>(match overrides.get(&coord)
+            {
+                Some(overridden) => overridden.apply([&[*cell], &neighbors[..]].concat()),
+                None => rule(cell, &neighbors)
+            })
+        }));
+        match stepped
+        {
+            Ok(()) => (),
+            Err(error) => match error.error {}
+        }
+    }
+
+    // A Vec rule under any kind: it is given the cell first, then its
+    // neighbors. Edge is the same as evolve. Both follow the automaton's
+    // boundary.
+    pub fn evolve_rule<R: Rule<T>>(&mut self, rule: &R, kind: NeighborhoodKind)
+    {
+        match kind
+        {
+            NeighborhoodKind::Edge => self.evolve(|ngh| rule.apply(ngh)),
+            _ => self.evolve_with(|cell, neighbors| {
+                let mut ngh = Vec::with_capacity(neighbors.len() + 1);
+                ngh.push(*cell);
+                ngh.extend_from_slice(neighbors);
+                rule.apply(ngh)
+            }, kind)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::analysis;
+    use crate::simulation::Simulation;
+    use crate::{validate, vertex};
+
+    fn random_cells(seed: u64, dims: (usize, usize)) -> Grid<bool>
+    {
+        validate::seeded_grid(seed, dims, |rng| rng.below(3) == 0)
+    }
+
+    // Lit when at least two neighbors are.
+    fn crowded(_: &bool, neighbors: &[bool]) -> bool
+    {
+        neighbors.iter().filter(|&&lit| lit).count() >= 2
+    }
+
+    #[test]
+    fn vertex_neighbors_see_more_of_the_grid()
+    {
+        let start = random_cells(4, (16, 10));
+        let (mut edge, mut vertex) = (Automata::new(start.clone()), Automata::new(start.clone()));
+        edge.evolve_with(crowded, NeighborhoodKind::Edge);
+        vertex.evolve_with(crowded, NeighborhoodKind::Vertex);
+        for j in 0..10
+        {
+            for i in 0..16
+            {
+                let count = |kind| start.neighbors_of_kind((i, j), kind).iter().filter(|&&coord| *start.get(coord).unwrap()).count();
+                assert_eq!(*edge.get((i, j)).unwrap(), count(NeighborhoodKind::Edge) >= 2);
+                assert_eq!(*vertex.get((i, j)).unwrap(), count(NeighborhoodKind::Vertex) >= 2);
+                // The edge neighbors are among the vertex ones, so a cell
+                // crowded by the first is crowded by the second.
+                assert!(!*edge.get((i, j)).unwrap() || *vertex.get((i, j)).unwrap());
+            }
+        }
+        let more = start.as_slice().iter().enumerate().filter(|&(n, _)| vertex.current().as_slice()[n] && !edge.current().as_slice()[n]).count();
+        assert!(more > 0);
+    }
+
+    #[test]
+    fn vertex_neighbors_share_a_corner()
+    {
+        let dims = (7, 5);
+        let grid = Grid::from_fn(dims, |coord| coord);
+        for j in 0..5
+        {
+            for i in 0..7
+            {
+                let mut expected: Vec<(usize, usize)> = vertex::vertices(dims)
+                    .map(|v| vertex::incident_faces(dims, v))
+                    .filter(|faces| faces.contains(&(i, j)))
+                    .flatten()
+                    .filter(|&face| face != (i, j))
+                    .collect();
+                expected.sort_by_key(|&(i, j)| (j, i));
+                expected.dedup();
+                assert_eq!(grid.neighbors_of_kind((i, j), NeighborhoodKind::Vertex), expected, "at {:?}", (i, j));
+            }
+        }
+        assert_eq!(grid.neighbors_of_kind((3, 2), NeighborhoodKind::Vertex).len(), 12);
+        assert_eq!(grid.neighbors_of_kind((0, 0), NeighborhoodKind::Vertex), [(1, 0), (2, 0), (0, 1), (1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn simulations_take_the_kind_too()
+    {
+        let start = random_cells(9, (12, 8));
+        let as_vec = |ngh: Vec<bool>| crowded(&ngh[0], &ngh[1..]);
+        let mut simulation = Simulation::new(start.clone(), as_vec);
+        simulation.set_neighborhood(NeighborhoodKind::Vertex);
+        simulation.run(3);
+        let mut automata = Automata::new(start);
+        for _ in 0..3
+        {
+            automata.evolve_with(crowded, NeighborhoodKind::Vertex);
+        }
+        assert_eq!(simulation.current(), automata.current());
+        assert_eq!(simulation.neighborhood(), NeighborhoodKind::Vertex);
+    }
+
+    #[test]
+    fn kinds_parse_from_the_command_line()
+    {
+        assert_eq!("edge".parse(), Ok(NeighborhoodKind::Edge));
+        assert_eq!("vertex".parse(), Ok(NeighborhoodKind::Vertex));
+        assert_eq!("radius=3".parse(), Ok(NeighborhoodKind::Radius(3)));
+        assert!("radius=-1".parse::<NeighborhoodKind>().is_err());
+        assert!("corner".parse::<NeighborhoodKind>().is_err());
+    }
+
+    #[test]
+    fn kinds_go_past_the_edges()
+    {
+        let grid = Grid::from_fn((8, 6), |(i, j)| (i + 10*j) as u8);
+        let vertex = |boundary| grid.neighbors_past_edges((0, 0), NeighborhoodKind::Vertex, &boundary);
+        assert_eq!(vertex(BoundaryCondition::Fixed(99)), [99, 99, 99, 99, 99, 1, 2, 99, 99, 10, 11, 12]);
+        assert_eq!(vertex(BoundaryCondition::Reflective), [0, 0, 0, 0, 0, 1, 2, 0, 0, 10, 11, 12]);
+        assert_eq!(vertex(BoundaryCondition::Periodic), [57, 50, 51, 6, 7, 1, 2, 16, 17, 10, 11, 12]);
+        for coord in (0..6).flat_map(|j| (0..8).map(move |i| (i, j)))
+        {
+            let open: Vec<u8> = grid.neighbors_of_kind(coord, NeighborhoodKind::Vertex).into_iter().map(|ncoord| *grid.get(ncoord).unwrap()).collect();
+            assert_eq!(grid.neighbors_past_edges(coord, NeighborhoodKind::Vertex, &BoundaryCondition::Open), open);
+            assert_eq!(grid.neighbors_past_edges(coord, NeighborhoodKind::Vertex, &BoundaryCondition::Fixed(99)).len(), 12);
+            assert_eq!(grid.neighbors_past_edges(coord, NeighborhoodKind::Edge, &BoundaryCondition::Reflective).len(), 3);
+            // On the torus, every cell has the rings of a cell far from the
+            // edges, 3 and 6 cells.
+            assert_eq!(grid.neighbors_past_edges(coord, NeighborhoodKind::Radius(2), &BoundaryCondition::Periodic).len(), 9);
+        }
+        // Radius neighborhoods leave the cells past fixed edges out.
+        assert_eq!(grid.neighbors_past_edges((0, 0), NeighborhoodKind::Radius(2), &BoundaryCondition::Fixed(99)).len(),
+                   grid.neighbors_of_kind((0, 0), NeighborhoodKind::Radius(2)).len());
+    }
+
+    #[test]
+    fn periodic_kinds_commute_with_shifts()
+    {
+        let start = random_cells(6, (12, 8));
+        // Shifting by (3, 1) keeps every triangle pointing the same way.
+        let shift = |grid: &Grid<bool>| Grid::from_fn(grid.dims, |(i, j)| *grid.get(((i + 9) % 12, (j + 7) % 8)).unwrap());
+        for &kind in &[NeighborhoodKind::Vertex, NeighborhoodKind::Radius(2)]
+        {
+            let run = |start: Grid<bool>| {
+                let mut automata = Automata::new(start);
+                automata.set_boundary(BoundaryCondition::Periodic).unwrap();
+                for _ in 0..4
+                {
+                    automata.evolve_with(crowded, kind);
+                }
+                automata.current().clone()
+            };
+            assert_eq!(run(shift(&start)), shift(&run(start.clone())), "{:?}", kind);
+        }
+    }
+
+    // Cells of the lattice are the nodes of a honeycomb, with 3d of them at
+    // distance d: inside a large grid, a radius r gathers 1 + 3r(r + 1)/2
+    // cells, the cell first and nearer ones before farther ones.
+    #[test]
+    fn radius_neighborhoods_grow_by_rings()
+    {
+        let grid = Grid::from_fn((40, 20), |(i, j)| (i, j));
+        for &coord in &[(20, 10), (21, 10)]
+        {
+            let field = analysis::distance_field(&grid, &[coord], |_| true);
+            for radius in 0..6
+            {
+                let cells = grid.neighborhood_radius(coord, radius);
+                let distances: Vec<u32> = cells.iter().map(|&&cell| field.get(cell).unwrap().unwrap()).collect();
+                assert_eq!(cells.len(), 1 + 3*radius*(radius + 1)/2, "radius {} around {:?}", radius, coord);
+                assert_eq!(*cells[0], coord);
+                assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", distances);
+                assert_eq!(distances.last(), Some(&(radius as u32)));
+            }
+            assert_eq!(grid.neighborhood_radius(coord, 1), grid.neighborhood(coord));
+        }
+        // Near the edges, the cells past them are left out.
+        assert_eq!(grid.neighborhood_radius((0, 0), 1).len(), 3);
+        assert!(grid.neighborhood_radius((40, 0), 2).is_empty());
+    }
+}
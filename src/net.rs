@@ -0,0 +1,208 @@
+// Streams generations to a remote viewer over TCP. Every frame is a
+// checkpoint snapshot (see checkpoint.rs) of the current generation and
+// its step, preceded by its length as a big endian u32.
+
+use crate::{Automata, Grid};
+use crate::checkpoint::{self, SnapshotError};
+use crate::codec::CellCodec;
+
+use std::fmt::{Debug, Display};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Frames waiting for a slow client before new ones get dropped.
+const QUEUE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StreamStats
+{
+    pub sent: usize,
+    pub dropped: usize
+}
+
+fn write_frame<W: Write>(writer: &mut W, frame: &[u8]) -> io::Result<()>
+{
+    writer.write_all(&(frame.len() as u32).to_be_bytes())?;
+    writer.write_all(frame)?;
+    writer.flush()
+}
+
+pub fn serve<T, F, A>(automata: &mut Automata<T>, rule: F, steps: usize, fps: f64, addr: A) -> io::Result<StreamStats>
+where
+    T: Clone + Display + Copy + Debug + PartialEq + CellCodec,
+    F: Fn(Vec<T>) -> T,
+    A: ToSocketAddrs
+{
+    serve_on(automata, rule, steps, fps, TcpListener::bind(addr)?)
+}
+
+// Waits for one client, then sends the current generation and the next
+// `steps` ones, at most `fps` per second (as fast as possible when fps is
+// not positive). The simulation never waits for the client: frames it has
+// not taken yet pile up in a small queue and, once the queue is full, new
+// frames are dropped. The last frame is always sent.
+pub fn serve_on<T, F>(automata: &mut Automata<T>, rule: F, steps: usize, fps: f64, listener: TcpListener) -> io::Result<StreamStats>
+where
+    T: Clone + Display + Copy + Debug + PartialEq + CellCodec,
+    F: Fn(Vec<T>) -> T
+{
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(QUEUE);
+    let writer = thread::spawn(move || -> io::Result<()> {
+        let mut writer = BufWriter::new(stream);
+        for frame in receiver
+        {
+            write_frame(&mut writer, &frame)?;
+        }
+        Ok(())
+    });
+
+    let period = if fps > 0.0 { Some(Duration::from_secs_f64(1.0 / fps)) } else { None };
+    let mut stats = StreamStats::default();
+    let mut disconnected = false;
+    for n in 0..=steps
+    {
+        let started = Instant::now();
+        if n > 0
+        {
+            automata.evolve(&rule);
+        }
+        let frame = checkpoint::encode(automata.current(), automata.step());
+        let sent = if n == steps
+        {
+            sender.send(frame).map(|_| true).map_err(|_| ())
+        }
+        else
+        {
+            match sender.try_send(frame)
+            {
+                Ok(()) => Ok(true),
+                Err(TrySendError::Full(_)) => Ok(false),
+                Err(TrySendError::Disconnected(_)) => Err(())
+            }
+        };
+        match sent
+        {
+            Ok(true) => stats.sent += 1,
+            Ok(false) => stats.dropped += 1,
+            Err(()) =>
+            {
+                disconnected = true;
+                break;
+            }
+        }
+        if let Some(period) = period
+        {
+            if let Some(rest) = period.checked_sub(started.elapsed())
+            {
+                thread::sleep(rest);
+            }
+        }
+    }
+    drop(sender);
+    let written = writer.join().unwrap_or_else(|_| Err(io::Error::other("writer thread panicked")));
+    match written
+    {
+        // The writer only stops early on an error, which it returns.
+        Ok(()) if disconnected => Err(io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected")),
+        Ok(()) => Ok(stats),
+        Err(error) => Err(error)
+    }
+}
+
+// Frames received from `serve`, with their step, until the server closes
+// the connection.
+pub struct Frames<T>
+{
+    reader: BufReader<TcpStream>,
+    cells: PhantomData<T>
+}
+
+pub fn connect<T: CellCodec, A: ToSocketAddrs>(addr: A) -> io::Result<Frames<T>>
+{
+    Ok(Frames{reader: BufReader::new(TcpStream::connect(addr)?), cells: PhantomData})
+}
+
+impl<T: Copy + Debug + CellCodec> Iterator for Frames<T>
+{
+    type Item = Result<(Grid<T>, u64), SnapshotError>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        let mut length = [0; 4];
+        match self.reader.read_exact(&mut length)
+        {
+            Ok(()) => (),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(error) => return Some(Err(SnapshotError::Io(error)))
+        }
+        let mut frame = vec![0; u32::from_be_bytes(length) as usize];
+        if let Err(error) = self.reader.read_exact(&mut frame)
+        {
+            return Some(Err(error.into()));
+        }
+        Some(checkpoint::decode(frame.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, Light};
+
+    type Received = Vec<(Grid<Light>, u64)>;
+
+    // The light demo, served to a client on this machine.
+    fn stream(steps: usize, fps: f64) -> (StreamStats, Received, Automata<Light>)
+    {
+        let mut automata = Automata::new(Grid::new((30, 20), Light::Space(0)));
+        *automata.get_mut((10, 10)).unwrap() = Light::Source(10);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || connect::<Light, _>(addr).unwrap().collect::<Result<Vec<_>, _>>().unwrap());
+        let stats = serve_on(&mut automata, rules::light_falloff, steps, fps, listener).unwrap();
+        (stats, client.join().unwrap(), automata)
+    }
+
+    #[test]
+    fn ten_frames_over_localhost()
+    {
+        let (stats, frames, automata) = stream(9, 50.0);
+        assert_eq!(stats.sent + stats.dropped, 10);
+        assert_eq!(frames.len(), stats.sent);
+        assert_eq!(frames[0], (Grid::from_fn((30, 20), |coord| if coord == (10, 10) {Light::Source(10)} else {Light::Space(0)}), 0));
+        let (last, step) = frames.last().unwrap();
+        assert_eq!((last, *step), (automata.current(), 9));
+    }
+
+    // Unpaced, the simulation may outrun the writer: whatever was dropped,
+    // the frames received are those sent, in order, ending on the last.
+    #[test]
+    fn frames_are_dropped_not_reordered()
+    {
+        let (stats, frames, automata) = stream(200, 0.0);
+        assert_eq!(stats.sent + stats.dropped, 201);
+        assert_eq!(frames.len(), stats.sent);
+        assert!(frames.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        assert_eq!(frames.last().map(|(grid, step)| (grid, *step)), Some((automata.current(), 200)));
+    }
+
+    #[test]
+    fn a_client_leaving_stops_the_server()
+    {
+        let mut automata = Automata::new(Grid::new((30, 20), Light::Space(0)));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || drop(TcpStream::connect(addr).unwrap()));
+        let served = serve_on(&mut automata, rules::light_falloff, 100_000, 1000.0, listener);
+        client.join().unwrap();
+        assert!(served.is_err());
+        assert!(automata.step() < 100_000);
+    }
+}
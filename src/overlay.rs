@@ -0,0 +1,192 @@
+// Annotations drawn over a grid without touching its cells: a highlight,
+// in a color or the renderer's default (inverse video in the terminal),
+// and an optional short label. render::render_overlaid, the SVG heatmap
+// and the raster images take one.
+
+use crate::Grid;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Debug;
+
+// Highlight of overlays without a color, in SVG and images.
+pub const DEFAULT_HIGHLIGHT: [u8; 3] = [255, 220, 0];
+
+// Colors given in turn to the components of from_components.
+const COMPONENT_COLORS: [[u8; 3]; 6] = [[31, 119, 180], [214, 39, 40], [44, 160, 44], [255, 127, 14], [148, 103, 189], [140, 86, 75]];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Annotation
+{
+    pub color: Option<[u8; 3]>,
+    // A few characters: the compact renderings show the first one only,
+    // the full one three.
+    pub label: Option<String>
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Overlay
+{
+    // Ordered, so that the renderers that list them do it the same way
+    // every time.
+    marks: BTreeMap<(usize, usize), Annotation>
+}
+
+impl Overlay
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    // Highlights the cell in the renderer's default way, keeping its label.
+    pub fn mark(&mut self, coord: (usize, usize)) -> &mut Self
+    {
+        self.marks.entry(coord).or_default();
+        self
+    }
+
+    pub fn highlight(&mut self, coord: (usize, usize), color: [u8; 3]) -> &mut Self
+    {
+        self.marks.entry(coord).or_default().color = Some(color);
+        self
+    }
+
+    pub fn label(&mut self, coord: (usize, usize), text: &str) -> &mut Self
+    {
+        self.marks.entry(coord).or_default().label = Some(text.to_string());
+        self
+    }
+
+    pub fn get(&self, coord: (usize, usize)) -> Option<&Annotation>
+    {
+        self.marks.get(&coord)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&(usize, usize), &Annotation)>
+    {
+        self.marks.iter()
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.marks.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.marks.is_empty()
+    }
+
+    // The cells that differ between the two generations, in `color`. Only
+    // the cells that both grids have are compared.
+    pub fn from_changes<T: Copy + Debug + PartialEq>(before: &Grid<T>, after: &Grid<T>, color: [u8; 3]) -> Self
+    {
+        let mut overlay = Self::new();
+        for j in 0..before.dims.1.min(after.dims.1)
+        {
+            for i in 0..before.dims.0.min(after.dims.0)
+            {
+                if before.get((i, j)) != after.get((i, j))
+                {
+                    overlay.highlight((i, j), color);
+                }
+            }
+        }
+        overlay
+    }
+
+    // The sets of edge-connected cells passing `include`, each in its own
+    // color and labeled with its number, from 1 in storage order.
+    pub fn from_components<T, F>(grid: &Grid<T>, include: F) -> Self
+    where
+        T: Copy + Debug,
+        F: Fn(&T) -> bool
+    {
+        let mut overlay = Self::new();
+        let mut component = 0;
+        for j in 0..grid.dims.1
+        {
+            for i in 0..grid.dims.0
+            {
+                if overlay.get((i, j)).is_some() || !include(grid.get((i, j)).unwrap())
+                {
+                    continue;
+                }
+                component += 1;
+                let color = COMPONENT_COLORS[(component - 1) % COMPONENT_COLORS.len()];
+                let label = component.to_string();
+                let mut queue = VecDeque::from(vec![(i, j)]);
+                overlay.highlight((i, j), color).label((i, j), &label);
+                while let Some(coord) = queue.pop_front()
+                {
+                    for next in grid.neighbor_coords(coord).into_iter().skip(1)
+                    {
+                        if overlay.get(next).is_none() && include(grid.get(next).unwrap())
+                        {
+                            overlay.highlight(next, color).label(next, &label);
+                            queue.push_back(next);
+                        }
+                    }
+                }
+            }
+        }
+        overlay
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const RED: [u8; 3] = [255, 0, 0];
+
+    #[test]
+    fn marks_combine()
+    {
+        let mut overlay = Overlay::new();
+        assert!(overlay.is_empty());
+        overlay.label((2, 1), "bfs").mark((2, 1)).highlight((0, 0), RED);
+        assert_eq!(overlay.get((2, 1)), Some(&Annotation{color: None, label: Some("bfs".to_string())}));
+        overlay.highlight((2, 1), RED);
+        assert_eq!(overlay.get((2, 1)).unwrap().color, Some(RED));
+        assert_eq!(overlay.get((1, 1)), None);
+        let coords: Vec<_> = overlay.iter().map(|(&coord, _)| coord).collect();
+        assert_eq!(coords, vec![(0, 0), (2, 1)]);
+        assert_eq!(overlay.len(), 2);
+    }
+
+    #[test]
+    fn changes_are_highlighted()
+    {
+        let before = Grid::new((5, 3), 0u8);
+        let mut after = before.clone();
+        *after.get_mut((4, 2)).unwrap() = 1;
+        *after.get_mut((0, 1)).unwrap() = 2;
+        let overlay = Overlay::from_changes(&before, &after, RED);
+        let coords: Vec<_> = overlay.iter().map(|(&coord, annotation)| (coord, annotation.color)).collect();
+        assert_eq!(coords, vec![((0, 1), Some(RED)), ((4, 2), Some(RED))]);
+        // Only the cells both grids have.
+        assert_eq!(Overlay::from_changes(&after, &Grid::new((3, 3), 0u8), RED).len(), 1);
+    }
+
+    #[test]
+    fn components_are_numbered()
+    {
+        // Two runs of a row, then a lone cell below nothing.
+        let grid = Grid::from_fn((8, 2), |(i, j)| (j == 0 && i != 3) || (i, j) == (3, 1));
+        let overlay = Overlay::from_components(&grid, |&cell| cell);
+        let label = |coord| overlay.get(coord).and_then(|annotation| annotation.label.clone());
+        assert_eq!(label((0, 0)).as_deref(), Some("1"));
+        assert_eq!(label((2, 0)).as_deref(), Some("1"));
+        assert_eq!(label((4, 0)).as_deref(), Some("2"));
+        assert_eq!(label((7, 0)).as_deref(), Some("2"));
+        assert_eq!(label((3, 0)), None);
+        // (3, 1) points up: its neighbors are on its row only.
+        assert_eq!(label((3, 1)).as_deref(), Some("3"));
+        assert_eq!(overlay.len(), 8);
+        let color = |coord| overlay.get(coord).unwrap().color;
+        assert_eq!(color((0, 0)), color((2, 0)));
+        assert_ne!(color((0, 0)), color((4, 0)));
+    }
+}
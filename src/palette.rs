@@ -0,0 +1,380 @@
+// Palettes: lists of colors used as continuous ramps or, for states that
+// are not ordered, as discrete colors. Built-in ones can be read by
+// colorblind users; others are given in hex, in code or in a small TOML
+// file:
+//
+//   name = "brand"
+//   kind = "discrete"        # or "continuous", the default
+//   colors = ["#112233", "#445566", "#778899"]
+//
+// Registered palettes are looked up by name through ColorMap::named, so
+// every output taking a ColorMap takes them. They live as long as the
+// program.
+
+use crate::color::ColorMap;
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette
+{
+    pub name: String,
+    pub colors: Vec<(u8, u8, u8)>,
+    // Colors taken as they are, rather than interpolated.
+    pub discrete: bool
+}
+
+#[derive(Debug)]
+pub enum PaletteError
+{
+    // The index of the color in the list.
+    BadHex{index: usize, text: String},
+    Empty,
+    UnknownName{name: String, known: Vec<String>},
+    AlreadyRegistered(String),
+    Syntax{line: usize, message: String},
+    Io(io::Error)
+}
+
+impl fmt::Display for PaletteError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            PaletteError::BadHex{index, text} => write!(f, "color {}: '{}' is not #rgb or #rrggbb", index, text),
+            PaletteError::Empty => write!(f, "a palette needs at least one color"),
+            PaletteError::UnknownName{name, known} => write!(f, "unknown palette '{}' ({})", name, known.join(", ")),
+            PaletteError::AlreadyRegistered(name) => write!(f, "a palette named '{}' is already registered", name),
+            PaletteError::Syntax{line, message} => write!(f, "line {}: {}", line, message),
+            PaletteError::Io(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+impl From<io::Error> for PaletteError
+{
+    fn from(error: io::Error) -> Self
+    {
+        PaletteError::Io(error)
+    }
+}
+
+// Okabe and Ito's colors, told apart under the common color blindnesses.
+const OKABE_ITO: [(u8, u8, u8); 8] = [
+    (0, 0, 0), (230, 159, 0), (86, 180, 233), (0, 158, 115),
+    (240, 228, 66), (0, 114, 178), (213, 94, 0), (204, 121, 167)
+];
+
+// Paul Tol's bright scheme, also colorblind safe.
+const TOL_BRIGHT: [(u8, u8, u8); 7] = [
+    (68, 119, 170), (238, 102, 119), (34, 136, 51), (204, 187, 68),
+    (102, 204, 238), (170, 51, 119), (187, 187, 187)
+];
+
+// Perceptually uniform ramps sampled from matplotlib's magma and inferno,
+// which keep their order in grays too.
+const MAGMA: [(u8, u8, u8); 9] = [
+    (0, 0, 4), (28, 16, 68), (79, 18, 123), (129, 37, 129), (181, 54, 122),
+    (229, 80, 100), (251, 135, 97), (254, 194, 135), (252, 253, 191)
+];
+
+const INFERNO: [(u8, u8, u8); 9] = [
+    (0, 0, 4), (31, 12, 72), (85, 15, 109), (136, 34, 106), (186, 54, 85),
+    (227, 89, 51), (249, 140, 10), (249, 201, 50), (252, 255, 164)
+];
+
+fn parse_hex(text: &str) -> Option<(u8, u8, u8)>
+{
+    let digits = text.strip_prefix('#')?;
+    if !digits.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return None;
+    }
+    let channel = |k: usize, len: usize| u8::from_str_radix(&digits[k*len..(k + 1)*len], 16).ok();
+    match digits.len()
+    {
+        // #abc is #aabbcc.
+        3 => Some((channel(0, 1)? * 17, channel(1, 1)? * 17, channel(2, 1)? * 17)),
+        6 => Some((channel(0, 2)?, channel(1, 2)?, channel(2, 2)?)),
+        _ => None
+    }
+}
+
+impl Palette
+{
+    pub fn new(name: &str, colors: &[(u8, u8, u8)], discrete: bool) -> Self
+    {
+        Self{name: name.to_string(), colors: colors.to_vec(), discrete}
+    }
+
+    // A continuous palette without a name, see named and discrete.
+    pub fn from_hex(colors: &[&str]) -> Result<Self, PaletteError>
+    {
+        if colors.is_empty()
+        {
+            return Err(PaletteError::Empty);
+        }
+        let colors = colors.iter()
+            .enumerate()
+            .map(|(index, text)| parse_hex(text.trim()).ok_or_else(|| PaletteError::BadHex{index, text: text.to_string()}))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self{name: String::new(), colors, discrete: false})
+    }
+
+    pub fn named(mut self, name: &str) -> Self
+    {
+        self.name = name.to_string();
+        self
+    }
+
+    pub fn discrete(mut self) -> Self
+    {
+        self.discrete = true;
+        self
+    }
+
+    // Clamped to [0, 1], NaN drawn as 0, like ColorMap::color. Discrete
+    // palettes split [0, 1] into as many equal parts as they have colors.
+    pub fn color(&self, t: f64) -> (u8, u8, u8)
+    {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        if self.colors.len() == 1
+        {
+            return self.colors[0];
+        }
+        if self.discrete
+        {
+            self.colors[((t * self.colors.len() as f64) as usize).min(self.colors.len() - 1)]
+        }
+        else
+        {
+            crate::color::interpolate(&self.colors, t)
+        }
+    }
+
+    // Color of the k-th state of an enum-like type, wrapping around.
+    pub fn index(&self, k: usize) -> (u8, u8, u8)
+    {
+        self.colors[k % self.colors.len()]
+    }
+
+    pub fn parse_toml(text: &str) -> Result<Self, PaletteError>
+    {
+        let syntax = |line: usize, message: &str| PaletteError::Syntax{line, message: message.to_string()};
+        let (mut name, mut discrete, mut colors) = (None, false, None);
+        let mut lines = text.lines().enumerate();
+        while let Some((n, line)) = lines.next()
+        {
+            let line = strip_comment(line);
+            if line.trim().is_empty()
+            {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| syntax(n + 1, "expected 'key = value'"))?;
+            let mut value = value.trim().to_string();
+            match key.trim()
+            {
+                "name" => name = Some(unquote(&value).ok_or_else(|| syntax(n + 1, "expected a quoted name"))?),
+                "kind" => discrete = match unquote(&value).as_deref()
+                {
+                    Some("discrete") => true,
+                    Some("continuous") => false,
+                    _ => return Err(syntax(n + 1, "expected \"discrete\" or \"continuous\""))
+                },
+                "colors" =>
+                {
+                    // Arrays may go on over several lines.
+                    while !value.ends_with(']')
+                    {
+                        let (_, more) = lines.next().ok_or_else(|| syntax(n + 1, "unclosed array"))?;
+                        value.push_str(strip_comment(more).trim());
+                    }
+                    let items = value.strip_prefix('[').and_then(|rest| rest.strip_suffix(']'))
+                        .ok_or_else(|| syntax(n + 1, "expected an array of colors"))?;
+                    let items = items.split(',')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(|item| unquote(item).ok_or_else(|| syntax(n + 1, "expected quoted colors")))
+                        .collect::<Result<Vec<String>, _>>()?;
+                    let items: Vec<&str> = items.iter().map(String::as_str).collect();
+                    colors = Some(Palette::from_hex(&items)?.colors);
+                },
+                other => return Err(syntax(n + 1, &format!("unknown key '{}'", other)))
+            }
+        }
+        let colors = colors.ok_or(PaletteError::Empty)?;
+        Ok(Self{name: name.unwrap_or_default(), colors, discrete})
+    }
+
+    // The file's own name is used when it does not give one.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PaletteError>
+    {
+        let path = path.as_ref();
+        let mut palette = Self::parse_toml(&std::fs::read_to_string(path)?)?;
+        if palette.name.is_empty()
+        {
+            palette.name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        }
+        Ok(palette)
+    }
+}
+
+// A # starts a comment unless it is in a string, as in colors.
+pub(crate) fn strip_comment(line: &str) -> &str
+{
+    let mut quoted = false;
+    for (k, c) in line.char_indices()
+    {
+        match c
+        {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..k],
+            _ => ()
+        }
+    }
+    line
+}
+
+pub(crate) fn unquote(text: &str) -> Option<String>
+{
+    text.trim().strip_prefix('"')?.strip_suffix('"').map(str::to_string)
+}
+
+fn registry() -> &'static Mutex<Vec<&'static Palette>>
+{
+    static PALETTES: OnceLock<Mutex<Vec<&'static Palette>>> = OnceLock::new();
+    PALETTES.get_or_init(|| Mutex::new(vec![
+        Box::leak(Box::new(Palette::new("okabe-ito", &OKABE_ITO, true))),
+        Box::leak(Box::new(Palette::new("tol-bright", &TOL_BRIGHT, true))),
+        Box::leak(Box::new(Palette::new("magma", &MAGMA, false))),
+        Box::leak(Box::new(Palette::new("inferno", &INFERNO, false)))
+    ]))
+}
+
+// Makes the palette available by name, as the ColorMap it returns.
+pub fn register(palette: Palette) -> Result<ColorMap, PaletteError>
+{
+    if palette.colors.is_empty()
+    {
+        return Err(PaletteError::Empty);
+    }
+    if ColorMap::named(&palette.name).is_ok()
+    {
+        return Err(PaletteError::AlreadyRegistered(palette.name));
+    }
+    let palette: &'static Palette = Box::leak(Box::new(palette));
+    registry().lock().unwrap().push(palette);
+    Ok(ColorMap::Palette(palette))
+}
+
+pub fn get(name: &str) -> Option<&'static Palette>
+{
+    registry().lock().unwrap().iter().find(|palette| palette.name == name).copied()
+}
+
+// In registration order, the built-in ones first.
+pub fn names() -> Vec<String>
+{
+    registry().lock().unwrap().iter().map(|palette| palette.name.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn hex_colors()
+    {
+        let palette = Palette::from_hex(&["#112233", " #fff ", "#A0b0C0"]).unwrap();
+        assert_eq!(palette.colors, [(17, 34, 51), (255, 255, 255), (160, 176, 192)]);
+        assert!(!palette.discrete);
+        for (bad, index) in [("112233", 1), ("#12345", 1), ("#ggg", 1), ("#", 1), ("#1122334", 1), ("#ff00ü", 1)].iter().copied()
+        {
+            match Palette::from_hex(&["#000", bad])
+            {
+                Err(PaletteError::BadHex{index: found, text}) => assert_eq!((found, text.as_str()), (index, bad)),
+                other => panic!("'{}' gave {:?}", bad, other)
+            }
+        }
+        assert!(matches!(Palette::from_hex(&[]), Err(PaletteError::Empty)));
+        assert_eq!(Palette::from_hex(&["#12"]).unwrap_err().to_string(), "color 0: '#12' is not #rgb or #rrggbb");
+    }
+
+    #[test]
+    fn ramps_and_discrete_colors()
+    {
+        let ramp = Palette::from_hex(&["#000000", "#ff0000", "#ffffff"]).unwrap();
+        assert_eq!(ramp.color(0.0), (0, 0, 0));
+        assert_eq!(ramp.color(0.5), (255, 0, 0));
+        assert_eq!(ramp.color(1.0), (255, 255, 255));
+        assert_eq!(ramp.color(0.25), (128, 0, 0));
+        assert_eq!((ramp.color(-3.0), ramp.color(7.0), ramp.color(f64::NAN)), ((0, 0, 0), (255, 255, 255), (0, 0, 0)));
+
+        let steps = ramp.clone().discrete();
+        assert_eq!(steps.color(0.0), (0, 0, 0));
+        assert_eq!(steps.color(0.4), (255, 0, 0));
+        assert_eq!(steps.color(1.0), (255, 255, 255));
+        assert_eq!((steps.index(1), steps.index(4)), ((255, 0, 0), (255, 0, 0)));
+        assert_eq!(Palette::from_hex(&["#123"]).unwrap().color(0.7), (17, 34, 51));
+    }
+
+    #[test]
+    fn built_in_names_resolve()
+    {
+        for (name, discrete) in [("okabe-ito", true), ("tol-bright", true), ("magma", false), ("inferno", false)].iter().copied()
+        {
+            let palette = get(name).unwrap();
+            assert_eq!(palette.discrete, discrete);
+            assert_eq!(ColorMap::named(name).unwrap(), ColorMap::Palette(palette));
+            assert!(names().contains(&name.to_string()));
+        }
+        assert_eq!(ColorMap::named("magma").unwrap().color(0.0), MAGMA[0]);
+        assert_eq!(ColorMap::named("magma").unwrap().color(1.0), MAGMA[8]);
+        match ColorMap::named("sepia")
+        {
+            Err(error @ PaletteError::UnknownName{..}) => assert!(error.to_string().starts_with("unknown palette 'sepia' (gray, heat, viridis, okabe-ito")),
+            other => panic!("{:?}", other)
+        }
+    }
+
+    #[test]
+    fn user_palettes_register_once()
+    {
+        let name = format!("brand-{}", std::process::id());
+        let palette = Palette::from_hex(&["#112233", "#445566"]).unwrap().named(&name).discrete();
+        let map = register(palette.clone()).unwrap();
+        assert_eq!(map, ColorMap::named(&name).unwrap());
+        assert_eq!(map.hex(1.0), "#445566");
+        assert!(matches!(register(palette), Err(PaletteError::AlreadyRegistered(_))));
+        assert!(matches!(register(Palette::new("none", &[], false)), Err(PaletteError::Empty)));
+        assert!(matches!(register(Palette::new("gray", &[(1, 2, 3)], false)), Err(PaletteError::AlreadyRegistered(_))));
+    }
+
+    #[test]
+    fn palette_files()
+    {
+        let text = "# figures\nname = \"brand\"\nkind = \"discrete\"   # told apart\ncolors = [\"#112233\",\n  \"#445566\", # second\n  \"#778899\"]\n";
+        assert_eq!(Palette::parse_toml(text).unwrap(), Palette::new("brand", &[(17, 34, 51), (68, 85, 102), (119, 136, 153)], true));
+        let error = |text: &str| Palette::parse_toml(text).unwrap_err().to_string();
+        assert_eq!(error("colors = [\"#123\"]\nkind = \"loud\"\n"), "line 2: expected \"discrete\" or \"continuous\"");
+        assert_eq!(error("colors = [\"#123\",\n"), "line 1: unclosed array");
+        assert_eq!(error("shade = 3\n"), "line 1: unknown key 'shade'");
+        assert_eq!(error("name = brand\n"), "line 1: expected a quoted name");
+        assert_eq!(error("name = \"brand\"\n"), "a palette needs at least one color");
+        assert_eq!(error("colors = [\"#1234\"]\n"), "color 0: '#1234' is not #rgb or #rrggbb");
+
+        let path = std::env::temp_dir().join(format!("triangle-automata-{}-sunset.toml", std::process::id()));
+        std::fs::write(&path, "colors = [\"#f00\", \"#00f\"]\n").unwrap();
+        let loaded = Palette::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.name, format!("triangle-automata-{}-sunset", std::process::id()));
+        assert_eq!(loaded.colors, [(255, 0, 0), (0, 0, 255)]);
+        assert!(matches!(Palette::load(&path), Err(PaletteError::Io(_))));
+    }
+}
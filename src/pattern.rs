@@ -0,0 +1,348 @@
+// Pieces of grids that remember the orientation of their first cell, so
+// that they are only stamped where every triangle keeps pointing the same
+// way.
+
+use crate::Grid;
+use crate::error::{check_dims, GridError};
+
+use std::fmt::{self, Debug};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern<T>
+{
+    pub cells: Grid<T>,
+    // 0 when cell (0, 0) of the pattern points up, 1 when it points down.
+    pub parity: usize
+}
+
+// A stamp would have put the pattern's cells on triangles of the other
+// orientation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParityMismatch
+{
+    pub offset: (usize, usize),
+    pub parity: usize
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternParseError
+{
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for PatternParseError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl<T: Copy + Debug> Pattern<T>
+{
+    pub fn dims(&self) -> (usize, usize)
+    {
+        self.cells.dims
+    }
+
+    // Mirror across the vertical axis, in any width: the parity follows.
+    pub fn mirror_x(&self) -> Self
+    {
+        let (w, _) = self.cells.dims;
+        Self{
+            cells: Grid::from_fn(self.cells.dims, |(i, j)| *self.cells.get((w-1-i, j)).unwrap()),
+            parity: (self.parity + w + 1) % 2
+        }
+    }
+
+    // Flip across the horizontal axis, in any height.
+    pub fn flip_y(&self) -> Self
+    {
+        let (_, h) = self.cells.dims;
+        Self{
+            cells: Grid::from_fn(self.cells.dims, |(i, j)| *self.cells.get((i, h-1-j)).unwrap()),
+            parity: (self.parity + h) % 2
+        }
+    }
+
+    // A header line `pattern <width> <height> up|down`, then one line per
+    // row with the cells written by `cell`, separated by spaces (so `cell`
+    // must not write any).
+    pub fn to_text<F: Fn(&T) -> String>(&self, cell: F) -> String
+    {
+        let (w, h) = self.cells.dims;
+        let mut out = format!("pattern {} {} {}\n", w, h, if self.parity == 0 { "up" } else { "down" });
+        for j in 0..h
+        {
+            let row: Vec<String> = self.cells.row(j).iter().map(&cell).collect();
+            out.push_str(&row.join(" "));
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn from_text<F>(text: &str, cell: F) -> Result<Self, PatternParseError>
+    where
+        F: Fn(&str) -> Result<T, String>
+    {
+        let error = |line: usize, message: String| PatternParseError{line, message};
+        let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+        let (_, header) = lines.next().ok_or_else(|| error(1, "empty pattern".to_string()))?;
+        let words: Vec<&str> = header.split_whitespace().collect();
+        let (w, h, parity) = match words.as_slice()
+        {
+            ["pattern", w, h, orientation] =>
+            {
+                let w = w.parse().map_err(|_| error(1, format!("invalid width '{}'", w)))?;
+                let h = h.parse().map_err(|_| error(1, format!("invalid height '{}'", h)))?;
+                let parity = match *orientation
+                {
+                    "up" => 0,
+                    "down" => 1,
+                    other => return Err(error(1, format!("expected up or down, not '{}'", other)))
+                };
+                (w, h, parity)
+            },
+            _ => return Err(error(1, "expected 'pattern <width> <height> up|down'".to_string()))
+        };
+
+        let cells = check_dims((w, h)).map_err(|too_large| error(1, too_large.to_string()))?;
+        let mut data = Vec::with_capacity(cells);
+        for _ in 0..h
+        {
+            let (n, line) = lines.next().ok_or_else(|| error(text.lines().count(), format!("expected {} rows", h)))?;
+            let row = line.split_whitespace()
+                .map(|word| cell(word).map_err(|message| error(n + 1, message)))
+                .collect::<Result<Vec<T>, _>>()?;
+            if row.len() != w
+            {
+                return Err(error(n + 1, format!("expected {} cells, found {}", w, row.len())));
+            }
+            data.extend(row);
+        }
+        if let Some((n, _)) = lines.next()
+        {
+            return Err(error(n + 1, "unexpected row after the pattern".to_string()));
+        }
+        Ok(Self{cells: Grid{data, dims: (w, h)}, parity})
+    }
+}
+
+impl<T: Copy + Debug> Grid<T>
+{
+    // The cells of the (origin, dims) rectangle, or None if it does not fit
+    // in the grid.
+    pub fn extract(&self, origin: (usize, usize), dims: (usize, usize)) -> Option<Pattern<T>>
+    {
+        if origin.0 + dims.0 > self.dims.0 || origin.1 + dims.1 > self.dims.1
+        {
+            return None;
+        }
+        Some(Pattern{
+            cells: Grid::from_fn(dims, |(i, j)| *self.get((origin.0 + i, origin.1 + j)).unwrap()),
+            parity: (origin.0 + origin.1) % 2
+        })
+    }
+
+    // Copies the pattern with its first cell at `offset`, provided the
+    // orientations match. Cells falling outside of the grid are dropped.
+    pub fn stamp(&mut self, pattern: &Pattern<T>, offset: (usize, usize)) -> Result<(), ParityMismatch>
+    {
+        if (offset.0 + offset.1) % 2 != pattern.parity
+        {
+            return Err(ParityMismatch{offset, parity: pattern.parity});
+        }
+        for j in 0..pattern.cells.dims.1
+        {
+            for i in 0..pattern.cells.dims.0
+            {
+                if let Some(cell) = self.get_mut((offset.0 + i, offset.1 + j))
+                {
+                    *cell = *pattern.cells.get((i, j)).unwrap();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Repeats the pattern over the whole grid, one copy having its first
+    // cell at `origin`. Copies side by side keep their orientation only
+    // when the pattern width is even, so odd widths are refused; with an
+    // odd height every band of copies is shifted one cell to the right of
+    // the band above, for the same reason.
+    pub fn tile(&mut self, pattern: &Pattern<T>, origin: (usize, usize)) -> Result<(), ParityMismatch>
+    {
+        let (w, h) = pattern.cells.dims;
+        if (origin.0 + origin.1) % 2 != pattern.parity
+        {
+            return Err(ParityMismatch{offset: origin, parity: pattern.parity});
+        }
+        if w % 2 == 1
+        {
+            return Err(ParityMismatch{offset: (origin.0 + w, origin.1), parity: pattern.parity});
+        }
+        if w == 0 || h == 0
+        {
+            return Ok(());
+        }
+        let h = h as isize;
+        for j in 0..self.dims.1
+        {
+            let dy = j as isize - origin.1 as isize;
+            let shift = if h % 2 == 1 { dy.div_euclid(h) } else { 0 };
+            for i in 0..self.dims.0
+            {
+                let dx = i as isize - origin.0 as isize - shift;
+                // Not get_signed_wrapped: odd patterns wrap too, the shift
+                // above setting their orientation right.
+                let cell = *pattern.cells.get((dx.rem_euclid(w as isize) as usize, dy.rem_euclid(h) as usize)).unwrap();
+                *self.get_mut((i, j)).unwrap() = cell;
+            }
+        }
+        Ok(())
+    }
+
+    // A grid covered by the pattern, starting at the top left corner (or
+    // one cell to its right for patterns starting with a down triangle). An
+    // empty pattern has nothing to fill the grid with.
+    pub fn tiled(dims: (usize, usize), pattern: &Pattern<T>) -> Result<Self, GridError>
+    {
+        let fill = *pattern.cells.data.first().ok_or(GridError::EmptyPattern)?;
+        let mut grid = Grid::try_new(dims, fill)?;
+        grid.tile(pattern, (pattern.parity, 0))?;
+        Ok(grid)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn numbered() -> Grid<u8>
+    {
+        Grid::from_fn((8, 5), |(i, j)| (i + 10*j) as u8)
+    }
+
+    #[test]
+    fn extracts_remember_their_orientation()
+    {
+        let grid = numbered();
+        let up = grid.extract((2, 2), (3, 2)).unwrap();
+        assert_eq!(up.parity, 0);
+        assert_eq!(up.cells.as_slice(), [22, 23, 24, 32, 33, 34]);
+        assert_eq!(grid.extract((3, 2), (3, 2)).unwrap().parity, 1);
+        assert_eq!(grid.extract((6, 0), (3, 2)), None);
+        assert_eq!(grid.extract((0, 4), (1, 2)), None);
+    }
+
+    #[test]
+    fn stamps_keep_triangles_pointing_the_same_way()
+    {
+        let pattern = numbered().extract((1, 0), (2, 2)).unwrap();
+        let mut grid = Grid::new((8, 5), 0u8);
+        // (4, 2) points up, the pattern starts with a down triangle.
+        assert_eq!(grid.stamp(&pattern, (4, 2)), Err(ParityMismatch{offset: (4, 2), parity: 1}));
+        assert_eq!(grid, Grid::new((8, 5), 0));
+
+        assert_eq!(grid.stamp(&pattern, (5, 2)), Ok(()));
+        assert_eq!(grid.row(2), [0, 0, 0, 0, 0, 1, 2, 0]);
+        assert_eq!(grid.row(3), [0, 0, 0, 0, 0, 11, 12, 0]);
+        // Cells past the edges are dropped.
+        assert_eq!(grid.stamp(&pattern, (7, 4)), Ok(()));
+        assert_eq!(grid.get((7, 4)), Some(&1));
+    }
+
+    #[test]
+    fn flipped_patterns_stamp_at_the_other_parity()
+    {
+        let pattern = numbered().extract((0, 0), (3, 2)).unwrap();
+        let mirrored = pattern.mirror_x();
+        // An odd width puts the last cell, pointing the same way as the
+        // first, at the start.
+        assert_eq!(mirrored.parity, 0);
+        assert_eq!(mirrored.cells.row(0), [2, 1, 0]);
+        assert_eq!(numbered().extract((0, 0), (2, 2)).unwrap().mirror_x().parity, 1);
+        let flipped = pattern.flip_y();
+        assert_eq!(flipped.parity, 0);
+        assert_eq!(flipped.cells.row(0), [10, 11, 12]);
+        let tall = numbered().extract((0, 0), (3, 3)).unwrap().flip_y();
+        assert_eq!(tall.parity, 1);
+        let mut grid = Grid::new((8, 5), 0u8);
+        assert!(grid.stamp(&tall, (2, 0)).is_err());
+        assert_eq!(grid.stamp(&tall, (1, 0)), Ok(()));
+        assert_eq!(grid.get((1, 0)), Some(&20));
+        assert_eq!(tall.flip_y(), numbered().extract((0, 0), (3, 3)).unwrap());
+        assert_eq!(pattern.mirror_x().mirror_x(), pattern);
+    }
+
+    #[test]
+    fn text_keeps_the_orientation()
+    {
+        let pattern = numbered().extract((1, 1), (3, 2)).unwrap();
+        let text = pattern.to_text(|cell| cell.to_string());
+        assert_eq!(text, "pattern 3 2 up\n11 12 13\n21 22 23\n");
+        assert_eq!(Pattern::from_text(&text, |word| word.parse().map_err(|_| word.to_string())), Ok(pattern));
+        let down = numbered().extract((2, 1), (1, 1)).unwrap();
+        assert_eq!(down.to_text(|cell| cell.to_string()), "pattern 1 1 down\n12\n");
+
+        let parse = |text| Pattern::<u8>::from_text(text, |word| word.parse().map_err(|_| format!("bad cell '{}'", word)));
+        assert_eq!(parse("pattern 2 1 sideways\n1 2\n").unwrap_err().line, 1);
+        assert_eq!(parse("pattern 2 2 up\n1 2\n3\n").unwrap_err(), PatternParseError{line: 3, message: "expected 2 cells, found 1".to_string()});
+        assert_eq!(parse("pattern 1 1 up\nx\n").unwrap_err().message, "bad cell 'x'");
+        assert_eq!(parse("pattern 1 1 up\n1\n2\n").unwrap_err().line, 3);
+    }
+
+    // A pattern whose cells are their own coordinates in it.
+    fn coords(dims: (usize, usize), parity: usize) -> Pattern<(usize, usize)>
+    {
+        Pattern{cells: Grid::from_fn(dims, |coord| coord), parity}
+    }
+
+    // Every cell of the grid holds a cell of the pattern pointing the same
+    // way.
+    fn oriented(grid: &Grid<(usize, usize)>, parity: usize) -> bool
+    {
+        (0..grid.dims.1).all(|j| grid.row_coords(j).all(|((i, j), &(pi, pj))| (i + j) % 2 == (pi + pj + parity) % 2))
+    }
+
+    #[test]
+    fn tiles_cover_the_grid_and_clip_at_the_edges()
+    {
+        let mut grid = Grid::new((7, 5), (9, 9));
+        assert_eq!(grid.tile(&coords((2, 2), 0), (0, 0)), Ok(()));
+        assert_eq!(grid, Grid::from_fn((7, 5), |(i, j)| (i % 2, j % 2)));
+        // Copies around an origin inside the grid, clipped on every side.
+        let mut grid = Grid::new((9, 7), (9, 9));
+        assert_eq!(grid.tile(&coords((4, 3), 1), (3, 2)), Ok(()));
+        assert!(grid.as_slice().iter().all(|&(i, j)| i < 4 && j < 3));
+        assert_eq!(grid.get((3, 2)), Some(&(0, 0)));
+        assert!(oriented(&grid, 1));
+        let tiled = Grid::tiled((9, 7), &coords((4, 3), 1)).unwrap();
+        assert_eq!(tiled.get((1, 0)), Some(&(0, 0)));
+        assert!(oriented(&tiled, 1));
+    }
+
+    #[test]
+    fn odd_heights_shift_every_band()
+    {
+        let tiled = Grid::tiled((8, 9), &coords((2, 3), 0)).unwrap();
+        assert_eq!(tiled.row(0)[..4], [(0, 0), (1, 0), (0, 0), (1, 0)]);
+        assert_eq!(tiled.row(3)[..4], [(1, 0), (0, 0), (1, 0), (0, 0)]);
+        assert_eq!(tiled.row(6)[..4], [(0, 0), (1, 0), (0, 0), (1, 0)]);
+        assert!(oriented(&tiled, 0));
+    }
+
+    #[test]
+    fn tiles_keep_the_parity_rule()
+    {
+        let mut grid = Grid::new((8, 6), (9, 9));
+        assert_eq!(grid.tile(&coords((3, 2), 0), (2, 2)), Err(ParityMismatch{offset: (5, 2), parity: 0}));
+        assert_eq!(grid.tile(&coords((2, 2), 0), (1, 2)), Err(ParityMismatch{offset: (1, 2), parity: 0}));
+        assert_eq!(grid, Grid::new((8, 6), (9, 9)));
+        assert_eq!(Grid::tiled((8, 6), &coords((3, 1), 0)), Err(GridError::Parity(ParityMismatch{offset: (3, 0), parity: 0})));
+        assert_eq!(Grid::tiled((8, 6), &coords((0, 2), 0)), Err(GridError::EmptyPattern));
+        assert_eq!(grid.tile(&coords((0, 2), 0), (0, 0)), Ok(()));
+    }
+}
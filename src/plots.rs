@@ -0,0 +1,270 @@
+// SVG charts, written by hand so that no plotting library is needed: a
+// heatmap drawing the actual triangles of a grid, and line charts of
+// logged metrics.
+
+use crate::color::ColorMap;
+use crate::coord::Coord;
+use crate::overlay::{Overlay, DEFAULT_HIGHLIGHT};
+use crate::render;
+use crate::Grid;
+
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Side of a triangle in the heatmap, in SVG units.
+const CELL: f64 = 12.0;
+
+const SERIES_COLORS: [&str; 6] = ["#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b"];
+
+fn escape(text: &str) -> String
+{
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// Colors every cell by `value`, scaled so that the smallest value gets the
+// bottom of the color map and the largest the top.
+pub fn write_heatmap<T, F, W>(grid: &Grid<T>, value: F, colormap: ColorMap, writer: W) -> io::Result<()>
+where
+    T: Copy + Debug,
+    F: Fn(&T) -> f64,
+    W: Write
+{
+    write_heatmap_overlaid(grid, value, colormap, &Overlay::new(), writer)
+}
+
+// The same with the annotated cells outlined, and their labels written
+// over them.
+pub fn write_heatmap_overlaid<T, F, W>(grid: &Grid<T>, value: F, colormap: ColorMap, overlay: &Overlay, mut writer: W) -> io::Result<()>
+where
+    T: Copy + Debug,
+    F: Fn(&T) -> f64,
+    W: Write
+{
+    let values: Vec<f64> = grid.data.iter().map(&value).collect();
+    let low = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let high = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let range = if high > low { high - low } else { 1.0 };
+
+    let row_height = CELL * 3f64.sqrt() / 2.0;
+    let width = (grid.dims.0 + 1) as f64 * CELL / 2.0;
+    let height = grid.dims.1 as f64 * row_height;
+    writeln!(writer, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.1}\" height=\"{:.1}\" viewBox=\"0 0 {:.3} {:.3}\">",
+             width, height, width, height)?;
+    for j in 0..grid.dims.1
+    {
+        for i in 0..grid.dims.0
+        {
+            let t = (values[j*grid.dims.0 + i] - low) / range;
+            let points: Vec<String> = render::corners((i, j)).iter()
+                .map(|&(x, y)| format!("{:.3},{:.3}", x as f64 * CELL / 2.0, y as f64 * row_height))
+                .collect();
+            // The stroke in the fill color hides the seams antialiasing
+            // leaves between adjacent polygons.
+            let color = colormap.hex(t);
+            writeln!(writer, "<polygon points=\"{}\" fill=\"{}\" stroke=\"{}\" stroke-width=\"0.3\"/>",
+                     points.join(" "), color, color)?;
+        }
+    }
+    for (&(i, j), annotation) in overlay.iter().filter(|(&(i, j), _)| i < grid.dims.0 && j < grid.dims.1)
+    {
+        let [r, g, b] = annotation.color.unwrap_or(DEFAULT_HIGHLIGHT);
+        let points: Vec<String> = render::corners((i, j)).iter()
+            .map(|&(x, y)| format!("{:.3},{:.3}", x as f64 * CELL / 2.0, y as f64 * row_height))
+            .collect();
+        writeln!(writer, "<polygon points=\"{}\" fill=\"none\" stroke=\"#{:02x}{:02x}{:02x}\" stroke-width=\"1.2\"/>",
+                 points.join(" "), r, g, b)?;
+        if let Some(label) = &annotation.label
+        {
+            let (x, y) = Coord::from((i, j)).centroid();
+            writeln!(writer, "<text x=\"{:.3}\" y=\"{:.3}\" font-size=\"{:.1}\" font-family=\"monospace\" text-anchor=\"middle\" dominant-baseline=\"central\">{}</text>",
+                     x * CELL, y * CELL, CELL * 0.4, escape(label))?;
+        }
+    }
+    writeln!(writer, "</svg>")?;
+    writer.flush()
+}
+
+pub fn heatmap<T, F, P>(grid: &Grid<T>, value: F, colormap: ColorMap, path: P) -> io::Result<()>
+where
+    T: Copy + Debug,
+    F: Fn(&T) -> f64,
+    P: AsRef<Path>
+{
+    write_heatmap(grid, value, colormap, BufWriter::new(File::create(path)?))
+}
+
+pub fn heatmap_overlaid<T, F, P>(grid: &Grid<T>, value: F, colormap: ColorMap, overlay: &Overlay, path: P) -> io::Result<()>
+where
+    T: Copy + Debug,
+    F: Fn(&T) -> f64,
+    P: AsRef<Path>
+{
+    write_heatmap_overlaid(grid, value, colormap, overlay, BufWriter::new(File::create(path)?))
+}
+
+// Ticks at 1, 2 or 5 times a power of ten, about `count` of them and no
+// closer than `min_step`, with their labels.
+fn ticks(low: f64, high: f64, count: usize, min_step: f64) -> Vec<(f64, String)>
+{
+    let rough = ((high - low) / count as f64).max(min_step);
+    let magnitude = 10f64.powf(rough.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0].iter()
+        .map(|m| m * magnitude)
+        .find(|&step| step >= rough)
+        .unwrap_or(10.0 * magnitude);
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    let mut ticks = vec![];
+    let mut n = (low / step).ceil();
+    while n * step <= high + step * 1e-9
+    {
+        ticks.push((n * step, format!("{:.*}", decimals, n * step)));
+        n += 1.0;
+    }
+    ticks
+}
+
+// Line chart of named series against the step number (the index of the
+// sample), with a legend. Empty series are listed in the legend only.
+pub fn write_timeseries<W: Write>(series: &[(String, Vec<f64>)], mut writer: W) -> io::Result<()>
+{
+    let (width, height) = (640.0, 400.0);
+    let (left, right, top, bottom) = (60.0, 140.0, 20.0, 50.0);
+    let (plot_w, plot_h) = (width - left - right, height - top - bottom);
+
+    let steps = series.iter().map(|(_, samples)| samples.len()).max().unwrap_or(0);
+    let x_max = steps.saturating_sub(1).max(1) as f64;
+    let finite = || series.iter().flat_map(|(_, samples)| samples.iter().cloned()).filter(|v| v.is_finite());
+    let mut y_min = finite().fold(f64::INFINITY, f64::min);
+    let mut y_max = finite().fold(f64::NEG_INFINITY, f64::max);
+    if y_min > y_max
+    {
+        y_min = 0.0;
+        y_max = 1.0;
+    }
+    if y_min == y_max
+    {
+        y_min -= 0.5;
+        y_max += 0.5;
+    }
+    let x = |step: f64| left + step / x_max * plot_w;
+    let y = |value: f64| top + (y_max - value) / (y_max - y_min) * plot_h;
+
+    writeln!(writer, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"11\">", width, height)?;
+    writeln!(writer, "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>", width, height)?;
+    writeln!(writer, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"black\"/>", left, top, plot_w, plot_h)?;
+    for (tick, label) in ticks(0.0, x_max, 8, 1.0)
+    {
+        writeln!(writer, "<line x1=\"{0:.2}\" y1=\"{1}\" x2=\"{0:.2}\" y2=\"{2}\" stroke=\"black\"/>", x(tick), top + plot_h, top + plot_h + 4.0)?;
+        writeln!(writer, "<text x=\"{:.2}\" y=\"{}\" text-anchor=\"middle\">{}</text>", x(tick), top + plot_h + 16.0, label)?;
+    }
+    for (tick, label) in ticks(y_min, y_max, 6, 0.0)
+    {
+        writeln!(writer, "<line x1=\"{0}\" y1=\"{1:.2}\" x2=\"{2}\" y2=\"{1:.2}\" stroke=\"#dddddd\"/>", left, y(tick), left + plot_w)?;
+        writeln!(writer, "<text x=\"{}\" y=\"{:.2}\" text-anchor=\"end\" dominant-baseline=\"middle\">{}</text>", left - 6.0, y(tick), label)?;
+    }
+    writeln!(writer, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">step</text>", left + plot_w / 2.0, height - 12.0)?;
+    writeln!(writer, "<text transform=\"translate(16 {}) rotate(-90)\" text-anchor=\"middle\">value</text>", top + plot_h / 2.0)?;
+
+    for (n, (name, samples)) in series.iter().enumerate()
+    {
+        let color = SERIES_COLORS[n % SERIES_COLORS.len()];
+        let points: Vec<(f64, f64)> = samples.iter().enumerate()
+            .filter(|(_, v)| v.is_finite())
+            .map(|(step, &v)| (x(step as f64), y(v)))
+            .collect();
+        match points.as_slice()
+        {
+            [] => (),
+            [(cx, cy)] => writeln!(writer, "<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"2\" fill=\"{}\"/>", cx, cy, color)?,
+            _ =>
+            {
+                let points: Vec<String> = points.iter().map(|(px, py)| format!("{:.2},{:.2}", px, py)).collect();
+                writeln!(writer, "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\"/>", points.join(" "), color)?;
+            }
+        }
+        let legend_y = top + 10.0 + 16.0 * n as f64;
+        writeln!(writer, "<line x1=\"{0}\" y1=\"{1}\" x2=\"{2}\" y2=\"{1}\" stroke=\"{3}\" stroke-width=\"2\"/>", left + plot_w + 10.0, legend_y, left + plot_w + 30.0, color)?;
+        writeln!(writer, "<text x=\"{}\" y=\"{}\" dominant-baseline=\"middle\">{}</text>", left + plot_w + 36.0, legend_y, escape(name))?;
+    }
+    writeln!(writer, "</svg>")?;
+    writer.flush()
+}
+
+pub fn timeseries<P: AsRef<Path>>(series: &[(String, Vec<f64>)], path: P) -> io::Result<()>
+{
+    write_timeseries(series, BufWriter::new(File::create(path)?))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn svg<F: FnOnce(&mut Vec<u8>) -> io::Result<()>>(write: F) -> String
+    {
+        let mut out = vec![];
+        write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn heatmaps_draw_every_triangle()
+    {
+        let grid = Grid::from_fn((5, 3), |(i, j)| (i + 5*j) as f64);
+        let heatmap = svg(|out| write_heatmap(&grid, |&v| v, ColorMap::Gray, out));
+        let polygons: Vec<&str> = heatmap.lines().filter(|line| line.starts_with("<polygon")).collect();
+        assert_eq!(polygons.len(), 15);
+        // The smallest value at the bottom of the map, the largest at the top.
+        assert!(polygons[0].contains(&format!("fill=\"{}\"", ColorMap::Gray.hex(0.0))));
+        assert!(polygons[14].contains(&format!("fill=\"{}\"", ColorMap::Gray.hex(1.0))));
+        // (0, 0) points up, (1, 0) down.
+        assert!(polygons[0].contains("points=\"6.000,0.000 0.000,10.392 12.000,10.392\""));
+        assert!(polygons[1].contains("points=\"6.000,0.000 12.000,10.392 18.000,0.000\""));
+        // A flat grid does not divide by zero.
+        let flat = svg(|out| write_heatmap(&Grid::new((2, 2), 1.0), |&v| v, ColorMap::Heat, out));
+        assert!(!flat.contains("NaN"));
+    }
+
+    #[test]
+    fn overlays_are_outlined_and_labeled()
+    {
+        let grid = Grid::from_fn((5, 3), |(i, j)| (i + 5*j) as f64);
+        let mut overlay = Overlay::new();
+        overlay.highlight((1, 0), [255, 0, 0]).label((1, 0), "a<b").mark((4, 2)).mark((9, 9));
+        let heatmap = svg(|out| write_heatmap_overlaid(&grid, |&v| v, ColorMap::Gray, &overlay, out));
+        let outlines: Vec<&str> = heatmap.lines().filter(|line| line.contains("fill=\"none\"")).collect();
+        // The cell outside of the grid is left out.
+        assert_eq!(outlines.len(), 2);
+        assert!(outlines[0].contains("points=\"6.000,0.000 12.000,10.392 18.000,0.000\" fill=\"none\" stroke=\"#ff0000\""));
+        assert!(outlines[1].contains("stroke=\"#ffdc00\""));
+        assert_eq!(heatmap.matches("<text").count(), 1);
+        assert!(heatmap.contains(">a&lt;b</text>"));
+    }
+
+    #[test]
+    fn charts_of_few_samples()
+    {
+        let empty = svg(|out| write_timeseries(&[], out));
+        assert!(empty.ends_with("</svg>\n"));
+        let series = vec![("none".to_string(), vec![]), ("one".to_string(), vec![3.0]), ("nan".to_string(), vec![f64::NAN])];
+        let chart = svg(|out| write_timeseries(&series, out));
+        assert_eq!(chart.matches("<circle").count(), 1);
+        assert!(!chart.contains("polyline"));
+        assert!(chart.contains(">none</text>") && chart.contains(">nan</text>"));
+        assert!(!chart.contains("NaN"));
+        let line = svg(|out| write_timeseries(&[("a<b".to_string(), vec![0.0, 1.0, 4.0])], out));
+        assert_eq!(line.matches("<polyline").count(), 1);
+        assert!(line.contains("a&lt;b"));
+    }
+
+    #[test]
+    fn ticks_are_round()
+    {
+        let labels = |ticks: Vec<(f64, String)>| ticks.into_iter().map(|(_, label)| label).collect::<Vec<_>>();
+        assert_eq!(labels(ticks(0.0, 100.0, 5, 1.0)), ["0", "20", "40", "60", "80", "100"]);
+        assert_eq!(labels(ticks(0.0, 3.0, 8, 1.0)), ["0", "1", "2", "3"]);
+        assert_eq!(labels(ticks(-0.5, 0.5, 6, 0.0)), ["-0.4", "-0.2", "0.0", "0.2", "0.4"]);
+    }
+}
@@ -0,0 +1,242 @@
+// Which source lit each cell: light cells carrying the id of their source
+// along with their level, for Voronoi-like maps of the regions sources
+// light. Sources are given ids when the grid is traced, in storage order
+// (row by row, from the left), and under light_falloff_traced every other
+// cell takes its level as light_falloff does and the id of the cell its
+// light came from, the brightest of the neighborhood.
+//
+// Settled, a cell holds the id of its nearest source, counting cells
+// travelled, and where sources of the same level are equally near, the
+// lowest id: between two equal sources the cells halfway go to the first
+// one, the upper or the left one. Cells no light reaches have none.
+
+use crate::{CellState, Grid};
+use crate::palette::Palette;
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Display};
+
+pub type SourceId = u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Traced<L>
+{
+    pub light: L,
+    pub source: Option<SourceId>
+}
+
+impl<L: CellState> CellState for Traced<L>
+{
+    fn level(&self) -> u8
+    {
+        self.light.level()
+    }
+
+    // Keeps the id: light_falloff_traced is what changes it.
+    fn with_level(&self, level: u8) -> Self
+    {
+        Self{light: self.light.with_level(level), source: self.source}
+    }
+
+    fn default_free() -> Self
+    {
+        Self{light: L::default_free(), source: None}
+    }
+
+    fn is_pinned(&self) -> bool
+    {
+        self.light.is_pinned()
+    }
+}
+
+impl<L: Display> Display for Traced<L>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        self.light.fmt(f)
+    }
+}
+
+// The grid with an id on every pinned cell, none on the others.
+pub fn traced<L: CellState + Debug>(grid: &Grid<L>) -> Grid<Traced<L>>
+{
+    let mut next: SourceId = 0;
+    let data = grid.data.iter()
+        .map(|&light| {
+            let source = if light.is_pinned() { next += 1; Some(next - 1) } else { None };
+            Traced{light, source}
+        })
+        .collect();
+    Grid{data, dims: grid.dims}
+}
+
+// light_falloff, the cell taking the id of the brightest cell of its
+// neighborhood (itself included, as when light fades), the lowest id among
+// equally bright ones; unlit cells have none.
+pub fn light_falloff_traced<L: CellState>(ngh: Vec<Traced<L>>) -> Traced<L>
+{
+    let cell = ngh[0];
+    if cell.is_pinned()
+    {
+        return cell;
+    }
+    let brightest = ngh.iter().map(CellState::level).max().unwrap_or(0);
+    let level = brightest.saturating_sub(1);
+    let source = if level == 0
+    {
+        None
+    }
+    else
+    {
+        ngh.iter().filter(|ncel| ncel.level() == brightest).filter_map(|ncel| ncel.source).min()
+    };
+    Traced{light: cell.light.with_level(level), source}
+}
+
+// The ids alone, for analysis and for drawing with a discrete palette.
+pub fn provenance<L: Copy + Debug>(grid: &Grid<Traced<L>>) -> Grid<Option<SourceId>>
+{
+    Grid{data: grid.data.iter().map(|cell| cell.source).collect(), dims: grid.dims}
+}
+
+// The number of cells of every source, itself included.
+pub fn region_sizes(provenance: &Grid<Option<SourceId>>) -> HashMap<SourceId, usize>
+{
+    let mut sizes = HashMap::new();
+    for &source in provenance.data.iter().flatten()
+    {
+        *sizes.entry(source).or_insert(0) += 1;
+    }
+    sizes
+}
+
+// The color of a source's region, the k-th of the palette, wrapping
+// around; black for the cells without one.
+pub fn region_color(palette: &Palette, source: Option<SourceId>) -> (u8, u8, u8)
+{
+    source.map_or((0, 0, 0), |source| palette.index(source as usize))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{neighbor_coords, palette, Automata, Light};
+
+    use std::collections::VecDeque;
+
+    // Cells travelled from `source`, for checking.
+    fn distances(dims: (usize, usize), source: (usize, usize)) -> Grid<Option<usize>>
+    {
+        let mut distances = Grid::new(dims, None);
+        *distances.get_mut(source).unwrap() = Some(0);
+        let mut queue = VecDeque::from(vec![source]);
+        while let Some(coord) = queue.pop_front()
+        {
+            let d = distances.get(coord).unwrap().unwrap();
+            for other in neighbor_coords(dims, coord)
+            {
+                let cell = distances.get_mut(other).unwrap();
+                if cell.is_none()
+                {
+                    *cell = Some(d + 1);
+                    queue.push_back(other);
+                }
+            }
+        }
+        distances
+    }
+
+    fn settled(dims: (usize, usize), sources: &[((usize, usize), u8)], steps: usize) -> Grid<Option<SourceId>>
+    {
+        let grid = Grid::from_fn(dims, |coord| match sources.iter().find(|(at, _)| *at == coord)
+        {
+            Some(&(_, level)) => Light::Source(level),
+            None => Light::Space(0)
+        });
+        let mut automata = Automata::new(traced(&grid));
+        for _ in 0..steps
+        {
+            automata.evolve(light_falloff_traced);
+        }
+        provenance(automata.current())
+    }
+
+    // Two sources of the same level on a grid they light whole, one the
+    // mirror of the other: every cell holds the id of the nearer one, the
+    // first one where they are as near, which is the column between them,
+    // and the second region is the mirror of the first but for that column.
+    #[test]
+    fn equal_sources_split_the_grid_in_mirrors()
+    {
+        // An odd width keeps the mirror of a cell of the same orientation.
+        let dims = (21, 9);
+        let (first, second) = ((5, 4), (15, 4));
+        let regions = settled(dims, &[(first, 40), (second, 40)], 40);
+        let (near_first, near_second) = (distances(dims, first), distances(dims, second));
+        for j in 0..dims.1
+        {
+            for i in 0..dims.0
+            {
+                let (d0, d1) = (near_first.get((i, j)).unwrap().unwrap(), near_second.get((i, j)).unwrap().unwrap());
+                let expected = if d0 <= d1 { 0 } else { 1 };
+                assert_eq!(*regions.get((i, j)).unwrap(), Some(expected), "cell {:?}, {} and {} away", (i, j), d0, d1);
+                // The tie is the middle column, which goes to the first.
+                assert_eq!(d0 == d1, i == dims.0/2, "{:?}", (i, j));
+                if d0 != d1
+                {
+                    assert_eq!(*regions.get((dims.0 - 1 - i, j)).unwrap(), Some(1 - expected), "{:?}", (i, j));
+                }
+            }
+        }
+        let sizes = region_sizes(&regions);
+        assert_eq!(sizes.len(), 2);
+        assert_eq!(sizes[&0], sizes[&1] + dims.1);
+        assert_eq!(sizes[&0] + sizes[&1], dims.0*dims.1);
+    }
+
+    #[test]
+    fn ties_go_to_the_upper_source()
+    {
+        // One above the other, ids in storage order: the upper one is 0.
+        let dims = (9, 12);
+        let regions = settled(dims, &[((4, 1), 30), ((4, 9), 30)], 30);
+        let (upper, lower) = (distances(dims, (4, 1)), distances(dims, (4, 9)));
+        for (k, &source) in regions.data.iter().enumerate()
+        {
+            let (d0, d1) = (upper.data[k].unwrap(), lower.data[k].unwrap());
+            assert_eq!(source, Some(if d0 <= d1 { 0 } else { 1 }));
+        }
+        assert!(upper.data.iter().zip(&lower.data).any(|(d0, d1)| d0 == d1));
+    }
+
+    #[test]
+    fn unlit_cells_and_dim_sources()
+    {
+        // A source of level 3 lights the cells up to 2 edges away, and
+        // the rest stays dark.
+        let regions = settled((15, 7), &[((7, 3), 3)], 10);
+        let sizes = region_sizes(&regions);
+        assert_eq!(regions.data.iter().filter(|source| source.is_none()).count(), 15*7 - sizes[&0]);
+        assert_eq!(sizes[&0], 1 + 3 + 6);
+        // Brighter light wins over a nearer dim source.
+        let regions = settled((15, 7), &[((2, 3), 40), ((8, 3), 3)], 20);
+        assert_eq!(regions.get((8, 3)), Some(&Some(1)));
+        assert_eq!(regions.get((9, 3)), Some(&Some(0)));
+        assert_eq!(region_sizes(&regions)[&1], 1);
+    }
+
+    #[test]
+    fn traced_grids()
+    {
+        let grid = Grid::from_fn((4, 2), |(i, j)| if i == j { Light::Source(5) } else { Light::Space(2) });
+        let traced = traced(&grid);
+        assert_eq!(traced.data.iter().map(|cell| cell.source).collect::<Vec<_>>(),
+                   vec![Some(0), None, None, None, None, Some(1), None, None]);
+        assert_eq!(traced.get((1, 1)).unwrap().with_level(9), Traced{light: Light::Source(5).with_level(9), source: Some(1)});
+        assert_eq!(traced.get((1, 1)).unwrap().to_string(), Light::Source(5).to_string());
+        let palette = palette::get("okabe-ito").unwrap();
+        assert_eq!(region_color(palette, None), (0, 0, 0));
+        assert_eq!(region_color(palette, Some(2)), palette.index(2));
+    }
+}
@@ -0,0 +1,182 @@
+// Light through media of several densities. Every cell has a cost, the
+// levels light loses entering it: 1 in empty space, more in dense media,
+// where light then spreads slower and its fronts bend. The field the
+// automaton settles on is analysis::weighted_light_field with that cost.
+
+use crate::{CellState, Grid, Light, Rule};
+use crate::analysis;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Medium
+{
+    pub light: Light,
+    pub cost: u8
+}
+
+impl Medium
+{
+    pub fn space(cost: u8) -> Self
+    {
+        Self{light: Light::Space(0), cost}
+    }
+
+    pub fn source(level: u8) -> Self
+    {
+        Self{light: Light::Source(level), cost: 1}
+    }
+}
+
+impl CellState for Medium
+{
+    fn level(&self) -> u8
+    {
+        self.light.level()
+    }
+
+    fn with_level(&self, level: u8) -> Self
+    {
+        Self{light: self.light.with_level(level), cost: self.cost}
+    }
+
+    fn default_free() -> Self
+    {
+        Self::space(1)
+    }
+
+    fn is_pinned(&self) -> bool
+    {
+        self.light.is_pinned()
+    }
+}
+
+impl fmt::Display for Medium
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}", self.light)
+    }
+}
+
+// The brightest neighbor minus the cost of the cell, down to 0. Sources
+// keep their level. With a cost of 1 everywhere this is light_falloff,
+// but for the cell's own light, which is not kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RefractiveFalloff;
+
+impl Rule<Medium> for RefractiveFalloff
+{
+    fn apply(&self, ngh: Vec<Medium>) -> Medium
+    {
+        let cell = ngh[0];
+        if cell.is_pinned()
+        {
+            return cell;
+        }
+        let brightest = ngh[1..].iter().map(CellState::level).max().unwrap_or(0);
+        cell.with_level(brightest.saturating_sub(cell.cost))
+    }
+
+    fn name(&self) -> Option<&str>
+    {
+        Some("refractive falloff")
+    }
+}
+
+// The levels RefractiveFalloff converges to from the sources of the grid.
+// Light does not go through other sources, which keep their own level.
+pub fn reference_field(grid: &Grid<Medium>) -> Grid<u32>
+{
+    let sources: Vec<((usize, usize), u32)> = (0..grid.dims.1)
+        .flat_map(|j| (0..grid.dims.0).map(move |i| (i, j)))
+        .filter_map(|coord| grid.get(coord).filter(|cell| cell.is_pinned()).map(|cell| (coord, u32::from(cell.level()))))
+        .collect();
+    analysis::weighted_light_field(grid, |cell| if cell.is_pinned() { None } else { Some(u32::from(cell.cost)) }, &sources)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, Automata};
+    use crate::convergence::{self, Tolerance};
+    use crate::rng::SplitMix64;
+
+    fn level(cell: &Medium) -> f64
+    {
+        f64::from(cell.level())
+    }
+
+    // A lens of cost 3 right of a source, and a few denser cells about.
+    fn scene(seed: u64) -> Grid<Medium>
+    {
+        let mut rng = SplitMix64::new(seed);
+        let mut grid = Grid::from_fn((30, 30), |(i, j)| {
+            let (x, y) = (i as f64 - 18.0, (j as f64 - 15.0) * 1.7);
+            if x*x/16.0 + y*y/144.0 <= 1.0 { Medium::space(3) } else { Medium::space(1 + (rng.below(8) == 0) as u8 * 4) }
+        });
+        *grid.get_mut((4, 15)).unwrap() = Medium::source(90);
+        *grid.get_mut((25, 3)).unwrap() = Medium::source(40);
+        grid
+    }
+
+    #[test]
+    fn settles_on_the_cheapest_paths()
+    {
+        for seed in 0..4
+        {
+            let grid = scene(seed);
+            let reference = reference_field(&grid);
+            let mut automata = Automata::new(grid);
+            convergence::run_until_stable(&mut automata, |ngh| RefractiveFalloff.apply(ngh), level, Tolerance::exact(), 1000).unwrap();
+            let levels = Grid::from_fn((30, 30), |coord| u32::from(automata.current().get(coord).unwrap().level()));
+            assert_eq!(levels, reference, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn dense_media_hold_light_back()
+    {
+        let grid = scene(0);
+        let reference = reference_field(&grid);
+        let free = reference_field(&Grid::from_fn((30, 30), |coord| Medium{cost: 1, ..*grid.get(coord).unwrap()}));
+        // Through the lens, the light is dimmer than as far in free space.
+        let at = |field: &Grid<u32>, coord| *field.get(coord).unwrap();
+        assert!(at(&reference, (20, 15)) + 10 <= at(&free, (20, 15)));
+        assert!((0..30).all(|j| (0..30).all(|i| at(&reference, (i, j)) <= at(&free, (i, j)))));
+        // The sources keep their level.
+        assert_eq!((at(&reference, (4, 15)), at(&reference, (25, 3))), (90, 40));
+    }
+
+    #[test]
+    fn unit_costs_are_the_falloff()
+    {
+        let mut rng = SplitMix64::new(169);
+        let mut grid = Grid::new((24, 12), Light::Space(0));
+        for _ in 0..3
+        {
+            *grid.get_mut((rng.below(24) as usize, rng.below(12) as usize)).unwrap() = Light::Source(20 + rng.below(20) as u8);
+        }
+        let mut falloff = Automata::new(grid.clone());
+        let mut media = Automata::new(Grid::from_fn((24, 12), |coord| Medium{light: *grid.get(coord).unwrap(), cost: 1}));
+        for _ in 0..40
+        {
+            falloff.evolve(rules::light_falloff);
+            media.evolve(|ngh| RefractiveFalloff.apply(ngh));
+        }
+        // Lit from the dark, the cells' own light makes no difference.
+        let lights = Grid::from_fn((24, 12), |coord| media.current().get(coord).unwrap().light);
+        assert_eq!(&lights, falloff.current());
+    }
+
+    #[test]
+    fn saturates_and_pins()
+    {
+        let dense = Medium::space(9).with_level(50);
+        assert_eq!(RefractiveFalloff.apply(vec![dense, Medium::source(5), Medium::space(1)]), Medium::space(9));
+        assert_eq!(RefractiveFalloff.apply(vec![dense, Medium::source(30)]), Medium::space(9).with_level(21));
+        assert_eq!(RefractiveFalloff.apply(vec![Medium::source(3), Medium::source(200)]), Medium::source(3));
+        assert_eq!(RefractiveFalloff.name(), Some("refractive falloff"));
+    }
+}
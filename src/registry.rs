@@ -0,0 +1,387 @@
+// Rules known by name, with a description and the parameters they take,
+// so that scripts, the REPL and sweeps all look them up in one place.
+// There is one global registry per cell type; rules can be added to it at
+// runtime.
+
+use crate::{Light, Rule};
+use crate::machine;
+use crate::rules::{self, Clamp};
+use crate::sweep::RuleConfig;
+
+use std::fmt;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamSpec
+{
+    pub name: String,
+    pub description: String,
+    pub default: f64,
+    // Inclusive.
+    pub range: (f64, f64)
+}
+
+impl ParamSpec
+{
+    pub fn new(name: &str, description: &str, default: f64, range: (f64, f64)) -> Self
+    {
+        Self{name: name.to_string(), description: description.to_string(), default, range}
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistryError
+{
+    UnknownRule{name: String, known: Vec<String>},
+    UnknownParam{rule: String, param: String},
+    OutOfRange{param: String, value: f64, range: (f64, f64)},
+    AlreadyRegistered(String)
+}
+
+impl fmt::Display for RegistryError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            RegistryError::UnknownRule{name, known} => write!(f, "unknown rule '{}' ({})", name, known.join(", ")),
+            RegistryError::UnknownParam{rule, param} => write!(f, "rule {} has no parameter '{}'", rule, param),
+            RegistryError::OutOfRange{param, value, range} =>
+                write!(f, "{} = {} is out of range ({} to {})", param, value, range.0, range.1),
+            RegistryError::AlreadyRegistered(name) => write!(f, "a rule named '{}' is already registered", name)
+        }
+    }
+}
+
+type Constructor<T> = Box<dyn Fn(&RuleConfig) -> Box<dyn Rule<T>> + Send>;
+
+pub struct RuleEntry<T>
+{
+    pub name: String,
+    pub description: String,
+    pub params: Vec<ParamSpec>,
+    constructor: Constructor<T>,
+    // Built-in rules are, see Rule::is_deterministic.
+    deterministic: bool
+}
+
+// A rule made by the registry, reporting its registered name and the
+// parameters it was made with.
+struct Registered<T>
+{
+    name: String,
+    config: RuleConfig,
+    rule: Box<dyn Rule<T>>,
+    deterministic: bool
+}
+
+impl<T> Rule<T> for Registered<T>
+{
+    fn apply(&self, ngh: Vec<T>) -> T
+    {
+        self.rule.apply(ngh)
+    }
+
+    fn name(&self) -> Option<&str>
+    {
+        Some(&self.name)
+    }
+
+    fn config(&self) -> Option<&RuleConfig>
+    {
+        Some(&self.config)
+    }
+
+    fn is_deterministic(&self) -> bool
+    {
+        self.deterministic || self.rule.is_deterministic()
+    }
+}
+
+pub struct RuleRegistry<T>
+{
+    entries: Vec<RuleEntry<T>>,
+    // Other names of registered rules, with the name they stand for.
+    aliases: Vec<(String, String)>
+}
+
+impl<T: 'static> RuleRegistry<T>
+{
+    pub fn new() -> Self
+    {
+        Self{entries: vec![], aliases: vec![]}
+    }
+
+    pub fn register<C, R>(&mut self, name: &str, description: &str, params: Vec<ParamSpec>, constructor: C) -> Result<(), RegistryError>
+    where
+        C: Fn(&RuleConfig) -> R + Send + 'static,
+        R: Rule<T> + 'static
+    {
+        if self.get(name).is_some()
+        {
+            return Err(RegistryError::AlreadyRegistered(name.to_string()));
+        }
+        self.entries.push(RuleEntry{
+            name: name.to_string(),
+            description: description.to_string(),
+            params,
+            constructor: Box::new(move |config| Box::new(constructor(config))),
+            deterministic: false
+        });
+        Ok(())
+    }
+
+    fn builtin<C, R>(&mut self, name: &str, description: &str, params: Vec<ParamSpec>, constructor: C)
+    where
+        C: Fn(&RuleConfig) -> R + Send + 'static,
+        R: Rule<T> + 'static
+    {
+        self.register(name, description, params, constructor).expect("built-in rule names are distinct");
+        self.entries.last_mut().unwrap().deterministic = true;
+    }
+
+    pub fn alias(&mut self, alias: &str, name: &str) -> Result<(), RegistryError>
+    {
+        if self.get(alias).is_some()
+        {
+            return Err(RegistryError::AlreadyRegistered(alias.to_string()));
+        }
+        self.aliases.push((alias.to_string(), name.to_string()));
+        Ok(())
+    }
+
+    // In registration order.
+    pub fn entries(&self) -> &[RuleEntry<T>]
+    {
+        &self.entries
+    }
+
+    pub fn names(&self) -> Vec<String>
+    {
+        self.entries.iter().map(|entry| entry.name.clone()).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RuleEntry<T>>
+    {
+        let name = self.aliases.iter()
+            .find(|(alias, _)| alias == name)
+            .map_or(name, |(_, target)| target.as_str());
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    // Builds the rule from `config`: its parameters must all be in the
+    // rule's schema and within range, the missing ones take their default.
+    pub fn instantiate(&self, name: &str, config: &RuleConfig) -> Result<Box<dyn Rule<T>>, RegistryError>
+    {
+        let entry = self.get(name).ok_or_else(|| RegistryError::UnknownRule{name: name.to_string(), known: self.names()})?;
+        let mut full = RuleConfig::new(config.seed);
+        for spec in &entry.params
+        {
+            full.set(&spec.name, spec.default);
+        }
+        for (param, &value) in &config.params
+        {
+            let spec = entry.params.iter()
+                .find(|spec| &spec.name == param)
+                .ok_or_else(|| RegistryError::UnknownParam{rule: entry.name.clone(), param: param.clone()})?;
+            if !(spec.range.0..=spec.range.1).contains(&value)
+            {
+                return Err(RegistryError::OutOfRange{param: param.clone(), value, range: spec.range});
+            }
+            full.set(param, value);
+        }
+        let rule = (entry.constructor)(&full);
+        Ok(Box::new(Registered{name: entry.name.clone(), config: full, rule, deterministic: entry.deterministic}))
+    }
+}
+
+impl<T: 'static> Default for RuleRegistry<T>
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+// Parameters are whole numbers for most rules.
+fn level(config: &RuleConfig, name: &str) -> u8
+{
+    config.get(name).unwrap_or(0.0).round() as u8
+}
+
+impl RuleRegistry<Light>
+{
+    pub fn with_builtins() -> Self
+    {
+        let level_range = (0.0, 255.0);
+        let mut registry = Self::new();
+        registry.builtin("falloff", "light spreads, losing one level per cell", vec![],
+                         |_| rules::light_falloff::<Light>);
+        registry.builtin("additive", "light from every neighbor adds up", vec![],
+                         |_| rules::light_additive::<Light>);
+        registry.builtin("decay", "light spreads, losing `amount` levels per cell",
+                         vec![ParamSpec::new("amount", "levels lost per cell travelled", 2.0, level_range)],
+                         |config| rules::light_decay::<Light>(level(config, "amount")));
+        registry.builtin("clamped", "falloff with lit cells capped at `max`",
+                         vec![ParamSpec::new("max", "highest level of lit cells", 255.0, level_range)],
+                         |config| {
+                             let rule = rules::light_falloff_clamped(Clamp::saturate(level(config, "max")));
+                             move |ngh| rule(ngh).expect("saturating rules do not fail")
+                         });
+        for &(alias, name) in &[("light-falloff", "falloff"), ("light-additive", "additive")]
+        {
+            registry.alias(alias, name).expect("built-in rule names are distinct");
+        }
+        registry
+    }
+
+    pub fn global() -> MutexGuard<'static, Self>
+    {
+        static GLOBAL: OnceLock<Mutex<RuleRegistry<Light>>> = OnceLock::new();
+        GLOBAL.get_or_init(|| Mutex::new(Self::with_builtins())).lock().unwrap()
+    }
+}
+
+impl RuleRegistry<u8>
+{
+    pub fn with_builtins() -> Self
+    {
+        let mut registry = Self::new();
+        registry.builtin("sandpile", "cells holding 3 grains or more topple", vec![], |_| rules::sandpile);
+        registry.builtin("wireworld", "electrons running along wires (see machine.rs)", vec![], |_| machine::wireworld());
+        registry
+    }
+
+    pub fn global() -> MutexGuard<'static, Self>
+    {
+        static GLOBAL: OnceLock<Mutex<RuleRegistry<u8>>> = OnceLock::new();
+        GLOBAL.get_or_init(|| Mutex::new(Self::with_builtins())).lock().unwrap()
+    }
+}
+
+// One line per rule: its name, description and parameters with their
+// defaults.
+pub fn describe<T: 'static>(registry: &RuleRegistry<T>) -> String
+{
+    registry.entries().iter()
+        .map(|entry| {
+            let params: Vec<String> = entry.params.iter()
+                .map(|spec| format!(" [{}={}: {}, {} to {}]", spec.name, spec.default, spec.description, spec.range.0, spec.range.1))
+                .collect();
+            format!("{}: {}{}", entry.name, entry.description, params.concat())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Rule parameters written as name=value words, as in `decay amount=3`.
+pub fn parse_params<'a, I: Iterator<Item = &'a str>>(words: I) -> Result<RuleConfig, String>
+{
+    let mut config = RuleConfig::new(0);
+    for word in words
+    {
+        let (name, value) = word.split_once('=').ok_or_else(|| format!("expected name=value, not '{}'", word))?;
+        let value = value.parse().map_err(|_| format!("invalid value '{}' for {}", value, name))?;
+        config.set(name, value);
+    }
+    Ok(config)
+}
+
+// A rule as saved next to checkpoints: its name, then its parameters and
+// seed as name=value words, as in `decay amount=3 seed=0`.
+pub fn rule_line(name: &str, config: &RuleConfig) -> String
+{
+    let params: String = config.params.iter().map(|(param, value)| format!(" {}={}", param, value)).collect();
+    format!("{}{} seed={}", name, params, config.seed)
+}
+
+// The name and parameters of a rule_line. A bare name, as older
+// checkpoints saved, has no parameters and seed 0.
+pub fn parse_rule_line(line: &str) -> Result<(String, RuleConfig), String>
+{
+    let mut words = line.split_whitespace();
+    let name = words.next().ok_or("no rule name")?.to_string();
+    let (seed, params): (Vec<&str>, Vec<&str>) = words.partition(|word| word.starts_with("seed="));
+    let mut config = parse_params(params.into_iter())?;
+    if let Some(seed) = seed.last()
+    {
+        config.seed = seed["seed=".len()..].parse().map_err(|_| format!("invalid seed '{}'", seed))?;
+    }
+    Ok((name, config))
+}
+
+impl<T: 'static> RuleRegistry<T>
+{
+    // The rule of a rule_line, with the same parameters and seed.
+    pub fn instantiate_line(&self, line: &str) -> Result<Box<dyn Rule<T>>, String>
+    {
+        let (name, config) = parse_rule_line(line)?;
+        self.instantiate(&name, &config).map_err(|error| error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn listings_have_every_built_in()
+    {
+        assert_eq!(RuleRegistry::<Light>::with_builtins().names(), ["falloff", "additive", "decay", "clamped"]);
+        assert_eq!(RuleRegistry::<u8>::with_builtins().names(), ["sandpile", "wireworld"]);
+        let listing = describe(&RuleRegistry::<Light>::with_builtins());
+        assert_eq!(listing.lines().count(), 4);
+        assert!(listing.lines().any(|line| line.starts_with("decay: ") && line.contains("[amount=2: ")));
+    }
+
+    #[test]
+    fn unknown_rules_and_parameters_are_errors()
+    {
+        let registry = RuleRegistry::<Light>::with_builtins();
+        match registry.instantiate("sunlight", &RuleConfig::new(0))
+        {
+            Err(RegistryError::UnknownRule{name, known}) => assert_eq!((name.as_str(), known), ("sunlight", registry.names())),
+            _ => panic!("sunlight is not a rule")
+        }
+        assert!(matches!(registry.instantiate("falloff", &RuleConfig::new(0).with("amount", 1.0)),
+                         Err(RegistryError::UnknownParam{..})));
+        assert!(matches!(registry.instantiate("decay", &RuleConfig::new(0).with("amount", 256.0)),
+                         Err(RegistryError::OutOfRange{value, ..}) if value == 256.0));
+        // Aliases give the rule under its own name.
+        assert_eq!(registry.instantiate("light-falloff", &RuleConfig::new(0)).unwrap().name(), Some("falloff"));
+    }
+
+    #[test]
+    fn users_can_register_their_rules()
+    {
+        let mut registry = RuleRegistry::<u8>::with_builtins();
+        registry.register("fill", "every cell becomes `value`", vec![ParamSpec::new("value", "the state", 1.0, (0.0, 9.0))],
+                          |config| {
+                              let value = level(config, "value");
+                              move |_: Vec<u8>| value
+                          }).unwrap();
+        assert_eq!(registry.names().last().map(String::as_str), Some("fill"));
+        let rule = registry.instantiate("fill", &RuleConfig::new(3).with("value", 7.0)).unwrap();
+        assert_eq!(rule.apply(vec![0, 1, 2, 3]), 7);
+        assert_eq!(rule.config(), Some(&RuleConfig::new(3).with("value", 7.0)));
+        assert_eq!(registry.instantiate("fill", &RuleConfig::new(0)).unwrap().apply(vec![5]), 1);
+        assert_eq!(registry.register("sandpile", "", vec![], |_| |ngh: Vec<u8>| ngh[0]).err(),
+                   Some(RegistryError::AlreadyRegistered("sandpile".to_string())));
+        assert!(registry.alias("fill", "sandpile").is_err());
+    }
+
+    #[test]
+    fn parameters_are_name_value_words()
+    {
+        assert_eq!(parse_params("amount=3 max=2.5".split_whitespace()), Ok(RuleConfig::new(0).with("amount", 3.0).with("max", 2.5)));
+        assert!(parse_params("amount".split_whitespace()).is_err());
+        assert!(parse_params("amount=lots".split_whitespace()).is_err());
+        let config = RuleConfig::new(7).with("amount", 3.0);
+        assert_eq!(rule_line("decay", &config), "decay amount=3 seed=7");
+        assert_eq!(parse_rule_line("decay amount=3 seed=7"), Ok(("decay".to_string(), config)));
+        assert_eq!(parse_rule_line("decay"), Ok(("decay".to_string(), RuleConfig::new(0))));
+        assert!(parse_rule_line("decay seed=-1").is_err());
+        assert!(parse_rule_line("").is_err());
+    }
+}
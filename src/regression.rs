@@ -0,0 +1,261 @@
+// Golden runs of the built-in rules: every rule has a canonical scenario,
+// a seeded start and a number of steps, and its fixture in regression/
+// holds the fingerprint (see gallery.rs) of every generation of the run,
+// the start included, one per line:
+//
+//     # falloff: light, 4 seeded sources on 32x16, defaults, 60 steps
+//     90b9496247e27917
+//     ...
+//
+// verify runs the scenario again and compares it step by step, so that a
+// change of behavior is reported at the first step it shows in rather than
+// as a different end. With TRIANGLE_AUTOMATA_BLESS set, verify writes the
+// fixture from the run instead, for changes of behavior that are meant.
+
+use crate::{Automata, Grid, Light, Rule};
+use crate::palette::strip_comment;
+use crate::registry::RuleRegistry;
+use crate::rng::SplitMix64;
+use crate::rules::{self, Forest};
+use crate::sweep::RuleConfig;
+
+use std::fmt::{self, Debug, Display};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+pub const BLESS_VAR: &str = "TRIANGLE_AUTOMATA_BLESS";
+pub const RULES: &[&str] = &["falloff", "additive", "decay", "clamped", "forest-fire", "sandpile", "wireworld"];
+
+const SEED: u64 = 1;
+const STEPS: usize = 60;
+const DIMS: (usize, usize) = (32, 16);
+
+#[derive(Debug)]
+pub enum RegressionError
+{
+    Io(io::Error),
+    UnknownRule(String),
+    // A line of the fixture that is not a fingerprint.
+    BadFixture(usize),
+    // The first generation that differs.
+    Diverged{step: usize, expected: u64, found: u64},
+    // The fixture and the run agree as long as both go.
+    Length{expected: usize, found: usize}
+}
+
+impl fmt::Display for RegressionError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            RegressionError::Io(error) => write!(f, "{}", error),
+            RegressionError::UnknownRule(name) => write!(f, "no scenario for rule '{}'", name),
+            RegressionError::BadFixture(line) => write!(f, "line {} of the fixture is not a fingerprint", line),
+            RegressionError::Diverged{step, expected, found} =>
+                write!(f, "diverged at step {}: fingerprint {:016x}, the fixture has {:016x}", step, found, expected),
+            RegressionError::Length{expected, found} =>
+                write!(f, "the fixture has {} generations, the run {}", expected, found)
+        }
+    }
+}
+
+fn fixture_path(rule: &str) -> PathBuf
+{
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("regression").join(format!("{}.chain", rule))
+}
+
+// Fingerprints of the start and of every step.
+fn run<T, F>(start: Grid<T>, mut step: F) -> Vec<u64>
+where
+    T: Clone + Display + Copy + Debug,
+    F: FnMut(&mut Automata<T>)
+{
+    let mut automata = Automata::new(start);
+    let mut chain = vec![automata.current().fingerprint()];
+    for _ in 0..STEPS
+    {
+        step(&mut automata);
+        chain.push(automata.current().fingerprint());
+    }
+    chain
+}
+
+fn registered<T>(registry: &RuleRegistry<T>, name: &str, start: Grid<T>) -> Vec<u64>
+where
+    T: Clone + Display + Copy + Debug + 'static
+{
+    let rule = registry.instantiate(name, &RuleConfig::new(SEED)).expect("the scenarios are of built-in rules");
+    run(start, |automata| automata.evolve(|ngh| rule.apply(ngh)))
+}
+
+// Sources of levels 8 to 20 at seeded places in the dark.
+fn light_start() -> Grid<Light>
+{
+    let mut rng = SplitMix64::new(SEED);
+    let mut grid = Grid::new(DIMS, Light::Space(0));
+    for _ in 0..4
+    {
+        let coord = (rng.below(DIMS.0 as u64) as usize, rng.below(DIMS.1 as u64) as usize);
+        *grid.get_mut(coord).unwrap() = Light::Source(8 + rng.below(13) as u8);
+    }
+    grid
+}
+
+// The scenario's description, for the head of the fixture, and its
+// chain.
+fn scenario(rule: &str) -> Result<(&'static str, Vec<u64>), RegressionError>
+{
+    let light = || RuleRegistry::<Light>::with_builtins();
+    let grains = || RuleRegistry::<u8>::with_builtins();
+    Ok(match rule
+    {
+        "falloff" | "additive" | "decay" | "clamped" =>
+            ("light, 4 seeded sources on 32x16, defaults", registered(&light(), rule, light_start())),
+        "sandpile" =>
+        {
+            let mut rng = SplitMix64::new(SEED);
+            ("grains, 0 to 5 seeded on every cell of 32x16", registered(&grains(), rule, Grid::from_fn(DIMS, |_| rng.below(6) as u8)))
+        },
+        "wireworld" =>
+        {
+            // Wires along every other row, a head and its tail on each.
+            let start = Grid::from_fn(DIMS, |(i, j)| match (j % 2, i % 11) { (1, 0) => 2, (1, 1) => 1, (1, _) => 3, _ => 0 });
+            ("wires along every other row of 32x16, a head on each every 11 cells", registered(&grains(), rule, start))
+        },
+        "forest-fire" =>
+        {
+            let mut rng = SplitMix64::new(SEED);
+            let start = Grid::from_fn(DIMS, |_| if rng.below(2) == 0 { Forest::Tree } else { Forest::Empty });
+            let fire = rules::forest_fire(0.05, 0.002);
+            ("half trees seeded on 32x16, growth 0.05, lightning 0.002, seeded steps",
+             run(start, |automata| automata.evolve_stochastic(&fire, SEED)))
+        },
+        _ => return Err(RegressionError::UnknownRule(rule.to_string()))
+    })
+}
+
+// The chain of the rule's scenario as it runs now.
+pub fn chain(rule: &str) -> Result<Vec<u64>, RegressionError>
+{
+    scenario(rule).map(|(_, chain)| chain)
+}
+
+// A chain as in the fixtures: comments, then a fingerprint per line.
+pub fn parse_chain(text: &str) -> Result<Vec<u64>, RegressionError>
+{
+    text.lines().enumerate()
+        .map(|(n, line)| (n, strip_comment(line).trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(n, line)| u64::from_str_radix(line, 16).map_err(|_| RegressionError::BadFixture(n + 1)))
+        .collect()
+}
+
+pub fn chain_text(header: &str, chain: &[u64]) -> String
+{
+    let mut text = format!("# {}\n", header);
+    for fingerprint in chain
+    {
+        text.push_str(&format!("{:016x}\n", fingerprint));
+    }
+    text
+}
+
+pub fn read_fixture(rule: &str) -> Result<Vec<u64>, RegressionError>
+{
+    parse_chain(&fs::read_to_string(fixture_path(rule)).map_err(RegressionError::Io)?)
+}
+
+// Writes the fixture from the run as it is now.
+pub fn bless(rule: &str) -> Result<(), RegressionError>
+{
+    let (description, chain) = scenario(rule)?;
+    let text = chain_text(&format!("{}: {}, {} steps", rule, description, STEPS), &chain);
+    let path = fixture_path(rule);
+    fs::create_dir_all(path.parent().expect("fixtures are in a directory")).map_err(RegressionError::Io)?;
+    fs::write(path, text).map_err(RegressionError::Io)
+}
+
+// Replays the rule's scenario against its fixture, or blesses it under
+// TRIANGLE_AUTOMATA_BLESS.
+pub fn verify(rule: &str) -> Result<(), RegressionError>
+{
+    if std::env::var_os(BLESS_VAR).is_some_and(|value| !value.is_empty())
+    {
+        return bless(rule);
+    }
+    compare(&read_fixture(rule)?, &chain(rule)?)
+}
+
+// The first step two chains differ at, if any.
+fn compare(expected: &[u64], found: &[u64]) -> Result<(), RegressionError>
+{
+    if let Some(step) = (0..expected.len().min(found.len())).find(|&step| expected[step] != found[step])
+    {
+        return Err(RegressionError::Diverged{step, expected: expected[step], found: found[step]});
+    }
+    if expected.len() != found.len()
+    {
+        return Err(RegressionError::Length{expected: expected.len(), found: found.len()});
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // The corpus, as the crate's tests run it: a failure names the rule
+    // and the first step it diverged at.
+    #[test]
+    fn every_rule_replays_its_fixture()
+    {
+        let problems: Vec<String> = RULES.iter()
+            .filter_map(|rule| verify(rule).err().map(|error| format!("{}: {}", rule, error)))
+            .collect();
+        assert!(problems.is_empty(), "{}", problems.join("\n"));
+    }
+
+    #[test]
+    fn chains_have_the_start_and_every_step()
+    {
+        let falloff = chain("falloff").unwrap();
+        assert_eq!(falloff.len(), STEPS + 1);
+        assert_eq!(falloff[0], light_start().fingerprint());
+        assert_eq!(falloff, chain_of_falloff());
+        assert!(matches!(chain("sunlight"), Err(RegressionError::UnknownRule(_))));
+    }
+
+    fn chain_of_falloff() -> Vec<u64>
+    {
+        run(light_start(), |automata| automata.evolve(rules::light_falloff))
+    }
+
+    #[test]
+    fn the_first_divergent_step_is_reported()
+    {
+        let expected = [1, 2, 3, 4, 5];
+        assert!(compare(&expected, &expected).is_ok());
+        match compare(&expected, &[1, 2, 7, 8, 5])
+        {
+            Err(RegressionError::Diverged{step, expected, found}) => assert_eq!((step, expected, found), (2, 3, 7)),
+            other => panic!("expected a divergence, got {:?}", other)
+        }
+        assert!(matches!(compare(&expected, &[1, 2, 3]), Err(RegressionError::Length{expected: 5, found: 3})));
+        assert_eq!(compare(&expected, &[1, 9]).unwrap_err().to_string(),
+                   "diverged at step 1: fingerprint 0000000000000009, the fixture has 0000000000000002");
+    }
+
+    #[test]
+    fn fixtures_read_back()
+    {
+        let chain = vec![0x90b9496247e27917, 0, u64::MAX];
+        let text = chain_text("falloff: a test", &chain);
+        assert!(text.starts_with("# falloff: a test\n90b9496247e27917\n"));
+        assert_eq!(parse_chain(&text).unwrap(), chain);
+        assert_eq!(parse_chain("\n  # comment\nff  # trailing\n").unwrap(), [0xff]);
+        assert!(matches!(parse_chain("# head\n12\nxyz\n"), Err(RegressionError::BadFixture(3))));
+    }
+}
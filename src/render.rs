@@ -0,0 +1,857 @@
+use crate::{Automata, CellState, Grid, Light, Slot};
+use crate::analysis::GradientInfo;
+use crate::overlay::{Annotation, Overlay};
+use crate::run::FrameFilter;
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+pub const CLEAR: &str = "\x1b[2J\x1b[H";
+
+pub(crate) fn json_string(s: &str) -> String
+{
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars()
+    {
+        match c
+        {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
+
+// (columns, rows) needed to show a rendered frame.
+fn frame_size(frame: &str) -> (usize, usize)
+{
+    let width = frame.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+    (width, frame.lines().count())
+}
+
+// Writes an asciinema v2 recording of `steps` steps: a header line, then
+// one event per frame (the initial state included) clearing the screen and
+// drawing the grid, `1/fps` seconds apart. The terminal size in the header
+// is the size of the largest frame.
+pub fn write_asciicast<T, F, W>(automata: &mut Automata<T>, rule: F, steps: usize, fps: f64, writer: W) -> io::Result<()>
+where
+    T: Copy + Debug + Display,
+    F: Fn(Vec<T>) -> T,
+    W: Write
+{
+    write_asciicast_filtered(automata, rule, steps, fps, &FrameFilter::all(), writer)
+}
+
+// The same with only the generations `filter` selects as frames; they are
+// still `1/fps` seconds apart.
+pub fn write_asciicast_filtered<T, F, W>(automata: &mut Automata<T>, rule: F, steps: usize, fps: f64, filter: &FrameFilter<T>, mut writer: W) -> io::Result<()>
+where
+    T: Copy + Debug + Display,
+    F: Fn(Vec<T>) -> T,
+    W: Write
+{
+    let mut frames = vec![];
+    for step in 0..=steps
+    {
+        if step > 0
+        {
+            automata.evolve(&rule);
+        }
+        if filter.selects(automata.step(), automata.current())
+        {
+            frames.push(automata.current().render());
+        }
+    }
+
+    let (width, height) = frames.iter()
+        .map(|frame| frame_size(frame))
+        .fold((0, 0), |(w, h), (fw, fh)| (w.max(fw), h.max(fh)));
+    writeln!(writer, "{{\"version\": 2, \"width\": {}, \"height\": {}, \"env\": {{\"TERM\": \"xterm-256color\"}}}}", width, height)?;
+    for (n, frame) in frames.iter().enumerate()
+    {
+        let data = format!("{}{}", CLEAR, frame.replace('\n', "\r\n"));
+        writeln!(writer, "[{:.6}, \"o\", {}]", n as f64 / fps, json_string(&data))?;
+    }
+    writer.flush()
+}
+
+pub fn record_asciicast<T, F, P>(automata: &mut Automata<T>, rule: F, steps: usize, fps: f64, path: P) -> io::Result<()>
+where
+    T: Copy + Debug + Display,
+    F: Fn(Vec<T>) -> T,
+    P: AsRef<Path>
+{
+    write_asciicast(automata, rule, steps, fps, BufWriter::new(File::create(path)?))
+}
+
+pub fn record_asciicast_filtered<T, F, P>(automata: &mut Automata<T>, rule: F, steps: usize, fps: f64, filter: &FrameFilter<T>, path: P) -> io::Result<()>
+where
+    T: Copy + Debug + Display,
+    F: Fn(Vec<T>) -> T,
+    P: AsRef<Path>
+{
+    write_asciicast_filtered(automata, rule, steps, fps, filter, BufWriter::new(File::create(path)?))
+}
+
+
+// Corners of the cell as lattice points (x in half edges, y in rows),
+// counterclockwise when seen from above the exported mesh.
+pub fn corners((i, j): (usize, usize)) -> [(usize, usize); 3]
+{
+    if (i + j).is_multiple_of(2)
+    {
+        [(i+1, j), (i, j+1), (i+2, j+1)]
+    }
+    else
+    {
+        [(i, j), (i+1, j+1), (i+2, j)]
+    }
+}
+
+// Wavefront OBJ mesh of the grid, y up and rows along z, one triangle per
+// cell with edges `cell_size` long. Adjacent faces share their vertices,
+// whose height is the mean of `height` over the cells touching them, so
+// the surface is smooth and watertight.
+pub fn to_obj<T, F, W>(grid: &Grid<T>, mut writer: W, height: F, cell_size: f32) -> io::Result<()>
+where
+    T: Copy + Debug,
+    F: Fn(&T) -> f32,
+    W: Write
+{
+    let mut index = HashMap::new();
+    let mut vertices: Vec<((usize, usize), f32, usize)> = vec![];
+    let mut faces = Vec::with_capacity(grid.dims.0*grid.dims.1);
+    for j in 0..grid.dims.1
+    {
+        for i in 0..grid.dims.0
+        {
+            let h = height(grid.get((i, j)).unwrap());
+            let mut face = [0; 3];
+            for (corner, point) in face.iter_mut().zip(corners((i, j)).iter())
+            {
+                let n = *index.entry(*point).or_insert_with(|| {
+                    vertices.push((*point, 0.0, 0));
+                    vertices.len() - 1
+                });
+                vertices[n].1 += h;
+                vertices[n].2 += 1;
+                *corner = n;
+            }
+            faces.push(face);
+        }
+    }
+
+    writeln!(writer, "# {}x{} triangle grid", grid.dims.0, grid.dims.1)?;
+    let row_height = cell_size * 3f32.sqrt() / 2.0;
+    for ((x, y), total, count) in &vertices
+    {
+        writeln!(writer, "v {} {} {}", *x as f32 * cell_size / 2.0, total / *count as f32, *y as f32 * row_height)?;
+    }
+    for [a, b, c] in faces
+    {
+        writeln!(writer, "f {} {} {}", a + 1, b + 1, c + 1)?;
+    }
+    writer.flush()
+}
+
+// One character per cell, for the compact renderers.
+pub trait Glyph
+{
+    fn glyph(&self) -> char;
+}
+
+fn level_glyph(level: u8) -> char
+{
+    match level
+    {
+        0 => ' ',
+        1..=9 => (b'0' + level) as char,
+        _ => '+'
+    }
+}
+
+// Pinned cells are drawn as '*', the others by their level.
+impl<L: CellState> Glyph for L
+{
+    fn glyph(&self) -> char
+    {
+        if self.is_pinned() { '*' } else { level_glyph(self.level()) }
+    }
+}
+
+impl Glyph for u8
+{
+    fn glyph(&self) -> char
+    {
+        level_glyph(*self)
+    }
+}
+
+impl Glyph for bool
+{
+    fn glyph(&self) -> char
+    {
+        if *self {'#'} else {' '}
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notation
+{
+    Fixed,
+    Scientific
+}
+
+// How DisplayCell writes a cell: `width` characters, numbers with
+// `precision` digits after the point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellFormat
+{
+    pub width: usize,
+    pub precision: usize,
+    pub notation: Notation
+}
+
+impl Default for CellFormat
+{
+    fn default() -> Self
+    {
+        Self{width: 3, precision: 1, notation: Notation::Fixed}
+    }
+}
+
+impl CellFormat
+{
+    pub fn number(&self, value: f64) -> String
+    {
+        let text = match self.notation
+        {
+            Notation::Fixed => format!("{:.*}", self.precision, value),
+            Notation::Scientific => format!("{:.*e}", self.precision, value)
+        };
+        self.fit(&text)
+    }
+
+    // Centers the text in `width` characters, cutting what does not fit.
+    pub fn fit(&self, text: &str) -> String
+    {
+        let text: String = text.chars().take(self.width).collect();
+        format!("{:^width$}", text, width = self.width)
+    }
+}
+
+// Cell contents for the formatted renderers, whatever the type's own
+// Display does.
+pub trait DisplayCell
+{
+    fn display_cell(&self, format: &CellFormat) -> String;
+}
+
+impl DisplayCell for f32
+{
+    fn display_cell(&self, format: &CellFormat) -> String
+    {
+        format.number(f64::from(*self))
+    }
+}
+
+impl DisplayCell for f64
+{
+    fn display_cell(&self, format: &CellFormat) -> String
+    {
+        format.number(*self)
+    }
+}
+
+impl DisplayCell for Light
+{
+    fn display_cell(&self, format: &CellFormat) -> String
+    {
+        format.fit(self.to_string().trim())
+    }
+}
+
+impl DisplayCell for u8
+{
+    fn display_cell(&self, format: &CellFormat) -> String
+    {
+        format.fit(&self.to_string())
+    }
+}
+
+impl DisplayCell for bool
+{
+    fn display_cell(&self, format: &CellFormat) -> String
+    {
+        format.fit(if *self { "#" } else { "" })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode
+{
+    // The ASCII-art drawing of Grid::render.
+    Full,
+    // One glyph per triangle, one line per row.
+    Compact,
+    // Compact rendering of the (origin, dims) part of the grid only.
+    Viewport{origin: (usize, usize), dims: (usize, usize)}
+}
+
+// Size in characters of Grid::render's output.
+pub fn full_size((w, h): (usize, usize)) -> (usize, usize)
+{
+    (3*w + 7, 3*h + 1)
+}
+
+// Picks the richest rendering that fits in the terminal, keeping one line
+// for the note printed by `auto`.
+pub fn choose_mode(grid_dims: (usize, usize), term_dims: (usize, usize)) -> RenderMode
+{
+    let (cols, rows) = (term_dims.0, term_dims.1.saturating_sub(1));
+    let (full_w, full_h) = full_size(grid_dims);
+    if full_w <= cols && full_h <= rows
+    {
+        RenderMode::Full
+    }
+    else if grid_dims.0 <= cols && grid_dims.1 <= rows
+    {
+        RenderMode::Compact
+    }
+    else
+    {
+        let dims = (grid_dims.0.min(cols).max(1), grid_dims.1.min(rows).max(1));
+        RenderMode::Viewport{origin: (0, 0), dims}
+    }
+}
+
+// (columns, rows) of the terminal on stdout, or 80x24 when stdout is not a
+// terminal or its size cannot be found.
+pub fn terminal_size() -> (usize, usize)
+{
+    let fallback = (80, 24);
+    if !io::stdout().is_terminal()
+    {
+        return fallback;
+    }
+    let from_env = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<usize>().ok());
+    if let (Some(cols), Some(rows)) = (from_env("COLUMNS"), from_env("LINES"))
+    {
+        return (cols, rows);
+    }
+    let output = File::open("/dev/tty").ok().and_then(|tty| {
+        Command::new("stty").arg("size").stdin(Stdio::from(tty)).output().ok()
+    });
+    let size = output.and_then(|output| {
+        let text = String::from_utf8(output.stdout).ok()?;
+        let mut fields = text.split_whitespace().map(|f| f.parse::<usize>().ok());
+        let rows = fields.next()??;
+        let cols = fields.next()??;
+        Some((cols, rows))
+    });
+    size.unwrap_or(fallback)
+}
+
+pub fn render_compact<T: Copy + Debug + Glyph>(grid: &Grid<T>) -> String
+{
+    render_viewport(grid, (0, 0), grid.dims)
+}
+
+pub fn render_viewport<T: Copy + Debug + Glyph>(grid: &Grid<T>, origin: (usize, usize), dims: (usize, usize)) -> String
+{
+    let mut out = String::new();
+    for j in origin.1..(origin.1 + dims.1).min(grid.dims.1)
+    {
+        for i in origin.0..(origin.0 + dims.0).min(grid.dims.0)
+        {
+            out.push(grid.get((i, j)).unwrap().glyph());
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// Compact rendering with the uphill direction of every cell drawn over
+// it: < and > towards the side neighbors, v and ^ across the horizontal
+// edge (below up triangles, above down ones). Cells without a brighter
+// neighbor show their own glyph.
+pub fn render_flow<T: Copy + Debug + Glyph>(grid: &Grid<T>, field: &Grid<GradientInfo>) -> String
+{
+    let mut out = String::new();
+    for j in 0..grid.dims.1
+    {
+        for i in 0..grid.dims.0
+        {
+            let glyph = match field.get((i, j)).and_then(|info| info.toward)
+            {
+                Some(Slot::Left) => '<',
+                Some(Slot::Right) => '>',
+                Some(Slot::Across) if (i + j).is_multiple_of(2) => 'v',
+                Some(Slot::Across) => '^',
+                None => grid.get((i, j)).unwrap().glyph()
+            };
+            out.push(glyph);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+pub fn render_mode<T: Copy + Debug + Display + Glyph>(grid: &Grid<T>, mode: RenderMode) -> String
+{
+    match mode
+    {
+        RenderMode::Full => grid.render(),
+        RenderMode::Compact => render_compact(grid),
+        RenderMode::Viewport{origin, dims} => render_viewport(grid, origin, dims)
+    }
+}
+
+// ANSI escapes wrapping `text` for the annotation: the highlight color as
+// background, inverse video without one.
+fn annotated(text: &str, annotation: &Annotation) -> String
+{
+    match annotation.color
+    {
+        Some([r, g, b]) => format!("\x1b[48;2;{};{};{}m{}\x1b[0m", r, g, b, text),
+        None => format!("\x1b[7m{}\x1b[0m", text)
+    }
+}
+
+// render_mode with the annotated cells highlighted, their label (or its
+// first character in the compact renderings) written instead of the cell.
+pub fn render_overlaid<T: Copy + Debug + Display + Glyph>(grid: &Grid<T>, mode: RenderMode, overlay: &Overlay) -> String
+{
+    let (origin, dims) = match mode
+    {
+        RenderMode::Full =>
+        {
+            let w = grid.dims.0;
+            let indexed = Grid::from_fn(grid.dims, |(i, j)| (*grid.get((i, j)).unwrap(), j*w + i));
+            return indexed.render_labels(|&(cell, index)| match overlay.get((index % w, index / w))
+            {
+                Some(annotation) =>
+                {
+                    let text = match &annotation.label
+                    {
+                        Some(label) => format!("{:^3.3}", label),
+                        None => format!("{}", cell)
+                    };
+                    annotated(&text, annotation)
+                },
+                None => format!("{}", cell)
+            });
+        },
+        RenderMode::Compact => ((0, 0), grid.dims),
+        RenderMode::Viewport{origin, dims} => (origin, dims)
+    };
+    let mut out = String::new();
+    for j in origin.1..(origin.1 + dims.1).min(grid.dims.1)
+    {
+        for i in origin.0..(origin.0 + dims.0).min(grid.dims.0)
+        {
+            let glyph = grid.get((i, j)).unwrap().glyph();
+            match overlay.get((i, j))
+            {
+                Some(annotation) =>
+                {
+                    let glyph = annotation.label.as_ref().and_then(|label| label.chars().next()).unwrap_or(glyph);
+                    out.push_str(&annotated(&glyph.to_string(), annotation));
+                },
+                None => out.push(glyph)
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// The full drawing has room for three characters per triangle, so it
+// ignores the width of the format; the compact ones give every triangle
+// `width` characters, separated by a space when wider than one.
+pub fn render_formatted<T: Copy + Debug + DisplayCell>(grid: &Grid<T>, mode: RenderMode, format: &CellFormat) -> String
+{
+    let (origin, dims) = match mode
+    {
+        RenderMode::Full =>
+        {
+            let format = CellFormat{width: 3, ..*format};
+            return grid.render_labels(|cell| cell.display_cell(&format));
+        },
+        RenderMode::Compact => ((0, 0), grid.dims),
+        RenderMode::Viewport{origin, dims} => (origin, dims)
+    };
+    let separator = if format.width > 1 { " " } else { "" };
+    let mut out = String::new();
+    for j in origin.1..(origin.1 + dims.1).min(grid.dims.1)
+    {
+        let row: Vec<String> = (origin.0..(origin.0 + dims.0).min(grid.dims.0))
+            .map(|i| grid.get((i, j)).unwrap().display_cell(format))
+            .collect();
+        out.push_str(&row.join(separator));
+        out.push('\n');
+    }
+    out
+}
+
+// Redraws compact frames by moving the cursor to the cells that changed
+// since the previous frame and writing only their glyphs. The screen is
+// cleared and repainted instead for the first frame, after a change of
+// grid, mode or terminal size, and when more than `max_changed` (a
+// fraction of the visible cells) changed, where moving the cursor around
+// would cost more than it saves. The full layout is always repainted.
+pub struct DiffRenderer<T>
+{
+    previous: Option<(Grid<T>, RenderMode, (usize, usize))>,
+    max_changed: f64
+}
+
+impl<T: Copy + Debug + PartialEq + Display + Glyph> DiffRenderer<T>
+{
+    pub fn new(max_changed: f64) -> Self
+    {
+        Self{previous: None, max_changed}
+    }
+
+    // Drops the previous frame, so that the next one is a full repaint.
+    pub fn invalidate(&mut self)
+    {
+        self.previous = None;
+    }
+
+    // Escape sequences and glyphs turning the previous frame into this one,
+    // the grid being drawn from the top left corner of the screen.
+    pub fn frame(&mut self, grid: &Grid<T>, mode: RenderMode, term_dims: (usize, usize)) -> String
+    {
+        let (origin, dims) = match mode
+        {
+            RenderMode::Full => ((0, 0), (0, 0)),
+            RenderMode::Compact => ((0, 0), grid.dims),
+            RenderMode::Viewport{origin, dims} => (origin, dims)
+        };
+        let columns = origin.0..(origin.0 + dims.0).min(grid.dims.0);
+        let rows = origin.1..(origin.1 + dims.1).min(grid.dims.1);
+
+        let changed: Option<Vec<(usize, usize)>> = match &self.previous
+        {
+            Some((previous, previous_mode, previous_term))
+                if previous.dims == grid.dims && *previous_mode == mode && *previous_term == term_dims =>
+                Some(rows.clone()
+                    .flat_map(|j| columns.clone().map(move |i| (i, j)))
+                    .filter(|&coord| previous.get(coord) != grid.get(coord))
+                    .collect()),
+            _ => None
+        };
+        self.previous = Some((grid.clone(), mode, term_dims));
+
+        let visible = (columns.len()*rows.len()) as f64;
+        let changed = match changed
+        {
+            Some(changed) if mode != RenderMode::Full && changed.len() as f64 <= self.max_changed * visible => changed,
+            _ => return format!("{}{}", CLEAR, render_mode(grid, mode))
+        };
+
+        let mut out = String::new();
+        let mut cursor = None;
+        for (i, j) in changed
+        {
+            if cursor != Some((i, j))
+            {
+                out.push_str(&format!("\x1b[{};{}H", j - origin.1 + 1, i - origin.0 + 1));
+            }
+            out.push(grid.get((i, j)).unwrap().glyph());
+            cursor = Some((i + 1, j));
+        }
+        out
+    }
+}
+
+pub fn describe(grid_dims: (usize, usize), mode: RenderMode) -> String
+{
+    match mode
+    {
+        RenderMode::Full => format!("{}x{} grid, full rendering", grid_dims.0, grid_dims.1),
+        RenderMode::Compact => format!("{}x{} grid, compact rendering (1 char per triangle)", grid_dims.0, grid_dims.1),
+        RenderMode::Viewport{origin, dims} => format!("{}x{} grid, compact viewport of {}x{} at ({}, {})", grid_dims.0, grid_dims.1, dims.0, dims.1, origin.0, origin.1)
+    }
+}
+
+// Prints the grid with the mode chosen for the current terminal, preceded by
+// a line saying which one it is.
+pub fn auto<T: Copy + Debug + Display + Glyph>(grid: &Grid<T>)
+{
+    show(grid, choose_mode(grid.dims, terminal_size()));
+}
+
+pub fn show_formatted<T: Copy + Debug + DisplayCell>(grid: &Grid<T>, mode: RenderMode, format: &CellFormat)
+{
+    match mode
+    {
+        RenderMode::Compact if format.width != 1 =>
+            println!("{}x{} grid, compact rendering ({} chars per triangle)", grid.dims.0, grid.dims.1, format.width),
+        _ => println!("{}", describe(grid.dims, mode))
+    }
+    print!("{}", render_formatted(grid, mode, format));
+}
+
+pub fn show<T: Copy + Debug + Display + Glyph>(grid: &Grid<T>, mode: RenderMode)
+{
+    println!("{}", describe(grid.dims, mode));
+    print!("{}", render_mode(grid, mode));
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::color::ColorMap;
+    use crate::plots;
+    use crate::rules::{self, Clamp};
+    use crate::snapshot::assert_matches;
+
+    // A source in a corner and its light, with an odd width so that the
+    // last column of the drawing is covered.
+    fn lit() -> Grid<Light>
+    {
+        let mut automata = Automata::new(Grid::new((7, 4), Light::Space(0)));
+        *automata.get_mut((1, 1)).unwrap() = Light::Source(5);
+        for _ in 0..3
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        automata.current().clone()
+    }
+
+    #[test]
+    fn full()
+    {
+        assert_matches("full", &render_mode(&lit(), RenderMode::Full));
+    }
+
+    #[test]
+    fn compact()
+    {
+        assert_matches("compact", &render_mode(&lit(), RenderMode::Compact));
+    }
+
+    #[test]
+    fn viewport()
+    {
+        assert_matches("viewport", &render_mode(&lit(), RenderMode::Viewport{origin: (1, 1), dims: (4, 2)}));
+    }
+
+    #[test]
+    fn formatted()
+    {
+        let heat = Grid::from_fn((4, 2), |(i, j)| (i + 4*j) as f32 / 3.0);
+        let format = CellFormat{width: 5, ..CellFormat::default()};
+        assert_matches("formatted-full", &render_formatted(&heat, RenderMode::Full, &format));
+        assert_matches("formatted-compact", &render_formatted(&heat, RenderMode::Compact, &format));
+    }
+
+    #[test]
+    fn overlaid()
+    {
+        let mut overlay = Overlay::new();
+        overlay.label((1, 1), "src").mark((3, 0));
+        assert_matches("overlaid", &render_overlaid(&lit(), RenderMode::Full, &overlay));
+    }
+
+    // The highlighted cell is drawn in inverse video, its neighbor to the
+    // left, of the same level, as it is.
+    #[test]
+    fn overlaid_compact()
+    {
+        let grid = Grid::from_fn((3, 1), |_| Light::Space(4));
+        let mut overlay = Overlay::new();
+        overlay.mark((1, 0)).highlight((2, 0), [10, 20, 30]).label((2, 0), "x!");
+        let out = render_overlaid(&grid, RenderMode::Compact, &overlay);
+        let plain = render_mode(&grid, RenderMode::Compact);
+        let glyph = plain.chars().next().unwrap();
+        assert_eq!(out, format!("{}\x1b[7m{}\x1b[0m\x1b[48;2;10;20;30mx\x1b[0m\n", glyph, glyph));
+        assert_eq!(render_overlaid(&grid, RenderMode::Compact, &Overlay::new()), plain);
+    }
+
+    #[test]
+    fn heatmap()
+    {
+        let mut svg = vec![];
+        plots::write_heatmap(&lit(), |cell| f64::from(cell.level()), ColorMap::Viridis, &mut svg).unwrap();
+        assert_matches("heatmap", &String::from_utf8(svg).unwrap());
+    }
+
+    // The light demo of the binary: a source of 10 in the middle of a dark
+    // 30x20 grid under the falloff rule.
+    #[test]
+    fn light_demo()
+    {
+        let rule = rules::light_falloff_clamped(Clamp::saturate(u8::MAX));
+        let mut automata = Automata::new(Grid::new((30, 20), Light::Space(0)));
+        *automata.get_mut((10, 10)).unwrap() = Light::Source(10);
+        for step in 0..=15
+        {
+            if let 0 | 5 | 15 = step
+            {
+                assert_matches(&format!("light-demo-{}", step), &automata.current().render());
+            }
+            automata.evolve(|ngh| rule(ngh).unwrap());
+        }
+    }
+
+    #[test]
+    fn asciicast_events()
+    {
+        let mut automata = Automata::new(lit());
+        let mut cast = vec![];
+        write_asciicast(&mut automata, rules::light_falloff, 6, 4.0, &mut cast).unwrap();
+        let cast = String::from_utf8(cast).unwrap();
+        let mut lines = cast.lines();
+        let (width, height) = full_size((7, 4));
+        assert_eq!(lines.next().unwrap(),
+                   format!("{{\"version\": 2, \"width\": {}, \"height\": {}, \"env\": {{\"TERM\": \"xterm-256color\"}}}}", width, height));
+        let events: Vec<&str> = lines.collect();
+        assert_eq!(events.len(), 7);
+        let mut last = -1.0;
+        for (n, event) in events.iter().enumerate()
+        {
+            let time: f64 = event.trim_start_matches('[').split(',').next().unwrap().parse().unwrap();
+            assert!(time > last);
+            assert_eq!(time, n as f64 / 4.0);
+            assert!(event.contains(", \"o\", \"\\u001b[2J\\u001b[H"), "{}", event);
+            assert!(event.ends_with("\"]"));
+            last = time;
+        }
+    }
+
+    #[test]
+    fn modes_for_terminal_sizes()
+    {
+        // The full drawing of a 20x10 grid takes 67x31 characters, plus
+        // the line of the note.
+        let grid = (20, 10);
+        let cases = [
+            ((67, 32), RenderMode::Full),
+            ((66, 32), RenderMode::Compact),
+            ((67, 31), RenderMode::Compact),
+            ((80, 24), RenderMode::Compact),
+            ((20, 11), RenderMode::Compact),
+            ((19, 11), RenderMode::Viewport{origin: (0, 0), dims: (19, 10)}),
+            ((20, 10), RenderMode::Viewport{origin: (0, 0), dims: (20, 9)}),
+            ((8, 4), RenderMode::Viewport{origin: (0, 0), dims: (8, 3)}),
+            ((0, 0), RenderMode::Viewport{origin: (0, 0), dims: (1, 1)})
+        ];
+        for &(term, mode) in &cases
+        {
+            assert_eq!(choose_mode(grid, term), mode, "in {:?}", term);
+        }
+        assert_eq!(full_size(grid), (67, 31));
+        assert_eq!(frame_size(&Grid::new(grid, Light::Space(0)).render()), full_size(grid));
+    }
+
+    #[test]
+    fn formats_keep_their_width()
+    {
+        let values = [0.0, -1.5, 1.0/3.0, 123456.789, -1e-12, f64::MAX];
+        for &notation in &[Notation::Fixed, Notation::Scientific]
+        {
+            for width in 1..9
+            {
+                for precision in 0..4
+                {
+                    let format = CellFormat{width, precision, notation};
+                    for &value in &values
+                    {
+                        assert_eq!(format.number(value).chars().count(), width, "{} in {:?}", value, format);
+                    }
+                    assert_eq!(Light::Source(200).display_cell(&format).chars().count(), width);
+                }
+            }
+        }
+        let format = CellFormat{width: 7, precision: 2, notation: Notation::Fixed};
+        assert_eq!(format.number(4.56789), " 4.57  ");
+        assert_eq!(CellFormat{notation: Notation::Scientific, ..format}.number(1234.0), "1.23e3 ");
+        assert_eq!(CellFormat::default().number(12.34), "12.");
+    }
+
+    #[test]
+    fn diff_frames_write_only_the_changes()
+    {
+        let mut renderer = DiffRenderer::new(0.25);
+        let first = Grid::new((8, 4), false);
+        let repaint = format!("{}{}", CLEAR, render_mode(&first, RenderMode::Compact));
+        assert_eq!(renderer.frame(&first, RenderMode::Compact, (80, 24)), repaint);
+        assert_eq!(repaint.len(), CLEAR.len() + 4*9);
+
+        let mut second = first.clone();
+        for &coord in &[(2, 1), (3, 1), (5, 3)]
+        {
+            second.set(coord, true).unwrap();
+        }
+        // A cursor move per run of changed cells in a row.
+        let frame = renderer.frame(&second, RenderMode::Compact, (80, 24));
+        assert_eq!(frame, "\x1b[2;3H##\x1b[4;6H#");
+        assert_eq!(frame.len(), 15);
+        assert_eq!(renderer.frame(&second, RenderMode::Compact, (80, 24)), "");
+
+        // Past a quarter of the cells, or on a resize, the frame is repainted.
+        let lit = Grid::from_fn((8, 4), |(i, _)| i < 3);
+        assert!(renderer.frame(&lit, RenderMode::Compact, (80, 24)).starts_with(CLEAR));
+        assert!(renderer.frame(&lit, RenderMode::Compact, (100, 30)).starts_with(CLEAR));
+        renderer.invalidate();
+        assert!(renderer.frame(&lit, RenderMode::Compact, (100, 30)).starts_with(CLEAR));
+        // Viewport positions are relative to its origin.
+        let viewport = RenderMode::Viewport{origin: (2, 1), dims: (4, 2)};
+        renderer.frame(&first, viewport, (100, 30));
+        assert_eq!(renderer.frame(&second, viewport, (100, 30)), "\x1b[1;1H##");
+    }
+
+    #[test]
+    fn obj_mesh_of_a_small_grid()
+    {
+        let grid = Grid::from_fn((4, 4), |(i, j)| (i + 4*j) as f32);
+        let mut obj = vec![];
+        to_obj(&grid, &mut obj, |&cell| cell, 2.0).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        let numbers = |line: &str| -> Vec<f32> {line.split_whitespace().skip(1).map(|n| n.parse().unwrap()).collect()};
+        let vertices: Vec<Vec<f32>> = obj.lines().filter(|line| line.starts_with("v ")).map(numbers).collect();
+        let faces: Vec<Vec<usize>> = obj.lines().filter(|line| line.starts_with("f "))
+            .map(|line| numbers(line).iter().map(|&n| n as usize).collect())
+            .collect();
+        // Five lattice lines of three points each.
+        assert_eq!(vertices.len(), 15);
+        assert_eq!(faces.len(), 16);
+        assert!(faces.iter().flatten().all(|&n| (1..=15).contains(&n)));
+
+        // Watertight and consistently wound: an edge is used at most once in
+        // each direction, and every edge used once one way and not the other
+        // is on the border, where the 4x4 grid has 12 of them.
+        let mut edges = HashMap::new();
+        for face in &faces
+        {
+            for k in 0..3
+            {
+                *edges.entry((face[k], face[(k + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        assert!(edges.values().all(|&count| count == 1));
+        let border = edges.keys().filter(|(a, b)| !edges.contains_key(&(*b, *a))).count();
+        assert_eq!(border, 12);
+
+        // The first vertex, the tip of cell (0, 0), is shared with (1, 0);
+        // the start of the next line with (0, 1).
+        assert_eq!(vertices[0], [1.0, 0.5, 0.0]);
+        let below = vertices.iter().find(|v| v[0] == 0.0 && v[2] > 0.0 && v[2] < 2.0).unwrap();
+        assert!((below[1] - 2.0).abs() < 1e-6, "{:?}", below);
+    }
+}
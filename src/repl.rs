@@ -0,0 +1,542 @@
+// Command prompt driving a Light automaton, one command per line:
+//
+//   step [n]              evolve n steps (1 by default)
+//   set i j source|space level
+//   brush cell|ball=R|rect|line   shape painted by paint
+//   paint i j source|space level  rect and line go from the last click
+//   fill i j source|space level   bucket fill of the region of (i, j)
+//   undo, u               the last set, paint or fill since the last step
+//   print                 draw the grid
+//   stats                 step number and light totals
+//   profile row|column k  levels along a row or a column
+//   save path / load path checkpoints
+//   rule name [p=v...]    a registered rule, with its parameters
+//   rules                 list the registered rules
+//   neighborhood kind     edge, vertex or radius=N
+//   source add name=I,J,LEVEL[,period=P][,ttl=T]
+//   source level name l / source move name i j / source remove name
+//   sources               list the named sources
+//   help, quit
+
+use crate::{Automata, CellState, Grid, Light};
+use crate::analysis;
+use crate::edit::{Brush, EditStack};
+use crate::error::check_coord;
+use crate::neighborhood::NeighborhoodKind;
+use crate::registry::{self, RuleRegistry};
+use crate::render::{self, RenderMode};
+use crate::session;
+use crate::sources::{self, Source, SourceSet};
+use crate::sweep::RuleConfig;
+
+use std::cell::RefCell;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+// The prompt's state around a session::Session, which steps go through.
+pub struct Session
+{
+    pub core: session::Session<Light>,
+    pub mode: RenderMode,
+    // Attached to the automaton, again after every load.
+    pub sources: Rc<RefCell<SourceSet>>,
+    pub brush: Brush,
+    // Where the last paint was, for the rect and line brushes.
+    pub last_click: Option<(usize, usize)>,
+    pub edits: EditStack<Light>
+}
+
+// Edits the prompt can undo.
+const UNDO_DEPTH: usize = 64;
+
+impl Session
+{
+    pub fn new(dims: (usize, usize), mode: RenderMode) -> Self
+    {
+        let rule = RuleRegistry::<Light>::global().instantiate("falloff", &RuleConfig::new(0)).expect("falloff is built in");
+        let mut session = Self{
+            core: session::Session::new(Automata::new(Grid::new(dims, Light::Space(0))), rule),
+            mode,
+            sources: Rc::new(RefCell::new(SourceSet::new())),
+            brush: Brush::default(),
+            last_click: None,
+            edits: EditStack::new(UNDO_DEPTH)
+        };
+        sources::attach(&session.sources, &mut session.core.automata);
+        session
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command
+{
+    Step(u64),
+    Set((usize, usize), Light),
+    Brush(Brush),
+    Paint((usize, usize), Light),
+    Fill((usize, usize), Light),
+    Undo,
+    Print,
+    Stats,
+    // Row (false) or column (true), and its index.
+    Profile(bool, usize),
+    Save(String),
+    Load(String),
+    Rule(String, RuleConfig),
+    Rules,
+    Neighborhood(NeighborhoodKind),
+    SourceAdd(Source),
+    SourceLevel(String, u8),
+    SourceMove(String, (usize, usize)),
+    SourceRemove(String),
+    Sources,
+    Help,
+    Quit
+}
+
+// What the prompt should do after a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome
+{
+    // Print the text, which may be empty, and read the next command.
+    Continue(String),
+    Quit
+}
+
+const HELP: &str = "commands: step [n], set i j source|space level, brush cell|ball=R|rect|line, paint i j source|space level, fill i j source|space level, undo (u), print, stats, profile row|column k, save path, load path, rule name [param=value...], rules, neighborhood edge|vertex|radius=N, source add name=I,J,LEVEL[,period=P][,ttl=T], source level name l, source move name i j, source remove name, sources, help, quit";
+
+fn number<T: std::str::FromStr>(word: Option<&str>, what: &str) -> Result<T, String>
+{
+    let word = word.ok_or_else(|| format!("missing {}", what))?;
+    word.parse().map_err(|_| format!("invalid {} '{}'", what, word))
+}
+
+// `i j source|space level`, as set, paint and fill take.
+fn cell_and_state<'a, I: Iterator<Item = &'a str>>(words: &mut I) -> Result<((usize, usize), Light), String>
+{
+    let i = number(words.next(), "column")?;
+    let j = number(words.next(), "row")?;
+    let kind = words.next().ok_or("missing source or space")?;
+    let level = number(words.next(), "level")?;
+    let state = match kind
+    {
+        "source" => Light::Source(level),
+        "space" => Light::Space(level),
+        other => return Err(format!("expected source or space, not '{}'", other))
+    };
+    Ok(((i, j), state))
+}
+
+fn inside(session: &Session, coord: (usize, usize)) -> Result<(), String>
+{
+    check_coord(session.core.automata.current().dims, coord).map_err(|error| error.to_string())
+}
+
+// Shows a change to the named sources right away rather than after the
+// next step.
+fn source_changed(session: &mut Session, name: &str, found: bool) -> Result<String, String>
+{
+    if !found
+    {
+        return Err(format!("no source named '{}'", name));
+    }
+    session.sources.borrow_mut().apply(&mut session.core.automata);
+    Ok(String::new())
+}
+
+impl Command
+{
+    // None for a blank line.
+    pub fn parse(line: &str) -> Result<Option<Command>, String>
+    {
+        let mut words = line.split_whitespace();
+        let name = match words.next()
+        {
+            Some(name) => name,
+            None => return Ok(None)
+        };
+        let command = match name
+        {
+            "step" => Command::Step(match words.next()
+            {
+                Some(count) => number(Some(count), "step count")?,
+                None => 1
+            }),
+            "set" =>
+            {
+                let (coord, state) = cell_and_state(&mut words)?;
+                Command::Set(coord, state)
+            },
+            "brush" => Command::Brush(words.next().ok_or("missing brush")?.parse()?),
+            "paint" =>
+            {
+                let (coord, state) = cell_and_state(&mut words)?;
+                Command::Paint(coord, state)
+            },
+            "fill" =>
+            {
+                let (coord, state) = cell_and_state(&mut words)?;
+                Command::Fill(coord, state)
+            },
+            "undo" | "u" => Command::Undo,
+            "print" => Command::Print,
+            "stats" => Command::Stats,
+            "profile" =>
+            {
+                let column = match words.next().ok_or("missing row or column")?
+                {
+                    "row" => false,
+                    "column" => true,
+                    other => return Err(format!("expected row or column, not '{}'", other))
+                };
+                Command::Profile(column, number(words.next(), "index")?)
+            },
+            "save" => Command::Save(words.next().ok_or("missing path")?.to_string()),
+            "load" => Command::Load(words.next().ok_or("missing path")?.to_string()),
+            "rule" =>
+            {
+                let name = words.next().ok_or("missing rule name")?.to_string();
+                Command::Rule(name, registry::parse_params(words.by_ref())?)
+            },
+            "rules" => Command::Rules,
+            "neighborhood" => Command::Neighborhood(words.next().ok_or("missing neighborhood")?.parse()?),
+            "source" =>
+            {
+                let action = words.next().ok_or("missing add, level, move or remove")?;
+                if action == "add"
+                {
+                    Command::SourceAdd(words.next().ok_or("missing source")?.parse()?)
+                }
+                else
+                {
+                    let name = words.next().ok_or("missing source name")?.to_string();
+                    match action
+                    {
+                        "level" => Command::SourceLevel(name, number(words.next(), "level")?),
+                        "move" => Command::SourceMove(name, (number(words.next(), "column")?, number(words.next(), "row")?)),
+                        "remove" => Command::SourceRemove(name),
+                        other => return Err(format!("expected add, level, move or remove, not '{}'", other))
+                    }
+                }
+            },
+            "sources" => Command::Sources,
+            "help" => Command::Help,
+            "quit" | "exit" => Command::Quit,
+            other => return Err(format!("unknown command '{}' (try help)", other))
+        };
+        match words.next()
+        {
+            Some(extra) => Err(format!("unexpected '{}'", extra)),
+            None => Ok(Some(command))
+        }
+    }
+
+    pub fn execute(&self, session: &mut Session) -> Result<Outcome, String>
+    {
+        let text = match self
+        {
+            Command::Step(count) =>
+            {
+                session.core.run(*count);
+                format!("step {}", session.core.automata.step())
+            },
+            Command::Set(coord, state) =>
+            {
+                inside(session, *coord)?;
+                session.edits.paint(&mut session.core.automata, &[*coord], *state);
+                String::new()
+            },
+            Command::Brush(brush) =>
+            {
+                session.brush = *brush;
+                format!("brush {:?}", brush)
+            },
+            Command::Paint(coord, state) =>
+            {
+                inside(session, *coord)?;
+                let changed = session.edits.brush(&mut session.core.automata, session.brush, *coord, session.last_click, *state);
+                session.last_click = Some(*coord);
+                format!("{} cells painted", changed)
+            },
+            Command::Fill(coord, state) =>
+            {
+                inside(session, *coord)?;
+                format!("{} cells filled", session.edits.fill(&mut session.core.automata, *coord, *state))
+            },
+            Command::Undo =>
+            {
+                if !session.edits.undo(&mut session.core.automata)
+                {
+                    return Err("nothing to undo since the last step".to_string());
+                }
+                String::new()
+            },
+            Command::Print =>
+            {
+                let grid = session.core.automata.current();
+                format!("{}\n{}", render::describe(grid.dims, session.mode), render::render_mode(grid, session.mode).trim_end_matches('\n'))
+            },
+            Command::Stats =>
+            {
+                let cells = &session.core.automata.current().data;
+                let lit = cells.iter().filter(|cell| cell.level() > 0).count();
+                let sources = cells.iter().filter(|cell| cell.is_pinned()).count();
+                let total: u64 = cells.iter().map(|cell| u64::from(cell.level())).sum();
+                let max = cells.iter().map(CellState::level).max().unwrap_or(0);
+                format!("step {}: {} of {} cells lit, {} sources, total intensity {}, max {}",
+                        session.core.automata.step(), lit, cells.len(), sources, total, max)
+            },
+            Command::Profile(column, k) =>
+            {
+                let grid = session.core.automata.current();
+                let level = |cell: &Light| f64::from(cell.level());
+                let (what, size, values) = if *column
+                {
+                    ("column", grid.dims.0, analysis::column_profile(grid, *k, level))
+                }
+                else
+                {
+                    ("row", grid.dims.1, analysis::profile(grid, *k, level))
+                };
+                if *k >= size
+                {
+                    return Err(format!("no {} {} in the {}x{} grid", what, k, grid.dims.0, grid.dims.1));
+                }
+                values.iter().map(f64::to_string).collect::<Vec<_>>().join(" ")
+            },
+            Command::Save(path) =>
+            {
+                session.core.automata.save_checkpoint(path).map_err(|error| format!("{}: {}", path, error))?;
+                format!("saved step {} to {}", session.core.automata.step(), path)
+            },
+            Command::Load(path) =>
+            {
+                let mut automata = Automata::load_checkpoint(path).map_err(|error| format!("{}: {}", path, error))?;
+                automata.restore_cell_rules(path, &RuleRegistry::<Light>::global()).map_err(|error| format!("{}: {}", path, error))?;
+                session.core.automata = automata;
+                session.edits = EditStack::new(UNDO_DEPTH);
+                session.last_click = None;
+                sources::attach(&session.sources, &mut session.core.automata);
+                format!("loaded step {} from {}", session.core.automata.step(), path)
+            },
+            Command::Rule(name, config) =>
+            {
+                session.core.set_rule(RuleRegistry::<Light>::global().instantiate(name, config).map_err(|error| error.to_string())?);
+                format!("rule {}", name)
+            },
+            Command::Rules => registry::describe(&RuleRegistry::<Light>::global()),
+            Command::Neighborhood(kind) =>
+            {
+                session.core.neighborhood = *kind;
+                format!("neighborhood {:?}", kind)
+            },
+            Command::SourceAdd(source) =>
+            {
+                inside(session, source.position)?;
+                session.sources.borrow_mut().add(source.clone());
+                session.sources.borrow_mut().apply(&mut session.core.automata);
+                String::new()
+            },
+            Command::SourceLevel(name, level) =>
+            {
+                let found = session.sources.borrow_mut().set_level(name, *level);
+                source_changed(session, name, found)?
+            },
+            Command::SourceMove(name, (i, j)) =>
+            {
+                inside(session, (*i, *j))?;
+                let found = session.sources.borrow_mut().move_to(name, (*i, *j));
+                source_changed(session, name, found)?
+            },
+            Command::SourceRemove(name) =>
+            {
+                let found = session.sources.borrow_mut().remove(name);
+                source_changed(session, name, found)?
+            },
+            Command::Sources => session.sources.borrow().to_text().trim_end().to_string(),
+            Command::Help => HELP.to_string(),
+            Command::Quit => return Ok(Outcome::Quit)
+        };
+        Ok(Outcome::Continue(text))
+    }
+}
+
+// Reads commands from stdin until `quit` or the end of the input. Errors
+// are printed and the session continues.
+pub fn run(session: &mut Session) -> io::Result<()>
+{
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut lines = stdin.lock().lines();
+    loop
+    {
+        write!(stdout, "> ")?;
+        stdout.flush()?;
+        let line = match lines.next()
+        {
+            Some(line) => line?,
+            None => return Ok(())
+        };
+        let outcome = Command::parse(&line)
+            .and_then(|command| match command
+            {
+                Some(command) => command.execute(session),
+                None => Ok(Outcome::Continue(String::new()))
+            });
+        match outcome
+        {
+            Ok(Outcome::Continue(text)) if text.is_empty() => (),
+            Ok(Outcome::Continue(text)) => writeln!(stdout, "{}", text)?,
+            Ok(Outcome::Quit) => return Ok(()),
+            Err(message) => writeln!(stdout, "error: {}", message)?
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn session() -> Session
+    {
+        Session::new((9, 5), RenderMode::Compact)
+    }
+
+    // The text printed for the line, "quit" for Quit.
+    fn run(session: &mut Session, line: &str) -> Result<String, String>
+    {
+        match Command::parse(line)?.expect("a command").execute(session)?
+        {
+            Outcome::Continue(text) => Ok(text),
+            Outcome::Quit => Ok("quit".to_string())
+        }
+    }
+
+    fn cell(session: &Session, coord: (usize, usize)) -> Light
+    {
+        *session.core.automata.get(coord).unwrap()
+    }
+
+    #[test]
+    fn steps_edits_and_undo()
+    {
+        let mut session = session();
+        assert_eq!(run(&mut session, "step"), Ok("step 1".to_string()));
+        assert_eq!(run(&mut session, "step 3"), Ok("step 4".to_string()));
+        assert_eq!(run(&mut session, "set 2 1 source 9"), Ok(String::new()));
+        assert_eq!(cell(&session, (2, 1)), Light::Source(9));
+        assert!(run(&mut session, "set 9 0 source 1").unwrap_err().contains("(9, 0)"));
+        assert_eq!(run(&mut session, "undo"), Ok(String::new()));
+        assert_eq!(cell(&session, (2, 1)), Light::Space(0));
+        assert_eq!(run(&mut session, "u"), Err("nothing to undo since the last step".to_string()));
+
+        assert_eq!(run(&mut session, "brush ball=1"), Ok("brush Ball(1)".to_string()));
+        assert_eq!(run(&mut session, "paint 4 2 space 5"), Ok("4 cells painted".to_string()));
+        assert_eq!(run(&mut session, "brush rect"), Ok("brush Rect".to_string()));
+        assert_eq!(run(&mut session, "paint 1 0 space 6"), Ok("12 cells painted".to_string()));
+        assert_eq!(cell(&session, (3, 1)), Light::Space(6));
+        // The dark cells but for (0, 0) and (0, 1), which the rect cut off.
+        assert_eq!(run(&mut session, "fill 8 4 space 2"), Ok("29 cells filled".to_string()));
+        assert!(run(&mut session, "fill 8 5 space 2").is_err());
+    }
+
+    #[test]
+    fn views()
+    {
+        let mut session = session();
+        assert_eq!(run(&mut session, "stats").unwrap(), "step 0: 0 of 45 cells lit, 0 sources, total intensity 0, max 0");
+        run(&mut session, "set 4 2 source 3").unwrap();
+        run(&mut session, "step").unwrap();
+        assert_eq!(run(&mut session, "stats").unwrap(), "step 1: 4 of 45 cells lit, 1 sources, total intensity 9, max 3");
+        let print = run(&mut session, "print").unwrap();
+        assert!(print.starts_with(&render::describe((9, 5), RenderMode::Compact)));
+        // Dark rows at the bottom are kept.
+        assert_eq!(print.lines().count(), 6);
+        assert_eq!(run(&mut session, "profile row 2").unwrap(), "0 0 0 2 3 2 0 0 0");
+        assert_eq!(run(&mut session, "profile column 4").unwrap().split(' ').count(), 5);
+        assert_eq!(run(&mut session, "profile column 9"), Err("no column 9 in the 9x5 grid".to_string()));
+        assert_eq!(run(&mut session, "help").unwrap(), HELP);
+        assert_eq!(run(&mut session, "quit").unwrap(), "quit");
+        assert_eq!(run(&mut session, "exit").unwrap(), "quit");
+    }
+
+    #[test]
+    fn rules_and_neighborhoods()
+    {
+        let mut session = session();
+        assert!(run(&mut session, "rules").unwrap().contains("falloff: "));
+        assert_eq!(run(&mut session, "rule decay amount=3"), Ok("rule decay".to_string()));
+        run(&mut session, "set 4 2 space 7").unwrap();
+        run(&mut session, "step").unwrap();
+        assert_eq!(cell(&session, (4, 2)), Light::Space(4));
+        assert!(run(&mut session, "rule nothing").is_err());
+        assert!(run(&mut session, "rule decay amount=x").is_err());
+        assert_eq!(run(&mut session, "neighborhood radius=2"), Ok("neighborhood Radius(2)".to_string()));
+        assert_eq!(session.core.neighborhood, NeighborhoodKind::Radius(2));
+        assert!(run(&mut session, "neighborhood far").is_err());
+    }
+
+    #[test]
+    fn named_sources()
+    {
+        let mut session = session();
+        assert_eq!(run(&mut session, "source add a=1,1,8"), Ok(String::new()));
+        assert_eq!(cell(&session, (1, 1)), Light::Source(8));
+        assert_eq!(run(&mut session, "sources").unwrap(), "a=1,1,8");
+        run(&mut session, "source level a 3").unwrap();
+        assert_eq!(cell(&session, (1, 1)), Light::Source(3));
+        run(&mut session, "source move a 2 2").unwrap();
+        assert_eq!((cell(&session, (1, 1)), cell(&session, (2, 2))), (Light::Space(3), Light::Source(3)));
+        run(&mut session, "source remove a").unwrap();
+        assert_eq!(cell(&session, (2, 2)), Light::Space(3));
+        assert_eq!(run(&mut session, "source level a 1"), Err("no source named 'a'".to_string()));
+        assert!(run(&mut session, "source add b=20,0,1").is_err());
+        assert_eq!(run(&mut session, "sources").unwrap(), "");
+    }
+
+    #[test]
+    fn save_and_load()
+    {
+        let path = std::env::temp_dir().join(format!("triangle-automata-{}-repl.tria", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut session = session();
+        run(&mut session, "set 3 3 source 4").unwrap();
+        run(&mut session, "step 2").unwrap();
+        assert_eq!(run(&mut session, &format!("save {}", path)), Ok(format!("saved step 2 to {}", path)));
+        let saved = session.core.automata.current().clone();
+        run(&mut session, "step 5").unwrap();
+        let loaded = run(&mut session, &format!("load {}", path));
+        let _ = std::fs::remove_file(path);
+        assert_eq!(loaded, Ok(format!("loaded step 2 from {}", path)));
+        assert_eq!(session.core.automata.current(), &saved);
+        assert!(run(&mut session, "undo").is_err());
+        assert!(run(&mut session, &format!("load {}", path)).unwrap_err().starts_with(path));
+    }
+
+    #[test]
+    fn malformed_input()
+    {
+        assert_eq!(Command::parse("   "), Ok(None));
+        let cases = [
+            ("bogus", "unknown command 'bogus' (try help)"),
+            ("step x", "invalid step count 'x'"),
+            ("step 1 2", "unexpected '2'"),
+            ("set 1", "missing row"),
+            ("set 1 1 lamp 3", "expected source or space, not 'lamp'"),
+            ("set 1 1 source 256", "invalid level '256'"),
+            ("brush blob", "unknown brush 'blob' (cell, ball=R, rect or line)"),
+            ("profile diagonal 1", "expected row or column, not 'diagonal'"),
+            ("save", "missing path"),
+            ("source eat a", "expected add, level, move or remove, not 'eat'"),
+            ("source add", "missing source"),
+            ("print now", "unexpected 'now'")
+        ];
+        for (line, message) in &cases
+        {
+            assert_eq!(Command::parse(line), Err(message.to_string()), "for {:?}", line);
+        }
+        assert_eq!(Command::parse("u"), Ok(Some(Command::Undo)));
+        assert_eq!(Command::parse("source move a 1 2"), Ok(Some(Command::SourceMove("a".to_string(), (1, 2)))));
+    }
+}
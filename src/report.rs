@@ -0,0 +1,474 @@
+// The results of a run gathered in a directory to archive, with an index,
+// results.json, of the run and of its files:
+//
+//     {
+//       "rule": "falloff",
+//       "seed": 1,
+//       "params": {},
+//       "steps": 12,
+//       "dims": [16, 8],
+//       "chain_start": 0,
+//       "files": {
+//         "snapshot": "final.tria",
+//         "stats": "stats.csv",
+//         "thumbnail": "thumbnail.png",
+//         "chain": "chain.txt"
+//       },
+//       "omitted": {}
+//     }
+//
+// The snapshot is the checkpoint of the last generation (see checkpoint.rs),
+// with its ages and cell rules next to it when the automaton has them. The
+// stats are the CSV of a StatsLogger, the chain the fingerprints of a
+// FingerprintChain as in the fixtures of regression/, and the thumbnail a
+// PNG of the last generation. Those need attachments or colors that the
+// run might not have: without them they are left out, and the index says
+// why under "omitted".
+//
+// load restores the automaton from the snapshot and checks the chain
+// against it: it must end with the snapshot's fingerprint, at its step.
+// The run itself is not replayed, the index has what is needed to.
+
+use crate::Automata;
+use crate::checkpoint::SnapshotError;
+use crate::codec::CellCodec;
+use crate::image::{self, RenderOptions};
+use crate::regression;
+use crate::render::json_string;
+use crate::session::{Phase, Session, StepHook};
+use crate::stats::StatsLogger;
+use crate::sweep::RuleConfig;
+
+use std::cell::RefCell;
+use std::fmt::{self, Debug, Display};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+pub const INDEX: &str = "results.json";
+const SNAPSHOT: &str = "final.tria";
+const STATS: &str = "stats.csv";
+const THUMBNAIL: &str = "thumbnail.png";
+const CHAIN: &str = "chain.txt";
+
+#[derive(Debug)]
+pub enum ReportError
+{
+    Io(io::Error),
+    Snapshot(SnapshotError),
+    // An index without the entry, or with one that does not read.
+    Index(String),
+    // A chain that does not lead to the snapshot.
+    Chain(String)
+}
+
+impl fmt::Display for ReportError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            ReportError::Io(error) => write!(f, "{}", error),
+            ReportError::Snapshot(error) => write!(f, "{}", error),
+            ReportError::Index(message) => write!(f, "{}: {}", INDEX, message),
+            ReportError::Chain(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl From<io::Error> for ReportError
+{
+    fn from(error: io::Error) -> Self
+    {
+        ReportError::Io(error)
+    }
+}
+
+impl From<SnapshotError> for ReportError
+{
+    fn from(error: SnapshotError) -> Self
+    {
+        ReportError::Snapshot(error)
+    }
+}
+
+// The fingerprints (see gallery.rs) of the generation it was made on and
+// of every step after it, as an attachment of a session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FingerprintChain
+{
+    start: u64,
+    fingerprints: Vec<u64>
+}
+
+impl FingerprintChain
+{
+    pub fn new<T: Clone + Display + Copy + Debug>(automata: &Automata<T>) -> Self
+    {
+        Self{start: automata.step(), fingerprints: vec![automata.current().fingerprint()]}
+    }
+
+    // The step of the first fingerprint.
+    pub fn start(&self) -> u64
+    {
+        self.start
+    }
+
+    pub fn fingerprints(&self) -> &[u64]
+    {
+        &self.fingerprints
+    }
+}
+
+impl<T: Clone + Display + Copy + Debug> StepHook<T> for FingerprintChain
+{
+    fn phase(&self) -> Phase
+    {
+        Phase::Log
+    }
+
+    fn after_step(&mut self, automata: &Automata<T>)
+    {
+        self.fingerprints.push(automata.current().fingerprint());
+    }
+}
+
+type Colors<T> = Box<dyn Fn(&T) -> (u8, u8, u8)>;
+
+// What the session alone does not have: the rule's seed and parameters,
+// shared attachments and the colors of the thumbnail.
+pub struct ExportOptions<T>
+{
+    config: Option<RuleConfig>,
+    stats: Option<Rc<RefCell<StatsLogger<T>>>>,
+    chain: Option<Rc<RefCell<FingerprintChain>>>,
+    thumbnail: Option<(Colors<T>, RenderOptions)>
+}
+
+impl<T> Default for ExportOptions<T>
+{
+    fn default() -> Self
+    {
+        Self{config: None, stats: None, chain: None, thumbnail: None}
+    }
+}
+
+impl<T> ExportOptions<T>
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    pub fn with_config(mut self, config: &RuleConfig) -> Self
+    {
+        self.config = Some(config.clone());
+        self
+    }
+
+    pub fn with_stats(mut self, stats: &Rc<RefCell<StatsLogger<T>>>) -> Self
+    {
+        self.stats = Some(Rc::clone(stats));
+        self
+    }
+
+    pub fn with_chain(mut self, chain: &Rc<RefCell<FingerprintChain>>) -> Self
+    {
+        self.chain = Some(Rc::clone(chain));
+        self
+    }
+
+    pub fn with_thumbnail<F: Fn(&T) -> (u8, u8, u8) + 'static>(mut self, color: F, options: RenderOptions) -> Self
+    {
+        self.thumbnail = Some((Box::new(color), options));
+        self
+    }
+}
+
+// The index and the files of an export, as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exported
+{
+    // What each file is, and its name in the directory.
+    pub files: Vec<(String, String)>,
+    // What was left out, and why.
+    pub omitted: Vec<(String, String)>
+}
+
+// Writes the results of the session into `path`, which is created if
+// needed. Files of parts left out that an earlier export left there are
+// removed, so that the directory has exactly the files of the index.
+pub fn export<T, P>(session: &Session<T>, path: P, options: &ExportOptions<T>) -> Result<Exported, ReportError>
+where
+    T: Clone + Display + Copy + Debug + PartialEq + CellCodec,
+    P: AsRef<Path>
+{
+    let dir = path.as_ref();
+    fs::create_dir_all(dir)?;
+    let mut exported = Exported{files: vec![], omitted: vec![]};
+    let part = |exported: &mut Exported, what: &str, file: Option<&str>, why: &str| match file
+    {
+        Some(file) => exported.files.push((what.to_string(), file.to_string())),
+        None => exported.omitted.push((what.to_string(), why.to_string()))
+    };
+
+    session.automata.save_checkpoint(dir.join(SNAPSHOT))?;
+    part(&mut exported, "snapshot", Some(SNAPSHOT), "");
+    for (what, suffix) in &[("ages", ".ages"), ("cell rules", ".cells")]
+    {
+        let file = format!("{}{}", SNAPSHOT, suffix);
+        if dir.join(&file).exists()
+        {
+            part(&mut exported, what, Some(&file), "");
+        }
+    }
+
+    let stats = match &options.stats
+    {
+        Some(stats) => Some(stats.borrow().save_csv(dir.join(STATS)).map(|_| STATS)?),
+        None => None
+    };
+    part(&mut exported, "stats", stats, "no stats logger attached");
+
+    let thumbnail = match &options.thumbnail
+    {
+        Some((color, render)) => Some(image::png(session.current(), color, render, dir.join(THUMBNAIL)).map(|_| THUMBNAIL)?),
+        None => None
+    };
+    part(&mut exported, "thumbnail", thumbnail, "no colors given");
+
+    let chain = match &options.chain
+    {
+        Some(chain) =>
+        {
+            let chain = chain.borrow();
+            let header = format!("{}: steps {} to {}", session.rule.name().unwrap_or("rule"), chain.start(), session.automata.step());
+            fs::write(dir.join(CHAIN), regression::chain_text(&header, chain.fingerprints()))?;
+            Some(CHAIN)
+        },
+        None => None
+    };
+    part(&mut exported, "chain", chain, "no fingerprint chain attached");
+
+    for (file, what) in &[(STATS, "stats"), (THUMBNAIL, "thumbnail"), (CHAIN, "chain")]
+    {
+        if exported.omitted.iter().any(|(omitted, _)| omitted == what)
+        {
+            match fs::remove_file(dir.join(file))
+            {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+                _ => ()
+            }
+        }
+    }
+
+    let start = options.chain.as_ref().map_or(session.automata.step(), |chain| chain.borrow().start());
+    fs::write(dir.join(INDEX), index(session, options.config.as_ref(), start, &exported))?;
+    Ok(exported)
+}
+
+fn index<T>(session: &Session<T>, config: Option<&RuleConfig>, chain_start: u64, exported: &Exported) -> String
+where
+    T: Clone + Display + Copy + Debug
+{
+    let entries = |pairs: &[(String, String)]| -> String {
+        if pairs.is_empty()
+        {
+            return "{}".to_string();
+        }
+        let lines: Vec<String> = pairs.iter().map(|(key, value)| format!("    {}: {}", json_string(key), json_string(value))).collect();
+        format!("{{\n{}\n  }}", lines.join(",\n"))
+    };
+    let params = config.map_or("null".to_string(), |config| {
+        let params: Vec<String> = config.params.iter().map(|(name, value)| format!("{}: {}", json_string(name), value)).collect();
+        format!("{{{}}}", params.join(", "))
+    });
+    let current = session.current();
+    format!("{{\n  \"rule\": {},\n  \"seed\": {},\n  \"params\": {},\n  \"steps\": {},\n  \"dims\": [{}, {}],\n  \"chain_start\": {},\n  \"files\": {},\n  \"omitted\": {}\n}}\n",
+            session.rule.name().map_or("null".to_string(), json_string),
+            config.map_or("null".to_string(), |config| config.seed.to_string()),
+            params, session.automata.step(), current.dims.0, current.dims.1, chain_start,
+            entries(&exported.files), entries(&exported.omitted))
+}
+
+// The `"key": "value"` lines of the block `key` of the index.
+fn block(text: &str, key: &str) -> Vec<(String, String)>
+{
+    let unquote = |s: &str| s.trim().trim_end_matches(',').trim().trim_matches('"').to_string();
+    text.lines()
+        .skip_while(|line| line.trim() != format!("{}: {{", json_string(key)))
+        .skip(1)
+        .take_while(|line| !line.trim().starts_with('}'))
+        .filter_map(|line| line.split_once(':').map(|(key, value)| (unquote(key), unquote(value))))
+        .collect()
+}
+
+fn number(text: &str, key: &str) -> Option<u64>
+{
+    let prefix = format!("{}: ", json_string(key));
+    text.lines().find_map(|line| line.trim().strip_prefix(&prefix)).and_then(|value| value.trim_end_matches(',').parse().ok())
+}
+
+pub struct Report<T>
+{
+    pub automata: Automata<T>,
+    // The fingerprint chain, when it was exported.
+    pub chain: Option<Vec<u64>>,
+    pub files: Vec<(String, String)>,
+    pub omitted: Vec<(String, String)>
+}
+
+// Restores the results exported in `path`, checking the chain against the
+// snapshot.
+pub fn load<T, P>(path: P) -> Result<Report<T>, ReportError>
+where
+    T: Clone + Display + Copy + Debug + PartialEq + CellCodec,
+    P: AsRef<Path>
+{
+    let dir = path.as_ref();
+    let text = fs::read_to_string(dir.join(INDEX))?;
+    let files = block(&text, "files");
+    let file = |what: &str| files.iter().find(|(key, _)| key == what).map(|(_, file)| dir.join(file));
+    let snapshot = file("snapshot").ok_or_else(|| ReportError::Index("no snapshot".to_string()))?;
+    let automata: Automata<T> = Automata::load_checkpoint(snapshot)?;
+    let chain = match file("chain")
+    {
+        Some(chain) =>
+        {
+            let chain = regression::parse_chain(&fs::read_to_string(chain)?).map_err(|error| ReportError::Chain(error.to_string()))?;
+            let start = number(&text, "chain_start").ok_or_else(|| ReportError::Index("no chain_start".to_string()))?;
+            if start + chain.len() as u64 != automata.step() + 1
+            {
+                return Err(ReportError::Chain(format!("a chain of {} fingerprints from step {} does not end at step {}", chain.len(), start, automata.step())));
+            }
+            let found = automata.current().fingerprint();
+            if chain.last() != Some(&found)
+            {
+                return Err(ReportError::Chain(format!("the chain ends with {:016x}, the snapshot is {:016x}", chain.last().unwrap_or(&0), found)));
+            }
+            Some(chain)
+        },
+        None => None
+    };
+    Ok(Report{automata, chain, files, omitted: block(&text, "omitted")})
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{CellState, Grid, Light};
+    use crate::color::ColorMap;
+    use crate::registry::RuleRegistry;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf
+    {
+        std::env::temp_dir().join(format!("triangle-automata-{}-{}.report", std::process::id(), name))
+    }
+
+    type Stats = Rc<RefCell<StatsLogger<Light>>>;
+
+    // A small light run of 12 steps with stats and a chain attached.
+    fn run() -> (Session<Light>, Stats, Rc<RefCell<FingerprintChain>>)
+    {
+        let rule = RuleRegistry::<Light>::with_builtins().instantiate("falloff", &RuleConfig::new(1)).unwrap();
+        let grid = Grid::from_fn((16, 8), |coord| if coord == (8, 4) { Light::Source(9) } else { Light::Space(0) });
+        let mut session = Session::new(Automata::new(grid), rule);
+        let stats = Rc::new(RefCell::new(StatsLogger::new().metric("lit", |grid: &Grid<Light>| grid.data.iter().filter(|cell| cell.level() > 0).count() as f64)));
+        let chain = Rc::new(RefCell::new(FingerprintChain::new(&session.automata)));
+        session.attach(Rc::clone(&stats));
+        session.attach(Rc::clone(&chain));
+        session.run(12);
+        (session, stats, chain)
+    }
+
+    // The files of the directory, sorted.
+    fn listing(dir: &Path) -> Vec<String>
+    {
+        let mut found: Vec<String> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        found.sort();
+        found
+    }
+
+    fn indexed(exported: &Exported) -> Vec<String>
+    {
+        let mut expected: Vec<String> = exported.files.iter().map(|(_, file)| file.clone()).chain(Some(INDEX.to_string())).collect();
+        expected.sort();
+        expected
+    }
+
+    #[test]
+    fn exports_load_back()
+    {
+        let dir = temp_dir("full");
+        let (session, stats, chain) = run();
+        let full = ExportOptions::new()
+            .with_config(&RuleConfig::new(1))
+            .with_stats(&stats)
+            .with_chain(&chain)
+            .with_thumbnail(|cell: &Light| ColorMap::Heat.color(f64::from(cell.level()) / 9.0), RenderOptions::default());
+        let exported = export(&session, &dir, &full).unwrap();
+        let found = listing(&dir);
+        let report = load::<Light, _>(&dir);
+        let index = fs::read_to_string(dir.join(INDEX));
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(found, indexed(&exported));
+        assert_eq!(found, [CHAIN, SNAPSHOT, INDEX, STATS, THUMBNAIL]);
+        let report = report.unwrap();
+        assert_eq!(report.automata.current(), session.current());
+        assert_eq!(report.automata.step(), 12);
+        assert_eq!(report.files, exported.files);
+        assert!(report.omitted.is_empty());
+        assert_eq!(report.chain.as_deref(), Some(chain.borrow().fingerprints()));
+        assert_eq!(chain.borrow().fingerprints().len(), 13);
+        let index = index.unwrap();
+        assert!(index.contains("\"rule\": \"falloff\"") && index.contains("\"steps\": 12"), "{}", index);
+    }
+
+    // Without stats or colors, they are left out and the index says so;
+    // the directory holds what it lists and nothing of an earlier export.
+    #[test]
+    fn missing_parts_are_omitted()
+    {
+        let dir = temp_dir("bare");
+        let (session, stats, chain) = run();
+        let full = ExportOptions::new().with_stats(&stats).with_chain(&chain);
+        export(&session, &dir, &full).unwrap();
+        let exported = export(&session, &dir, &ExportOptions::new().with_chain(&chain)).unwrap();
+        let found = listing(&dir);
+        let report = load::<Light, _>(&dir);
+        let unchained = export(&session, &dir, &ExportOptions::new()).and_then(|_| load::<Light, _>(&dir));
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(found, indexed(&exported));
+        let report = report.unwrap();
+        assert_eq!(report.automata.current(), session.current());
+        let omitted: Vec<&str> = report.omitted.iter().map(|(what, _)| what.as_str()).collect();
+        assert_eq!(omitted, ["stats", "thumbnail"]);
+        let unchained = unchained.unwrap();
+        assert!(unchained.chain.is_none());
+        assert_eq!(unchained.automata.step(), 12);
+    }
+
+    #[test]
+    fn edited_chains_do_not_load()
+    {
+        let dir = temp_dir("edited");
+        let (session, _, chain) = run();
+        export(&session, &dir, &ExportOptions::new().with_chain(&chain)).unwrap();
+        let text = fs::read_to_string(dir.join(CHAIN)).unwrap();
+        let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+        let last = lines.len() - 1;
+        lines[last] = format!("{:016x}", !u64::from_str_radix(&lines[last], 16).unwrap());
+        fs::write(dir.join(CHAIN), lines.join("\n")).unwrap();
+        let edited = load::<Light, _>(&dir);
+        fs::remove_file(dir.join(INDEX)).unwrap();
+        let unindexed = load::<Light, _>(&dir);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(matches!(edited, Err(ReportError::Chain(_))), "{:?}", edited.err());
+        assert!(matches!(unindexed, Err(ReportError::Io(_))), "{:?}", unindexed.err());
+    }
+}
@@ -0,0 +1,183 @@
+use crate::Grid;
+
+use std::fmt::Debug;
+
+// Groups the triangles (2k, j) and (2k+1, j) of every row into one rhombic
+// cell (k, j). Whatever the row parity, such a pair always shares an edge:
+//
+//     ·-----·      ·-----·
+//    / \ 1 /        \ 0 / \
+//   / 0 \ /          \ / 1 \
+//  ·-----·            ·-----·
+//
+// Only grids with an even width can be split this way.
+
+pub struct RhombusView<'a, T>
+{
+    grid: &'a Grid<T>
+}
+
+impl<'a, T: Copy + Debug> RhombusView<'a, T>
+{
+    pub fn new(grid: &'a Grid<T>) -> Option<Self>
+    {
+        if grid.dims.0.is_multiple_of(2)
+        {
+            Some(Self{grid})
+        }
+        else
+        {
+            None
+        }
+    }
+
+    pub fn dims(&self) -> (usize, usize)
+    {
+        (self.grid.dims.0 / 2, self.grid.dims.1)
+    }
+
+    pub fn get_pair(&self, (i, j): (usize, usize)) -> Option<(&'a T, &'a T)>
+    {
+        if i >= self.dims().0
+        {
+            return None;
+        }
+        Some((self.grid.get((2*i, j))?, self.grid.get((2*i+1, j))?))
+    }
+
+    pub fn map_pairs<U, F>(&self, f: F) -> Grid<U>
+    where
+        U: Copy + Debug,
+        F: Fn(&T, &T) -> U
+    {
+        Grid::from_fn(self.dims(), |co| {
+            let (left, right) = self.get_pair(co).unwrap();
+            f(left, right)
+        })
+    }
+}
+
+pub struct RhombusViewMut<'a, T>
+{
+    grid: &'a mut Grid<T>
+}
+
+impl<'a, T: Copy + Debug> RhombusViewMut<'a, T>
+{
+    pub fn new(grid: &'a mut Grid<T>) -> Option<Self>
+    {
+        if grid.dims.0.is_multiple_of(2)
+        {
+            Some(Self{grid})
+        }
+        else
+        {
+            None
+        }
+    }
+
+    pub fn dims(&self) -> (usize, usize)
+    {
+        (self.grid.dims.0 / 2, self.grid.dims.1)
+    }
+
+    pub fn get_pair(&self, (i, j): (usize, usize)) -> Option<(&T, &T)>
+    {
+        if i >= self.dims().0
+        {
+            return None;
+        }
+        Some((self.grid.get((2*i, j))?, self.grid.get((2*i+1, j))?))
+    }
+
+    // Returns false when the rhombus is out of the grid.
+    pub fn set_pair(&mut self, (i, j): (usize, usize), (left, right): (T, T)) -> bool
+    {
+        if i >= self.dims().0 || j >= self.dims().1
+        {
+            return false;
+        }
+        *self.grid.get_mut((2*i, j)).unwrap() = left;
+        *self.grid.get_mut((2*i+1, j)).unwrap() = right;
+        true
+    }
+}
+
+// Inverse of RhombusView::map_pairs: every rhombic cell is split back into
+// its two triangles, giving a grid twice as wide.
+pub fn expand_pairs<U, T, F>(rhombi: &Grid<U>, f: F) -> Grid<T>
+where
+    U: Copy + Debug,
+    T: Copy + Debug,
+    F: Fn(&U) -> (T, T)
+{
+    Grid::from_fn((rhombi.dims.0*2, rhombi.dims.1), |(i, j)| {
+        let (left, right) = f(rhombi.get((i/2, j)).unwrap());
+        if i.is_multiple_of(2) {left} else {right}
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{Automata, CellState, Light};
+    use crate::rules;
+
+    #[test]
+    fn odd_widths_are_refused()
+    {
+        assert!(RhombusView::new(&Grid::new((5, 2), 0u8)).is_none());
+        assert!(RhombusViewMut::new(&mut Grid::new((5, 2), 0u8)).is_none());
+    }
+
+    #[test]
+    fn reads_borrow_the_grid()
+    {
+        let grid = Grid::from_fn((6, 3), |(i, j)| (i, j));
+        let view = RhombusView::new(&grid).unwrap();
+        assert_eq!(view.dims(), (3, 3));
+        let (left, right) = view.get_pair((2, 1)).unwrap();
+        assert!(std::ptr::eq(left, grid.get((4, 1)).unwrap()));
+        assert!(std::ptr::eq(right, grid.get((5, 1)).unwrap()));
+        assert_eq!(view.get_pair((3, 0)), None);
+    }
+
+    #[test]
+    fn set_pair_writes_both_triangles()
+    {
+        let mut grid = Grid::new((4, 2), 0u8);
+        let mut view = RhombusViewMut::new(&mut grid).unwrap();
+        assert!(view.set_pair((1, 1), (7, 8)));
+        assert!(!view.set_pair((2, 0), (1, 1)));
+        assert_eq!(view.get_pair((1, 1)), Some((&7, &8)));
+        assert_eq!(grid.row(1), [0, 0, 7, 8]);
+    }
+
+    // The light demo collapsed by the brightest triangle of every rhombus:
+    // expanded back, both triangles of a pair hold that maximum.
+    #[test]
+    fn light_demo_coarse_field()
+    {
+        let mut automata = Automata::new(Grid::new((30, 20), Light::Space(0)));
+        *automata.get_mut((10, 10)).unwrap() = Light::Source(10);
+        for _ in 0..6
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        let fine = automata.current();
+        let coarse = RhombusView::new(fine).unwrap().map_pairs(|left, right| left.level().max(right.level()));
+        assert_eq!(coarse.dims, (15, 20));
+        assert_eq!(*coarse.get((5, 10)).unwrap(), 10);
+        let expanded = expand_pairs(&coarse, |&level| (level, level));
+        assert_eq!(expanded.dims, fine.dims);
+        for j in 0..20
+        {
+            for i in 0..30
+            {
+                let pair = (fine.get((i - i % 2, j)).unwrap().level(), fine.get((i - i % 2 + 1, j)).unwrap().level());
+                assert_eq!(*expanded.get((i, j)).unwrap(), pair.0.max(pair.1), "at {:?}", (i, j));
+            }
+        }
+    }
+}
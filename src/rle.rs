@@ -0,0 +1,428 @@
+// Run-length encoded patterns, after Life's .rle files, for large patterns
+// that are mostly background:
+//
+//   #L . Space(0)
+//   #L A Source(10)
+//   x = 40, y = 20, first = up
+//   12.A27.$3$
+//   5.2A!
+//
+// `#L` lines give the symbol of every state, written as the Debug form of
+// the cell; the first one is the background. Other `#` lines are comments.
+// The header gives the dims and the orientation of the first cell. Then
+// come runs, a count (1 if left out) and a symbol, rows ending with `$`
+// (`n$` ends n rows) and the pattern with `!`. Cells left out at the end of
+// a row, and rows left out at the end, are background. Whitespace between
+// runs is ignored.
+
+use crate::Grid;
+use crate::error::check_dims;
+use crate::pattern::{Pattern, PatternParseError};
+
+use std::fmt::{self, Debug};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RleErrorKind
+{
+    MissingHeader,
+    BadHeader(String),
+    BadLegend(String),
+    // Symbols can be anything but digits, whitespace, $, ! and #.
+    BadSymbol(char),
+    DuplicateSymbol(char),
+    // Cells left out, with no background to fill them with.
+    NoLegend,
+    UnknownSymbol(char),
+    // A run going past the end of its row.
+    RowOverflow{row: usize, width: usize},
+    TooManyRows{height: usize},
+    // A count with no symbol or $ after it, or a count of 0.
+    BadCount,
+    MissingTerminator
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RleError
+{
+    // From 1, of the start of the header, legend line or run.
+    pub line: usize,
+    pub column: usize,
+    pub kind: RleErrorKind
+}
+
+impl fmt::Display for RleError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        match &self.kind
+        {
+            RleErrorKind::MissingHeader => write!(f, "expected 'x = <width>, y = <height>, first = up|down'"),
+            RleErrorKind::BadHeader(message) | RleErrorKind::BadLegend(message) => write!(f, "{}", message),
+            RleErrorKind::BadSymbol(symbol) => write!(f, "'{}' cannot be a symbol", symbol),
+            RleErrorKind::DuplicateSymbol(symbol) => write!(f, "symbol '{}' given twice", symbol),
+            RleErrorKind::NoLegend => write!(f, "cells left out without a #L line for the background"),
+            RleErrorKind::UnknownSymbol(symbol) => write!(f, "unknown symbol '{}'", symbol),
+            RleErrorKind::RowOverflow{row, width} => write!(f, "run goes past the end of row {} ({} cells)", row, width),
+            RleErrorKind::TooManyRows{height} => write!(f, "more than {} rows", height),
+            RleErrorKind::BadCount => write!(f, "count of 0 or without anything to repeat"),
+            RleErrorKind::MissingTerminator => write!(f, "missing '!' at the end of the pattern")
+        }
+    }
+}
+
+fn is_symbol(c: char) -> bool
+{
+    !(c.is_ascii_digit() || c.is_whitespace() || c == '$' || c == '!' || c == '#')
+}
+
+// Symbols given in turn by Pattern::rle_legend.
+const SYMBOLS: &str = ".ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+// Longest line of runs written by to_rle, as in Life's files.
+const LINE_WIDTH: usize = 70;
+
+impl<T: Copy + Debug + PartialEq> Pattern<T>
+{
+    // A symbol for every state of the pattern, in the order they first
+    // appear; the first cell's state is then the background. Panics past
+    // the 53 symbols it has.
+    pub fn rle_legend(&self) -> Vec<(char, T)>
+    {
+        let mut states: Vec<T> = vec![];
+        for cell in &self.cells.data
+        {
+            if !states.contains(cell)
+            {
+                states.push(*cell);
+            }
+        }
+        assert!(states.len() <= SYMBOLS.len(), "{} states, more than the {} symbols of rle_legend", states.len(), SYMBOLS.len());
+        SYMBOLS.chars().zip(states).collect()
+    }
+
+    // The RLE text of the pattern, or the first cell, in storage order,
+    // whose state has no symbol in the legend.
+    pub fn to_rle(&self, legend: &[(char, T)]) -> Result<String, (usize, usize)>
+    {
+        assert!(legend.iter().all(|&(symbol, _)| is_symbol(symbol)), "RLE symbols cannot be digits, whitespace, $, ! or #");
+        let (w, h) = self.cells.dims;
+        let mut out: String = legend.iter().map(|(symbol, state)| format!("#L {} {:?}\n", symbol, state)).collect();
+        out.push_str(&format!("x = {}, y = {}, first = {}\n", w, h, if self.parity == 0 { "up" } else { "down" }));
+        let background = legend.first().map(|&(_, state)| state);
+
+        let mut runs: Vec<String> = vec![];
+        let mut rows_ended = 0;
+        for j in 0..h
+        {
+            let row = self.cells.row(j);
+            let end = row.iter().rposition(|cell| Some(*cell) != background).map_or(0, |last| last + 1);
+            if end > 0 && rows_ended > 0
+            {
+                runs.push(if rows_ended > 1 { format!("{}$", rows_ended) } else { "$".to_string() });
+                rows_ended = 0;
+            }
+            let mut i = 0;
+            while i < end
+            {
+                let symbol = legend.iter()
+                    .find(|&&(_, state)| state == row[i])
+                    .map(|&(symbol, _)| symbol)
+                    .ok_or((i, j))?;
+                let count = row[i..end].iter().take_while(|&&cell| cell == row[i]).count();
+                runs.push(if count > 1 { format!("{}{}", count, symbol) } else { symbol.to_string() });
+                i += count;
+            }
+            rows_ended += 1;
+        }
+        runs.push("!".to_string());
+
+        let mut line = String::new();
+        for run in runs
+        {
+            if line.len() + run.len() > LINE_WIDTH
+            {
+                out.push_str(&line);
+                out.push('\n');
+                line.clear();
+            }
+            line.push_str(&run);
+        }
+        out.push_str(&line);
+        out.push('\n');
+        Ok(out)
+    }
+
+    // Reads the RLE text, with `state` reading the states of the legend.
+    pub fn from_rle<F>(text: &str, state: F) -> Result<Self, RleError>
+    where
+        F: Fn(&str) -> Result<T, String>
+    {
+        let error = |line: usize, column: usize, kind: RleErrorKind| RleError{line, column, kind};
+        let mut legend: Vec<(char, T)> = vec![];
+        let mut lines = text.lines().enumerate();
+        let header = loop
+        {
+            let (n, line) = lines.next().ok_or_else(|| error(text.lines().count().max(1), 1, RleErrorKind::MissingHeader))?;
+            let trimmed = line.trim();
+            if let Some(entry) = trimmed.strip_prefix("#L")
+            {
+                let entry = entry.trim();
+                let symbol = entry.chars().next()
+                    .ok_or_else(|| error(n + 1, 1, RleErrorKind::BadLegend("expected '#L <symbol> <state>'".to_string())))?;
+                if !is_symbol(symbol)
+                {
+                    return Err(error(n + 1, 1, RleErrorKind::BadSymbol(symbol)));
+                }
+                if legend.iter().any(|&(known, _)| known == symbol)
+                {
+                    return Err(error(n + 1, 1, RleErrorKind::DuplicateSymbol(symbol)));
+                }
+                let cell = state(entry[symbol.len_utf8()..].trim()).map_err(|message| error(n + 1, 1, RleErrorKind::BadLegend(message)))?;
+                legend.push((symbol, cell));
+            }
+            else if !trimmed.is_empty() && !trimmed.starts_with('#')
+            {
+                break (n, trimmed);
+            }
+        };
+        let (w, h, parity) = parse_header(header.1).map_err(|kind| error(header.0 + 1, 1, kind))?;
+        // Only needed by patterns with cells left out.
+        let background = legend.first().map(|&(_, state)| state);
+        let fill = |data: &mut Vec<T>, len: usize, at: (usize, usize)| -> Result<(), RleError> {
+            if data.len() < len
+            {
+                let background = background.ok_or_else(|| error(at.0, at.1, RleErrorKind::NoLegend))?;
+                data.resize(len, background);
+            }
+            Ok(())
+        };
+
+        let mut data = Vec::with_capacity(w*h);
+        let (mut row, mut column) = (0, 0);
+        // The pending count, and where its run starts.
+        let mut count: Option<usize> = None;
+        let mut start = (0, 0);
+        let mut last = (header.0 + 1, 1);
+        for (n, line) in lines
+        {
+            for (c, symbol) in line.chars().enumerate()
+            {
+                let here = (n + 1, c + 1);
+                last = here;
+                if symbol.is_whitespace()
+                {
+                    continue;
+                }
+                if count.is_none()
+                {
+                    start = here;
+                }
+                if let Some(digit) = symbol.to_digit(10)
+                {
+                    let so_far = count.unwrap_or(0);
+                    count = Some(so_far.checked_mul(10).and_then(|k| k.checked_add(digit as usize))
+                        .ok_or_else(|| error(start.0, start.1, RleErrorKind::BadCount))?);
+                    continue;
+                }
+                let run = count.take().unwrap_or(1);
+                if run == 0
+                {
+                    return Err(error(start.0, start.1, RleErrorKind::BadCount));
+                }
+                match symbol
+                {
+                    '!' =>
+                    {
+                        fill(&mut data, w*h, start)?;
+                        return Ok(Self{cells: Grid{data, dims: (w, h)}, parity});
+                    },
+                    '$' =>
+                    {
+                        if row + run > h
+                        {
+                            return Err(error(start.0, start.1, RleErrorKind::TooManyRows{height: h}));
+                        }
+                        fill(&mut data, (row + run)*w, start)?;
+                        row += run;
+                        column = 0;
+                    },
+                    _ =>
+                    {
+                        let cell = legend.iter()
+                            .find(|&&(known, _)| known == symbol)
+                            .map(|&(_, state)| state)
+                            .ok_or_else(|| error(here.0, here.1, RleErrorKind::UnknownSymbol(symbol)))?;
+                        if row >= h
+                        {
+                            return Err(error(start.0, start.1, RleErrorKind::TooManyRows{height: h}));
+                        }
+                        if column + run > w
+                        {
+                            return Err(error(start.0, start.1, RleErrorKind::RowOverflow{row, width: w}));
+                        }
+                        data.extend(std::iter::repeat_n(cell, run));
+                        column += run;
+                    }
+                }
+            }
+        }
+        if count.is_some()
+        {
+            return Err(error(start.0, start.1, RleErrorKind::BadCount));
+        }
+        Err(error(last.0, last.1, RleErrorKind::MissingTerminator))
+    }
+
+    // Either format: the plain text one of Pattern::to_text when the first
+    // line starts with `pattern`, RLE otherwise.
+    pub fn parse_any<F>(text: &str, state: F) -> Result<Self, String>
+    where
+        F: Fn(&str) -> Result<T, String>
+    {
+        let plain = text.lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .is_some_and(|line| line.starts_with("pattern"));
+        if plain
+        {
+            Self::from_text(text, state).map_err(|PatternParseError{line, message}| format!("line {}: {}", line, message))
+        }
+        else
+        {
+            Self::from_rle(text, state).map_err(|error| error.to_string())
+        }
+    }
+}
+
+impl<T: Copy + Debug + PartialEq> Grid<T>
+{
+    // The RLE text of the whole grid, see Pattern::to_rle.
+    pub fn to_rle(&self, legend: &[(char, T)]) -> Result<String, (usize, usize)>
+    {
+        self.extract((0, 0), self.dims).expect("the grid fits in itself").to_rle(legend)
+    }
+}
+
+fn parse_header(header: &str) -> Result<(usize, usize, usize), RleErrorKind>
+{
+    let mut dims = (None, None);
+    let mut parity = 0;
+    for field in header.split(',')
+    {
+        let (key, value) = field.split_once('=').ok_or(RleErrorKind::MissingHeader)?;
+        let value = value.trim();
+        let number = || value.parse::<usize>().map_err(|_| RleErrorKind::BadHeader(format!("invalid {} '{}'", key.trim(), value)));
+        match key.trim()
+        {
+            "x" => dims.0 = Some(number()?),
+            "y" => dims.1 = Some(number()?),
+            "first" => parity = match value
+            {
+                "up" => 0,
+                "down" => 1,
+                other => return Err(RleErrorKind::BadHeader(format!("expected up or down, not '{}'", other)))
+            },
+            other => return Err(RleErrorKind::BadHeader(format!("unknown field '{}'", other)))
+        }
+    }
+    match dims
+    {
+        (Some(w), Some(h)) => match check_dims((w, h))
+        {
+            Ok(_) => Ok((w, h, parity)),
+            Err(too_large) => Err(RleErrorKind::BadHeader(too_large.to_string()))
+        },
+        _ => Err(RleErrorKind::MissingHeader)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::Light;
+    use crate::rng::SplitMix64;
+
+    fn read(text: &str) -> Result<Pattern<Light>, RleError>
+    {
+        Pattern::from_rle(text, str::parse)
+    }
+
+    // Mostly dark, with a few lit cells and sources.
+    fn sparse(rng: &mut SplitMix64, dims: (usize, usize)) -> Grid<Light>
+    {
+        Grid::from_fn(dims, |_| match rng.below(12)
+        {
+            0 => Light::Source(rng.below(4) as u8 + 6),
+            1 | 2 => Light::Space(rng.below(3) as u8 + 1),
+            _ => Light::Space(0)
+        })
+    }
+
+    #[test]
+    fn random_patterns_round_trip()
+    {
+        let mut rng = SplitMix64::new(8);
+        for n in 0..40
+        {
+            let dims = (1 + rng.below(90) as usize, 1 + rng.below(30) as usize);
+            let grid = sparse(&mut rng, dims);
+            let pattern = Pattern{cells: grid.clone(), parity: n % 2};
+            let legend = pattern.rle_legend();
+            let text = pattern.to_rle(&legend).unwrap();
+            assert!(text.lines().all(|line| line.len() <= LINE_WIDTH), "{}", text);
+            assert_eq!(read(&text), Ok(pattern), "{}", text);
+            if n % 2 == 0
+            {
+                assert_eq!(grid.to_rle(&legend), Ok(text));
+            }
+        }
+        // Dark rows and the ends of rows are left out.
+        let mut grid = Grid::new((40, 20), Light::Space(0));
+        *grid.get_mut((12, 0)).unwrap() = Light::Source(10);
+        *grid.get_mut((5, 4)).unwrap() = Light::Source(10);
+        *grid.get_mut((6, 4)).unwrap() = Light::Source(10);
+        let legend = [('.', Light::Space(0)), ('A', Light::Source(10))];
+        assert_eq!(grid.to_rle(&legend).unwrap(), "#L . Space(0)\n#L A Source(10)\nx = 40, y = 20, first = up\n12.A4$5.2A!\n");
+        assert_eq!(grid.to_rle(&legend[1..]), Err((0, 0)));
+    }
+
+    #[test]
+    fn hand_written_files()
+    {
+        let text = "# a lamp\n#L . Space(0)\n#L A Source(10)\n\nx = 6, y = 3, first = down\n 2. A $\n\n 3A!";
+        let pattern = read(text).unwrap();
+        assert_eq!(pattern.parity, 1);
+        let levels: Vec<u8> = pattern.cells.data.iter().map(|cell| match cell { Light::Source(l) | Light::Space(l) => *l }).collect();
+        assert_eq!(levels, [0, 0, 10, 0, 0, 0, 10, 10, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let kind = |text: &str| read(text).map(|_| ()).map_err(|error| (error.line, error.column, error.kind));
+        let header = "#L . Space(0)\n#L A Source(4)\nx = 4, y = 2, first = up\n";
+        let with = |runs: &str| format!("{}{}", header, runs);
+        assert_eq!(kind(&with("2.B!")), Err((4, 3, RleErrorKind::UnknownSymbol('B'))));
+        assert_eq!(kind(&with("A\n  3.A$!")), Err((5, 5, RleErrorKind::RowOverflow{row: 0, width: 4})));
+        assert_eq!(kind(&with("A$A$A!")), Err((4, 5, RleErrorKind::TooManyRows{height: 2})));
+        assert_eq!(kind(&with("A$\n2A")), Err((5, 2, RleErrorKind::MissingTerminator)));
+        assert_eq!(kind(&with("0A!")), Err((4, 1, RleErrorKind::BadCount)));
+        assert_eq!(kind(&with("A3")), Err((4, 2, RleErrorKind::BadCount)));
+        assert_eq!(kind("#L . Space(0)\n"), Err((1, 1, RleErrorKind::MissingHeader)));
+        assert_eq!(kind("#L 1 Space(0)\nx = 1, y = 1\n!"), Err((1, 1, RleErrorKind::BadSymbol('1'))));
+        assert_eq!(kind("#L . Space(0)\n#L . Source(1)\nx = 1, y = 1\n!"), Err((2, 1, RleErrorKind::DuplicateSymbol('.'))));
+        assert!(matches!(kind("#L . Light(0)\nx = 1, y = 1\n!"), Err((1, 1, RleErrorKind::BadLegend(_)))));
+        assert!(matches!(kind("x = 1, y = 1, first = left\n!"), Err((1, 1, RleErrorKind::BadHeader(_)))));
+        assert_eq!(kind("x = 2, y = 1\n!"), Err((2, 1, RleErrorKind::NoLegend)));
+        assert_eq!(read(&with("A$\n2A")).unwrap_err().to_string(), "line 5, column 2: missing '!' at the end of the pattern");
+    }
+
+    #[test]
+    fn either_format_is_read()
+    {
+        let pattern = Pattern{cells: Grid::from_fn((4, 2), |(i, _)| if i == 1 { Light::Source(3) } else { Light::Space(0) }), parity: 0};
+        let rle = pattern.to_rle(&pattern.rle_legend()).unwrap();
+        assert_eq!(Pattern::parse_any(&rle, str::parse), Ok(pattern.clone()));
+        assert_eq!(Pattern::parse_any(&pattern.to_text(|cell| format!("{:?}", cell)), str::parse), Ok(pattern));
+        assert!(Pattern::<Light>::parse_any("pattern 2 1 up\nSpace(0)\n", str::parse).unwrap_err().starts_with("line "));
+        assert!(Pattern::<Light>::parse_any("2A!", str::parse).unwrap_err().starts_with("line 1, column 1: "));
+    }
+}
@@ -0,0 +1,88 @@
+// SplitMix64: tiny, fast and good enough for seeding and for the
+// stochastic parts of the crate, without pulling in a dependency.
+
+#[derive(Debug, Clone)]
+pub struct SplitMix64
+{
+    state: u64
+}
+
+impl SplitMix64
+{
+    pub fn new(seed: u64) -> Self
+    {
+        Self{state: seed}
+    }
+
+    pub fn next_u64(&mut self) -> u64
+    {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        finalize(self.state)
+    }
+
+    // Uniform in [0, 1).
+    pub fn next_f64(&mut self) -> f64
+    {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Uniform in [0, n), n > 0.
+    pub fn below(&mut self, n: u64) -> u64
+    {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+}
+
+fn finalize(mut z: u64) -> u64
+{
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Derives an independent seed from a base seed and an index.
+pub fn mix(seed: u64, index: u64) -> u64
+{
+    finalize(seed ^ finalize(index.wrapping_add(0x9e37_79b9_7f4a_7c15)))
+}
+
+// Generator of one cell during one step of a run, derived from the three
+// numbers alone: what the cell draws does not depend on the order the cells
+// are computed in, nor on the thread computing it.
+pub fn cell_rng(seed: u64, step: u64, index: u64) -> SplitMix64
+{
+    SplitMix64::new(mix(mix(seed, step), index))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn cell_generators_only_depend_on_their_numbers()
+    {
+        let draws = |seed, step, index| {
+            let mut rng = cell_rng(seed, step, index);
+            (0..4).map(|_| rng.next_u64()).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(1, 2, 3), draws(1, 2, 3));
+        assert_ne!(draws(1, 2, 3), draws(1, 2, 4));
+        assert_ne!(draws(1, 2, 3), draws(1, 3, 3));
+        assert_ne!(draws(1, 2, 3), draws(2, 2, 3));
+        // Swapping the step and the index gives another generator.
+        assert_ne!(draws(1, 2, 3), draws(1, 3, 2));
+    }
+
+    #[test]
+    fn draws_stay_in_range()
+    {
+        let mut rng = SplitMix64::new(9);
+        for n in 1..200
+        {
+            assert!(rng.below(n) < n);
+            let x = rng.next_f64();
+            assert!((0.0..1.0).contains(&x));
+        }
+    }
+}
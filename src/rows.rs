@@ -0,0 +1,359 @@
+// Layer letting evolve skip the rows that cannot change, off until
+// enable_row_skipping. A cell only sees its row and the ones right above
+// and below it, so a row whose neighborhood rows all came out of the last
+// step unchanged would be computed into what it already is: evolve copies
+// it instead. Once the light has settled every row is like that, and a
+// step costs a copy of the grid.
+//
+// This holds for the same deterministic rule from one evolve to the next
+// (see Rule::is_deterministic), of states equal under PartialEq only when
+// they are the same. evolve cannot tell which closure it is given, so the
+// layer is for rules the caller keeps: Simulation::skip_quiet_rows turns
+// it on for the rule a simulation owns, and off again when set_rule gives
+// it one that is not deterministic. Edits (get_mut, injector and source
+// program writes) are taken into account; other kinds of steps, restarts
+// and loads make the next evolve compute every row, as does
+// invalidate_rows, to be called when the rule changes (the set_rule of
+// Simulation and Session do).
+
+use crate::{Automata, Grid, Light};
+use crate::rules;
+
+use std::fmt::{Debug, Display};
+use std::time::{Duration, Instant};
+
+pub struct RowTracker<T>
+{
+    same: fn(&T, &T) -> bool,
+    // Rows that changed during the last step or were written to since,
+    // None when that is not known.
+    changed: Option<Vec<bool>>,
+    // Set by evolve for the step it is making.
+    evolving: bool,
+    skipped: u64
+}
+
+impl<T: Clone + Display + Copy + Debug + PartialEq> Automata<T>
+{
+    pub fn enable_row_skipping(&mut self)
+    {
+        self.rows = Some(RowTracker{same: |a, b| a == b, changed: None, evolving: false, skipped: 0});
+    }
+}
+
+impl<T: Clone + Display + Copy + Debug> Automata<T>
+{
+    pub fn disable_row_skipping(&mut self)
+    {
+        self.rows = None;
+    }
+
+    // Rows copied instead of computed so far.
+    pub fn rows_skipped(&self) -> u64
+    {
+        self.rows.as_ref().map_or(0, |rows| rows.skipped)
+    }
+
+    // Rows flagged as changed or not, for memory_report.
+    pub(crate) fn row_flags(&self) -> usize
+    {
+        self.rows.as_ref().and_then(|rows| rows.changed.as_ref()).map_or(0, Vec::len)
+    }
+
+    // The next evolve computes every row.
+    pub fn invalidate_rows(&mut self)
+    {
+        if let Some(rows) = self.rows.as_mut()
+        {
+            rows.changed = None;
+        }
+    }
+
+    pub(crate) fn mark_row_changed(&mut self, j: usize)
+    {
+        if let Some(changed) = self.rows.as_mut().and_then(|rows| rows.changed.as_mut())
+        {
+            if let Some(row) = changed.get_mut(j)
+            {
+                *row = true;
+            }
+        }
+    }
+
+    // evolve with the layer enabled.
+    pub(crate) fn evolve_rows<F>(&mut self, rule: F)
+    where
+        F: Fn(Vec<T>) -> T
+    {
+        self.apply_source_programs();
+        let (w, h) = self.current.dims;
+        let rows = self.rows.as_mut().expect("row skipping is enabled");
+        let clean: Vec<bool> = match &rows.changed
+        {
+            Some(changed) => (0..h).map(|j| !changed[j.saturating_sub(1)..(j + 2).min(h)].contains(&true)).collect(),
+            None => vec![false; h]
+        };
+        for (j, &clean) in clean.iter().enumerate()
+        {
+            let row = j*w..(j + 1)*w;
+            if clean
+            {
+                self.scratch.data[row.clone()].copy_from_slice(&self.current.data[row]);
+                rows.skipped += 1;
+                continue;
+            }
+            for i in 0..w
+            {
+                self.scratch.data[j*w + i] = rule(self.current.neighborhood((i, j)).into_iter().cloned().collect());
+            }
+        }
+        std::mem::swap(&mut self.current, &mut self.scratch);
+        self.previous = None;
+        rows.evolving = true;
+        self.finish_step();
+    }
+
+    // Called by finish_step before the injector writes: the rows the step
+    // changed if it was made by evolve, unknown otherwise.
+    pub(crate) fn update_rows(&mut self)
+    {
+        let rows = match self.rows.as_mut()
+        {
+            Some(rows) => rows,
+            None => return
+        };
+        if !std::mem::take(&mut rows.evolving)
+        {
+            rows.changed = None;
+            return;
+        }
+        let (same, w) = (rows.same, self.current.dims.0);
+        let before = self.previous.as_ref().unwrap_or(&self.scratch);
+        rows.changed = Some(self.current.data.chunks(w).zip(before.data.chunks(w))
+            .map(|(new, old)| new.iter().zip(old).any(|(new, old)| !same(new, old)))
+            .collect());
+    }
+}
+
+// The light demo's grid, settled, then stepped `steps` more times with
+// and without skipping: the time each took and the rows skipped.
+pub fn benchmark(steps: u64) -> (Duration, Duration, u64)
+{
+    let mut grid = Grid::new((300, 200), Light::Space(0));
+    *grid.get_mut((100, 100)).unwrap() = Light::Source(200);
+    let mut full = Automata::new(grid);
+    loop
+    {
+        let before = full.current().clone();
+        full.evolve(rules::light_falloff);
+        if *full.current() == before
+        {
+            break;
+        }
+    }
+    let mut skipping = Automata::new(full.current().clone());
+    skipping.enable_row_skipping();
+    let time = |automata: &mut Automata<Light>| {
+        let started = Instant::now();
+        for _ in 0..steps
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        started.elapsed()
+    };
+    (time(&mut full), time(&mut skipping), skipping.rows_skipped())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{CellState, Rule};
+    use crate::registry::RuleRegistry;
+    use crate::simulation::Simulation;
+    use crate::sweep::RuleConfig;
+    use crate::rng::SplitMix64;
+    use crate::validate::{self, seeded_grid};
+
+    use std::cell::RefCell;
+
+    // A rule hashing its neighborhood into a new state, the same one for
+    // the same neighborhood, but for neighborhoods of 0 which stay 0:
+    // activity flickers, spreads and dies out, so the grids are never
+    // settled everywhere.
+    fn scramble(ngh: Vec<u8>) -> u8
+    {
+        if ngh.iter().all(|&cell| cell == 0)
+        {
+            return 0;
+        }
+        let mixed = ngh.iter().fold(ngh.len() as u64, |hash, &cell| hash.wrapping_mul(0x100000001b3) ^ u64::from(cell));
+        if SplitMix64::new(mixed).below(6) == 0 { (mixed % 4) as u8 } else { 0 }
+    }
+
+    // A grain one cell in eight.
+    fn sparse(rng: &mut SplitMix64) -> u8
+    {
+        if rng.below(8) == 0 { rng.below(4) as u8 } else { 0 }
+    }
+
+    fn skipping(grid: Grid<u8>) -> Automata<u8>
+    {
+        let mut automata = Automata::new(grid);
+        automata.enable_row_skipping();
+        automata
+    }
+
+    // Every fifth step, a random write from the injector.
+    fn inject(automata: &mut Automata<u8>, seed: u64)
+    {
+        let dims = automata.current().dims;
+        let mut rng = SplitMix64::new(seed);
+        automata.set_injector(move |step| {
+            if step % 5 != 0
+            {
+                return vec![];
+            }
+            let coord = (rng.below(dims.0 as u64) as usize, rng.below(dims.1 as u64) as usize);
+            vec![(coord, rng.below(4) as u8)]
+        });
+    }
+
+    #[test]
+    fn skipping_matches_full_steps()
+    {
+        for seed in 0..20
+        {
+            let grid = seeded_grid(seed, (24, 16), sparse);
+            let (mut full, mut skipping) = (Automata::new(grid.clone()), skipping(grid));
+            inject(&mut full, seed ^ 1);
+            inject(&mut skipping, seed ^ 1);
+            for step in 1..=200
+            {
+                full.evolve(scramble);
+                skipping.evolve(scramble);
+                assert_eq!(full.current(), skipping.current(), "seed {}, step {}", seed, step);
+            }
+            assert!(skipping.rows_skipped() > 0);
+            assert_eq!(full.rows_skipped(), 0);
+        }
+    }
+
+    #[test]
+    fn edits_and_other_steps_are_seen()
+    {
+        let grid = seeded_grid(3, (16, 12), sparse);
+        let (mut full, mut skipping) = (Automata::new(grid.clone()), skipping(grid));
+        let mut rng = SplitMix64::new(5);
+        for step in 0..120u64
+        {
+            let coord = (rng.below(16) as usize, rng.below(12) as usize);
+            let state = rng.below(4) as u8;
+            *full.get_mut(coord).unwrap() = state;
+            *skipping.get_mut(coord).unwrap() = state;
+            // Stochastic steps now and then, which do not go through evolve.
+            if step % 7 == 0
+            {
+                let rule = |rng: &mut SplitMix64, ngh: Vec<u8>| if rng.below(3) == 0 { 0 } else { scramble(ngh) };
+                full.evolve_stochastic(rule, step);
+                skipping.evolve_stochastic(rule, step);
+            }
+            else
+            {
+                full.evolve(scramble);
+                skipping.evolve(scramble);
+            }
+            assert_eq!(full.current(), skipping.current(), "step {}", step);
+        }
+    }
+
+    #[test]
+    fn settled_light_is_copied()
+    {
+        let mut automata = Automata::new(lamp());
+        automata.enable_row_skipping();
+        for _ in 0..60
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        let (settled, skipped) = (automata.current().clone(), automata.rows_skipped());
+        automata.evolve(rules::light_falloff);
+        assert_eq!(automata.current(), &settled);
+        assert_eq!(automata.rows_skipped(), skipped + 20);
+        automata.disable_row_skipping();
+        automata.evolve(rules::light_falloff);
+        assert_eq!(automata.rows_skipped(), 0);
+    }
+
+    fn lamp() -> Grid<Light>
+    {
+        validate::lamp((30, 20), (10, 10), 40)
+    }
+
+    // Falloff, but a cell keeps its state one time in four, drawn from
+    // a generator of the rule's own.
+    fn flicker(seed: u64) -> Box<dyn Rule<Light>>
+    {
+        let rng = RefCell::new(SplitMix64::new(seed));
+        Box::new(move |ngh: Vec<Light>| if rng.borrow_mut().below(4) == 0 { ngh[0] } else { rules::light_falloff(ngh) })
+    }
+
+    #[test]
+    fn automata_compute_every_row_by_default()
+    {
+        let mut automata = Automata::new(lamp());
+        for _ in 0..80
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        assert_eq!(automata.rows_skipped(), 0);
+        // So a new rule is seen without invalidate_rows.
+        let dark = |ngh: Vec<Light>| if ngh[0].is_pinned() { ngh[0] } else { Light::Space(0) };
+        automata.evolve(dark);
+        assert_eq!(automata.current(), &lamp());
+    }
+
+    #[test]
+    fn random_rules_are_never_skipped()
+    {
+        let mut simulation = Simulation::new(lamp(), flicker(7));
+        assert!(!simulation.skip_quiet_rows());
+        let mut full = Automata::new(lamp());
+        let rule = flicker(7);
+        for step in 1..=150
+        {
+            simulation.evolve();
+            full.evolve(|ngh| rule.apply(ngh));
+            assert_eq!(simulation.current(), full.current(), "step {}", step);
+        }
+        assert_eq!(simulation.automata().rows_skipped(), 0);
+    }
+
+    #[test]
+    fn simulations_skip_while_the_rule_is_deterministic()
+    {
+        let falloff = RuleRegistry::<Light>::global().instantiate("falloff", &RuleConfig::new(0)).unwrap();
+        let mut simulation = Simulation::new(lamp(), falloff);
+        assert!(simulation.skip_quiet_rows());
+        let mut full = Automata::new(lamp());
+        for _ in 0..60
+        {
+            simulation.evolve();
+            full.evolve(rules::light_falloff);
+        }
+        assert_eq!(simulation.current(), full.current());
+        let skipped = simulation.automata().rows_skipped();
+        assert!(skipped > 0);
+        // A random rule turns skipping off, and every row is stepped.
+        simulation.set_rule(flicker(3));
+        let rule = flicker(3);
+        for _ in 0..40
+        {
+            simulation.evolve();
+            full.evolve(|ngh| rule.apply(ngh));
+            assert_eq!(simulation.current(), full.current());
+        }
+        assert_eq!(simulation.automata().rows_skipped(), 0);
+        assert!(!simulation.skip_quiet_rows());
+    }
+}
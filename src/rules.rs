@@ -0,0 +1,286 @@
+use crate::{CellState, Grid};
+use crate::render::Glyph;
+use crate::rng::SplitMix64;
+
+use std::fmt;
+
+// What the clamped light rules do with levels above their ceiling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy
+{
+    Saturate,
+    // Fail, for Automata::try_evolve to report the cell.
+    Error
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clamp
+{
+    pub max: u8,
+    pub on_overflow: OverflowPolicy
+}
+
+impl Clamp
+{
+    pub fn saturate(max: u8) -> Self
+    {
+        Self{max, on_overflow: OverflowPolicy::Saturate}
+    }
+
+    fn apply<L: CellState>(self, cell: L, level: u32) -> Result<L, Overflow>
+    {
+        if level <= u32::from(self.max)
+        {
+            Ok(cell.with_level(level as u8))
+        }
+        else
+        {
+            match self.on_overflow
+            {
+                OverflowPolicy::Saturate => Ok(cell.with_level(self.max)),
+                OverflowPolicy::Error => Err(Overflow{level, max: self.max})
+            }
+        }
+    }
+}
+
+impl Default for Clamp
+{
+    fn default() -> Self
+    {
+        Self::saturate(u8::MAX)
+    }
+}
+
+// A level a clamped rule computed above its ceiling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overflow
+{
+    pub level: u32,
+    pub max: u8
+}
+
+fn falloff_level<L: CellState>(ngh: &[L]) -> u32
+{
+    u32::from(ngh.iter().map(CellState::level).max().unwrap_or(0).saturating_sub(1))
+}
+
+fn additive_level<L: CellState>(ngh: &[L]) -> u32
+{
+    ngh[1..].iter().map(|ncel| u32::from(ncel.level().saturating_sub(1))).sum()
+}
+
+// Light spreads to the neighbors, losing one level per cell travelled.
+// Sources keep their level forever.
+pub fn light_falloff<L: CellState>(ngh: Vec<L>) -> L
+{
+    let cell = ngh[0];
+    if cell.is_pinned()
+    {
+        return cell;
+    }
+    cell.with_level(falloff_level(&ngh) as u8)
+}
+
+// Light adds up: every neighbor contributes its level minus one, so cells
+// lit from several sides get brighter than any of them alone (up to 255).
+// Sources keep their level forever.
+pub fn light_additive<L: CellState>(ngh: Vec<L>) -> L
+{
+    let cell = ngh[0];
+    if cell.is_pinned()
+    {
+        return cell;
+    }
+    cell.with_level(additive_level(&ngh).min(255) as u8)
+}
+
+// Like light_falloff, losing `amount` levels per cell travelled instead
+// of one: short-lived glows, or with 0 light that never fades.
+pub fn light_decay<L: CellState>(amount: u8) -> impl Fn(Vec<L>) -> L
+{
+    move |ngh| {
+        let cell = ngh[0];
+        if cell.is_pinned()
+        {
+            return cell;
+        }
+        let brightest = ngh.iter().map(CellState::level).max().unwrap_or(0);
+        cell.with_level(brightest.saturating_sub(amount))
+    }
+}
+
+// The same rules with a ceiling on the level of lit cells. Sources are
+// left alone, whatever their level.
+pub fn light_falloff_clamped<L: CellState>(clamp: Clamp) -> impl Fn(Vec<L>) -> Result<L, Overflow>
+{
+    move |ngh| {
+        let cell = ngh[0];
+        if cell.is_pinned() { Ok(cell) } else { clamp.apply(cell, falloff_level(&ngh)) }
+    }
+}
+
+pub fn light_additive_clamped<L: CellState>(clamp: Clamp) -> impl Fn(Vec<L>) -> Result<L, Overflow>
+{
+    move |ngh| {
+        let cell = ngh[0];
+        if cell.is_pinned() { Ok(cell) } else { clamp.apply(cell, additive_level(&ngh)) }
+    }
+}
+
+// Heat moves towards the mean of the neighbors, by a fraction `relaxation`
+// of the difference at each step (1.0 jumps straight to the mean).
+pub fn heat_diffusion(relaxation: f32) -> impl Fn(Vec<f32>) -> f32
+{
+    move |ngh| {
+        let cell = ngh[0];
+        if ngh.len() == 1
+        {
+            return cell;
+        }
+        let mean = ngh[1..].iter().sum::<f32>() / (ngh.len() - 1) as f32;
+        cell + relaxation * (mean - cell)
+    }
+}
+
+// Sandpile: a cell holding 3 grains or more topples, sending one grain to
+// each of its neighbors. Along the edges some of those grains fall off the
+// grid, see sandpile_boundary_loss.
+pub fn sandpile(ngh: Vec<u8>) -> u8
+{
+    let cell = ngh[0];
+    let kept = if cell >= 3 { cell - 3 } else { cell };
+    let received = ngh[1..].iter().filter(|&&ncel| ncel >= 3).count() as u8;
+    kept.saturating_add(received)
+}
+
+// Change of the total number of grains over the next sandpile step: every
+// toppling cell loses a grain per missing neighbor.
+pub fn sandpile_boundary_loss(grid: &Grid<u8>) -> i64
+{
+    let mut loss = 0;
+    for j in 0..grid.dims.1
+    {
+        for i in 0..grid.dims.0
+        {
+            if *grid.get((i, j)).unwrap() >= 3
+            {
+                loss += 4 - grid.neighbor_coords((i, j)).len() as i64;
+            }
+        }
+    }
+    -loss
+}
+
+// Block rule for Automata::evolve_blocks: the two cells of a pair trade
+// their grains, so the total amount of grains never changes.
+pub fn grain_shuffle((left, right): (u8, u8)) -> (u8, u8)
+{
+    (right, left)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Forest
+{
+    Empty,
+    Tree,
+    Burning
+}
+
+impl Glyph for Forest
+{
+    fn glyph(&self) -> char
+    {
+        match self
+        {
+            Forest::Empty => ' ',
+            Forest::Tree => '^',
+            Forest::Burning => '*'
+        }
+    }
+}
+
+impl fmt::Display for Forest
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{:^3}", self.glyph())
+    }
+}
+
+// Drossel-Schwabl forest fire, for Automata::evolve_stochastic: a fire
+// burns out in one step and spreads to the trees next to it, a tree is
+// struck by lightning with probability `lightning`, and a tree grows on an
+// empty cell with probability `growth`.
+pub fn forest_fire(growth: f64, lightning: f64) -> impl Fn(&mut SplitMix64, Vec<Forest>) -> Forest + Sync
+{
+    move |rng, ngh| match ngh[0]
+    {
+        Forest::Burning => Forest::Empty,
+        Forest::Tree if ngh[1..].contains(&Forest::Burning) || rng.next_f64() < lightning => Forest::Burning,
+        Forest::Tree => Forest::Tree,
+        Forest::Empty if rng.next_f64() < growth => Forest::Tree,
+        Forest::Empty => Forest::Empty
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{Automata, Light, RuleError};
+
+    fn source(level: u8) -> Automata<Light>
+    {
+        let mut automata = Automata::new(Grid::new((9, 5), Light::Space(0)));
+        *automata.get_mut((4, 2)).unwrap() = Light::Source(level);
+        automata
+    }
+
+    #[test]
+    fn low_ceilings_saturate()
+    {
+        let mut automata = source(20);
+        let rule = light_falloff_clamped(Clamp::saturate(5));
+        for _ in 0..6
+        {
+            automata.try_evolve(&rule).unwrap();
+        }
+        assert_eq!(automata.get((4, 2)), Some(&Light::Source(20)));
+        assert_eq!(automata.get((3, 2)), Some(&Light::Space(5)));
+        assert!(automata.current().data.iter().filter(|cell| !cell.is_pinned()).all(|cell| cell.level() <= 5));
+        // Below the ceiling, as the plain rule: 3 cells away.
+        assert_eq!(automata.get((1, 2)), Some(&Light::Space(3)));
+
+        let rule = light_additive_clamped(Clamp::saturate(5));
+        let three = vec![Light::Space(0), Light::Space(3), Light::Space(3), Light::Space(3)];
+        assert_eq!(rule(three.clone()), Ok(Light::Space(5)));
+        assert_eq!(light_additive(three), Light::Space(6));
+        assert_eq!(rule(vec![Light::Source(9), Light::Space(200)]), Ok(Light::Source(9)));
+    }
+
+    #[test]
+    fn overflows_name_the_cell()
+    {
+        let clamp = Clamp{max: 5, on_overflow: OverflowPolicy::Error};
+        let mut automata = source(20);
+        let before = automata.current().clone();
+        // (4, 2) points up: its first neighbor in row order is (3, 2).
+        let error = automata.try_evolve(light_falloff_clamped(clamp)).unwrap_err();
+        assert_eq!(error, RuleError{coord: (3, 2), error: Overflow{level: 19, max: 5}});
+        assert_eq!(automata.current(), &before);
+        assert_eq!(automata.step(), 0);
+
+        // Two sources of 4 are within the ceiling alone, not added up in
+        // the cell between them.
+        let two = Grid::from_fn((9, 5), |coord| match coord
+        {
+            (3, 2) | (5, 2) => Light::Source(4),
+            _ => Light::Space(0)
+        });
+        assert!(Automata::new(two.clone()).try_evolve(light_falloff_clamped(clamp)).is_ok());
+        let mut automata = Automata::new(two);
+        let error = automata.try_evolve(light_additive_clamped(clamp)).unwrap_err();
+        assert_eq!(error, RuleError{coord: (4, 2), error: Overflow{level: 6, max: 5}});
+    }
+}
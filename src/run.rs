@@ -0,0 +1,551 @@
+// Run loops: a paced single-threaded one, and one running the simulation
+// and the rendering on two threads, so that slow renderers do not slow the
+// simulation down (or only as much as asked).
+
+use crate::{Automata, Grid};
+use crate::analysis::ActivityTracker;
+
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// What the simulation does when the renderer is `capacity` frames behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FullQueue
+{
+    // Forget the oldest waiting frame: the renderer skips ahead.
+    DropOldest,
+    // Wait for the renderer: every frame is drawn.
+    Block
+}
+
+#[derive(Debug, Clone)]
+pub struct PipelineOptions
+{
+    pub capacity: usize,
+    pub full: FullQueue,
+    // Checked before every step; setting it (from a Ctrl-C handler, say)
+    // ends the run after the frames already queued are rendered.
+    pub stop: Arc<AtomicBool>
+}
+
+impl Default for PipelineOptions
+{
+    fn default() -> Self
+    {
+        Self{capacity: 4, full: FullQueue::DropOldest, stop: Arc::new(AtomicBool::new(false))}
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PipelineStats
+{
+    pub steps: u64,
+    pub rendered: usize,
+    pub dropped: usize
+}
+
+struct State<T>
+{
+    frames: VecDeque<(Grid<T>, u64)>,
+    // Set when either side is done.
+    closed: bool
+}
+
+struct Queue<T>
+{
+    state: Mutex<State<T>>,
+    filled: Condvar,
+    emptied: Condvar
+}
+
+impl<T> Queue<T>
+{
+    fn close(&self)
+    {
+        self.state.lock().unwrap().closed = true;
+        self.filled.notify_all();
+        self.emptied.notify_all();
+    }
+}
+
+// Closes the queue when the render thread ends, even by a panic, so that
+// a blocked simulation does not wait forever.
+struct CloseOnDrop<'a, T>(&'a Queue<T>);
+
+impl<T> Drop for CloseOnDrop<'_, T>
+{
+    fn drop(&mut self)
+    {
+        self.0.close();
+    }
+}
+
+// Evolves the automaton `steps` times on this thread while `renderer` is
+// called on another one with every generation (the current one included)
+// and its step, in order, minus the ones dropped under
+// FullQueue::DropOldest. The last generation queued is always rendered
+// before this returns.
+pub fn run_pipelined<T, F, R>(automata: &mut Automata<T>, rule: F, steps: u64, renderer: R, options: &PipelineOptions) -> PipelineStats
+where
+    T: Clone + Display + Copy + Debug + Send,
+    F: Fn(Vec<T>) -> T,
+    R: FnMut(&Grid<T>, u64) + Send
+{
+    let capacity = options.capacity.max(1);
+    let queue = Queue{state: Mutex::new(State{frames: VecDeque::with_capacity(capacity), closed: false}), filled: Condvar::new(), emptied: Condvar::new()};
+    let mut stats = PipelineStats::default();
+
+    thread::scope(|scope| {
+        let render = scope.spawn(|| {
+            let mut renderer = renderer;
+            let _close = CloseOnDrop(&queue);
+            let mut rendered = 0;
+            loop
+            {
+                let (grid, step) = {
+                    let mut state = queue.state.lock().unwrap();
+                    while state.frames.is_empty() && !state.closed
+                    {
+                        state = queue.filled.wait(state).unwrap();
+                    }
+                    match state.frames.pop_front()
+                    {
+                        Some(frame) => frame,
+                        None => break
+                    }
+                };
+                queue.emptied.notify_one();
+                renderer(&grid, step);
+                rendered += 1;
+            }
+            rendered
+        });
+
+        let start = automata.step();
+        for n in 0..=steps
+        {
+            if options.stop.load(Ordering::Relaxed)
+            {
+                break;
+            }
+            if n > 0
+            {
+                automata.evolve(&rule);
+            }
+            let frame = (automata.current().clone(), automata.step());
+            let mut state = queue.state.lock().unwrap();
+            if options.full == FullQueue::Block
+            {
+                while state.frames.len() >= capacity && !state.closed
+                {
+                    state = queue.emptied.wait(state).unwrap();
+                }
+            }
+            if state.closed
+            {
+                // The renderer is gone.
+                break;
+            }
+            if state.frames.len() >= capacity
+            {
+                state.frames.pop_front();
+                stats.dropped += 1;
+            }
+            state.frames.push_back(frame);
+            drop(state);
+            queue.filled.notify_one();
+        }
+        stats.steps = automata.step() - start;
+        queue.close();
+        stats.rendered = match render.join()
+        {
+            Ok(rendered) => rendered,
+            Err(panic) => std::panic::resume_unwind(panic)
+        };
+    });
+    stats
+}
+
+// Paces frames at a fixed rate. Frames are due every 1/fps seconds from
+// the first call to should_render; when the loop falls more than a frame
+// behind, drawing is skipped to catch up, but never more than
+// `max_frame_skip` frames in a row. After a frame drawn late the schedule
+// starts over from it rather than trying to catch up forever.
+#[derive(Debug, Clone)]
+pub struct FrameClock
+{
+    period: Option<Duration>,
+    deadline: Option<Instant>,
+    max_frame_skip: usize,
+    skipped: usize
+}
+
+impl FrameClock
+{
+    // Without a positive fps every frame is drawn and nothing waits.
+    pub fn new(target_fps: f32, max_frame_skip: usize) -> Self
+    {
+        let period = if target_fps > 0.0 { Some(Duration::from_secs_f32(1.0 / target_fps)) } else { None };
+        Self{period, deadline: None, max_frame_skip, skipped: 0}
+    }
+
+    // Called at the start of every frame.
+    pub fn should_render(&mut self, now: Instant) -> bool
+    {
+        let period = match self.period
+        {
+            Some(period) => period,
+            None => return true
+        };
+        let deadline = *self.deadline.get_or_insert(now);
+        let late = now >= deadline + period;
+        self.deadline = Some(deadline + period);
+        if late && self.skipped < self.max_frame_skip
+        {
+            self.skipped += 1;
+            return false;
+        }
+        self.skipped = 0;
+        if late
+        {
+            self.deadline = Some(now + period);
+        }
+        true
+    }
+
+    // How long to wait before the next frame is due.
+    pub fn sleep_time(&self, now: Instant) -> Duration
+    {
+        match self.deadline
+        {
+            Some(deadline) if self.period.is_some() => deadline.saturating_duration_since(now),
+            _ => Duration::from_secs(0)
+        }
+    }
+}
+
+type Predicate<T> = Box<dyn Fn(&Grid<T>) -> bool>;
+
+// Which steps side effects (frames, log rows, callbacks) fire on, the
+// simulation still going through every step: the multiples of
+// every_n_steps, counted from step 0 of the automaton, whose generation
+// also passes `only_when` if there is one.
+pub struct FrameFilter<T>
+{
+    pub every_n_steps: usize,
+    only_when: Option<Predicate<T>>
+}
+
+impl<T> FrameFilter<T>
+{
+    // 0 is taken as 1.
+    pub fn every(n: usize) -> Self
+    {
+        Self{every_n_steps: n.max(1), only_when: None}
+    }
+
+    pub fn all() -> Self
+    {
+        Self::every(1)
+    }
+
+    pub fn only_when<P: Fn(&Grid<T>) -> bool + 'static>(mut self, predicate: P) -> Self
+    {
+        self.only_when = Some(Box::new(predicate));
+        self
+    }
+
+    pub fn selects(&self, step: u64, grid: &Grid<T>) -> bool
+    {
+        step.is_multiple_of(self.every_n_steps.max(1) as u64)
+            && self.only_when.as_ref().is_none_or(|predicate| predicate(grid))
+    }
+}
+
+impl<T> Default for FrameFilter<T>
+{
+    fn default() -> Self
+    {
+        Self::all()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoopOptions
+{
+    pub steps_per_frame: usize,
+    // Frames per second, 0 for as fast as possible.
+    pub target_fps: f32,
+    pub max_frame_skip: usize
+}
+
+impl Default for LoopOptions
+{
+    fn default() -> Self
+    {
+        Self{steps_per_frame: 1, target_fps: 0.0, max_frame_skip: 5}
+    }
+}
+
+// Runs `frames` frames, each drawing the current generation (unless the
+// clock skips it) then evolving `steps_per_frame` times.
+pub fn run_loop<T, F, R>(automata: &mut Automata<T>, rule: F, frames: usize, render: R, options: &LoopOptions)
+where
+    T: Clone + Display + Copy + Debug,
+    F: Fn(Vec<T>) -> T,
+    R: FnMut(&Grid<T>)
+{
+    paced_loop(automata, |automata| automata.evolve(&rule), frames, render, options, |_| ());
+}
+
+// The same with each step made by `step`, for automata evolved some other
+// way than with evolve.
+pub fn run_loop_with<T, S, R>(automata: &mut Automata<T>, step: S, frames: usize, render: R, options: &LoopOptions)
+where
+    T: Clone + Display + Copy + Debug,
+    S: FnMut(&mut Automata<T>),
+    R: FnMut(&Grid<T>)
+{
+    paced_loop(automata, step, frames, render, options, |_| ());
+}
+
+// The same, recording every step (drawn or not) in `tracker` if given.
+pub fn run_loop_tracked<T, F, R>(automata: &mut Automata<T>, rule: F, frames: usize, render: R, options: &LoopOptions,
+                                 mut tracker: Option<&mut ActivityTracker>)
+where
+    T: Clone + Display + Copy + Debug + PartialEq,
+    F: Fn(Vec<T>) -> T,
+    R: FnMut(&Grid<T>)
+{
+    paced_loop(automata, |automata| automata.evolve(&rule), frames, render, options, |automata| {
+        if let Some(tracker) = tracker.as_mut()
+        {
+            tracker.observe(automata);
+        }
+    });
+}
+
+// run_loop calling `observer` after the steps `filter` selects, drawn or
+// not, with the automaton as it is after them.
+pub fn run_loop_observed<T, F, R, O>(automata: &mut Automata<T>, rule: F, frames: usize, render: R, options: &LoopOptions,
+                                     filter: &FrameFilter<T>, mut observer: O)
+where
+    T: Clone + Display + Copy + Debug,
+    F: Fn(Vec<T>) -> T,
+    R: FnMut(&Grid<T>),
+    O: FnMut(&Automata<T>)
+{
+    paced_loop(automata, |automata| automata.evolve(&rule), frames, render, options, |automata| {
+        if filter.selects(automata.step(), automata.current())
+        {
+            observer(automata);
+        }
+    });
+}
+
+fn paced_loop<T, F, R, S>(automata: &mut Automata<T>, mut step: F, frames: usize, mut render: R, options: &LoopOptions, mut after_step: S)
+where
+    T: Clone + Display + Copy + Debug,
+    F: FnMut(&mut Automata<T>),
+    R: FnMut(&Grid<T>),
+    S: FnMut(&Automata<T>)
+{
+    let mut clock = FrameClock::new(options.target_fps, options.max_frame_skip);
+    for _ in 0..frames
+    {
+        if clock.should_render(Instant::now())
+        {
+            render(automata.current());
+        }
+        for _ in 0..options.steps_per_frame
+        {
+            step(automata);
+            after_step(automata);
+        }
+        thread::sleep(clock.sleep_time(Instant::now()));
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, CellState, Light};
+
+    fn light() -> Automata<Light>
+    {
+        let mut automata = Automata::new(Grid::new((15, 9), Light::Space(0)));
+        *automata.get_mut((7, 4)).unwrap() = Light::Source(6);
+        automata
+    }
+
+    fn options(capacity: usize, full: FullQueue) -> PipelineOptions
+    {
+        PipelineOptions{capacity, full, ..PipelineOptions::default()}
+    }
+
+    #[test]
+    fn blocking_renders_every_frame_in_order()
+    {
+        let mut automata = light();
+        let mut expected = light();
+        let mut frames = vec![expected.current().clone()];
+        for _ in 0..20
+        {
+            expected.evolve(rules::light_falloff);
+            frames.push(expected.current().clone());
+        }
+        let mut seen = vec![];
+        let stats = run_pipelined(&mut automata, rules::light_falloff, 20, |grid: &Grid<Light>, step| {
+            thread::sleep(Duration::from_millis(1));
+            seen.push((grid.clone(), step));
+        }, &options(1, FullQueue::Block));
+        assert_eq!(stats, PipelineStats{steps: 20, rendered: 21, dropped: 0});
+        assert_eq!(seen, frames.into_iter().zip(0..).collect::<Vec<_>>());
+        assert_eq!(automata.current(), expected.current());
+    }
+
+    #[test]
+    fn a_slow_renderer_skips_ahead()
+    {
+        let mut automata = light();
+        let mut steps = vec![];
+        let stats = run_pipelined(&mut automata, rules::light_falloff, 60, |_: &Grid<Light>, step| {
+            thread::sleep(Duration::from_millis(3));
+            steps.push(step);
+        }, &options(2, FullQueue::DropOldest));
+        assert_eq!(stats.steps, 60);
+        assert_eq!(stats.rendered, steps.len());
+        assert_eq!(stats.rendered + stats.dropped, 61);
+        assert!(stats.dropped > 0);
+        assert!(steps.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(steps.last(), Some(&60));
+    }
+
+    #[test]
+    fn stopping_ends_the_run()
+    {
+        let stopped = options(1, FullQueue::Block);
+        stopped.stop.store(true, Ordering::Relaxed);
+        let stats = run_pipelined(&mut light(), rules::light_falloff, 1000, |_: &Grid<Light>, _| (), &stopped);
+        assert_eq!(stats, PipelineStats::default());
+
+        // Stopped from the render thread while the simulation waits on it.
+        let options = options(1, FullQueue::Block);
+        let stop = Arc::clone(&options.stop);
+        let mut automata = light();
+        let stats = run_pipelined(&mut automata, rules::light_falloff, 1_000_000, move |_: &Grid<Light>, step| {
+            if step == 5
+            {
+                stop.store(true, Ordering::Relaxed);
+            }
+        }, &options);
+        assert!(stats.steps < 10, "{:?}", stats);
+        assert_eq!(stats.rendered as u64, stats.steps + 1);
+        assert_eq!(automata.step(), stats.steps);
+    }
+
+    #[test]
+    fn a_renderer_panic_does_not_deadlock()
+    {
+        let result = std::panic::catch_unwind(|| {
+            run_pipelined(&mut light(), rules::light_falloff, 1_000_000, |_: &Grid<Light>, step| {
+                assert!(step < 3, "renderer gives up");
+            }, &options(1, FullQueue::Block))
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn frame_clock_on_time_and_late()
+    {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        // 125 ms apart, exactly in f32.
+        let mut clock = FrameClock::new(8.0, 2);
+        // On time: every frame is drawn, the loop waits for the next one.
+        assert!(clock.should_render(at(0)));
+        assert_eq!(clock.sleep_time(at(30)), Duration::from_millis(95));
+        assert!(clock.should_render(at(125)));
+        assert!(clock.should_render(at(249)));
+        // Far behind: two frames skipped, then one drawn and the schedule
+        // restarted from it.
+        assert!(!clock.should_render(at(900)));
+        assert!(!clock.should_render(at(901)));
+        assert!(clock.should_render(at(902)));
+        assert_eq!(clock.sleep_time(at(902)), Duration::from_millis(125));
+        assert!(clock.should_render(at(1027)));
+        assert_eq!(clock.sleep_time(at(1500)), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn unpaced_clocks_draw_everything()
+    {
+        let mut clock = FrameClock::new(0.0, 0);
+        let start = Instant::now();
+        for ms in &[0, 5000, 5001]
+        {
+            assert!(clock.should_render(start + Duration::from_millis(*ms)));
+            assert_eq!(clock.sleep_time(start), Duration::from_secs(0));
+        }
+        // Without skipping, late frames are still drawn.
+        let mut clock = FrameClock::new(100.0, 0);
+        assert!(clock.should_render(start));
+        assert!(clock.should_render(start + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn several_steps_per_frame()
+    {
+        let mut automata = light();
+        let mut drawn = vec![];
+        let options = LoopOptions{steps_per_frame: 3, ..LoopOptions::default()};
+        run_loop(&mut automata, rules::light_falloff, 4, |grid: &Grid<Light>| drawn.push(grid.clone()), &options);
+        assert_eq!(automata.step(), 12);
+        let mut expected = light();
+        for (n, frame) in drawn.iter().enumerate()
+        {
+            assert_eq!(frame, expected.current(), "frame {}", n);
+            for _ in 0..3
+            {
+                expected.evolve(rules::light_falloff);
+            }
+        }
+        assert_eq!(drawn.len(), 4);
+    }
+
+    #[test]
+    fn filters_select_steps_and_generations()
+    {
+        let grid = Grid::new((3, 2), Light::Space(0));
+        let filter = FrameFilter::every(7);
+        let selected: Vec<u64> = (0..=100).filter(|&step| filter.selects(step, &grid)).collect();
+        assert_eq!(selected, (0..=100).step_by(7).collect::<Vec<u64>>());
+        assert!((0..5).all(|step| FrameFilter::every(0).selects(step, &grid)));
+        let lit = FrameFilter::every(2).only_when(|grid: &Grid<Light>| grid.data.iter().any(|cell| cell.level() > 0));
+        assert!(!lit.selects(4, &grid));
+        let mut bright = grid.clone();
+        *bright.get_mut((1, 1)).unwrap() = Light::Space(1);
+        assert!(lit.selects(4, &bright));
+        assert!(!lit.selects(5, &bright));
+    }
+
+    // The observer sees the steps themselves, not the generation the loop
+    // starts from: 7 to 98 out of 100.
+    #[test]
+    fn observers_fire_on_the_selected_steps()
+    {
+        let mut automata = light();
+        let mut observed = vec![];
+        let mut drawn = 0;
+        run_loop_observed(&mut automata, rules::light_falloff, 100, |_: &Grid<Light>| drawn += 1, &LoopOptions::default(),
+                          &FrameFilter::every(7), |automata| observed.push(automata.step()));
+        assert_eq!(automata.step(), 100);
+        assert_eq!(drawn, 100);
+        assert_eq!(observed, (7..=100).step_by(7).collect::<Vec<u64>>());
+    }
+}
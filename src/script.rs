@@ -0,0 +1,348 @@
+// Experiments written as command files, one command per line, `#` starting
+// a comment:
+//
+//   grid 30 20 Space(0)          size and initial state of every cell
+//   rule decay amount=3          a registered rule, see registry.rs
+//   neighborhood vertex          cells the rule sees (edge by default)
+//   palette magma                colors of the heatmaps (heat by default)
+//   at 0 set 10 10 Source(10)    actions at a given step...
+//   every 5 print                ...or at every multiple of a period
+//   run 30                       number of steps
+//
+// Actions are `set i j state`, `print`, `save path` (a checkpoint) and
+// `heatmap path` (an SVG of the intensities); `{step}` in a path is
+// replaced by the step number. Actions happen once the step is reached,
+// before the next one is computed, in the order of the file.
+
+use crate::{Automata, CellState, Grid, Light, Rule};
+use crate::color::ColorMap;
+use crate::error::{check_coord, check_dims};
+use crate::neighborhood::NeighborhoodKind;
+use crate::plots;
+use crate::registry::{self, RuleRegistry};
+use crate::render::{self, RenderMode};
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError
+{
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for ScriptError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule
+{
+    At(u64),
+    Every(u64)
+}
+
+impl Schedule
+{
+    fn matches(self, step: u64) -> bool
+    {
+        match self
+        {
+            Schedule::At(at) => step == at,
+            Schedule::Every(period) => step.is_multiple_of(period)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action
+{
+    Set((usize, usize), Light),
+    Print,
+    Save(String),
+    Heatmap(String)
+}
+
+pub struct Timeline
+{
+    pub dims: (usize, usize),
+    pub fill: Light,
+    pub rule: Box<dyn Rule<Light>>,
+    pub neighborhood: NeighborhoodKind,
+    pub colormap: ColorMap,
+    pub steps: u64,
+    // With the line they come from.
+    pub actions: Vec<(Schedule, Action, usize)>
+}
+
+fn number<T: std::str::FromStr>(word: Option<&str>, what: &str) -> Result<T, String>
+{
+    let word = word.ok_or_else(|| format!("missing {}", what))?;
+    word.parse().map_err(|_| format!("invalid {} '{}'", what, word))
+}
+
+fn action<'a, I: Iterator<Item = &'a str>>(mut words: I) -> Result<Action, String>
+{
+    let command = words.next().ok_or("missing action")?;
+    let action = match command
+    {
+        "set" =>
+        {
+            let i = number(words.next(), "column")?;
+            let j = number(words.next(), "row")?;
+            let state = words.next().ok_or("missing state")?.parse()?;
+            Action::Set((i, j), state)
+        },
+        "print" => Action::Print,
+        "save" => Action::Save(words.next().ok_or("missing path")?.to_string()),
+        "heatmap" => Action::Heatmap(words.next().ok_or("missing path")?.to_string()),
+        other => return Err(format!("unknown action '{}'", other))
+    };
+    match words.next()
+    {
+        Some(extra) => Err(format!("unexpected '{}'", extra)),
+        None => Ok(action)
+    }
+}
+
+pub fn parse(text: &str) -> Result<Timeline, ScriptError>
+{
+    let mut grid = None;
+    let mut rule = None;
+    let mut neighborhood = NeighborhoodKind::Edge;
+    let mut colormap = ColorMap::Heat;
+    let mut steps = None;
+    let mut actions = vec![];
+    for (n, line) in text.lines().enumerate()
+    {
+        let line_number = n + 1;
+        let error = |message: String| ScriptError{line: line_number, message};
+        let line = line.split('#').next().unwrap();
+        let mut words = line.split_whitespace();
+        let command = match words.next()
+        {
+            Some(command) => command,
+            None => continue
+        };
+        match command
+        {
+            "grid" =>
+            {
+                let w = number(words.next(), "width").map_err(error)?;
+                let h = number(words.next(), "height").map_err(error)?;
+                check_dims((w, h)).map_err(|e| error(e.to_string()))?;
+                let fill = words.next().ok_or_else(|| error("missing state".to_string()))?
+                    .parse().map_err(error)?;
+                grid = Some(((w, h), fill));
+            },
+            "rule" =>
+            {
+                let name = words.next().ok_or_else(|| error("missing rule name".to_string()))?;
+                let config = registry::parse_params(words).map_err(error)?;
+                rule = Some(RuleRegistry::<Light>::global().instantiate(name, &config).map_err(|e| error(e.to_string()))?);
+            },
+            "neighborhood" =>
+            {
+                neighborhood = words.next().ok_or_else(|| error("missing neighborhood".to_string()))?
+                    .parse().map_err(error)?;
+            },
+            "palette" =>
+            {
+                let name = words.next().ok_or_else(|| error("missing palette".to_string()))?;
+                colormap = ColorMap::named(name).map_err(|e| error(e.to_string()))?;
+            },
+            "run" => steps = Some((number(words.next(), "step count").map_err(error)?, line_number)),
+            "at" =>
+            {
+                let step = number(words.next(), "step").map_err(error)?;
+                actions.push((Schedule::At(step), action(words).map_err(error)?, line_number));
+            },
+            "every" =>
+            {
+                let period: u64 = number(words.next(), "period").map_err(error)?;
+                if period == 0
+                {
+                    return Err(error("the period must be positive".to_string()));
+                }
+                actions.push((Schedule::Every(period), action(words).map_err(error)?, line_number));
+            },
+            other => return Err(error(format!("unknown command '{}'", other)))
+        }
+    }
+
+    let last_line = text.lines().count();
+    let missing = |what: &str| ScriptError{line: last_line, message: format!("no {} command", what)};
+    let (dims, fill) = grid.ok_or_else(|| missing("grid"))?;
+    let rule = rule.ok_or_else(|| missing("rule"))?;
+    let (steps, _) = steps.ok_or_else(|| missing("run"))?;
+    for (schedule, action, line) in &actions
+    {
+        if let Schedule::At(step) = schedule
+        {
+            if *step > steps
+            {
+                return Err(ScriptError{line: *line, message: format!("step {} is after the end of the run ({})", step, steps)});
+            }
+        }
+        if let Action::Set((i, j), _) = action
+        {
+            check_coord(dims, (*i, *j)).map_err(|e| ScriptError{line: *line, message: e.to_string()})?;
+        }
+    }
+    Ok(Timeline{dims, fill, rule, neighborhood, colormap, steps, actions})
+}
+
+fn with_step(path: &str, step: u64) -> String
+{
+    path.replace("{step}", &format!("{:05}", step))
+}
+
+impl Timeline
+{
+    fn writes(actions: &[(Schedule, Action, usize)], step: u64) -> Vec<((usize, usize), Light)>
+    {
+        actions.iter()
+            .filter(|(schedule, _, _)| schedule.matches(step))
+            .filter_map(|(_, action, _)| match action
+            {
+                Action::Set(coord, state) => Some((*coord, *state)),
+                _ => None
+            })
+            .collect()
+    }
+
+    fn outputs(&self, automata: &Automata<Light>, mode: RenderMode) -> Result<(), ScriptError>
+    {
+        let step = automata.step();
+        for (schedule, action, line) in &self.actions
+        {
+            if !schedule.matches(step)
+            {
+                continue;
+            }
+            let error = |error: std::io::Error| ScriptError{line: *line, message: error.to_string()};
+            match action
+            {
+                Action::Set(..) => (),
+                Action::Print => render::show(automata.current(), mode),
+                Action::Save(path) => automata.save_checkpoint(with_step(path, step)).map_err(error)?,
+                Action::Heatmap(path) =>
+                    plots::heatmap(automata.current(), |cell| f64::from(cell.level()), self.colormap, with_step(path, step))
+                        .map_err(error)?
+            }
+        }
+        Ok(())
+    }
+
+    // Runs the whole timeline, the writes of `set` going through the
+    // automaton's injector, and returns the final automaton.
+    pub fn run(&self, mode: RenderMode) -> Result<Automata<Light>, ScriptError>
+    {
+        let mut automata = Automata::new(Grid::new(self.dims, self.fill));
+        for ((i, j), state) in Self::writes(&self.actions, 0)
+        {
+            // Checked against the dims when parsed.
+            *automata.get_mut((i, j)).unwrap() = state;
+        }
+        let actions = self.actions.clone();
+        automata.set_injector(move |step| Self::writes(&actions, step));
+
+        self.outputs(&automata, mode)?;
+        for _ in 0..self.steps
+        {
+            automata.evolve_rule(&self.rule, self.neighborhood);
+            self.outputs(&automata, mode)?;
+        }
+        Ok(automata)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::rules;
+
+    fn error(text: &str) -> ScriptError
+    {
+        match parse(text)
+        {
+            Ok(_) => panic!("{:?} parsed", text),
+            Err(error) => error
+        }
+    }
+
+    // scripts/light.txt is the light demo of the binary, run without
+    // options.
+    #[test]
+    fn the_light_script_is_the_demo()
+    {
+        let timeline = parse(include_str!("../scripts/light.txt")).unwrap();
+        assert_eq!(timeline.actions.len(), 2);
+        let scripted = timeline.run(RenderMode::Compact).unwrap();
+
+        let mut automata = Automata::new(Grid::new((30, 20), Light::Space(0)));
+        *automata.get_mut((10, 10)).unwrap() = Light::Source(10);
+        for _ in 0..10
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        *automata.get_mut((10, 10)).unwrap() = Light::Space(10);
+        for _ in 0..20
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        assert_eq!(scripted.step(), 30);
+        assert_eq!(scripted.current(), automata.current());
+    }
+
+    #[test]
+    fn schedules()
+    {
+        let timeline = parse("grid 5 3 Space(0)\nrule falloff\nevery 2 set 0 0 Source(3)\nat 3 set 4 2 Source(1)\nrun 4\n").unwrap();
+        assert_eq!(timeline.actions[0], (Schedule::Every(2), Action::Set((0, 0), Light::Source(3)), 3));
+        assert_eq!(Timeline::writes(&timeline.actions, 0), [((0, 0), Light::Source(3))]);
+        assert_eq!(Timeline::writes(&timeline.actions, 3), [((4, 2), Light::Source(1))]);
+        assert!(Timeline::writes(&timeline.actions, 1).is_empty());
+        assert_eq!(with_step("out/{step}.svg", 42), "out/00042.svg");
+    }
+
+    #[test]
+    fn errors_have_line_numbers()
+    {
+        let start = "grid 5 3 Space(0)\nrule falloff\n";
+        let cases = [
+            ("grid 5 3 Space(0)\n\n# comment\nrules falloff\n", 4, "unknown command 'rules'"),
+            ("grid 5 Space(0)\n", 1, "invalid height 'Space(0)'"),
+            ("grid 5 3 Lamp\n", 1, "invalid light 'Lamp'"),
+            ("grid 5 3 Space(0)\nrule nothing\n", 2, "nothing"),
+            ("grid 5 3 Space(0)\nrule falloff\n", 2, "no run command"),
+            ("run 3\n", 1, "no grid command")
+        ];
+        for (text, line, message) in &cases
+        {
+            let found = error(text);
+            assert_eq!(found.line, *line, "{:?}", text);
+            assert!(found.message.contains(message), "{:?}: {}", text, found);
+        }
+        let body = [
+            ("at 4 print\nrun 3\n", 3, "step 4 is after the end of the run (3)"),
+            ("run 3\nat 1 set 5 0 Source(1)\n", 4, "(5, 0)"),
+            ("every 0 print\nrun 3\n", 3, "the period must be positive"),
+            ("at 1 blink\nrun 3\n", 3, "unknown action 'blink'"),
+            ("at 1 print twice\nrun 3\n", 3, "unexpected 'twice'"),
+            ("at x print\nrun 3\n", 3, "invalid step 'x'")
+        ];
+        for (text, line, message) in &body
+        {
+            let found = error(&format!("{}{}", start, text));
+            assert_eq!(found.line, *line, "{:?}", text);
+            assert!(found.message.contains(message), "{:?}: {}", text, found);
+        }
+        assert_eq!(error("grid 5 3 Space(0)\nfoo\n").to_string(), "line 2: unknown command 'foo'");
+    }
+}
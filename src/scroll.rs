@@ -0,0 +1,299 @@
+// A fixed window on an unbounded world, for runs whose pattern travels for
+// ever: scroll moves the window by (dx, dy) cells, dropping what falls off
+// one side and filling the other side with fresh cells, and keeps count of
+// where the window is. Cell (i, j) of the grid is cell
+//
+//     (i + ox, j + oy)
+//
+// of the world, (ox, oy) being world_offset. A triangle points up or down
+// with the parity of i + j, so dx + dy must be even for cells to keep
+// their orientation: an even dx along a row, an odd one with an odd dy.
+//
+// Everything tied to cells moves with them: both buffers, ages, source
+// programs and cell rules, those that fall off being dropped. The
+// reversible history ends there, the cells dropped being gone, and the
+// coordinates an injector writes to stay those of the grid.
+
+use crate::{Automata, Grid};
+use crate::session::{Phase, StepHook};
+
+use std::fmt::{Debug, Display};
+
+// A scroll that would flip the triangles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OddScroll
+{
+    pub dx: isize,
+    pub dy: isize
+}
+
+// Moves the contents of the grid by (-dx, -dy): cell (i, j) gets the
+// state of (i + dx, j + dy), or `fill` past the border.
+pub(crate) fn shift<T: Copy + Debug>(grid: &mut Grid<T>, dx: isize, dy: isize, fill: T)
+{
+    let (w, h) = grid.dims;
+    let old = std::mem::replace(&mut grid.data, Vec::with_capacity(w*h));
+    let source = |i: usize, j: usize| {
+        let (x, y) = (i as isize + dx, j as isize + dy);
+        if x < 0 || y < 0 || x >= w as isize || y >= h as isize { None } else { Some(y as usize*w + x as usize) }
+    };
+    for j in 0..h
+    {
+        for i in 0..w
+        {
+            grid.data.push(source(i, j).map_or(fill, |index| old[index]));
+        }
+    }
+}
+
+// The coordinates after a shift, None for those falling off.
+fn shifted((w, h): (usize, usize), (i, j): (usize, usize), dx: isize, dy: isize) -> Option<(usize, usize)>
+{
+    let (x, y) = (i as isize - dx, j as isize - dy);
+    if x < 0 || y < 0 || x >= w as isize || y >= h as isize { None } else { Some((x as usize, y as usize)) }
+}
+
+impl<T: Clone + Display + Copy + Debug> Automata<T>
+{
+    pub fn scroll(&mut self, dx: isize, dy: isize, fill: T) -> Result<(), OddScroll>
+    {
+        if (dx + dy) % 2 != 0
+        {
+            return Err(OddScroll{dx, dy});
+        }
+        let dims = self.current.dims;
+        shift(&mut self.current, dx, dy, fill);
+        shift(&mut self.scratch, dx, dy, fill);
+        self.previous = None;
+        self.reversible = false;
+        self.scroll_ages(dx, dy);
+        self.programs = std::mem::take(&mut self.programs).into_iter()
+            .filter_map(|(coord, program)| Some((shifted(dims, coord, dx, dy)?, program)))
+            .collect();
+        self.cell_rules = std::mem::take(&mut self.cell_rules).into_iter()
+            .filter_map(|(coord, rule)| Some((shifted(dims, coord, dx, dy)?, rule)))
+            .collect();
+        self.hotspots = None;
+        self.invalidate_rows();
+        self.pending_row = None;
+        self.world_offset = (self.world_offset.0 + dx, self.world_offset.1 + dy);
+        Ok(())
+    }
+
+    // Where cell (0, 0) is in the world.
+    pub fn world_offset(&self) -> (isize, isize)
+    {
+        self.world_offset
+    }
+
+    pub fn world_coord(&self, (i, j): (usize, usize)) -> (isize, isize)
+    {
+        (i as isize + self.world_offset.0, j as isize + self.world_offset.1)
+    }
+
+    // The cell of the grid at a place of the world, None outside of the
+    // window.
+    pub fn local_coord(&self, (x, y): (isize, isize)) -> Option<(usize, usize)>
+    {
+        let (i, j) = (x - self.world_offset.0, y - self.world_offset.1);
+        let (w, h) = self.current.dims;
+        if i < 0 || j < 0 || i >= w as isize || j >= h as isize { None } else { Some((i as usize, j as usize)) }
+    }
+}
+
+// A camera keeping a cell found by `locate` (the source of a moving light,
+// the head of a glider...) at least `margin` cells from every side, by
+// scrolls of `jump` cells, made even. Attached to a session, it looks
+// before every step.
+pub struct FollowCamera<T, L>
+{
+    margin: usize,
+    jump: isize,
+    fill: T,
+    locate: L,
+    // World coordinates of the cell when last found.
+    tracked: Option<(isize, isize)>,
+    scrolls: u64
+}
+
+impl<T, L> FollowCamera<T, L>
+where
+    T: Clone + Display + Copy + Debug,
+    L: Fn(&Grid<T>) -> Option<(usize, usize)>
+{
+    pub fn new(margin: usize, jump: usize, fill: T, locate: L) -> Self
+    {
+        let jump = (jump + jump % 2).max(2) as isize;
+        Self{margin, jump, fill, locate, tracked: None, scrolls: 0}
+    }
+
+    // Scrolls the automaton if the cell is too near a side, horizontally
+    // and vertically by one jump at most each. Returns whether it did.
+    pub fn follow(&mut self, automata: &mut Automata<T>) -> bool
+    {
+        let coord = match (self.locate)(automata.current())
+        {
+            Some(coord) => coord,
+            None => return false
+        };
+        let (w, h) = automata.current().dims;
+        let axis = |at: usize, len: usize| {
+            if at + self.margin >= len { self.jump } else if at < self.margin { -self.jump } else { 0 }
+        };
+        let (dx, dy) = (axis(coord.0, w), axis(coord.1, h));
+        self.tracked = Some(automata.world_coord(coord));
+        if (dx, dy) == (0, 0)
+        {
+            return false;
+        }
+        automata.scroll(dx, dy, self.fill).expect("jumps are even");
+        self.scrolls += 1;
+        true
+    }
+
+    pub fn tracked(&self) -> Option<(isize, isize)>
+    {
+        self.tracked
+    }
+
+    pub fn scrolls(&self) -> u64
+    {
+        self.scrolls
+    }
+}
+
+impl<T, L> StepHook<T> for FollowCamera<T, L>
+where
+    T: Clone + Display + Copy + Debug,
+    L: Fn(&Grid<T>) -> Option<(usize, usize)>
+{
+    fn phase(&self) -> Phase
+    {
+        Phase::Inject
+    }
+
+    fn before_step(&mut self, automata: &mut Automata<T>)
+    {
+        self.follow(automata);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, CellState, Light, Rule};
+    use crate::events::EventMapper;
+    use crate::session::Session;
+
+    fn locate(grid: &Grid<Light>) -> Option<(usize, usize)>
+    {
+        grid.data.iter().position(|cell| cell.is_pinned()).map(|n| (n % grid.dims.0, n / grid.dims.0))
+    }
+
+    #[test]
+    fn contents_move_with_the_scroll()
+    {
+        let dims = (12, 6);
+        let grid = Grid::from_fn(dims, |(i, j)| Light::Space((i + 20*j) as u8));
+        for &(dx, dy) in &[(4, 0), (-2, 0), (1, 1), (-3, 5), (0, -2), (14, 0)]
+        {
+            let mut automata = Automata::new(grid.clone());
+            automata.scroll(dx, dy, Light::Space(255)).unwrap();
+            for j in 0..dims.1
+            {
+                for i in 0..dims.0
+                {
+                    let (x, y) = (i as isize + dx, j as isize + dy);
+                    let expected = if x < 0 || y < 0 { None } else { grid.get((x as usize, y as usize)).copied() }.unwrap_or(Light::Space(255));
+                    assert_eq!(automata.get((i, j)), Some(&expected), "{:?}, {:?}", (dx, dy), (i, j));
+                    assert_eq!(automata.scratch.get((i, j)), Some(&expected));
+                }
+            }
+            assert_eq!(automata.world_offset(), (dx, dy));
+        }
+        assert_eq!(Automata::new(grid.clone()).scroll(1, 0, Light::Space(0)), Err(OddScroll{dx: 1, dy: 0}));
+        assert_eq!(Automata::new(grid).scroll(2, -1, Light::Space(0)), Err(OddScroll{dx: 2, dy: -1}));
+    }
+
+    #[test]
+    fn programs_and_overrides_move_too()
+    {
+        let mut automata = Automata::new(Grid::new((10, 4), Light::Space(0)));
+        automata.add_source_program((6, 1), crate::SourceProgram::Constant(Light::Source(5)));
+        automata.add_source_program((1, 1), crate::SourceProgram::Constant(Light::Source(5)));
+        automata.set_cell_rule((7, 2), |ngh: Vec<Light>| ngh[0]);
+        automata.set_cell_rule((0, 0), |ngh: Vec<Light>| ngh[0]);
+        automata.scroll(4, 0, Light::Space(0)).unwrap();
+        assert_eq!(automata.cell_rules().keys().collect::<Vec<_>>(), [&(3, 2)]);
+        automata.evolve(rules::light_falloff);
+        assert_eq!(automata.get((2, 1)), Some(&Light::Source(5)));
+        assert_eq!(locate(automata.current()), Some((2, 1)));
+    }
+
+    // A light source moved right a cell a step for 150 steps under a
+    // camera: it must be where the world says all the way, its light
+    // behind it.
+    #[test]
+    fn the_camera_follows_a_travelling_source()
+    {
+        let mut automata = Automata::new(Grid::new((24, 8), Light::Space(0)));
+        let start = (3, 4);
+        *automata.get_mut(start).unwrap() = Light::Source(12);
+        let mut camera = FollowCamera::new(3, 7, Light::Space(0), locate);
+        for step in 1..=150isize
+        {
+            camera.follow(&mut automata);
+            let at = locate(automata.current()).unwrap();
+            let world = (start.0 as isize + step - 1, start.1 as isize);
+            assert_eq!(automata.world_coord(at), world, "step {}", step);
+            assert_eq!(camera.tracked(), Some(world));
+            assert_eq!(automata.local_coord(world), Some(at));
+            assert!(at.0 + 3 < 24);
+            *automata.get_mut(at).unwrap() = Light::Space(12);
+            let next = automata.local_coord((world.0 + 1, world.1)).unwrap();
+            *automata.get_mut(next).unwrap() = Light::Source(12);
+            automata.evolve(rules::light_falloff);
+        }
+        // By jumps of 8, 7 made even.
+        assert_eq!(automata.world_offset().0 % 8, 0);
+        assert_eq!(camera.scrolls() as isize, automata.world_offset().0 / 8);
+        assert!(camera.scrolls() >= 10);
+        assert_eq!(automata.world_offset().1, 0);
+        let behind = automata.local_coord((start.0 as isize + 149, 4)).unwrap();
+        assert_eq!(automata.get(behind).map(CellState::level), Some(11));
+        assert_eq!(automata.local_coord((0, 4)), None);
+    }
+
+    #[test]
+    fn cameras_look_before_the_steps_of_a_session()
+    {
+        let mut grid = Grid::new((16, 10), Light::Space(0));
+        *grid.get_mut((13, 4)).unwrap() = Light::Source(6);
+        let falloff: Box<dyn Rule<Light>> = Box::new(rules::light_falloff::<Light>);
+        let mut session = Session::new(Automata::new(grid), falloff);
+        session.attach(FollowCamera::new(4, 4, Light::Space(0), locate));
+        session.step();
+        assert_eq!(session.automata.world_offset(), (4, 0));
+        assert_eq!(locate(session.current()), Some((9, 4)));
+        assert_eq!(session.automata.world_coord((9, 4)), (13, 4));
+    }
+
+    #[test]
+    fn restarts_go_back_to_the_origin()
+    {
+        let mut automata = Automata::new(Grid::new((10, 4), Light::Space(0)));
+        automata.enable_events(EventMapper::levels(false), 100);
+        *automata.get_mut((5, 2)).unwrap() = Light::Source(4);
+        automata.evolve(rules::light_falloff);
+        automata.scroll(2, 0, Light::Space(0)).unwrap();
+        assert!(automata.events_waiting() > 0);
+        let start = Grid::new((10, 4), Light::Space(0));
+        automata.reset_from(&start).unwrap();
+        assert_eq!((automata.world_offset(), automata.events_waiting(), automata.events_dropped()), ((0, 0), 0, 0));
+        automata.scroll(-2, 0, Light::Space(0)).unwrap();
+        automata.reset_with(|_| Light::Space(0));
+        assert_eq!(automata.world_offset(), (0, 0));
+        assert_eq!(automata.world_coord((3, 1)), (3, 1));
+    }
+}
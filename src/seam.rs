@@ -0,0 +1,264 @@
+// Renderings of grids whose opposite edges are glued together: the cells
+// past a wrapped edge are drawn again as "ghosts", copied from the other
+// side, with a dashed seam between them and the grid, so that a pattern
+// crossing the edge reads in one piece. Seams are those of grids under
+// BoundaryCondition::Periodic, both axes wrapped, and of even dims (see
+// boundary.rs); other boundaries have none, and are drawn as they are.
+//
+// Ghost margins are kept even so that every cell still points the way it
+// does in the grid.
+
+use crate::Grid;
+use crate::boundary::BoundaryCondition;
+use crate::render::{Glyph, RenderMode};
+
+use std::fmt::{Debug, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Seams
+{
+    // Whether the edges are glued, the boundary being Periodic.
+    wrap: bool,
+    // Ghost columns or rows drawn past each wrapped edge.
+    pub ghosts: usize
+}
+
+// k modulo len, negative k included.
+fn wrapped_index(k: isize, len: usize) -> usize
+{
+    k.rem_euclid(len as isize) as usize
+}
+
+// The cell at (i, j) modulo the dims, odd axes included (unlike
+// Grid::get_signed_wrapped under Periodic), for grids with cells.
+fn wrapped_get<T: Copy + Debug>(grid: &Grid<T>, (i, j): (isize, isize)) -> &T
+{
+    grid.get((wrapped_index(i, grid.dims.0), wrapped_index(j, grid.dims.1))).unwrap()
+}
+
+impl Seams
+{
+    pub fn new<T>(boundary: &BoundaryCondition<T>, ghosts: usize) -> Self
+    {
+        Self{wrap: boundary.is_periodic(), ghosts}
+    }
+
+    // Ghost columns and rows on each side of the grid.
+    pub fn margins(&self) -> (usize, usize)
+    {
+        let even = if self.wrap { self.ghosts + self.ghosts % 2 } else { 0 };
+        (even, even)
+    }
+
+    pub fn ghosted_dims(&self, (w, h): (usize, usize)) -> (usize, usize)
+    {
+        let (mx, my) = self.margins();
+        (w + 2*mx, h + 2*my)
+    }
+
+    // Whether the cell of the ghosted grid is a copy rather than a cell of
+    // the grid itself.
+    pub fn is_ghost(&self, (w, h): (usize, usize), (i, j): (usize, usize)) -> bool
+    {
+        let (mx, my) = self.margins();
+        i < mx || i >= mx + w || j < my || j >= my + h
+    }
+
+    // The grid with its ghost margins around it.
+    pub fn ghost_grid<T: Copy + Debug>(&self, grid: &Grid<T>) -> Grid<T>
+    {
+        let (mx, my) = self.margins();
+        Grid::from_fn(self.ghosted_dims(grid.dims), |(i, j)| {
+            *wrapped_get(grid, (i as isize - mx as isize, j as isize - my as isize))
+        })
+    }
+
+    // Like RenderMode's renderings, with the ghosts and seams. The
+    // viewport is not given ghosts: on a wrapped grid it goes on past the
+    // edges instead, its coordinates taken modulo the dimensions.
+    pub fn render<T: Copy + Debug + Display + Glyph>(&self, grid: &Grid<T>, mode: RenderMode) -> String
+    {
+        let (mx, my) = self.margins();
+        match mode
+        {
+            RenderMode::Full => self.render_full(grid),
+            RenderMode::Compact =>
+            {
+                let (w, h) = self.ghosted_dims(grid.dims);
+                self.window(grid, (-(mx as isize), -(my as isize)), (w, h))
+            },
+            RenderMode::Viewport{origin, dims} => self.window(grid, (origin.0 as isize, origin.1 as isize), dims)
+        }
+    }
+
+    // Glyphs of the cells in `dims` from `origin`, clipped to the grid
+    // unless it is wrapped, with a `:` column or a dashed row where the
+    // window crosses an edge.
+    fn window<T: Copy + Debug + Glyph>(&self, grid: &Grid<T>, origin: (isize, isize), dims: (usize, usize)) -> String
+    {
+        let (w, h) = grid.dims;
+        let span = |start: isize, len: usize, wrap: bool, size: usize| -> Vec<isize> {
+            (start..start + len as isize)
+                .filter(|&k| wrap || (k >= 0 && k < size as isize))
+                .collect()
+        };
+        let columns = span(origin.0, dims.0, self.wrap, w);
+        let rows = span(origin.1, dims.1, self.wrap, h);
+        let crosses = |k: isize, ks: &[isize], size: usize| k != ks[0] && wrapped_index(k, size) == 0;
+
+        let mut out = String::new();
+        for &j in &rows
+        {
+            if self.wrap && crosses(j, &rows, h)
+            {
+                for (n, &i) in columns.iter().enumerate()
+                {
+                    if crosses(i, &columns, w)
+                    {
+                        out.push('+');
+                    }
+                    out.push(if n % 2 == 0 { '-' } else { ' ' });
+                }
+                out.push('\n');
+            }
+            for &i in &columns
+            {
+                if self.wrap && crosses(i, &columns, w)
+                {
+                    out.push(':');
+                }
+                out.push(wrapped_get(grid, (i, j)).glyph());
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    // Grid::render of the ghosted grid. The flat edges along a horizontal
+    // seam are dashed; the edges along a vertical seam zigzag, so the
+    // seam is marked by `:` in a ruler above and below the drawing.
+    fn render_full<T: Copy + Debug + Display>(&self, grid: &Grid<T>) -> String
+    {
+        let (w, h) = grid.dims;
+        let (mx, my) = self.margins();
+        let drawing = self.ghost_grid(grid).render();
+        let seam_lines = if self.wrap { vec![3*my, 3*(my + h)] } else { vec![] };
+        let mut lines: Vec<String> = drawing.lines()
+            .enumerate()
+            .map(|(n, line)| {
+                if !seam_lines.contains(&n)
+                {
+                    return line.to_string();
+                }
+                let mut dashes = 0;
+                line.chars()
+                    .map(|c| if c == '-' { dashes += 1; if dashes % 2 == 1 { '-' } else { ' ' } } else { c })
+                    .collect()
+            })
+            .collect();
+        if self.wrap
+        {
+            // Character 3x + 3 of a line is at x half edges; the seam runs
+            // between x and x + 1.
+            let width = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+            let ruler: String = (0..width)
+                .map(|c| if c == 3*mx + 4 || c == 3*(mx + w) + 4 { ':' } else { ' ' })
+                .collect::<String>()
+                .trim_end()
+                .to_string();
+            lines.insert(0, ruler.clone());
+            lines.push(ruler);
+        }
+        let mut out = lines.join("\n");
+        out.push('\n');
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // A bar of three cells across the right edge of an 8x4 grid: two
+    // cells on the right, one on the left.
+    fn straddling() -> Grid<bool>
+    {
+        Grid::from_fn((8, 4), |(i, j)| j == 1 && (i == 0 || i >= 6))
+    }
+
+    #[test]
+    fn ghosts_copy_the_other_side()
+    {
+        let seams = Seams::new(&BoundaryCondition::<bool>::Periodic, 1);
+        // One ghost column would flip the cells; margins stay even.
+        assert_eq!(seams.margins(), (2, 2));
+        assert_eq!(seams.ghosted_dims((8, 4)), (12, 8));
+        let grid = straddling();
+        let ghosted = seams.ghost_grid(&grid);
+        for j in 0..8
+        {
+            for i in 0..12
+            {
+                let copied = ((i as isize - 2).rem_euclid(8) as usize, (j as isize - 2).rem_euclid(4) as usize);
+                assert_eq!(ghosted.get((i, j)), grid.get(copied));
+            }
+        }
+        assert!(seams.is_ghost((8, 4), (1, 3)));
+        assert!(!seams.is_ghost((8, 4), (2, 5)));
+        assert!(seams.is_ghost((8, 4), (10, 3)));
+        assert!(seams.is_ghost((8, 4), (4, 6)));
+        // Other boundaries have no ghosts.
+        let open = Seams::new(&BoundaryCondition::Fixed(false), 2);
+        assert_eq!((open.margins(), open.ghost_grid(&grid)), ((0, 0), grid));
+    }
+
+    #[test]
+    fn patterns_across_the_seam_read_in_one_piece()
+    {
+        let grid = straddling();
+        let seams = Seams::new(&BoundaryCondition::<bool>::Periodic, 2);
+        let compact = seams.render(&grid, RenderMode::Compact);
+        // The bar reads "###" across both seams, its cells on the far side
+        // of each being ghosts, and again in the ghost rows below.
+        assert_eq!(compact, concat!(
+            "  :        :  \n",
+            "  :        :  \n",
+            "- +- - - - +- \n",
+            "  :        :  \n",
+            "##:#     ##:# \n",
+            "  :        :  \n",
+            "  :        :  \n",
+            "- +- - - - +- \n",
+            "  :        :  \n",
+            "##:#     ##:# \n"));
+    }
+
+    #[test]
+    fn viewports_go_on_past_wrapped_edges()
+    {
+        let grid = straddling();
+        let seams = Seams::new(&BoundaryCondition::<bool>::Periodic, 0);
+        let window = seams.render(&grid, RenderMode::Viewport{origin: (5, 0), dims: (5, 3)});
+        assert_eq!(window, "   :  \n ##:# \n   :  \n");
+        // On a grid that is not wrapped, the window stops at the edge.
+        let open = Seams::new(&BoundaryCondition::<bool>::Open, 0).render(&grid, RenderMode::Viewport{origin: (5, 0), dims: (5, 3)});
+        assert_eq!(open, "   \n ##\n   \n");
+    }
+
+    #[test]
+    fn full_renderings_mark_both_seams()
+    {
+        let grid = Grid::from_fn((4, 2), |(i, _)| i == 0);
+        let full = Seams::new(&BoundaryCondition::<bool>::Periodic, 2).render(&grid, RenderMode::Full);
+        let lines: Vec<_> = full.lines().collect();
+        // Rulers above and below mark the vertical seams...
+        assert_eq!(lines[0], "          :           :");
+        assert_eq!(lines.last(), lines.first());
+        // ...and the edges along the horizontal ones are dashed, those
+        // inside the grid or its ghosts being solid.
+        let dashed: Vec<_> = (0..lines.len()).filter(|&n| lines[n].contains("- -")).collect();
+        assert_eq!(dashed, vec![7, 13]);
+        assert_eq!(lines.len(), 2 + 3*(2 + 2 + 2) + 1);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::{Automata, Grid};
+
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternKind
+{
+    StillLife,
+    Oscillator(usize),
+    Transient
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoundPattern
+{
+    // Trimmed to the bounding box of its non-zero cells.
+    pub cells: Grid<u8>,
+    // Orientation of the first cell: 0 when it points up, 1 when down.
+    pub parity: usize,
+    pub kind: PatternKind
+}
+
+// Bounding box of the non-zero cells, as (origin, dims).
+fn bounds(grid: &Grid<u8>) -> Option<((usize, usize), (usize, usize))>
+{
+    let mut min = (usize::MAX, usize::MAX);
+    let mut max = (0, 0);
+    for j in 0..grid.dims.1
+    {
+        for i in 0..grid.dims.0
+        {
+            if *grid.get((i, j)).unwrap() != 0
+            {
+                min = (min.0.min(i), min.1.min(j));
+                max = (max.0.max(i), max.1.max(j));
+            }
+        }
+    }
+    if min.0 == usize::MAX
+    {
+        None
+    }
+    else
+    {
+        Some((min, (max.0 - min.0 + 1, max.1 - min.1 + 1)))
+    }
+}
+
+fn trim(grid: &Grid<u8>) -> Option<(Grid<u8>, usize)>
+{
+    let (origin, dims) = bounds(grid)?;
+    let cells = Grid::from_fn(dims, |(i, j)| *grid.get((origin.0 + i, origin.1 + j)).unwrap());
+    Some((cells, (origin.0 + origin.1) % 2))
+}
+
+// Places a trimmed pattern in a grid whose first cell points up, with extra
+// empty columns and rows so that the grid dims reach at least `dims`.
+fn embed(cells: &Grid<u8>, parity: usize, dims: (usize, usize)) -> Grid<u8>
+{
+    let dims = (dims.0.max(cells.dims.0 + parity), dims.1.max(cells.dims.1));
+    Grid::from_fn(dims, |(i, j)| {
+        if i < parity
+        {
+            return 0;
+        }
+        cells.get((i - parity, j)).cloned().unwrap_or(0)
+    })
+}
+
+// The smallest representation of the pattern among its images under the
+// mirror, the flip and the half turn. Every transform is applied on a grid
+// padded just enough for it to preserve triangle orientations, and the
+// result is trimmed again so padding does not matter.
+fn canonical(cells: &Grid<u8>, parity: usize) -> (usize, (usize, usize), Vec<u8>)
+{
+    let base = embed(cells, parity, (0, 0));
+    let (w, h) = base.dims;
+    let odd_w = w | 1;
+    let even_h = h + h % 2;
+    let variants = [
+        Some(base.clone()),
+        embed(cells, parity, (odd_w, h)).mirror_x(),
+        embed(cells, parity, (w, even_h)).flip_y(),
+        embed(cells, parity, (odd_w, even_h)).mirror_x().and_then(|g| g.flip_y()),
+        embed(cells, parity, (if (w + h) % 2 == 0 {w + 1} else {w}, h)).rotate_180()
+    ];
+    variants.iter()
+        .flatten()
+        .filter_map(trim)
+        .map(|(cells, parity)| (parity, cells.dims, cells.data))
+        .min()
+        .unwrap()
+}
+
+fn classify<F>(rule: &F, start: &Grid<u8>, max_period: usize) -> PatternKind
+where
+    F: Fn(Vec<u8>) -> u8
+{
+    let mut automata = Automata::new(start.clone());
+    for period in 1..=max_period
+    {
+        automata.evolve(rule);
+        if automata.current() == start
+        {
+            return if period == 1 {PatternKind::StillLife} else {PatternKind::Oscillator(period)};
+        }
+    }
+    PatternKind::Transient
+}
+
+pub fn enumerate_patterns<F>(rule: F, bbox_dims: (usize, usize), max_states: u8, max_period: usize) -> Vec<FoundPattern>
+where
+    F: Fn(Vec<u8>) -> u8
+{
+    enumerate_patterns_with(rule, bbox_dims, max_states, max_period, |_, _| {}, |_| false)
+}
+
+// Tries every assignment of states 0..max_states to a bbox_dims box, with 0
+// as the empty state. Each candidate is run alone in an empty grid padded by
+// more than max_period cells, so that nothing it emits can come back, and
+// classified by whether the whole grid returns to its initial state.
+// Patterns equal up to translation, mirror, flip or half turn are reported
+// once. `progress` receives (candidates done, total) and the search stops
+// as soon as `stop` returns true for a newly found pattern.
+//
+// Panics if the number of candidates, max_states^(w*h), overflows a usize.
+pub fn enumerate_patterns_with<F, P, S>(rule: F, bbox_dims: (usize, usize), max_states: u8, max_period: usize, mut progress: P, mut stop: S) -> Vec<FoundPattern>
+where
+    F: Fn(Vec<u8>) -> u8,
+    P: FnMut(usize, usize),
+    S: FnMut(&FoundPattern) -> bool
+{
+    let cells = bbox_dims.0 * bbox_dims.1;
+    let total = (max_states as usize).checked_pow(cells as u32)
+        .expect("search space too large");
+    let pad = (max_period + 2) & !1;
+    let dims = (bbox_dims.0 + 2*pad, bbox_dims.1 + 2*pad);
+
+    let mut seen = HashSet::new();
+    let mut found = vec![];
+    for index in 1..total
+    {
+        progress(index, total);
+        let mut digits = index;
+        let mut candidate = Grid::new(bbox_dims, 0u8);
+        for cell in candidate.data.iter_mut()
+        {
+            *cell = (digits % max_states as usize) as u8;
+            digits /= max_states as usize;
+        }
+        let (cells, parity) = trim(&candidate).unwrap();
+        if !seen.insert(canonical(&cells, parity))
+        {
+            continue;
+        }
+
+        let start = Grid::from_fn(dims, |(i, j)| {
+            if i < pad || j < pad
+            {
+                return 0;
+            }
+            candidate.get((i - pad, j - pad)).cloned().unwrap_or(0)
+        });
+        let pattern = FoundPattern{cells, parity, kind: classify(&rule, &start, max_period)};
+        let done = stop(&pattern);
+        found.push(pattern);
+        if done
+        {
+            break;
+        }
+    }
+    progress(total, total);
+    found
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // Live cells (1) survive next to another live cell, and nothing is
+    // born: the still lifes are the connected clusters of two cells or
+    // more.
+    fn clusters(ngh: Vec<u8>) -> u8
+    {
+        u8::from(ngh[0] == 1 && ngh[1..].contains(&1))
+    }
+
+    fn still_lifes(found: &[FoundPattern]) -> Vec<(Vec<u8>, (usize, usize), usize)>
+    {
+        found.iter()
+            .filter(|pattern| pattern.kind == PatternKind::StillLife)
+            .map(|pattern| (pattern.cells.data.clone(), pattern.cells.dims, pattern.parity))
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_still_lifes_of_a_small_box()
+    {
+        let found = enumerate_patterns(clusters, (2, 2), 2, 4);
+        // The pairs across a slanted and the horizontal edge, the path of
+        // three (the other one being its half turn) and the whole box.
+        assert_eq!(still_lifes(&found), [(vec![1, 1], (2, 1), 0), (vec![1, 1], (1, 2), 0), (vec![1, 1, 1, 0], (2, 2), 0), (vec![1, 1, 1, 1], (2, 2), 0)]);
+        // A lone cell dies, whichever way it points.
+        assert_eq!(found.iter().filter(|pattern| pattern.cells.data == [1]).count(), 1);
+        assert_eq!(found[0].kind, PatternKind::Transient);
+    }
+
+    // A cell that blinks on its own, period 2, found in both of its
+    // phases.
+    #[test]
+    fn finds_oscillators()
+    {
+        let blink = |ngh: Vec<u8>| match ngh[0] { 1 => 2, 2 => 1, cell => cell };
+        let found = enumerate_patterns(blink, (1, 1), 3, 4);
+        let kinds: Vec<(Vec<u8>, PatternKind)> = found.iter().map(|pattern| (pattern.cells.data.clone(), pattern.kind)).collect();
+        assert_eq!(kinds, [(vec![1], PatternKind::Oscillator(2)), (vec![2], PatternKind::Oscillator(2))]);
+    }
+
+    #[test]
+    fn progress_and_early_exit()
+    {
+        let mut calls = vec![];
+        let found = enumerate_patterns_with(clusters, (2, 2), 2, 4, |done, total| calls.push((done, total)),
+                                            |pattern| pattern.kind == PatternKind::StillLife);
+        assert_eq!(found.len(), 2);
+        assert_eq!(calls.first(), Some(&(1, 16)));
+        assert_eq!(calls.last(), Some(&(16, 16)));
+    }
+}
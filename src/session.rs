@@ -0,0 +1,338 @@
+// A run put together from its parts: the automaton, the rule it evolves
+// with and attachments called around every step. Session::step calls
+//
+//  1. before_step of the attachments, so that injectors write into the
+//     generation about to be evolved,
+//  2. the step itself, with the automaton's own sources and injector,
+//  3. after_step of the attachments: observers, then loggers, then
+//     renderers.
+//
+// Attachments are called in the order of their phase, and of attachment
+// within a phase. The handle attach returns removes them again, from the
+// next step on.
+
+use crate::{Automata, Grid, Rule};
+use crate::analysis::ActivityTracker;
+use crate::neighborhood::NeighborhoodKind;
+use crate::run::{self, LoopOptions};
+use crate::stats::StatsLogger;
+
+use std::cell::RefCell;
+use std::fmt::{Debug, Display};
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Phase
+{
+    Inject,
+    Observe,
+    Log,
+    Render
+}
+
+pub trait StepHook<T>
+{
+    fn phase(&self) -> Phase
+    {
+        Phase::Observe
+    }
+
+    fn before_step(&mut self, _automata: &mut Automata<T>)
+    {
+    }
+
+    fn after_step(&mut self, _automata: &Automata<T>)
+    {
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HookHandle(u64);
+
+type Attachment<T> = (HookHandle, Box<dyn StepHook<T>>);
+
+pub struct Session<T>
+{
+    pub automata: Automata<T>,
+    pub rule: Box<dyn Rule<T>>,
+    pub neighborhood: NeighborhoodKind,
+    // Sorted by phase.
+    hooks: Vec<Attachment<T>>,
+    next_handle: u64
+}
+
+impl<T: Clone + Display + Copy + Debug> Session<T>
+{
+    pub fn new(automata: Automata<T>, rule: Box<dyn Rule<T>>) -> Self
+    {
+        Self{automata, rule, neighborhood: NeighborhoodKind::Edge, hooks: vec![], next_handle: 0}
+    }
+
+    pub fn attach<H: StepHook<T> + 'static>(&mut self, hook: H) -> HookHandle
+    {
+        let handle = HookHandle(self.next_handle);
+        self.next_handle += 1;
+        let phase = hook.phase();
+        let at = self.hooks.iter().position(|(_, other)| other.phase() > phase).unwrap_or(self.hooks.len());
+        self.hooks.insert(at, (handle, Box::new(hook)));
+        handle
+    }
+
+    // The attachment, or None when it was already removed.
+    pub fn detach(&mut self, handle: HookHandle) -> Option<Box<dyn StepHook<T>>>
+    {
+        let index = self.hooks.iter().position(|(other, _)| *other == handle)?;
+        Some(self.hooks.remove(index).1)
+    }
+
+    pub fn is_attached(&self, handle: HookHandle) -> bool
+    {
+        self.hooks.iter().any(|(other, _)| *other == handle)
+    }
+
+    pub fn attachments(&self) -> usize
+    {
+        self.hooks.len()
+    }
+
+    // The next step uses the new rule; the old one is returned.
+    pub fn set_rule(&mut self, rule: Box<dyn Rule<T>>) -> Box<dyn Rule<T>>
+    {
+        self.automata.invalidate_rows();
+        std::mem::replace(&mut self.rule, rule)
+    }
+
+    pub fn current(&self) -> &Grid<T>
+    {
+        self.automata.current()
+    }
+
+    pub fn step(&mut self)
+    {
+        step_with(&mut self.automata, &self.rule, self.neighborhood, &mut self.hooks);
+    }
+
+    pub fn run(&mut self, steps: u64)
+    {
+        for _ in 0..steps
+        {
+            self.step();
+        }
+    }
+
+    // run::run_loop_with over the session's steps.
+    pub fn run_loop<F: FnMut(&Grid<T>)>(&mut self, frames: usize, render: F, options: &LoopOptions)
+    {
+        let (rule, kind, hooks) = (&self.rule, self.neighborhood, &mut self.hooks);
+        run::run_loop_with(&mut self.automata, |automata| step_with(automata, rule, kind, hooks), frames, render, options);
+    }
+}
+
+fn step_with<T, R>(automata: &mut Automata<T>, rule: &R, kind: NeighborhoodKind, hooks: &mut [Attachment<T>])
+where
+    T: Clone + Display + Copy + Debug,
+    R: Rule<T>
+{
+    for (_, hook) in hooks.iter_mut()
+    {
+        hook.before_step(automata);
+    }
+    automata.evolve_rule(rule, kind);
+    for (_, hook) in hooks.iter_mut()
+    {
+        hook.after_step(automata);
+    }
+}
+
+struct Injector<F>(F);
+
+impl<T, F: FnMut(&mut Automata<T>)> StepHook<T> for Injector<F>
+{
+    fn phase(&self) -> Phase
+    {
+        Phase::Inject
+    }
+
+    fn before_step(&mut self, automata: &mut Automata<T>)
+    {
+        (self.0)(automata)
+    }
+}
+
+struct After<F>(Phase, F);
+
+impl<T, F: FnMut(&Automata<T>)> StepHook<T> for After<F>
+{
+    fn phase(&self) -> Phase
+    {
+        self.0
+    }
+
+    fn after_step(&mut self, automata: &Automata<T>)
+    {
+        (self.1)(automata)
+    }
+}
+
+// A shared attachment, for loggers and trackers read while the session
+// runs or after it.
+impl<T, H: StepHook<T>> StepHook<T> for Rc<RefCell<H>>
+{
+    fn phase(&self) -> Phase
+    {
+        self.borrow().phase()
+    }
+
+    fn before_step(&mut self, automata: &mut Automata<T>)
+    {
+        self.borrow_mut().before_step(automata)
+    }
+
+    fn after_step(&mut self, automata: &Automata<T>)
+    {
+        self.borrow_mut().after_step(automata)
+    }
+}
+
+// Attachments made of a closure.
+pub fn injector<T, F: FnMut(&mut Automata<T>)>(f: F) -> impl StepHook<T>
+{
+    Injector(f)
+}
+
+pub fn observer<T, F: FnMut(&Automata<T>)>(f: F) -> impl StepHook<T>
+{
+    After(Phase::Observe, f)
+}
+
+pub fn renderer<T: Clone + Display + Copy + Debug, F: FnMut(&Grid<T>)>(mut f: F) -> impl StepHook<T>
+{
+    After(Phase::Render, move |automata: &Automata<T>| f(automata.current()))
+}
+
+impl<T: Clone + Display + Copy + Debug> StepHook<T> for StatsLogger<T>
+{
+    fn phase(&self) -> Phase
+    {
+        Phase::Log
+    }
+
+    fn after_step(&mut self, automata: &Automata<T>)
+    {
+        self.observe(automata);
+    }
+}
+
+impl<T: Copy + Debug + Display + PartialEq> StepHook<T> for ActivityTracker
+{
+    fn after_step(&mut self, automata: &Automata<T>)
+    {
+        self.observe(automata);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::boundary::BoundaryCondition;
+
+    type Log = Rc<RefCell<Vec<String>>>;
+
+    fn counting() -> Session<u8>
+    {
+        Session::new(Automata::new(Grid::new((5, 3), 0u8)), Box::new(|ngh: Vec<u8>| ngh[0].wrapping_add(1)))
+    }
+
+    fn after(log: &Log, phase: Phase, name: &'static str) -> impl StepHook<u8>
+    {
+        let log = log.clone();
+        After(phase, move |automata: &Automata<u8>| log.borrow_mut().push(format!("{} {}", name, automata.current().get((0, 0)).unwrap())))
+    }
+
+    #[test]
+    fn hooks_run_in_phase_order()
+    {
+        let log: Log = Rc::default();
+        let mut session = counting();
+        // Attached out of order on purpose.
+        session.attach(after(&log, Phase::Render, "render"));
+        session.attach(after(&log, Phase::Log, "log"));
+        session.attach(observer({
+            let log = log.clone();
+            move |automata: &Automata<u8>| log.borrow_mut().push(format!("observe {}", automata.step()))
+        }));
+        session.attach(after(&log, Phase::Render, "render again"));
+        session.attach(injector({
+            let log = log.clone();
+            move |automata: &mut Automata<u8>| {
+                log.borrow_mut().push(format!("inject {}", automata.step()));
+                *automata.get_mut((0, 0)).unwrap() = 100;
+            }
+        }));
+        session.attach(after(&log, Phase::Observe, "observe again"));
+        session.step();
+        // The injected value was evolved, then seen by every later phase.
+        assert_eq!(*log.borrow(), ["inject 0", "observe 1", "observe again 101", "log 101", "render 101", "render again 101"]);
+        assert_eq!(session.current().get((1, 0)), Some(&1));
+        assert_eq!(session.attachments(), 6);
+    }
+
+    #[test]
+    fn detached_hooks_stop_their_effects()
+    {
+        let log: Log = Rc::default();
+        let mut session = counting();
+        let inject = session.attach(injector(|automata: &mut Automata<u8>| *automata.get_mut((0, 0)).unwrap() = 100));
+        let watch = session.attach(after(&log, Phase::Observe, "seen"));
+        session.run(2);
+        assert!(session.detach(inject).is_some());
+        assert!(!session.is_attached(inject) && session.is_attached(watch));
+        session.run(2);
+        assert!(session.detach(watch).is_some());
+        session.run(2);
+        assert_eq!(*log.borrow(), ["seen 101", "seen 101", "seen 102", "seen 103"]);
+        assert_eq!(session.current().get((0, 0)), Some(&105));
+        assert!(session.detach(watch).is_none());
+        assert_eq!(session.attachments(), 0);
+        // Handles are not reused.
+        assert_ne!(session.attach(after(&log, Phase::Log, "new")), inject);
+    }
+
+    #[test]
+    fn shared_loggers_and_rules()
+    {
+        let logger = Rc::new(RefCell::new(StatsLogger::new()));
+        let mut session = counting();
+        session.attach(logger.clone());
+        session.run(3);
+        assert_eq!(logger.borrow().rows().iter().map(|(step, _)| *step).collect::<Vec<_>>(), [1, 2, 3]);
+
+        session.set_rule(Box::new(|ngh: Vec<u8>| ngh[0]));
+        session.run(2);
+        assert_eq!(session.current(), &Grid::new((5, 3), 3u8));
+        assert_eq!(session.automata.step(), 5);
+
+        let frames: Log = Rc::default();
+        let seen = frames.clone();
+        session.attach(renderer(move |grid: &Grid<u8>| seen.borrow_mut().push(grid.get((0, 0)).unwrap().to_string())));
+        session.run_loop(2, |_| (), &LoopOptions::default());
+        assert_eq!(frames.borrow().len(), 2);
+        assert_eq!(logger.borrow().rows().len(), 7);
+    }
+
+    #[test]
+    fn steps_follow_the_boundary()
+    {
+        // Every cell becomes the size of its neighborhood, itself included.
+        let mut session = Session::new(Automata::new(Grid::new((6, 4), 0u8)), Box::new(|ngh: Vec<u8>| ngh.len() as u8));
+        session.step();
+        assert_eq!(session.current().get((0, 0)), Some(&3));
+        session.automata.set_boundary(BoundaryCondition::Fixed(0)).unwrap();
+        session.step();
+        assert_eq!(session.current(), &Grid::new((6, 4), 4u8));
+        session.neighborhood = NeighborhoodKind::Vertex;
+        session.step();
+        assert_eq!(session.current(), &Grid::new((6, 4), 13u8));
+    }
+}
@@ -0,0 +1,392 @@
+// An automaton that owns its rule, so that every step uses the same one
+// until it is explicitly replaced.
+
+use crate::{cell_rules, Automata, Grid, Light, Rule};
+use crate::boundary::BoundaryCondition;
+use crate::checkpoint::SnapshotError;
+use crate::codec::CellCodec;
+use crate::error::GridError;
+use crate::neighborhood::NeighborhoodKind;
+use crate::run::{self, LoopOptions};
+use crate::registry::RuleRegistry;
+use crate::sweep::RuleConfig;
+
+use std::fmt::{Debug, Display};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// A plain function rule with a name, such as the built-in light rules.
+#[derive(Debug, Clone)]
+pub struct NamedRule<T>
+{
+    pub name: String,
+    pub rule: fn(Vec<T>) -> T
+}
+
+impl<T> Rule<T> for NamedRule<T>
+{
+    fn apply(&self, ngh: Vec<T>) -> T
+    {
+        (self.rule)(ngh)
+    }
+
+    fn name(&self) -> Option<&str>
+    {
+        Some(&self.name)
+    }
+
+    // A plain function of the neighborhood.
+    fn is_deterministic(&self) -> bool
+    {
+        true
+    }
+}
+
+impl<T> Rule<T> for Box<dyn Rule<T>>
+{
+    fn apply(&self, ngh: Vec<T>) -> T
+    {
+        (**self).apply(ngh)
+    }
+
+    fn name(&self) -> Option<&str>
+    {
+        (**self).name()
+    }
+
+    fn config(&self) -> Option<&RuleConfig>
+    {
+        (**self).config()
+    }
+
+    fn is_deterministic(&self) -> bool
+    {
+        (**self).is_deterministic()
+    }
+}
+
+pub struct Simulation<T, R>
+{
+    automata: Automata<T>,
+    rule: R,
+    neighborhood: NeighborhoodKind
+}
+
+fn rule_path(path: &Path) -> PathBuf
+{
+    let mut name = path.as_os_str().to_owned();
+    name.push(".rule");
+    PathBuf::from(name)
+}
+
+impl<T, R> Simulation<T, R>
+where
+    T: Clone + Display + Copy + Debug,
+    R: Rule<T>
+{
+    pub fn new(grid: Grid<T>, rule: R) -> Self
+    {
+        Self::from_automata(Automata::new(grid), rule)
+    }
+
+    pub fn from_automata(automata: Automata<T>, rule: R) -> Self
+    {
+        Self{automata, rule, neighborhood: NeighborhoodKind::Edge}
+    }
+
+    pub fn evolve(&mut self)
+    {
+        let rule = &self.rule;
+        self.automata.evolve_rule(rule, self.neighborhood);
+    }
+
+    pub fn run(&mut self, steps: u64)
+    {
+        for _ in 0..steps
+        {
+            self.evolve();
+        }
+    }
+
+    // The next step uses the new rule; the old one is returned. Rows are
+    // no longer skipped if the new rule is not deterministic.
+    pub fn set_rule(&mut self, rule: R) -> R
+    {
+        self.automata.invalidate_rows();
+        if !rule.is_deterministic()
+        {
+            self.automata.disable_row_skipping();
+        }
+        std::mem::replace(&mut self.rule, rule)
+    }
+
+    pub fn rule(&self) -> &R
+    {
+        &self.rule
+    }
+
+    // The rule is still given a Vec, the cell first and then its
+    // neighbors for `kind`.
+    pub fn set_neighborhood(&mut self, kind: NeighborhoodKind)
+    {
+        self.neighborhood = kind;
+    }
+
+    pub fn neighborhood(&self) -> NeighborhoodKind
+    {
+        self.neighborhood
+    }
+
+    // See Automata::set_boundary; the neighbors of every kind follow it.
+    pub fn set_boundary(&mut self, boundary: BoundaryCondition<T>) -> Result<(), GridError>
+    {
+        self.automata.set_boundary(boundary)
+    }
+
+    pub fn automata(&self) -> &Automata<T>
+    {
+        &self.automata
+    }
+
+    // For edits, source programs and injectors.
+    pub fn automata_mut(&mut self) -> &mut Automata<T>
+    {
+        &mut self.automata
+    }
+
+    pub fn current(&self) -> &Grid<T>
+    {
+        self.automata.current()
+    }
+
+    pub fn step(&self) -> u64
+    {
+        self.automata.step()
+    }
+
+    pub fn run_loop<F: FnMut(&Grid<T>)>(&mut self, frames: usize, render: F, options: &LoopOptions)
+    {
+        let (rule, kind) = (&self.rule, self.neighborhood);
+        run::run_loop_with(&mut self.automata, |automata| automata.evolve_rule(rule, kind), frames, render, options);
+    }
+}
+
+impl<T, R> Simulation<T, R>
+where
+    T: Clone + Display + Copy + Debug + PartialEq,
+    R: Rule<T>
+{
+    // Lets evolve skip the rows that cannot change while the rule is
+    // deterministic, see rows.rs; whether it does.
+    pub fn skip_quiet_rows(&mut self) -> bool
+    {
+        if self.rule.is_deterministic()
+        {
+            self.automata.enable_row_skipping();
+        }
+        self.rule.is_deterministic()
+    }
+}
+
+impl<T, R> Simulation<T, R>
+where
+    T: Clone + Display + Copy + Debug + PartialEq + CellCodec,
+    R: Rule<T>
+{
+    // Saves the generation and the step, and the rule, when it has a name,
+    // in `<path>.rule`: the name and the parameters it was made with, as
+    // registry::rule_line writes them. A stale `.rule` is removed.
+    pub fn save_checkpoint<P: AsRef<Path>>(&self, path: P) -> io::Result<()>
+    {
+        let path = path.as_ref();
+        self.automata.save_checkpoint(path)?;
+        match cell_rules::saved_rule(&self.rule)
+        {
+            Some(line) => fs::write(rule_path(path), format!("{}\n", line)),
+            None => match fs::remove_file(rule_path(path))
+            {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+                _ => Ok(())
+            }
+        }
+    }
+
+    pub fn load_checkpoint<P: AsRef<Path>>(path: P, rule: R) -> Result<Self, SnapshotError>
+    {
+        Ok(Self::from_automata(Automata::load_checkpoint(path)?, rule))
+    }
+}
+
+// The rule saved with a checkpoint, if it had a name, as written by
+// registry::rule_line.
+pub fn saved_rule_line<P: AsRef<Path>>(path: P) -> io::Result<Option<String>>
+{
+    match fs::read_to_string(rule_path(path.as_ref()))
+    {
+        Ok(line) => Ok(Some(line.trim().to_string())),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error)
+    }
+}
+
+impl Simulation<Light, Box<dyn Rule<Light>>>
+{
+    // Restores a light simulation together with its rule and cell rules,
+    // made again by the global registry with the parameters they were
+    // saved with, or None when no rule was saved with the checkpoint. A
+    // saved rule the registry cannot make is an error.
+    pub fn load_named<P: AsRef<Path>>(path: P) -> Result<Option<Self>, SnapshotError>
+    {
+        let path = path.as_ref();
+        let line = match saved_rule_line(path)?
+        {
+            Some(line) => line,
+            None => return Ok(None)
+        };
+        let registry = RuleRegistry::<Light>::global();
+        let rule = registry.instantiate_line(&line).map_err(|reason| SnapshotError::BadRule{rule: line, reason})?;
+        let mut simulation = Self::load_checkpoint(path, rule)?;
+        simulation.automata.restore_cell_rules(path, &registry)?;
+        Ok(Some(simulation))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, validate, CellState};
+
+    fn lamp() -> Grid<Light>
+    {
+        validate::lamp((15, 9), (7, 4), 12)
+    }
+
+    fn temp_path(name: &str) -> PathBuf
+    {
+        std::env::temp_dir().join(format!("triangle-automata-{}-{}.tria", std::process::id(), name))
+    }
+
+    fn remove(path: &Path)
+    {
+        for extension in ["", ".rule", ".ages"]
+        {
+            let mut name = path.as_os_str().to_owned();
+            name.push(extension);
+            let _ = fs::remove_file(name);
+        }
+    }
+
+    #[test]
+    fn swapped_rules_apply_from_the_next_step()
+    {
+        let falloff: Box<dyn Rule<Light>> = Box::new(rules::light_falloff::<Light>);
+        let mut simulation = Simulation::new(lamp(), falloff);
+        simulation.run(3);
+        let mut unswapped = Automata::new(lamp());
+        for _ in 0..3
+        {
+            unswapped.evolve(rules::light_falloff);
+        }
+        assert_eq!(simulation.current(), unswapped.current());
+
+        simulation.set_rule(Box::new(rules::light_decay::<Light>(3)));
+        let mut swapped = Automata::new(unswapped.current().clone());
+        swapped.evolve(rules::light_decay(3));
+        unswapped.evolve(rules::light_falloff);
+        simulation.evolve();
+        assert_eq!(simulation.step(), 4);
+        assert_eq!(simulation.current(), swapped.current());
+        assert_ne!(simulation.current(), unswapped.current());
+    }
+
+    #[test]
+    fn checkpoints_keep_the_rule_and_its_parameters()
+    {
+        let path = temp_path("rule-config");
+        let decay = RuleRegistry::<Light>::global().instantiate("decay", &RuleConfig::new(7).with("amount", 3.0)).unwrap();
+        let mut simulation = Simulation::new(lamp(), decay);
+        let sensor = RuleRegistry::<Light>::global().instantiate("clamped", &RuleConfig::new(0).with("max", 2.0)).unwrap();
+        simulation.automata_mut().set_cell_rule((6, 4), sensor);
+        simulation.run(2);
+        simulation.save_checkpoint(&path).unwrap();
+        assert_eq!(saved_rule_line(&path).unwrap().as_deref(), Some("decay amount=3 seed=7"));
+
+        let mut loaded = Simulation::load_named(&path).unwrap().unwrap();
+        assert_eq!(loaded.rule().name(), Some("decay"));
+        assert_eq!(loaded.rule().config(), Some(&RuleConfig::new(7).with("amount", 3.0)));
+        let cell = &loaded.automata().cell_rules()[&(6, 4)];
+        assert_eq!(cell.config(), Some(&RuleConfig::new(0).with("max", 2.0)));
+        loaded.run(3);
+        simulation.run(3);
+        assert_eq!(loaded.current(), simulation.current());
+        remove(&path);
+    }
+
+    #[test]
+    fn rules_that_cannot_be_restored_are_errors()
+    {
+        let path = temp_path("bad-rule");
+        let unnamed: Box<dyn Rule<Light>> = Box::new(rules::light_falloff::<Light>);
+        Simulation::new(lamp(), unnamed).save_checkpoint(&path).unwrap();
+        assert!(Simulation::load_named(&path).unwrap().is_none());
+
+        fs::write(rule_path(&path), "sunlight\n").unwrap();
+        match Simulation::load_named(&path)
+        {
+            Err(SnapshotError::BadRule{rule, ..}) => assert_eq!(rule, "sunlight"),
+            other => panic!("expected a bad rule, got {:?}", other.map(|_| ()))
+        }
+        fs::write(rule_path(&path), "decay amount=300 seed=0\n").unwrap();
+        assert!(matches!(Simulation::load_named(&path), Err(SnapshotError::BadRule{..})));
+        // Older checkpoints saved the name alone.
+        fs::write(rule_path(&path), "decay\n").unwrap();
+        assert_eq!(Simulation::load_named(&path).unwrap().unwrap().rule().config(), Some(&RuleConfig::new(0).with("amount", 2.0)));
+        remove(&path);
+    }
+
+    #[test]
+    fn settled_rows_see_the_new_rule()
+    {
+        let falloff = RuleRegistry::<Light>::global().instantiate("falloff", &RuleConfig::new(0)).unwrap();
+        let mut simulation = Simulation::new(lamp(), falloff);
+        assert!(simulation.skip_quiet_rows());
+        simulation.run(30);
+        assert!(simulation.automata().rows_skipped() > 0);
+        simulation.set_rule(RuleRegistry::<Light>::global().instantiate("decay", &RuleConfig::new(0).with("amount", 3.0)).unwrap());
+        assert!(simulation.automata().rows_skipped() > 0);
+        let mut full = Automata::new(simulation.current().clone());
+        full.evolve(rules::light_decay(3));
+        simulation.evolve();
+        assert_eq!(simulation.current(), full.current());
+    }
+
+    #[test]
+    fn boundaries_hold_for_every_kind()
+    {
+        let start = Grid::from_fn((16, 10), |coord| if coord == (1, 4) {Light::Source(12)} else {Light::Space(0)});
+        for &kind in &[NeighborhoodKind::Edge, NeighborhoodKind::Vertex, NeighborhoodKind::Radius(2)]
+        {
+            let falloff: Box<dyn Rule<Light>> = Box::new(rules::light_falloff::<Light>);
+            let mut simulation = Simulation::new(start.clone(), falloff);
+            simulation.set_neighborhood(kind);
+            simulation.set_boundary(BoundaryCondition::Periodic).unwrap();
+            simulation.run(4);
+            let mut automata = Automata::new(start.clone());
+            automata.set_boundary(BoundaryCondition::Periodic).unwrap();
+            for _ in 0..4
+            {
+                automata.evolve_rule(&rules::light_falloff::<Light>, kind);
+            }
+            assert_eq!(simulation.current(), automata.current(), "{:?}", kind);
+            // The light went on across the left edge.
+            assert!(simulation.current().get((15, 4)).unwrap().level() > 0, "{:?}", kind);
+        }
+        let falloff: Box<dyn Rule<Light>> = Box::new(rules::light_falloff::<Light>);
+        let mut simulation = Simulation::new(lamp(), falloff);
+        assert_eq!(simulation.set_boundary(BoundaryCondition::Periodic), Err(GridError::OddPeriodic((15, 9))));
+        assert_eq!(simulation.set_boundary(BoundaryCondition::Reflective), Ok(()));
+        assert_eq!(simulation.automata().boundary(), &BoundaryCondition::Reflective);
+    }
+}
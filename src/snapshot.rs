@@ -0,0 +1,118 @@
+// Golden files for the tests of the renderers: assert_matches compares a
+// rendering with tests/snapshots/<name>.txt. On a mismatch the rendering
+// is written next to it as <name>.txt.new, and the test fails with the
+// lines that differ. Run the tests with BLESS_SNAPSHOTS=1 (or true, or
+// yes) to write the renderings as the new snapshots instead, then review
+// them in the diff; any other value, 0 say, compares them.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::PathBuf;
+
+pub const BLESS: &str = "BLESS_SNAPSHOTS";
+
+fn path(name: &str) -> PathBuf
+{
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("snapshots").join(format!("{}.txt", name))
+}
+
+// Whether a value of BLESS asks for blessing.
+fn blesses(value: Option<&OsStr>) -> bool
+{
+    value.and_then(OsStr::to_str).is_some_and(|value| ["1", "true", "yes"].iter().any(|truthy| value.trim().eq_ignore_ascii_case(truthy)))
+}
+
+// The lines of `expected` and `found` that differ, as -expected and
+// +found under the number of their line.
+fn diff(expected: &str, found: &str) -> String
+{
+    let (expected, found): (Vec<&str>, Vec<&str>) = (expected.lines().collect(), found.lines().collect());
+    let mut out = String::new();
+    for line in 0..expected.len().max(found.len())
+    {
+        let (old, new) = (expected.get(line), found.get(line));
+        if old != new
+        {
+            out.push_str(&format!("line {}:\n", line + 1));
+            if let Some(old) = old
+            {
+                out.push_str(&format!("-{}\n", old));
+            }
+            if let Some(new) = new
+            {
+                out.push_str(&format!("+{}\n", new));
+            }
+        }
+    }
+    out
+}
+
+pub fn assert_matches(name: &str, rendered: &str)
+{
+    let path = path(name);
+    let new = path.with_extension("txt.new");
+    if blesses(std::env::var_os(BLESS).as_deref())
+    {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, rendered).unwrap();
+        let _ = fs::remove_file(&new);
+        return;
+    }
+    match fs::read_to_string(&path)
+    {
+        Ok(expected) if expected == rendered =>
+        {
+            let _ = fs::remove_file(&new);
+        },
+        Ok(expected) =>
+        {
+            fs::write(&new, rendered).unwrap();
+            panic!("{} does not match its snapshot, written to {}:\n{}(run with {}=1 to bless it)",
+                   name, new.display(), diff(&expected, rendered), BLESS);
+        },
+        Err(_) =>
+        {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&new, rendered).unwrap();
+            panic!("no snapshot {}, the rendering is in {} (run with {}=1 to bless it)", path.display(), new.display(), BLESS);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn diffs_list_the_lines_that_differ()
+    {
+        assert_eq!(diff("a\nb\nc\n", "a\nb\nc\n"), "");
+        assert_eq!(diff("a\nb\nc\n", "a\nB\nc\n"), "line 2:\n-b\n+B\n");
+        // Lines only one side has.
+        assert_eq!(diff("a\n", "a\nb\n"), "line 2:\n+b\n");
+        assert_eq!(diff("a\nb\n", ""), "line 1:\n-a\nline 2:\n-b\n");
+    }
+
+    #[test]
+    fn only_truthy_values_bless()
+    {
+        for value in ["1", "true", "TRUE", "yes", " 1\n"]
+        {
+            assert!(blesses(Some(OsStr::new(value))), "{:?}", value);
+        }
+        for value in ["0", "", "false", "no", "2", "bless"]
+        {
+            assert!(!blesses(Some(OsStr::new(value))), "{:?}", value);
+        }
+        assert!(!blesses(None));
+    }
+
+    #[test]
+    fn snapshots_live_under_tests()
+    {
+        let path = path("grid");
+        assert!(path.ends_with("tests/snapshots/grid.txt"));
+        assert_eq!(path.with_extension("txt.new").file_name().unwrap(), "grid.txt.new");
+    }
+}
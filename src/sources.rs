@@ -0,0 +1,364 @@
+// Named light sources that can be changed while the automaton runs. The
+// set is shared with the automaton's injector (see attach), which writes the
+// sources into every new generation; a source that is moved, removed or
+// runs out of time leaves its last level behind as space, which the rule
+// then lets fade.
+//
+// A source reads `name=I,J,LEVEL[,period=P][,ttl=T]`, as given to --source
+// and in the text form of a whole set, one source per line.
+
+use crate::{Automata, Light};
+use crate::error::{check_coord, GridError};
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source
+{
+    pub name: String,
+    pub position: (usize, usize),
+    pub level: u8,
+    // Lit during the first half of every `period` steps, dark (a source of
+    // level 0) during the other half.
+    pub period: Option<u64>,
+    // Steps the source lasts once in the grid.
+    pub ttl: Option<u64>
+}
+
+impl Source
+{
+    pub fn new(name: &str, position: (usize, usize), level: u8) -> Self
+    {
+        Self{name: name.to_string(), position, level, period: None, ttl: None}
+    }
+
+    // Sources outside of the grid would never show: those given by users
+    // are checked first.
+    pub fn check(&self, dims: (usize, usize)) -> Result<(), GridError>
+    {
+        check_coord(dims, self.position)
+    }
+
+    // The state of the cell `age` steps after the source appeared.
+    fn state(&self, age: u64) -> Light
+    {
+        match self.period
+        {
+            Some(period) if age % period.max(1) >= period.max(1).div_ceil(2) => Light::Source(0),
+            _ => Light::Source(self.level)
+        }
+    }
+}
+
+impl fmt::Display for Source
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}={},{},{}", self.name, self.position.0, self.position.1, self.level)?;
+        if let Some(period) = self.period
+        {
+            write!(f, ",period={}", period)?;
+        }
+        if let Some(ttl) = self.ttl
+        {
+            write!(f, ",ttl={}", ttl)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Source
+{
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String>
+    {
+        let syntax = || format!("invalid source '{}' (name=I,J,LEVEL[,period=P][,ttl=T])", text);
+        let (name, fields) = text.split_once('=').ok_or_else(syntax)?;
+        if name.is_empty()
+        {
+            return Err(syntax());
+        }
+        let mut fields = fields.split(',');
+        let mut number = || fields.next().and_then(|field| field.trim().parse::<usize>().ok()).ok_or_else(syntax);
+        let position = (number()?, number()?);
+        let level = fields.next().and_then(|field| field.trim().parse().ok()).ok_or_else(syntax)?;
+        let mut source = Source::new(name, position, level);
+        for option in fields
+        {
+            match option.split_once('=')
+            {
+                Some(("period", value)) => source.period = Some(value.parse().map_err(|_| syntax())?),
+                Some(("ttl", value)) => source.ttl = Some(value.parse().map_err(|_| syntax())?),
+                _ => return Err(syntax())
+            }
+        }
+        Ok(source)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceSet
+{
+    // With the step each source appeared at, once it has.
+    sources: Vec<(Source, Option<u64>)>,
+    // Cells left by sources since the last writes, with their last level.
+    released: Vec<((usize, usize), u8)>
+}
+
+impl SourceSet
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    // Replaces the source of the same name, if any.
+    pub fn add(&mut self, source: Source)
+    {
+        self.remove(&source.name);
+        self.sources.push((source, None));
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool
+    {
+        match self.sources.iter().position(|(source, _)| source.name == name)
+        {
+            Some(index) =>
+            {
+                // A source that never showed has nothing to leave behind.
+                if let (source, Some(_)) = self.sources.remove(index)
+                {
+                    self.released.push((source.position, source.level));
+                }
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Source>
+    {
+        self.sources.iter().map(|(source, _)| source).find(|source| source.name == name)
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut Source>
+    {
+        self.sources.iter_mut().map(|(source, _)| source).find(|source| source.name == name)
+    }
+
+    // False when there is no source of that name.
+    pub fn set_level(&mut self, name: &str, level: u8) -> bool
+    {
+        self.get_mut(name).map(|source| source.level = level).is_some()
+    }
+
+    pub fn move_to(&mut self, name: &str, position: (usize, usize)) -> bool
+    {
+        match self.sources.iter_mut().find(|(source, _)| source.name == name)
+        {
+            Some((source, born)) =>
+            {
+                if source.position != position && born.is_some()
+                {
+                    self.released.push((source.position, source.level));
+                }
+                source.position = position;
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Source>
+    {
+        self.sources.iter().map(|(source, _)| source)
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.sources.is_empty()
+    }
+
+    // The cells to write into the generation of `step`: the sources left
+    // since last time as space first, then the sources, whose time starts
+    // on their first step here. Sources out of time are released.
+    pub fn writes(&mut self, step: u64) -> Vec<((usize, usize), Light)>
+    {
+        for (source, born) in self.sources.iter_mut()
+        {
+            let born = *born.get_or_insert(step);
+            if source.ttl.is_some_and(|ttl| step.saturating_sub(born) >= ttl)
+            {
+                self.released.push((source.position, source.level));
+            }
+        }
+        self.sources.retain(|(source, born)| source.ttl.is_none_or(|ttl| step.saturating_sub(born.unwrap()) < ttl));
+
+        let mut writes: Vec<((usize, usize), Light)> = self.released.drain(..)
+            .map(|(position, level)| (position, Light::Space(level)))
+            .collect();
+        writes.extend(self.sources.iter().map(|(source, born)| (source.position, source.state(step.saturating_sub(born.unwrap())))));
+        writes
+    }
+
+    // Writes the sources into the current generation right away, for the
+    // changes made between steps.
+    pub fn apply(&mut self, automata: &mut Automata<Light>)
+    {
+        for (coord, state) in self.writes(automata.step())
+        {
+            if let Some(cell) = automata.get_mut(coord)
+            {
+                *cell = state;
+            }
+        }
+    }
+
+    // One source per line.
+    pub fn to_text(&self) -> String
+    {
+        self.iter().map(|source| format!("{}\n", source)).collect()
+    }
+
+    // The text form, blank lines and lines starting with # left out.
+    pub fn parse(text: &str) -> Result<Self, String>
+    {
+        let mut set = Self::new();
+        for (n, line) in text.lines().enumerate()
+        {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#')
+            {
+                continue;
+            }
+            set.add(line.parse().map_err(|error| format!("line {}: {}", n + 1, error))?);
+        }
+        Ok(set)
+    }
+}
+
+// Applies the sources to the automaton now, then after every step through
+// its injector, which the set keeps until another one is installed.
+pub fn attach(sources: &Rc<RefCell<SourceSet>>, automata: &mut Automata<Light>)
+{
+    sources.borrow_mut().apply(automata);
+    let sources = Rc::clone(sources);
+    automata.set_injector(move |step| sources.borrow_mut().writes(step));
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, CellState, Grid};
+
+    fn dark() -> Automata<Light>
+    {
+        Automata::new(Grid::new((30, 20), Light::Space(0)))
+    }
+
+    fn level(automata: &Automata<Light>, coord: (usize, usize)) -> u8
+    {
+        automata.get(coord).unwrap().level()
+    }
+
+    #[test]
+    fn moved_sources_leave_a_fading_light()
+    {
+        let sources = Rc::new(RefCell::new(SourceSet::new()));
+        sources.borrow_mut().add(Source::new("east", (20, 10), 8));
+        sources.borrow_mut().add(Source::new("west", (5, 5), 3));
+        let mut automata = dark();
+        attach(&sources, &mut automata);
+        for _ in 0..10
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        assert_eq!(automata.get((20, 10)), Some(&Light::Source(8)));
+        assert_eq!(level(&automata, (21, 10)), 7);
+
+        assert!(sources.borrow_mut().move_to("east", (12, 3)));
+        assert!(sources.borrow_mut().set_level("west", 5));
+        assert!(!sources.borrow_mut().move_to("north", (0, 0)));
+        // Before it showed, a source moves without a trace.
+        let mut unseen = SourceSet::new();
+        unseen.add(Source::new("north", (1, 1), 4));
+        unseen.move_to("north", (2, 2));
+        assert_eq!(unseen.writes(0), vec![((2, 2), Light::Source(4))]);
+        automata.evolve(rules::light_falloff);
+        // The old place is space, as bright as the source was, and the new
+        // one a source.
+        assert_eq!(automata.get((20, 10)), Some(&Light::Space(8)));
+        assert_eq!(automata.get((12, 3)), Some(&Light::Source(8)));
+        assert_eq!(automata.get((5, 5)), Some(&Light::Source(5)));
+        automata.evolve(rules::light_falloff);
+        assert_eq!(automata.get((20, 10)), Some(&Light::Space(7)));
+        for _ in 0..20
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        // Nothing is left of it, the light around the new place aside:
+        // falloff keeps the brightest neighbor minus one, so the old light
+        // goes out one level a step.
+        let far_from_both = (27, 18);
+        assert_eq!(level(&automata, far_from_both), 0);
+        assert_eq!(automata.get((20, 10)), Some(&Light::Space(0)));
+        assert_eq!(automata.get((12, 3)), Some(&Light::Source(8)));
+    }
+
+    #[test]
+    fn sources_blink_and_run_out()
+    {
+        let mut set = SourceSet::new();
+        set.add(Source{period: Some(4), ..Source::new("blink", (1, 1), 6)});
+        set.add(Source{ttl: Some(3), ..Source::new("short", (4, 4), 9)});
+        let states: Vec<(Light, Option<Light>)> = (10..16).map(|step| {
+            let writes = set.writes(step);
+            let at = |coord| writes.iter().find(|(at, _)| *at == coord).map(|&(_, state)| state);
+            (at((1, 1)).unwrap(), at((4, 4)))
+        }).collect();
+        // Lit half of the period from the step it first shows, whichever
+        // that is.
+        let blink: Vec<Light> = states.iter().map(|&(state, _)| state).collect();
+        assert_eq!(blink, [6, 6, 0, 0, 6, 6].iter().map(|&level| Light::Source(level)).collect::<Vec<_>>());
+        // Three steps, then left behind as space, then gone.
+        let short: Vec<Option<Light>> = states.iter().map(|&(_, state)| state).collect();
+        assert_eq!(short, vec![Some(Light::Source(9)), Some(Light::Source(9)), Some(Light::Source(9)), Some(Light::Space(9)), None, None]);
+        assert_eq!(set.len(), 1);
+        assert!(set.get("short").is_none());
+
+        assert!(set.remove("blink"));
+        assert!(!set.remove("blink"));
+        assert_eq!(set.writes(16), vec![((1, 1), Light::Space(6))]);
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn sources_read_as_they_are_written()
+    {
+        let source: Source = "east=12,3,4,period=6,ttl=40".parse().unwrap();
+        assert_eq!(source, Source{period: Some(6), ttl: Some(40), ..Source::new("east", (12, 3), 4)});
+        assert_eq!(source.to_string(), "east=12,3,4,period=6,ttl=40");
+        assert_eq!("a=1,2,3".parse::<Source>().unwrap().to_string(), "a=1,2,3");
+        for bad in "=1,2,3 a=1,2 a=1,2,300 a=1,2,3,speed=4 a=1,2,3,ttl=x east".split_whitespace()
+        {
+            assert!(bad.parse::<Source>().unwrap_err().contains(&format!("invalid source '{}'", bad)), "{}", bad);
+        }
+        assert!(source.check((30, 20)).is_ok());
+        assert!(source.check((12, 20)).is_err());
+
+        let set = SourceSet::parse("# two lamps\neast=12,3,4,period=6\n\nwest=1,1,9\neast=2,2,2\n").unwrap();
+        assert_eq!(set.to_text(), "west=1,1,9\neast=2,2,2\n");
+        assert_eq!(SourceSet::parse(&set.to_text()).unwrap(), set);
+        assert!(SourceSet::parse("a=1,1,1\nb=2\n").unwrap_err().starts_with("line 2: "));
+    }
+}
@@ -0,0 +1,228 @@
+use crate::Grid;
+
+use std::fmt::{Debug, Display};
+
+// A prism of triangular layers with identical dims. Besides its in-plane
+// neighbors, every cell touches the cell at the same (i, j) in the layers
+// directly below (k-1) and above (k+1).
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stack<T>
+{
+    layers: Vec<Grid<T>>,
+    dims: (usize, usize)
+}
+
+impl<T: Copy + Debug> Stack<T>
+{
+    pub fn new(dims: (usize, usize), depth: usize, default: T) -> Self
+    {
+        Self{layers: vec![Grid::new(dims, default); depth], dims}
+    }
+
+    // None when the layers do not all share the same dims.
+    pub fn from_layers(layers: Vec<Grid<T>>) -> Option<Self>
+    {
+        let dims = layers.first().map(|layer| layer.dims).unwrap_or((0, 0));
+        if layers.iter().all(|layer| layer.dims == dims)
+        {
+            Some(Self{layers, dims})
+        }
+        else
+        {
+            None
+        }
+    }
+
+    // (width, height, depth)
+    pub fn dims(&self) -> (usize, usize, usize)
+    {
+        (self.dims.0, self.dims.1, self.layers.len())
+    }
+
+    pub fn layer(&self, k: usize) -> Option<&Grid<T>>
+    {
+        self.layers.get(k)
+    }
+    pub fn layer_mut(&mut self, k: usize) -> Option<&mut Grid<T>>
+    {
+        self.layers.get_mut(k)
+    }
+
+    pub fn get(&self, (i, j, k): (usize, usize, usize)) -> Option<&T>
+    {
+        self.layers.get(k)?.get((i, j))
+    }
+    pub fn get_mut(&mut self, (i, j, k): (usize, usize, usize)) -> Option<&mut T>
+    {
+        self.layers.get_mut(k)?.get_mut((i, j))
+    }
+
+    // The cell itself first, then its in-plane neighbors in the order of
+    // Grid::neighbor_coords, then the cells below and above.
+    pub fn neighbor_coords_3d(&self, (i, j, k): (usize, usize, usize)) -> Vec<(usize, usize, usize)>
+    {
+        let mut coords = match self.layers.get(k)
+        {
+            Some(layer) => layer.neighbor_coords((i, j)).into_iter()
+                .map(|(i, j)| (i, j, k))
+                .collect::<Vec<_>>(),
+            None => return vec![]
+        };
+        if k > 0
+        {
+            coords.push((i, j, k-1));
+        }
+        if k+1 < self.layers.len()
+        {
+            coords.push((i, j, k+1));
+        }
+        coords
+    }
+
+    pub fn neighborhood_3d(&self, (i, j, k): (usize, usize, usize)) -> Vec<&T>
+    {
+        self.neighbor_coords_3d((i, j, k)).into_iter()
+            .filter_map(|co| self.get(co))
+            .collect()
+    }
+}
+
+
+pub struct Automata3<T>
+{
+    stacks: [Stack<T>; 2],
+    flag: usize
+}
+
+impl<T: Copy + Debug> Automata3<T>
+{
+    pub fn new(stack: Stack<T>) -> Self
+    {
+        Self
+        {
+            stacks: [stack.clone(), stack],
+            flag: 0
+        }
+    }
+
+    // Same contract as Automata::evolve, with the rule seeing the extended
+    // neighborhood of Stack::neighborhood_3d.
+    pub fn evolve<F>(&mut self, rule: F)
+    where
+        F: Fn(Vec<T>) -> T
+    {
+        let (w, h, d) = self.stacks[self.flag].dims();
+        for k in 0..d
+        {
+            for j in 0..h
+            {
+                for i in 0..w
+                {
+                    let ngh = self.stacks[self.flag].neighborhood_3d((i, j, k)).into_iter().cloned().collect::<Vec<_>>();
+                    let new_cell = self.stacks[(self.flag+1)%2].get_mut((i, j, k)).unwrap();
+                    *new_cell = rule(ngh);
+                }
+            }
+        }
+        self.flag = (self.flag+1) % 2;
+    }
+
+    pub fn stack(&self) -> &Stack<T>
+    {
+        &self.stacks[self.flag]
+    }
+
+    pub fn get(&self, (i, j, k): (usize, usize, usize)) -> Option<&T>
+    {
+        self.stacks[self.flag].get((i, j, k))
+    }
+    pub fn get_mut(&mut self, (i, j, k): (usize, usize, usize)) -> Option<&mut T>
+    {
+        self.stacks[self.flag].get_mut((i, j, k))
+    }
+}
+
+impl<T: Copy + Debug + Display> Automata3<T>
+{
+    // Layers are drawn one at a time with the regular grid renderer.
+    pub fn print_layer(&self, k: usize)
+    {
+        if let Some(layer) = self.stacks[self.flag].layer(k)
+        {
+            layer.print();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::Automata;
+
+    fn sorted(mut coords: Vec<(usize, usize, usize)>) -> Vec<(usize, usize, usize)>
+    {
+        coords.sort_unstable();
+        coords
+    }
+
+    #[test]
+    fn interior_cells_have_five_neighbors()
+    {
+        let stack = Stack::new((6, 4), 3, 0u8);
+        // (2, 1) points down: the cell across its horizontal edge is above.
+        assert_eq!(sorted(stack.neighbor_coords_3d((2, 1, 1))),
+                   [(1, 1, 1), (2, 0, 1), (2, 1, 0), (2, 1, 1), (2, 1, 2), (3, 1, 1)]);
+    }
+
+    #[test]
+    fn face_cells_lose_one_side()
+    {
+        let stack = Stack::new((6, 4), 3, 0u8);
+        // On the top layer.
+        assert_eq!(sorted(stack.neighbor_coords_3d((2, 1, 2))),
+                   [(1, 1, 2), (2, 0, 2), (2, 1, 1), (2, 1, 2), (3, 1, 2)]);
+        // On the left side of a middle layer.
+        assert_eq!(sorted(stack.neighbor_coords_3d((0, 2, 1))),
+                   [(0, 2, 0), (0, 2, 1), (0, 2, 2), (0, 3, 1), (1, 2, 1)]);
+    }
+
+    #[test]
+    fn corner_cells()
+    {
+        let stack = Stack::new((6, 4), 3, 0u8);
+        // (0, 0) points up, its horizontal edge is inside the grid.
+        assert_eq!(sorted(stack.neighbor_coords_3d((0, 0, 0))), [(0, 0, 0), (0, 0, 1), (0, 1, 0), (1, 0, 0)]);
+        // (5, 3) points up too, with nothing below it on the last row.
+        assert_eq!(sorted(stack.neighbor_coords_3d((5, 3, 2))), [(4, 3, 2), (5, 3, 1), (5, 3, 2)]);
+        assert!(stack.neighbor_coords_3d((0, 0, 3)).is_empty());
+    }
+
+    #[test]
+    fn layers_must_match()
+    {
+        assert!(Stack::from_layers(vec![Grid::new((2, 2), 0u8), Grid::new((3, 2), 0)]).is_none());
+        assert_eq!(Stack::from_layers(vec![Grid::new((2, 2), 0u8); 4]).unwrap().dims(), (2, 2, 4));
+    }
+
+    #[test]
+    fn evolve_reaches_the_other_layers()
+    {
+        let mut stack = Stack::new((4, 4), 3, 0u8);
+        *stack.get_mut((1, 1, 0)).unwrap() = 1;
+        let mut automata = Automata3::new(stack);
+        let any = |ngh: Vec<u8>| ngh.into_iter().max().unwrap();
+        automata.evolve(any);
+        assert_eq!(automata.get((1, 1, 1)), Some(&1));
+        assert_eq!(automata.get((1, 1, 2)), Some(&0));
+        automata.evolve(any);
+        assert_eq!(automata.get((1, 1, 2)), Some(&1));
+        // What comes back down from the layer above was already there.
+        let mut flat = Automata::new(Grid::new((4, 4), 0u8));
+        *flat.get_mut((1, 1)).unwrap() = 1;
+        flat.evolve(any);
+        flat.evolve(any);
+        assert_eq!(automata.stack().layer(0), Some(flat.current()));
+    }
+}
@@ -0,0 +1,202 @@
+// Named metrics of the generations of a run, one row per logged step,
+// written as CSV or handed to plots::timeseries.
+
+use crate::{Automata, Grid};
+use crate::flux::{BoundaryFlux, Side};
+use crate::run::FrameFilter;
+
+use std::fmt::{Debug, Display};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+type Metric<T> = Box<dyn Fn(&Grid<T>) -> f64>;
+
+const FLUX_NAMES: [&str; 4] = ["flux_top", "flux_bottom", "flux_left", "flux_right"];
+
+pub struct StatsLogger<T>
+{
+    metrics: Vec<(String, Metric<T>)>,
+    filter: FrameFilter<T>,
+    // Whether the rows end with the automaton's boundary flux.
+    flux: bool,
+    // Whether they end with the bytes it holds, after the flux.
+    memory: bool,
+    // The step of the automaton, then one value per metric.
+    rows: Vec<(u64, Vec<f64>)>
+}
+
+impl<T: Copy + Debug> StatsLogger<T>
+{
+    // Logs every step; see with_filter.
+    pub fn new() -> Self
+    {
+        Self{metrics: vec![], filter: FrameFilter::all(), flux: false, memory: false, rows: vec![]}
+    }
+
+    pub fn metric<M: Fn(&Grid<T>) -> f64 + 'static>(mut self, name: &str, metric: M) -> Self
+    {
+        self.metrics.push((name.to_string(), Box::new(metric)));
+        self
+    }
+
+    pub fn with_filter(mut self, filter: FrameFilter<T>) -> Self
+    {
+        self.filter = filter;
+        self
+    }
+
+    // Four more metrics after the others, flux_top, flux_bottom, flux_left
+    // and flux_right, from Automata::last_boundary_flux. Rows logged
+    // without the automaton have NaN for them.
+    pub fn with_flux(mut self) -> Self
+    {
+        self.flux = true;
+        self
+    }
+
+    // One more metric after all of them, memory_bytes, the total of
+    // Automata::memory_report (see memory.rs). NaN without the automaton,
+    // as the flux.
+    pub fn with_memory(mut self) -> Self
+    {
+        self.memory = true;
+        self
+    }
+
+    // Adds a row for the generation if the filter selects its step.
+    pub fn log(&mut self, step: u64, grid: &Grid<T>)
+    {
+        self.log_with(step, grid, None, None);
+    }
+
+    pub fn observe(&mut self, automata: &Automata<T>)
+    where
+        T: Clone + Display
+    {
+        let memory = if self.memory { Some(automata.memory_report().total()) } else { None };
+        self.log_with(automata.step(), automata.current(), Some(automata.last_boundary_flux()), memory);
+    }
+
+    fn log_with(&mut self, step: u64, grid: &Grid<T>, flux: Option<BoundaryFlux>, memory: Option<usize>)
+    {
+        if self.filter.selects(step, grid)
+        {
+            let mut row: Vec<f64> = self.metrics.iter().map(|(_, metric)| metric(grid)).collect();
+            if self.flux
+            {
+                let sides = [Side::Top, Side::Bottom, Side::Left, Side::Right];
+                row.extend(sides.iter().map(|&side| flux.map_or(f64::NAN, |flux| flux.get(side))));
+            }
+            if self.memory
+            {
+                row.push(memory.map_or(f64::NAN, |bytes| bytes as f64));
+            }
+            self.rows.push((step, row));
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str>
+    {
+        let flux: &[&str] = if self.flux { &FLUX_NAMES } else { &[] };
+        let memory: &[&str] = if self.memory { &["memory_bytes"] } else { &[] };
+        self.metrics.iter().map(|(name, _)| name.as_str()).chain(flux.iter().copied()).chain(memory.iter().copied()).collect()
+    }
+
+    pub fn rows(&self) -> &[(u64, Vec<f64>)]
+    {
+        &self.rows
+    }
+
+    // One series per metric, for plots::timeseries, whose x axis is the
+    // index of the row rather than the step.
+    pub fn series(&self) -> Vec<(String, Vec<f64>)>
+    {
+        self.names().into_iter()
+            .enumerate()
+            .map(|(n, name)| (name.to_string(), self.rows.iter().map(|(_, row)| row[n]).collect()))
+            .collect()
+    }
+
+    // A header line `step,<metric names>`, then one line per row.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()>
+    {
+        writeln!(writer, "step,{}", self.names().join(","))?;
+        for (step, row) in &self.rows
+        {
+            let values: Vec<String> = row.iter().map(f64::to_string).collect();
+            writeln!(writer, "{},{}", step, values.join(","))?;
+        }
+        writer.flush()
+    }
+
+    pub fn save_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()>
+    {
+        self.write_csv(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<T: Copy + Debug> Default for StatsLogger<T>
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, CellState, Light};
+
+    fn lit(grid: &Grid<Light>) -> f64
+    {
+        grid.data.iter().filter(|cell| cell.level() > 0).count() as f64
+    }
+
+    #[test]
+    fn one_row_out_of_seven()
+    {
+        let mut automata = Automata::new(Grid::new((15, 9), Light::Space(0)));
+        *automata.get_mut((7, 4)).unwrap() = Light::Source(6);
+        let mut logger = StatsLogger::new().metric("lit", lit).with_filter(FrameFilter::every(7));
+        let mut expected = vec![];
+        logger.observe(&automata);
+        expected.push((0, vec![lit(automata.current())]));
+        for step in 1..=100
+        {
+            automata.evolve(rules::light_falloff);
+            logger.observe(&automata);
+            if step % 7 == 0
+            {
+                expected.push((step, vec![lit(automata.current())]));
+            }
+        }
+        assert_eq!(logger.rows().len(), 15);
+        assert_eq!(logger.rows(), &expected[..]);
+        let mut csv = vec![];
+        logger.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let steps: Vec<&str> = csv.lines().skip(1).map(|line| line.split(',').next().unwrap()).collect();
+        assert_eq!(csv.lines().next(), Some("step,lit"));
+        assert_eq!(steps, (0..=100).step_by(7).map(|step: u64| step.to_string()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn rows_end_with_the_flux_and_the_memory()
+    {
+        let automata = Automata::new(Grid::new((4, 2), Light::Space(0)));
+        let mut logger = StatsLogger::new().metric("lit", lit).with_flux().with_memory();
+        assert_eq!(logger.names(), ["lit", "flux_top", "flux_bottom", "flux_left", "flux_right", "memory_bytes"]);
+        logger.log(3, automata.current());
+        logger.observe(&automata);
+        let (step, row) = &logger.rows()[0];
+        assert_eq!(*step, 3);
+        assert!(row[1..].iter().all(|value| value.is_nan()));
+        let (_, row) = &logger.rows()[1];
+        assert_eq!(row[..5], [0.0; 5]);
+        assert_eq!(row[5], automata.memory_report().total() as f64);
+        assert_eq!(logger.series()[5].1.len(), 2);
+    }
+}
@@ -0,0 +1,178 @@
+// Rules drawing random numbers, given a generator along with the
+// neighborhood, and steps computing the rows on several threads.
+//
+// Under RngStrategy::PerCell, the default, what a cell draws during a step
+// only depends on the seed of the run, the index of the step and the index
+// of the cell (rng::cell_rng). A seed then gives the same generations from
+// one run to the next, and whether they are computed by evolve_stochastic
+// or by evolve_stochastic_par on any number of threads.
+
+use crate::Automata;
+use crate::rng::{self, SplitMix64};
+
+use std::fmt::{Debug, Display};
+use std::thread;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RngStrategy
+{
+    // A generator per cell and step, see above.
+    #[default]
+    PerCell,
+    // One generator per step, seeded from the seed and the step, drawn by
+    // the cells in the order evolve computes them. Cheaper to set up, for
+    // serial runs only.
+    Sequential
+}
+
+impl<T: Clone + Display + Copy + Debug> Automata<T>
+{
+    pub fn evolve_stochastic<F>(&mut self, rule: F, seed: u64)
+    where
+        F: Fn(&mut SplitMix64, Vec<T>) -> T
+    {
+        self.evolve_stochastic_with(rule, seed, RngStrategy::default());
+    }
+
+    pub fn evolve_stochastic_with<F>(&mut self, rule: F, seed: u64, strategy: RngStrategy)
+    where
+        F: Fn(&mut SplitMix64, Vec<T>) -> T
+    {
+        let (step, width) = (self.step, self.current.dims.0);
+        match strategy
+        {
+            RngStrategy::PerCell => self.next_generation(|(i, j), ngh| {
+                rule(&mut rng::cell_rng(seed, step, (j*width + i) as u64), ngh)
+            }),
+            RngStrategy::Sequential =>
+            {
+                let mut rng = SplitMix64::new(rng::mix(seed, step));
+                self.next_generation(|_, ngh| rule(&mut rng, ngh));
+            }
+        }
+    }
+}
+
+impl<T: Clone + Display + Copy + Debug + Send + Sync> Automata<T>
+{
+    // evolve with the rows cut into `threads` bands computed at the same
+    // time, 0 for as many threads as the machine runs at once.
+    pub fn evolve_par<F>(&mut self, rule: F, threads: usize)
+    where
+        F: Fn(Vec<T>) -> T + Sync
+    {
+        self.par_next_generation(|_, ngh| rule(ngh), threads);
+    }
+
+    // evolve_stochastic on several threads, with the same results.
+    pub fn evolve_stochastic_par<F>(&mut self, rule: F, seed: u64, threads: usize)
+    where
+        F: Fn(&mut SplitMix64, Vec<T>) -> T + Sync
+    {
+        let (step, width) = (self.step, self.current.dims.0);
+        self.par_next_generation(|(i, j), ngh| rule(&mut rng::cell_rng(seed, step, (j*width + i) as u64), ngh), threads);
+    }
+
+    fn par_next_generation<G>(&mut self, cell_rule: G, threads: usize)
+    where
+        G: Fn((usize, usize), Vec<T>) -> T + Sync
+    {
+        self.apply_source_programs();
+        let threads = match threads
+        {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n
+        };
+        let (w, h) = self.current.dims;
+        let band = h.div_ceil(threads).max(1) * w;
+        let (current, scratch, boundary) = (&self.current, &mut self.scratch.data, &self.boundary);
+        let cell_rule = &cell_rule;
+        thread::scope(|scope| {
+            for (n, cells) in scratch.chunks_mut(band.max(1)).enumerate()
+            {
+                scope.spawn(move || {
+                    for (k, cell) in cells.iter_mut().enumerate()
+                    {
+                        let index = n*band + k;
+                        let coord = (index % w, index / w);
+                        *cell = cell_rule(coord, current.neighborhood_under(coord, boundary));
+                    }
+                });
+            }
+        });
+        // Cell rules need not be Sync: their few cells are done here.
+        for (&coord, overridden) in &self.cell_rules
+        {
+            scratch[coord.1*w + coord.0] = overridden.apply(current.neighborhood_under(coord, boundary));
+        }
+        std::mem::swap(&mut self.current, &mut self.scratch);
+        self.previous = None;
+        self.finish_step();
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, Grid, Light};
+    use crate::rules::Forest;
+
+    fn woods() -> Automata<Forest>
+    {
+        let mut rng = SplitMix64::new(5);
+        Automata::new(Grid::from_fn((41, 23), |_| if rng.below(2) == 0 { Forest::Tree } else { Forest::Empty }))
+    }
+
+    fn burn<S: FnMut(&mut Automata<Forest>)>(mut step: S) -> Vec<Grid<Forest>>
+    {
+        let mut automata = woods();
+        (0..50).map(|_| {
+            step(&mut automata);
+            automata.current().clone()
+        }).collect()
+    }
+
+    #[test]
+    fn threads_do_not_change_forest_fires()
+    {
+        let rule = rules::forest_fire(0.05, 0.002);
+        let serial = burn(|automata| automata.evolve_stochastic(&rule, 42));
+        for &threads in &[1, 2, 3, 7, 0]
+        {
+            assert_eq!(burn(|automata| automata.evolve_stochastic_par(&rule, 42, threads)), serial, "{} threads", threads);
+        }
+        assert_eq!(burn(|automata| automata.evolve_stochastic(&rule, 42)), serial);
+        // Something did happen, and depends on the seed.
+        assert!(serial.iter().any(|grid| grid.data.contains(&Forest::Burning)));
+        assert_ne!(burn(|automata| automata.evolve_stochastic(&rule, 43)), serial);
+    }
+
+    #[test]
+    fn sequential_generators_repeat_too()
+    {
+        let rule = rules::forest_fire(0.05, 0.002);
+        let sequential = burn(|automata| automata.evolve_stochastic_with(&rule, 42, RngStrategy::Sequential));
+        assert_eq!(burn(|automata| automata.evolve_stochastic_with(&rule, 42, RngStrategy::Sequential)), sequential);
+        assert_ne!(sequential, burn(|automata| automata.evolve_stochastic_with(&rule, 42, RngStrategy::PerCell)));
+    }
+
+    #[test]
+    fn parallel_evolve_matches_evolve()
+    {
+        let lit = || {
+            let mut automata = Automata::new(Grid::new((30, 20), Light::Space(0)));
+            *automata.get_mut((10, 10)).unwrap() = Light::Source(10);
+            automata
+        };
+        let mut serial = lit();
+        let mut parallel = lit();
+        for threads in 0..12
+        {
+            serial.evolve(rules::light_falloff);
+            parallel.evolve_par(rules::light_falloff, threads);
+            assert_eq!(parallel.current(), serial.current());
+            assert_eq!(parallel.step(), serial.step());
+        }
+    }
+}
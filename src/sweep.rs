@@ -0,0 +1,535 @@
+use crate::{Automata, CellState, Grid, Light, Rule};
+use crate::registry::{RegistryError, RuleRegistry};
+use crate::rng;
+use crate::rules;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{self, Debug, Display};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Named numeric parameters of a rule, plus the seed runs are derived from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleConfig
+{
+    pub seed: u64,
+    pub params: BTreeMap<String, f64>
+}
+
+impl RuleConfig
+{
+    pub fn new(seed: u64) -> Self
+    {
+        Self{seed, params: BTreeMap::new()}
+    }
+
+    pub fn with(mut self, name: &str, value: f64) -> Self
+    {
+        self.params.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<f64>
+    {
+        self.params.get(name).cloned()
+    }
+
+    pub fn set(&mut self, name: &str, value: f64)
+    {
+        self.params.insert(name.to_string(), value);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamAxis
+{
+    pub name: String,
+    pub values: Vec<f64>
+}
+
+impl ParamAxis
+{
+    pub fn new(name: &str, values: Vec<f64>) -> Self
+    {
+        Self{name: name.to_string(), values}
+    }
+}
+
+// Shared by the threads of run_par.
+type Measure<T> = Box<dyn Fn(&Grid<T>, &Grid<T>) -> f64 + Send + Sync>;
+
+// A number measured at the end of a run, from the grid before and after the
+// last step.
+pub struct Metric<T>
+{
+    pub name: String,
+    measure: Measure<T>
+}
+
+impl<T: Copy + Debug + PartialEq + 'static> Metric<T>
+{
+    pub fn new<F>(name: &str, measure: F) -> Self
+    where
+        F: Fn(&Grid<T>, &Grid<T>) -> f64 + Send + Sync + 'static
+    {
+        Self{name: name.to_string(), measure: Box::new(measure)}
+    }
+
+    // Fraction of the cells changed by the last step.
+    pub fn activity() -> Self
+    {
+        Self::new("activity", |prev, current| {
+            let changed = prev.data.iter().zip(current.data.iter())
+                .filter(|(a, b)| a != b)
+                .count();
+            changed as f64 / current.data.len().max(1) as f64
+        })
+    }
+
+    pub fn lit_fraction<F>(is_lit: F) -> Self
+    where
+        F: Fn(&T) -> bool + Send + Sync + 'static
+    {
+        Self::new("lit_fraction", move |_, current| {
+            let lit = current.data.iter().filter(|cell| is_lit(cell)).count();
+            lit as f64 / current.data.len().max(1) as f64
+        })
+    }
+
+    // Shannon entropy, in bits, of the distribution of states in the grid.
+    pub fn entropy() -> Self
+    {
+        Self::new("entropy", |_, current| {
+            let mut counts: Vec<(T, usize)> = vec![];
+            for cell in current.data.iter()
+            {
+                match counts.iter_mut().find(|(state, _)| state == cell)
+                {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((*cell, 1))
+                }
+            }
+            let total = current.data.len() as f64;
+            counts.iter()
+                .map(|&(_, count)| count as f64 / total)
+                .map(|p| -p * p.log2())
+                .sum()
+        })
+    }
+
+    pub fn measure(&self, prev: &Grid<T>, current: &Grid<T>) -> f64
+    {
+        (self.measure)(prev, current)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepRow
+{
+    pub index: usize,
+    pub seed: u64,
+    pub params: Vec<f64>,
+    pub values: Vec<f64>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepResults
+{
+    pub param_names: Vec<String>,
+    pub metric_names: Vec<String>,
+    pub rows: Vec<SweepRow>
+}
+
+impl SweepResults
+{
+    pub fn to_csv<W: Write>(&self, mut writer: W) -> io::Result<()>
+    {
+        let mut header = vec!["index".to_string(), "seed".to_string()];
+        header.extend(self.param_names.iter().cloned());
+        header.extend(self.metric_names.iter().cloned());
+        writeln!(writer, "{}", header.join(","))?;
+        for row in self.rows.iter()
+        {
+            let mut fields = vec![row.index.to_string(), row.seed.to_string()];
+            fields.extend(row.params.iter().map(|v| v.to_string()));
+            fields.extend(row.values.iter().map(|v| v.to_string()));
+            writeln!(writer, "{}", fields.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+// The parameter values of combination `index`, the last axis varying
+// fastest.
+fn combination(axes: &[ParamAxis], mut index: usize) -> Vec<f64>
+{
+    let mut values = vec![0.0; axes.len()];
+    for (k, axis) in axes.iter().enumerate().rev()
+    {
+        values[k] = axis.values[index % axis.values.len()];
+        index /= axis.values.len();
+    }
+    values
+}
+
+fn combination_count(axes: &[ParamAxis]) -> usize
+{
+    if axes.iter().any(|axis| axis.values.is_empty())
+    {
+        0
+    }
+    else
+    {
+        axes.iter().map(|axis| axis.values.len()).product()
+    }
+}
+
+// Runs combination `index` alone: its rule is built from the base config
+// with the axis values overridden, its initial grid from a seed derived
+// from the base seed and the index, so that nothing of one run depends on
+// the others or on the order they run in. The automaton is `reused` if
+// there is one with the same dims, reset to the grid.
+#[allow(clippy::too_many_arguments)]
+fn run_combination<T, R, MR, I>(base: &RuleConfig, axes: &[ParamAxis], index: usize, make_rule: &MR, init: &I, steps: usize, metrics: &[Metric<T>], reused: &mut Option<Automata<T>>) -> SweepRow
+where
+    T: Copy + Debug + Display + PartialEq + 'static,
+    R: Rule<T>,
+    MR: Fn(&RuleConfig) -> R,
+    I: Fn(u64) -> Grid<T>
+{
+    let params = combination(axes, index);
+    let mut config = base.clone();
+    for (axis, &value) in axes.iter().zip(params.iter())
+    {
+        config.set(&axis.name, value);
+    }
+    let seed = rng::mix(base.seed, index as u64);
+    config.seed = seed;
+
+    let rule = make_rule(&config);
+    let grid = init(seed);
+    if reused.as_mut().is_none_or(|automata| automata.reset_from(&grid).is_err())
+    {
+        *reused = Some(Automata::new(grid));
+    }
+    let automata = reused.as_mut().unwrap();
+    let mut prev = automata.current().clone();
+    for step in 0..steps
+    {
+        if step+1 == steps
+        {
+            prev = automata.current().clone();
+        }
+        automata.evolve(|ngh| rule.apply(ngh));
+    }
+    let values = metrics.iter()
+        .map(|metric| metric.measure(&prev, automata.current()))
+        .collect();
+    SweepRow{index, seed, params, values}
+}
+
+fn results<T>(axes: &[ParamAxis], metrics: &[Metric<T>], rows: Vec<SweepRow>) -> SweepResults
+{
+    SweepResults
+    {
+        param_names: axes.iter().map(|axis| axis.name.clone()).collect(),
+        metric_names: metrics.iter().map(|metric| metric.name.clone()).collect(),
+        rows
+    }
+}
+
+// Runs every combination of the axes' values for `steps` steps, one after
+// the other (see run_combination), so any single row can be reproduced on
+// its own.
+pub fn run<T, R, MR, I>(base: RuleConfig, axes: Vec<ParamAxis>, make_rule: MR, init: I, steps: usize, metrics: Vec<Metric<T>>) -> SweepResults
+where
+    T: Copy + Debug + Display + PartialEq + 'static,
+    R: Rule<T>,
+    MR: Fn(&RuleConfig) -> R,
+    I: Fn(u64) -> Grid<T>
+{
+    // Reset for every run rather than built again, as long as the initial
+    // grids keep the same dims.
+    let mut reused = None;
+    let rows = (0..combination_count(&axes))
+        .map(|index| run_combination(&base, &axes, index, &make_rule, &init, steps, &metrics, &mut reused))
+        .collect();
+    results(&axes, &metrics, rows)
+}
+
+// How far a sweep is, after every combination run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress
+{
+    pub completed: usize,
+    pub total: usize,
+    // From the mean time between the last completions; None until there
+    // are two.
+    pub eta: Option<Duration>
+}
+
+impl fmt::Display for Progress
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}/{} combinations", self.completed, self.total)?;
+        match self.eta
+        {
+            Some(eta) if self.completed < self.total => write!(f, ", {:.1}s left", eta.as_secs_f64()),
+            _ => Ok(())
+        }
+    }
+}
+
+// Completions averaged over for the ETA.
+const ETA_WINDOW: usize = 16;
+
+struct Eta
+{
+    total: usize,
+    completions: VecDeque<Instant>
+}
+
+impl Eta
+{
+    fn tick(&mut self, completed: usize) -> Progress
+    {
+        if self.completions.len() > ETA_WINDOW
+        {
+            self.completions.pop_front();
+        }
+        self.completions.push_back(Instant::now());
+        let eta = match (self.completions.front(), self.completions.back())
+        {
+            (Some(first), Some(last)) if self.completions.len() > 1 =>
+            {
+                let interval = (*last - *first) / (self.completions.len() - 1) as u32;
+                Some(interval * (self.total - completed) as u32)
+            },
+            _ => None
+        };
+        Progress{completed, total: self.total, eta}
+    }
+}
+
+// The same on `threads` threads (0 for as many as the machine runs), with
+// `progress` called on the calling thread after every combination. Threads
+// take the next combination not taken yet as they finish one, so that
+// slow ones do not hold the others up, and every run is on an automaton of
+// its own: the rows, and so the CSV, are the same on any number of
+// threads, in the order of the combinations whatever the order they end
+// in.
+#[allow(clippy::too_many_arguments)]
+pub fn run_par<T, R, MR, I, P>(base: RuleConfig, axes: Vec<ParamAxis>, make_rule: MR, init: I, steps: usize, metrics: Vec<Metric<T>>, threads: usize, mut progress: P) -> SweepResults
+where
+    T: Copy + Debug + Display + PartialEq + Send + 'static,
+    R: Rule<T>,
+    MR: Fn(&RuleConfig) -> R + Sync,
+    I: Fn(u64) -> Grid<T> + Sync,
+    P: FnMut(Progress)
+{
+    let total = combination_count(&axes);
+    let threads = match threads
+    {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n
+    };
+    let next = AtomicUsize::new(0);
+    let mut rows: Vec<Option<SweepRow>> = vec![None; total];
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads.min(total)
+        {
+            let sender = sender.clone();
+            let (next, base, axes, make_rule, init, metrics) = (&next, &base, &axes, &make_rule, &init, &metrics);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= total
+                {
+                    break;
+                }
+                let row = run_combination(base, axes, index, make_rule, init, steps, metrics, &mut None);
+                if sender.send(row).is_err()
+                {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+        let mut eta = Eta{total, completions: VecDeque::new()};
+        for (completed, row) in receiver.iter().enumerate()
+        {
+            let index = row.index;
+            rows[index] = Some(row);
+            progress(eta.tick(completed + 1));
+        }
+    });
+    let rows = rows.into_iter().map(|row| row.expect("every combination ran")).collect();
+    results(&axes, &metrics, rows)
+}
+
+// The sweep of --sweep: light_decay by amount, from 1 to 16, on 48x24
+// grids with 6 sources of random levels at random places, for 40 steps.
+pub fn decay_sweep<P: FnMut(Progress)>(seed: u64, threads: usize, progress: P) -> SweepResults
+{
+    let init = |seed: u64| {
+        let mut rng = rng::SplitMix64::new(seed);
+        let mut grid = Grid::new((48, 24), Light::Space(0));
+        for _ in 0..6
+        {
+            let coord = (rng.below(48) as usize, rng.below(24) as usize);
+            *grid.get_mut(coord).unwrap() = Light::Source(8 + rng.below(24) as u8);
+        }
+        grid
+    };
+    let make_rule = |config: &RuleConfig| rules::light_decay(config.get("amount").unwrap_or(1.0) as u8);
+    let metrics = vec![Metric::lit_fraction(|cell: &Light| cell.level() > 0), Metric::activity(), Metric::entropy()];
+    let amounts = ParamAxis::new("amount", (1..=16).map(f64::from).collect());
+    run_par(RuleConfig::new(seed), vec![amounts], make_rule, init, 40, metrics, threads, progress)
+}
+
+// The same with a rule of the registry. Every combination is checked
+// against the rule's parameters before anything runs.
+pub fn run_registered<T, I>(registry: &RuleRegistry<T>, name: &str, base: RuleConfig, axes: Vec<ParamAxis>, init: I, steps: usize, metrics: Vec<Metric<T>>) -> Result<SweepResults, RegistryError>
+where
+    T: Copy + Debug + Display + PartialEq + 'static,
+    I: Fn(u64) -> Grid<T>
+{
+    registry.instantiate(name, &base)?;
+    for axis in &axes
+    {
+        for &value in &axis.values
+        {
+            registry.instantiate(name, &base.clone().with(&axis.name, value))?;
+        }
+    }
+    let make_rule = |config: &RuleConfig| registry.instantiate(name, config).expect("parameters checked above");
+    Ok(run(base, axes, make_rule, init, steps, metrics))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn init(seed: u64) -> Grid<Light>
+    {
+        let mut rng = rng::SplitMix64::new(seed);
+        Grid::from_fn((10, 6), |_| if rng.below(8) == 0 { Light::Source(4 + rng.below(8) as u8) } else { Light::Space(0) })
+    }
+
+    fn decay(config: &RuleConfig) -> impl Fn(Vec<Light>) -> Light
+    {
+        let rule = rules::light_decay(config.get("amount").unwrap_or(1.0) as u8);
+        let threshold = config.get("threshold").unwrap_or(0.0) as u8;
+        move |ngh| {
+            let cell = rule(ngh);
+            if cell.level() < threshold { cell.with_level(0) } else { cell }
+        }
+    }
+
+    fn two_by_two() -> SweepResults
+    {
+        let axes = vec![ParamAxis::new("amount", vec![1.0, 3.0]), ParamAxis::new("threshold", vec![0.0, 2.0])];
+        let metrics = vec![Metric::lit_fraction(|cell: &Light| cell.level() > 0), Metric::activity(), Metric::entropy()];
+        run(RuleConfig::new(11), axes, decay, init, 8, metrics)
+    }
+
+    #[test]
+    fn table_has_a_row_per_combination()
+    {
+        let results = two_by_two();
+        assert_eq!(results.param_names, ["amount", "threshold"]);
+        assert_eq!(results.metric_names, ["lit_fraction", "activity", "entropy"]);
+        let params: Vec<Vec<f64>> = results.rows.iter().map(|row| row.params.clone()).collect();
+        assert_eq!(params, [[1.0, 0.0], [1.0, 2.0], [3.0, 0.0], [3.0, 2.0]]);
+        for (index, row) in results.rows.iter().enumerate()
+        {
+            assert_eq!(row.index, index);
+            assert_eq!(row.seed, rng::mix(11, index as u64));
+            assert_eq!(row.values.len(), 3);
+        }
+        let mut csv = vec![];
+        results.to_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().next(), Some("index,seed,amount,threshold,lit_fraction,activity,entropy"));
+        assert_eq!(csv.lines().count(), 5);
+    }
+
+    #[test]
+    fn sweeps_are_deterministic()
+    {
+        assert_eq!(two_by_two(), two_by_two());
+        // A row reproduced on its own, from its seed and parameters.
+        let row = &two_by_two().rows[2];
+        let config = RuleConfig::new(row.seed).with("amount", 3.0).with("threshold", 0.0);
+        let mut automata = Automata::new(init(row.seed));
+        let rule = decay(&config);
+        for _ in 0..8
+        {
+            automata.evolve(&rule);
+        }
+        let lit = automata.current().data.iter().filter(|cell| cell.level() > 0).count() as f64 / 60.0;
+        assert_eq!(row.values[0], lit);
+    }
+
+    #[test]
+    fn empty_axis_runs_nothing()
+    {
+        let results = run(RuleConfig::new(0), vec![ParamAxis::new("amount", vec![])], decay, init, 3, vec![Metric::activity()]);
+        assert!(results.rows.is_empty());
+    }
+
+    fn csv(results: &SweepResults) -> Vec<u8>
+    {
+        let mut bytes = vec![];
+        results.to_csv(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn par(threads: usize) -> SweepResults
+    {
+        let axes = vec![ParamAxis::new("amount", vec![1.0, 2.0, 3.0]), ParamAxis::new("threshold", vec![0.0, 1.0, 2.0])];
+        let metrics = vec![Metric::lit_fraction(|cell: &Light| cell.level() > 0), Metric::activity(), Metric::entropy()];
+        run_par(RuleConfig::new(5), axes, decay, init, 12, metrics, threads, |_| ())
+    }
+
+    #[test]
+    fn the_csv_is_the_same_on_any_number_of_threads()
+    {
+        let one = csv(&par(1));
+        assert_eq!(String::from_utf8_lossy(&one), String::from_utf8_lossy(&csv(&par(4))));
+        assert_eq!(one, csv(&par(0)));
+        // More threads than combinations.
+        assert_eq!(one, csv(&par(32)));
+        let axes = vec![ParamAxis::new("amount", vec![1.0, 2.0, 3.0]), ParamAxis::new("threshold", vec![0.0, 1.0, 2.0])];
+        let metrics = vec![Metric::lit_fraction(|cell: &Light| cell.level() > 0), Metric::activity(), Metric::entropy()];
+        assert_eq!(one, csv(&run(RuleConfig::new(5), axes, decay, init, 12, metrics)));
+    }
+
+    #[test]
+    fn progress_counts_every_combination_once()
+    {
+        let mut seen = vec![];
+        let results = decay_sweep(7, 4, |progress| seen.push(progress));
+        assert_eq!(results.rows.len(), 16);
+        assert_eq!(seen.iter().map(|progress| progress.completed).collect::<Vec<_>>(), (1..=16).collect::<Vec<_>>());
+        assert!(seen.iter().all(|progress| progress.total == 16));
+        assert_eq!(seen[0].eta, None);
+        assert!(seen[1..].iter().all(|progress| progress.eta.is_some()));
+        assert_eq!(seen[15].eta, Some(Duration::ZERO));
+        assert_eq!(csv(&results), csv(&decay_sweep(7, 1, |_| ())));
+    }
+
+    #[test]
+    fn progress_lines()
+    {
+        let progress = Progress{completed: 3, total: 8, eta: Some(Duration::from_millis(2500))};
+        assert_eq!(progress.to_string(), "3/8 combinations, 2.5s left");
+        assert_eq!(Progress{eta: None, ..progress}.to_string(), "3/8 combinations");
+        assert_eq!(Progress{completed: 8, ..progress}.to_string(), "8/8 combinations");
+    }
+}
@@ -0,0 +1,324 @@
+// Keyframes of a run, for jumping back to any of its steps without running
+// it again from the start: every `interval` steps, the generation is kept
+// as a snapshot (see checkpoint.rs, runs of identical cells), and seek
+// loads the last keyframe at or before the step asked for, then steps the
+// automaton the rest of the way, fewer than `interval` steps.
+//
+// That gives back the run only if its steps are a function of the
+// generation and the step index: plain rules and source programs are,
+// injectors are if they only look at the step, and stochastic rules are
+// under RngStrategy::PerCell. Ages start over from the keyframe; the
+// phase of evolve_blocks is kept with it.
+//
+// Keyframes are kept within a budget of bytes, the oldest dropped first;
+// steps before the oldest one left cannot be sought anymore. Under the
+// memory budget of the automaton recorded (see memory.rs), the oldest
+// keyframes but the initial one are dropped too, until they fit in it
+// with the automaton's parts.
+
+use crate::Automata;
+use crate::checkpoint::{self, SnapshotError};
+use crate::codec::CellCodec;
+use crate::Grid;
+
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Display};
+
+#[derive(Debug)]
+pub enum SeekError
+{
+    // The keyframes before the step were dropped, or none was taken yet.
+    Evicted{step: u64, oldest: Option<u64>},
+    // The keyframe is of other dims than the automaton.
+    WrongDims,
+    // Stepping left the automaton at this step, short of the one asked
+    // for, instead of moving it on.
+    Stalled{step: u64},
+    Snapshot(SnapshotError)
+}
+
+impl fmt::Display for SeekError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            SeekError::Evicted{step, oldest: Some(oldest)} => write!(f, "no keyframe before step {} (the oldest is at step {})", step, oldest),
+            SeekError::Evicted{step, oldest: None} => write!(f, "no keyframe before step {} (none kept)", step),
+            SeekError::WrongDims => write!(f, "the keyframes are of other dims than the automaton"),
+            SeekError::Stalled{step} => write!(f, "stepping did not move the automaton on from step {}", step),
+            SeekError::Snapshot(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Keyframe
+{
+    step: u64,
+    block_phase: usize,
+    snapshot: Vec<u8>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timeline
+{
+    interval: u64,
+    budget: usize,
+    // Oldest first, by step.
+    keyframes: VecDeque<Keyframe>,
+    bytes: usize,
+    evicted: usize
+}
+
+impl Timeline
+{
+    // A keyframe every `interval` steps (1 at least), within `budget`
+    // bytes of snapshots.
+    pub fn new(interval: u64, budget: usize) -> Self
+    {
+        Self{interval: interval.max(1), budget, keyframes: VecDeque::new(), bytes: 0, evicted: 0}
+    }
+
+    pub fn interval(&self) -> u64
+    {
+        self.interval
+    }
+
+    pub fn budget(&self) -> usize
+    {
+        self.budget
+    }
+
+    // Bytes taken by the keyframes kept.
+    pub fn memory(&self) -> usize
+    {
+        self.bytes
+    }
+
+    // The steps of the keyframes kept, oldest first.
+    pub fn keyframes(&self) -> Vec<u64>
+    {
+        self.keyframes.iter().map(|keyframe| keyframe.step).collect()
+    }
+
+    // Keyframes dropped so far to stay within the budget.
+    pub fn evicted(&self) -> usize
+    {
+        self.evicted
+    }
+
+    // To be called after every step, and before the first one: keeps the
+    // generation if the step is a multiple of the interval. Keyframes of
+    // later steps, from before a seek back, are dropped first, and others
+    // after it as the budgets ask.
+    pub fn record<T>(&mut self, automata: &Automata<T>)
+    where
+        T: Clone + Display + Copy + Debug + PartialEq + CellCodec
+    {
+        let step = automata.step();
+        if !step.is_multiple_of(self.interval)
+        {
+            return;
+        }
+        while self.keyframes.back().is_some_and(|last| last.step >= step)
+        {
+            let last = self.keyframes.pop_back().unwrap();
+            self.bytes -= last.snapshot.len();
+        }
+        let snapshot = checkpoint::encode(automata.current(), step);
+        self.bytes += snapshot.len();
+        self.keyframes.push_back(Keyframe{step, block_phase: automata.block_phase, snapshot});
+        while self.bytes > self.budget
+        {
+            let oldest = self.keyframes.pop_front().expect("the bytes are those of the keyframes");
+            self.bytes -= oldest.snapshot.len();
+            self.evicted += 1;
+        }
+        if let Some(budget) = automata.memory_budget()
+        {
+            let parts = automata.memory_report().total();
+            while parts + self.bytes > budget && self.keyframes.len() > 1
+            {
+                let oldest = self.keyframes.remove(1).unwrap();
+                self.bytes -= oldest.snapshot.len();
+                self.evicted += 1;
+            }
+        }
+    }
+
+    // Brings the automaton to `step` from the last keyframe before it,
+    // stepping it with `step_once`, the way the run was stepped. An error
+    // if a call to it does not move the step counter on.
+    pub fn seek<T, S>(&self, automata: &mut Automata<T>, step: u64, mut step_once: S) -> Result<(), SeekError>
+    where
+        T: Clone + Display + Copy + Debug + PartialEq + CellCodec,
+        S: FnMut(&mut Automata<T>)
+    {
+        let keyframe = self.keyframes.iter().rev()
+            .find(|keyframe| keyframe.step <= step)
+            .ok_or(SeekError::Evicted{step, oldest: self.keyframes.front().map(|oldest| oldest.step)})?;
+        let (grid, _): (Grid<T>, u64) = checkpoint::decode(keyframe.snapshot.as_slice()).map_err(SeekError::Snapshot)?;
+        automata.reset_from(&grid).map_err(|_| SeekError::WrongDims)?;
+        automata.step = keyframe.step;
+        automata.block_phase = keyframe.block_phase;
+        while automata.step() < step
+        {
+            let before = automata.step();
+            step_once(automata);
+            if automata.step() <= before
+            {
+                return Err(SeekError::Stalled{step: automata.step()});
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, Light};
+    use crate::rng::SplitMix64;
+
+    type Run = (Grid<Light>, Box<dyn Fn(&mut Automata<Light>)>);
+
+    // Light sources flickering at random, and a source written by the
+    // injector.
+    fn flickering(seed: u64) -> Run
+    {
+        let mut rng = SplitMix64::new(seed);
+        let grid = Grid::from_fn((24, 14), |_| if rng.below(30) == 0 { Light::Source(rng.below(10) as u8) } else { Light::Space(0) });
+        let flicker = |rng: &mut SplitMix64, ngh: Vec<Light>| if rng.below(5) == 0 { ngh[0] } else { rules::light_falloff(ngh) };
+        (grid, Box::new(move |automata: &mut Automata<Light>| automata.evolve_stochastic(flicker, seed)))
+    }
+
+    fn started(grid: &Grid<Light>) -> Automata<Light>
+    {
+        let mut automata = Automata::new(grid.clone());
+        automata.set_injector(|step| vec![(((step*7 % 24) as usize, (step % 14) as usize), Light::Source(9))]);
+        automata
+    }
+
+    #[test]
+    fn seeks_give_the_generations_of_the_run()
+    {
+        for seed in 0..3
+        {
+            let (grid, step_once) = flickering(seed);
+            let mut automata = started(&grid);
+            let mut timeline = Timeline::new(16, usize::MAX);
+            timeline.record(&automata);
+            let mut run = vec![automata.current().clone()];
+            for _ in 0..200
+            {
+                step_once(&mut automata);
+                timeline.record(&automata);
+                run.push(automata.current().clone());
+            }
+            assert_eq!(timeline.keyframes(), (0..=192).step_by(16).collect::<Vec<u64>>());
+            let mut replay = started(&grid);
+            for &step in &[137, 5, 64, 200, 0, 199, 16, 111]
+            {
+                timeline.seek(&mut replay, step, &step_once).unwrap();
+                assert_eq!(replay.step(), step);
+                assert!(replay.current() == &run[step as usize], "seed {}, step {}", seed, step);
+            }
+        }
+    }
+
+    #[test]
+    fn the_budget_holds()
+    {
+        let (grid, step_once) = flickering(1);
+        // Room for three of the largest keyframes, at least.
+        let mut automata = started(&grid);
+        let mut largest = checkpoint::encode(&grid, 0).len();
+        for _ in 0..200
+        {
+            step_once(&mut automata);
+            largest = largest.max(checkpoint::encode(automata.current(), 0).len());
+        }
+        let mut automata = started(&grid);
+        let mut small = Timeline::new(16, 3*largest);
+        small.record(&automata);
+        for _ in 0..200
+        {
+            step_once(&mut automata);
+            small.record(&automata);
+            assert!(small.memory() <= small.budget());
+        }
+        assert!(small.evicted() > 0);
+        assert!(small.keyframes().len() >= 3);
+        assert_eq!(small.keyframes().len() + small.evicted(), 13);
+        let oldest = small.keyframes()[0];
+        let mut replay = started(&grid);
+        match small.seek(&mut replay, 5, &step_once)
+        {
+            Err(error @ SeekError::Evicted{..}) =>
+                assert_eq!(error.to_string(), format!("no keyframe before step 5 (the oldest is at step {})", oldest)),
+            other => panic!("expected an evicted keyframe, got {:?}", other)
+        }
+        assert!(matches!(Timeline::new(4, 0).seek(&mut replay, 3, &step_once), Err(SeekError::Evicted{step: 3, oldest: None})));
+    }
+
+    #[test]
+    fn recording_after_a_seek_back_drops_the_later_keyframes()
+    {
+        let step_once = |automata: &mut Automata<Light>| automata.evolve(rules::light_falloff);
+        let mut automata = Automata::new(Grid::from_fn((10, 6), |coord| if coord == (4, 3) {Light::Source(8)} else {Light::Space(0)}));
+        let mut timeline = Timeline::new(0, usize::MAX);
+        assert_eq!(timeline.interval(), 1);
+        timeline.record(&automata);
+        for _ in 0..6
+        {
+            step_once(&mut automata);
+            timeline.record(&automata);
+        }
+        timeline.seek(&mut automata, 2, step_once).unwrap();
+        timeline.record(&automata);
+        assert_eq!(timeline.keyframes(), [0, 1, 2]);
+
+        // Another grid cannot take the keyframes.
+        let mut other = Automata::new(Grid::new((3, 3), Light::Space(0)));
+        assert!(matches!(timeline.seek(&mut other, 1, step_once), Err(SeekError::WrongDims)));
+    }
+
+    #[test]
+    fn block_steps_resume_in_their_phase()
+    {
+        let swap = |(a, b): (Light, Light)| (b, a);
+        let mut rng = SplitMix64::new(2);
+        let mut automata = Automata::new(Grid::from_fn((9, 4), |_| Light::Space(rng.below(10) as u8)));
+        let mut timeline = Timeline::new(3, usize::MAX);
+        timeline.record(&automata);
+        let mut run = vec![automata.current().clone()];
+        for _ in 0..10
+        {
+            automata.evolve_blocks(swap);
+            timeline.record(&automata);
+            run.push(automata.current().clone());
+        }
+        // From the keyframe of step 3, taken between the two phases.
+        let mut replay = Automata::new(run[0].clone());
+        for step in [4, 8, 3, 10]
+        {
+            timeline.seek(&mut replay, step, |automata| automata.evolve_blocks(swap)).unwrap();
+            assert_eq!(replay.current(), &run[step as usize], "step {}", step);
+        }
+    }
+
+    #[test]
+    fn seeks_that_do_not_step_stop()
+    {
+        let mut automata = Automata::new(Grid::new((4, 2), Light::Space(0)));
+        let mut timeline = Timeline::new(5, usize::MAX);
+        timeline.record(&automata);
+        match timeline.seek(&mut automata, 3, |_| ())
+        {
+            Err(error @ SeekError::Stalled{step: 0}) => assert_eq!(error.to_string(), "stepping did not move the automaton on from step 0"),
+            other => panic!("expected a stalled seek, got {:?}", other)
+        }
+    }
+}
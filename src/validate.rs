@@ -0,0 +1,692 @@
+use crate::{Automata, Grid, Light};
+use crate::rng::SplitMix64;
+
+use std::fmt::{self, Debug, Display};
+use std::panic::{self, AssertUnwindSafe};
+
+// Invariant checks meant to be run against user rules, with any grid the
+// caller cares about.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalityViolation
+{
+    pub edited: (usize, usize),
+    pub affected: (usize, usize)
+}
+
+fn step<T, F>(grid: &Grid<T>, rule: F) -> Grid<T>
+where
+    T: Copy + Debug + Display,
+    F: Fn(Vec<T>) -> T
+{
+    let mut automata = Automata::new(grid.clone());
+    automata.evolve(rule);
+    automata.current().clone()
+}
+
+// Edits every cell of `grid` to `alt` in turn and checks that, after one
+// step, only the edited cell and its neighbors differ from the unedited run.
+// This is quadratic in the number of cells, so keep the grids small.
+pub fn check_rule_locality<T, F>(grid: &Grid<T>, rule: F, alt: T) -> Result<(), LocalityViolation>
+where
+    T: Copy + Debug + Display + PartialEq,
+    F: Fn(Vec<T>) -> T
+{
+    let reference = step(grid, &rule);
+    for j in 0..grid.dims.1
+    {
+        for i in 0..grid.dims.0
+        {
+            if grid.get((i, j)) == Some(&alt)
+            {
+                continue;
+            }
+            let mut edited = grid.clone();
+            if let Some(cell) = edited.get_mut((i, j))
+            {
+                *cell = alt;
+            }
+            let next = step(&edited, &rule);
+            let allowed = grid.neighbor_coords((i, j));
+            for l in 0..grid.dims.1
+            {
+                for k in 0..grid.dims.0
+                {
+                    if next.get((k, l)) != reference.get((k, l)) && !allowed.contains(&(k, l))
+                    {
+                        return Err(LocalityViolation{edited: (i, j), affected: (k, l)});
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Runs the rule for a few steps on degenerate grids (single cells, single
+// rows and single columns) and reports the first dims on which it panicked.
+pub fn check_boundary_safety<T, F>(rule: F, fill: T, steps: usize) -> Result<(), (usize, usize)>
+where
+    T: Copy + Debug + Display,
+    F: Fn(Vec<T>) -> T
+{
+    for &dims in &[(1, 1), (2, 1), (1, 2), (7, 1), (1, 7), (2, 2)]
+    {
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut automata = Automata::new(Grid::new(dims, fill));
+            for _ in 0..steps
+            {
+                automata.evolve(&rule);
+            }
+        }));
+        if outcome.is_err()
+        {
+            return Err(dims);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConservationViolation
+{
+    // The step (counted from the automaton's step when the check started)
+    // after which the total was off.
+    pub step: u64,
+    // Change of the total over that step, and the change the boundary
+    // flux accounted for.
+    pub change: i64,
+    pub expected: i64
+}
+
+// Evolves `automata` `steps` times, checking that the sum of `quantity`
+// over the grid only changes by what `boundary_flux` (computed on the
+// generation before each step) says leaves or enters through the edges,
+// or not at all without a flux.
+pub fn check_conservation<T, F, Q, B>(automata: &mut Automata<T>, rule: F, quantity: Q, steps: u64, boundary_flux: Option<B>)
+                                      -> Result<(), ConservationViolation>
+where
+    T: Copy + Debug + Display,
+    F: Fn(Vec<T>) -> T,
+    Q: Fn(&T) -> i64,
+    B: Fn(&Grid<T>) -> i64
+{
+    let total = |grid: &Grid<T>| grid.data.iter().map(&quantity).sum::<i64>();
+    let mut before = total(automata.current());
+    for step in 1..=steps
+    {
+        let expected = boundary_flux.as_ref().map_or(0, |flux| flux(automata.current()));
+        automata.evolve(&rule);
+        let after = total(automata.current());
+        if after - before != expected
+        {
+            return Err(ConservationViolation{step, change: after - before, expected});
+        }
+        before = after;
+    }
+    Ok(())
+}
+
+// A neighborhood (center first) on which a rule broke an invariant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvariantViolation<T>
+{
+    pub neighborhood: Vec<T>,
+    pub output: T
+}
+
+// Runs the rule on every neighborhood of `sampler` and checks that
+// `invariant(center, output)` holds, stopping at the first one that
+// breaks it.
+pub fn check_invariant<T, F, I, S>(rule: F, invariant: I, sampler: S) -> Result<(), InvariantViolation<T>>
+where
+    T: Copy + Debug,
+    F: Fn(Vec<T>) -> T,
+    I: Fn(&T, &T) -> bool,
+    S: IntoIterator<Item = Vec<T>>
+{
+    for neighborhood in sampler
+    {
+        let output = rule(neighborhood.clone());
+        if !invariant(&neighborhood[0], &output)
+        {
+            return Err(InvariantViolation{neighborhood, output});
+        }
+    }
+    Ok(())
+}
+
+// Where two rules first gave different cells: the neighborhood both were
+// given and what each made of it. For grids, also the seed of the initial
+// grid, the step (1 for the first) and the cell.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence<T>
+{
+    pub at: Option<(u64, u64, (usize, usize))>,
+    pub neighborhood: Vec<T>,
+    pub outputs: (T, T)
+}
+
+impl<T: Debug> fmt::Display for Divergence<T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        if let Some((seed, step, coord)) = self.at
+        {
+            write!(f, "seed {}, step {}, cell {:?}: ", seed, step, coord)?;
+        }
+        write!(f, "{:?} gives {:?} and {:?}", self.neighborhood, self.outputs.0, self.outputs.1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EquivalenceReport<T>
+{
+    // Initial grids, or neighborhoods, tried.
+    pub runs: usize,
+    // Cells both rules computed.
+    pub compared: u64,
+    // The first divergence of every run that had one.
+    pub divergences: Vec<Divergence<T>>
+}
+
+impl<T> EquivalenceReport<T>
+{
+    pub fn is_equivalent(&self) -> bool
+    {
+        self.divergences.is_empty()
+    }
+}
+
+impl<T: Debug> fmt::Display for EquivalenceReport<T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self.divergences.first()
+        {
+            None => write!(f, "equivalent on {} runs ({} cells compared)", self.runs, self.compared),
+            Some(first) =>
+            {
+                writeln!(f, "{} of {} runs diverge ({} cells compared)", self.divergences.len(), self.runs, self.compared)?;
+                write!(f, "first: {}", first)
+            }
+        }
+    }
+}
+
+// Evolves random grids of `dims`, one per seed from 0 to `seeds`, with
+// both rules in lockstep for `steps` steps, cells drawn by `state`. A run
+// stops at its first divergence; the neighborhood recorded is the one of
+// the generation both rules still agreed on.
+pub fn equivalence<T, A, B, G>(rule_a: A, rule_b: B, dims: (usize, usize), seeds: usize, steps: u64, mut state: G) -> EquivalenceReport<T>
+where
+    T: Copy + Debug + Display + PartialEq,
+    A: Fn(Vec<T>) -> T,
+    B: Fn(Vec<T>) -> T,
+    G: FnMut(&mut SplitMix64) -> T
+{
+    let mut report = EquivalenceReport{runs: seeds, compared: 0, divergences: vec![]};
+    for seed in 0..seeds as u64
+    {
+        let mut rng = SplitMix64::new(seed);
+        let mut grid = Grid::from_fn(dims, |_| state(&mut rng));
+        for step in 1..=steps
+        {
+            let (next_a, next_b) = (self::step(&grid, &rule_a), self::step(&grid, &rule_b));
+            report.compared += grid.data.len() as u64;
+            let diverged = next_a.data.iter().zip(&next_b.data).position(|(a, b)| a != b);
+            if let Some(k) = diverged
+            {
+                let coord = (k % dims.0, k / dims.0);
+                let neighborhood = grid.neighborhood(coord).into_iter().copied().collect();
+                report.divergences.push(Divergence{at: Some((seed, step, coord)), neighborhood, outputs: (next_a.data[k], next_b.data[k])});
+                break;
+            }
+            grid = next_a;
+        }
+    }
+    report
+}
+
+// Evolves random grids of `dims` as equivalence does, through evolve on two
+// automata in lockstep: one computing every row, the other skipping the
+// quiet ones (see rows.rs). Each of `rules` is used for `steps` steps in
+// turn, the rows invalidated at every change as rows.rs asks. Outputs are
+// (every row, skipping); a divergence is a rule that is not deterministic
+// or a bug of the skipping.
+pub fn row_skipping_equivalence<T, G>(rules: &[&dyn Fn(Vec<T>) -> T], dims: (usize, usize), seeds: usize, steps: u64, mut state: G) -> EquivalenceReport<T>
+where
+    T: Copy + Debug + Display + PartialEq,
+    G: FnMut(&mut SplitMix64) -> T
+{
+    let mut report = EquivalenceReport{runs: seeds, compared: 0, divergences: vec![]};
+    for seed in 0..seeds as u64
+    {
+        let mut rng = SplitMix64::new(seed);
+        let grid = Grid::from_fn(dims, |_| state(&mut rng));
+        let (mut every, mut skipping) = (Automata::new(grid.clone()), Automata::new(grid));
+        skipping.enable_row_skipping();
+        'run: for (n, rule) in rules.iter().enumerate()
+        {
+            skipping.invalidate_rows();
+            for step in 1..=steps
+            {
+                let before = every.current().clone();
+                every.evolve(rule);
+                skipping.evolve(rule);
+                report.compared += before.data.len() as u64;
+                let diverged = every.current().data.iter().zip(&skipping.current().data).position(|(a, b)| a != b);
+                if let Some(k) = diverged
+                {
+                    let coord = (k % dims.0, k / dims.0);
+                    let neighborhood = before.neighborhood(coord).into_iter().copied().collect();
+                    let outputs = (every.current().data[k], skipping.current().data[k]);
+                    report.divergences.push(Divergence{at: Some((seed, n as u64*steps + step, coord)), neighborhood, outputs});
+                    break 'run;
+                }
+            }
+        }
+    }
+    report
+}
+
+// Both rules on every neighborhood of `sampler`, exhaustive_neighborhoods
+// or random_neighborhoods say, every divergence recorded.
+pub fn neighborhood_equivalence<T, A, B, S>(rule_a: A, rule_b: B, sampler: S) -> EquivalenceReport<T>
+where
+    T: Copy + Debug + PartialEq,
+    A: Fn(Vec<T>) -> T,
+    B: Fn(Vec<T>) -> T,
+    S: IntoIterator<Item = Vec<T>>
+{
+    let mut report = EquivalenceReport{runs: 0, compared: 0, divergences: vec![]};
+    for neighborhood in sampler
+    {
+        let outputs = (rule_a(neighborhood.clone()), rule_b(neighborhood.clone()));
+        report.runs += 1;
+        report.compared += 1;
+        if outputs.0 != outputs.1
+        {
+            report.divergences.push(Divergence{at: None, neighborhood, outputs});
+        }
+    }
+    report
+}
+
+// The states 0 to `bound` of grain cells, to enumerate with
+// exhaustive_neighborhoods: (bound + 1)^4 neighborhoods of 4 cells.
+pub fn grain_states(bound: u8) -> Vec<u8>
+{
+    (0..=bound).collect()
+}
+
+// Every neighborhood of 1 to 4 cells (edge and corner cells have fewer
+// neighbors) over the given states: sum of n^k for k = 1..4 of them.
+pub fn exhaustive_neighborhoods<T: Copy>(states: &[T]) -> impl Iterator<Item = Vec<T>> + '_
+{
+    (1..=4u32).flat_map(move |len| {
+        (0..states.len().pow(len)).map(move |mut index| {
+            (0..len).map(|_| {
+                let state = states[index % states.len()];
+                index /= states.len();
+                state
+            }).collect()
+        })
+    })
+}
+
+// `count` neighborhoods of random sizes from 1 to 4, with cells drawn by
+// `state`.
+pub fn random_neighborhoods<T, G>(seed: u64, count: usize, mut state: G) -> impl Iterator<Item = Vec<T>>
+where
+    G: FnMut(&mut SplitMix64) -> T
+{
+    let mut rng = SplitMix64::new(seed);
+    (0..count).map(move |_| {
+        let len = 1 + rng.below(4) as usize;
+        (0..len).map(|_| state(&mut rng)).collect()
+    })
+}
+
+// A grid of random dims from 1x1 to `max_dims`, with cells drawn by
+// `state`: random_light, or |rng| rng.below(4) as u8 for grains.
+pub fn random_grid<T, G>(rng: &mut SplitMix64, max_dims: (usize, usize), mut state: G) -> Grid<T>
+where
+    T: Copy + Debug,
+    G: FnMut(&mut SplitMix64) -> T
+{
+    let dims = (1 + rng.below(max_dims.0 as u64) as usize, 1 + rng.below(max_dims.1 as u64) as usize);
+    Grid::from_fn(dims, |_| state(rng))
+}
+
+// A grid of `dims` with cells drawn by `state` from a generator seeded
+// with `seed`: the same grid for the same seed.
+pub fn seeded_grid<T, G>(seed: u64, dims: (usize, usize), mut state: G) -> Grid<T>
+where
+    T: Copy + Debug,
+    G: FnMut(&mut SplitMix64) -> T
+{
+    let mut rng = SplitMix64::new(seed);
+    Grid::from_fn(dims, |_| state(&mut rng))
+}
+
+// Any light state, each level as likely as the others.
+pub fn random_light(rng: &mut SplitMix64) -> Light
+{
+    let level = rng.below(256) as u8;
+    if rng.below(2) == 0 { Light::Source(level) } else { Light::Space(level) }
+}
+
+// Any grain count, each as likely as the others.
+pub fn random_grain(rng: &mut SplitMix64) -> u8
+{
+    rng.next_u64() as u8
+}
+
+// A dark grid of `dims` but for a source of `level` at `at`.
+pub fn lamp(dims: (usize, usize), at: (usize, usize), level: u8) -> Grid<Light>
+{
+    Grid::from_fn(dims, |coord| if coord == at { Light::Source(level) } else { Light::Space(0) })
+}
+
+// The invariant the light rules rely on: a source stays what it is.
+pub fn sources_are_fixed(input: &Light, output: &Light) -> bool
+{
+    match input
+    {
+        Light::Source(_) => output == input,
+        Light::Space(_) => true
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::checkpoint;
+    use crate::pattern::Pattern;
+    use crate::rules;
+
+    fn grains(rng: &mut SplitMix64) -> u8
+    {
+        rng.below(6) as u8
+    }
+
+    #[test]
+    fn random_grids_stay_in_bounds()
+    {
+        let mut rng = SplitMix64::new(0);
+        for _ in 0..100
+        {
+            let grid = random_grid(&mut rng, (5, 3), grains);
+            assert!((1..=5).contains(&grid.dims.0) && (1..=3).contains(&grid.dims.1), "{:?}", grid.dims);
+            assert_eq!(grid.data.len(), grid.dims.0*grid.dims.1);
+        }
+    }
+
+    #[test]
+    fn built_in_rules_are_local()
+    {
+        let mut rng = SplitMix64::new(1);
+        for _ in 0..20
+        {
+            let grid = random_grid(&mut rng, (7, 5), random_light);
+            assert_eq!(check_rule_locality(&grid, rules::light_falloff, Light::Source(200)), Ok(()));
+            assert_eq!(check_rule_locality(&grid, rules::light_additive, Light::Space(0)), Ok(()));
+            assert_eq!(check_rule_locality(&grid, rules::light_decay(3), Light::Source(9)), Ok(()));
+            let grid = random_grid(&mut rng, (7, 5), grains);
+            assert_eq!(check_rule_locality(&grid, rules::sandpile, 5), Ok(()));
+        }
+    }
+
+    #[test]
+    fn built_in_rules_survive_degenerate_grids()
+    {
+        assert_eq!(check_boundary_safety(rules::light_falloff, Light::Source(7), 5), Ok(()));
+        assert_eq!(check_boundary_safety(rules::light_additive, Light::Source(7), 5), Ok(()));
+        assert_eq!(check_boundary_safety(rules::sandpile, 4u8, 5), Ok(()));
+    }
+
+    #[test]
+    fn degenerate_grids_report_the_rule_that_panics()
+    {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| ()));
+        let four_cells = |ngh: Vec<u8>| ngh[3];
+        let result = check_boundary_safety(four_cells, 0, 1);
+        panic::set_hook(hook);
+        assert_eq!(result, Err((1, 1)));
+    }
+
+    #[test]
+    fn light_rules_keep_sources()
+    {
+        let small = [Light::Source(0), Light::Source(255), Light::Space(0), Light::Space(3), Light::Space(255)];
+        assert!(check_invariant(rules::light_falloff, sources_are_fixed, exhaustive_neighborhoods(&small)).is_ok());
+        assert!(check_invariant(rules::light_additive, sources_are_fixed, random_neighborhoods(2, 10_000, random_light)).is_ok());
+        assert!(check_invariant(rules::light_falloff, sources_are_fixed, random_neighborhoods(3, 10_000, random_light)).is_ok());
+        assert!(check_invariant(rules::light_additive, sources_are_fixed, exhaustive_neighborhoods(&small)).is_ok());
+        for amount in [0, 1, 7, 255]
+        {
+            assert!(check_invariant(rules::light_decay(amount), sources_are_fixed, exhaustive_neighborhoods(&small)).is_ok());
+        }
+        // The clamped rules, wrapped the way a user would check a closure.
+        let falloff = rules::light_falloff_clamped(rules::Clamp::saturate(5));
+        let additive = rules::light_additive_clamped(rules::Clamp::saturate(5));
+        assert!(check_invariant(|ngh| falloff(ngh).unwrap(), sources_are_fixed, random_neighborhoods(4, 10_000, random_light)).is_ok());
+        assert!(check_invariant(|ngh| additive(ngh).unwrap(), sources_are_fixed, random_neighborhoods(5, 10_000, random_light)).is_ok());
+    }
+
+    #[test]
+    fn extinguished_sources_are_caught()
+    {
+        let small = [Light::Source(2), Light::Space(0), Light::Space(9)];
+        // Sources fade like the space around them.
+        let buggy = |ngh: Vec<Light>| match ngh[0]
+        {
+            Light::Source(level) => Light::Source(level.saturating_sub(1)),
+            _ => rules::light_falloff(ngh)
+        };
+        let violation = check_invariant(buggy, sources_are_fixed, exhaustive_neighborhoods(&small)).unwrap_err();
+        assert_eq!(violation.neighborhood[0], Light::Source(2));
+        assert_eq!(violation.output, Light::Source(1));
+        // Space cells may change freely.
+        assert!(check_invariant(|_| Light::Space(0), sources_are_fixed, [vec![Light::Space(3); 4]]).is_ok());
+    }
+
+    #[test]
+    fn patterns_round_trip()
+    {
+        let mut rng = SplitMix64::new(3);
+        for _ in 0..50
+        {
+            let cells = random_grid(&mut rng, (8, 6), random_light);
+            let pattern = Pattern{cells, parity: rng.below(2) as usize};
+            let text = pattern.to_text(|cell| format!("{:?}", cell));
+            assert_eq!(Pattern::from_text(&text, str::parse), Ok(pattern));
+        }
+    }
+
+    #[test]
+    fn snapshots_round_trip()
+    {
+        let mut rng = SplitMix64::new(4);
+        for step in 0..50
+        {
+            let grid = random_grid(&mut rng, (9, 7), random_light);
+            let (decoded, decoded_step) = checkpoint::decode::<Light, _>(&checkpoint::encode(&grid, step)[..]).unwrap();
+            assert_eq!((decoded, decoded_step), (grid, step));
+            let grid = random_grid(&mut rng, (9, 7), grains);
+            assert_eq!(checkpoint::decode::<u8, _>(&checkpoint::encode(&grid, step)[..]).unwrap(), (grid, step));
+        }
+    }
+
+    fn grain_count(cell: &u8) -> i64
+    {
+        i64::from(*cell)
+    }
+
+    #[test]
+    fn sandpiles_conserve_grains_but_at_the_edges()
+    {
+        let mut rng = SplitMix64::new(12);
+        for _ in 0..10
+        {
+            let mut automata = Automata::new(random_grid(&mut rng, (12, 9), grains));
+            assert_eq!(check_conservation(&mut automata, rules::sandpile, grain_count, 40, Some(rules::sandpile_boundary_loss)), Ok(()));
+        }
+        // Without the flux, the grain a corner drops off the grid is a
+        // violation.
+        let mut corner = Automata::new(Grid::from_fn((6, 4), |coord| if coord == (0, 0) {3} else {0}));
+        let none: Option<fn(&Grid<u8>) -> i64> = None;
+        assert_eq!(check_conservation(&mut corner, rules::sandpile, grain_count, 5, none),
+                   Err(ConservationViolation{step: 1, change: -1, expected: 0}));
+    }
+
+    // A sandpile losing a grain whenever a cell topples.
+    fn leaky(ngh: Vec<u8>) -> u8
+    {
+        let toppling = ngh[0] >= 3;
+        let next = rules::sandpile(ngh);
+        if toppling {next.saturating_sub(1)} else {next}
+    }
+
+    #[test]
+    fn violations_report_the_step_and_the_delta()
+    {
+        let mut automata = Automata::new(Grid::from_fn((7, 5), |coord| if coord == (3, 2) {4} else {0}));
+        automata.evolve(rules::sandpile);
+        automata.evolve(rules::sandpile);
+        // Settled: the leak only shows once a cell topples again.
+        *automata.get_mut((3, 2)).unwrap() = 4;
+        let violation = check_conservation(&mut automata, leaky, grain_count, 10, Some(rules::sandpile_boundary_loss));
+        assert_eq!(violation, Err(ConservationViolation{step: 1, change: -1, expected: 0}));
+        assert_eq!(automata.step(), 3);
+    }
+
+    // The falloff as the request for it read: the brightest of the cell and
+    // its neighbors, less one, sources untouched.
+    fn naive_falloff(ngh: Vec<Light>) -> Light
+    {
+        match ngh[0]
+        {
+            Light::Source(level) => Light::Source(level),
+            Light::Space(_) =>
+            {
+                let mut brightest = 0;
+                for cell in &ngh
+                {
+                    let level = match cell { Light::Source(level) | Light::Space(level) => *level };
+                    brightest = brightest.max(level);
+                }
+                Light::Space(if brightest > 0 { brightest - 1 } else { 0 })
+            }
+        }
+    }
+
+    fn naive_sandpile(ngh: Vec<u8>) -> u8
+    {
+        let mut cell = if ngh[0] >= 3 { ngh[0] - 3 } else { ngh[0] };
+        for &ncel in &ngh[1..]
+        {
+            if ncel >= 3
+            {
+                cell = cell.saturating_add(1);
+            }
+        }
+        cell
+    }
+
+    #[test]
+    fn built_in_rules_match_naive_ones()
+    {
+        let report = equivalence(rules::light_falloff, naive_falloff, (17, 9), 40, 30, random_light);
+        assert!(report.is_equivalent(), "{}", report);
+        assert_eq!(report.runs, 40);
+        assert_eq!(report.compared, 40 * 30 * 17 * 9);
+        assert_eq!(report.to_string(), format!("equivalent on 40 runs ({} cells compared)", 40 * 30 * 17 * 9));
+
+        let report = equivalence(rules::sandpile, naive_sandpile, (13, 7), 40, 30, grains);
+        assert!(report.is_equivalent(), "{}", report);
+        let report = neighborhood_equivalence(rules::sandpile, naive_sandpile, exhaustive_neighborhoods(&grain_states(7)));
+        assert!(report.is_equivalent(), "{}", report);
+        assert_eq!(report.runs, 8 + 64 + 512 + 4096);
+
+        let report = neighborhood_equivalence(rules::light_falloff, rules::light_decay(1), random_neighborhoods(5, 20_000, random_light));
+        assert!(report.is_equivalent(), "{}", report);
+        let clamped = rules::light_falloff_clamped(rules::Clamp::saturate(255));
+        let report = equivalence(rules::light_falloff, |ngh| clamped(ngh).unwrap(), (9, 5), 20, 20, random_light);
+        assert!(report.is_equivalent(), "{}", report);
+    }
+
+    #[test]
+    fn skipped_rows_match_computed_ones()
+    {
+        // A few lamps in the dark, so that rows settle and get skipped.
+        let lamps = |rng: &mut SplitMix64| if rng.below(40) == 0 { Light::Source(rng.below(256) as u8) } else { Light::Space(0) };
+        let decay = rules::light_decay(3);
+        let rules: [&dyn Fn(Vec<Light>) -> Light; 3] = [&rules::light_falloff, &decay, &rules::light_falloff];
+        let report = row_skipping_equivalence(&rules, (23, 17), 20, 40, lamps);
+        assert!(report.is_equivalent(), "{}", report);
+        assert_eq!(report.compared, 20 * 3 * 40 * 23 * 17);
+
+        let mut rng = SplitMix64::new(0);
+        let mut automata = Automata::new(Grid::from_fn((23, 17), |_| lamps(&mut rng)));
+        automata.enable_row_skipping();
+        for _ in 0..40
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        assert!(automata.rows_skipped() > 0);
+
+        // A rule drawing at random is caught.
+        let draws = std::cell::RefCell::new(SplitMix64::new(3));
+        let random = |_: Vec<Light>| Light::Space(draws.borrow_mut().below(4) as u8);
+        let report = row_skipping_equivalence(&[&random], (23, 17), 5, 40, lamps);
+        assert_eq!(report.divergences.len(), 5);
+    }
+
+    #[test]
+    fn divergences_can_be_reproduced()
+    {
+        // Forgets the cell's own light: only differs where a cell is
+        // brighter than all of its neighbors.
+        let neighbors_only = |ngh: Vec<Light>| match ngh[0]
+        {
+            Light::Source(_) => ngh[0],
+            Light::Space(_) => naive_falloff(vec![Light::Space(0)].into_iter().chain(ngh[1..].iter().copied()).collect())
+        };
+        let report = equivalence(rules::light_falloff, neighbors_only, (12, 6), 10, 20, random_light);
+        assert_eq!(report.divergences.len(), 10);
+        for divergence in &report.divergences
+        {
+            let (seed, step, coord) = divergence.at.unwrap();
+            assert!(seed < 10 && step >= 1 && coord.0 < 12 && coord.1 < 6);
+            // Enough to replay it without the grid.
+            assert_eq!(rules::light_falloff(divergence.neighborhood.clone()), divergence.outputs.0);
+            assert_eq!(neighbors_only(divergence.neighborhood.clone()), divergence.outputs.1);
+            assert_ne!(divergence.outputs.0, divergence.outputs.1);
+        }
+        // The first one shows up on the grid the seed draws.
+        let first = &report.divergences[0];
+        let (seed, step, coord) = first.at.unwrap();
+        let mut rng = SplitMix64::new(seed);
+        let mut automata = Automata::new(Grid::from_fn((12, 6), |_| random_light(&mut rng)));
+        for _ in 1..step
+        {
+            automata.evolve(rules::light_falloff);
+        }
+        assert_eq!(automata.current().neighborhood(coord).into_iter().copied().collect::<Vec<_>>(), first.neighborhood);
+        let text = report.to_string();
+        assert!(text.starts_with("10 of 10 runs diverge ("));
+        assert!(text.contains(&format!("\nfirst: seed {}, step {}, cell {:?}: {:?} gives ", seed, step, coord, first.neighborhood)));
+
+        let exhaustive = neighborhood_equivalence(rules::sandpile, |ngh: Vec<u8>| if ngh[0] == 3 { ngh[0] } else { rules::sandpile(ngh) },
+                                                  exhaustive_neighborhoods(&grain_states(3)));
+        // Every neighborhood whose center holds 3 grains, but for the one
+        // getting 3 back from its toppling neighbors.
+        assert_eq!(exhaustive.divergences.len(), 1 + 4 + 16 + 64 - 1);
+        assert!(exhaustive.divergences.iter().all(|divergence| divergence.at.is_none() && divergence.neighborhood[0] == 3));
+        assert_eq!(exhaustive.divergences[0].to_string(), "[3] gives 0 and 3");
+    }
+}
@@ -0,0 +1,232 @@
+// Values on the lattice vertices instead of the triangles: potentials
+// whose differences drive flows between cells, heights of a surface...
+// Vertices are the (x, y) points of render::corners, x counting half edges
+// from the left and y rows from the top, and on a grid of dims (w, h) they
+// are the points with x <= w + 1, y <= h and x + y odd (the `·` of the
+// ASCII drawing). Row y holds
+//
+//  - (w + 2) / 2 of them when w is even,
+//  - (w + 1) / 2 when w is odd and y even, (w + 3) / 2 when both are odd,
+//
+// the k-th one at x = 2k + (y + 1) % 2, for (h + 1)(w + 2) / 2 vertices in
+// all, rounded down.
+//
+// A vertex touches the cells of the rows above and below it whose corners
+// it is: 6 inside the grid, 3 along the top and bottom, 2 or 4 along the
+// slanted left and right sides and 1 or 2 at the corners.
+
+use crate::Grid;
+use crate::render;
+
+use std::fmt::{Debug, Display};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VertexGrid<T>
+{
+    // Of the cells, as the grids the vertices come from.
+    pub dims: (usize, usize),
+    // Row by row, from the left.
+    pub data: Vec<T>
+}
+
+// Vertices of row y of a grid w cells wide.
+fn row_len(w: usize, y: usize) -> usize
+{
+    (w + 1 - (y + 1) % 2) / 2 + 1
+}
+
+fn row_start(w: usize, y: usize) -> usize
+{
+    // Rows pair up into w + 2 vertices.
+    (y / 2)*(w + 2) + if y % 2 == 1 { row_len(w, 0) } else { 0 }
+}
+
+pub fn vertex_count((w, h): (usize, usize)) -> usize
+{
+    row_start(w, h + 1)
+}
+
+// The vertices of a grid of `dims`, row by row.
+pub fn vertices((w, h): (usize, usize)) -> impl Iterator<Item = (usize, usize)>
+{
+    (0..=h).flat_map(move |y| (0..row_len(w, y)).map(move |k| (2*k + (y + 1) % 2, y)))
+}
+
+// The cells a vertex is a corner of, row by row; empty off the lattice.
+pub fn incident_faces((w, h): (usize, usize), (x, y): (usize, usize)) -> Vec<(usize, usize)>
+{
+    let mut faces = vec![];
+    for j in y.saturating_sub(1)..(y + 1).min(h)
+    {
+        for i in x.saturating_sub(2)..(x + 1).min(w)
+        {
+            if render::corners((i, j)).contains(&(x, y))
+            {
+                faces.push((i, j));
+            }
+        }
+    }
+    faces
+}
+
+impl<T: Copy + Debug> VertexGrid<T>
+{
+    pub fn new(dims: (usize, usize), value: T) -> Self
+    {
+        Self{dims, data: vec![value; vertex_count(dims)]}
+    }
+
+    fn index(&self, (x, y): (usize, usize)) -> Option<usize>
+    {
+        let (w, h) = self.dims;
+        if y > h || (x + y) % 2 == 0 || x / 2 >= row_len(w, y)
+        {
+            return None;
+        }
+        Some(row_start(w, y) + x / 2)
+    }
+
+    pub fn get(&self, vertex: (usize, usize)) -> Option<&T>
+    {
+        self.index(vertex).map(|n| &self.data[n])
+    }
+
+    pub fn get_mut(&mut self, vertex: (usize, usize)) -> Option<&mut T>
+    {
+        self.index(vertex).map(move |n| &mut self.data[n])
+    }
+
+    // The ASCII drawing of the grid, with `marker` in place of the `·` of
+    // every vertex.
+    pub fn render_over<C, M>(&self, grid: &Grid<C>, marker: M) -> String
+    where
+        C: Copy + Debug + Display,
+        M: Fn(&T) -> char
+    {
+        grid.render_marked(|cell| format!("{:^3}", cell), |vertex| self.get(vertex).map_or('·', &marker))
+    }
+}
+
+// Every vertex from the cells it touches.
+pub fn face_to_vertex<T, U, R>(grid: &Grid<T>, reduce: R) -> VertexGrid<U>
+where
+    T: Copy + Debug,
+    R: Fn(&[T]) -> U
+{
+    let data = vertices(grid.dims)
+        .map(|vertex| {
+            let faces: Vec<T> = incident_faces(grid.dims, vertex).into_iter().map(|coord| *grid.get(coord).unwrap()).collect();
+            reduce(&faces)
+        })
+        .collect();
+    VertexGrid{dims: grid.dims, data}
+}
+
+// Every cell from its three corners, in the order of render::corners.
+pub fn vertex_to_face<T, U, R>(vertices: &VertexGrid<T>, reduce: R) -> Grid<U>
+where
+    T: Copy + Debug,
+    U: Copy + Debug,
+    R: Fn(&[T]) -> U
+{
+    Grid::from_fn(vertices.dims, |coord| {
+        let corners = render::corners(coord).map(|vertex| *vertices.get(vertex).unwrap());
+        reduce(&corners)
+    })
+}
+
+// The mean of values, for face_to_vertex and vertex_to_face.
+pub fn mean(values: &[f64]) -> f64
+{
+    values.iter().sum::<f64>() / values.len().max(1) as f64
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // A vertex and the cells it touches.
+    type Incidence = ((usize, usize), &'static [(usize, usize)]);
+
+    #[test]
+    fn corner_side_and_inner_vertices()
+    {
+        let dims = (6, 3);
+        let pinned: [Incidence; 7] = [
+            ((7, 0), &[(5, 0)]),
+            ((0, 3), &[(0, 2)]),
+            ((1, 0), &[(0, 0), (1, 0)]),
+            ((3, 0), &[(1, 0), (2, 0), (3, 0)]),
+            ((0, 1), &[(0, 0), (0, 1)]),
+            ((6, 1), &[(4, 0), (5, 0), (4, 1), (5, 1)]),
+            ((3, 2), &[(1, 1), (2, 1), (3, 1), (1, 2), (2, 2), (3, 2)])
+        ];
+        for &(vertex, faces) in &pinned
+        {
+            assert_eq!(incident_faces(dims, vertex), faces, "vertex {:?}", vertex);
+        }
+        // Off the lattice.
+        assert_eq!(incident_faces(dims, (2, 0)), vec![]);
+        assert_eq!(VertexGrid::new(dims, ()).get((2, 0)), None);
+        assert_eq!(VertexGrid::new(dims, ()).get((1, 4)), None);
+    }
+
+    #[test]
+    fn vertices_and_cells_meet()
+    {
+        for &dims in &[(5, 4), (6, 3), (1, 1), (2, 5), (7, 7)]
+        {
+            let (w, h) = dims;
+            let all: Vec<(usize, usize)> = vertices(dims).collect();
+            assert_eq!(all.len(), vertex_count(dims));
+            assert_eq!(vertex_count(dims), (h + 1)*(w + 2) / 2, "{:?}", dims);
+            assert!(all.iter().all(|&vertex| !incident_faces(dims, vertex).is_empty()), "{:?}", dims);
+            let grid = VertexGrid::new(dims, ());
+            for coord in (0..h).flat_map(|j| (0..w).map(move |i| (i, j)))
+            {
+                assert!(render::corners(coord).iter().all(|&vertex| grid.get(vertex).is_some()), "{:?}: {:?}", dims, coord);
+            }
+            // Every cell counted once per corner.
+            let touches: usize = all.iter().map(|&vertex| incident_faces(dims, vertex).len()).sum();
+            assert_eq!(touches, 3*w*h);
+        }
+    }
+
+    #[test]
+    fn faces_onto_vertices_and_back()
+    {
+        let grid = Grid::from_fn((6, 3), |(i, j)| (i + 10*j) as f64);
+        let means = face_to_vertex(&grid, mean);
+        assert_eq!(means.data.len(), vertex_count((6, 3)));
+        assert_eq!(means.get((7, 0)), Some(&5.0));
+        assert_eq!(means.get((3, 0)), Some(&2.0));
+        assert_eq!(means.get((3, 2)), Some(&((11.0 + 12.0 + 13.0 + 21.0 + 22.0 + 23.0) / 6.0)));
+        let counts = face_to_vertex(&grid, <[f64]>::len);
+        assert_eq!(counts.get((3, 2)), Some(&6));
+
+        // A constant field goes round unchanged.
+        let flat = vertex_to_face(&VertexGrid::new((6, 3), 2.5), mean);
+        assert_eq!(flat, Grid::new((6, 3), 2.5));
+        let mut ramp = VertexGrid::new((6, 3), 0.0);
+        for vertex in vertices((6, 3))
+        {
+            *ramp.get_mut(vertex).unwrap() = vertex.0 as f64;
+        }
+        let faces = vertex_to_face(&ramp, |corners| corners.iter().cloned().fold(f64::MIN, f64::max) - corners.iter().cloned().fold(f64::MAX, f64::min));
+        assert!(faces.data.iter().all(|&spread| spread == 2.0));
+    }
+
+    #[test]
+    fn markers_replace_the_dots()
+    {
+        let grid = Grid::new((4, 2), 0u8);
+        let mut vertices = VertexGrid::new((4, 2), false);
+        *vertices.get_mut((1, 0)).unwrap() = true;
+        *vertices.get_mut((4, 1)).unwrap() = true;
+        let text = vertices.render_over(&grid, |&high| if high {'+'} else {'o'});
+        assert_eq!(text.matches('+').count(), 2);
+        assert_eq!(text.matches('o').count(), vertex_count((4, 2)) - 2);
+        assert_eq!(text.matches('·').count(), 0);
+    }
+}
@@ -0,0 +1,287 @@
+// Rules read from a file that a run picks up again whenever the file is
+// edited: a registered rule with its parameters, one line as in scripts,
+//
+//     decay amount=3        # `#` starts a comment
+//
+// or a state machine (see machine.rs), or anything else given a parser.
+// The file is polled before every step, by its length and modification
+// time; once it changed, it is parsed again and the rule swapped in for
+// the next step. A file that does not parse, or cannot be read, is
+// reported and the old rule goes on. Every swap is kept with the step it
+// happened at, the new rule computing the steps after it.
+
+use crate::Rule;
+use crate::machine::StateMachineRule;
+use crate::palette::strip_comment;
+use crate::registry::{self, RuleRegistry};
+use crate::simulation::Simulation;
+
+use std::fmt::{self, Debug, Display};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Debug)]
+pub enum WatchError
+{
+    Io(io::Error),
+    Parse(String)
+}
+
+impl fmt::Display for WatchError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            WatchError::Io(error) => write!(f, "{}", error),
+            WatchError::Parse(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Swap
+{
+    // The step the rule was swapped in after.
+    pub step: u64,
+    // Its name, or the file's first line for rules without one.
+    pub rule: String
+}
+
+type Parser<T> = Box<dyn Fn(&str) -> Result<Box<dyn Rule<T>>, String>>;
+
+pub struct RuleWatcher<T>
+{
+    path: PathBuf,
+    parse: Parser<T>,
+    // Length and modification time of the file when last read.
+    seen: Option<(u64, SystemTime)>,
+    on_error: Box<dyn FnMut(&WatchError)>,
+    swaps: Vec<Swap>
+}
+
+impl<T: 'static> RuleWatcher<T>
+{
+    // Rules of `registry`, as `name param=value...` on the first line that
+    // is not blank or a comment.
+    pub fn new<P: AsRef<Path>>(path: P, registry: RuleRegistry<T>) -> Self
+    {
+        Self::with_parser(path, move |text| {
+            let line = text.lines().map(strip_comment).find(|line| !line.trim().is_empty()).ok_or("no rule in the file")?;
+            let mut words = line.split_whitespace();
+            let name = words.next().expect("the line is not blank");
+            let config = registry::parse_params(words)?;
+            registry.instantiate(name, &config).map_err(|error| error.to_string())
+        })
+    }
+
+    pub fn with_parser<P, F>(path: P, parse: F) -> Self
+    where
+        P: AsRef<Path>,
+        F: Fn(&str) -> Result<Box<dyn Rule<T>>, String> + 'static
+    {
+        Self{
+            path: path.as_ref().to_path_buf(),
+            parse: Box::new(parse),
+            seen: None,
+            on_error: Box::new(|error| eprintln!("{}", error)),
+            swaps: vec![]
+        }
+    }
+
+    // Where errors go, stderr by default.
+    pub fn on_error<F: FnMut(&WatchError) + 'static>(mut self, f: F) -> Self
+    {
+        self.on_error = Box::new(f);
+        self
+    }
+
+    fn read(&mut self) -> Result<(Box<dyn Rule<T>>, String), WatchError>
+    {
+        let metadata = fs::metadata(&self.path).map_err(WatchError::Io)?;
+        self.seen = Some((metadata.len(), metadata.modified().map_err(WatchError::Io)?));
+        let text = fs::read_to_string(&self.path).map_err(WatchError::Io)?;
+        let rule = (self.parse)(&text).map_err(WatchError::Parse)?;
+        let name = rule.name().map(str::to_string)
+            .unwrap_or_else(|| text.lines().next().unwrap_or("").trim().to_string());
+        Ok((rule, name))
+    }
+
+    // The rule in the file now, to start the run with.
+    pub fn load(&mut self) -> Result<Box<dyn Rule<T>>, WatchError>
+    {
+        self.read().map(|(rule, _)| rule)
+    }
+
+    fn changed(&self) -> bool
+    {
+        let now = fs::metadata(&self.path).and_then(|metadata| Ok((metadata.len(), metadata.modified()?)));
+        match (now, self.seen)
+        {
+            (Ok(now), Some(seen)) => now != seen,
+            // Gone for now: an editor writing the file anew, say.
+            (Err(_), Some(_)) => false,
+            (_, None) => true
+        }
+    }
+
+    // The rule of the file if it changed and parses; errors are reported
+    // and give None.
+    pub fn poll(&mut self) -> Option<(Box<dyn Rule<T>>, String)>
+    {
+        if !self.changed()
+        {
+            return None;
+        }
+        match self.read()
+        {
+            Ok(rule) => Some(rule),
+            Err(error) =>
+            {
+                (self.on_error)(&error);
+                None
+            }
+        }
+    }
+
+    // Polls the file, swaps a new rule in, then evolves.
+    pub fn step(&mut self, simulation: &mut Simulation<T, Box<dyn Rule<T>>>)
+    where
+        T: Clone + Display + Copy + Debug
+    {
+        if let Some((rule, name)) = self.poll()
+        {
+            simulation.set_rule(rule);
+            self.swaps.push(Swap{step: simulation.step(), rule: name});
+        }
+        simulation.evolve();
+    }
+
+    pub fn swaps(&self) -> &[Swap]
+    {
+        &self.swaps
+    }
+}
+
+impl RuleWatcher<u8>
+{
+    // State machines, in the TOML of machine.rs.
+    pub fn machine<P: AsRef<Path>>(path: P) -> Self
+    {
+        Self::with_parser(path, |text| {
+            StateMachineRule::parse_toml(text).map(|rule| Box::new(rule) as Box<dyn Rule<u8>>).map_err(|error| error.to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, Automata, Grid, Light};
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn temp_path(name: &str) -> PathBuf
+    {
+        std::env::temp_dir().join(format!("triangle-automata-{}-{}.rule", std::process::id(), name))
+    }
+
+    // Steps `simulation` and the same generation with `rule` once, which
+    // must agree.
+    fn agrees<R: Fn(Vec<Light>) -> Light>(watcher: &mut RuleWatcher<Light>, simulation: &mut Simulation<Light, Box<dyn Rule<Light>>>, rule: R) -> bool
+    {
+        let mut expected = Automata::new(simulation.current().clone());
+        expected.evolve(rule);
+        watcher.step(simulation);
+        simulation.current() == expected.current()
+    }
+
+    // A light simulation on a rule file edited part way: the next step
+    // must be the new rule's, the swap recorded at the step before it, and
+    // a broken edit must be reported and leave the rule as it was.
+    #[test]
+    fn edits_swap_the_rule_at_the_next_step()
+    {
+        let path = temp_path("reload");
+        let write = |text: &str| fs::write(&path, text).unwrap();
+        write("falloff\n");
+        let errors = Rc::new(RefCell::new(vec![]));
+        let reported = Rc::clone(&errors);
+        let mut watcher = RuleWatcher::new(&path, RuleRegistry::<Light>::with_builtins())
+            .on_error(move |error| reported.borrow_mut().push(error.to_string()));
+        let grid = Grid::from_fn((16, 8), |coord| if coord == (8, 4) { Light::Source(20) } else { Light::Space(0) });
+        let mut simulation = Simulation::new(grid, watcher.load().unwrap());
+        let mut results = vec![];
+        for _ in 0..5
+        {
+            results.push(agrees(&mut watcher, &mut simulation, rules::light_falloff));
+        }
+        assert!(watcher.swaps().is_empty());
+
+        write("# fading faster\ndecay amount=4\n");
+        results.push(agrees(&mut watcher, &mut simulation, rules::light_decay(4)));
+        let first = watcher.swaps().to_vec();
+        write("decay amount=\n");
+        results.push(agrees(&mut watcher, &mut simulation, rules::light_decay(4)));
+        let broken = (watcher.swaps().len(), errors.borrow().len());
+        write("decay amount=1\n");
+        results.push(agrees(&mut watcher, &mut simulation, rules::light_decay(1)));
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(results, vec![true; 8]);
+        assert_eq!(first, [Swap{step: 5, rule: "decay".to_string()}]);
+        assert_eq!(broken, (1, 1));
+        assert_eq!(watcher.swaps().last(), Some(&Swap{step: 7, rule: "decay".to_string()}));
+        assert_eq!(simulation.step(), 8);
+    }
+
+    #[test]
+    fn missing_files_and_unknown_rules()
+    {
+        let path = temp_path("missing");
+        let _ = fs::remove_file(&path);
+        let mut watcher = RuleWatcher::new(&path, RuleRegistry::<Light>::with_builtins());
+        assert!(matches!(watcher.load(), Err(WatchError::Io(_))));
+        fs::write(&path, "# nothing yet\n\n").unwrap();
+        let empty = watcher.load().err().map(|error| error.to_string());
+        fs::write(&path, "sunlight\n").unwrap();
+        let unknown = watcher.load().err();
+        let _ = fs::remove_file(&path);
+        assert_eq!(empty.as_deref(), Some("no rule in the file"));
+        assert!(matches!(unknown, Some(WatchError::Parse(_))));
+    }
+
+    #[test]
+    fn removed_files_keep_the_rule()
+    {
+        let path = temp_path("removed");
+        fs::write(&path, "falloff\n").unwrap();
+        let errors = Rc::new(RefCell::new(0));
+        let reported = Rc::clone(&errors);
+        let mut watcher = RuleWatcher::new(&path, RuleRegistry::<Light>::with_builtins())
+            .on_error(move |_| *reported.borrow_mut() += 1);
+        let mut simulation = Simulation::new(Grid::new((6, 4), Light::Source(3)), watcher.load().unwrap());
+        fs::remove_file(&path).unwrap();
+        let kept = agrees(&mut watcher, &mut simulation, rules::light_falloff);
+        assert!(kept);
+        assert!(watcher.swaps().is_empty());
+        assert_eq!(*errors.borrow(), 0);
+    }
+
+    #[test]
+    fn machines_are_watched_too()
+    {
+        let path = temp_path("machine");
+        fs::write(&path, "states = [\"off\", \"on\"]\n[[transition]]\nfrom = \"on\"\nto = \"off\"\n").unwrap();
+        let mut watcher = RuleWatcher::machine(&path);
+        let rule = watcher.load();
+        let _ = fs::remove_file(&path);
+        let rule = rule.unwrap();
+        assert_eq!(rule.apply(vec![1, 0, 0, 0]), 0);
+        assert_eq!(rule.apply(vec![0, 0, 0, 0]), 0);
+    }
+}
@@ -0,0 +1,532 @@
+// Live view of a run in a browser: a small WebSocket server (RFC 6455,
+// written by hand like the rest of the crate) broadcasting every
+// generation to any number of clients as a JSON text message
+//
+//   {"step": 12, "dims": [30, 20], "cells": [[count, value], ...]}
+//
+// where `cells` are runs of identical values in row order. A plain HTTP
+// request gets the viewer page from examples/ws_viewer.html instead.
+
+use crate::{Automata, Grid};
+
+use std::fmt::{Debug, Display, Write as _};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const VIEWER: &str = include_str!("../examples/ws_viewer.html");
+
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// How often the accepting thread looks at the shutdown flag.
+const ACCEPT_POLL: Duration = Duration::from_millis(20);
+
+// How long a new connection may take to send its request, and a client a
+// message, before it is dropped; shutdown waits for them at most this long.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct BroadcastOptions
+{
+    // At most this many generations per second, as fast as possible when
+    // not positive.
+    pub fps: f64,
+    // Messages waiting for a slow client before it skips new ones.
+    pub queue: usize,
+    // Checked before every step; setting it ends the run early. Clients
+    // get a close message either way.
+    pub stop: Arc<AtomicBool>
+}
+
+impl Default for BroadcastOptions
+{
+    fn default() -> Self
+    {
+        Self{fps: 10.0, queue: 4, stop: Arc::new(AtomicBool::new(false))}
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BroadcastStats
+{
+    pub steps: u64,
+    pub clients: usize,
+    pub sent: usize,
+    // Messages skipped by clients that fell behind.
+    pub skipped: usize
+}
+
+fn sha1(data: &[u8]) -> [u8; 20]
+{
+    let mut h: [u32; 5] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476, 0xc3d2_e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56
+    {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64)
+    {
+        let mut w = [0u32; 80];
+        for (t, word) in block.chunks(4).enumerate()
+        {
+            w[t] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for t in 16..80
+        {
+            w[t] = (w[t-3] ^ w[t-8] ^ w[t-14] ^ w[t-16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (t, &word) in w.iter().enumerate()
+        {
+            let (f, k) = match t
+            {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6)
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (total, value) in h.iter_mut().zip([a, b, c, d, e])
+        {
+            *total = total.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(h.iter())
+    {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String
+{
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3)
+    {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (n, &byte)| bits | u32::from(byte) << (16 - 8*n));
+        for n in 0..4
+        {
+            if n <= chunk.len()
+            {
+                out.push(ALPHABET[(bits >> (18 - 6*n) & 0x3f) as usize] as char);
+            }
+            else
+            {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn accept_key(key: &str) -> String
+{
+    base64(&sha1(format!("{}{}", key, HANDSHAKE_GUID).as_bytes()))
+}
+
+// Reads the HTTP request of a new connection. Upgrades to WebSocket
+// return the stream ready for messages; the others are answered with the
+// viewer page and return None.
+fn handshake(stream: TcpStream) -> io::Result<Option<BufWriter<TcpStream>>>
+{
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut key = None;
+    loop
+    {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0
+        {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty()
+        {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+        {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key")
+            {
+                key = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let mut writer = BufWriter::new(stream);
+    match key
+    {
+        Some(key) =>
+        {
+            write!(writer, "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                   accept_key(&key))?;
+            writer.flush()?;
+            Ok(Some(writer))
+        },
+        None =>
+        {
+            write!(writer, "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                   VIEWER.len(), VIEWER)?;
+            writer.flush()?;
+            Ok(None)
+        }
+    }
+}
+
+// A single unmasked frame, as servers send them.
+fn write_message<W: Write>(writer: &mut W, opcode: u8, payload: &[u8]) -> io::Result<()>
+{
+    writer.write_all(&[0x80 | opcode])?;
+    match payload.len()
+    {
+        len if len < 126 => writer.write_all(&[len as u8])?,
+        len if len <= 0xffff =>
+        {
+            writer.write_all(&[126])?;
+            writer.write_all(&(len as u16).to_be_bytes())?;
+        },
+        len =>
+        {
+            writer.write_all(&[127])?;
+            writer.write_all(&(len as u64).to_be_bytes())?;
+        }
+    }
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+pub fn encode_message<T, V>(grid: &Grid<T>, step: u64, value: V) -> String
+where
+    T: Copy + Debug,
+    V: Fn(&T) -> f64
+{
+    let mut message = format!("{{\"step\": {}, \"dims\": [{}, {}], \"cells\": [", step, grid.dims.0, grid.dims.1);
+    let mut runs: Vec<(usize, f64)> = Vec::new();
+    for cell in &grid.data
+    {
+        let value = value(cell);
+        match runs.last_mut()
+        {
+            // Bit equality, so that NaN runs are merged too.
+            Some((count, last)) if last.to_bits() == value.to_bits() => *count += 1,
+            _ => runs.push((1, value))
+        }
+    }
+    for (n, (count, value)) in runs.iter().enumerate()
+    {
+        let separator = if n == 0 { "" } else { ", " };
+        if value.is_finite()
+        {
+            let _ = write!(message, "{}[{}, {}]", separator, count, value);
+        }
+        else
+        {
+            let _ = write!(message, "{}[{}, null]", separator, count);
+        }
+    }
+    message.push_str("]}");
+    message
+}
+
+struct Client
+{
+    messages: SyncSender<Arc<String>>,
+    writer: JoinHandle<()>
+}
+
+// Sends the messages it receives until the broadcast ends or the client
+// goes away, then says goodbye.
+fn spawn_client(mut writer: BufWriter<TcpStream>, queue: usize) -> Client
+{
+    let (messages, receiver) = mpsc::sync_channel::<Arc<String>>(queue);
+    let writer = thread::spawn(move || {
+        for message in receiver
+        {
+            if write_message(&mut writer, 0x1, message.as_bytes()).is_err()
+            {
+                return;
+            }
+        }
+        // Close, status 1000 (normal closure).
+        let _ = write_message(&mut writer, 0x8, &1000u16.to_be_bytes());
+    });
+    Client{messages, writer}
+}
+
+pub fn broadcast<T, F, V, A>(automata: &mut Automata<T>, rule: F, steps: usize, value: V, addr: A, options: &BroadcastOptions) -> io::Result<BroadcastStats>
+where
+    T: Clone + Display + Copy + Debug,
+    F: Fn(Vec<T>) -> T,
+    V: Fn(&T) -> f64,
+    A: ToSocketAddrs
+{
+    broadcast_on(automata, rule, steps, value, TcpListener::bind(addr)?, options)
+}
+
+// Runs `steps` steps, sending the current generation and every following
+// one to the clients connected at the time. Clients may come and go during
+// the run; the simulation never waits for them, a client whose queue is
+// full skips the message.
+pub fn broadcast_on<T, F, V>(automata: &mut Automata<T>, rule: F, steps: usize, value: V, listener: TcpListener, options: &BroadcastOptions) -> io::Result<BroadcastStats>
+where
+    T: Clone + Display + Copy + Debug,
+    F: Fn(Vec<T>) -> T,
+    V: Fn(&T) -> f64
+{
+    listener.set_nonblocking(true)?;
+    let clients: Arc<Mutex<Vec<Client>>> = Arc::new(Mutex::new(Vec::new()));
+    let accepting = Arc::new(AtomicBool::new(true));
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let acceptor = {
+        let (clients, accepting, accepted) = (clients.clone(), accepting.clone(), accepted.clone());
+        let queue = options.queue.max(1);
+        thread::spawn(move || {
+            let mut handshakes: Vec<JoinHandle<()>> = Vec::new();
+            while accepting.load(Ordering::Relaxed)
+            {
+                match listener.accept()
+                {
+                    Ok((stream, _)) =>
+                    {
+                        // Each handshake has a thread of its own, so that a
+                        // silent connection holds up neither the others nor
+                        // the shutdown.
+                        let (clients, accepted) = (clients.clone(), accepted.clone());
+                        handshakes.retain(|handshake| !handshake.is_finished());
+                        handshakes.push(thread::spawn(move || {
+                            let upgraded = stream.set_nonblocking(false)
+                                .and_then(|()| stream.set_nodelay(true))
+                                .and_then(|()| stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)))
+                                .and_then(|()| stream.set_write_timeout(Some(WRITE_TIMEOUT)))
+                                .and_then(|()| handshake(stream));
+                            if let Ok(Some(writer)) = upgraded
+                            {
+                                clients.lock().unwrap().push(spawn_client(writer, queue));
+                                accepted.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }));
+                    },
+                    // Nobody waiting (or a connection that failed early).
+                    Err(_) => thread::sleep(ACCEPT_POLL)
+                }
+            }
+            for handshake in handshakes
+            {
+                let _ = handshake.join();
+            }
+        })
+    };
+
+    let period = if options.fps > 0.0 { Some(Duration::from_secs_f64(1.0 / options.fps)) } else { None };
+    let mut stats = BroadcastStats::default();
+    for n in 0..=steps
+    {
+        if options.stop.load(Ordering::Relaxed)
+        {
+            break;
+        }
+        let started = Instant::now();
+        if n > 0
+        {
+            automata.evolve(&rule);
+            stats.steps += 1;
+        }
+        let message = Arc::new(encode_message(automata.current(), automata.step(), &value));
+        let mut clients = clients.lock().unwrap();
+        let mut gone = Vec::new();
+        for (index, client) in clients.iter().enumerate()
+        {
+            match client.messages.try_send(message.clone())
+            {
+                Ok(()) => stats.sent += 1,
+                Err(TrySendError::Full(_)) => stats.skipped += 1,
+                Err(TrySendError::Disconnected(_)) => gone.push(index)
+            }
+        }
+        for index in gone.into_iter().rev()
+        {
+            let _ = clients.swap_remove(index).writer.join();
+        }
+        drop(clients);
+        if let Some(period) = period
+        {
+            if let Some(rest) = period.checked_sub(started.elapsed())
+            {
+                thread::sleep(rest);
+            }
+        }
+    }
+
+    // Shutdown: no new clients, and the others get the messages already
+    // queued followed by a close message. Connections still in their
+    // handshake have until HANDSHAKE_TIMEOUT to finish it, and clients that
+    // stopped reading until WRITE_TIMEOUT.
+    accepting.store(false, Ordering::Relaxed);
+    let _ = acceptor.join();
+    let clients = std::mem::take(&mut *clients.lock().unwrap());
+    for client in clients
+    {
+        drop(client.messages);
+        let _ = client.writer.join();
+    }
+    stats.clients = accepted.load(Ordering::Relaxed);
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::{rules, validate, Light, CellState};
+
+    use std::io::Read;
+
+    // The example of RFC 6455.
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+    #[test]
+    fn accept_keys_follow_the_rfc()
+    {
+        assert_eq!(base64(&sha1(b"abc")), "qZk+NkcGgWq6PiVxeFDCbJzQ2J0=");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(accept_key(KEY), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn messages_are_runs_of_values()
+    {
+        let grid = Grid::from_fn((3, 2), |(i, j)| if (i, j) == (2, 1) {f64::NAN} else {(i / 2) as f64});
+        assert_eq!(encode_message(&grid, 4, |cell| *cell), r#"{"step": 4, "dims": [3, 2], "cells": [[2, 0], [1, 1], [2, 0], [1, null]]}"#);
+    }
+
+    fn lamp() -> Automata<Light>
+    {
+        Automata::new(validate::lamp((12, 8), (5, 4), 9))
+    }
+
+    fn spawn_broadcast(steps: usize, fps: f64) -> (std::net::SocketAddr, JoinHandle<BroadcastStats>)
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = BroadcastOptions{fps, ..BroadcastOptions::default()};
+        let server = thread::spawn(move || {
+            broadcast_on(&mut lamp(), rules::light_falloff, steps, |cell| f64::from(cell.level()), listener, &options).unwrap()
+        });
+        (addr, server)
+    }
+
+    // The text messages a client receives until the close message.
+    fn receive(addr: std::net::SocketAddr) -> Vec<String>
+    {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n", KEY).unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut status = String::new();
+        reader.read_line(&mut status).unwrap();
+        assert_eq!(status.trim_end(), "HTTP/1.1 101 Switching Protocols");
+        let mut accepted = false;
+        loop
+        {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            accepted |= line.trim_end() == format!("Sec-WebSocket-Accept: {}", accept_key(KEY));
+            if line.trim_end().is_empty()
+            {
+                break;
+            }
+        }
+        assert!(accepted);
+        let mut messages = vec![];
+        loop
+        {
+            let mut header = [0; 2];
+            reader.read_exact(&mut header).unwrap();
+            let len = match header[1]
+            {
+                126 =>
+                {
+                    let mut len = [0; 2];
+                    reader.read_exact(&mut len).unwrap();
+                    usize::from(u16::from_be_bytes(len))
+                },
+                len => usize::from(len)
+            };
+            let mut payload = vec![0; len];
+            reader.read_exact(&mut payload).unwrap();
+            match header[0]
+            {
+                0x81 => messages.push(String::from_utf8(payload).unwrap()),
+                0x88 =>
+                {
+                    assert_eq!(payload, 1000u16.to_be_bytes());
+                    return messages;
+                },
+                other => panic!("unexpected frame {:x}", other)
+            }
+        }
+    }
+
+    fn step_of(message: &str) -> u64
+    {
+        message["{\"step\": ".len()..].split(',').next().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn clients_receive_increasing_steps()
+    {
+        let (addr, server) = spawn_broadcast(30, 50.0);
+        let messages = receive(addr);
+        let stats = server.join().unwrap();
+        assert!(messages.len() >= 3, "{} messages", messages.len());
+        let steps: Vec<u64> = messages.iter().map(|message| step_of(message)).collect();
+        assert!(steps.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", steps);
+        assert_eq!(steps.last(), Some(&30));
+        assert!(messages.iter().all(|message| message.contains("\"dims\": [12, 8]")));
+        assert_eq!((stats.steps, stats.clients), (30, 1));
+        assert_eq!(stats.sent, messages.len());
+    }
+
+    #[test]
+    fn silent_connections_hold_nobody_up()
+    {
+        let (addr, server) = spawn_broadcast(25, 50.0);
+        // Connects and never says anything.
+        let silent = TcpStream::connect(addr).unwrap();
+        let messages = receive(addr);
+        assert!(messages.len() >= 3, "{} messages", messages.len());
+        let started = Instant::now();
+        let stats = server.join().unwrap();
+        assert!(started.elapsed() < HANDSHAKE_TIMEOUT + Duration::from_secs(1));
+        assert_eq!(stats.clients, 1);
+        drop(silent);
+    }
+
+    #[test]
+    fn plain_requests_get_the_viewer()
+    {
+        let (addr, server) = spawn_broadcast(10, 50.0);
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(VIEWER));
+        assert_eq!(server.join().unwrap().clients, 0);
+    }
+}